@@ -1,5 +1,26 @@
-pub use crate::InputSize;
+pub use crate::{InputSize, PrioField};
+pub use bridge::client_server::PartitionPolicy;
+use bridge::{
+    end_timer,
+    id_tracker::MAX_SESSION_ROUNDS,
+    start_timer,
+    tcp_bridge::{ClientID, ClientToken},
+    tls::ClientTls,
+};
 use clap::{Arg, Command};
+use crypto_primitives::{
+    cot::dims::ProtocolDims,
+    malpriv::MessageHash,
+    message::size::{estimate_mpc_sizes, estimate_sizes, Protocol, MESSAGE_HEADER_SIZE},
+    uint::UInt,
+};
+pub use elsa_client::{BitAggregation, Variant};
+use elsa_client::{ClientConfig, ClientError, ElsaClient, InputRing};
+use rand::{rngs::StdRng, SeedableRng};
+use rayon::prelude::*;
+use std::sync::Arc;
+use tracing::{debug, info};
+
 pub struct Options {
     pub server_alice: String,
     pub server_bob: String,
@@ -7,11 +28,45 @@ pub struct Options {
     pub gsize: usize,
     pub log_level: tracing_core::Level,
     pub input_size: InputSize,
+    pub sec_param: usize,
+    pub partition: PartitionPolicy,
+    /// Token to register with, all zeros if not set.
+    pub client_token: ClientToken,
+    pub session: u64,
+    /// Seed of the clients, to reproduce a run. Random if `None`.
+    pub seed: Option<u64>,
+    /// Compress the message to the OT receiver with LZ4. Only client-po2
+    /// supports it, see [`Options::require_uncompressed`].
+    pub compress: bool,
+    /// Split the inputs of each client in the middle, so that each server is
+    /// OT sender for one half. Only client-po2 supports it.
+    pub split_b2a: bool,
+    /// Prime field of the messages. Only client-baseline-mp uses it.
+    pub prio_field: PrioField,
+    /// How the servers aggregate the bits. Only client-bits uses it.
+    pub bit_aggregation: BitAggregation,
+    /// Print the message sizes of one client and exit, without connecting.
+    pub estimate_only: bool,
+    /// Number of rounds of the session, each with its own inputs, over the
+    /// same connections.
+    pub rounds: u64,
+    /// Connect to the servers over TLS, or in plaintext if `None`.
+    pub tls: Option<ClientTls>,
+    /// Check the COTs and square correlations of each client before sending,
+    /// see `ClientConfig::self_check`.
+    pub self_check: bool,
+    /// Draw the inputs at most this bound, and prove it to the servers. Only
+    /// client-l2 and client-mp support it, see `ClientConfig::bound`.
+    pub bound: Option<u64>,
+    /// File of the signing key of each client, with `{uid}` replaced by its
+    /// uid, see `ClientConfig::signing_key`.
+    #[cfg(feature = "signed-uploads")]
+    pub signing_key: Option<String>,
 }
 
 impl Options {
     pub fn load_from_args(program_name: &str) -> Self {
-        let matches = Command::new(program_name)
+        let command = Command::new(program_name)
             .version("0.1")
             .arg(
                 Arg::new("server_alice")
@@ -57,9 +112,110 @@ impl Options {
                     .long("input-size")
                     .takes_value(true)
                     .default_value("8")
-                    .help("input size"),
+                    .help("input size in bits: 8, 32 or 64"),
+            )
+            .arg(
+                Arg::new("sec_param")
+                    .long("sec-param")
+                    .takes_value(true)
+                    .default_value("40")
+                    .help("statistical security parameter for OT verification"),
+            )
+            .arg(
+                Arg::new("partition")
+                    .long("partition")
+                    .takes_value(true)
+                    .default_value("parity")
+                    .help("which server is OT sender for each client: parity, or ratio:<a>:<b> (must match the servers)"),
+            )
+            .arg(
+                Arg::new("client_token")
+                    .long("client-token")
+                    .takes_value(true)
+                    .help("64 hex digits to register with (must match the servers)"),
+            )
+            .arg(
+                Arg::new("session")
+                    .long("session")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("session id bound into the share commitments of the clients"),
+            )
+            .arg(
+                Arg::new("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .help("seed client <uid> with <seed> + <uid>, to reproduce the messages of a run (default: random)"),
+            )
+            .arg(
+                Arg::new("compress")
+                    .long("compress")
+                    .help("compress the message to the OT receiver with LZ4 (client-po2 only, the servers need --compress too)"),
+            )
+            .arg(
+                Arg::new("split_b2a")
+                    .long("split-b2a")
+                    .help("split the inputs in the middle, with each server OT sender for one half (client-po2 only, the servers need --split-b2a too)"),
+            )
+            .arg(
+                Arg::new("prio_field")
+                    .long("field")
+                    .takes_value(true)
+                    .default_value("64")
+                    .help("prime field of prio: 64 or 128 bits (client-baseline-mp only, the servers need the same --field)"),
+            )
+            .arg(
+                Arg::new("bit_aggregation")
+                    .long("aggregation")
+                    .takes_value(true)
+                    .default_value("sum")
+                    .help("how the servers aggregate the bits: xor or sum (client-bits only, the servers need the same --aggregation)"),
+            )
+            .arg(
+                Arg::new("rounds")
+                    .long("rounds")
+                    .takes_value(true)
+                    .default_value("1")
+                    .help("submit new inputs in this many rounds over the same connections (must match the servers)"),
+            )
+            .arg(
+                Arg::new("tls_ca")
+                    .long("tls-ca")
+                    .takes_value(true)
+                    .help("connect over TLS, trusting the server certificates signed by a certificate in this PEM file (the servers need --tls-cert)"),
+            )
+            .arg(
+                Arg::new("tls_domain")
+                    .long("tls-domain")
+                    .takes_value(true)
+                    .default_value("localhost")
+                    .help("name the certificates of both servers must be valid for, with --tls-ca"),
+            )
+            .arg(
+                Arg::new("self_check")
+                    .long("self-check")
+                    .help("check the COTs and square correlations of every client against its inputs before sending, and abort if they do not match (not the prio baselines)"),
+            )
+            .arg(
+                Arg::new("bound")
+                    .long("bound")
+                    .takes_value(true)
+                    .help("draw the inputs at most this bound, and send the servers the witnesses that they are (client-l2 and client-mp only, the servers need the same --bound)"),
             )
-            .get_matches();
+            .arg(
+                Arg::new("estimate_only")
+                    .long("estimate-only")
+                    .help("print the message sizes of one client and the MPC traffic of the servers, then exit without connecting"),
+            );
+        #[cfg(feature = "signed-uploads")]
+        let command = command.arg(
+            Arg::new("signing_key")
+                .long("signing-key")
+                .takes_value(true)
+                .required_unless_present("estimate_only")
+                .help("sign the uploads with the 32-byte secret key in this file, where {uid} is replaced by the uid of each client"),
+        );
+        let matches = command.get_matches();
 
         let log_level = if matches.is_present("verbose") {
             tracing_core::Level::DEBUG
@@ -82,6 +238,44 @@ impl Options {
             .unwrap()
             .parse::<InputSize>()
             .unwrap();
+        let sec_param = matches
+            .value_of("sec_param")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
+        let partition = matches
+            .value_of("partition")
+            .unwrap()
+            .parse::<PartitionPolicy>()
+            .unwrap();
+        let client_token = matches
+            .value_of("client_token")
+            .map(|t| t.parse::<ClientToken>().unwrap())
+            .unwrap_or_default();
+        let session = matches.value_of("session").unwrap().parse::<u64>().unwrap();
+        let seed = matches.value_of("seed").map(|s| s.parse::<u64>().unwrap());
+        let prio_field = matches
+            .value_of("prio_field")
+            .unwrap()
+            .parse::<PrioField>()
+            .unwrap();
+        let bit_aggregation = matches
+            .value_of("bit_aggregation")
+            .unwrap()
+            .parse::<BitAggregation>()
+            .unwrap();
+        let rounds = matches
+            .value_of("rounds")
+            .unwrap()
+            .parse::<u64>()
+            .ok()
+            .filter(|rounds| (1..=MAX_SESSION_ROUNDS).contains(rounds))
+            .expect("invalid number of rounds");
+        let tls = matches.value_of("tls_ca").map(|ca| {
+            let roots = crate::tls::load_certs(ca).expect("invalid --tls-ca");
+            let domain = matches.value_of("tls_domain").unwrap();
+            ClientTls::new(domain, &roots).expect("invalid TLS settings")
+        });
 
         Options {
             server_alice: server_alice.to_string(),
@@ -90,6 +284,305 @@ impl Options {
             gsize,
             log_level,
             input_size,
+            sec_param,
+            partition,
+            client_token,
+            session,
+            seed,
+            compress: matches.is_present("compress"),
+            split_b2a: matches.is_present("split_b2a"),
+            prio_field,
+            bit_aggregation,
+            estimate_only: matches.is_present("estimate_only"),
+            rounds,
+            tls,
+            self_check: matches.is_present("self_check"),
+            bound: matches
+                .value_of("bound")
+                .map(|bound| bound.parse().expect("invalid bound")),
+            #[cfg(feature = "signed-uploads")]
+            signing_key: matches.value_of("signing_key").map(String::from),
+        }
+    }
+}
+
+impl Options {
+    /// Exit with an error if `--compress` is set, for the clients whose
+    /// servers do not decompress.
+    pub fn require_uncompressed(&self) {
+        if self.compress {
+            eprintln!("--compress is only supported by client-po2");
+            std::process::exit(2);
+        }
+    }
+}
+
+impl Options {
+    /// Config of client `uid` of `variant`. Like the servers, it waits for its
+    /// peers as long as it takes.
+    pub fn client_config(&self, variant: Variant, uid: u64) -> ClientConfig {
+        let input_ring = match self.input_size {
+            InputSize::U8 => InputRing::U8,
+            InputSize::U32 => InputRing::U32,
+            InputSize::U64 => InputRing::U64,
+        };
+        ClientConfig {
+            uid: ClientID::new(uid),
+            token: self.client_token,
+            session: self.session,
+            sec_param: self.sec_param,
+            partition: self.partition.clone(),
+            compress: self.compress,
+            split: self.split_b2a.then_some(self.gsize / 2),
+            connect_timeout: None,
+            phase2_timeout: None,
+            tls: self.tls.clone(),
+            self_check: self.self_check,
+            bound: self.bound,
+            #[cfg(feature = "signed-uploads")]
+            signing_key: self.signing_key.as_ref().map(|path| {
+                let path = path.replace("{uid}", &uid.to_string());
+                crate::signing::load_signing_key(&path).expect("invalid --signing-key")
+            }),
+            ..ClientConfig::new(
+                variant,
+                input_ring,
+                self.gsize,
+                &self.server_alice,
+                &self.server_bob,
+            )
+        }
+    }
+}
+
+/// Run `options.num_clients` clients of `variant` on random inputs: prepare
+/// the messages of all clients, connect all of them, and then submit. Exits
+/// the process if a client fails.
+pub async fn run_clients(options: &Options, variant: Variant) {
+    info!(
+        "{:?} Client: num_clients: {}, Server address alice: {}, server address bob: {}, gsize: {}, log_level: {}",
+        variant, options.num_clients, options.server_alice, options.server_bob, options.gsize, options.log_level
+    );
+    match options.input_size {
+        InputSize::U8 => run_clients_in::<u8>(options, variant).await,
+        InputSize::U32 => run_clients_in::<u32>(options, variant).await,
+        InputSize::U64 => run_clients_in::<u64>(options, variant).await,
+    }
+}
+
+async fn run_clients_in<I: UInt + Into<u64>>(options: &Options, variant: Variant) {
+    let clients = (0..options.num_clients)
+        .map(|uid| ElsaClient::new(options.client_config(variant, uid as u64)))
+        .collect::<Vec<_>>();
+    let mut rngs = client_rngs(options.seed, options.num_clients);
+    // connected in the first round, and kept for the others
+    let mut registered = Vec::with_capacity(options.num_clients);
+    for round in 0..options.rounds {
+        let timer = start_timer!(|| "Preparing Client Input");
+        let data = (0..options.num_clients as u64)
+            .into_par_iter()
+            .map(|uid| match variant {
+                Variant::Bits(_) => client_round_bits(uid, round, options.gsize)
+                    .into_iter()
+                    .map(I::from_bool)
+                    .collect(),
+                _ => {
+                    let inputs = client_round_inputs::<I>(uid, round, options.gsize);
+                    match options.bound {
+                        Some(bound) => within_bound(inputs, bound),
+                        None => inputs,
+                    }
+                },
+            })
+            .collect::<Vec<Vec<I>>>();
+        end_timer!(timer);
+
+        let timer = start_timer!(|| "Preparing Client Message");
+        let prepared = clients
+            .par_iter()
+            .zip(data)
+            .zip(rngs.par_iter_mut())
+            .map(|((client, input), rng)| client.prepare_with_rng(&input, rng))
+            .collect::<Vec<_>>();
+        end_timer!(timer);
+        let prepared = prepared
+            .into_iter()
+            .enumerate()
+            .map(|(uid, prepared)| prepared.unwrap_or_else(|e| exit_on_client_error(uid, e)))
+            .collect::<Vec<_>>();
+
+        if round == 0 {
+            info!("Attempting to connect to server");
+            let connecting = clients
+                .iter()
+                .cloned()
+                .map(|client| tokio::spawn(async move { client.connect().await }))
+                .collect::<Vec<_>>();
+            for (uid, client) in connecting.into_iter().enumerate() {
+                let client = client.await.unwrap();
+                registered.push(Arc::new(
+                    client.unwrap_or_else(|e| exit_on_client_error(uid, e)),
+                ));
+            }
+            info!("All clients connected! Sending clients data...");
         }
+
+        let timer = start_timer!(|| "Submitting Client Message");
+        let submitting = registered
+            .iter()
+            .cloned()
+            .zip(prepared)
+            .map(|(client, prepared)| {
+                tokio::spawn(async move { client.submit_round(round, prepared).await })
+            })
+            .collect::<Vec<_>>();
+        for (uid, receipt) in submitting.into_iter().enumerate() {
+            if let Err(e) = receipt.await.unwrap() {
+                exit_on_client_error(uid, e);
+            }
+        }
+        end_timer!(timer);
+    }
+}
+
+/// The `gsize` random inputs of client `uid` in the benchmarks, seeded by the
+/// uid so that other systems, e.g. the prio baseline, can aggregate the same
+/// data and the sums can be compared.
+pub fn client_inputs<I: UInt>(uid: u64, gsize: usize) -> Vec<I> {
+    client_round_inputs(uid, 0, gsize)
+}
+
+/// The inputs of client `uid` in round `round` of a session, which are
+/// `client_inputs` in round 0.
+pub fn client_round_inputs<I: UInt>(uid: u64, round: u64, gsize: usize) -> Vec<I> {
+    let mut rng = StdRng::seed_from_u64(uid.wrapping_add(round << 32));
+    (0..gsize).map(|_| I::rand(&mut rng)).collect()
+}
+
+/// `inputs` reduced modulo `bound + 1`, so that they are at most `bound`.
+fn within_bound<I: UInt + Into<u64>>(inputs: Vec<I>, bound: u64) -> Vec<I> {
+    match bound.checked_add(1) {
+        Some(modulus) => inputs
+            .into_iter()
+            .map(|x| I::from(x.into() % modulus).unwrap())
+            .collect(),
+        None => inputs,
+    }
+}
+
+/// The bits of client `uid` in round `round` of a session, for
+/// `Variant::Bits`: the lowest bit of each of its `client_round_inputs`.
+pub fn client_round_bits(uid: u64, round: u64, gsize: usize) -> Vec<bool> {
+    client_round_inputs::<u8>(uid, round, gsize)
+        .into_iter()
+        .map(|x| x & 1 == 1)
+        .collect()
+}
+
+fn exit_on_client_error(uid: usize, e: ClientError) -> ! {
+    eprintln!("client {}: {}", uid, e);
+    std::process::exit(1);
+}
+
+/// The rng of each of `num_clients` clients, in uid order. With a `seed`,
+/// client `uid` is seeded with `seed + uid`, so that a run can be reproduced.
+pub fn client_rngs(seed: Option<u64>, num_clients: usize) -> Vec<StdRng> {
+    let num_clients = num_clients as u64;
+    match seed {
+        Some(seed) => (0..num_clients)
+            .map(|uid| {
+                let seed = seed.wrapping_add(uid);
+                debug!("client {} seed: {}", uid, seed);
+                StdRng::seed_from_u64(seed)
+            })
+            .collect(),
+        None => {
+            let mut rng = StdRng::from_entropy();
+            // each client gets the full entropy of a seed, not a `u64`
+            (0..num_clients)
+                .map(|_| StdRng::from_rng(&mut rng).expect("cannot seed client rng"))
+                .collect()
+        },
+    }
+}
+
+/// Print the message sizes of one client of `protocol`, and the MPC traffic
+/// of the servers for `options.num_clients` clients, with arithmetic shares
+/// in ring `A`, correlations in ring `C` and transcript hash `H`.
+pub fn print_estimate<A, C, H>(options: &Options, protocol: Protocol)
+where
+    A: UInt,
+    C: UInt,
+    H: MessageHash + Default,
+{
+    match options.input_size {
+        InputSize::U8 => print_estimate_for::<u8, A, C, H>(options, protocol),
+        InputSize::U32 => print_estimate_for::<u32, A, C, H>(options, protocol),
+        InputSize::U64 => print_estimate_for::<u64, A, C, H>(options, protocol),
+    }
+}
+
+fn print_estimate_for<I, A, C, H>(options: &Options, protocol: Protocol)
+where
+    I: UInt,
+    A: UInt,
+    C: UInt,
+    H: MessageHash + Default,
+{
+    let dims = match ProtocolDims::new::<I>(options.gsize, options.sec_param) {
+        Ok(dims) => dims,
+        Err(e) => {
+            eprintln!("invalid dimensions: {}", e);
+            std::process::exit(2);
+        },
+    };
+    let report = estimate_sizes::<I, C, H>(protocol, dims);
+    println!(
+        "{:?} client, gsize {}, {}-bit inputs, {} OTs ({} for verification)",
+        protocol,
+        dims.gsize(),
+        I::NUM_BITS,
+        dims.num_total(),
+        dims.num_total() - dims.num_ot()
+    );
+    println!(
+        "{:<12}{:>16}{:>16}",
+        "bytes", "to OT sender", "to OT receiver"
+    );
+    let (alice, bob) = (&report.to_alice, &report.to_bob);
+    let rows = [
+        ("inputs", alice.inputs, bob.inputs),
+        ("commitment", alice.commitment, bob.commitment),
+        ("cot", alice.cot, bob.cot),
+        ("sqcorr", alice.sqcorr, bob.sqcorr),
+        ("hashes", alice.hashes, bob.hashes),
+        ("total", alice.total(), bob.total()),
+        (
+            "with header",
+            alice.total() + MESSAGE_HEADER_SIZE,
+            bob.total() + MESSAGE_HEADER_SIZE,
+        ),
+    ];
+    for (name, alice, bob) in rows {
+        println!("{:<12}{:>16}{:>16}", name, alice, bob);
+    }
+
+    let mpc = estimate_mpc_sizes::<I, A, C>(protocol, dims, options.num_clients);
+    println!(
+        "MPC traffic of both servers for {} clients, without the release",
+        options.num_clients
+    );
+    let rows = [
+        ("commitments", mpc.commitments),
+        ("seeds", mpc.seeds),
+        ("ot verify", mpc.ot_verify),
+        ("b2a", mpc.b2a),
+        ("failures", mpc.failures),
+        ("sqcorr", mpc.sqcorr_verify),
+        ("a2s", mpc.a2s),
+        ("total", mpc.total()),
+    ];
+    for (name, size) in rows {
+        println!("{:<12}{:>16}", name, size);
     }
 }
@@ -0,0 +1,244 @@
+//! Hashers of the messages a server receives from its peer for each client,
+//! compared at the end of the round with the hashes the client sent to the
+//! other server.
+//!
+//! A hasher is moved into the task of its client and put back when the task
+//! is joined. Both go by the index of the client in the phase, so a hasher
+//! put back to another client, or twice, panics at the `put` site instead of
+//! silently failing the hash verification.
+
+use crypto_primitives::malpriv::MessageHash;
+
+/// Phases of the MP protocols whose messages are hashed. The suffix is the
+/// direction of the messages, e.g. `OtBA` hashes the messages from Bob to
+/// Alice, so it has one hasher per client of Alice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashPhase {
+    OtBA,
+    B2aAB,
+    SqcorrBA,
+    SqcorrAB,
+    A2s,
+}
+
+const NUM_PHASES: usize = 5;
+
+pub struct HashPool<H: MessageHash> {
+    hashers: [Option<Vec<Option<H>>>; NUM_PHASES],
+}
+
+impl<H: MessageHash> HashPool<H> {
+    /// A pool with `len` hashers for each of `phases`. Other phases are not
+    /// used by the protocol, and panic if asked for.
+    pub fn init(
+        phases: impl IntoIterator<Item = (HashPhase, usize)>,
+        hasher: impl Fn() -> H,
+    ) -> Self {
+        let mut hashers = [None, None, None, None, None];
+        for (phase, len) in phases {
+            let slot = &mut hashers[phase as usize];
+            assert!(slot.is_none(), "hash phase {:?} initialized twice", phase);
+            *slot = Some((0..len).map(|_| Some(hasher())).collect());
+        }
+        Self { hashers }
+    }
+
+    fn phase(&mut self, phase: HashPhase) -> &mut Vec<Option<H>> {
+        self.hashers[phase as usize]
+            .as_mut()
+            .unwrap_or_else(|| panic!("hash phase {:?} not in the pool", phase))
+    }
+
+    /// Take the hasher of client `idx` of `phase`, to move it into the task
+    /// of the client. Panics if it is already taken.
+    pub fn take(&mut self, phase: HashPhase, idx: usize) -> H {
+        self.phase(phase)[idx]
+            .take()
+            .unwrap_or_else(|| panic!("hasher {:?}[{}] already taken", phase, idx))
+    }
+
+    /// Put back the hasher of client `idx` of `phase`. Panics if it was not
+    /// taken.
+    pub fn put(&mut self, phase: HashPhase, idx: usize, hasher: H) {
+        let slot = &mut self.phase(phase)[idx];
+        assert!(
+            slot.is_none(),
+            "hasher {:?}[{}] put back but never taken",
+            phase,
+            idx
+        );
+        *slot = Some(hasher);
+    }
+
+    /// Whether the digest of each hasher of `phase` equals the expected hash
+    /// of its client. Consumes the hashers of `phase`, which must all be put
+    /// back.
+    pub fn verify(&mut self, phase: HashPhase, expected: &[H::Output]) -> Vec<bool> {
+        let expected = expected.iter().map(Some).collect::<Vec<_>>();
+        self.verify_surviving(phase, &expected)
+            .into_iter()
+            .map(|verified| verified.expect("no client dropped"))
+            .collect()
+    }
+
+    /// Same as `verify`, where the clients with no expected hash dropped out
+    /// and get `None`. Their hashers are tombstones that keep the indices of
+    /// the other clients, so they must never have been taken.
+    pub fn verify_surviving(
+        &mut self,
+        phase: HashPhase,
+        expected: &[Option<&H::Output>],
+    ) -> Vec<Option<bool>> {
+        let hashers = std::mem::take(self.phase(phase));
+        assert_eq!(
+            hashers.len(),
+            expected.len(),
+            "number of expected {:?} hashes",
+            phase
+        );
+        hashers
+            .into_iter()
+            .zip(expected)
+            .enumerate()
+            .map(|(idx, (hasher, expected))| match expected {
+                Some(expected) => {
+                    let hasher = hasher
+                        .unwrap_or_else(|| panic!("hasher {:?}[{}] not put back", phase, idx));
+                    Some(&hasher.digest() == *expected)
+                },
+                None => {
+                    assert!(
+                        hasher.is_some(),
+                        "hasher {:?}[{}] of a dropped client was taken",
+                        phase,
+                        idx
+                    );
+                    None
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialize::Communicate;
+
+    /// Concatenation of the absorbed messages.
+    #[derive(Default)]
+    struct Transcript(Vec<u8>);
+
+    impl MessageHash for Transcript {
+        type Output = Vec<u8>;
+
+        fn absorb<M: Communicate>(&mut self, msg: &M) {
+            msg.to_bytes(&mut self.0);
+        }
+
+        fn digest(self) -> Self::Output {
+            self.0
+        }
+    }
+
+    fn digest_of(msg: u8) -> Vec<u8> {
+        let mut hasher = Transcript::default();
+        hasher.absorb(&vec![msg]);
+        hasher.digest()
+    }
+
+    fn pool() -> HashPool<Transcript> {
+        HashPool::init(
+            [(HashPhase::OtBA, 3), (HashPhase::B2aAB, 2)],
+            Transcript::default,
+        )
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let mut pool = pool();
+        let mut hashers = (0..3)
+            .map(|idx| pool.take(HashPhase::OtBA, idx))
+            .collect::<Vec<_>>();
+        for (idx, hasher) in hashers.iter_mut().enumerate() {
+            hasher.absorb(&vec![idx as u8]);
+        }
+        // tasks finish in any order
+        for idx in [2, 0, 1] {
+            let hasher = std::mem::take(&mut hashers[idx]);
+            pool.put(HashPhase::OtBA, idx, hasher);
+        }
+        let expected = (0..3).map(digest_of).collect::<Vec<_>>();
+        assert_eq!(pool.verify(HashPhase::OtBA, &expected), [true; 3]);
+    }
+
+    #[test]
+    fn test_mismatched_digest() {
+        let mut pool = pool();
+        for idx in 0..2 {
+            let mut hasher = pool.take(HashPhase::B2aAB, idx);
+            hasher.absorb(&vec![7u8]);
+            pool.put(HashPhase::B2aAB, idx, hasher);
+        }
+        assert_eq!(
+            pool.verify(HashPhase::B2aAB, &[digest_of(7), digest_of(8)]),
+            [true, false]
+        );
+    }
+
+    #[test]
+    fn test_dropped_client_is_skipped() {
+        let mut pool = pool();
+        for idx in [0, 2] {
+            let mut hasher = pool.take(HashPhase::OtBA, idx);
+            hasher.absorb(&vec![idx as u8]);
+            pool.put(HashPhase::OtBA, idx, hasher);
+        }
+        let expected = [digest_of(0), digest_of(9)];
+        assert_eq!(
+            pool.verify_surviving(
+                HashPhase::OtBA,
+                &[Some(&expected[0]), None, Some(&expected[1])]
+            ),
+            [Some(true), None, Some(false)]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "of a dropped client was taken")]
+    fn test_dropped_client_taken() {
+        let mut pool = pool();
+        pool.take(HashPhase::B2aAB, 0);
+        pool.verify_surviving(HashPhase::B2aAB, &[None, Some(&vec![])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "put back but never taken")]
+    fn test_put_to_wrong_client() {
+        let mut pool = pool();
+        let hasher = pool.take(HashPhase::OtBA, 0);
+        pool.put(HashPhase::OtBA, 1, hasher);
+    }
+
+    #[test]
+    #[should_panic(expected = "already taken")]
+    fn test_take_twice() {
+        let mut pool = pool();
+        pool.take(HashPhase::OtBA, 2);
+        pool.take(HashPhase::OtBA, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "not put back")]
+    fn test_verify_without_put() {
+        let mut pool = pool();
+        pool.take(HashPhase::B2aAB, 1);
+        pool.verify(HashPhase::B2aAB, &[vec![], vec![]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not in the pool")]
+    fn test_phase_not_in_pool() {
+        pool().take(HashPhase::A2s, 0);
+    }
+}
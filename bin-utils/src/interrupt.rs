@@ -0,0 +1,126 @@
+//! Graceful shutdown of a server on Ctrl-C.
+//!
+//! [`run_interruptible`] races the round of a server with Ctrl-C and with an
+//! abort of the MPC peer. On Ctrl-C, the round is dropped, which stops
+//! accepting clients and closes their connections, and the peer is told to
+//! abort, so that it fails its pending subscriptions instead of waiting
+//! forever. The caller then emits a partial report and exits with
+//! [`INTERRUPTED_EXIT_CODE`] or [`PEER_ABORTED_EXIT_CODE`], see
+//! `Options::exit_interrupted`.
+
+use std::{future::Future, time::Duration};
+
+use bridge::mpc_conn::MpcConnection;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Exit code of a server stopped by Ctrl-C, as a shell reports a process
+/// killed by SIGINT.
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+/// Exit code of a server whose peer was stopped by Ctrl-C.
+pub const PEER_ABORTED_EXIT_CODE: i32 = 75;
+
+/// How long to wait for the abort to be written to the peer.
+const ABORT_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Why a round did not complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interrupted {
+    /// This server got Ctrl-C.
+    Signal,
+    /// The peer gave up the round.
+    PeerAborted,
+}
+
+impl Interrupted {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Interrupted::Signal => INTERRUPTED_EXIT_CODE,
+            Interrupted::PeerAborted => PEER_ABORTED_EXIT_CODE,
+        }
+    }
+}
+
+/// Run `round` until it completes, Ctrl-C, or an abort of `peer`.
+pub async fn run_interruptible<F: Future>(
+    round: F,
+    peer: &MpcConnection,
+) -> Result<F::Output, Interrupted> {
+    let signal = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("cannot listen to Ctrl-C: {}", e);
+            std::future::pending::<()>().await
+        }
+    };
+    run_interruptible_with(round, peer, signal).await
+}
+
+/// Same as `run_interruptible`, but interrupted when `signal` resolves.
+pub async fn run_interruptible_with<F: Future>(
+    round: F,
+    peer: &MpcConnection,
+    signal: impl Future<Output = ()>,
+) -> Result<F::Output, Interrupted> {
+    // the round is dropped before a branch runs, so no more messages are sent
+    // after the abort
+    tokio::select! {
+        biased;
+        _ = peer.aborted() => {
+            warn!("the peer aborted the round");
+            Err(Interrupted::PeerAborted)
+        },
+        _ = signal => {
+            warn!("interrupted, aborting the round");
+            match tokio::time::timeout(ABORT_SEND_TIMEOUT, peer.abort()).await {
+                Ok(Ok(())) => (),
+                _ => warn!("cannot tell the peer to abort"),
+            }
+            Err(Interrupted::Signal)
+        },
+        output = round => Ok(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bridge::{mpc_conn::MpcConnection, BridgeError};
+    use tokio::{net::TcpListener, sync::oneshot};
+
+    use super::{run_interruptible_with, Interrupted};
+
+    #[tokio::test]
+    async fn test_signal_aborts_peer_round() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (alice, bob) = tokio::join!(
+            MpcConnection::new_as_alice_with_listener(listener, 2),
+            MpcConnection::new_as_bob(addr, 2)
+        );
+
+        // each waits for a message the other never sends
+        let (signal, interrupted) = oneshot::channel::<()>();
+        let alice_round = alice.subscribe_and_get::<Vec<u32>>(12.into());
+        let alice_side = run_interruptible_with(alice_round, &alice, async {
+            interrupted.await.unwrap_or(())
+        });
+        let bob_round = bob.subscribe_and_get::<Vec<u32>>(13.into());
+        let bob_side = run_interruptible_with(bob_round, &bob, std::future::pending());
+        signal.send(()).unwrap();
+
+        let (alice_result, bob_result) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(alice_side, bob_side)
+        })
+        .await
+        .expect("a server still waits after Ctrl-C");
+        assert_eq!(alice_result.unwrap_err(), Interrupted::Signal);
+        // the abort wins over the error of the failed subscription
+        assert_eq!(bob_result.unwrap_err(), Interrupted::PeerAborted);
+        assert!(matches!(
+            bob.subscribe_and_get::<Vec<u32>>(13.into()).await,
+            Err(BridgeError::PeerAborted)
+        ));
+    }
+}
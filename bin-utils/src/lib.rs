@@ -3,17 +3,35 @@ use std::str::FromStr;
 #[cfg(feature = "client")]
 pub mod client;
 #[cfg(feature = "server")]
+pub mod hash_pool;
+#[cfg(feature = "server")]
+pub mod interrupt;
+#[cfg(feature = "server")]
+pub mod metrics;
+pub mod panic_report;
+#[cfg(feature = "server")]
+pub mod runtime;
+#[cfg(feature = "server")]
 pub mod server;
+pub mod signing;
+pub mod simulation;
+pub mod tls;
 pub enum InputSize {
     U8,
     U32,
+    U64,
 }
 
 impl InputSize {
+    /// The widest input size. The arithmetic ring of a binary must have at
+    /// least this many bits, see `const_assert!` in the mains.
+    pub const WIDEST: InputSize = InputSize::U64;
+
     pub const fn num_bits(&self) -> usize {
         match self {
             InputSize::U8 => 8,
             InputSize::U32 => 32,
+            InputSize::U64 => 64,
         }
     }
 }
@@ -25,7 +43,28 @@ impl FromStr for InputSize {
         match s {
             "8" => Ok(InputSize::U8),
             "32" => Ok(InputSize::U32),
+            "64" => Ok(InputSize::U64),
             _ => Err(format!("Unsupported input size: {}", s)),
         }
     }
 }
+
+/// Prime field of the prio baseline, which must hold the sum of the input
+/// bits over the clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrioField {
+    F64,
+    F128,
+}
+
+impl FromStr for PrioField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "64" => Ok(PrioField::F64),
+            "128" => Ok(PrioField::F128),
+            _ => Err(format!("Unsupported prio field: {}", s)),
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Metrics of a server for `--metrics-port`, served on `/metrics` in the
+//! Prometheus text format by a minimal HTTP responder, without a web
+//! framework.
+//!
+//! The counters of the rounds are fed by the `RunReport` of each round, see
+//! `Options::emit_report`, so they follow the same phases and checks as the
+//! reports. The bytes on the MPC link are the live counters of the
+//! `MetricsRegistry` of the connection, folded on every scrape.
+
+use crate::server::RunReport;
+use bridge::metrics::MetricsRegistry;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+    time,
+};
+use tracing::{debug, warn};
+
+/// Largest request read, headers included. Scrapers send a few hundred bytes.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// How long a scraper may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters and gauges of a server, shared by the rounds and the responder.
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    clients_connected: AtomicU64,
+    rounds: AtomicU64,
+    interrupted_rounds: AtomicU64,
+    client_bytes_received: AtomicU64,
+    clients: AtomicU64,
+    clients_dropped: AtomicU64,
+    /// Seconds of each phase, summed over the rounds.
+    phase_seconds: Mutex<BTreeMap<String, f64>>,
+    /// Clients that passed each check, summed over the rounds.
+    clients_verified: Mutex<BTreeMap<String, u64>>,
+}
+
+impl ServerMetrics {
+    /// Set the number of clients connected to this server.
+    pub fn set_clients_connected(&self, num_clients: usize) {
+        self.clients_connected
+            .store(num_clients as u64, Ordering::Relaxed);
+    }
+
+    /// Add the counters of a round from its report.
+    pub fn record_report(&self, report: &RunReport) {
+        self.rounds.fetch_add(1, Ordering::Relaxed);
+        if report.interrupted.is_some() {
+            self.interrupted_rounds.fetch_add(1, Ordering::Relaxed);
+        }
+        self.client_bytes_received
+            .fetch_add(report.client_comm as u64, Ordering::Relaxed);
+        self.clients
+            .fetch_add(report.num_clients as u64, Ordering::Relaxed);
+        self.clients_dropped
+            .fetch_add(report.dropped_clients.len() as u64, Ordering::Relaxed);
+        let mut phase_seconds = self.phase_seconds.lock().unwrap();
+        for (phase, secs) in &report.phases {
+            *phase_seconds.entry(phase.clone()).or_default() += secs;
+        }
+        let mut clients_verified = self.clients_verified.lock().unwrap();
+        for (check, num_clients) in &report.num_clients_verified {
+            *clients_verified.entry(check.clone()).or_default() += *num_clients as u64;
+        }
+    }
+
+    /// Render in the Prometheus text format, followed by the counters of the
+    /// MPC link `mpc` if any, after folding them.
+    pub fn to_prometheus(&self, mpc: Option<&MetricsRegistry>) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, values: &[(String, String)]| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            for (labels, value) in values {
+                if labels.is_empty() {
+                    writeln!(out, "{} {}", name, value).unwrap();
+                } else {
+                    writeln!(out, "{}{{{}}} {}", name, labels, value).unwrap();
+                }
+            }
+        };
+        let single =
+            |value: &AtomicU64| [(String::new(), value.load(Ordering::Relaxed).to_string())];
+        metric(
+            "elsa_clients_connected",
+            "gauge",
+            "Clients connected to this server.",
+            &single(&self.clients_connected),
+        );
+        metric(
+            "elsa_rounds_total",
+            "counter",
+            "Rounds run, including interrupted ones.",
+            &single(&self.rounds),
+        );
+        metric(
+            "elsa_interrupted_rounds_total",
+            "counter",
+            "Rounds that did not complete.",
+            &single(&self.interrupted_rounds),
+        );
+        metric(
+            "elsa_client_received_bytes_total",
+            "counter",
+            "Bytes received from clients.",
+            &single(&self.client_bytes_received),
+        );
+        metric(
+            "elsa_clients_total",
+            "counter",
+            "Clients of the rounds.",
+            &single(&self.clients),
+        );
+        metric(
+            "elsa_clients_dropped_total",
+            "counter",
+            "Clients that dropped out between the two client phases.",
+            &single(&self.clients_dropped),
+        );
+        let phase_seconds = self
+            .phase_seconds
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(phase, secs)| (format!("phase=\"{}\"", phase), secs.to_string()))
+            .collect::<Vec<_>>();
+        metric(
+            "elsa_phase_seconds_total",
+            "counter",
+            "Seconds spent in each phase.",
+            &phase_seconds,
+        );
+        let clients_verified = self
+            .clients_verified
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(check, n)| (format!("check=\"{}\"", check), n.to_string()))
+            .collect::<Vec<_>>();
+        metric(
+            "elsa_clients_verified_total",
+            "counter",
+            "Clients that passed each check.",
+            &clients_verified,
+        );
+        if let Some(mpc) = mpc {
+            out.push_str(&mpc.fold().to_prometheus());
+        }
+        out
+    }
+
+    /// Serve `/metrics` on `listener` until the task is aborted, with the
+    /// counters of the MPC link `mpc` if any.
+    pub fn serve(
+        self: &Arc<Self>,
+        listener: TcpListener,
+        mpc: Option<Arc<MetricsRegistry>>,
+    ) -> JoinHandle<()> {
+        let metrics = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let socket = match listener.accept().await {
+                    Ok((socket, _)) => socket,
+                    Err(e) => {
+                        warn!("failed to accept a metrics scraper: {}", e);
+                        continue;
+                    },
+                };
+                let (metrics, mpc) = (metrics.clone(), mpc.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = respond(socket, &metrics, mpc.as_deref()).await {
+                        debug!("metrics request failed: {}", e);
+                    }
+                });
+            }
+        })
+    }
+}
+
+/// Read the request head from `socket`, up to the empty line.
+async fn read_request(socket: &mut TcpStream) -> std::io::Result<String> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() >= MAX_REQUEST_SIZE {
+            break;
+        }
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+/// Answer one request, then close the connection.
+async fn respond(
+    mut socket: TcpStream,
+    metrics: &ServerMetrics,
+    mpc: Option<&MetricsRegistry>,
+) -> std::io::Result<()> {
+    let request = time::timeout(REQUEST_TIMEOUT, read_request(&mut socket))
+        .await
+        .map_err(|_| std::io::ErrorKind::TimedOut)??;
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.to_prometheus(mpc)),
+        (Some("GET"), Some(_)) => ("404 Not Found", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "only GET is supported\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bridge::metrics::{Direction, MetricsRegistry};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::ServerMetrics;
+    use crate::server::RunReport;
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    /// Value of the sample `name` in `body`, e.g. `metric{label="x"}`.
+    fn sample(body: &str, name: &str) -> f64 {
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no sample {} in\n{}", name, body))
+            .parse()
+            .unwrap()
+    }
+
+    fn report(client_comm: usize, b2a: f64, ot: usize) -> RunReport {
+        let mut report = RunReport {
+            num_clients: 4,
+            client_comm,
+            ..Default::default()
+        };
+        report.phase("b2a", b2a).verified("ot", ot);
+        report
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        let metrics = Arc::new(ServerMetrics::default());
+        let mpc = Arc::new(MetricsRegistry::new(1));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = metrics.serve(listener, Some(mpc.clone()));
+
+        metrics.set_clients_connected(4);
+        metrics.record_report(&report(1000, 1.5, 2));
+        mpc.record(0, Direction::Sent, 100);
+        let first = get(addr, "/metrics").await;
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(first.contains("# TYPE elsa_rounds_total counter"));
+        assert!(first.contains("# TYPE elsa_clients_connected gauge"));
+        assert_eq!(sample(&first, "elsa_clients_connected"), 4.);
+        assert_eq!(sample(&first, "elsa_rounds_total"), 1.);
+        assert_eq!(sample(&first, "elsa_client_received_bytes_total"), 1000.);
+        assert_eq!(
+            sample(&first, "elsa_phase_seconds_total{phase=\"b2a\"}"),
+            1.5
+        );
+        assert_eq!(
+            sample(&first, "elsa_clients_verified_total{check=\"ot\"}"),
+            2.
+        );
+        let mpc_sent = "mpc_payload_bytes_total{socket=\"0\",direction=\"sent\"}";
+        assert_eq!(sample(&first, mpc_sent), 100.);
+
+        // counters only grow from one scrape to the next
+        metrics.record_report(&report(500, 0.5, 3));
+        mpc.record(0, Direction::Sent, 20);
+        let second = get(addr, "/metrics").await;
+        assert_eq!(sample(&second, "elsa_rounds_total"), 2.);
+        assert_eq!(sample(&second, "elsa_client_received_bytes_total"), 1500.);
+        assert_eq!(
+            sample(&second, "elsa_phase_seconds_total{phase=\"b2a\"}"),
+            2.
+        );
+        assert_eq!(
+            sample(&second, "elsa_clients_verified_total{check=\"ot\"}"),
+            5.
+        );
+        assert_eq!(sample(&second, mpc_sent), 120.);
+
+        assert!(get(addr, "/other").await.starts_with("HTTP/1.1 404"));
+        server.abort();
+    }
+}
@@ -0,0 +1,289 @@
+//! Panic handling shared by all binaries.
+//!
+//! The libraries are built with `panic = "unwind"`, so that a panic in one
+//! client's work can be caught and attributed to that client. The binaries
+//! install a panic hook that turns every panic into a [`PanicRecord`] logged
+//! through `tracing`, and [`run_main`] converts a panic escaping `main` into
+//! the exit code [`PANIC_EXIT_CODE`].
+//!
+//! The uid of the client being processed is taken from the tokio task-local
+//! set by [`scope_client_uid`], or from the thread-local set by
+//! [`with_client_uid`] (for rayon workers).
+
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::Cell,
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    future::Future,
+    panic::{self, AssertUnwindSafe, PanicHookInfo},
+    sync::{Mutex, Once},
+    thread,
+};
+
+use tokio::task::JoinError;
+use tracing::error;
+
+#[cfg(panic = "abort")]
+compile_error!("ELSA needs `panic = \"unwind\"` to attribute panics to clients");
+
+/// Exit code of a binary whose `main` panicked. Differs from the default 101
+/// of Rust so that scripts can tell a reported panic from other crashes.
+pub const PANIC_EXIT_CODE: i32 = 70;
+
+tokio::task_local! {
+    static TASK_CLIENT_UID: u64;
+}
+
+thread_local! {
+    static THREAD_CLIENT_UID: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Number of panics kept for [`take_panic_records`]. A client that panics on
+/// every message must not grow the records without bound, so only the latest
+/// are kept.
+pub const MAX_PANIC_RECORDS: usize = 64;
+
+/// The latest panics, and how many there were in total.
+struct PanicRecords {
+    latest: VecDeque<PanicRecord>,
+    total: usize,
+}
+
+impl PanicRecords {
+    const fn new() -> Self {
+        Self {
+            latest: VecDeque::new(),
+            total: 0,
+        }
+    }
+
+    /// Keep `record`, dropping the oldest one if there are too many.
+    fn push(&mut self, record: PanicRecord) {
+        if self.latest.len() == MAX_PANIC_RECORDS {
+            self.latest.pop_front();
+        }
+        self.latest.push_back(record);
+        self.total += 1;
+    }
+}
+
+static PANIC_RECORDS: Mutex<PanicRecords> = Mutex::new(PanicRecords::new());
+static INSTALL_HOOK: Once = Once::new();
+
+/// A panic captured by the hook.
+#[derive(Debug, Clone)]
+pub struct PanicRecord {
+    pub thread: String,
+    pub client_uid: Option<u64>,
+    pub message: String,
+    pub location: Option<String>,
+    pub backtrace: String,
+}
+
+impl Display for PanicRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "thread '{}' panicked", self.thread)?;
+        if let Some(uid) = self.client_uid {
+            write!(f, " (client {})", uid)?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// Uid of the client whose work is running on this thread or task, if any.
+pub fn current_client_uid() -> Option<u64> {
+    TASK_CLIENT_UID
+        .try_with(|uid| *uid)
+        .ok()
+        .or_else(|| THREAD_CLIENT_UID.with(Cell::get))
+}
+
+fn record_panic(info: &PanicHookInfo) {
+    // the first panic is usually the cause of the others, so its backtrace is
+    // always captured, the others only if `RUST_BACKTRACE` asks for them
+    let is_first = PANIC_RECORDS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .total
+        == 0;
+    let backtrace = if is_first {
+        Backtrace::force_capture()
+    } else {
+        Backtrace::capture()
+    };
+    let record = PanicRecord {
+        thread: thread::current().name().unwrap_or("<unnamed>").to_string(),
+        client_uid: current_client_uid(),
+        message: payload_message(info.payload()),
+        location: info.location().map(ToString::to_string),
+        backtrace: backtrace.to_string(),
+    };
+    error!(
+        thread = %record.thread,
+        client_uid = ?record.client_uid,
+        backtrace = %record.backtrace,
+        "{}",
+        record
+    );
+    if !tracing::dispatcher::has_been_set() {
+        // nobody listens to tracing yet (e.g. panic while parsing options)
+        eprintln!("{}\n{}", record, record.backtrace);
+    }
+    PANIC_RECORDS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(record);
+}
+
+/// Route all panics of this process through [`PanicRecord`]s. Idempotent.
+pub fn install_panic_hook() {
+    INSTALL_HOOK.call_once(|| panic::set_hook(Box::new(record_panic)));
+}
+
+/// Remove and return the panics recorded so far, at most the latest
+/// [`MAX_PANIC_RECORDS`].
+pub fn take_panic_records() -> Vec<PanicRecord> {
+    let mut records = PANIC_RECORDS.lock().unwrap_or_else(|e| e.into_inner());
+    records.latest.drain(..).collect()
+}
+
+/// Run `future` with `uid` as the client of panics inside it.
+pub async fn scope_client_uid<F: Future>(uid: u64, future: F) -> F::Output {
+    TASK_CLIENT_UID.scope(uid, future).await
+}
+
+/// Run `f` on this thread with `uid` as the client of panics inside it.
+pub fn with_client_uid<R>(uid: u64, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<u64>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            THREAD_CLIENT_UID.with(|c| c.set(self.0));
+        }
+    }
+    let _restore = Restore(THREAD_CLIENT_UID.with(|c| c.replace(Some(uid))));
+    f()
+}
+
+/// Output of a joined task. A panic of the task continues unwinding here
+/// without being reported again, since the hook has already recorded it.
+pub fn expect_joined<T>(result: Result<T, JoinError>) -> T {
+    match result {
+        Ok(v) => v,
+        Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("task did not complete: {}", e),
+    }
+}
+
+/// Run the body of a binary. Panics are reported by the hook, and a panic
+/// escaping `main` exits the process with [`PANIC_EXIT_CODE`].
+pub fn run_main<F: FnOnce()>(main: F) {
+    install_panic_hook();
+    if panic::catch_unwind(AssertUnwindSafe(main)).is_err() {
+        let num_panics = PANIC_RECORDS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .total;
+        error!("exiting after {} panic(s)", num_panics);
+        std::process::exit(PANIC_EXIT_CODE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use rayon::prelude::*;
+
+    use super::*;
+
+    fn records_of(uid: u64) -> Vec<PanicRecord> {
+        // tests run in parallel and share the records, so only look at ours
+        let mut records = PANIC_RECORDS.lock().unwrap_or_else(|e| e.into_inner());
+        let (mine, others): (Vec<_>, Vec<_>) = records
+            .latest
+            .drain(..)
+            .partition(|r| r.client_uid == Some(uid));
+        records.latest = others.into();
+        mine
+    }
+
+    #[test]
+    fn test_rayon_panic_attributed() {
+        install_panic_hook();
+        let result = panic::catch_unwind(|| {
+            (0..8u64).into_par_iter().for_each(|i| {
+                with_client_uid(1000 + i, || {
+                    if i == 5 {
+                        panic!("bad share");
+                    }
+                })
+            })
+        });
+        assert!(result.is_err());
+        let records = records_of(1005);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, "bad share");
+        assert!(records[0]
+            .location
+            .as_ref()
+            .unwrap()
+            .contains("panic_report.rs"));
+
+        // the thread-local is restored after unwinding
+        let result = panic::catch_unwind(|| with_client_uid(1100, || panic!("bad mask")));
+        assert!(result.is_err());
+        assert_eq!(records_of(1100).len(), 1);
+        assert_eq!(current_client_uid(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_task_panic_attributed() {
+        install_panic_hook();
+        let handle = tokio::spawn(scope_client_uid(2001, async {
+            tokio::task::yield_now().await;
+            panic!("client {} misbehaves", 2001);
+        }));
+        let joined = handle.await;
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| expect_joined(joined)));
+        assert!(payload.is_err());
+        let records = records_of(2001);
+        // resuming the panic does not report it again
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].message, "client 2001 misbehaves");
+        assert!(records[0].thread.contains("tokio-runtime-worker"));
+        assert!(!records[0].backtrace.is_empty());
+    }
+
+    #[test]
+    fn test_records_bounded() {
+        let mut records = PanicRecords::new();
+        for i in 0..MAX_PANIC_RECORDS + 10 {
+            records.push(PanicRecord {
+                thread: "main".to_string(),
+                client_uid: Some(i as u64),
+                message: "again".to_string(),
+                location: None,
+                backtrace: String::new(),
+            });
+        }
+        assert_eq!(records.total, MAX_PANIC_RECORDS + 10);
+        assert_eq!(records.latest.len(), MAX_PANIC_RECORDS);
+        // the oldest are dropped
+        assert_eq!(records.latest[0].client_uid, Some(10));
+    }
+}
@@ -0,0 +1,486 @@
+//! Tokio runtimes of a server.
+//!
+//! Fetching client messages means many connections with small tasks, while
+//! the MPC link is a few loops moving large buffers. On a shared runtime, a
+//! burst of client wakeups delays the MPC read and write loops, so a server
+//! runs each on its own runtime. Tasks only talk across runtimes through
+//! channels and `Arc`s, which do not care which runtime polls them.
+//!
+//! `tokio::spawn` lands on the runtime of the caller, so client work must be
+//! started on the client runtime explicitly with [`run_on`]. A `ClientsPool`
+//! keeps spawning its per-client tasks on the runtime it was created on, and
+//! an `MpcConnection` runs its loops on the runtime it was created on.
+//!
+//! The CPU-bound phases run on the rayon global pool, the compute pool. Its
+//! size is set once with [`install_compute_pool`], so that it does not
+//! oversubscribe the cores along with the worker threads of both runtimes.
+//!
+//! The protocol spawns a task per client in each phase, but only a few of
+//! them make progress at once. A [`ClientGate`] bounds how many run at once,
+//! so that only that many clients' working buffers are alive.
+
+use std::{
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use rayon::{ThreadPoolBuildError, ThreadPoolBuilder};
+use tokio::{
+    runtime::{Builder, Handle, Runtime},
+    sync::{watch, Semaphore},
+    task::JoinHandle,
+};
+
+use crate::panic_report::expect_joined;
+
+/// The client runtime and the MPC runtime of a server.
+pub struct ServerRuntimes {
+    client: Runtime,
+    mpc: Runtime,
+}
+
+impl ServerRuntimes {
+    /// Build both runtimes with the given number of worker threads, or one
+    /// worker per core if `None`.
+    pub fn new(client_threads: Option<usize>, mpc_threads: Option<usize>) -> io::Result<Self> {
+        Ok(Self {
+            client: build_runtime("elsa-client", client_threads)?,
+            mpc: build_runtime("elsa-mpc", mpc_threads)?,
+        })
+    }
+
+    /// Runtime of the client connections.
+    pub fn client(&self) -> &Handle {
+        self.client.handle()
+    }
+
+    /// Runtime of the MPC connection and the protocol.
+    pub fn mpc(&self) -> &Handle {
+        self.mpc.handle()
+    }
+
+    /// Run `future` on the MPC runtime and block until it completes. Tasks it
+    /// spawns with `tokio::spawn` run on the MPC runtime.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.mpc.block_on(future)
+    }
+}
+
+fn build_runtime(name: &str, worker_threads: Option<usize>) -> io::Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.thread_name(name).enable_all();
+    if let Some(worker_threads) = worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    builder.build()
+}
+
+/// Build the compute pool with `threads` threads, or one per core if `None`.
+/// Must be called before the first `par_iter` of the process. Returns the
+/// number of threads of the pool.
+pub fn install_compute_pool(threads: Option<usize>) -> Result<usize, ThreadPoolBuildError> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads.unwrap_or(0))
+        .thread_name(|i| format!("elsa-compute-{}", i))
+        .build_global()?;
+    Ok(rayon::current_num_threads())
+}
+
+/// Run the CPU-bound `op` on the compute pool, from a task of a multi-thread
+/// runtime. The worker of the task blocks until `op` is done, and its other
+/// tasks move to the other workers meanwhile.
+pub fn block_on_compute<R, F>(op: F) -> R
+where
+    F: FnOnce() -> R + Send,
+    R: Send,
+{
+    // `scope` runs `op` on a thread of the global pool
+    tokio::task::block_in_place(|| rayon::scope(|_| op()))
+}
+
+/// Run `future` on the runtime of `handle`, and wait for it from any runtime.
+/// A panic of `future` continues unwinding here. If the returned future is
+/// dropped, e.g. on Ctrl-C, `future` is cancelled as well, so that it stops
+/// e.g. accepting clients.
+pub async fn run_on<F>(handle: &Handle, future: F) -> F::Output
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    struct AbortOnDrop<T>(JoinHandle<T>);
+    impl<T> Drop for AbortOnDrop<T> {
+        fn drop(&mut self) {
+            // no-op if the task is complete
+            self.0.abort();
+        }
+    }
+    let mut task = AbortOnDrop(handle.spawn(future));
+    expect_joined((&mut task.0).await)
+}
+
+tokio::task_local! {
+    static CLIENT_GATE: ClientGate;
+}
+
+/// Bound on the per-client tasks of the protocol that run at once, see
+/// [`spawn_gated`]. A task waits for a slot before it starts, and frees it
+/// when it is done. Slots are handed out in spawn order, and both servers
+/// spawn the tasks of a phase in the same order, so a client that waits for
+/// its messages from the peer is also running there, whatever the bound of
+/// the peer.
+#[derive(Debug, Clone, Default)]
+pub struct ClientGate(Option<Arc<Slots>>);
+
+#[derive(Debug)]
+struct Slots {
+    free: Semaphore,
+    /// Ticket of the next task to take a slot.
+    turn: watch::Sender<u64>,
+    next_ticket: AtomicU64,
+}
+
+impl ClientGate {
+    /// At most `max_inflight` clients at once. Panics if it is 0.
+    pub fn new(max_inflight: usize) -> Self {
+        assert!(max_inflight > 0, "at least one client must be in flight");
+        Self(Some(Arc::new(Slots {
+            free: Semaphore::new(max_inflight),
+            turn: watch::channel(0).0,
+            next_ticket: AtomicU64::new(0),
+        })))
+    }
+
+    /// No bound, the gate outside of any [`ClientGate::scope`].
+    pub fn unlimited() -> Self {
+        Self(None)
+    }
+
+    /// Run `future`, e.g. a round of the protocol, with this gate as
+    /// [`ClientGate::current`].
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        CLIENT_GATE.scope(self, future).await
+    }
+
+    /// Gate of the enclosing [`ClientGate::scope`] of this task.
+    pub fn current() -> Self {
+        CLIENT_GATE.try_with(Clone::clone).unwrap_or_default()
+    }
+
+    /// Run `task` once a slot is free. The order is taken now, rather than
+    /// when the returned future is first polled, which is in no particular
+    /// order for spawned tasks.
+    fn gated<F: Future>(self, task: F) -> impl Future<Output = F::Output> {
+        let ticket = self
+            .0
+            .as_ref()
+            .map(|slots| slots.next_ticket.fetch_add(1, Ordering::Relaxed));
+        async move {
+            let _permit = match (&self.0, ticket) {
+                (Some(slots), Some(ticket)) => {
+                    slots
+                        .turn
+                        .subscribe()
+                        .wait_for(|turn| *turn == ticket)
+                        .await
+                        .expect("the gate outlives its tasks");
+                    let permit = slots.free.acquire().await.expect("gate is never closed");
+                    slots.turn.send_modify(|turn| *turn += 1);
+                    Some(permit)
+                },
+                _ => None,
+            };
+            task.await
+        }
+    }
+}
+
+/// Spawn the task of a client, which starts once the [`ClientGate::current`]
+/// of the caller has a free slot. Tasks that exchange messages with the peer
+/// must be spawned in the same order on both servers, see [`ClientGate`].
+pub fn spawn_gated<T, F>(task: F) -> JoinHandle<T>
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    tokio::spawn(ClientGate::current().gated(task))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use bridge::{
+        client_server::ClientsPool,
+        id_tracker::{RecvId, SendId},
+        mpc_conn::MpcConnection,
+        tcp_bridge::{ClientID, ClientToken, TcpConnection},
+    };
+    use serialize::UseCast;
+    use tokio::{
+        net::{TcpListener, TcpStream},
+        runtime::{Builder, Handle},
+    };
+
+    use super::{block_on_compute, run_on, spawn_gated, ClientGate, ServerRuntimes};
+
+    fn thread_name() -> String {
+        thread::current().name().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn test_client_pool_stays_on_client_runtime() {
+        const NUM_CLIENTS: u64 = 4;
+        let runtimes = ServerRuntimes::new(Some(1), Some(1)).unwrap();
+        let client = runtimes.client().clone();
+        runtimes.block_on(async move {
+            let spawned = tokio::spawn(async { thread_name() }).await.unwrap();
+            assert_eq!(spawned, "elsa-mpc");
+            let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+            listener.set_nonblocking(true).unwrap();
+            let addr = listener.local_addr().unwrap();
+            let pool = run_on(&client, async move {
+                assert_eq!(thread_name(), "elsa-client");
+                let listener = TcpListener::from_std(listener).unwrap();
+                ClientsPool::new(NUM_CLIENTS as usize, listener, ClientToken::default()).await
+            });
+            let clients = async {
+                let mut conns = Vec::new();
+                for uid in 0..NUM_CLIENTS {
+                    let socket = TcpStream::connect(addr).await.unwrap();
+                    let (conn, registered) = TcpConnection::new_client_side(
+                        socket,
+                        ClientID::new(uid),
+                        ClientToken::default(),
+                    );
+                    registered.await.unwrap();
+                    conn.send_message(SendId::FIRST, UseCast(uid))
+                        .unwrap()
+                        .await
+                        .unwrap();
+                    conns.push(conn);
+                }
+                conns
+            };
+            let (pool, _conns) = tokio::join!(pool, clients);
+
+            // used from the MPC runtime, but the per-client tasks are spawned
+            // on the client runtime
+            let received = pool
+                .subscribe_and_get::<UseCast<u64>>(RecvId::FIRST)
+                .await
+                .unwrap();
+            let expected = (0..NUM_CLIENTS)
+                .map(|uid| (ClientID::new(uid), uid))
+                .collect::<Vec<_>>();
+            assert_eq!(received, expected);
+        });
+    }
+
+    #[test]
+    fn test_dropped_run_on_cancels_task() {
+        let runtimes = ServerRuntimes::new(Some(1), Some(1)).unwrap();
+        let client = runtimes.client().clone();
+        runtimes.block_on(async move {
+            let (holder, held) = tokio::sync::oneshot::channel::<()>();
+            let task = run_on(&client, async move {
+                let _holder = holder;
+                std::future::pending::<()>().await
+            });
+            assert!(tokio::time::timeout(Duration::from_millis(50), task)
+                .await
+                .is_err());
+            // the task is dropped along with what it holds
+            let cancelled = tokio::time::timeout(Duration::from_secs(5), held).await;
+            assert!(cancelled.expect("task still runs").is_err());
+        });
+    }
+
+    /// Largest number of the tasks in flight at once, and the order in
+    /// which they start, with a gate of `max_inflight`.
+    fn run_gated_tasks(max_inflight: usize) -> (usize, Vec<usize>) {
+        let runtimes = ServerRuntimes::new(Some(1), Some(4)).unwrap();
+        runtimes.block_on(ClientGate::new(max_inflight).scope(async {
+            let inflight = Arc::new(AtomicUsize::new(0));
+            let max_seen = Arc::new(AtomicUsize::new(0));
+            let starts = Arc::new(Mutex::new(Vec::new()));
+            let handles = (0..16)
+                .map(|i| {
+                    let (inflight, max_seen) = (inflight.clone(), max_seen.clone());
+                    let starts = starts.clone();
+                    spawn_gated(async move {
+                        starts.lock().unwrap().push(i);
+                        let now = inflight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_seen.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                        inflight.fetch_sub(1, Ordering::SeqCst);
+                        i
+                    })
+                })
+                .collect::<Vec<_>>();
+            for (i, handle) in handles.into_iter().enumerate() {
+                assert_eq!(handle.await.unwrap(), i);
+            }
+            let starts = starts.lock().unwrap().clone();
+            (max_seen.load(Ordering::SeqCst), starts)
+        }))
+    }
+
+    #[test]
+    fn test_client_gate_bounds_inflight_tasks() {
+        assert_eq!(run_gated_tasks(2).0, 2);
+        // one at a time, in spawn order
+        let (max_seen, starts) = run_gated_tasks(1);
+        assert_eq!(max_seen, 1);
+        assert_eq!(starts, (0..16).collect::<Vec<_>>());
+        // outside of a scope, tasks are not gated
+        assert!(ClientGate::current().0.is_none());
+    }
+
+    #[test]
+    fn test_block_on_compute_runs_on_compute_pool() {
+        let runtimes = ServerRuntimes::new(Some(1), Some(2)).unwrap();
+        let names = runtimes.block_on(async {
+            tokio::spawn(async {
+                let caller = thread_name();
+                let worker = block_on_compute(|| {
+                    rayon::current_thread_index().expect("not on a rayon thread")
+                });
+                (caller, worker)
+            })
+            .await
+            .unwrap()
+        });
+        assert_eq!(names.0, "elsa-mpc");
+        assert!(names.1 < rayon::current_num_threads());
+    }
+
+    /// Wall-clock work of handling one client message, e.g. deserializing it.
+    fn busy_wait(duration: Duration) {
+        let start = Instant::now();
+        while start.elapsed() < duration {}
+    }
+
+    /// Clients that keep connecting, sending one message, and disconnecting,
+    /// until `stop` is set. Runs on the runtime of `handle`.
+    fn spawn_client_churn(handle: &Handle, stop: Arc<AtomicBool>) {
+        const NUM_CONNECTORS: usize = 32;
+        let listener = handle
+            .block_on(TcpListener::bind(("127.0.0.1", 0)))
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        handle.spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let conn = TcpConnection::new_server_side(socket, ClientToken::default()).await;
+                    if conn.subscribe_and_get_bytes(RecvId::FIRST).await.is_ok() {
+                        busy_wait(Duration::from_micros(500));
+                    }
+                });
+            }
+        });
+        for _ in 0..NUM_CONNECTORS {
+            let stop = stop.clone();
+            handle.spawn(async move {
+                while !stop.load(Ordering::Relaxed) {
+                    let socket = match TcpStream::connect(addr).await {
+                        Ok(socket) => socket,
+                        Err(_) => continue,
+                    };
+                    let (conn, registered) = TcpConnection::new_client_side(
+                        socket,
+                        ClientID::new(0),
+                        ClientToken::default(),
+                    );
+                    if registered.await.is_err() {
+                        continue;
+                    }
+                    if let Ok(sent) = conn.send_message(SendId::FIRST, UseCast(0u64)) {
+                        let _ = sent.await;
+                    }
+                }
+            });
+        }
+    }
+
+    /// 99th percentile round trip of a small message between two MPC
+    /// connections on `mpc`, while clients churn on `client`.
+    fn mpc_p99_under_churn(client: &Handle, mpc: &Handle) -> Duration {
+        const NUM_ROUND_TRIPS: u64 = 400;
+        let (alice, bob) = mpc.block_on(async {
+            let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::join!(
+                MpcConnection::new_as_alice_with_listener(listener, 2),
+                MpcConnection::new_as_bob(addr, 2)
+            )
+        });
+        mpc.spawn(async move {
+            for i in 0..NUM_ROUND_TRIPS {
+                let msg = bob
+                    .subscribe_and_get::<UseCast<u64>>(RecvId(i + 100))
+                    .await
+                    .unwrap();
+                bob.send_message(SendId(i + 100), UseCast(msg));
+            }
+        });
+
+        let stop = Arc::new(AtomicBool::new(false));
+        spawn_client_churn(client, stop.clone());
+        let mut round_trips = mpc.block_on(async move {
+            // let the churn ramp up
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let mut round_trips = Vec::with_capacity(NUM_ROUND_TRIPS as usize);
+            for i in 0..NUM_ROUND_TRIPS {
+                let start = Instant::now();
+                alice.send_message(SendId(i + 100), UseCast(i));
+                let echo = alice
+                    .subscribe_and_get::<UseCast<u64>>(RecvId(i + 100))
+                    .await
+                    .unwrap();
+                assert_eq!(echo, i);
+                round_trips.push(start.elapsed());
+            }
+            round_trips
+        });
+        stop.store(true, Ordering::Relaxed);
+
+        round_trips.sort_unstable();
+        round_trips[round_trips.len() * 99 / 100]
+    }
+
+    // timing-dependent, run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_split_runtimes_reduce_mpc_latency_under_churn() {
+        let shared = Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+        let shared_p99 = mpc_p99_under_churn(shared.handle(), shared.handle());
+        shared.shutdown_background();
+
+        let runtimes = ServerRuntimes::new(Some(1), Some(1)).unwrap();
+        let split_p99 = mpc_p99_under_churn(runtimes.client(), runtimes.mpc());
+        let ServerRuntimes { client, mpc } = runtimes;
+        client.shutdown_background();
+        mpc.shutdown_background();
+
+        println!(
+            "MPC round trip p99 under client churn: shared runtime {:?}, split runtimes {:?}",
+            shared_p99, split_p99
+        );
+        assert!(split_p99 < shared_p99);
+    }
+}
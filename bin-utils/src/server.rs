@@ -1,5 +1,45 @@
 pub use crate::InputSize;
-use clap::{Arg, ArgMatches, Command};
+use crate::{
+    interrupt::Interrupted,
+    metrics::ServerMetrics,
+    runtime::{install_compute_pool, ClientGate, ServerRuntimes},
+    signing::ClientKeys,
+    simulation, tls,
+};
+pub use bridge::client_server::PartitionPolicy;
+use bridge::{
+    client_dump::ClientRecording,
+    id_tracker::MAX_SESSION_ROUNDS,
+    mpc_conn::MpcConnection,
+    netem::NetworkProfile,
+    perf_trace::{self, Span},
+    tcp_bridge::{ClientID, ClientToken},
+    tls::ServerTls,
+};
+use clap::{Arg, ArgMatches, Command, ErrorKind};
+use crypto_primitives::{
+    aggregate::RingBudget,
+    self_test::{
+        self, find_profile, HardwareProfile, Kernel, Measurement, SelfTestReport,
+        REFERENCE_PROFILES, SELF_TEST_SIZE,
+    },
+    uint::UInt,
+    utils::HookRegistry,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::info;
 
 pub struct Options<C = ()> {
     pub client_port: u16,
@@ -7,9 +47,56 @@ pub struct Options<C = ()> {
     pub gsize: usize,
     pub is_bob: bool,
     pub mpc_addr: String,
+    /// Address to listen to the clients on, `0.0.0.0` by default.
+    pub client_bind_addr: IpAddr,
+    /// Address that Alice listens to Bob on, or that Bob connects to Alice
+    /// from, to pick the interface of the MPC connection. `0.0.0.0` by
+    /// default, i.e. all interfaces for Alice and any for Bob.
+    pub mpc_bind_addr: IpAddr,
     pub num_mpc_sockets: usize,
+    /// Link emulated on the MPC connection, see
+    /// `MpcConnection::with_network_profile`. None by default.
+    pub mpc_network: NetworkProfile,
     pub log_level: tracing_core::Level,
     pub input_size: InputSize,
+    pub sec_param: usize,
+    pub partition: PartitionPolicy,
+    /// Token the clients must register with, all zeros if not set.
+    pub client_token: ClientToken,
+    /// Session the clients bind into their share commitments.
+    pub session: u64,
+    /// Worker threads of the client runtime, or one per core if `None`.
+    pub client_io_threads: Option<usize>,
+    /// Worker threads of the MPC runtime, or one per core if `None`.
+    pub mpc_io_threads: Option<usize>,
+    /// Threads of the compute pool, or one per core if `None`.
+    pub compute_threads: Option<usize>,
+    /// Per-client tasks that run at once, or two per core if `None`. See
+    /// `ClientGate`.
+    pub max_inflight_clients: Option<usize>,
+    /// Seed of the randomness this server samples locally, to reproduce a
+    /// run. Random if `None`. Also the seed of the dummy shares of `no-ot`,
+    /// shared by both servers.
+    pub seed: Option<u64>,
+    pub self_test: bool,
+    /// Reference the self test compares this host with.
+    pub self_test_profile: HardwareProfile,
+    /// Run even if the aggregate can wrap around in the share ring, see
+    /// `check_ring_budget`.
+    pub allow_overflow: bool,
+    /// Whether this is a production run, which refuses to start if any
+    /// simulation helper is compiled in.
+    pub production: bool,
+    /// Write the `RunReport` to this file instead of stdout.
+    pub report: Option<String>,
+    /// Print the legacy CSV line instead of the `RunReport` on stdout.
+    pub legacy_csv: bool,
+    /// Include the `start_timer!` spans in the `RunReport`.
+    pub trace_spans: bool,
+    /// Port to serve `metrics` on, see `serve_metrics`.
+    pub metrics_port: Option<u16>,
+    /// Counters of the rounds, fed by `emit_report`.
+    pub metrics: Arc<ServerMetrics>,
     pub custom_args: C,
 }
 
@@ -58,9 +145,23 @@ impl<C> Options<C> {
                     .short('m')
                     .long("mpc_addr")
                     .takes_value(true)
-                    .required(true)
+                    .required_unless_present("self_test")
                     .help("address of alice (should be a port number if I'm alice, otherwise, should be a complete address)")
             )
+            .arg(
+                Arg::new("client_bind_addr")
+                    .long("client-bind-addr")
+                    .takes_value(true)
+                    .default_value("0.0.0.0")
+                    .help("address of the interface to listen to clients on"),
+            )
+            .arg(
+                Arg::new("mpc_bind_addr")
+                    .long("mpc-bind-addr")
+                    .takes_value(true)
+                    .default_value("0.0.0.0")
+                    .help("address of the interface of the MPC connection: Alice listens on it, Bob connects from it (default: any)"),
+            )
             .arg(
                 Arg::new("num_mpc_sockets")
                     .short('s')
@@ -69,12 +170,133 @@ impl<C> Options<C> {
                     .default_value("16")
                     .help("number of mpc sockets to use")
             )
+            .arg(
+                Arg::new("emulate_rtt_ms")
+                    .long("emulate-rtt-ms")
+                    .takes_value(true)
+                    .help("emulate a WAN to the peer: delay each message received from it by half of this round-trip time (default: 0)"),
+            )
+            .arg(
+                Arg::new("emulate_bw_mbps")
+                    .long("emulate-bw-mbps")
+                    .takes_value(true)
+                    .help("emulate a WAN to the peer: receive from it at most this many Mbit/s (default: unlimited)"),
+            )
             .arg(Arg::new("input_size")
                 .short('i')
                 .long("input_size")
                 .takes_value(true)
                 .default_value("8")
-                .help("size of input"))
+                .help("size of input in bits: 8, 32 or 64"))
+            .arg(
+                Arg::new("sec_param")
+                    .long("sec-param")
+                    .takes_value(true)
+                    .default_value("40")
+                    .help("statistical security parameter for OT verification (must match clients)"),
+            )
+            .arg(
+                Arg::new("partition")
+                    .long("partition")
+                    .takes_value(true)
+                    .default_value("parity")
+                    .help("which server is OT sender for each client: parity, or ratio:<a>:<b> (must match the peer and clients)"),
+            )
+            .arg(
+                Arg::new("client_token")
+                    .long("client-token")
+                    .takes_value(true)
+                    .help("64 hex digits that clients must register with, to reject stray connections (must match the clients)"),
+            )
+            .arg(
+                Arg::new("session")
+                    .long("session")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("session id bound into the share commitments of the clients (must match the clients)"),
+            )
+            .arg(
+                Arg::new("compute_threads")
+                    .long("compute-threads")
+                    .takes_value(true)
+                    .help("threads of the pool of the CPU-bound phases, e.g. OT verification and B2A (default: one per core)"),
+            )
+            .arg(
+                Arg::new("max_inflight_clients")
+                    .long("max-inflight-clients")
+                    .takes_value(true)
+                    .help("clients whose per-client tasks run at once, which bounds the memory of their buffers (default: two per core)"),
+            )
+            .arg(
+                Arg::new("io_threads")
+                    .long("io-threads")
+                    .takes_value(true)
+                    .help("worker threads of each runtime, unless set by --client-io-threads or --mpc-io-threads (default: one per core)"),
+            )
+            .arg(
+                Arg::new("client_io_threads")
+                    .long("client-io-threads")
+                    .takes_value(true)
+                    .help("worker threads of the runtime of client connections (default: one per core)"),
+            )
+            .arg(
+                Arg::new("mpc_io_threads")
+                    .long("mpc-io-threads")
+                    .takes_value(true)
+                    .help("worker threads of the runtime of the MPC connection (default: one per core)"),
+            )
+            .arg(
+                Arg::new("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .conflicts_with("production")
+                    .help("seed the randomness sampled locally, to reproduce a run; the chi seed shares stay random (default: random). The dummy shares of `no-ot` are seeded with it on both servers, which must pass the same seed (default: 0)"),
+            )
+            .arg(
+                Arg::new("self_test")
+                    .long("self-test")
+                    .help("benchmark this host and project the duration of each phase, without connecting to any peer"),
+            )
+            .arg(
+                Arg::new("self_test_profile")
+                    .long("self-test-profile")
+                    .takes_value(true)
+                    .possible_values(REFERENCE_PROFILES.map(|p| p.name))
+                    .default_value(REFERENCE_PROFILES[0].name)
+                    .help("reference hardware that --self-test compares this host with"),
+            )
+            .arg(
+                Arg::new("allow_overflow")
+                    .long("allow-overflow")
+                    .help("run even if the aggregate of the clients can wrap around in the share ring, e.g. if the inputs are known to be small"),
+            )
+            .arg(
+                Arg::new("production")
+                    .long("production")
+                    .help("refuse to start if insecure simulation helpers are compiled in"),
+            )
+            .arg(
+                Arg::new("report")
+                    .long("report")
+                    .takes_value(true)
+                    .help("write the report of the run as JSON to this file instead of stdout"),
+            )
+            .arg(
+                Arg::new("legacy_csv")
+                    .long("legacy-csv")
+                    .help("print the legacy CSV line on stdout instead of the JSON report"),
+            )
+            .arg(
+                Arg::new("trace_spans")
+                    .long("trace-spans")
+                    .help("include the nested timer spans in the report (needs bridge/print-trace)"),
+            )
+            .arg(
+                Arg::new("metrics_port")
+                    .long("metrics-port")
+                    .takes_value(true)
+                    .help("serve metrics in the Prometheus text format on /metrics at this port"),
+            )
             .arg(
                 Arg::new("verbose")
                     .short('v')
@@ -84,7 +306,17 @@ impl<C> Options<C> {
         for arg in custom_args {
             builder = builder.arg(arg);
         }
-        let matches = builder.get_matches();
+        let matches = builder.get_matches_mut();
+
+        let production = matches.is_present("production");
+        if production && simulation::helpers_compiled() {
+            builder
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--production cannot be used with simulation helpers compiled in (features `simulation-helpers` or `no-comm`)",
+                )
+                .exit();
+        }
 
         let num_clients = matches
             .value_of("num_clients")
@@ -95,15 +327,29 @@ impl<C> Options<C> {
         let is_bob = matches.is_present("bob");
         let client_port = matches
             .value_of("port")
-            .unwrap_or_else(|| if is_bob { "6666" } else { "6667" })
+            .unwrap_or(if is_bob { "6666" } else { "6667" })
             .parse::<u16>()
             .unwrap();
-        let mpc_addr = matches.value_of("mpc_addr").unwrap().to_string();
+        let mpc_addr = matches.value_of("mpc_addr").unwrap_or_default().to_string();
+        let bind_addr = |name| matches.value_of(name).unwrap().parse::<IpAddr>().unwrap();
+        let client_bind_addr = bind_addr("client_bind_addr");
+        let mpc_bind_addr = bind_addr("mpc_bind_addr");
         let num_mpc_sockets = matches
             .value_of("num_mpc_sockets")
             .unwrap()
             .parse::<usize>()
             .unwrap();
+        let mpc_network = NetworkProfile {
+            rtt: Duration::from_millis(
+                matches
+                    .value_of("emulate_rtt_ms")
+                    .map_or(0, |ms| ms.parse::<u64>().unwrap()),
+            ),
+            bandwidth_bytes_per_sec: matches
+                .value_of("emulate_bw_mbps")
+                .map(|mbps| mbps.parse::<u64>().unwrap() * 1_000_000 / 8),
+            jitter: Duration::ZERO,
+        };
         let tracing_level = if matches.is_present("verbose") {
             tracing_core::Level::DEBUG
         } else {
@@ -114,6 +360,39 @@ impl<C> Options<C> {
             .unwrap()
             .parse::<InputSize>()
             .unwrap();
+        let sec_param = matches
+            .value_of("sec_param")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap();
+        let partition = matches
+            .value_of("partition")
+            .unwrap()
+            .parse::<PartitionPolicy>()
+            .unwrap();
+        let client_token = matches
+            .value_of("client_token")
+            .map(|t| t.parse::<ClientToken>().unwrap())
+            .unwrap_or_default();
+        let session = matches.value_of("session").unwrap().parse::<u64>().unwrap();
+        let threads = |name| matches.value_of(name).map(|n| n.parse::<usize>().unwrap());
+        let io_threads = threads("io_threads");
+        let client_io_threads = threads("client_io_threads").or(io_threads);
+        let mpc_io_threads = threads("mpc_io_threads").or(io_threads);
+        let compute_threads = threads("compute_threads");
+        let max_inflight_clients = threads("max_inflight_clients");
+        let seed = matches.value_of("seed").map(|s| s.parse::<u64>().unwrap());
+        let self_test = matches.is_present("self_test");
+        let self_test_profile =
+            find_profile(matches.value_of("self_test_profile").unwrap()).unwrap();
+        let allow_overflow = matches.is_present("allow_overflow");
+        let report = matches.value_of("report").map(String::from);
+        let legacy_csv = matches.is_present("legacy_csv");
+        let trace_spans = matches.is_present("trace_spans");
+        perf_trace::record_spans(trace_spans);
+        let metrics_port = matches
+            .value_of("metrics_port")
+            .map(|port| port.parse::<u16>().expect("invalid metrics port"));
         let custom_args = parser(&matches);
 
         Options {
@@ -122,9 +401,30 @@ impl<C> Options<C> {
             gsize,
             is_bob,
             mpc_addr,
+            client_bind_addr,
+            mpc_bind_addr,
             num_mpc_sockets,
+            mpc_network,
             log_level: tracing_level,
             input_size,
+            sec_param,
+            partition,
+            client_token,
+            session,
+            client_io_threads,
+            mpc_io_threads,
+            compute_threads,
+            max_inflight_clients,
+            seed,
+            self_test,
+            self_test_profile,
+            allow_overflow,
+            production,
+            report,
+            legacy_csv,
+            trace_spans,
+            metrics_port,
+            metrics: Arc::default(),
             custom_args,
         }
     }
@@ -132,6 +432,159 @@ impl<C> Options<C> {
     pub fn is_alice(&self) -> bool {
         !self.is_bob
     }
+
+    /// Address to listen to the clients on.
+    pub fn client_listen_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.client_bind_addr, self.client_port)
+    }
+
+    /// Exit with an error if the aggregate of `total_weight`, e.g. the
+    /// number of clients, over inputs of `input_size` can wrap around in
+    /// ring `A`. Only warn with `--allow-overflow`.
+    pub fn check_ring_budget<A: UInt>(&self, total_weight: u64) {
+        let budget = RingBudget::with_bits(self.input_size.num_bits(), A::NUM_BITS);
+        if let Err(e) = budget.check(total_weight) {
+            if self.allow_overflow {
+                eprintln!("WARNING: {}; the aggregate may be wrong", e);
+            } else {
+                eprintln!("{} (pass --allow-overflow to run anyway)", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /// With `--self-test`, benchmark this host, compare it with the profile
+    /// of `--self-test-profile`, print the report with the projected phases
+    /// of a run with these options, and return `true`: `main` stops there.
+    pub fn run_self_test(&self) -> bool {
+        if !self.self_test {
+            return false;
+        }
+        let mut measured = self_test::run_benchmarks(SELF_TEST_SIZE);
+        match measure_disk_write(SELF_TEST_SIZE) {
+            Ok(disk_write) => measured.push(disk_write),
+            Err(e) => eprintln!("WARNING: cannot benchmark the disk: {}", e),
+        }
+        let report = SelfTestReport::new(
+            &measured,
+            &self.self_test_profile,
+            self.gsize,
+            self.input_size.num_bits(),
+            self.num_clients,
+            self.sec_param,
+        );
+        println!("{}", report);
+        true
+    }
+
+    /// Build the client and MPC runtimes with the configured threads.
+    pub fn runtimes(&self) -> std::io::Result<ServerRuntimes> {
+        ServerRuntimes::new(self.client_io_threads, self.mpc_io_threads)
+    }
+
+    /// Build the compute pool with the configured threads. Call before
+    /// anything else, see [`install_compute_pool`].
+    pub fn install_compute_pool(&self) -> Result<usize, rayon::ThreadPoolBuildError> {
+        install_compute_pool(self.compute_threads)
+    }
+
+    /// Gate of the per-client tasks, to run the protocol in with
+    /// `ClientGate::scope`.
+    pub fn client_gate(&self) -> ClientGate {
+        let per_core = || thread::available_parallelism().map_or(1, |n| n.get());
+        ClientGate::new(self.max_inflight_clients.unwrap_or_else(|| 2 * per_core()))
+    }
+
+    /// Rng of the randomness this server samples locally. With `--seed`,
+    /// Alice is seeded with `seed` and Bob with `seed + 1`.
+    pub fn local_rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(self.is_bob as u64)),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// An empty report of a run of `server` with these options.
+    pub fn new_report(&self, server: &str) -> RunReport {
+        RunReport {
+            server: server.to_string(),
+            is_alice: self.is_alice(),
+            num_clients: self.num_clients,
+            gsize: self.gsize,
+            ..Default::default()
+        }
+    }
+
+    /// Emit `report` at the end of a run: as JSON to the `--report` file, or
+    /// to stdout unless `--legacy-csv` prints `legacy` there instead. With
+    /// `--trace-spans`, the overlap of the pipelined phases is logged too.
+    /// The reports of the rounds after the first of a session are appended
+    /// to the file, one per line.
+    pub fn emit_report(&self, mut report: RunReport, legacy: &LegacyCsv) {
+        self.metrics.record_report(&report);
+        // taken on every run, so that the registry does not grow
+        let hooks = HookRegistry::global().take();
+        if self.trace_spans {
+            report.spans = perf_trace::take_spans();
+            info!("overlap of the pipelined phases:\n{}", hooks);
+        }
+        if self.legacy_csv {
+            println!("{}", report.legacy_csv(legacy));
+        }
+        match &self.report {
+            Some(path) => {
+                let append = matches!(report.round, Some(round) if round > 0);
+                fs::OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(path)
+                    .and_then(|mut file| writeln!(file, "{}", report.to_json()))
+                    .unwrap_or_else(|e| panic!("cannot write the report to {}: {}", path, e))
+            },
+            None if !self.legacy_csv => println!("{}", report.to_json()),
+            None => (),
+        }
+    }
+
+    /// Emit `report` of a round stopped by `interrupted`, with the MPC
+    /// traffic so far, and exit with the exit code of `interrupted`.
+    pub fn exit_interrupted(
+        &self,
+        mut report: RunReport,
+        interrupted: Interrupted,
+        peer: &MpcConnection,
+        legacy: &LegacyCsv,
+    ) -> ! {
+        report.interrupted = Some(interrupted);
+        report.mpc_comm_sent = peer.num_bytes_sent();
+        report.mpc_comm_recv = peer.num_bytes_received();
+        self.emit_report(report, legacy);
+        std::process::exit(interrupted.exit_code())
+    }
+
+    /// With `--metrics-port`, serve `metrics` and the traffic with `peer` on
+    /// all interfaces, until the returned task is aborted.
+    pub async fn serve_metrics(&self, peer: &MpcConnection) -> Option<JoinHandle<()>> {
+        let port = self.metrics_port?;
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+            .await
+            .unwrap_or_else(|e| panic!("cannot listen for metrics on port {}: {}", port, e));
+        info!("serving metrics on port {}", port);
+        Some(self.metrics.serve(listener, Some(peer.metrics().clone())))
+    }
+
+    /// Log the number of threads of the compute pool and of both runtimes.
+    pub fn log_threads(&self) {
+        let per_core = || thread::available_parallelism().map_or(1, |n| n.get());
+        info!(
+            "threads: {} compute, {} client io, {} mpc io",
+            rayon::current_num_threads(),
+            self.client_io_threads.unwrap_or_else(per_core),
+            self.mpc_io_threads.unwrap_or_else(per_core),
+        );
+    }
 }
 
 impl Options {
@@ -140,3 +593,597 @@ impl Options {
         Self::load_from_args_custom(program_name, [], |_| ())
     }
 }
+
+/// Statistics of one run of a server, emitted as JSON by
+/// `Options::emit_report`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub server: String,
+    pub is_alice: bool,
+    /// Round of a session of several rounds, see `SessionOptions`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub round: Option<u64>,
+    pub num_clients: usize,
+    pub gsize: usize,
+    /// Bytes received from clients.
+    pub client_comm: usize,
+    pub mpc_comm_sent: usize,
+    pub mpc_comm_recv: usize,
+    /// Seconds of each phase, by name.
+    pub phases: BTreeMap<String, f64>,
+    /// Number of clients that passed each check, by name.
+    pub num_clients_verified: BTreeMap<String, usize>,
+    /// Uids of the clients that dropped out between the two client phases,
+    /// and are not in the aggregate.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped_clients: Vec<u64>,
+    /// Set if the round did not complete, and the report is partial.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interrupted: Option<Interrupted>,
+    /// Nested timer spans, with `--trace-spans`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spans: Vec<Span>,
+}
+
+impl RunReport {
+    pub fn phase(&mut self, name: &str, secs: f64) -> &mut Self {
+        self.phases.insert(name.to_string(), secs);
+        self
+    }
+
+    pub fn verified(&mut self, check: &str, num_clients: usize) -> &mut Self {
+        self.num_clients_verified
+            .insert(check.to_string(), num_clients);
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("cannot serialize the report")
+    }
+
+    /// The header and the line of the legacy CSV format: client comm, MPC
+    /// comm, then the phases of `legacy`, 0 for a skipped or missing column.
+    pub fn legacy_csv(&self, legacy: &LegacyCsv) -> String {
+        let phases = legacy
+            .phases
+            .iter()
+            .map(|phase| {
+                phase
+                    .and_then(|name| self.phases.get(name))
+                    .map_or(0f64, |secs| *secs)
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        format!(
+            "{}\n{}, {}, {}",
+            legacy.header,
+            self.client_comm,
+            if legacy.mpc_comm_sent {
+                self.mpc_comm_sent
+            } else {
+                self.mpc_comm_recv
+            },
+            phases.join(", ")
+        )
+    }
+}
+
+/// Layout of the legacy CSV line of a server, that benchmark scripts parse:
+/// its header, and the phase of each column after the communication, or
+/// `None` for a skipped column.
+pub struct LegacyCsv {
+    pub header: &'static str,
+    /// Whether the MPC comm column is the bytes sent rather than received.
+    pub mpc_comm_sent: bool,
+    pub phases: [Option<&'static str>; 6],
+}
+
+/// Rate of writing a recording of `num_bytes` of client messages to the temp
+/// directory with `ClientRecording::dump`, synced to disk.
+fn measure_disk_write(num_bytes: usize) -> Result<Measurement, bridge::BridgeError> {
+    let path = std::env::temp_dir().join(format!("elsa-self-test-{}", std::process::id()));
+    let recording = ClientRecording {
+        alice: vec![(ClientID::new(0), vec![0xab; num_bytes].into())],
+        bob: Vec::new(),
+    };
+    let mut written = Ok(());
+    let measured = self_test::measure(Kernel::DiskWrite, num_bytes, || {
+        written = recording
+            .dump(&path)
+            .and_then(|()| Ok(fs::File::open(&path)?.sync_all()?));
+    });
+    let _ = fs::remove_file(&path);
+    written.map(|()| measured)
+}
+
+/// Options to record the client messages to a file, or to replay them instead
+/// of accepting clients. Use with `Options::load_from_args_custom`.
+#[derive(Debug, Clone, Default)]
+pub struct ClientRecordOptions {
+    pub dump_clients: Option<String>,
+    pub replay_clients: Option<String>,
+}
+
+impl ClientRecordOptions {
+    pub fn args<'a>() -> [Arg<'a>; 2] {
+        [
+            Arg::new("dump_clients")
+                .long("dump-clients")
+                .takes_value(true)
+                .conflicts_with("replay_clients")
+                .help("save the messages received from clients to this file"),
+            Arg::new("replay_clients")
+                .long("replay-clients")
+                .takes_value(true)
+                .help("load the client messages from this file instead of accepting clients"),
+        ]
+    }
+
+    pub fn parse(matches: &ArgMatches) -> Self {
+        Self {
+            dump_clients: matches.value_of("dump_clients").map(String::from),
+            replay_clients: matches.value_of("replay_clients").map(String::from),
+        }
+    }
+}
+
+/// Options of the webhook that is notified of round progress. Use with
+/// `Options::load_from_args_custom`.
+#[derive(Debug, Clone, Default)]
+pub struct WebhookOptions {
+    pub url: Option<String>,
+    pub secret: String,
+    pub dead_letter: String,
+    /// Whether "alice" or "bob" sends the per-client outcomes.
+    pub outcomes_from: String,
+}
+
+impl WebhookOptions {
+    pub fn args<'a>() -> [Arg<'a>; 4] {
+        [
+            Arg::new("webhook_url")
+                .long("webhook-url")
+                .takes_value(true)
+                .requires("webhook_secret")
+                .help("POST round events to this http url"),
+            Arg::new("webhook_secret")
+                .long("webhook-secret")
+                .takes_value(true)
+                .help("HMAC-SHA256 key used to sign the webhook events"),
+            Arg::new("webhook_dead_letter")
+                .long("webhook-dead-letter")
+                .takes_value(true)
+                .default_value("webhook-dead-letter.jsonl")
+                .help("append webhook events that cannot be delivered to this file"),
+            Arg::new("webhook_outcomes_from")
+                .long("webhook-outcomes-from")
+                .takes_value(true)
+                .possible_values(["alice", "bob"])
+                .default_value("alice")
+                .help("the server that sends per-client outcomes to the webhook"),
+        ]
+    }
+
+    pub fn parse(matches: &ArgMatches) -> Self {
+        Self {
+            url: matches.value_of("webhook_url").map(String::from),
+            secret: matches
+                .value_of("webhook_secret")
+                .unwrap_or_default()
+                .to_string(),
+            dead_letter: matches.value_of("webhook_dead_letter").unwrap().to_string(),
+            outcomes_from: matches
+                .value_of("webhook_outcomes_from")
+                .unwrap()
+                .to_string(),
+        }
+    }
+}
+
+/// Options of the two-phase protocols for clients that do not complete phase
+/// 2. Use with `Options::load_from_args_custom`.
+#[derive(Debug, Clone, Default)]
+pub struct DropoutOptions {
+    /// How long to wait for the phase 2 message of each client, or forever if
+    /// `None`. A client that misses it is dropped.
+    pub phase2_timeout: Option<Duration>,
+}
+
+impl DropoutOptions {
+    pub fn args<'a>() -> [Arg<'a>; 1] {
+        [Arg::new("phase2_timeout")
+            .long("phase2-timeout")
+            .takes_value(true)
+            .help("seconds to wait for phase 2 of each client before dropping it (default: wait forever)")]
+    }
+
+    pub fn parse(matches: &ArgMatches) -> Self {
+        Self {
+            phase2_timeout: matches.value_of("phase2_timeout").map(|secs| {
+                secs.parse::<f64>()
+                    .ok()
+                    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .expect("invalid phase 2 timeout")
+            }),
+        }
+    }
+}
+
+/// Public weight of each client, e.g. its number of local samples, by uid.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientWeights(BTreeMap<u64, u64>);
+
+impl ClientWeights {
+    /// Load the weights from a file with one line `<uid> <weight>` per
+    /// client. Empty lines and lines starting with `#` are skipped.
+    pub fn load(path: &str) -> Result<Self, String> {
+        fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path, e))?
+            .parse()
+    }
+
+    /// Weights of `uids`, in the same order. Fails if a uid has no weight,
+    /// as both servers must weight every client the same way.
+    pub fn for_clients(&self, uids: &[ClientID]) -> Result<Vec<u64>, String> {
+        let missing = uids
+            .iter()
+            .filter(|uid| !self.0.contains_key(&uid.id))
+            .map(|uid| uid.id)
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(format!("no weight for client(s) {:?}", missing));
+        }
+        Ok(uids.iter().map(|uid| self.0[&uid.id]).collect())
+    }
+
+    /// Sum of all the weights, saturating. Bounds the total weight of any
+    /// round.
+    pub fn total(&self) -> u64 {
+        self.0.values().fold(0, |sum, w| sum.saturating_add(*w))
+    }
+}
+
+impl FromStr for ClientWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = BTreeMap::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("line {}: expected `<uid> <weight>`, got {:?}", i + 1, line);
+            let (uid, weight) = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [uid, weight] => (
+                    uid.parse::<u64>().map_err(|_| invalid())?,
+                    weight.parse::<u64>().map_err(|_| invalid())?,
+                ),
+                _ => return Err(invalid()),
+            };
+            if weights.insert(uid, weight).is_some() {
+                return Err(format!(
+                    "line {}: duplicate weight for client {}",
+                    i + 1,
+                    uid
+                ));
+            }
+        }
+        Ok(ClientWeights(weights))
+    }
+}
+
+/// Options of the aggregation of the client inputs. Use with
+/// `Options::load_from_args_custom`.
+#[derive(Debug, Clone, Default)]
+pub struct AggregationOptions {
+    /// Weight of each client in the aggregate, or 1 for every client if
+    /// `None`.
+    pub weights: Option<ClientWeights>,
+}
+
+impl AggregationOptions {
+    pub fn args<'a>() -> [Arg<'a>; 1] {
+        [Arg::new("weights")
+            .long("weights")
+            .takes_value(true)
+            .help("aggregate the weighted sum of the inputs, with the weight of each client on a line `<uid> <weight>` of this file (must match the peer)")]
+    }
+
+    pub fn parse(matches: &ArgMatches) -> Self {
+        Self {
+            weights: matches
+                .value_of("weights")
+                .map(|path| ClientWeights::load(path).expect("invalid weights")),
+        }
+    }
+
+    /// Weights of `uids`, in the same order, or `None` if every client has
+    /// weight 1. Panics if a connected client has no weight, so the server
+    /// refuses to run the round.
+    pub fn weights_of(&self, uids: &[ClientID]) -> Option<Vec<u64>> {
+        let weights = self.weights.as_ref()?;
+        Some(weights.for_clients(uids).expect("invalid weights"))
+    }
+
+    /// Largest total weight of a round of at most `num_clients` clients.
+    pub fn max_total_weight(&self, num_clients: usize) -> u64 {
+        match &self.weights {
+            Some(weights) => weights.total(),
+            None => num_clients as u64,
+        }
+    }
+}
+
+/// Options of the bound check of the client inputs after A2S. Use with
+/// `Options::load_from_args_custom`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BoundOptions {
+    /// Public bound of every input, or no bound check if `None`.
+    pub bound: Option<u64>,
+}
+
+impl BoundOptions {
+    pub fn args<'a>() -> [Arg<'a>; 1] {
+        [Arg::new("bound")
+            .long("bound")
+            .takes_value(true)
+            .help("check after A2S that every input is at most this bound, with witnesses sent by the clients, and leave out the clients whose inputs are not (must match the peer and the clients)")]
+    }
+
+    pub fn parse(matches: &ArgMatches) -> Self {
+        Self {
+            bound: matches
+                .value_of("bound")
+                .map(|bound| bound.parse().expect("invalid bound")),
+        }
+    }
+
+    /// The bound in the input ring `I`, for shares in ring `A`. Panics if it
+    /// does not fit in `I`, or if `I` is not at least two bits narrower than
+    /// `A`, as the check is only sound if `x + bound` cannot wrap around in
+    /// half of `A`, see `crypto_primitives::a2s`.
+    pub fn bound_in<I: UInt, A: UInt>(&self) -> Option<I> {
+        let bound = self.bound?;
+        assert!(
+            I::NUM_BITS + 2 <= A::NUM_BITS,
+            "a bound needs inputs of at most {} bits",
+            A::NUM_BITS - 2
+        );
+        Some(I::from(bound).expect("the bound does not fit in the input ring"))
+    }
+}
+
+/// Options to run several rounds over the same connections to the peer and
+/// the clients. Use with `Options::load_from_args_custom`.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    pub rounds: u64,
+}
+
+impl SessionOptions {
+    pub fn args<'a>() -> [Arg<'a>; 1] {
+        [Arg::new("rounds")
+            .long("rounds")
+            .takes_value(true)
+            .default_value("1")
+            .help("run this many rounds with the same clients, which send one input per round (must match the peer and the clients)")]
+    }
+
+    pub fn parse(matches: &ArgMatches) -> Self {
+        let rounds = matches
+            .value_of("rounds")
+            .unwrap()
+            .parse::<u64>()
+            .ok()
+            .filter(|rounds| (1..=MAX_SESSION_ROUNDS).contains(rounds))
+            .expect("invalid number of rounds");
+        Self { rounds }
+    }
+
+    /// Panics if `record` would record or replay a single round in a session
+    /// of several.
+    pub fn check_record(&self, record: &ClientRecordOptions) {
+        assert!(
+            self.rounds == 1 || (record.dump_clients.is_none() && record.replay_clients.is_none()),
+            "--dump-clients and --replay-clients only support one round"
+        );
+    }
+
+    /// The `round` of the reports, only set in a session of several rounds,
+    /// so that the report of a single round is unchanged.
+    pub fn report_round(&self, round: u64) -> Option<u64> {
+        (self.rounds > 1).then_some(round)
+    }
+}
+
+/// TLS of the connections with the clients, which connect in plaintext if it
+/// is not set. Use with `Options::load_from_args_custom`.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    pub server_tls: Option<ServerTls>,
+}
+
+impl TlsOptions {
+    pub fn args<'a>() -> [Arg<'a>; 2] {
+        [
+            Arg::new("tls_cert")
+                .long("tls-cert")
+                .takes_value(true)
+                .requires("tls_key")
+                .help("accept the clients over TLS, presenting the certificate chain in this PEM file (the clients must trust it)"),
+            Arg::new("tls_key")
+                .long("tls-key")
+                .takes_value(true)
+                .requires("tls_cert")
+                .help("private key of --tls-cert, in a PEM file"),
+        ]
+    }
+
+    pub fn parse(matches: &ArgMatches) -> Self {
+        let server_tls = matches.value_of("tls_cert").map(|cert| {
+            let key = matches.value_of("tls_key").unwrap();
+            let chain = tls::load_certs(cert).expect("invalid --tls-cert");
+            let key = tls::load_private_key(key).expect("invalid --tls-key");
+            ServerTls::new(chain, key).expect("invalid TLS certificate or key")
+        });
+        Self { server_tls }
+    }
+}
+
+/// Verifying keys of the clients, which sign their uploads with
+/// `signed-uploads`. Without it, there is no option and no key. Use with
+/// `Options::load_from_args_custom`.
+#[derive(Debug, Clone, Default)]
+pub struct ClientKeyOptions {
+    pub client_keys: ClientKeys,
+}
+
+impl ClientKeyOptions {
+    #[cfg(feature = "signed-uploads")]
+    pub fn args<'a>() -> [Arg<'a>; 1] {
+        [Arg::new("client_pubkeys")
+            .long("client-pubkeys")
+            .takes_value(true)
+            .required(true)
+            .help("directory with the 32-byte verifying key of each client in <uid>.pub; uploads not signed by it fail the client")]
+    }
+
+    #[cfg(not(feature = "signed-uploads"))]
+    pub fn args<'a>() -> [Arg<'a>; 0] {
+        []
+    }
+
+    #[cfg(feature = "signed-uploads")]
+    pub fn parse(matches: &ArgMatches) -> Self {
+        let dir = matches.value_of("client_pubkeys").unwrap();
+        Self {
+            client_keys: ClientKeys::load(dir).expect("invalid --client-pubkeys"),
+        }
+    }
+
+    #[cfg(not(feature = "signed-uploads"))]
+    pub fn parse(_matches: &ArgMatches) -> Self {
+        Self::default()
+    }
+}
+
+/// Traffic with the peer at the start of a round, to count the traffic of
+/// each round of a session.
+#[derive(Debug, Clone, Copy)]
+pub struct MpcCommSnapshot {
+    sent: usize,
+    received: usize,
+}
+
+impl MpcCommSnapshot {
+    pub fn take(peer: &MpcConnection) -> Self {
+        Self {
+            sent: peer.num_bytes_sent(),
+            received: peer.num_bytes_received(),
+        }
+    }
+
+    /// Set the MPC traffic of `report` to the bytes since the snapshot.
+    pub fn report_since(&self, report: &mut RunReport, peer: &MpcConnection) {
+        report.mpc_comm_sent = peer.num_bytes_sent() - self.sent;
+        report.mpc_comm_recv = peer.num_bytes_received() - self.received;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bridge::tcp_bridge::ClientID;
+    use crypto_primitives::self_test::Kernel;
+
+    use super::{measure_disk_write, ClientWeights, LegacyCsv, RunReport};
+    use crate::interrupt::Interrupted;
+
+    #[test]
+    fn test_measure_disk_write() {
+        let measured = measure_disk_write(1 << 16).unwrap();
+        assert_eq!(measured.kernel, Kernel::DiskWrite);
+        assert!(measured.rate > 0.);
+    }
+
+    #[test]
+    fn test_client_weights() {
+        let weights = "# uid weight\n0 3\n\n2 0\n 1\t7 \n"
+            .parse::<ClientWeights>()
+            .unwrap();
+        let uids = [2, 0, 1].map(ClientID::new);
+        assert_eq!(weights.for_clients(&uids), Ok(vec![0, 3, 7]));
+        assert_eq!(weights.total(), 10);
+        assert_eq!(
+            format!("0 {}\n1 1", u64::MAX)
+                .parse::<ClientWeights>()
+                .unwrap()
+                .total(),
+            u64::MAX
+        );
+        assert_eq!(
+            weights.for_clients(&[ClientID::new(0), ClientID::new(5)]),
+            Err("no weight for client(s) [5]".to_string())
+        );
+
+        assert!("0 1\n0 2".parse::<ClientWeights>().is_err());
+        assert!("0".parse::<ClientWeights>().is_err());
+        assert!("0 -1".parse::<ClientWeights>().is_err());
+    }
+
+    #[test]
+    fn test_run_report_json() {
+        let mut report = RunReport {
+            server: "test".to_string(),
+            num_clients: 3,
+            gsize: 10,
+            client_comm: 100,
+            mpc_comm_sent: 20,
+            mpc_comm_recv: 30,
+            ..Default::default()
+        };
+        report
+            .phase("b2a", 1.5)
+            .phase("a2s", 0.25)
+            .verified("ot", 2);
+        let json = report.to_json();
+        assert_eq!(serde_json::from_str::<RunReport>(&json).unwrap(), report);
+
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        for field in [
+            "client_comm",
+            "mpc_comm_sent",
+            "mpc_comm_recv",
+            "phases",
+            "num_clients_verified",
+        ] {
+            assert!(value.get(field).is_some(), "missing {}", field);
+        }
+        assert_eq!(value["phases"]["b2a"], 1.5);
+        assert_eq!(value["num_clients_verified"]["ot"], 2);
+        assert!(value.get("dropped_clients").is_none());
+        assert!(value.get("interrupted").is_none());
+        assert!(value.get("round").is_none());
+
+        report.dropped_clients = vec![1];
+        report.interrupted = Some(Interrupted::PeerAborted);
+        let json = report.to_json();
+        assert_eq!(serde_json::from_str::<RunReport>(&json).unwrap(), report);
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        assert_eq!(value["interrupted"], "peer_aborted");
+
+        report.round = Some(2);
+        let value = serde_json::from_str::<serde_json::Value>(&report.to_json()).unwrap();
+        assert_eq!(value["round"], 2);
+
+        let legacy = LegacyCsv {
+            header: "comm, comm, b2a, skip, a2s, skip, skip, skip",
+            mpc_comm_sent: false,
+            phases: [Some("b2a"), None, Some("a2s"), None, None, None],
+        };
+        assert_eq!(
+            report.legacy_csv(&legacy),
+            "comm, comm, b2a, skip, a2s, skip, skip, skip\n100, 30, 1.5, 0, 0.25, 0, 0, 0"
+        );
+    }
+}
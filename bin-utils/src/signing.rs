@@ -0,0 +1,136 @@
+//! Keys of the `--signing-key` and `--client-pubkeys` options, for clients and
+//! servers built with `signed-uploads`. Keys are raw bytes: the 32-byte
+//! secret key of a client, and the 32-byte verifying key of each client in
+//! `<uid>.pub` of a directory.
+
+#[cfg(feature = "signed-uploads")]
+use std::{collections::BTreeMap, fs};
+
+use bridge::tcp_bridge::ClientID;
+use bytes::Bytes;
+#[cfg(feature = "signed-uploads")]
+use crypto_primitives::message::signed::{
+    SignedMessage, SigningKey, VerifyingKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH,
+};
+#[cfg(feature = "signed-uploads")]
+use serialize::Communicate;
+
+#[cfg(feature = "signed-uploads")]
+fn read_key<const LEN: usize>(path: &str) -> Result<[u8; LEN], String> {
+    let bytes = fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    bytes
+        .try_into()
+        .map_err(|bytes: Vec<u8>| format!("{} has {} bytes, not {}", path, bytes.len(), LEN))
+}
+
+/// The secret key of a client in the file at `path`.
+#[cfg(feature = "signed-uploads")]
+pub fn load_signing_key(path: &str) -> Result<SigningKey, String> {
+    read_key::<SECRET_KEY_LENGTH>(path).map(|key| SigningKey::from_bytes(&key))
+}
+
+/// Verifying key of each client, by uid. Empty, and zero-sized, without
+/// `signed-uploads`, where uploads are not signed.
+#[derive(Debug, Clone, Default)]
+pub struct ClientKeys {
+    #[cfg(feature = "signed-uploads")]
+    keys: BTreeMap<ClientID, VerifyingKey>,
+}
+
+impl ClientKeys {
+    #[cfg(feature = "signed-uploads")]
+    pub fn new(keys: BTreeMap<ClientID, VerifyingKey>) -> Self {
+        ClientKeys { keys }
+    }
+
+    /// Load the key of each file `<uid>.pub` in `dir`. Other files are
+    /// skipped.
+    #[cfg(feature = "signed-uploads")]
+    pub fn load(dir: &str) -> Result<Self, String> {
+        let mut keys = BTreeMap::new();
+        for entry in fs::read_dir(dir).map_err(|e| format!("cannot read {}: {}", dir, e))? {
+            let path = entry
+                .map_err(|e| format!("cannot read {}: {}", dir, e))?
+                .path();
+            if path.extension().is_none_or(|ext| ext != "pub") {
+                continue;
+            }
+            let uid = path
+                .file_stem()
+                .and_then(|stem| stem.to_str()?.parse().ok())
+                .ok_or_else(|| format!("{} is not named <uid>.pub", path.display()))?;
+            let path = path.to_string_lossy();
+            let key = VerifyingKey::from_bytes(&read_key::<PUBLIC_KEY_LENGTH>(&path)?)
+                .map_err(|e| format!("invalid key in {}: {}", path, e))?;
+            keys.insert(ClientID::new(uid), key);
+        }
+        Ok(ClientKeys { keys })
+    }
+
+    /// The upload `msg` of `uid` without its signature, and whether it is
+    /// signed by the key of `uid`. A client without a key is never signed.
+    #[cfg(feature = "signed-uploads")]
+    pub fn open(&self, uid: ClientID, msg: Bytes) -> (Bytes, bool) {
+        match SignedMessage::<Bytes>::from_bytes_owned(msg.clone()) {
+            Ok(signed) => {
+                let verified = self
+                    .keys
+                    .get(&uid)
+                    .is_some_and(|key| signed.verify(key).is_ok());
+                (signed.msg, verified)
+            },
+            Err(_) => (msg, false),
+        }
+    }
+
+    /// Uploads are not signed, so `msg` is returned as is.
+    #[cfg(not(feature = "signed-uploads"))]
+    #[inline]
+    pub fn open(&self, _uid: ClientID, msg: Bytes) -> (Bytes, bool) {
+        (msg, true)
+    }
+}
+
+#[cfg(all(test, feature = "signed-uploads"))]
+mod tests {
+    use std::fs;
+
+    use bridge::tcp_bridge::ClientID;
+    use bytes::Bytes;
+    use crypto_primitives::message::signed::{SignedMessage, SigningKey};
+    use serialize::Communicate;
+
+    use super::{load_signing_key, ClientKeys};
+
+    #[test]
+    fn test_client_keys() {
+        let dir = std::env::temp_dir().join(format!("elsa-client-keys-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let secret = dir.join("client.key");
+        fs::write(&secret, [7; 32]).unwrap();
+        let key = load_signing_key(secret.to_str().unwrap()).unwrap();
+        fs::write(dir.join("3.pub"), key.verifying_key().as_bytes()).unwrap();
+        let other = SigningKey::from_bytes(&[8; 32]);
+        fs::write(dir.join("4.pub"), other.verifying_key().as_bytes()).unwrap();
+        let keys = ClientKeys::load(dir.to_str().unwrap()).unwrap();
+        fs::write(dir.join("x.pub"), [0; 32]).unwrap();
+        assert!(ClientKeys::load(dir.to_str().unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let msg = Bytes::from_static(b"phase 1 of client 3");
+        let signed = SignedMessage::sign(&key, msg.clone()).into_bytes_owned();
+        assert_eq!(
+            keys.open(ClientID::new(3), signed.clone()),
+            (msg.clone(), true)
+        );
+
+        let mut flipped = signed.to_vec();
+        flipped[0] ^= 1;
+        assert!(!keys.open(ClientID::new(3), flipped.into()).1);
+        // signed by client 3, but claimed by client 4, or by a client without
+        // a key
+        assert!(!keys.open(ClientID::new(4), signed.clone()).1);
+        assert!(!keys.open(ClientID::new(5), signed).1);
+        assert!(!keys.open(ClientID::new(3), msg).1);
+    }
+}
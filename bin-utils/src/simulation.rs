@@ -0,0 +1,69 @@
+//! Insecure simulation helpers of the libraries, as seen by a binary. See
+//! `bridge::simulation` and `crypto_primitives::simulation`.
+//!
+//! A production server is started with `--production`, which refuses to
+//! start if any helper is compiled in. Other runs should report
+//! [`helper_constructed`] with their results, so that numbers from a
+//! simulation are not mistaken for a real run. Flags that run a helper are
+//! made with [`insecure_arg`], and only offered by builds with the
+//! `simulation-helpers` feature of the server.
+
+use clap::Arg;
+use tracing::warn;
+
+/// Whether any simulation helper is compiled into this binary.
+pub fn helpers_compiled() -> bool {
+    bridge::simulation::HELPERS_COMPILED || crypto_primitives::simulation::HELPERS_COMPILED
+}
+
+/// Whether any simulation helper was constructed so far in this process.
+pub fn helper_constructed() -> bool {
+    bridge::simulation::helper_constructed() || crypto_primitives::simulation::helper_constructed()
+}
+
+/// Log a warning if a simulation helper was constructed, and return whether
+/// one was.
+pub fn warn_if_constructed() -> bool {
+    let constructed = helper_constructed();
+    if constructed {
+        warn!("this run used simulation helpers, its results are not from a secure run");
+    }
+    constructed
+}
+
+/// Flag `--<long>` that runs an insecure simulation helper. It conflicts
+/// with `--production`, and `help` should start with "INSECURE, benchmarking
+/// only".
+pub fn insecure_arg<'a>(name: &'a str, long: &'a str, help: &'a str) -> Arg<'a> {
+    Arg::new(name)
+        .long(long)
+        .conflicts_with("production")
+        .help(help)
+}
+
+/// Log that the insecure flag `--<long>` is set.
+pub fn warn_insecure_flag(long: &str) {
+    warn!(
+        "--{} is INSECURE, benchmarking only: never use it with real client data",
+        long
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{helper_constructed, helpers_compiled};
+
+    #[test]
+    #[cfg(not(feature = "simulation-helpers"))]
+    fn test_default_build_excludes_helpers() {
+        assert!(!helpers_compiled());
+        assert!(!helper_constructed());
+    }
+
+    #[test]
+    #[cfg(feature = "simulation-helpers")]
+    fn test_simulation_build_includes_helpers() {
+        assert!(helpers_compiled());
+        assert!(!helper_constructed());
+    }
+}
@@ -0,0 +1,39 @@
+//! Certificates and keys of the `--tls-*` options, in PEM files.
+
+use std::{fs::File, io::BufReader};
+
+use bridge::tls::rustls::{Certificate, PrivateKey};
+use rustls_pemfile::Item;
+
+fn read_pem(path: &str) -> Result<Vec<Item>, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("cannot read {}: {}", path, e))
+}
+
+/// The certificates in the PEM file at `path`, in order.
+pub fn load_certs(path: &str) -> Result<Vec<Certificate>, String> {
+    let certs = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", path));
+    }
+    Ok(certs)
+}
+
+/// The first private key in the PEM file at `path`, in PKCS #8, PKCS #1 or
+/// SEC1 form.
+pub fn load_private_key(path: &str) -> Result<PrivateKey, String> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no private key in {}", path))
+}
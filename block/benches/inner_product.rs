@@ -0,0 +1,36 @@
+use block::{gf::GF2_256, Block};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// The numbers of COTs of a client, as in `crypto-primitives/benches/primitives.rs`.
+#[cfg(not(feature = "bench-smoke"))]
+const LENS: &[usize] = &[32_000, 320_000, 3_200_000];
+#[cfg(feature = "bench-smoke")]
+const LENS: &[usize] = &[3_200];
+
+fn scalar_inner_product(a: &[Block], b: &[Block]) -> GF2_256 {
+    a.iter()
+        .zip(b)
+        .fold(GF2_256::default(), |prev, (left, right)| {
+            prev.add_gf(left.mul_gf_no_reduction(*right))
+        })
+}
+
+fn bench_inner_product(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inner_product");
+    let mut rng = StdRng::seed_from_u64(12345);
+    for &len in LENS {
+        let a = (0..len).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+        let b = (0..len).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+        group.bench_with_input(BenchmarkId::new("scalar", len), &len, |bencher, _| {
+            bencher.iter(|| scalar_inner_product(&a, &b))
+        });
+        group.bench_with_input(BenchmarkId::new("batched", len), &len, |bencher, _| {
+            bencher.iter(|| Block::inner_product_no_reduction(&a, &b))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_inner_product);
+criterion_main!(benches);
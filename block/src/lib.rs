@@ -1,8 +1,8 @@
-#[cfg(all(target_arch = "x86_64", target_feature = "pclmulqdq"))]
+#[cfg(target_arch = "x86_64")]
 pub mod x86;
 
 use bytes::Bytes;
-#[cfg(all(target_arch = "x86_64", target_feature = "pclmulqdq"))]
+#[cfg(target_arch = "x86_64")]
 pub use x86::*;
 
 /// Helper trait for a list of blocks. Should be implemented by [Block].
@@ -35,10 +35,10 @@ impl AsBlockSlice for [u8] {
     }
 }
 
-#[cfg(not(all(target_arch = "x86_64", target_feature = "pclmulqdq")))]
+#[cfg(not(target_arch = "x86_64"))]
 pub mod fallback {
-    compile_error!("This library only supports x86-64 with PCLMULQDQ instruction. If you are already running on x86-64 architecture, try compile it with environment variable RUSTFLAGS='-c target-cpu=native' ");
+    compile_error!("This library only supports x86-64. PCLMULQDQ is used if the CPU supports it, see `clmul::Backend`.");
 }
 
-#[cfg(not(all(target_arch = "x86_64", target_feature = "pclmulqdq")))]
+#[cfg(not(target_arch = "x86_64"))]
 pub use fallback::*;
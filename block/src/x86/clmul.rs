@@ -0,0 +1,134 @@
+//! Carry-less multiplication of 64-bit lanes, with PCLMULQDQ when the CPU
+//! supports it, and a portable software implementation otherwise. Support is
+//! detected once, so one binary built for the generic x86-64 target runs
+//! everywhere, and still uses PCLMULQDQ where it is available.
+
+use std::sync::OnceLock;
+
+use safe_arch::m128i;
+
+/// Set this environment variable to use the software implementation even if
+/// the CPU supports PCLMULQDQ. It is read once, at the first multiplication.
+pub const SOFTWARE_CLMUL_ENV: &str = "ELSA_SOFTWARE_CLMUL";
+
+/// Implementation of the carry-less multiplications of GF(2^128).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Pclmulqdq,
+    Software,
+}
+
+impl Backend {
+    /// The backend used by [`crate::Block`]: PCLMULQDQ if this CPU supports
+    /// it, unless `SOFTWARE_CLMUL_ENV` is set.
+    pub fn detected() -> Self {
+        static DETECTED: OnceLock<Backend> = OnceLock::new();
+        *DETECTED.get_or_init(|| {
+            if std::env::var_os(SOFTWARE_CLMUL_ENV).is_none() && Self::pclmulqdq_supported() {
+                Backend::Pclmulqdq
+            } else {
+                Backend::Software
+            }
+        })
+    }
+
+    pub fn pclmulqdq_supported() -> bool {
+        is_x86_feature_detected!("pclmulqdq") && is_x86_feature_detected!("sse2")
+    }
+}
+
+/// Carry-less multiplication of the `i64` lane of `a` selected by bit 0 of
+/// `IMM` with the lane of `b` selected by bit 4, as `_mm_clmulepi64_si128`.
+pub(crate) trait Clmul {
+    fn mul<const IMM: i32>(a: m128i, b: m128i) -> m128i;
+}
+
+/// PCLMULQDQ. Only used by functions compiled with
+/// `#[target_feature(enable = "pclmulqdq")]`, which are called only if
+/// `Backend::detected` is `Backend::Pclmulqdq`.
+pub(crate) struct HardwareClmul;
+
+impl Clmul for HardwareClmul {
+    #[inline(always)]
+    fn mul<const IMM: i32>(a: m128i, b: m128i) -> m128i {
+        // SAFETY: the caller runs on a CPU that supports PCLMULQDQ, see above
+        m128i(unsafe { core::arch::x86_64::_mm_clmulepi64_si128::<IMM>(a.0, b.0) })
+    }
+}
+
+pub(crate) struct SoftwareClmul;
+
+impl Clmul for SoftwareClmul {
+    #[inline]
+    fn mul<const IMM: i32>(a: m128i, b: m128i) -> m128i {
+        let lane = |x: m128i, high: bool| {
+            let x = u128::from(x);
+            if high {
+                (x >> 64) as u64
+            } else {
+                x as u64
+            }
+        };
+        m128i::from(clmul64(lane(a, IMM & 0x01 != 0), lane(b, IMM & 0x10 != 0)))
+    }
+}
+
+/// `a * b` as polynomials over GF(2). It does not branch on the bits of the
+/// operands, which may be secret.
+fn clmul64(a: u64, b: u64) -> u128 {
+    let a = a as u128;
+    (0..64).fold(0u128, |product, i| {
+        let mask = 0u128.wrapping_sub(((b >> i) & 1) as u128);
+        product ^ ((a << i) & mask)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{prelude::StdRng, Rng, SeedableRng};
+    use safe_arch::m128i;
+
+    use super::{clmul64, Backend, Clmul, HardwareClmul, SoftwareClmul};
+
+    #[test]
+    fn test_clmul64() {
+        assert_eq!(clmul64(0b101110, 0b110101), 0b11110110110);
+        assert_eq!(clmul64(u64::MAX, 1), u64::MAX as u128);
+        assert_eq!(clmul64(1 << 63, 1 << 63), 1 << 126);
+        assert_eq!(clmul64(0b11, 0b11), 0b101);
+    }
+
+    #[target_feature(enable = "pclmulqdq", enable = "sse2")]
+    unsafe fn hardware_lanes(a: m128i, b: m128i) -> [m128i; 4] {
+        [
+            HardwareClmul::mul::<0x00>(a, b),
+            HardwareClmul::mul::<0x01>(a, b),
+            HardwareClmul::mul::<0x10>(a, b),
+            HardwareClmul::mul::<0x11>(a, b),
+        ]
+    }
+
+    #[test]
+    fn test_software_matches_hardware() {
+        if !Backend::pclmulqdq_supported() {
+            return;
+        }
+        let mut rng = StdRng::seed_from_u64(12345);
+        for _ in 0..1024 {
+            let (a, b) = (
+                m128i::from(rng.gen::<u128>()),
+                m128i::from(rng.gen::<u128>()),
+            );
+            let software = [
+                SoftwareClmul::mul::<0x00>(a, b),
+                SoftwareClmul::mul::<0x01>(a, b),
+                SoftwareClmul::mul::<0x10>(a, b),
+                SoftwareClmul::mul::<0x11>(a, b),
+            ];
+            let hardware = unsafe { hardware_lanes(a, b) };
+            for (s, h) in software.iter().zip(&hardware) {
+                assert_eq!(u128::from(*s), u128::from(*h));
+            }
+        }
+    }
+}
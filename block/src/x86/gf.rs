@@ -1,11 +1,31 @@
 //! Defined Block represented as GF(2^128) polynomial.
 
-use std::io::{Read, Write};
-use crate::Block;
+use crate::{
+    x86::{
+        clmul::{Backend, Clmul, HardwareClmul, SoftwareClmul},
+        parse_hex_u128,
+    },
+    Block, ParseBlockError,
+};
+use bytemuck_derive::{Pod, Zeroable};
 use safe_arch::*;
 use serialize::{AsUseCast, Communicate, UseCast};
+use std::{
+    convert::TryInto,
+    fmt,
+    io::{Read, Write},
+    str::FromStr,
+};
 
 impl Block {
+    /// The additive identity of GF(2^128).
+    // SAFETY: `m128i` is 128 bits with no padding, and any bits are valid
+    pub const ZERO: Block = Block(unsafe { std::mem::transmute::<u128, m128i>(0) });
+
+    /// The multiplicative identity of GF(2^128), i.e. the polynomial 1.
+    // SAFETY: as above
+    pub const ONE: Block = Block(unsafe { std::mem::transmute::<u128, m128i>(1) });
+
     /// addition in GF(2^128)
     pub fn add_gf(self, other: Block) -> Block {
         self ^ other
@@ -16,30 +36,289 @@ impl Block {
     /// Calculator: http://www.ee.unb.ca/cgi-bin/tervo/calc.pl?num=1100101&den=1101&f=m&e=1&m=1
     /// Adapted from: https://github.com/emp-toolkit/emp-tool/blob/d48e2b165e557d14a40e5918ef44dd646ae20bec/emp-tool/utils/f2k.h#L8-L24
     pub fn mul_gf_no_reduction(self, other: Block) -> GF2_256 {
-        let mut tmp3 = mul_i64_carryless_m128i::<0x00>(self.0, other.0);
-        let mut tmp4 = mul_i64_carryless_m128i::<0x10>(self.0, other.0);
-        let mut tmp5 = mul_i64_carryless_m128i::<0x01>(self.0, other.0);
-        let mut tmp6 = mul_i64_carryless_m128i::<0x11>(self.0, other.0);
+        mul_no_reduction_on(Backend::detected(), self, other)
+    }
+
+    /// multiplication of two blocks in GF(2^128), modulo x^128 + x^7 + x^2 +
+    /// x + 1.
+    pub fn mul_gf(self, other: Block) -> Block {
+        self.mul_gf_no_reduction(other).reduce()
+    }
+
+    /// `self^exp` in GF(2^128), by square-and-multiply. It branches on the
+    /// bits of `exp`, which must not be secret.
+    pub fn pow(self, exp: u128) -> Block {
+        let (mut base, mut result) = (self, Block::ONE);
+        for i in 0..(128 - exp.leading_zeros()) {
+            if (exp >> i) & 1 == 1 {
+                result = result.mul_gf(base);
+            }
+            base = base.mul_gf(base);
+        }
+        result
+    }
+
+    /// Multiplicative inverse in GF(2^128), i.e. `self^(2^128 - 2)` by
+    /// Fermat's little theorem, or `None` for zero.
+    pub fn inverse(self) -> Option<Block> {
+        if self == Block::ZERO {
+            None
+        } else {
+            Some(self.pow(u128::MAX - 1))
+        }
+    }
+
+    /// Inner product of `a` and `b` in GF(2^128) without modulo, i.e.
+    /// `sum(a[i].mul_gf_no_reduction(b[i]))`.
+    ///
+    /// Each product takes three carry-less multiplications (Karatsuba) instead
+    /// of four. The partial products are summed separately,
+    /// `INNER_PRODUCT_LANES` pairs per iteration, and only folded into a
+    /// `GF2_256` at the end. This is valid because the fold is linear.
+    pub fn inner_product_no_reduction(a: &[Block], b: &[Block]) -> GF2_256 {
+        inner_product_on(Backend::detected(), a, b)
+    }
+
+    /// Sum of `blocks[i]` for which `choices[i]` is true, i.e. the inner
+    /// product with a vector of booleans. Uses two accumulators to break the
+    /// dependency chain.
+    pub fn sum_selected(choices: impl Iterator<Item = bool>, blocks: &[Block]) -> Block {
+        let mut acc = [m128i::default(); 2];
+        for (i, (choice, block)) in choices.zip(blocks).enumerate() {
+            // all ones if selected, otherwise all zeros
+            let mask = m128i::from(-(choice as i128));
+            acc[i & 1] ^= block.0 & mask;
+        }
+        Block(acc[0] ^ acc[1])
+    }
+}
+
+// Each operation that multiplies is written once, generic over `Clmul`, and
+// instantiated for PCLMULQDQ in a function compiled with the target feature,
+// so that the intrinsics are inlined. `*_on` picks the instantiation.
+
+fn mul_no_reduction_on(backend: Backend, a: Block, b: Block) -> GF2_256 {
+    #[target_feature(enable = "pclmulqdq", enable = "sse2")]
+    unsafe fn hardware(a: Block, b: Block) -> GF2_256 {
+        mul_no_reduction::<HardwareClmul>(a, b)
+    }
+    match backend {
+        // SAFETY: `Backend::Pclmulqdq` is only used if the CPU supports it
+        Backend::Pclmulqdq => unsafe { hardware(a, b) },
+        Backend::Software => mul_no_reduction::<SoftwareClmul>(a, b),
+    }
+}
+
+fn inner_product_on(backend: Backend, a: &[Block], b: &[Block]) -> GF2_256 {
+    #[target_feature(enable = "pclmulqdq", enable = "sse2")]
+    unsafe fn hardware(a: &[Block], b: &[Block]) -> GF2_256 {
+        inner_product::<HardwareClmul>(a, b)
+    }
+    match backend {
+        // SAFETY: `Backend::Pclmulqdq` is only used if the CPU supports it
+        Backend::Pclmulqdq => unsafe { hardware(a, b) },
+        Backend::Software => inner_product::<SoftwareClmul>(a, b),
+    }
+}
+
+fn reduce_on(backend: Backend, x: GF2_256) -> Block {
+    #[target_feature(enable = "pclmulqdq", enable = "sse2")]
+    unsafe fn hardware(x: GF2_256) -> Block {
+        reduce::<HardwareClmul>(x)
+    }
+    match backend {
+        // SAFETY: `Backend::Pclmulqdq` is only used if the CPU supports it
+        Backend::Pclmulqdq => unsafe { hardware(x) },
+        Backend::Software => reduce::<SoftwareClmul>(x),
+    }
+}
+
+#[inline(always)]
+fn mul_no_reduction<M: Clmul>(a: Block, b: Block) -> GF2_256 {
+    let mut tmp3 = M::mul::<0x00>(a.0, b.0);
+    let mut tmp4 = M::mul::<0x10>(a.0, b.0);
+    let mut tmp5 = M::mul::<0x01>(a.0, b.0);
+    let mut tmp6 = M::mul::<0x11>(a.0, b.0);
+
+    tmp4 ^= tmp5;
+    tmp5 = byte_shl_imm_u128_m128i::<8>(tmp4);
+    tmp4 = byte_shr_imm_u128_m128i::<8>(tmp4);
+    tmp3 ^= tmp5;
+    tmp6 ^= tmp4;
+
+    GF2_256(Block(tmp3), Block(tmp6))
+}
+
+#[inline(always)]
+fn inner_product<M: Clmul>(a: &[Block], b: &[Block]) -> GF2_256 {
+    debug_assert_eq!(a.len(), b.len());
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+
+    let mut lo = m128i::default();
+    let mut karatsuba = m128i::default();
+    let mut hi = m128i::default();
+
+    let a_chunks = a.chunks_exact(INNER_PRODUCT_LANES);
+    let b_chunks = b.chunks_exact(INNER_PRODUCT_LANES);
+    let (a_rem, b_rem) = (a_chunks.remainder(), b_chunks.remainder());
+    for (x, y) in a_chunks.zip(b_chunks) {
+        let x: &[Block; INNER_PRODUCT_LANES] = x.try_into().unwrap();
+        let y: &[Block; INNER_PRODUCT_LANES] = y.try_into().unwrap();
+        let (l, k, h) = partial_products::<M, INNER_PRODUCT_LANES>(x, y);
+        lo ^= l;
+        karatsuba ^= k;
+        hi ^= h;
+    }
+    for (x, y) in a_rem.iter().zip(b_rem) {
+        let (l, k, h) = partial_products::<M, 1>(&[*x], &[*y]);
+        lo ^= l;
+        karatsuba ^= k;
+        hi ^= h;
+    }
+
+    let mid = karatsuba ^ lo ^ hi;
+    GF2_256(
+        Block(lo ^ byte_shl_imm_u128_m128i::<8>(mid)),
+        Block(hi ^ byte_shr_imm_u128_m128i::<8>(mid)),
+    )
+}
+
+#[inline(always)]
+fn reduce<M: Clmul>(x: GF2_256) -> Block {
+    let GF2_256(Block(lo), Block(hi)) = x;
+    let modulus = m128i::from(MODULUS_LOW);
+    // hi * (x^7 + x^2 + x + 1) has up to 135 bits: fold the low 128 bits
+    // into `lo`, and the 7 bits above once more
+    let fold_lo = M::mul::<0x00>(hi, modulus);
+    let fold_hi = M::mul::<0x01>(hi, modulus);
+    let overflow = byte_shr_imm_u128_m128i::<8>(fold_hi);
+    let fold_overflow = M::mul::<0x00>(overflow, modulus);
+    Block(lo ^ fold_lo ^ byte_shl_imm_u128_m128i::<8>(fold_hi) ^ fold_overflow)
+}
 
-        tmp4 = tmp4 ^ tmp5;
-        tmp5 = byte_shl_imm_u128_m128i::<8>(tmp4);
-        tmp4 = byte_shr_imm_u128_m128i::<8>(tmp4);
-        tmp3 = tmp3 ^ tmp5;
-        tmp6 = tmp6 ^ tmp4;
+/// Number of pairs processed per iteration in
+/// [`Block::inner_product_no_reduction`].
+const INNER_PRODUCT_LANES: usize = 4;
 
-        GF2_256(Block(tmp3), Block(tmp6))
+/// Sums of the partial products of `x[i] * y[i]` with Karatsuba: the low
+/// halves, the high halves, and `(x_lo ^ x_hi) * (y_lo ^ y_hi)`. The products
+/// are independent, so they can be computed in parallel.
+#[inline(always)]
+fn partial_products<M: Clmul, const N: usize>(
+    x: &[Block; N],
+    y: &[Block; N],
+) -> (m128i, m128i, m128i) {
+    let mut lo = [m128i::default(); N];
+    let mut karatsuba = [m128i::default(); N];
+    let mut hi = [m128i::default(); N];
+    for i in 0..N {
+        let (x, y) = (x[i].0, y[i].0);
+        lo[i] = M::mul::<0x00>(x, y);
+        hi[i] = M::mul::<0x11>(x, y);
+        karatsuba[i] = M::mul::<0x00>(
+            x ^ byte_shr_imm_u128_m128i::<8>(x),
+            y ^ byte_shr_imm_u128_m128i::<8>(y),
+        );
     }
+    let sum = |v: [m128i; N]| v.iter().fold(m128i::default(), |l, r| l ^ *r);
+    (sum(lo), sum(karatsuba), sum(hi))
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+/// A polynomial of degree less than 256 over GF(2), e.g. the product of two
+/// blocks before reduction: `self.0 + self.1 * x^128`.
+///
+/// It is `repr(C)`, so in memory the low block comes first, then the high
+/// block, 32 bytes without padding. It is displayed the other way around, as
+/// 64 hex digits with the coefficient of x^255 leftmost, like [`Block`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Pod, Zeroable)]
 pub struct GF2_256(pub Block, pub Block);
 
+/// x^128 mod the GF(2^128) modulus x^128 + x^7 + x^2 + x + 1.
+const MODULUS_LOW: u128 = 0x87;
+
 impl GF2_256 {
-    pub fn add_gf(self, other: GF2_256) -> GF2_256 {
+    /// The low and high blocks.
+    pub fn as_blocks(&self) -> [Block; 2] {
+        [self.0, self.1]
+    }
+
+    /// The low block then the high block, each little-endian, as they are
+    /// sent by [`Communicate`].
+    pub fn to_bytes_le(&self) -> [u8; 32] {
+        // x86-64 is little-endian, see `Block`
+        bytemuck::cast(*self)
+    }
+
+    /// Inverse of [`GF2_256::to_bytes_le`].
+    pub fn from_bytes_le(bytes: [u8; 32]) -> Self {
+        bytemuck::cast(bytes)
+    }
+
+    /// addition in GF(2^256)
+    pub fn add_gf(&self, other: GF2_256) -> GF2_256 {
         GF2_256(self.0.add_gf(other.0), self.1.add_gf(other.1))
     }
+
+    /// Reduce to GF(2^128) modulo x^128 + x^7 + x^2 + x + 1, i.e. `lo + hi *
+    /// x^128`, where `hi * x^128 = hi * (x^7 + x^2 + x + 1)` is folded with
+    /// carry-less multiplications.
+    pub fn reduce(self) -> Block {
+        reduce_on(Backend::detected(), self)
+    }
+}
+
+impl fmt::Display for GF2_256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for GF2_256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        write!(f, "{:x}{:x}", self.1, self.0)
+    }
+}
+
+impl fmt::UpperHex for GF2_256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        write!(f, "{:X}{:X}", self.1, self.0)
+    }
+}
+
+impl fmt::Binary for GF2_256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0b")?;
+        }
+        write!(f, "{:b}{:b}", self.1, self.0)
+    }
 }
 
+/// Parses 64 hex digits, the format of `Display`.
+impl FromStr for GF2_256 {
+    type Err = ParseBlockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = ParseBlockError { num_digits: 64 };
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(err);
+        }
+        let (hi, lo) = s.split_at(32);
+        let hi = parse_hex_u128(hi, 32).map_err(|_| err.clone())?;
+        let lo = parse_hex_u128(lo, 32).map_err(|_| err)?;
+        Ok(GF2_256(Block(lo.into()), Block(hi.into())))
+    }
+}
+
+/// Sent as 32 bytes, see [`GF2_256::to_bytes_le`].
 impl Communicate for GF2_256 {
     type Deserialized = Self;
 
@@ -61,7 +340,7 @@ impl Communicate for GF2_256 {
 
 #[cfg(test)]
 mod tests {
-    use rand::{prelude::StdRng, SeedableRng};
+    use rand::{prelude::StdRng, Rng, SeedableRng};
 
     use super::*;
 
@@ -81,6 +360,303 @@ mod tests {
     #[test]
     fn test_gf256_from_gf128() {}
 
+    /// Schoolbook multiplication in GF(2^128), one bit at a time.
+    fn mul_gf_reference(a: u128, b: u128) -> u128 {
+        let (mut a, mut result) = (a, 0u128);
+        for i in 0..128 {
+            if (b >> i) & 1 == 1 {
+                result ^= a;
+            }
+            let carry = a >> 127;
+            a <<= 1;
+            if carry == 1 {
+                a ^= MODULUS_LOW;
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_reduce() {
+        let block = |x: u128| Block(x.into());
+        // x^127 * x = x^128 = x^7 + x^2 + x + 1
+        assert_eq!(block(1 << 127).mul_gf(block(2)), block(0x87));
+        // x^127 * x^127 = x^254 = x^126 * (x^7 + x^2 + x + 1)
+        assert_eq!(
+            block(1 << 127).mul_gf(block(1 << 127)),
+            block(mul_gf_reference(1 << 126, 0x87))
+        );
+        assert_eq!(GF2_256(block(5), block(0)).reduce(), block(5));
+
+        let mut rng = StdRng::seed_from_u64(12345);
+        for _ in 0..1024 {
+            let (a, b) = (rng.gen::<u128>(), rng.gen::<u128>());
+            let expected = block(mul_gf_reference(a, b));
+            assert_eq!(block(a).mul_gf_no_reduction(block(b)).reduce(), expected);
+            assert_eq!(block(a).mul_gf(block(b)), expected);
+        }
+    }
+
+    /// `a * b` as polynomials over GF(2), as a 256-bit integer `(lo, hi)`,
+    /// reduced by long division with the full modulus. Independent of
+    /// `mul_gf_reference`, which reduces on the fly.
+    fn mul_gf_u256_reference(a: u128, b: u128) -> u128 {
+        let (mut lo, mut hi) = (0u128, 0u128);
+        for i in 0..128 {
+            if (b >> i) & 1 == 1 {
+                lo ^= a << i;
+                if i > 0 {
+                    hi ^= a >> (128 - i);
+                }
+            }
+        }
+        // subtract x^(128 + i) + x^i * (x^7 + x^2 + x + 1) from the top down
+        for i in (0..128).rev() {
+            if (hi >> i) & 1 == 1 {
+                hi ^= 1 << i;
+                let fold = MODULUS_LOW << i;
+                lo ^= fold;
+                if i > 0 {
+                    hi ^= MODULUS_LOW >> (128 - i);
+                }
+            }
+        }
+        assert_eq!(hi, 0);
+        lo
+    }
+
+    #[test]
+    fn test_mul_gf_against_u256() {
+        let block = |x: u128| Block(x.into());
+        let mut rng = StdRng::seed_from_u64(12345);
+        for _ in 0..1024 {
+            let a = rng.gen::<u128>();
+            // by 1, by x, and by a random element
+            assert_eq!(block(a).mul_gf(Block::ONE), block(a));
+            assert_eq!(
+                block(a).mul_gf(block(2)),
+                block(mul_gf_u256_reference(a, 2))
+            );
+            let b = rng.gen::<u128>();
+            assert_eq!(
+                block(a).mul_gf(block(b)),
+                block(mul_gf_u256_reference(a, b))
+            );
+        }
+        assert_eq!(mul_gf_u256_reference(1 << 127, 2), 0x87);
+        assert_eq!(Block::ONE, block(1));
+        assert_eq!(Block::ZERO, Block::default());
+        assert_eq!(block(3).mul_gf(Block::ZERO), Block::ZERO);
+    }
+
+    #[test]
+    fn test_pow() {
+        let block = |x: u128| Block(x.into());
+        // x^128 = x^7 + x^2 + x + 1
+        assert_eq!(block(2).pow(127), block(1 << 127));
+        assert_eq!(block(2).pow(128), block(0x87));
+        assert_eq!(Block::ZERO.pow(0), Block::ONE);
+
+        let mut rng = StdRng::seed_from_u64(12345);
+        for _ in 0..64 {
+            let a = Block::rand(&mut rng);
+            let exp = rng.gen::<u8>() as u128;
+            let expected = (0..exp).fold(Block::ONE, |acc, _| acc.mul_gf(a));
+            assert_eq!(a.pow(exp), expected);
+            // the multiplicative group has order 2^128 - 1
+            assert_eq!(
+                a.pow(u128::MAX),
+                if a == Block::ZERO { a } else { Block::ONE }
+            );
+        }
+    }
+
+    #[test]
+    fn test_inverse() {
+        let x = Block(2u128.into());
+        let x_inv = x.inverse().unwrap();
+        assert_eq!(x_inv.mul_gf(x), Block::ONE);
+        // x * (x^127 + x^6 + x + 1) = x^128 + x^7 + x^2 + x = 1
+        assert_eq!(x_inv, Block(((1u128 << 127) | 0x43).into()));
+        assert_eq!(Block::ONE.inverse(), Some(Block::ONE));
+        assert_eq!(Block::ZERO.inverse(), None);
+
+        let mut rng = StdRng::seed_from_u64(12345);
+        for _ in 0..64 {
+            let a = Block::rand(&mut rng);
+            assert_eq!(a.inverse().unwrap().mul_gf(a), Block::ONE);
+        }
+    }
+
+    #[test]
+    fn test_reduce_inner_product() {
+        // the reduction is linear, so the inner product can be reduced once
+        let mut rng = StdRng::seed_from_u64(12345);
+        let a = (0..100).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+        let b = (0..100).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+        let expected = a
+            .iter()
+            .zip(&b)
+            .fold(Block::default(), |prev, (l, r)| prev.add_gf(l.mul_gf(*r)));
+        assert_eq!(Block::inner_product_no_reduction(&a, &b).reduce(), expected);
+    }
+
+    #[test]
+    fn test_inner_product_no_reduction() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        for len in [0, 1, 7, 1000] {
+            let a = (0..len).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+            let b = (0..len).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+            let expected = a.iter().zip(&b).fold(GF2_256::default(), |prev, (l, r)| {
+                prev.add_gf(l.mul_gf_no_reduction(*r))
+            });
+            assert_eq!(Block::inner_product_no_reduction(&a, &b), expected);
+        }
+    }
+
+    #[test]
+    fn test_inner_product_with_ones() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let a = (0..100).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+        let chi = vec![Block::ONE; a.len()];
+        let expected = a.iter().fold(GF2_256::default(), |prev, x| {
+            prev.add_gf(x.mul_gf_no_reduction(Block::ONE))
+        });
+        assert_eq!(Block::inner_product_no_reduction(&a, &chi), expected);
+        // multiplying by 1 leaves the high block empty
+        let sum = a.iter().fold(Block::ZERO, |prev, x| prev.add_gf(*x));
+        assert_eq!(expected.as_blocks(), [sum, Block::ZERO]);
+    }
+
+    #[test]
+    fn test_gf256_bytes_round_trip() {
+        let lo = Block(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10u128.into());
+        let hi = Block(0x1112_1314_1516_1718_191a_1b1c_1d1e_1f20u128.into());
+        let x = GF2_256(lo, hi);
+        let bytes = x.to_bytes_le();
+        // low block first, each block little-endian
+        let expected = (0x01..=0x20u8)
+            .collect::<Vec<_>>()
+            .chunks(16)
+            .flat_map(|c| c.iter().rev().copied())
+            .collect::<Vec<_>>();
+        assert_eq!(bytes.as_ref(), expected.as_slice());
+        assert_eq!(GF2_256::from_bytes_le(bytes), x);
+        assert_eq!(x.as_blocks(), [lo, hi]);
+
+        let sent = x.into_bytes_owned();
+        assert_eq!(sent.as_ref(), bytes.as_ref());
+        assert_eq!(GF2_256::from_bytes_owned(sent).unwrap(), x);
+
+        let mut rng = StdRng::seed_from_u64(12345);
+        for _ in 0..64 {
+            let x = GF2_256(Block::rand(&mut rng), Block::rand(&mut rng));
+            assert_eq!(GF2_256::from_bytes_le(x.to_bytes_le()), x);
+            assert_eq!(bytemuck::cast::<_, [Block; 2]>(x), x.as_blocks());
+        }
+    }
+
+    #[test]
+    fn test_gf256_format_is_pinned() {
+        let lo = Block(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10u128.into());
+        let hi = Block(0xdeadbeef12345678abcdef0123456789u128.into());
+        let x = GF2_256(lo, hi);
+        // the high block, i.e. the coefficient of x^255, first
+        assert_eq!(
+            x.to_string(),
+            "deadbeef12345678abcdef01234567890102030405060708090a0b0c0d0e0f10"
+        );
+        assert_eq!(
+            format!("{:#X}", x),
+            "0xDEADBEEF12345678ABCDEF01234567890102030405060708090A0B0C0D0E0F10"
+        );
+        assert_eq!(GF2_256::default().to_string(), "0".repeat(64));
+        let one = GF2_256(Block::ONE, Block::ZERO);
+        assert_eq!(one.to_string(), format!("{}1", "0".repeat(63)));
+        assert_eq!(format!("{:b}", one), format!("{}1", "0".repeat(255)));
+
+        let mut rng = StdRng::seed_from_u64(12345);
+        for x in [
+            x,
+            one,
+            GF2_256(Block::rand(&mut rng), Block::rand(&mut rng)),
+        ] {
+            assert_eq!(x.to_string().parse::<GF2_256>(), Ok(x));
+            assert_eq!(format!("{:X}", x).parse::<GF2_256>(), Ok(x));
+        }
+        let err = Err(ParseBlockError { num_digits: 64 });
+        assert_eq!("0".repeat(32).parse::<GF2_256>(), err);
+        assert_eq!(format!("{}é", "0".repeat(62)).parse::<GF2_256>(), err);
+        assert_eq!(format!("{}+1", "0".repeat(62)).parse::<GF2_256>(), err);
+    }
+
+    #[test]
+    fn test_software_backend() {
+        let block = |x: u128| Block(x.into());
+        let mut rng = StdRng::seed_from_u64(12345);
+        for _ in 0..256 {
+            let (a, b) = (rng.gen::<u128>(), rng.gen::<u128>());
+            let product = mul_no_reduction_on(Backend::Software, block(a), block(b));
+            assert_eq!(
+                reduce_on(Backend::Software, product),
+                block(mul_gf_reference(a, b))
+            );
+        }
+    }
+
+    /// Both backends give the same results bit for bit, when this CPU has
+    /// PCLMULQDQ.
+    #[test]
+    fn test_software_matches_pclmulqdq() {
+        if !Backend::pclmulqdq_supported() {
+            return;
+        }
+        let mut rng = StdRng::seed_from_u64(12345);
+        for _ in 0..256 {
+            let (a, b, c) = (
+                Block::rand(&mut rng),
+                Block::rand(&mut rng),
+                Block::rand(&mut rng),
+            );
+            let [software, hardware] = [Backend::Software, Backend::Pclmulqdq]
+                .map(|backend| mul_no_reduction_on(backend, a, b));
+            assert_eq!(software, hardware);
+            assert_eq!(
+                software.add_gf(GF2_256(c, a)),
+                hardware.add_gf(GF2_256(c, a))
+            );
+            assert_eq!(
+                reduce_on(Backend::Software, software),
+                reduce_on(Backend::Pclmulqdq, hardware)
+            );
+        }
+        for len in [0, 1, 7, 1000] {
+            let a = (0..len).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+            let b = (0..len).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+            assert_eq!(
+                inner_product_on(Backend::Software, &a, &b),
+                inner_product_on(Backend::Pclmulqdq, &a, &b)
+            );
+        }
+    }
+
+    #[test]
+    fn test_sum_selected() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        for len in [0, 1, 7, 1000] {
+            let choices = (0..len).map(|_| rng.gen::<bool>()).collect::<Vec<_>>();
+            let blocks = (0..len).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+            let expected = choices
+                .iter()
+                .zip(&blocks)
+                .fold(
+                    Block::default(),
+                    |prev, (c, b)| if *c { prev.add_gf(*b) } else { prev },
+                );
+            assert_eq!(Block::sum_selected(choices.into_iter(), &blocks), expected);
+        }
+    }
+
     #[test]
     fn test_basic_law() {
         let mut rng = StdRng::seed_from_u64(12345);
@@ -1,37 +1,37 @@
+pub mod clmul;
 pub mod gf;
 
-use std::ops::{BitAnd, BitXor, Not};
+use std::{
+    fmt,
+    ops::{BitAnd, BitXor, Not},
+    str::FromStr,
+    sync::atomic,
+};
 
 use bytemuck::{Pod, Zeroable};
 use bytemuck_derive::TransparentWrapper;
 use core::fmt::Debug;
-use derive_more::{Binary, Display, LowerExp, LowerHex, UpperExp, UpperHex};
+use derive_more::{LowerExp, UpperExp};
 use rand::Rng;
 use safe_arch::*;
+use zeroize::Zeroize;
 
 use crate::Blocks;
 
 /// An 128-bit block.
 /// Internally represented as an 128-bit XMM vector. Computation is vectorized
-/// using SSE2 and PCLMULQDQ intrinsics.
+/// using SSE2 intrinsics, and PCLMULQDQ if the CPU supports it, see
+/// [`clmul::Backend`].
 ///
 /// When represented as an element in GF128, the leftmost bit is the coefficient
 /// of x^127, and the rightmost bit is the coefficient of x^0.
+///
+/// It is displayed in this order as 32 lowercase hex digits, e.g. `Block::ONE`
+/// as `00000000000000000000000000000001`, independently of how `m128i` is
+/// formatted, and parsed back with [`FromStr`]. `Binary` and `UpperHex` use
+/// the same order.
 #[repr(transparent)]
-#[derive(
-    Clone,
-    Copy,
-    Default,
-    PartialEq,
-    Eq,
-    TransparentWrapper,
-    Display,
-    Binary,
-    LowerHex,
-    UpperHex,
-    LowerExp,
-    UpperExp,
-)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, TransparentWrapper, LowerExp, UpperExp)]
 pub struct Block(pub m128i);
 
 impl Debug for Block {
@@ -40,9 +40,89 @@ impl Debug for Block {
     }
 }
 
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
+    }
+}
+
+impl fmt::LowerHex for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        write!(f, "{:032x}", u128::from(self.0))
+    }
+}
+
+impl fmt::UpperHex for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0x")?;
+        }
+        write!(f, "{:032X}", u128::from(self.0))
+    }
+}
+
+impl fmt::Binary for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.write_str("0b")?;
+        }
+        write!(f, "{:0128b}", u128::from(self.0))
+    }
+}
+
+/// The string is not in the format of `Display`, e.g. of [`Block`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBlockError {
+    /// Number of hex digits expected.
+    pub num_digits: usize,
+}
+
+impl fmt::Display for ParseBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {} hex digits", self.num_digits)
+    }
+}
+
+impl std::error::Error for ParseBlockError {}
+
+/// Parse exactly `num_digits` hex digits of either case, without prefix or
+/// sign.
+fn parse_hex_u128(s: &str, num_digits: usize) -> Result<u128, ParseBlockError> {
+    let err = ParseBlockError { num_digits };
+    if s.len() != num_digits || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(err);
+    }
+    u128::from_str_radix(s, 16).map_err(|_| err)
+}
+
+/// Parses 32 hex digits, the format of `Display`.
+impl FromStr for Block {
+    type Err = ParseBlockError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Block(parse_hex_u128(s, 32)?.into()))
+    }
+}
+
 unsafe impl Zeroable for Block {}
+// x86-64 is little-endian, so `Block` takes the blanket
+// `serialize::util::LePod` impl and is sent as its two u64 lanes, low lane
+// first. A big-endian port would have to swap both lanes.
 unsafe impl Pod for Block {}
 
+impl Zeroize for Block {
+    /// Overwrite with zeros through a volatile write, so that it is not
+    /// optimized away although the block is never read again.
+    fn zeroize(&mut self) {
+        // safety: `self` is a valid and aligned `Block`
+        unsafe { std::ptr::write_volatile(self, Block::default()) };
+        atomic::compiler_fence(atomic::Ordering::SeqCst);
+    }
+}
+
 impl BitAnd for Block {
     type Output = Block;
 
@@ -74,6 +154,18 @@ impl Block {
         Self(val.into())
     }
 
+    /// The low lane then the high lane, each little-endian, as the block is
+    /// sent by `UseCast`.
+    pub fn to_bytes_le(&self) -> [u8; 16] {
+        // x86-64 is little-endian
+        bytemuck::cast(*self)
+    }
+
+    /// Inverse of [`Block::to_bytes_le`].
+    pub fn from_bytes_le(bytes: [u8; 16]) -> Self {
+        bytemuck::cast(bytes)
+    }
+
     /// view the list of blocks as a slice of blocks. This operation is O(1)
     pub fn batch_cast_from_u8_slice(slice: &[u8]) -> &[Self] {
         bytemuck::cast_slice(slice)
@@ -95,7 +187,7 @@ impl Blocks for [Block] {
     }
 }
 
-impl From<m128i> for Block{
+impl From<m128i> for Block {
     fn from(val: m128i) -> Self {
         Self(val)
     }
@@ -105,12 +197,14 @@ impl From<m128i> for Block{
 mod tests {
     use rand::{prelude::StdRng, Rng, SeedableRng};
     use safe_arch::m128i;
+    use serialize::{Communicate, UseCast};
+    use zeroize::{Zeroize, Zeroizing};
 
-    use crate::{Block, Blocks};
+    use crate::{Block, Blocks, ParseBlockError};
 
     #[test]
+    #[cfg(all(target_feature = "pclmulqdq", target_feature = "sse4.1"))]
     /// make sure CLMUL is supported
-
     fn test_valid_instruction() {
         let a = Block(safe_arch::set_i32_m128i(0b0, 0b0, 0b0, 0b101110));
         let b = Block(safe_arch::set_i32_m128i(0b0, 0b0, 0b0, 0b110101));
@@ -141,6 +235,24 @@ mod tests {
         assert_eq!(a, b);
     }
 
+    #[test]
+    fn test_zeroize() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let mut block = Block::rand(&mut rng);
+        block.zeroize();
+        assert_eq!(block, Block::default());
+
+        // the blocks are wiped in place when dropped, without a copy
+        let mut blocks = Zeroizing::new((0..37).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>());
+        let ptr = blocks.as_ptr();
+        assert_eq!(
+            Block::batch_cast_from_u8_slice(blocks.as_u8_slice()).as_ptr(),
+            ptr
+        );
+        blocks.zeroize();
+        assert!(blocks.is_empty());
+    }
+
     #[test]
     fn test_to_bytes() {
         let mut rng = StdRng::seed_from_u64(12345);
@@ -152,6 +264,71 @@ mod tests {
         assert_eq!(&blocks, blocks_from_bytes);
     }
 
+    #[test]
+    fn test_array_round_trip() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let blocks = [(); 3].map(|_| Block::rand(&mut rng));
+
+        let bytes = blocks.into_bytes_owned();
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(bytes.as_ref(), blocks.to_vec().store_to_bytes().as_ref());
+        assert_eq!(<[Block; 3]>::from_bytes_owned(bytes).unwrap(), blocks);
+    }
+
+    #[test]
+    fn test_use_cast_is_le() {
+        let block = Block(m128i::from(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10u128));
+        let bytes = UseCast(block).into_bytes_owned();
+        // low lane first, each lane little-endian
+        assert_eq!(
+            bytes.as_ref(),
+            [
+                0x10, 0x0f, 0x0e, 0x0d, 0x0c, 0x0b, 0x0a, 0x09, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03,
+                0x02, 0x01
+            ]
+        );
+        assert_eq!(block.to_bytes_le(), bytes.as_ref());
+        assert_eq!(UseCast::<Block>::from_bytes_owned(bytes).unwrap(), block);
+        assert_eq!(Block::from_bytes_le(block.to_bytes_le()), block);
+    }
+
+    #[test]
+    fn test_format_is_pinned() {
+        let block = Block(m128i::from(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10u128));
+        assert_eq!(block.to_string(), "0102030405060708090a0b0c0d0e0f10");
+        assert_eq!(format!("{:x}", block), "0102030405060708090a0b0c0d0e0f10");
+        assert_eq!(
+            format!("{:#x}", block),
+            "0x0102030405060708090a0b0c0d0e0f10"
+        );
+        let pattern = Block(m128i::from(0xdeadbeef12345678abcdef0123456789u128));
+        assert_eq!(pattern.to_string(), "deadbeef12345678abcdef0123456789");
+        assert_eq!(format!("{:X}", pattern), "DEADBEEF12345678ABCDEF0123456789");
+        assert_eq!(Block::ZERO.to_string(), "0".repeat(32));
+        assert_eq!(Block::ONE.to_string(), format!("{}1", "0".repeat(31)));
+        // the leftmost bit is the coefficient of x^127
+        assert_eq!(format!("{:b}", Block::ONE), format!("{}1", "0".repeat(127)));
+        let x127 = Block(m128i::from(1u128 << 127));
+        assert_eq!(format!("{:b}", x127), format!("1{}", "0".repeat(127)));
+        assert_eq!(x127.to_string(), format!("8{}", "0".repeat(31)));
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        for block in [Block::ZERO, Block::ONE, Block::rand(&mut rng)] {
+            assert_eq!(block.to_string().parse::<Block>(), Ok(block));
+            assert_eq!(format!("{:X}", block).parse::<Block>(), Ok(block));
+        }
+
+        let err = Err(ParseBlockError { num_digits: 32 });
+        assert_eq!("1".parse::<Block>(), err);
+        assert_eq!("0".repeat(33).parse::<Block>(), err);
+        assert_eq!(format!("+{}", "0".repeat(31)).parse::<Block>(), err);
+        assert_eq!(format!("0x{}", "0".repeat(30)).parse::<Block>(), err);
+        assert_eq!(format!("{}g", "0".repeat(31)).parse::<Block>(), err);
+    }
+
     #[test]
     #[should_panic]
     fn unaligned_cast_should_fail() {
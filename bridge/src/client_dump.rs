@@ -0,0 +1,189 @@
+//! Record raw client messages to a file, and replay them later, so that the
+//! server phases can be benchmarked without running the clients again.
+//!
+//! The file starts with the number of records as `u64`, followed by one
+//! `(uid, is_alice_side, message)` tuple per client in `Communicate` framing.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use bytes::Bytes;
+use serialize::{AsUseCast, Communicate, UseCast};
+
+use crate::{
+    client_server::{ClientsPool, PartitionPolicy},
+    id_tracker::RecvId,
+    metrics::HEADER_SIZE,
+    tcp_bridge::ClientID,
+};
+
+type Error = crate::BridgeError;
+type Result<T> = std::result::Result<T, Error>;
+
+/// Raw messages received from the clients, split the same way as
+/// `ClientsPool::split`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientRecording {
+    pub alice: Vec<(ClientID, Bytes)>,
+    pub bob: Vec<(ClientID, Bytes)>,
+}
+
+impl ClientRecording {
+    /// Receive message `message_id` from all clients, without deserializing.
+    pub async fn fetch(
+        clients_alice: &ClientsPool,
+        clients_bob: &ClientsPool,
+        message_id: RecvId,
+    ) -> Result<Self> {
+        let (alice, bob) = tokio::join!(
+            clients_alice.subscribe_and_get_bytes(message_id),
+            clients_bob.subscribe_and_get_bytes(message_id)
+        );
+        Ok(Self {
+            alice: alice?,
+            bob: bob?,
+        })
+    }
+
+    /// Uids of all clients, in order.
+    pub fn uids(&self) -> Vec<ClientID> {
+        let mut uids = self
+            .alice
+            .iter()
+            .chain(&self.bob)
+            .map(|(uid, _)| *uid)
+            .collect::<Vec<_>>();
+        uids.sort_unstable();
+        uids
+    }
+
+    /// Uids of the clients where I'm Alice, in the order of `alice`.
+    pub fn uids_alice(&self) -> Vec<ClientID> {
+        self.alice.iter().map(|(uid, _)| *uid).collect()
+    }
+
+    /// Uids of the clients where I'm Bob, in the order of `bob`.
+    pub fn uids_bob(&self) -> Vec<ClientID> {
+        self.bob.iter().map(|(uid, _)| *uid).collect()
+    }
+
+    /// Whether the clients are split as `policy` splits them for the server
+    /// with global role `is_alice`. A recording replayed with another policy
+    /// would mix up the messages of the clients.
+    pub fn matches_policy(&self, is_alice: bool, policy: &PartitionPolicy) -> bool {
+        self.alice
+            .iter()
+            .all(|(uid, _)| policy.is_ot_sender(is_alice, *uid))
+            && self
+                .bob
+                .iter()
+                .all(|(uid, _)| !policy.is_ot_sender(is_alice, *uid))
+    }
+
+    /// Total size of the messages of the clients where I'm Alice.
+    pub fn num_bytes_alice(&self) -> usize {
+        self.alice.iter().map(|(_, m)| m.len()).sum()
+    }
+
+    /// Total size of the messages of the clients where I'm Bob.
+    pub fn num_bytes_bob(&self) -> usize {
+        self.bob.iter().map(|(_, m)| m.len()).sum()
+    }
+
+    /// Bytes received for the messages of the clients where I'm Alice and
+    /// where I'm Bob, with their headers, as counted by `TcpConnection`.
+    pub fn num_bytes_received(&self) -> (usize, usize) {
+        let header = HEADER_SIZE as usize;
+        (
+            self.num_bytes_alice() + header * self.alice.len(),
+            self.num_bytes_bob() + header * self.bob.len(),
+        )
+    }
+
+    pub fn write_to<W: Write>(&self, mut dest: W) -> Result<()> {
+        let records = self
+            .alice
+            .iter()
+            .map(|r| (true, r))
+            .chain(self.bob.iter().map(|r| (false, r)));
+        ((self.alice.len() + self.bob.len()) as u64)
+            .use_cast()
+            .to_bytes(&mut dest);
+        for (is_alice_side, (uid, message)) in records {
+            uid.use_cast().to_bytes(&mut dest);
+            (is_alice_side as u8).use_cast().to_bytes(&mut dest);
+            message[..].to_bytes(&mut dest);
+        }
+        dest.flush()?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut src: R) -> Result<Self> {
+        let num_records = UseCast::<u64>::from_bytes(&mut src)?;
+        let mut recording = Self::default();
+        for _ in 0..num_records {
+            let (uid, is_alice_side, message) =
+                <(UseCast<ClientID>, UseCast<u8>, Vec<u8>)>::from_bytes(&mut src)?;
+            let record = (uid, Bytes::from(message));
+            if is_alice_side != 0 {
+                recording.alice.push(record);
+            } else {
+                recording.bob.push(record);
+            }
+        }
+        Ok(recording)
+    }
+
+    pub fn dump(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::ClientRecording;
+    use crate::{client_server::PartitionPolicy, tcp_bridge::ClientID};
+
+    #[test]
+    fn test_write_read() {
+        let recording = ClientRecording {
+            alice: vec![
+                (ClientID::new(0), Bytes::from_static(b"alice 0")),
+                (ClientID::new(2), Bytes::new()),
+            ],
+            bob: vec![(ClientID::new(1), Bytes::from(vec![7u8; 1000]))],
+        };
+        let mut buf = Vec::new();
+        recording.write_to(&mut buf).unwrap();
+        assert_eq!(ClientRecording::read_from(&buf[..]).unwrap(), recording);
+        assert_eq!(recording.num_bytes_alice(), 7);
+        assert_eq!(recording.num_bytes_bob(), 1000);
+        assert_eq!(
+            recording.uids(),
+            (0..3).map(ClientID::new).collect::<Vec<_>>()
+        );
+        assert!(recording.matches_policy(true, &PartitionPolicy::Parity));
+        assert!(!recording.matches_policy(false, &PartitionPolicy::Parity));
+        assert!(!recording.matches_policy(true, &PartitionPolicy::Ratio(2, 1)));
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let recording = ClientRecording {
+            alice: vec![(ClientID::new(0), Bytes::from_static(b"alice 0"))],
+            bob: vec![],
+        };
+        let mut buf = Vec::new();
+        recording.write_to(&mut buf).unwrap();
+        assert!(ClientRecording::read_from(&buf[..buf.len() - 1]).is_err());
+    }
+}
@@ -1,54 +1,410 @@
-use std::{collections::BTreeSet, fmt::Debug, iter::FromIterator};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Display},
+    iter::FromIterator,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use bytes::Bytes;
-use tokio::net::{TcpListener, ToSocketAddrs};
-use tracing::{debug, error};
+use futures::{stream, StreamExt};
+use thiserror::Error;
+use tokio::{
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    runtime::Handle,
+    sync::mpsc,
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
 
-use itertools::Itertools;
 use serialize::Communicate;
 
 use crate::{
+    client_dump::ClientRecording,
     id_tracker::{RecvId, SendId},
-    tcp_bridge::{ClientID, TcpConnection},
+    tcp_bridge::{ClientID, ClientToken, Registration, TcpConnection, TcpConnectionConfig},
     tcp_connect_or_retry,
+    tls::ServerTls,
 };
 
 type Error = crate::BridgeError;
 type Result<T> = std::result::Result<T, Error>;
 
+/// How long an accepted socket has to send its version and registration.
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Decides which server is OT sender for each client. Both servers and the
+/// clients must use the same policy.
+#[derive(Clone, Default)]
+pub enum PartitionPolicy {
+    /// Alice is OT sender for clients with even uid.
+    #[default]
+    Parity,
+    /// Out of every `a + b` consecutive uids, Alice is OT sender for the first
+    /// `a`, and Bob for the other `b`.
+    Ratio(u32, u32),
+    /// Alice is OT sender for clients where the function returns true. It has
+    /// no descriptor, so it can only be set in code.
+    Custom(Arc<dyn Fn(ClientID) -> bool + Send + Sync>),
+}
+
+impl PartitionPolicy {
+    /// Whether Alice (global role) is OT sender for client `uid`.
+    pub fn alice_is_ot_sender(&self, uid: ClientID) -> bool {
+        match self {
+            PartitionPolicy::Parity => uid.is_even(),
+            PartitionPolicy::Ratio(a, b) => {
+                let period = *a as u64 + *b as u64;
+                assert!(period > 0, "empty partition ratio");
+                uid.id % period < *a as u64
+            },
+            PartitionPolicy::Custom(f) => f(uid),
+        }
+    }
+
+    /// Whether the server with global role `is_alice` is OT sender for client
+    /// `uid`.
+    pub fn is_ot_sender(&self, is_alice: bool, uid: ClientID) -> bool {
+        self.alice_is_ot_sender(uid) == is_alice
+    }
+
+    /// Split messages of clients, keyed by uid, into messages of clients where
+    /// I'm OT sender and messages of clients where I'm OT receiver. Both
+    /// parts keep the order of `msgs`.
+    #[allow(clippy::type_complexity)]
+    pub fn split_iter<T>(
+        &self,
+        is_alice: bool,
+        msgs: impl IntoIterator<Item = (ClientID, T)>,
+    ) -> (Vec<(ClientID, T)>, Vec<(ClientID, T)>) {
+        msgs.into_iter()
+            .partition(|(uid, _)| self.is_ot_sender(is_alice, *uid))
+    }
+
+    /// Inverse of `split_iter`: merge messages of clients where I'm OT sender
+    /// and where I'm OT receiver, keyed by uid, so that they are in uid order
+    /// whatever the order of each part.
+    ///
+    /// # Panics
+    /// If a client is in the wrong part, or has several messages.
+    pub fn merge_msg<T>(
+        &self,
+        is_alice: bool,
+        from_alice: impl IntoIterator<Item = (ClientID, T)>,
+        from_bob: impl IntoIterator<Item = (ClientID, T)>,
+    ) -> BTreeMap<ClientID, T> {
+        let from_alice = from_alice.into_iter().map(|m| (true, m));
+        let from_bob = from_bob.into_iter().map(|m| (false, m));
+        let mut merged = BTreeMap::new();
+        for (is_sender, (uid, m)) in from_alice.chain(from_bob) {
+            assert_eq!(
+                self.is_ot_sender(is_alice, uid),
+                is_sender,
+                "message of client {} in the wrong partition",
+                uid.id
+            );
+            if merged.insert(uid, m).is_some() {
+                panic!("several messages of client {}", uid.id);
+            }
+        }
+        merged
+    }
+}
+
+/// Key `msgs` by `uids`, in the same order.
+///
+/// # Panics
+/// If there is not one message per uid.
+pub fn with_uids<T>(uids: &[ClientID], msgs: impl IntoIterator<Item = T>) -> Vec<(ClientID, T)> {
+    let keyed = uids.iter().copied().zip(msgs).collect::<Vec<_>>();
+    assert_eq!(keyed.len(), uids.len(), "fewer messages than clients");
+    keyed
+}
+
+/// Messages of `keyed` in the order of `uids`, whatever the order of `keyed`.
+///
+/// # Panics
+/// If a client of `uids` has no message or several, or a message is of
+/// another client.
+pub fn join_uids<T>(uids: &[ClientID], keyed: impl IntoIterator<Item = (ClientID, T)>) -> Vec<T> {
+    let mut by_uid = BTreeMap::new();
+    for (uid, m) in keyed {
+        if by_uid.insert(uid, m).is_some() {
+            panic!("several messages of client {}", uid.id);
+        }
+    }
+    let mut keyed = by_uid;
+    let joined = uids
+        .iter()
+        .map(|uid| {
+            keyed
+                .remove(uid)
+                .unwrap_or_else(|| panic!("no message of client {}", uid.id))
+        })
+        .collect();
+    if let Some(uid) = keyed.keys().next() {
+        panic!("message of unexpected client {}", uid.id);
+    }
+    joined
+}
+
+impl Debug for PartitionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionPolicy::Parity => f.write_str("Parity"),
+            PartitionPolicy::Ratio(a, b) => write!(f, "Ratio({}, {})", a, b),
+            PartitionPolicy::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// The descriptor parsed by `from_str`, or `custom` for a custom policy.
+impl Display for PartitionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionPolicy::Parity => f.write_str("parity"),
+            PartitionPolicy::Ratio(a, b) => write!(f, "ratio:{}:{}", a, b),
+            PartitionPolicy::Custom(_) => f.write_str("custom"),
+        }
+    }
+}
+
+/// Parse a descriptor `parity` or `ratio:<a>:<b>`.
+impl FromStr for PartitionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts = s.split(':').collect::<Vec<_>>();
+        match parts.as_slice() {
+            ["parity"] => Ok(PartitionPolicy::Parity),
+            ["ratio", a, b] => {
+                let parse = |x: &str| {
+                    x.parse::<u32>()
+                        .map_err(|e| format!("invalid partition ratio {}: {}", s, e))
+                };
+                let (a, b) = (parse(a)?, parse(b)?);
+                if a == 0 && b == 0 {
+                    return Err(format!("invalid partition ratio {}: both parts are 0", s));
+                }
+                Ok(PartitionPolicy::Ratio(a, b))
+            },
+            _ => Err(format!("Unsupported partition policy: {}", s)),
+        }
+    }
+}
+
+/// What a `ClientsPool` does when a client connects again with a uid it
+/// already has, e.g. after its connection dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReconnectPolicy {
+    /// A duplicate uid is a bug of the clients, so the pool panics.
+    Never,
+    /// Serve the client on its new connection, as long as no message has
+    /// been consumed from the old one, and close the new connection
+    /// otherwise. The pool keeps accepting reconnections until it is dropped.
+    #[default]
+    Unconsumed,
+}
+
+/// Stops accepting clients when the last pool using it is dropped.
+struct Acceptor(Vec<JoinHandle<()>>);
+
+impl Drop for Acceptor {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
 /// An agent that receive data with multiple clients.
+///
+/// Per-client tasks are spawned on the runtime the pool was created on, even
+/// if the pool is used from another runtime, so that client work stays off
+/// the runtime of the MPC link.
 #[derive(Clone)]
 pub struct ClientsPool {
     pub clients: Vec<TcpConnection>,
+    pub policy: PartitionPolicy,
+    runtime: Handle,
+    acceptor: Option<Arc<Acceptor>>,
+    /// Client ends of a pool created by `in_memory`.
+    in_memory: Option<Arc<BTreeMap<ClientID, TcpConnection>>>,
 }
 
 impl ClientsPool {
-    pub async fn new(num_clients: usize, listener: TcpListener) -> Self {
-        // first, accept all the needed clients
-        let mut clients_handle = Vec::with_capacity(num_clients);
-        for _ in 0..num_clients {
-            let (socket, addr) = listener.accept().await.unwrap();
-            debug!("Connected to peer at {}", addr);
-            let conn = tokio::spawn(TcpConnection::new_server_side(socket));
-            clients_handle.push(conn);
+    /// Accept `num_clients` clients with distinct uids below `num_clients`
+    /// that register with `token`, with the default `ReconnectPolicy`.
+    pub async fn new(num_clients: usize, listener: TcpListener, token: ClientToken) -> Self {
+        Self::new_with_reconnect(num_clients, listener, token, ReconnectPolicy::default()).await
+    }
+
+    /// Accept `num_clients` clients with distinct uids below `num_clients`
+    /// that register with `token`. Connections with another token or an out
+    /// of range uid are closed, and the pool keeps waiting for the others. A
+    /// client that connects again with its uid is handled according to
+    /// `reconnect`.
+    pub async fn new_with_reconnect(
+        num_clients: usize,
+        listener: TcpListener,
+        token: ClientToken,
+        reconnect: ReconnectPolicy,
+    ) -> Self {
+        Self::accept_clients(num_clients, listener, token, reconnect, None).await
+    }
+
+    /// Same as `new`, but the clients connect over TLS, and the server
+    /// presents the certificate of `tls`. A client whose TLS handshake fails
+    /// is closed, and the pool keeps waiting for the others.
+    pub async fn new_tls(
+        num_clients: usize,
+        listener: TcpListener,
+        token: ClientToken,
+        tls: ServerTls,
+    ) -> Self {
+        Self::accept_clients(
+            num_clients,
+            listener,
+            token,
+            ReconnectPolicy::default(),
+            Some(tls),
+        )
+        .await
+    }
+
+    async fn accept_clients(
+        num_clients: usize,
+        listener: TcpListener,
+        token: ClientToken,
+        reconnect: ReconnectPolicy,
+        tls: Option<ServerTls>,
+    ) -> Self {
+        let runtime = Handle::current();
+        // registrations in the order they complete, so that a socket that
+        // never registers does not hold back the others, each with its rank
+        // in the order of accepting, so that a reconnection is not replaced
+        // by the connection it replaces
+        let (registrations_sender, mut registrations) = mpsc::unbounded_channel();
+        let accept_loop = runtime.spawn(async move {
+            for accepted in 0.. {
+                let socket = match listener.accept().await {
+                    Ok((socket, addr)) => {
+                        debug!("Connected to peer at {}", addr);
+                        socket
+                    },
+                    Err(e) => {
+                        warn!("failed to accept a client: {}", e);
+                        continue;
+                    },
+                };
+                if registrations_sender.is_closed() {
+                    break;
+                }
+                let tls = tls.clone();
+                let registrations_sender = registrations_sender.clone();
+                tokio::spawn(async move {
+                    let registration = tokio::time::timeout(
+                        REGISTRATION_TIMEOUT,
+                        Registration::receive(
+                            socket,
+                            tls.as_ref(),
+                            token,
+                            TcpConnectionConfig::default(),
+                        ),
+                    )
+                    .await
+                    .unwrap_or(Err(Error::ConnectionTimedOut));
+                    // the pool may be complete without reconnections
+                    let _ = registrations_sender.send((accepted, registration));
+                });
+            }
+        });
+
+        let mut clients = BTreeMap::new();
+        let mut latest = BTreeMap::new();
+        while clients.len() < num_clients {
+            let (accepted, registration) = registrations.recv().await.unwrap();
+            match registration {
+                Ok(registration) if registration.uid().id >= num_clients as u64 => warn!(
+                    "client {} is out of range of {} clients, close it",
+                    registration.uid().id,
+                    num_clients
+                ),
+                Ok(registration) if is_stale(&mut latest, registration.uid(), accepted) => {},
+                Ok(registration) => admit(&mut clients, registration, reconnect),
+                Err(e) => warn!("client failed to register: {}", e),
+            }
         }
-        let mut clients = Vec::with_capacity(num_clients);
-        for c in clients_handle {
-            clients.push(c.await.unwrap());
+
+        let acceptor = match reconnect {
+            ReconnectPolicy::Never => {
+                accept_loop.abort();
+                None
+            },
+            ReconnectPolicy::Unconsumed => {
+                let mut known = clients.clone();
+                let reconnect_loop = runtime.spawn(async move {
+                    while let Some((accepted, registration)) = registrations.recv().await {
+                        match registration {
+                            Ok(registration) if !known.contains_key(&registration.uid()) => warn!(
+                                "client {} connected after all clients, close it",
+                                registration.uid().id
+                            ),
+                            Ok(registration)
+                                if is_stale(&mut latest, registration.uid(), accepted) => {},
+                            Ok(registration) => admit(&mut known, registration, reconnect),
+                            Err(e) => warn!("client failed to register: {}", e),
+                        }
+                    }
+                });
+                Some(Arc::new(Acceptor(vec![accept_loop, reconnect_loop])))
+            },
+        };
+
+        Self {
+            clients: clients.into_values().collect(),
+            policy: PartitionPolicy::default(),
+            runtime,
+            acceptor,
+            in_memory: None,
         }
-        clients.sort_by_key(|c| c.uid());
+    }
 
-        // check if there is any duplicate key
-        assert_eq!(
-            clients
-                .iter()
-                .map(|x| x.uid())
-                .collect::<BTreeSet<_>>()
-                .len(),
-            clients.len(),
-            "Duplicate client uid"
-        );
-        Self { clients }
+    /// Clients connected in memory, without sockets, e.g. to test a server.
+    /// Client `uid` sends each message of `messages[uid]` under the id it is
+    /// received with. The client ends stay open as long as the pool, and
+    /// receive what the pool sends, see `in_memory_client`. Must be called
+    /// within a tokio runtime.
+    pub fn in_memory(messages: BTreeMap<ClientID, BTreeMap<RecvId, Bytes>>) -> Self {
+        let mut client_ends = BTreeMap::new();
+        let mut pool = messages
+            .into_iter()
+            .map(|(uid, msgs)| {
+                let (client, server) = TcpConnection::in_memory_pair(uid);
+                for (id, msg) in msgs {
+                    // written in order by the write loop of the client
+                    drop(client.send_message_bytes(SendId(id.0), msg));
+                }
+                client_ends.insert(uid, client);
+                server
+            })
+            .collect::<Self>();
+        pool.in_memory = Some(Arc::new(client_ends));
+        pool
+    }
+
+    /// The client end of client `uid` of a pool created by `in_memory`, to
+    /// receive the messages sent to the client, e.g. by
+    /// `broadcast_messages`.
+    pub fn in_memory_client(&self, uid: ClientID) -> Option<&TcpConnection> {
+        self.in_memory.as_ref()?.get(&uid)
+    }
+
+    /// Use `policy` to decide which server is OT sender in `split`.
+    pub fn with_policy(mut self, policy: PartitionPolicy) -> Self {
+        self.policy = policy;
+        self
     }
 
     pub fn num_of_clients(&self) -> usize {
@@ -62,70 +418,116 @@ impl ClientsPool {
             .sum()
     }
 
-    /// subscribe and wait to get bytes
-    pub async fn subscribe_and_get_bytes(&self, message_id: RecvId) -> Result<Vec<Bytes>> {
+    /// Subscribe and wait to get bytes, with the uid of each client, in pool
+    /// order.
+    pub async fn subscribe_and_get_bytes(
+        &self,
+        message_id: RecvId,
+    ) -> Result<Vec<(ClientID, Bytes)>> {
         // for each client, subscribe the struct
         let msg_handle = self
             .clients
             .iter()
             .map(|client| {
+                let uid = client.uid();
                 let client = client.clone();
-                tokio::spawn(async move { client.subscribe_and_get_bytes(message_id).await })
+                let handle = self
+                    .runtime
+                    .spawn(async move { client.subscribe_and_get_bytes(message_id).await });
+                (uid, handle)
             })
             .collect::<Vec<_>>();
         let mut result = Vec::with_capacity(self.clients.len());
-        for handle in msg_handle {
-            result.push(handle.await.unwrap());
+        for (uid, handle) in msg_handle {
+            result.push((uid, handle.await.unwrap()?));
         }
 
-        return Ok(result);
+        Ok(result)
     }
 
-    /// Subscribe and get message that does not contain any references
+    /// Subscribe and get message that does not contain any references, with
+    /// the uid of each client, in pool order. Fail with the error of the
+    /// first client whose message cannot be received or deserialized.
     pub async fn subscribe_and_get<T: Communicate>(
         &self,
         message_id: RecvId,
-    ) -> Result<Vec<T::Deserialized>> {
+    ) -> Result<Vec<(ClientID, T::Deserialized)>> {
+        self.subscribe_and_get_each::<T>(message_id)
+            .await
+            .into_iter()
+            .map(|(uid, m)| Ok((uid, m?)))
+            .collect()
+    }
+
+    /// Same as `subscribe_and_get`, with one result per client, so that a
+    /// malformed message only fails the client that sent it.
+    pub async fn subscribe_and_get_each<T: Communicate>(
+        &self,
+        message_id: RecvId,
+    ) -> Vec<(ClientID, Result<T::Deserialized>)> {
         // for each client, subscribe the struct
         let msg_handle = self
             .clients
             .iter()
             .map(|client| {
+                let uid = client.uid();
                 let client = client.clone();
-                tokio::spawn(
-                    async move { client.subscribe_and_get::<T>(message_id).await.unwrap() },
-                )
+                let handle = self
+                    .runtime
+                    .spawn(async move { client.subscribe_and_get::<T>(message_id).await });
+                (uid, handle)
             })
             .collect::<Vec<_>>();
         let mut result = Vec::with_capacity(self.clients.len());
-        for handle in msg_handle {
-            result.push(handle.await.unwrap());
+        for (uid, handle) in msg_handle {
+            result.push((uid, handle.await.unwrap()));
         }
+        result
+    }
 
-        return Ok(result);
+    /// Same as `subscribe_and_get_each`, but a client whose message has not
+    /// arrived within `timeout` fails with `BridgeError::SubscribeTimeout`.
+    /// The clients are awaited concurrently, so this takes at most `timeout`
+    /// in total.
+    pub async fn subscribe_and_get_each_timeout<T: Communicate>(
+        &self,
+        message_id: RecvId,
+        timeout: Duration,
+    ) -> Vec<(ClientID, Result<T::Deserialized>)> {
+        let msg_handle = self
+            .clients
+            .iter()
+            .map(|client| {
+                let uid = client.uid();
+                let client = client.clone();
+                let handle = self.runtime.spawn(async move {
+                    client
+                        .subscribe_and_get_timeout::<T>(message_id, timeout)
+                        .await
+                });
+                (uid, handle)
+            })
+            .collect::<Vec<_>>();
+        let mut result = Vec::with_capacity(self.clients.len());
+        for (uid, handle) in msg_handle {
+            result.push((uid, handle.await.unwrap()));
+        }
+        result
     }
 
     /// Broadcast message as bytes to all clients
     pub async fn broadcast_messages_as_bytes(&self, message_id: SendId, message: Bytes) {
+        // queueing a message is non-blocking, so no need to spawn a task per client
         let handles = self
             .clients
             .iter()
-            .map(|client| {
-                let message = message.clone(); // this is cheap
-                let client = client.clone();
-                tokio::spawn(async move { client.send_message_bytes(message_id, message) })
-            })
+            .map(|client| client.send_message_bytes(message_id, message.clone()))
             .collect::<Vec<_>>();
 
         // wait for all handles to complete
         for handle in handles {
-            match handle.await {
-                Ok(w) => w.await.unwrap_or_else(|e| {
-                    error!("failed to send message: {:?}", e);
-                }),
-                Err(e) => {
-                    error!("failed to send message: {:?}", e);
-                },
+            if let Err(e) = handle.await {
+                error!("failed to send message: {:?}", e);
             }
         }
     }
@@ -140,117 +542,386 @@ impl ClientsPool {
         self.clients.iter()
     }
 
+    /// Uids of the clients, in order.
+    pub fn uids(&self) -> Vec<ClientID> {
+        self.clients.iter().map(|c| c.uid()).collect()
+    }
+
+    /// Split into clients where I'm OT sender and clients where I'm OT
+    /// receiver, according to the policy of the pool. `is_alice` is my global
+    /// role. Both parts keep the policy.
     pub fn split(&self, is_alice: bool) -> (Self, Self) {
-        let clients_with_odd_uid = self
-            .iter()
-            .filter(|c| c.uid().is_odd())
-            .cloned()
-            .collect::<ClientsPool>();
-        let clients_with_even_uid = self
-            .iter()
-            .filter(|c| c.uid().is_even())
-            .cloned()
-            .collect::<ClientsPool>();
-        let (clients_alice, clients_bob) = if is_alice {
-            (clients_with_even_uid, clients_with_odd_uid)
-        } else {
-            (clients_with_odd_uid, clients_with_even_uid)
-        };
-        (clients_alice, clients_bob)
+        let (clients_alice, clients_bob) = self
+            .policy
+            .split_iter(is_alice, self.clients.iter().map(|c| (c.uid(), c.clone())));
+        let (clients_alice, clients_bob) = (
+            clients_alice.into_iter().map(|(_, c)| c).collect(),
+            clients_bob.into_iter().map(|(_, c)| c).collect(),
+        );
+        (
+            Self {
+                clients: clients_alice,
+                policy: self.policy.clone(),
+                runtime: self.runtime.clone(),
+                acceptor: self.acceptor.clone(),
+                in_memory: self.in_memory.clone(),
+            },
+            Self {
+                clients: clients_bob,
+                policy: self.policy.clone(),
+                runtime: self.runtime.clone(),
+                acceptor: self.acceptor.clone(),
+                in_memory: self.in_memory.clone(),
+            },
+        )
     }
+}
 
-    pub fn merge_msg<'a, T>(
+/// Clients of a session of several rounds: accepted once, and kept connected
+/// for all the rounds. Clones share the clients.
+#[derive(Clone)]
+pub struct ClientSession {
+    is_alice: bool,
+    clients_alice: ClientsPool,
+    clients_bob: ClientsPool,
+    rounds: u64,
+}
+
+impl ClientSession {
+    /// Accept `num_clients` clients that register with `token`, see
+    /// `ClientsPool::new`, and split them by `policy` for the server with
+    /// global role `is_alice`. The session has a single round, see
+    /// `with_rounds`.
+    pub async fn accept(
         is_alice: bool,
-        from_alice: impl Iterator<Item = T> + 'a,
-        from_bob: impl Iterator<Item = T> + 'a,
-    ) -> Vec<T> {
-        if is_alice {
-            // from_alice is even uid
-            from_alice.interleave(from_bob).collect()
-        } else {
-            from_bob.interleave(from_alice).collect()
-        }
+        policy: PartitionPolicy,
+        listener: TcpListener,
+        num_clients: usize,
+        token: ClientToken,
+    ) -> Self {
+        Self::accept_with_tls(is_alice, policy, listener, num_clients, token, None).await
     }
 
-    pub fn split_iter<'a, T>(
+    /// Same as `accept`, over TLS if `tls` is set, see `ClientsPool::new_tls`.
+    pub async fn accept_with_tls(
         is_alice: bool,
-        msg: impl Iterator<Item = T> + 'a,
-    ) -> (Vec<T>, Vec<T>) {
-        let mut alice = Vec::new();
-        let mut bob = Vec::new();
-        let mut i = 0usize;
-        for m in msg {
-            if is_alice {
-                if i % 2 == 0 {
-                    alice.push(m);
-                } else {
-                    bob.push(m);
-                }
-            } else {
-                if i % 2 == 0 {
-                    bob.push(m);
-                } else {
-                    alice.push(m);
-                }
-            }
-            i += 1;
+        policy: PartitionPolicy,
+        listener: TcpListener,
+        num_clients: usize,
+        token: ClientToken,
+        tls: Option<ServerTls>,
+    ) -> Self {
+        let clients = match tls {
+            Some(tls) => ClientsPool::new_tls(num_clients, listener, token, tls).await,
+            None => ClientsPool::new(num_clients, listener, token).await,
+        };
+        Self::from_pool(is_alice, policy, clients)
+    }
+
+    /// The clients of `clients`, e.g. of `ClientsPool::in_memory`, split by
+    /// `policy` for the server with global role `is_alice`. The session has a
+    /// single round, see `with_rounds`.
+    pub fn from_pool(is_alice: bool, policy: PartitionPolicy, clients: ClientsPool) -> Self {
+        // "alice" here is the OT sender of a client, not the global role
+        let (clients_alice, clients_bob) = clients.with_policy(policy).split(is_alice);
+        Self {
+            is_alice,
+            clients_alice,
+            clients_bob,
+            rounds: 1,
+        }
+    }
+
+    /// Run `rounds` rounds with the clients.
+    pub fn with_rounds(mut self, rounds: u64) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    pub fn is_alice(&self) -> bool {
+        self.is_alice
+    }
+
+    pub fn policy(&self) -> &PartitionPolicy {
+        &self.clients_alice.policy
+    }
+
+    /// The clients where I'm Alice and the clients where I'm Bob.
+    pub fn pools(&self) -> (&ClientsPool, &ClientsPool) {
+        (&self.clients_alice, &self.clients_bob)
+    }
+
+    /// Receive the first message of round `round` from all clients, sent with
+    /// `SendId::FIRST.in_round(round)`, without deserializing.
+    pub async fn fetch_round(&self, round: u64) -> Result<ClientRecording> {
+        assert!(
+            round < self.rounds,
+            "round {} of a session of {} rounds",
+            round,
+            self.rounds
+        );
+        ClientRecording::fetch(
+            &self.clients_alice,
+            &self.clients_bob,
+            RecvId::FIRST.in_round(round),
+        )
+        .await
+    }
+
+    /// Bytes received from the clients where I'm Alice and where I'm Bob in
+    /// the round of `recording`. With a single round, this is all the traffic
+    /// of the clients, including the registrations. Otherwise clients may
+    /// send a round before the servers are done with the previous one, so
+    /// only the messages of the round count, see
+    /// `ClientRecording::num_bytes_received`.
+    pub fn round_comm(&self, recording: &ClientRecording) -> (usize, usize) {
+        if self.rounds == 1 {
+            (
+                self.clients_alice.num_bytes_received_from_all(),
+                self.clients_bob.num_bytes_received_from_all(),
+            )
+        } else {
+            recording.num_bytes_received()
         }
-        (alice, bob)
     }
 }
 
+/// Must be called within a tokio runtime, which then runs the per-client
+/// tasks of the pool.
 impl FromIterator<TcpConnection> for ClientsPool {
     fn from_iter<T: IntoIterator<Item = TcpConnection>>(iter: T) -> Self {
         Self {
             clients: iter.into_iter().collect(),
+            policy: PartitionPolicy::default(),
+            runtime: Handle::current(),
+            acceptor: None,
+            in_memory: None,
+        }
+    }
+}
+
+/// Whether a connection of `uid` accepted after the one of rank `accepted`
+/// registered first, and record `accepted` as the latest of `uid` otherwise.
+fn is_stale(latest: &mut BTreeMap<ClientID, usize>, uid: ClientID, accepted: usize) -> bool {
+    match latest.insert(uid, accepted) {
+        Some(newer) if newer > accepted => {
+            latest.insert(uid, newer);
+            warn!(
+                "client {} registered an older connection late, close it",
+                uid.id
+            );
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Add the client of `registration` to `clients`, or move it to its new
+/// connection if it is already there.
+fn admit(
+    clients: &mut BTreeMap<ClientID, TcpConnection>,
+    registration: Registration,
+    reconnect: ReconnectPolicy,
+) {
+    let uid = registration.uid();
+    let Some(conn) = clients.get(&uid) else {
+        let conn = TcpConnection::from_registration(registration, TcpConnectionConfig::default());
+        clients.insert(uid, conn);
+        return;
+    };
+    match reconnect {
+        ReconnectPolicy::Never => panic!("Duplicate client uid {}", uid.id),
+        ReconnectPolicy::Unconsumed => {
+            if conn.replace_socket(registration) {
+                info!("client {} reconnected", uid.id);
+            } else {
+                warn!(
+                    "client {} reconnected after its messages were consumed, close it",
+                    uid.id
+                );
+            }
+        },
+    }
+}
+
+/// Connect to `addr`, retrying until `timeout` if set. Fails with
+/// `BridgeError::ConnectionTimedOut` if nobody accepts in time.
+pub async fn tcp_connect_timeout(
+    addr: impl ToSocketAddrs + Copy + Debug,
+    timeout: Option<Duration>,
+) -> Result<TcpStream> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, tcp_connect_or_retry(addr))
+            .await
+            .map_err(|_| Error::ConnectionTimedOut),
+        None => Ok(tcp_connect_or_retry(addr).await),
+    }
+}
+
+/// How `init_meta_clients` ramps up its connections. The default connects
+/// one client at a time and retries forever, since the servers may come up
+/// after the clients.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientConnectConfig {
+    /// Clients connecting at the same time, each to both servers. Keep it
+    /// below the accept backlog of the servers: a connection that overflows
+    /// the backlog may look established to the client but never be accepted.
+    pub max_concurrent_connects: usize,
+    /// Wait between starting two batches of `max_concurrent_connects`
+    /// clients, so that the accept queues of the servers can drain.
+    pub connect_batch_delay: Duration,
+    /// Attempts after the first failed connect to a server, 100ms apart.
+    pub retries: usize,
+}
+
+impl Default for ClientConnectConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_connects: 1,
+            connect_batch_delay: Duration::ZERO,
+            retries: usize::MAX,
+        }
+    }
+}
+
+/// Log the progress of `init_meta_clients` every that many clients.
+const CONNECT_PROGRESS_INTERVAL: usize = 1000;
+
+/// Clients of `init_meta_clients` that are not connected to both servers.
+#[derive(Error, Debug)]
+#[error(
+    "{} of {} clients failed to connect: {:?}",
+    .failed.len(),
+    .failed.len() + .connected.len(),
+    .failed
+)]
+pub struct InitClientsError {
+    /// Uids that failed after all retries. A client may still be registered
+    /// to the other server, which then sees it disconnect.
+    pub failed: Vec<ClientID>,
+    /// Connections of the other clients, by increasing uid.
+    pub connected: Vec<(TcpConnection, TcpConnection)>,
+}
+
+/// Connect to `addr`, with at most `retries` more attempts if it fails.
+async fn tcp_connect_with_retries(
+    addr: impl ToSocketAddrs + Copy + Debug,
+    retries: usize,
+) -> Result<TcpStream> {
+    let mut attempts = 0;
+    loop {
+        match TcpStream::connect(addr).await {
+            Ok(socket) => return Ok(socket),
+            Err(e) if attempts < retries => {
+                debug!("Error connect to {:?}: {}. Retry in 100ms", addr, e);
+                attempts += 1;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            },
+            Err(e) => return Err(e.into()),
         }
     }
 }
 
+/// Connect client `uid` to `addr` and register it with `token`.
+async fn connect_meta_client(
+    addr: impl ToSocketAddrs + Copy + Debug,
+    uid: ClientID,
+    token: ClientToken,
+    retries: usize,
+) -> Result<TcpConnection> {
+    let socket = tcp_connect_with_retries(addr, retries).await?;
+    debug!("Connected to server at {}", socket.peer_addr()?);
+    let (conn, registered) = TcpConnection::new_client_side(socket, uid, token);
+    registered.await.map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            "connection closed before registration was sent",
+        )
+    })?;
+    Ok(conn)
+}
+
 /// returns a vector of length `num_of_clients` with each element a pair of
-/// (address_to_server0, address_to_server1)
+/// (address_to_server0, address_to_server1), all registered with `token`.
+/// Clients connect as `config` says. If some fail, the others are returned in
+/// the error.
 pub async fn init_meta_clients(
     num_clients: usize,
     server0: impl ToSocketAddrs + Copy + Debug,
     server1: impl ToSocketAddrs + Copy + Debug,
-) -> Vec<(TcpConnection, TcpConnection)> {
-    let mut connections = Vec::with_capacity(num_clients);
-    let mut progresses = Vec::with_capacity(num_clients * 2);
-    for uid in 0..num_clients {
-        let uid = ClientID::new(uid as u64);
-        let socket0 = tcp_connect_or_retry(server0).await;
-        let socket1 = tcp_connect_or_retry(server1).await;
-        debug!(
-            "Connected to peer at server0 at {}",
-            socket0.peer_addr().unwrap()
-        );
-        debug!(
-            "Connected to peer at server1 at {}",
-            socket1.peer_addr().unwrap()
-        );
-        let (conn0, p0) = TcpConnection::new_client_side(socket0, uid);
-        let (conn1, p1) = TcpConnection::new_client_side(socket1, uid);
-        connections.push((conn0, conn1));
-        progresses.push(p0);
-        progresses.push(p1);
+    token: ClientToken,
+    config: ClientConnectConfig,
+) -> std::result::Result<Vec<(TcpConnection, TcpConnection)>, InitClientsError> {
+    let batch_size = config.max_concurrent_connects.max(1);
+    let mut results = stream::iter(0..num_clients)
+        .then(|uid| async move {
+            if uid > 0 && uid % batch_size == 0 && !config.connect_batch_delay.is_zero() {
+                tokio::time::sleep(config.connect_batch_delay).await;
+            }
+            ClientID::new(uid as u64)
+        })
+        .map(|uid| async move {
+            // the servers may come up in either order
+            let connections = tokio::try_join!(
+                connect_meta_client(server0, uid, token, config.retries),
+                connect_meta_client(server1, uid, token, config.retries)
+            );
+            (uid, connections)
+        })
+        .buffer_unordered(batch_size)
+        .enumerate()
+        .map(|(done, result)| {
+            if (done + 1) % CONNECT_PROGRESS_INTERVAL == 0 {
+                info!("{}/{} clients connected", done + 1, num_clients);
+            }
+            result
+        })
+        .collect::<Vec<_>>()
+        .await;
+    results.sort_unstable_by_key(|(uid, _)| *uid);
+
+    let mut connected = Vec::with_capacity(num_clients);
+    let mut failed = Vec::new();
+    for (uid, result) in results {
+        match result {
+            Ok(connections) => connected.push(connections),
+            Err(e) => {
+                warn!("client {} failed to connect: {}", uid.id, e);
+                failed.push(uid);
+            },
+        }
     }
-    for w in progresses {
-        w.await.unwrap();
+    if failed.is_empty() {
+        Ok(connected)
+    } else {
+        Err(InitClientsError { failed, connected })
     }
-
-    connections
 }
 
 #[cfg(test)]
 mod tests {
-    use tokio::net::{TcpListener, TcpStream};
+    use tokio::net::{TcpListener, TcpSocket, TcpStream};
     use tracing::{info, Level};
 
     use serialize::UseCast;
 
+    use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+    use serialize::Communicate;
+
     use crate::{
-        client_server::ClientsPool,
-        tcp_bridge::{ClientID, TcpConnection},
+        client_server::{
+            init_meta_clients, join_uids, with_uids, ClientConnectConfig, ClientSession,
+            ClientsPool, PartitionPolicy,
+        },
+        id_tracker::{RecvId, SendId},
+        metrics::HEADER_SIZE,
+        tcp_bridge::{ClientID, ClientToken, TcpConnection, TcpConnectionConfig},
+        tls::{
+            rustls::{Certificate, PrivateKey},
+            ClientTls, ServerTls,
+        },
+        BridgeError,
     };
 
     const TEST_ADDRESS: &str = "localhost:6665";
@@ -268,14 +939,17 @@ mod tests {
             let listener = TcpListener::bind(TEST_ADDRESS).await.unwrap();
             info!("Listening to {}", TEST_ADDRESS);
 
-            let aggregator = ClientsPool::new(NUM_CLIENTS, listener).await;
+            let aggregator = ClientsPool::new(NUM_CLIENTS, listener, ClientToken::default()).await;
 
             let received_payload = aggregator
                 .subscribe_and_get::<UseCast<usize>>(12.into())
                 .await
                 .unwrap();
 
-            assert_eq!(received_payload, (0..NUM_CLIENTS).collect::<Vec<_>>());
+            let expected = (0..NUM_CLIENTS)
+                .map(|i| (ClientID::new(i as u64), i))
+                .collect::<Vec<_>>();
+            assert_eq!(received_payload, expected);
 
             info!("Received Payload: {:?}", received_payload);
         });
@@ -295,14 +969,557 @@ mod tests {
                         },
                     }
                 }
-                let (conn, wait) =
-                    TcpConnection::new_client_side(socket, ClientID::new(client_index as u64));
+                let (conn, wait) = TcpConnection::new_client_side(
+                    socket,
+                    ClientID::new(client_index as u64),
+                    ClientToken::default(),
+                );
                 wait.await.unwrap();
-                conn.send_message(12.into(), &UseCast(client_index))
-                    .unwrap();
+                conn.send_message(12.into(), UseCast(client_index)).unwrap();
             });
         });
 
         handle.await.unwrap();
     }
+
+    async fn connect_with_token(
+        addr: std::net::SocketAddr,
+        uid: u64,
+        token: ClientToken,
+    ) -> TcpConnection {
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let config = TcpConnectionConfig {
+            keep_sent: true,
+            ..Default::default()
+        };
+        let (conn, registered) =
+            TcpConnection::new_client_side_with_config(socket, ClientID::new(uid), token, config);
+        registered.await.unwrap();
+        conn
+    }
+
+    async fn connect_keeping_sent(addr: std::net::SocketAddr, uid: u64) -> TcpConnection {
+        connect_with_token(addr, uid, ClientToken::default()).await
+    }
+
+    #[tokio::test]
+    async fn test_pool_replaces_reconnected_client() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = tokio::spawn(ClientsPool::new(2, listener, ClientToken::default()));
+
+        let first = connect_keeping_sent(addr, 0).await;
+        first
+            .send_message(SendId::FIRST, UseCast(10u64))
+            .unwrap()
+            .await
+            .unwrap();
+        // same uid again, before the pool is complete
+        first.reconnect(addr).await.unwrap();
+        let second = connect_keeping_sent(addr, 1).await;
+        second.send_message(SendId::FIRST, UseCast(11u64)).unwrap();
+
+        let pool = pool.await.unwrap();
+        assert_eq!(pool.uids(), vec![ClientID::new(0), ClientID::new(1)]);
+        let received = pool
+            .subscribe_and_get::<UseCast<u64>>(RecvId::FIRST)
+            .await
+            .unwrap();
+        assert_eq!(
+            received,
+            vec![(ClientID::new(0), 10), (ClientID::new(1), 11)]
+        );
+    }
+
+    /// A self-signed certificate for localhost, and a client trusting it.
+    fn self_signed_tls() -> (ServerTls, ClientTls) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let der = Certificate(cert.serialize_der().unwrap());
+        let key = PrivateKey(cert.serialize_private_key_der());
+        (
+            ServerTls::new(vec![der.clone()], key).unwrap(),
+            ClientTls::new("localhost", &[der]).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_pool_over_tls() {
+        let (server_tls, client_tls) = self_signed_tls();
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = tokio::spawn(ClientsPool::new_tls(
+            2,
+            listener,
+            ClientToken::default(),
+            server_tls,
+        ));
+
+        // a client that does not trust the certificate fails the handshake,
+        // and the pool keeps waiting
+        let (_, stranger_tls) = self_signed_tls();
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let stranger = TcpConnection::new_client_side_tls(
+            socket,
+            ClientID::new(0),
+            ClientToken::default(),
+            &stranger_tls,
+        )
+        .await;
+        assert!(matches!(stranger, Err(BridgeError::IoError(_))));
+
+        let mut clients = Vec::new();
+        for uid in 0..2u64 {
+            let socket = TcpStream::connect(addr).await.unwrap();
+            let (conn, registered) = TcpConnection::new_client_side_tls(
+                socket,
+                ClientID::new(uid),
+                ClientToken::default(),
+                &client_tls,
+            )
+            .await
+            .unwrap();
+            registered.await.unwrap();
+            conn.send_message(SendId::FIRST, vec![uid; 100])
+                .unwrap()
+                .await
+                .unwrap();
+            clients.push(conn);
+        }
+        let pool = pool.await.unwrap();
+        let received = pool
+            .subscribe_and_get::<Vec<u64>>(RecvId::FIRST)
+            .await
+            .unwrap();
+        assert_eq!(
+            received,
+            vec![
+                (ClientID::new(0), vec![0; 100]),
+                (ClientID::new(1), vec![1; 100])
+            ]
+        );
+        pool.clients[1]
+            .send_message(SendId::FIRST, UseCast(7u64))
+            .unwrap();
+        let answer = clients[1]
+            .subscribe_and_get::<UseCast<u64>>(RecvId::FIRST)
+            .await
+            .unwrap();
+        assert_eq!(answer, 7);
+
+        // the counters see the framed messages, not the TLS records
+        let header = HEADER_SIZE as usize;
+        let sent = 2 * header + 40 + vec![0u64; 100].size_in_bytes();
+        for (client, server) in clients.iter().zip(&pool.clients) {
+            assert_eq!(client.num_bytes_sent(), sent);
+            assert_eq!(server.num_bytes_received(), sent);
+        }
+        assert_eq!(clients[1].num_bytes_received(), header + 8);
+        assert_eq!(pool.clients[1].num_bytes_sent(), header + 8);
+    }
+
+    #[tokio::test]
+    async fn test_pool_malformed_message_fails_one_client() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = tokio::spawn(ClientsPool::new(2, listener, ClientToken::default()));
+        let good = connect_keeping_sent(addr, 0).await;
+        good.send_message(SendId::FIRST, vec![1u64, 2]).unwrap();
+        let bad = connect_keeping_sent(addr, 1).await;
+        // declares 2 elements, but carries only one
+        bad.send_message(SendId::FIRST, (UseCast(2u64), UseCast(3u64)))
+            .unwrap();
+        let pool = pool.await.unwrap();
+
+        let received = pool.subscribe_and_get_each::<Vec<u64>>(RecvId::FIRST).await;
+        assert_eq!(received[0].0, ClientID::new(0));
+        assert_eq!(received[0].1.as_ref().unwrap(), &vec![1, 2]);
+        assert!(matches!(
+            received[1],
+            (uid, Err(BridgeError::SerializationError(_))) if uid == ClientID::new(1)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pool_timeout_fails_one_client() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = tokio::spawn(ClientsPool::new(2, listener, ClientToken::default()));
+        let sending = connect_keeping_sent(addr, 0).await;
+        sending.send_message(SendId::FIRST, UseCast(10u64)).unwrap();
+        // registers, but never sends
+        let _silent = connect_keeping_sent(addr, 1).await;
+        let pool = pool.await.unwrap();
+
+        let received = pool
+            .subscribe_and_get_each_timeout::<UseCast<u64>>(
+                RecvId::FIRST,
+                Duration::from_millis(300),
+            )
+            .await;
+        assert_eq!(received[0].1.as_ref().unwrap(), &10);
+        assert!(matches!(
+            received[1],
+            (uid, Err(BridgeError::SubscribeTimeout { .. })) if uid == ClientID::new(1)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pool_keeps_consumed_client() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = tokio::spawn(ClientsPool::new(1, listener, ClientToken::default()));
+        let client = connect_keeping_sent(addr, 0).await;
+        client.send_message(SendId::FIRST, UseCast(10u64)).unwrap();
+        let pool = pool.await.unwrap();
+        let received = pool
+            .subscribe_and_get::<UseCast<u64>>(RecvId::FIRST)
+            .await
+            .unwrap();
+        assert_eq!(received, vec![(ClientID::new(0), 10)]);
+
+        // the pool closes the new connection, so later messages are lost
+        client.reconnect(addr).await.unwrap();
+        client.send_message(SendId::SECOND, UseCast(11u64)).unwrap();
+        let late = pool.clients[0]
+            .subscribe_and_get_bytes_timeout(RecvId::SECOND, Duration::from_millis(300))
+            .await;
+        assert!(matches!(late, Err(BridgeError::SubscribeTimeout { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_pool_keys_messages_by_uid() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = tokio::spawn(ClientsPool::new(3, listener, ClientToken::default()));
+        let mut clients = Vec::new();
+        for uid in [2, 0, 1] {
+            let client = connect_keeping_sent(addr, uid).await;
+            client
+                .send_message(SendId::FIRST, UseCast(uid * 10))
+                .unwrap();
+            clients.push(client);
+        }
+        let pool = pool.await.unwrap();
+
+        // a pool of the same clients in another order gets the same messages
+        let scrambled = pool.clients.iter().rev().cloned().collect::<ClientsPool>();
+        let received = scrambled
+            .subscribe_and_get::<UseCast<u64>>(RecvId::FIRST)
+            .await
+            .unwrap();
+        assert_eq!(
+            received,
+            [2, 1, 0].map(|uid| (ClientID::new(uid), uid * 10))
+        );
+        let (alice, bob) = scrambled.split(true);
+        assert_eq!(alice.uids(), [2, 0].map(ClientID::new));
+        assert_eq!(bob.uids(), [ClientID::new(1)]);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_pool() {
+        let messages = (0..3u64)
+            .map(|uid| {
+                let msg = |x: u64| UseCast(uid * 10 + x).into_bytes_owned();
+                let msgs = BTreeMap::from([(RecvId::FIRST, msg(0)), (RecvId::SECOND, msg(1))]);
+                (ClientID::new(uid), msgs)
+            })
+            .collect();
+        let session = ClientSession::from_pool(
+            true,
+            PartitionPolicy::Parity,
+            ClientsPool::in_memory(messages),
+        );
+        let (alice, bob) = session.pools();
+        assert_eq!(alice.uids(), [0, 2].map(ClientID::new));
+        assert_eq!(bob.uids(), [ClientID::new(1)]);
+
+        let recording = session.fetch_round(0).await.unwrap();
+        assert_eq!(recording.uids(), [0, 1, 2].map(ClientID::new));
+        let second = alice
+            .subscribe_and_get::<UseCast<u64>>(RecvId::SECOND)
+            .await
+            .unwrap();
+        assert_eq!(second, [(ClientID::new(0), 1), (ClientID::new(2), 21)]);
+        // no registration, only the headers and the messages
+        assert_eq!(
+            alice.num_bytes_received_from_all(),
+            4 * (HEADER_SIZE as usize + 8)
+        );
+
+        // the client ends receive what the pool sends, after the split too
+        bob.broadcast_messages(SendId(5), UseCast(77u64)).await;
+        let client = bob.in_memory_client(ClientID::new(1)).unwrap();
+        let received = client.subscribe_and_get::<UseCast<u64>>(RecvId(5)).await;
+        assert_eq!(received.unwrap(), 77);
+        assert!(alice.in_memory_client(ClientID::new(3)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pool_drops_unauthenticated_clients() {
+        let token = ClientToken([7; 32]);
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = tokio::spawn(ClientsPool::new(2, listener, token));
+
+        // both are rejected, and do not take the slots of the legitimate
+        // clients
+        let impostor = connect_with_token(addr, 0, ClientToken([8; 32])).await;
+        let out_of_range = connect_with_token(addr, 2, token).await;
+        let first = connect_with_token(addr, 0, token).await;
+        first.send_message(SendId::FIRST, UseCast(10u64)).unwrap();
+        let second = connect_with_token(addr, 1, token).await;
+        second.send_message(SendId::FIRST, UseCast(11u64)).unwrap();
+
+        let pool = pool.await.unwrap();
+        assert_eq!(pool.uids(), vec![ClientID::new(0), ClientID::new(1)]);
+        let received = pool
+            .subscribe_and_get::<UseCast<u64>>(RecvId::FIRST)
+            .await
+            .unwrap();
+        // the slot of uid 0 went to the client with the right token
+        assert_eq!(
+            received,
+            vec![(ClientID::new(0), 10), (ClientID::new(1), 11)]
+        );
+        drop((impostor, out_of_range));
+    }
+
+    #[tokio::test]
+    async fn test_pool_fills_behind_silent_socket() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pool = tokio::spawn(ClientsPool::new(2, listener, ClientToken::default()));
+
+        // connects first, and never registers
+        let silent = TcpStream::connect(addr).await.unwrap();
+        let first = connect_keeping_sent(addr, 0).await;
+        first.send_message(SendId::FIRST, UseCast(10u64)).unwrap();
+        let second = connect_keeping_sent(addr, 1).await;
+        second.send_message(SendId::FIRST, UseCast(11u64)).unwrap();
+
+        // well within `REGISTRATION_TIMEOUT`
+        let pool = tokio::time::timeout(Duration::from_secs(5), pool)
+            .await
+            .expect("the silent socket blocked the pool")
+            .unwrap();
+        assert_eq!(pool.uids(), vec![ClientID::new(0), ClientID::new(1)]);
+        let received = pool
+            .subscribe_and_get::<UseCast<u64>>(RecvId::FIRST)
+            .await
+            .unwrap();
+        assert_eq!(
+            received,
+            vec![(ClientID::new(0), 10), (ClientID::new(1), 11)]
+        );
+        drop(silent);
+    }
+
+    #[test]
+    fn test_parse_token() {
+        let token = "00ff".repeat(16).parse::<ClientToken>().unwrap();
+        let mut expected = ClientToken([0xff; 32]);
+        expected.0.iter_mut().step_by(2).for_each(|b| *b = 0);
+        assert!(token.ct_eq(&expected));
+        assert!(!token.ct_eq(&ClientToken::default()));
+        for s in [
+            "",
+            "00ff",
+            &"0g".repeat(32),
+            &"+f".repeat(32),
+            &"00".repeat(33),
+        ] {
+            assert!(s.parse::<ClientToken>().is_err(), "{}", s);
+        }
+    }
+
+    fn policies() -> Vec<PartitionPolicy> {
+        vec![
+            PartitionPolicy::Parity,
+            PartitionPolicy::Ratio(7, 3),
+            PartitionPolicy::Ratio(1, 0),
+            PartitionPolicy::Custom(Arc::new(|uid: ClientID| {
+                uid.id.wrapping_mul(0x9e3779b97f4a7c15) >> 63 == 0
+            })),
+        ]
+    }
+
+    #[test]
+    fn test_merge_split_is_identity() {
+        // not contiguous, like uids of a replayed subset of clients
+        let uids = (0..100)
+            .map(|i| ClientID::new(i * 3 + 1))
+            .collect::<Vec<_>>();
+        let msgs = (0..uids.len()).map(|i| i * 10).collect::<Vec<_>>();
+        for policy in policies() {
+            for is_alice in [true, false] {
+                let (a, b) = policy.split_iter(is_alice, with_uids(&uids, msgs.iter().copied()));
+                assert_eq!(a.len() + b.len(), msgs.len(), "{:?}", policy);
+                let merged = policy.merge_msg(is_alice, a, b);
+                assert_eq!(join_uids(&uids, merged), msgs, "{:?}", policy);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_reorders_by_uid() {
+        let uids = (0..20).map(ClientID::new).collect::<Vec<_>>();
+        let mut keyed = with_uids(&uids, 0..20);
+        keyed.reverse();
+        let policy = PartitionPolicy::Parity;
+        let (a, b) = policy.split_iter(true, keyed);
+        assert_eq!(a[0], (ClientID::new(18), 18));
+        let merged = policy.merge_msg(true, a, b);
+        assert_eq!(
+            merged.into_values().collect::<Vec<_>>(),
+            (0..20).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_merge_parts_of_different_lengths() {
+        // 3 even and 2 odd uids, so one part runs out before the other
+        let uids = (0..5).map(ClientID::new).collect::<Vec<_>>();
+        let policy = PartitionPolicy::Parity;
+        for is_alice in [true, false] {
+            let (a, b) = policy.split_iter(is_alice, with_uids(&uids, 0..5));
+            assert_eq!((a.len(), b.len()), if is_alice { (3, 2) } else { (2, 3) });
+            let (mut a_rev, mut b_rev) = (a.clone(), b.clone());
+            a_rev.reverse();
+            b_rev.reverse();
+            let merged = policy.merge_msg(is_alice, a_rev, b_rev);
+            assert!(merged.keys().eq(&uids));
+            assert_eq!(
+                merged.clone().into_values().collect::<Vec<_>>(),
+                (0..5).collect::<Vec<_>>()
+            );
+            assert_eq!(policy.split_iter(is_alice, merged), (a, b));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "wrong partition")]
+    fn test_merge_rejects_wrong_partition() {
+        let uids = (0..4).map(ClientID::new).collect::<Vec<_>>();
+        let (a, b) = PartitionPolicy::Parity.split_iter(true, with_uids(&uids, 0..4));
+        PartitionPolicy::Parity.merge_msg(true, b, a);
+    }
+
+    #[test]
+    fn test_join_uids() {
+        let uids = [5, 1, 3].map(ClientID::new);
+        let keyed = [(1, 'a'), (3, 'b'), (5, 'c')].map(|(uid, m)| (ClientID::new(uid), m));
+        assert_eq!(join_uids(&uids, keyed), vec!['c', 'a', 'b']);
+        let missing = std::panic::catch_unwind(|| join_uids(&uids, keyed[1..].to_vec()));
+        assert!(missing.is_err());
+        let extra = std::panic::catch_unwind(|| join_uids(&uids[1..], keyed));
+        assert!(extra.is_err());
+    }
+
+    #[test]
+    fn test_partitions_are_complementary() {
+        let uids = (0..100).map(ClientID::new).collect::<Vec<_>>();
+        for policy in policies() {
+            let (alice_a, alice_b) = policy.split_iter(true, with_uids(&uids, 0..100));
+            let (bob_a, bob_b) = policy.split_iter(false, with_uids(&uids, 0..100));
+            // a client where Alice is OT sender is one where Bob is OT receiver
+            assert_eq!(alice_a, bob_b, "{:?}", policy);
+            assert_eq!(alice_b, bob_a, "{:?}", policy);
+            assert!(alice_a
+                .iter()
+                .all(|&(uid, _)| policy.alice_is_ot_sender(uid)));
+        }
+
+        let (a, b) = PartitionPolicy::Ratio(7, 3).split_iter(true, with_uids(&uids, 0..100));
+        assert_eq!((a.len(), b.len()), (70, 30));
+        let (a, b) = PartitionPolicy::Parity.split_iter(false, with_uids(&uids, 0..100));
+        assert_eq!(
+            a.into_iter().map(|(_, m)| m).collect::<Vec<_>>(),
+            (0..50).map(|i| i * 2 + 1).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            b.into_iter().map(|(_, m)| m).collect::<Vec<_>>(),
+            (0..50).map(|i| i * 2).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        for s in ["parity", "ratio:7:3", "ratio:0:1"] {
+            assert_eq!(s.parse::<PartitionPolicy>().unwrap().to_string(), s);
+        }
+        for s in ["", "even", "ratio:0:0", "ratio:7", "ratio:7:-3", "parity:1"] {
+            assert!(s.parse::<PartitionPolicy>().is_err(), "{}", s);
+        }
+    }
+
+    /// Listener on a free localhost port, with an accept queue of `backlog`.
+    fn listener_with_backlog(backlog: u32) -> TcpListener {
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        socket.listen(backlog).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_init_meta_clients_small_backlog() {
+        const NUM_META_CLIENTS: usize = 200;
+        let (listener0, listener1) = (listener_with_backlog(16), listener_with_backlog(16));
+        let addr0 = listener0.local_addr().unwrap();
+        let addr1 = listener1.local_addr().unwrap();
+        let pools = tokio::spawn(async move {
+            tokio::join!(
+                ClientsPool::new(NUM_META_CLIENTS, listener0, ClientToken::default()),
+                ClientsPool::new(NUM_META_CLIENTS, listener1, ClientToken::default())
+            )
+        });
+
+        let config = ClientConnectConfig {
+            max_concurrent_connects: 8,
+            connect_batch_delay: Duration::from_millis(1),
+            retries: 50,
+        };
+        let connections = init_meta_clients(
+            NUM_META_CLIENTS,
+            addr0,
+            addr1,
+            ClientToken::default(),
+            config,
+        )
+        .await
+        .unwrap();
+        for (uid, (conn0, conn1)) in connections.iter().enumerate() {
+            assert_eq!(conn0.uid(), ClientID::new(uid as u64));
+            assert_eq!(conn1.uid(), ClientID::new(uid as u64));
+        }
+
+        let (pool0, pool1) = tokio::time::timeout(Duration::from_secs(30), pools)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pool0.num_of_clients(), NUM_META_CLIENTS);
+        assert_eq!(pool1.num_of_clients(), NUM_META_CLIENTS);
+    }
+
+    #[tokio::test]
+    async fn test_init_meta_clients_reports_failed_uids() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // nobody listens there anymore, so every connect is refused
+        let refused = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let _pool = tokio::spawn(ClientsPool::new(3, listener, ClientToken::default()));
+
+        let config = ClientConnectConfig {
+            retries: 2,
+            ..Default::default()
+        };
+        let err = init_meta_clients(3, addr, refused, ClientToken::default(), config)
+            .await
+            .unwrap_err();
+        assert_eq!(err.failed, (0..3).map(ClientID::new).collect::<Vec<_>>());
+        assert!(err.connected.is_empty());
+    }
 }
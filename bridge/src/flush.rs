@@ -0,0 +1,78 @@
+//! When the write loops of `TcpConnection` and `MpcConnection` flush the
+//! messages they have written to their buffered socket.
+
+use std::{
+    io,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::oneshot,
+};
+
+/// A write loop flushes as soon as its send queue is empty. While the queue
+/// stays busy, it also flushes once `max_bytes` or `max_messages` are
+/// unflushed, or once the oldest unflushed message is `max_delay` old, so that
+/// a message the peer waits for is never held back by the messages queued
+/// after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushPolicy {
+    pub max_bytes: usize,
+    pub max_messages: usize,
+    pub max_delay: Duration,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: 32 * 1024,
+            max_messages: 64,
+            max_delay: Duration::from_millis(1),
+        }
+    }
+}
+
+/// Messages written to the buffer of a write loop since its last flush.
+#[derive(Debug, Default)]
+pub(crate) struct Unflushed {
+    bytes: usize,
+    messages: usize,
+    since: Option<Instant>,
+    /// Signaled once the message is flushed, so that a completed send is
+    /// handed to the socket.
+    completions: Vec<oneshot::Sender<()>>,
+}
+
+impl Unflushed {
+    /// Record a message of `len` bytes, header included.
+    pub(crate) fn push(&mut self, len: usize, complete: oneshot::Sender<()>) {
+        self.bytes += len;
+        self.messages += 1;
+        self.since.get_or_insert_with(Instant::now);
+        self.completions.push(complete);
+    }
+
+    /// Whether `policy` flushes now, although more messages are queued.
+    pub(crate) fn is_due(&self, policy: &FlushPolicy) -> bool {
+        self.bytes >= policy.max_bytes
+            || self.messages >= policy.max_messages
+            || matches!(self.since, Some(since) if since.elapsed() >= policy.max_delay)
+    }
+
+    /// Flush `writer` if anything is unflushed, and complete the flushed
+    /// messages.
+    pub(crate) async fn flush<W: AsyncWrite + Unpin>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.messages == 0 {
+            return Ok(());
+        }
+        writer.flush().await?;
+        for complete in self.completions.drain(..) {
+            complete.send(()).unwrap_or(());
+        }
+        self.bytes = 0;
+        self.messages = 0;
+        self.since = None;
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 use std::fmt::{self, Display, Formatter};
 
 use bytemuck::{Pod, Zeroable};
+#[cfg(target_endian = "big")]
+use serialize::util::LePod;
 
 /// Message ID used to send
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Pod, Zeroable)]
@@ -11,6 +13,18 @@ impl SendId {
     pub const FIRST: Self = SendId(COMMON_MESSAGE_ID_START);
     pub const SECOND: Self = SendId(COMMON_MESSAGE_ID_START + 1);
     pub const THIRD: Self = SendId(COMMON_MESSAGE_ID_START + 2);
+
+    /// The same id in round `round` of a session, see `IdGen::for_round`.
+    pub fn in_round(self, round: u64) -> Self {
+        SendId(self.0 + round_offset(round))
+    }
+}
+
+#[cfg(target_endian = "big")]
+impl LePod for SendId {
+    fn swap_le(&mut self) {
+        self.0.swap_le()
+    }
 }
 
 impl From<u64> for SendId {
@@ -33,6 +47,18 @@ impl RecvId {
     pub const FIRST: Self = RecvId(COMMON_MESSAGE_ID_START);
     pub const SECOND: Self = RecvId(COMMON_MESSAGE_ID_START + 1);
     pub const THIRD: Self = RecvId(COMMON_MESSAGE_ID_START + 2);
+
+    /// The same id in round `round` of a session, see `IdGen::for_round`.
+    pub fn in_round(self, round: u64) -> Self {
+        RecvId(self.0 + round_offset(round))
+    }
+}
+
+#[cfg(target_endian = "big")]
+impl LePod for RecvId {
+    fn swap_le(&mut self) {
+        self.0.swap_le()
+    }
 }
 
 impl From<u64> for RecvId {
@@ -80,7 +106,57 @@ impl From<u64> for ExchangeId {
 
 /// message id 0 is reserved for register message
 pub const REGISTER_MESSAGE_ID: u64 = 0;
+/// message id `u64::MAX` is reserved for heartbeat of `TcpConnection`
+pub const HEARTBEAT_MESSAGE_ID: u64 = u64::MAX;
+/// message id `u64::MAX - 1` is reserved for the version handshake, see
+/// `version`
+pub const VERSION_MESSAGE_ID: u64 = u64::MAX - 1;
+/// message id `u64::MAX - 2` is reserved for frames packing several messages,
+/// see `BatchId`
+pub const BATCH_MESSAGE_ID: u64 = u64::MAX - 2;
+/// message id `u64::MAX - 3` is reserved for aborting a round, see `AbortId`
+pub const ABORT_MESSAGE_ID: u64 = u64::MAX - 3;
 pub const COMMON_MESSAGE_ID_START: u64 = 1;
+/// Ids of round `r` of a session are `ROUND_ID_STRIDE * r` above the ids of
+/// round 0, so that a late message of a round is never taken for one of the
+/// next round.
+pub const ROUND_ID_STRIDE: u64 = 1 << 40;
+/// Number of rounds of a session, such that the ids of its last round stay
+/// below the reserved ids.
+pub const MAX_SESSION_ROUNDS: u64 = (ABORT_MESSAGE_ID - COMMON_MESSAGE_ID_START) / ROUND_ID_STRIDE;
+
+fn round_offset(round: u64) -> u64 {
+    assert!(
+        round < MAX_SESSION_ROUNDS,
+        "round {} is beyond the {} rounds of a session",
+        round,
+        MAX_SESSION_ROUNDS
+    );
+    round * ROUND_ID_STRIDE
+}
+
+/// Id of a frame that packs several messages, each with its own id, see
+/// `MpcConnection::send_batch`. All batches share the reserved id
+/// `BATCH_MESSAGE_ID`: a batch is never subscribed, but unpacked by the
+/// receiver into its messages, which are subscribed as usual.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BatchId;
+
+impl BatchId {
+    pub const SEND: SendId = SendId(BATCH_MESSAGE_ID);
+    pub const RECV: RecvId = RecvId(BATCH_MESSAGE_ID);
+}
+
+/// Id of the frame that tells the peer this server gives up the round, see
+/// `MpcConnection::abort`. It is never subscribed: the receiver fails all its
+/// subscriptions with `BridgeError::PeerAborted` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AbortId;
+
+impl AbortId {
+    pub const SEND: SendId = SendId(ABORT_MESSAGE_ID);
+    pub const RECV: RecvId = RecvId(ABORT_MESSAGE_ID);
+}
 
 /// Used to generate a new message ID for each message to be sent or received.
 /// Starting from 0.
@@ -92,6 +168,12 @@ pub struct IdGen {
     next_recv_id_bound: u64,
 }
 
+impl Default for IdGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl IdGen {
     pub fn new() -> Self {
         Self {
@@ -102,6 +184,18 @@ impl IdGen {
         }
     }
 
+    /// Ids of round `round` of a session. Round 0 has the ids of `new`, and
+    /// each round can use `ROUND_ID_STRIDE` ids.
+    pub fn for_round(round: u64) -> Self {
+        let start = COMMON_MESSAGE_ID_START + round_offset(round);
+        Self {
+            next_send_id: start,
+            next_recv_id: start,
+            next_send_id_bound: start + ROUND_ID_STRIDE,
+            next_recv_id_bound: start + ROUND_ID_STRIDE,
+        }
+    }
+
     pub fn next_send_id(&mut self) -> SendId {
         if self.next_send_id == self.next_send_id_bound {
             panic!("sending too many messages than expected")
@@ -140,3 +234,33 @@ impl IdGen {
         reserved
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_of_rounds_are_disjoint() {
+        let mut round_0 = IdGen::for_round(0);
+        assert_eq!(round_0.next_send_id(), SendId::FIRST);
+        assert_eq!(round_0.next_recv_id(), RecvId::FIRST);
+
+        let mut round_1 = IdGen::for_round(1);
+        assert_eq!(round_1.next_send_id(), SendId::FIRST.in_round(1));
+        assert_eq!(
+            round_1.next_exchange_id().recv_id,
+            RecvId::FIRST.in_round(1)
+        );
+        // round 0 ends where round 1 starts
+        assert_eq!(round_0.next_send_id_bound, SendId::FIRST.in_round(1).0);
+
+        let last = IdGen::for_round(MAX_SESSION_ROUNDS - 1);
+        assert!(last.next_send_id_bound <= ABORT_MESSAGE_ID);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_round_beyond_session() {
+        IdGen::for_round(MAX_SESSION_ROUNDS);
+    }
+}
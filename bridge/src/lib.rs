@@ -2,17 +2,29 @@
 
 use std::{
     fmt::{Debug, Display},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
 };
 
 use thiserror::Error;
-use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::net::{lookup_host, TcpSocket, TcpStream, ToSocketAddrs};
 use tracing::warn;
+pub mod client_dump;
 pub mod client_server;
+pub mod flush;
 pub mod id_tracker;
+pub mod metrics;
 pub mod mpc_conn;
+pub mod netem;
 pub mod perf_trace;
+pub mod simulation;
+mod subscription;
+pub mod sync;
 /// Trait for abstract asynchronous connection
 pub mod tcp_bridge;
+pub mod tls;
+pub mod version;
 
 #[derive(Error, Debug)]
 pub enum BridgeError {
@@ -20,18 +32,52 @@ pub enum BridgeError {
     IoError(#[from] std::io::Error),
     #[error("serialization error: {0}")]
     SerializationError(#[from] serialize::Error),
+    #[error("message {0} is already subscribed by another caller")]
+    DuplicateSubscription(id_tracker::RecvId),
+    #[error("connection timed out")]
+    ConnectionTimedOut,
+    #[error("message {id} did not arrive within {waited:?}")]
+    SubscribeTimeout {
+        id: id_tracker::RecvId,
+        waited: Duration,
+    },
+    #[error("peer speaks wire format {theirs}, incompatible with ours {ours}")]
+    VersionMismatch {
+        ours: version::ProtocolVersion,
+        theirs: version::ProtocolVersion,
+    },
+    #[error("malformed batch of messages: {0}")]
+    MalformedBatch(&'static str),
+    #[error("peer sent a message of {size} bytes, more than the limit of {max}")]
+    FrameTooLarge { size: u64, max: u64 },
+    #[error("client registered with a wrong token")]
+    WrongToken,
+    #[error("peer aborted the round")]
+    PeerAborted,
+    #[error("subscription of message {0} was dropped without a close reason")]
+    Disconnected(id_tracker::RecvId),
+    #[error("TLS error: {0}")]
+    Tls(#[from] tls::rustls::Error),
+    #[error("{0:?} is not a valid TLS server name")]
+    InvalidServerName(String),
 }
 
 pub(crate) async fn tcp_connect_or_retry(
     remote_addr: impl ToSocketAddrs + Copy + Debug,
 ) -> TcpStream {
-    let socket;
+    tcp_connect_from_or_retry(Ipv4Addr::UNSPECIFIED.into(), remote_addr).await
+}
+
+/// Same as `tcp_connect_or_retry`, but the socket is bound to `local_addr`
+/// first, so that the traffic leaves through its interface. An unspecified
+/// `local_addr` lets the OS pick it.
+pub(crate) async fn tcp_connect_from_or_retry(
+    local_addr: IpAddr,
+    remote_addr: impl ToSocketAddrs + Copy + Debug,
+) -> TcpStream {
     loop {
-        match TcpStream::connect(remote_addr).await {
-            Ok(s) => {
-                socket = s;
-                break;
-            },
+        match tcp_connect_from(local_addr, remote_addr).await {
+            Ok(socket) => return socket,
             Err(e) => {
                 warn!(
                     "Error connect to {:?}: {}. Waiting to connect in 100ms",
@@ -41,7 +87,39 @@ pub(crate) async fn tcp_connect_or_retry(
             },
         }
     }
-    socket
+}
+
+/// Connect to the first address of `remote_addr` in the family of
+/// `local_addr` that accepts.
+async fn tcp_connect_from(
+    local_addr: IpAddr,
+    remote_addr: impl ToSocketAddrs,
+) -> io::Result<TcpStream> {
+    if local_addr.is_unspecified() {
+        return TcpStream::connect(remote_addr).await;
+    }
+    let mut last_err = None;
+    for addr in lookup_host(remote_addr).await? {
+        if addr.is_ipv4() != local_addr.is_ipv4() {
+            continue;
+        }
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket.bind(SocketAddr::new(local_addr, 0))?;
+        match socket.connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("no remote address in the family of {}", local_addr),
+        )
+    }))
 }
 
 #[derive(Debug)]
@@ -71,4 +149,4 @@ pub trait BlackBox: Sized {
     }
 }
 
-impl<T: Sized> BlackBox for T {}
\ No newline at end of file
+impl<T: Sized> BlackBox for T {}
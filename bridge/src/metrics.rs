@@ -0,0 +1,486 @@
+//! Byte counters of an `MpcConnection`, published as consistent snapshots.
+//!
+//! Socket loops record every message into the stripe of their socket, which
+//! only they and the aggregator lock. All counters of one message are updated
+//! under the same lock, so a message is either fully in an epoch or not at
+//! all. `MetricsRegistry::fold` drains the stripes into the cumulative totals,
+//! checks the invariants (payload <= wire, sum of phases <= total), and
+//! publishes a new `Snapshot`. Scrapes only read published snapshots, so
+//! related counters never tear. A fold that breaks an invariant is a bug in
+//! the registry: it is logged and the previous snapshot stays published.
+
+use std::{
+    fmt::Write,
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    time::Duration,
+};
+
+use tokio::task::JoinHandle;
+use tracing::error;
+
+/// Size of the message header on the wire: u64 id and u64 length.
+pub const HEADER_SIZE: u64 = 16;
+
+const NO_PHASE: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteCounters {
+    pub num_messages: u64,
+    /// Bytes of the messages.
+    pub payload: u64,
+    /// Bytes on the wire, including headers.
+    pub wire: u64,
+}
+
+impl ByteCounters {
+    fn add(&mut self, other: &Self) {
+        self.num_messages += other.num_messages;
+        self.payload += other.payload;
+        self.wire += other.wire;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkCounters {
+    pub sent: ByteCounters,
+    pub received: ByteCounters,
+}
+
+impl LinkCounters {
+    fn get_mut(&mut self, direction: Direction) -> &mut ByteCounters {
+        match direction {
+            Direction::Sent => &mut self.sent,
+            Direction::Received => &mut self.received,
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.sent.add(&other.sent);
+        self.received.add(&other.received);
+    }
+}
+
+/// Counters of one socket since the last fold, or the cumulative totals.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Counters {
+    socket: LinkCounters,
+    /// Payload bytes sent and received in each phase, indexed by phase id.
+    phases: Vec<(u64, u64)>,
+}
+
+impl Counters {
+    fn record(&mut self, direction: Direction, phase: usize, payload: u64) {
+        let counters = self.socket.get_mut(direction);
+        counters.num_messages += 1;
+        counters.payload += payload;
+        counters.wire += payload + HEADER_SIZE;
+        if phase != NO_PHASE {
+            if self.phases.len() <= phase {
+                self.phases.resize(phase + 1, (0, 0));
+            }
+            match direction {
+                Direction::Sent => self.phases[phase].0 += payload,
+                Direction::Received => self.phases[phase].1 += payload,
+            }
+        }
+    }
+}
+
+/// Cumulative counters of all sockets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Totals {
+    epoch: u64,
+    sockets: Vec<LinkCounters>,
+    phases: Vec<(u64, u64)>,
+}
+
+impl Totals {
+    fn new(num_sockets: usize) -> Self {
+        Self {
+            epoch: 0,
+            sockets: vec![LinkCounters::default(); num_sockets],
+            phases: Vec::new(),
+        }
+    }
+
+    /// Add the deltas of each socket, and start the next epoch.
+    fn fold(&mut self, deltas: &[Counters]) {
+        for (socket, delta) in self.sockets.iter_mut().zip(deltas) {
+            socket.add(&delta.socket);
+            if self.phases.len() < delta.phases.len() {
+                self.phases.resize(delta.phases.len(), (0, 0));
+            }
+            for (phase, (sent, received)) in self.phases.iter_mut().zip(&delta.phases) {
+                phase.0 += sent;
+                phase.1 += received;
+            }
+        }
+        self.epoch += 1;
+    }
+
+    fn total(&self) -> LinkCounters {
+        let mut total = LinkCounters::default();
+        self.sockets.iter().for_each(|socket| total.add(socket));
+        total
+    }
+
+    /// Check that payload <= wire everywhere, and that the phases do not sum
+    /// to more than the total.
+    fn check(&self) -> Result<(), String> {
+        for (idx, socket) in self.sockets.iter().enumerate() {
+            for (name, counters) in [("sent", socket.sent), ("received", socket.received)] {
+                if counters.payload > counters.wire {
+                    return Err(format!(
+                        "socket {} {}: payload {} > wire {}",
+                        idx, name, counters.payload, counters.wire
+                    ));
+                }
+            }
+        }
+        let total = self.total();
+        let phases_sent = self.phases.iter().map(|p| p.0).sum::<u64>();
+        let phases_received = self.phases.iter().map(|p| p.1).sum::<u64>();
+        if phases_sent > total.sent.payload || phases_received > total.received.payload {
+            return Err(format!(
+                "phases ({}, {}) > total payload ({}, {})",
+                phases_sent, phases_received, total.sent.payload, total.received.payload
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Counters published at the end of an epoch. All fields are from the same
+/// epoch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub epoch: u64,
+    pub sockets: Vec<LinkCounters>,
+    pub total: LinkCounters,
+    /// Payload bytes (sent, received) attributed to each phase.
+    pub phases: Vec<(&'static str, u64, u64)>,
+}
+
+impl Snapshot {
+    /// Render in the Prometheus text format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, values: &[(String, u64)]| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            for (labels, value) in values {
+                writeln!(out, "{}{{{}}} {}", name, labels, value).unwrap();
+            }
+        };
+        let by_socket = |f: fn(&ByteCounters) -> u64| {
+            let mut values = Vec::new();
+            for (idx, socket) in self.sockets.iter().enumerate() {
+                values.push((
+                    format!("socket=\"{}\",direction=\"sent\"", idx),
+                    f(&socket.sent),
+                ));
+                values.push((
+                    format!("socket=\"{}\",direction=\"received\"", idx),
+                    f(&socket.received),
+                ));
+            }
+            values
+        };
+        counter(
+            "mpc_wire_bytes_total",
+            "Bytes on the MPC link, including headers.",
+            &by_socket(|c| c.wire),
+        );
+        counter(
+            "mpc_payload_bytes_total",
+            "Message bytes on the MPC link.",
+            &by_socket(|c| c.payload),
+        );
+        counter(
+            "mpc_messages_total",
+            "Messages on the MPC link.",
+            &by_socket(|c| c.num_messages),
+        );
+        let phases = self
+            .phases
+            .iter()
+            .flat_map(|(phase, sent, received)| {
+                [
+                    (format!("phase=\"{}\",direction=\"sent\"", phase), *sent),
+                    (
+                        format!("phase=\"{}\",direction=\"received\"", phase),
+                        *received,
+                    ),
+                ]
+            })
+            .collect::<Vec<_>>();
+        counter(
+            "mpc_phase_payload_bytes_total",
+            "Message bytes on the MPC link in each phase.",
+            &phases,
+        );
+        writeln!(out, "# TYPE mpc_metrics_epoch gauge").unwrap();
+        writeln!(out, "mpc_metrics_epoch {}", self.epoch).unwrap();
+        out
+    }
+}
+
+pub struct MetricsRegistry {
+    stripes: Box<[Mutex<Counters>]>,
+    phase_names: Mutex<Vec<&'static str>>,
+    current_phase: AtomicUsize,
+    totals: Mutex<Totals>,
+    published: RwLock<Arc<Snapshot>>,
+}
+
+impl MetricsRegistry {
+    pub fn new(num_sockets: usize) -> Self {
+        Self {
+            stripes: (0..num_sockets)
+                .map(|_| Mutex::new(Counters::default()))
+                .collect(),
+            phase_names: Mutex::new(Vec::new()),
+            current_phase: AtomicUsize::new(NO_PHASE),
+            totals: Mutex::new(Totals::new(num_sockets)),
+            published: RwLock::new(Arc::new(Snapshot {
+                sockets: vec![LinkCounters::default(); num_sockets],
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Attribute messages recorded from now on to `phase`.
+    pub fn set_phase(&self, phase: &'static str) {
+        let mut names = self.phase_names.lock().unwrap();
+        let id = match names.iter().position(|name| *name == phase) {
+            Some(id) => id,
+            None => {
+                names.push(phase);
+                names.len() - 1
+            },
+        };
+        self.current_phase.store(id, Ordering::Relaxed);
+    }
+
+    /// Stop attributing messages to a phase.
+    pub fn clear_phase(&self) {
+        self.current_phase.store(NO_PHASE, Ordering::Relaxed);
+    }
+
+    /// Record a message of `payload` bytes on `socket`.
+    pub fn record(&self, socket: usize, direction: Direction, payload: usize) {
+        let phase = self.current_phase.load(Ordering::Relaxed);
+        self.stripes[socket]
+            .lock()
+            .unwrap()
+            .record(direction, phase, payload as u64);
+    }
+
+    /// End the current epoch: fold the counters recorded since the last fold,
+    /// and publish the new snapshot if it is consistent.
+    pub fn fold(&self) -> Arc<Snapshot> {
+        let mut totals = self.totals.lock().unwrap();
+        let deltas = self
+            .stripes
+            .iter()
+            .map(|stripe| mem::take(&mut *stripe.lock().unwrap()))
+            .collect::<Vec<_>>();
+        let mut folded = totals.clone();
+        folded.fold(&deltas);
+        if let Err(e) = folded.check() {
+            error!(
+                "metrics registry bug in epoch {}: {}, keep the last snapshot",
+                folded.epoch, e
+            );
+            return self.snapshot();
+        }
+        *totals = folded;
+
+        let names = self.phase_names.lock().unwrap();
+        let snapshot = Arc::new(Snapshot {
+            epoch: totals.epoch,
+            sockets: totals.sockets.clone(),
+            total: totals.total(),
+            phases: totals
+                .phases
+                .iter()
+                .zip(names.iter())
+                .map(|((sent, received), name)| (*name, *sent, *received))
+                .collect(),
+        });
+        *self.published.write().unwrap() = snapshot.clone();
+        snapshot
+    }
+
+    /// The last published snapshot.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.published.read().unwrap().clone()
+    }
+
+    /// Scrape handler: the last published snapshot in the Prometheus text
+    /// format.
+    pub fn scrape(&self) -> String {
+        self.snapshot().to_prometheus()
+    }
+
+    /// Fold every `interval` until the registry is dropped.
+    pub fn spawn_aggregator(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let registry: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match registry.upgrade() {
+                    Some(registry) => {
+                        registry.fold();
+                    },
+                    None => break,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::{
+        ByteCounters, Counters, Direction, MetricsRegistry, Snapshot, Totals, HEADER_SIZE, NO_PHASE,
+    };
+
+    fn check_invariants(snapshot: &Snapshot) {
+        let mut total = ByteCounters::default();
+        for socket in &snapshot.sockets {
+            for counters in [socket.sent, socket.received] {
+                assert!(counters.payload <= counters.wire);
+                assert_eq!(
+                    counters.wire,
+                    counters.payload + counters.num_messages * HEADER_SIZE
+                );
+            }
+            total.add(&socket.sent);
+        }
+        assert_eq!(total, snapshot.total.sent);
+        let phases_sent = snapshot.phases.iter().map(|p| p.1).sum::<u64>();
+        assert!(phases_sent <= snapshot.total.sent.payload);
+    }
+
+    #[test]
+    fn test_fold_epochs() {
+        let mut totals = Totals::new(2);
+        let mut delta = vec![Counters::default(), Counters::default()];
+        delta[0].record(Direction::Sent, 0, 100);
+        delta[1].record(Direction::Received, NO_PHASE, 7);
+        totals.fold(&delta);
+        assert_eq!(totals.epoch, 1);
+        assert_eq!(totals.sockets[0].sent.wire, 100 + HEADER_SIZE);
+        assert_eq!(totals.sockets[1].received.payload, 7);
+        assert_eq!(totals.phases, vec![(100, 0)]);
+        assert!(totals.check().is_ok());
+
+        let mut delta = vec![Counters::default(), Counters::default()];
+        delta[1].record(Direction::Sent, 1, 5);
+        totals.fold(&delta);
+        assert_eq!(totals.epoch, 2);
+        assert_eq!(totals.total().sent.payload, 105);
+        assert_eq!(totals.total().sent.num_messages, 2);
+        assert_eq!(totals.phases, vec![(100, 0), (5, 0)]);
+
+        // a broken fold is detected
+        let mut bad = totals.clone();
+        bad.sockets[0].sent.payload = bad.sockets[0].sent.wire + 1;
+        assert!(bad.check().is_err());
+        let mut bad = totals.clone();
+        bad.phases[0].1 = 1000;
+        assert!(bad.check().is_err());
+    }
+
+    #[test]
+    fn test_inconsistent_fold_is_not_published() {
+        let registry = MetricsRegistry::new(1);
+        registry.set_phase("B2A");
+        registry.record(0, Direction::Sent, 10);
+        let good = registry.fold();
+        assert_eq!(good.epoch, 1);
+        assert_eq!(good.phases, vec![("B2A", 10, 0)]);
+
+        registry.stripes[0].lock().unwrap().socket.sent.payload = 1000;
+        assert_eq!(registry.fold(), good);
+        assert_eq!(registry.snapshot(), good);
+    }
+
+    #[test]
+    fn test_scrape_is_consistent_under_load() {
+        const NUM_SOCKETS: usize = 4;
+        const NUM_MESSAGES: usize = 20000;
+        let registry = Arc::new(MetricsRegistry::new(NUM_SOCKETS));
+
+        let writers = (0..NUM_SOCKETS)
+            .map(|socket| {
+                let registry = registry.clone();
+                thread::spawn(move || {
+                    for i in 0..NUM_MESSAGES {
+                        if i % 1000 == 0 {
+                            if socket % 2 == 0 {
+                                registry.set_phase("A2S");
+                            } else {
+                                registry.clear_phase();
+                            }
+                        }
+                        registry.record(socket, Direction::Sent, i % 97);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let scraper = {
+            let registry = registry.clone();
+            thread::spawn(move || {
+                let mut last_epoch = 0;
+                for _ in 0..500 {
+                    let snapshot = registry.fold();
+                    check_invariants(&snapshot);
+                    assert!(snapshot.epoch > last_epoch);
+                    last_epoch = snapshot.epoch;
+                    assert!(registry.scrape().contains("mpc_wire_bytes_total"));
+                }
+            })
+        };
+
+        writers.into_iter().for_each(|w| w.join().unwrap());
+        scraper.join().unwrap();
+
+        let snapshot = registry.fold();
+        check_invariants(&snapshot);
+        assert_eq!(
+            snapshot.total.sent.num_messages,
+            (NUM_SOCKETS * NUM_MESSAGES) as u64
+        );
+        let per_socket = (0..NUM_MESSAGES).map(|i| (i % 97) as u64).sum::<u64>();
+        assert_eq!(snapshot.total.sent.payload, per_socket * NUM_SOCKETS as u64);
+    }
+
+    #[tokio::test]
+    async fn test_aggregator_publishes() {
+        let registry = Arc::new(MetricsRegistry::new(1));
+        let handle = registry.spawn_aggregator(std::time::Duration::from_millis(10));
+        registry.record(0, Direction::Received, 42);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let snapshot = registry.snapshot();
+        assert!(snapshot.epoch > 0);
+        assert_eq!(snapshot.total.received.payload, 42);
+        drop(registry);
+        handle.await.unwrap();
+    }
+}
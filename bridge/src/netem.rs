@@ -0,0 +1,153 @@
+//! Network emulation for benchmarks: a connection delays the messages it
+//! receives as if they crossed a slower link, e.g. a WAN between the servers,
+//! without configuring `tc` on the hosts. Only the delivery to the
+//! subscriptions is delayed; the byte counters count a message once it is
+//! read.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Latency and bandwidth of an emulated link. The default profile adds
+/// nothing, and messages are delivered as soon as they are read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NetworkProfile {
+    /// Round-trip time. Each message is delayed by half of it.
+    pub rtt: Duration,
+    /// Throughput of the link, or unlimited if `None`. A message is also
+    /// delayed by its length over the bandwidth, after the messages read
+    /// before it.
+    pub bandwidth_bytes_per_sec: Option<u64>,
+    /// Each message is delayed by up to this much more, uniformly at random.
+    /// Messages are still delivered in the order they are read.
+    pub jitter: Duration,
+}
+
+impl NetworkProfile {
+    pub fn is_none(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Time to put `len` bytes on the link.
+    fn transmission_time(&self, len: usize) -> Duration {
+        match self.bandwidth_bytes_per_sec {
+            Some(bandwidth) if bandwidth > 0 => {
+                Duration::from_secs_f64(len as f64 / bandwidth as f64)
+            },
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LinkState {
+    profile: NetworkProfile,
+    /// When the link is done transmitting the messages read so far.
+    busy_until: Option<Instant>,
+}
+
+/// The receiving end of an emulated link, shared by the read loops of a
+/// connection, so that its sockets share the bandwidth.
+#[derive(Debug, Default)]
+pub(crate) struct EmulatedLink {
+    state: Mutex<LinkState>,
+}
+
+impl EmulatedLink {
+    pub(crate) fn new(profile: NetworkProfile) -> Self {
+        let link = Self::default();
+        link.set_profile(profile);
+        link
+    }
+
+    /// Applies to the messages read from now on.
+    pub(crate) fn set_profile(&self, profile: NetworkProfile) {
+        let mut state = self.state.lock().unwrap();
+        state.profile = profile;
+        state.busy_until = None;
+    }
+
+    /// When a message of `len` bytes, read now, is delivered, or `None` to
+    /// deliver it right away.
+    pub(crate) fn delivery_time(&self, len: usize) -> Option<Instant> {
+        let mut state = self.state.lock().unwrap();
+        let profile = state.profile;
+        if profile.is_none() {
+            return None;
+        }
+        let now = Instant::now();
+        let start = state
+            .busy_until
+            .map_or(now, |busy_until| busy_until.max(now));
+        let transmitted = start + profile.transmission_time(len);
+        state.busy_until = Some(transmitted);
+        let jitter = if profile.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            rand::thread_rng().gen_range(Duration::ZERO..=profile.jitter)
+        };
+        Some(transmitted + profile.rtt / 2 + jitter)
+    }
+}
+
+/// Delivers messages at their delivery time, in the order they are pushed. A
+/// read loop pushes its messages here instead of sleeping itself, so that the
+/// messages behind are read meanwhile and their delays overlap.
+pub(crate) struct DelayLine<T> {
+    sender: mpsc::UnboundedSender<(Instant, T)>,
+    task: JoinHandle<()>,
+}
+
+impl<T: Send + 'static> DelayLine<T> {
+    pub(crate) fn spawn(mut deliver: impl FnMut(T) + Send + 'static) -> Self {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(Instant, T)>();
+        let task = tokio::spawn(async move {
+            while let Some((at, item)) = receiver.recv().await {
+                tokio::time::sleep_until(tokio::time::Instant::from_std(at)).await;
+                deliver(item);
+            }
+        });
+        Self { sender, task }
+    }
+
+    pub(crate) fn push(&self, at: Instant, item: T) {
+        // the task only quits once the sender is dropped
+        self.sender.send((at, item)).unwrap_or(());
+    }
+
+    /// Deliver the messages still in flight, then quit.
+    pub(crate) async fn finish(self) {
+        drop(self.sender);
+        self.task.await.unwrap_or(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::netem::{EmulatedLink, NetworkProfile};
+
+    #[test]
+    fn test_delivery_time() {
+        let link = EmulatedLink::default();
+        assert_eq!(link.delivery_time(1000), None);
+
+        link.set_profile(NetworkProfile {
+            rtt: Duration::from_millis(20),
+            bandwidth_bytes_per_sec: Some(100_000),
+            jitter: Duration::ZERO,
+        });
+        let read_at = Instant::now();
+        // 10 ms to transmit each message, and half of the rtt
+        let first = link.delivery_time(1000).unwrap();
+        let second = link.delivery_time(1000).unwrap();
+        assert!(first >= read_at + Duration::from_millis(20));
+        assert!(second >= first + Duration::from_millis(10));
+        assert!(second < Instant::now() + Duration::from_millis(40));
+    }
+}
@@ -6,7 +6,69 @@
 //!
 //! Additionally, one can use the `add_to_trace` macro to log additional context
 //! in the output.
+//!
+//! With `record_spans(true)`, every `end_timer!` also records a `Span`, to be
+//! collected with `take_spans`, e.g. into the report of a run.
+//!
+//! The timers above assume that they nest on one thread. Work that runs in
+//! many tasks at once, e.g. one per client, is timed with `TimerScope`
+//! instead, whose durations are summed per name over all tasks.
 pub use self::inner::*;
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+/// A timer that ended, recorded if `record_spans` is enabled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Span {
+    /// Message of the timer.
+    pub msg: String,
+    /// Number of timers that were running when it started.
+    pub depth: usize,
+    pub secs: f64,
+}
+
+/// Number of scopes that ended, and their total duration, per name.
+static SCOPES: Mutex<BTreeMap<String, (usize, Duration)>> = Mutex::new(BTreeMap::new());
+
+/// Time spent under `name`. The guard returned by `enter` records its
+/// duration when it is dropped, so it can be held across `.await` in a
+/// spawned task. `aggregate` sums the durations per name, e.g. the total time
+/// of `ot_verify_alice` over all clients, even though their tasks interleave.
+///
+/// `end_timer!` also records its timer here, under its message.
+#[must_use = "the scope ends when the guard is dropped"]
+pub struct TimerScope {
+    name: String,
+    start: std::time::Instant,
+}
+
+impl TimerScope {
+    pub fn enter(name: impl Into<String>) -> Self {
+        TimerScope {
+            name: name.into(),
+            start: std::time::Instant::now(),
+        }
+    }
+
+    /// Record a scope `name` that took `elapsed`.
+    pub fn record(name: &str, elapsed: Duration) {
+        let mut scopes = SCOPES.lock().unwrap();
+        let (count, total) = scopes.entry(name.to_string()).or_default();
+        *count += 1;
+        *total += elapsed;
+    }
+
+    /// Count and total duration of the scopes that ended, per name.
+    pub fn aggregate() -> BTreeMap<String, (usize, Duration)> {
+        SCOPES.lock().unwrap().clone()
+    }
+}
+
+impl Drop for TimerScope {
+    fn drop(&mut self) {
+        Self::record(&self.name, self.start.elapsed());
+    }
+}
 
 #[macro_use]
 #[cfg(feature = "print-trace")]
@@ -20,15 +82,45 @@ pub mod inner {
         sync::atomic::{AtomicUsize, Ordering},
         time::Instant,
     };
+    use std::{
+        sync::{atomic::AtomicBool, Mutex},
+        time::Duration,
+        vec::Vec,
+    };
+
+    use super::Span;
 
     pub static NUM_INDENT: AtomicUsize = AtomicUsize::new(0);
     pub const PAD_CHAR: &str = "·";
 
+    static RECORD_SPANS: AtomicBool = AtomicBool::new(false);
+    static SPANS: Mutex<Vec<Span>> = Mutex::new(Vec::new());
+
     pub struct TimerInfo {
         pub msg: String,
         pub time: Instant,
     }
 
+    /// Whether `end_timer!` records its span, see `take_spans`.
+    pub fn record_spans(enable: bool) {
+        RECORD_SPANS.store(enable, Ordering::Relaxed);
+    }
+
+    /// The spans recorded since the last call, in the order they ended.
+    pub fn take_spans() -> Vec<Span> {
+        std::mem::take(&mut *SPANS.lock().unwrap())
+    }
+
+    pub fn push_span(msg: &str, depth: usize, elapsed: Duration) {
+        if RECORD_SPANS.load(Ordering::Relaxed) {
+            SPANS.lock().unwrap().push(Span {
+                msg: msg.to_string(),
+                depth,
+                secs: elapsed.as_secs_f64(),
+            });
+        }
+    }
+
     #[macro_export]
     macro_rules! start_timer {
         ($msg:expr) => {{
@@ -63,7 +155,8 @@ pub mod inner {
             };
 
             let time = $time.time;
-            let final_time = time.elapsed();
+            let elapsed = time.elapsed();
+            let final_time = elapsed;
             let final_time = {
                 let secs = final_time.as_secs();
                 let millis = final_time.subsec_millis();
@@ -84,7 +177,10 @@ pub mod inner {
             let message = format!("{} {}", $time.msg, $msg());
 
             NUM_INDENT.fetch_sub(1, Ordering::Relaxed);
-            let indent_amount = 2 * NUM_INDENT.fetch_add(0, Ordering::Relaxed);
+            let depth = NUM_INDENT.fetch_add(0, Ordering::Relaxed);
+            $crate::perf_trace::push_span(&$time.msg, depth, elapsed);
+            $crate::perf_trace::TimerScope::record(&$time.msg, elapsed);
+            let indent_amount = 2 * depth;
             let indent = compute_indent(indent_amount);
 
             // Todo: Recursively ensure that *entire* string is of appropriate
@@ -136,7 +232,7 @@ pub mod inner {
     pub fn compute_indent_whitespace(indent_amount: usize) -> String {
         let mut indent = String::new();
         for _ in 0..indent_amount {
-            indent.push_str(" ");
+            indent.push(' ');
         }
         indent
     }
@@ -153,8 +249,18 @@ pub mod inner {
 #[macro_use]
 #[cfg(not(feature = "print-trace"))]
 mod inner {
+    use super::Span;
+
     pub struct TimerInfo;
 
+    /// No-op: spans are only recorded with the `print-trace` feature.
+    pub fn record_spans(_enable: bool) {}
+
+    /// Always empty: spans are only recorded with the `print-trace` feature.
+    pub fn take_spans() -> Vec<Span> {
+        Vec::new()
+    }
+
     #[macro_export]
     macro_rules! start_timer {
         ($msg:expr) => {
@@ -179,3 +285,53 @@ mod inner {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::TimerScope;
+    #[cfg(feature = "print-trace")]
+    use super::{record_spans, take_spans};
+
+    #[test]
+    #[cfg(feature = "print-trace")]
+    fn test_nested_spans() {
+        record_spans(true);
+        let outer = start_timer!(|| "outer");
+        let inner = start_timer!(|| "inner");
+        end_timer!(inner);
+        end_timer!(outer);
+        record_spans(false);
+
+        let spans = take_spans();
+        let inner = spans.iter().position(|s| s.msg == "inner").unwrap();
+        let outer = spans.iter().position(|s| s.msg == "outer").unwrap();
+        assert!(inner < outer);
+        assert_eq!(spans[inner].depth, spans[outer].depth + 1);
+        assert!(spans[inner].secs <= spans[outer].secs);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_scopes_across_tasks() {
+        const NAME: &str = "test_scopes_across_tasks";
+        const SLEEP: Duration = Duration::from_millis(20);
+        let tasks = (0..10)
+            .map(|_| {
+                tokio::spawn(async {
+                    let _scope = TimerScope::enter(NAME);
+                    tokio::time::sleep(SLEEP).await;
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let (count, total) = TimerScope::aggregate()[NAME];
+        assert_eq!(count, 10);
+        // the tasks sleep at the same time, but each one counts in full
+        assert!(total >= 10 * SLEEP);
+        assert!(total < 10 * (SLEEP + Duration::from_secs(1)));
+    }
+}
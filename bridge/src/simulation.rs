@@ -0,0 +1,30 @@
+//! Connection helpers that only pretend to talk to the peer, for simulating
+//! a server without communication.
+//!
+//! They are compiled only with the `simulation-helpers` feature. Constructing
+//! one creates a [`SimulationOnly`] marker, which records in this process
+//! that a run did not really communicate.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the simulation helpers of this crate are compiled in.
+pub const HELPERS_COMPILED: bool = cfg!(feature = "simulation-helpers");
+
+static HELPER_CONSTRUCTED: AtomicBool = AtomicBool::new(false);
+
+/// Created by every simulation helper of this crate.
+#[derive(Debug, Clone, Copy)]
+pub struct SimulationOnly(());
+
+impl SimulationOnly {
+    #[cfg_attr(not(feature = "simulation-helpers"), allow(dead_code))]
+    pub(crate) fn mark() -> Self {
+        HELPER_CONSTRUCTED.store(true, Ordering::Relaxed);
+        SimulationOnly(())
+    }
+}
+
+/// Whether a simulation helper of this crate was constructed in this process.
+pub fn helper_constructed() -> bool {
+    HELPER_CONSTRUCTED.load(Ordering::Relaxed)
+}
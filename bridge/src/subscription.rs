@@ -0,0 +1,350 @@
+//! Bookkeeping of subscriptions and unclaimed messages, shared by
+//! `TcpConnection` and `MpcConnection`.
+//!
+//! Each subscription gets a generation number. If the future waiting for a
+//! message is dropped (e.g. cancelled by a timeout), its entry is removed, so
+//! the same id can be subscribed again, and a message arriving later is kept
+//! for the new subscriber.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tokio::sync::oneshot;
+use tracing::{debug, trace};
+
+use crate::{id_tracker::RecvId, version::ProtocolVersion, BridgeError};
+
+type Result<T> = std::result::Result<T, BridgeError>;
+
+/// Should be protected by a mutex.
+#[derive(Debug, Default)]
+pub(crate) struct SubscribeBuffer {
+    pending_subscribe: HashMap<RecvId, (u64, oneshot::Sender<Bytes>)>,
+    pending_message: HashMap<RecvId, Bytes>,
+    next_generation: u64,
+    closed: Option<Closed>,
+    /// Bumped when a `TcpConnection` moves to a new socket, so that the read
+    /// loop of the old socket stops delivering.
+    epoch: u64,
+    /// Whether any message was handed to a subscriber.
+    consumed: bool,
+}
+
+/// Why no more messages will arrive.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Closed {
+    /// The peer is idle for too long.
+    TimedOut,
+    /// The peer speaks an incompatible wire format.
+    VersionMismatch {
+        ours: ProtocolVersion,
+        theirs: ProtocolVersion,
+    },
+    /// The peer gave up the round, e.g. on Ctrl-C.
+    Aborted,
+}
+
+impl Closed {
+    fn error(self) -> BridgeError {
+        match self {
+            Closed::TimedOut => BridgeError::ConnectionTimedOut,
+            Closed::VersionMismatch { ours, theirs } => {
+                BridgeError::VersionMismatch { ours, theirs }
+            },
+            Closed::Aborted => BridgeError::PeerAborted,
+        }
+    }
+}
+
+enum Subscription {
+    Ready(Bytes),
+    Wait(u64, oneshot::Receiver<Bytes>),
+}
+
+impl SubscribeBuffer {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hand a message read from the socket to its subscriber, or keep it
+    /// until someone subscribes. Returns `true` if a subscriber was waiting.
+    pub(crate) fn deliver(&mut self, message_id: RecvId, data: Bytes) -> bool {
+        if let Some((_, sender)) = self.pending_subscribe.remove(&message_id) {
+            match sender.send(data) {
+                Ok(()) => {
+                    self.consumed = true;
+                    return true;
+                },
+                Err(data) => {
+                    debug!(
+                        "subscriber of id={} is dead, keep message for a retry",
+                        message_id
+                    );
+                    self.pending_message.insert(message_id, data);
+                },
+            }
+        } else {
+            self.pending_message.insert(message_id, data);
+        }
+        false
+    }
+
+    /// No more messages will arrive. Pending and later subscriptions resolve
+    /// to the error of `reason`, e.g. `BridgeError::ConnectionTimedOut` if the
+    /// peer is idle for too long.
+    pub(crate) fn close(&mut self, reason: Closed) {
+        self.closed = Some(reason);
+        self.pending_subscribe.clear();
+    }
+
+    /// Epoch of the socket whose read loop may deliver.
+    pub(crate) fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Start serving a new socket. Returns its epoch. Pending subscriptions
+    /// keep waiting, and are served by the new socket.
+    pub(crate) fn next_epoch(&mut self) -> u64 {
+        self.epoch += 1;
+        self.closed = None;
+        self.epoch
+    }
+
+    /// Whether any message was handed to a subscriber.
+    pub(crate) fn is_consumed(&self) -> bool {
+        self.consumed
+    }
+
+    /// Drop the messages that nobody has claimed yet.
+    pub(crate) fn clear_unclaimed(&mut self) {
+        self.pending_message.clear();
+    }
+
+    fn subscribe(&mut self, message_id: RecvId) -> Result<Subscription> {
+        if let Some(v) = self.pending_message.remove(&message_id) {
+            trace!("found subscribed data: id={}", message_id);
+            self.consumed = true;
+            return Ok(Subscription::Ready(v));
+        }
+        if let Some(closed) = self.closed {
+            return Err(closed.error());
+        }
+        if let Some((_, sender)) = self.pending_subscribe.get(&message_id) {
+            if !sender.is_closed() {
+                return Err(BridgeError::DuplicateSubscription(message_id));
+            }
+            debug!("replacing stale subscription: id={}", message_id);
+        }
+        trace!(
+            "not found subscribed data: id={}, put to pending subscribe",
+            message_id
+        );
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        let (sender, receiver) = oneshot::channel();
+        self.pending_subscribe
+            .insert(message_id, (generation, sender));
+        Ok(Subscription::Wait(generation, receiver))
+    }
+
+    /// Take the message with `message_id` if it has arrived and nobody is
+    /// waiting for it.
+    fn take(&mut self, message_id: RecvId) -> Option<Bytes> {
+        let message = self.pending_message.remove(&message_id);
+        self.consumed |= message.is_some();
+        message
+    }
+
+    #[cfg(test)]
+    fn num_entries(&self) -> usize {
+        self.pending_subscribe.len() + self.pending_message.len()
+    }
+}
+
+/// Removes the subscription entry of its generation when dropped, so that a
+/// cancelled subscription does not block a retry.
+struct SubscriptionGuard<'a> {
+    buffer: &'a Mutex<SubscribeBuffer>,
+    message_id: RecvId,
+    generation: u64,
+}
+
+impl Drop for SubscriptionGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            let is_mine = matches!(
+                buffer.pending_subscribe.get(&self.message_id),
+                Some((generation, _)) if *generation == self.generation
+            );
+            if is_mine {
+                buffer.pending_subscribe.remove(&self.message_id);
+            }
+        }
+    }
+}
+
+/// Wait for message with `message_id`. Returns
+/// `BridgeError::DuplicateSubscription` if another live caller is already
+/// waiting for the same id, and `BridgeError::Disconnected` if the
+/// subscription is dropped while the buffer is not closed.
+pub(crate) async fn subscribe_and_get_bytes(
+    buffer: &Arc<Mutex<SubscribeBuffer>>,
+    message_id: RecvId,
+) -> Result<Bytes> {
+    let subscription = buffer.lock().unwrap().subscribe(message_id)?;
+    match subscription {
+        Subscription::Ready(v) => Ok(v),
+        Subscription::Wait(generation, receiver) => {
+            let _guard = SubscriptionGuard {
+                buffer,
+                message_id,
+                generation,
+            };
+            match receiver.await {
+                Ok(v) => Ok(v),
+                Err(_) => match buffer.lock().unwrap().closed {
+                    Some(closed) => Err(closed.error()),
+                    None => Err(BridgeError::Disconnected(message_id)),
+                },
+            }
+        },
+    }
+}
+
+/// Same as `subscribe_and_get_bytes`, but gives up after `timeout` with
+/// `BridgeError::SubscribeTimeout`. The subscription is removed on timeout, so
+/// the id can be subscribed again and a late message is kept for the retry.
+pub(crate) async fn subscribe_and_get_bytes_timeout(
+    buffer: &Arc<Mutex<SubscribeBuffer>>,
+    message_id: RecvId,
+    timeout: Duration,
+) -> Result<Bytes> {
+    tokio::time::timeout(timeout, subscribe_and_get_bytes(buffer, message_id))
+        .await
+        .unwrap_or_else(|_| {
+            Err(BridgeError::SubscribeTimeout {
+                id: message_id,
+                waited: timeout,
+            })
+        })
+}
+
+/// The message with `message_id` if it has already arrived, without waiting.
+pub(crate) fn try_get(buffer: &Mutex<SubscribeBuffer>, message_id: RecvId) -> Option<Bytes> {
+    buffer.lock().unwrap().take(message_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use bytes::Bytes;
+
+    use super::{subscribe_and_get_bytes, SubscribeBuffer};
+    use crate::{id_tracker::RecvId, BridgeError};
+
+    #[tokio::test]
+    async fn test_timeout_then_retry() {
+        let buffer = Arc::new(Mutex::new(SubscribeBuffer::new()));
+        let id = RecvId(12);
+
+        let first = tokio::time::timeout(
+            Duration::from_millis(10),
+            subscribe_and_get_bytes(&buffer, id),
+        )
+        .await;
+        assert!(first.is_err());
+
+        let retry = {
+            let buffer = buffer.clone();
+            tokio::spawn(async move { subscribe_and_get_bytes(&buffer, id).await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        buffer
+            .lock()
+            .unwrap()
+            .deliver(id, Bytes::from_static(b"late"));
+        assert_eq!(retry.await.unwrap().unwrap(), Bytes::from_static(b"late"));
+    }
+
+    #[tokio::test]
+    async fn test_message_kept_for_retry() {
+        let buffer = Arc::new(Mutex::new(SubscribeBuffer::new()));
+        let id = RecvId(12);
+
+        let first = tokio::time::timeout(
+            Duration::from_millis(10),
+            subscribe_and_get_bytes(&buffer, id),
+        )
+        .await;
+        assert!(first.is_err());
+
+        // message arrives while nobody is subscribed
+        assert!(!buffer
+            .lock()
+            .unwrap()
+            .deliver(id, Bytes::from_static(b"late")));
+        let retry = subscribe_and_get_bytes(&buffer, id).await.unwrap();
+        assert_eq!(retry, Bytes::from_static(b"late"));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicate_subscription() {
+        let buffer = Arc::new(Mutex::new(SubscribeBuffer::new()));
+        let id = RecvId(12);
+
+        let first = {
+            let buffer = buffer.clone();
+            tokio::spawn(async move { subscribe_and_get_bytes(&buffer, id).await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let second = subscribe_and_get_bytes(&buffer, id).await;
+        assert!(matches!(second, Err(BridgeError::DuplicateSubscription(i)) if i == id));
+
+        // the first subscriber is not affected
+        buffer
+            .lock()
+            .unwrap()
+            .deliver(id, Bytes::from_static(b"data"));
+        assert_eq!(first.await.unwrap().unwrap(), Bytes::from_static(b"data"));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_subscription_without_reason() {
+        let buffer = Arc::new(Mutex::new(SubscribeBuffer::new()));
+        let id = RecvId(12);
+
+        let waiting = {
+            let buffer = buffer.clone();
+            tokio::spawn(async move { subscribe_and_get_bytes(&buffer, id).await })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        buffer.lock().unwrap().pending_subscribe.clear();
+
+        let result = waiting.await.expect("subscriber panics");
+        assert!(matches!(result, Err(BridgeError::Disconnected(i)) if i == id));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_retry_bounded() {
+        let buffer = Arc::new(Mutex::new(SubscribeBuffer::new()));
+        for i in 0..1000 {
+            let id = RecvId(i % 4);
+            let r = tokio::time::timeout(
+                Duration::from_micros(1),
+                subscribe_and_get_bytes(&buffer, id),
+            )
+            .await;
+            assert!(r.is_err());
+            assert_eq!(buffer.lock().unwrap().num_entries(), 0);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Blocking variant of `TcpConnection` for tools and tests that do not run a
+//! tokio runtime. The framing (u64 id and u64 length, both little endian,
+//! followed by the message) is the same as `tcp_bridge`, so a
+//! `SyncTcpConnection` can talk to a `TcpConnection`.
+
+use std::{
+    collections::HashMap,
+    io::{BufReader, BufWriter, Read, Write},
+    net::{SocketAddr, TcpStream},
+};
+
+use bytes::Bytes;
+use serialize::{Communicate, UseCast};
+use tracing::trace;
+
+use crate::{
+    id_tracker::{ExchangeId, RecvId, SendId, HEARTBEAT_MESSAGE_ID, REGISTER_MESSAGE_ID},
+    metrics::HEADER_SIZE,
+    tcp_bridge::{ClientID, ClientToken, RegistrationMessage, DEFAULT_MAX_FRAME_SIZE},
+};
+
+type Error = crate::BridgeError;
+type Result<T> = std::result::Result<T, Error>;
+
+const CLIENT_TCP_BUFFER_SIZE: usize = 1024 * 32;
+
+/// Blocking TCP connection. Messages that arrive before they are subscribed
+/// are buffered until `subscribe_and_get` asks for their id.
+///
+/// Unlike `TcpConnection`, nothing reads from the socket while a message is
+/// being written, so two `SyncTcpConnection`s exchanging messages larger than
+/// the socket buffers can deadlock. A `TcpConnection` peer is always fine.
+#[derive(Debug)]
+pub struct SyncTcpConnection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    pending: HashMap<RecvId, Bytes>,
+    num_bytes_recv: usize,
+    socket_addr: SocketAddr,
+    uid: ClientID,
+}
+
+impl SyncTcpConnection {
+    fn new(socket: TcpStream, uid: ClientID) -> Result<Self> {
+        let socket_addr = socket.peer_addr()?;
+        socket.set_nodelay(true)?;
+        let reader = BufReader::with_capacity(CLIENT_TCP_BUFFER_SIZE, socket.try_clone()?);
+        let writer = BufWriter::with_capacity(CLIENT_TCP_BUFFER_SIZE, socket);
+        Ok(Self {
+            reader,
+            writer,
+            pending: HashMap::new(),
+            num_bytes_recv: 0,
+            socket_addr,
+            uid,
+        })
+    }
+
+    /// Initialize a connection and send the registration message with
+    /// `token`, like `TcpConnection::new_client_side`.
+    pub fn new_client_side(socket: TcpStream, uid: ClientID, token: ClientToken) -> Result<Self> {
+        let mut conn = Self::new(socket, uid)?;
+        conn.send_message(
+            SendId(REGISTER_MESSAGE_ID),
+            UseCast(RegistrationMessage { uid, token }),
+        )?;
+        Ok(conn)
+    }
+
+    /// Initialize a connection and wait for the registration message, like
+    /// `TcpConnection::new_server_side`. Fails with `BridgeError::WrongToken`
+    /// if the client does not register with `token`.
+    pub fn new_server_side(socket: TcpStream, token: ClientToken) -> Result<Self> {
+        let mut conn = Self::new(socket, ClientID::default())?;
+        let registration =
+            conn.subscribe_and_get::<UseCast<RegistrationMessage>>(RecvId(REGISTER_MESSAGE_ID))?;
+        if !registration.token.ct_eq(&token) {
+            return Err(Error::WrongToken);
+        }
+        conn.uid = registration.uid;
+        Ok(conn)
+    }
+
+    /// Get statistics of how many bytes received from the peer, including the
+    /// message headers but not counting heartbeats.
+    pub fn num_bytes_received(&self) -> usize {
+        self.num_bytes_recv
+    }
+
+    pub fn socket_addr(&self) -> SocketAddr {
+        self.socket_addr
+    }
+
+    pub fn uid(&self) -> ClientID {
+        self.uid
+    }
+
+    /// Send message to peer. Returns after the message is flushed to the
+    /// socket.
+    pub fn send_message_bytes(&mut self, id: SendId, message: Bytes) -> Result<()> {
+        write_one_message_without_flush(&mut self.writer, id, &message)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Block until the message with `id` arrives. Messages with other ids
+    /// read in the meantime are buffered.
+    pub fn subscribe_and_get_bytes(&mut self, id: RecvId) -> Result<Bytes> {
+        if let Some(data) = self.pending.remove(&id) {
+            return Ok(data);
+        }
+        loop {
+            let (message_id, data) = read_one_message(&mut self.reader)?;
+            if message_id.0 == HEARTBEAT_MESSAGE_ID {
+                trace!("received heartbeat");
+                continue;
+            }
+            self.num_bytes_recv += HEADER_SIZE as usize + data.len();
+            if message_id == id {
+                return Ok(data);
+            }
+            trace!("buffer message {} while waiting for {}", message_id, id);
+            self.pending.insert(message_id, data);
+        }
+    }
+
+    pub fn send_message<M: Communicate>(&mut self, id: SendId, msg: M) -> Result<()> {
+        self.send_message_bytes(id, msg.into_bytes_owned())
+    }
+
+    pub fn subscribe_and_get<M: Communicate>(&mut self, id: RecvId) -> Result<M::Deserialized> {
+        let data = self.subscribe_and_get_bytes(id)?;
+        let msg = M::from_bytes_owned(data)?;
+        Ok(msg)
+    }
+
+    pub fn exchange_message<M: Communicate>(
+        &mut self,
+        id: ExchangeId,
+        msg: M,
+    ) -> Result<M::Deserialized> {
+        self.send_message(id.send_id, msg)?;
+        self.subscribe_and_get::<M>(id.recv_id)
+    }
+}
+
+fn read_one_message(read_socket: &mut impl Read) -> Result<(RecvId, Bytes)> {
+    let mut header = [0u8; 8];
+    read_socket.read_exact(&mut header)?;
+    let message_id = u64::from_le_bytes(header);
+    read_socket.read_exact(&mut header)?;
+    let message_size = u64::from_le_bytes(header);
+
+    trace!(
+        "try read buffer: message_size: {}, id: {}",
+        message_size,
+        message_id
+    );
+    if message_size > DEFAULT_MAX_FRAME_SIZE {
+        return Err(Error::FrameTooLarge {
+            size: message_size,
+            max: DEFAULT_MAX_FRAME_SIZE,
+        });
+    }
+    let mut read_buffer = vec![0u8; message_size as usize];
+    read_socket.read_exact(&mut read_buffer)?;
+    Ok((message_id.into(), read_buffer.into()))
+}
+
+fn write_one_message_without_flush(
+    write_socket: &mut impl Write,
+    message_id: SendId,
+    data: &[u8],
+) -> Result<()> {
+    trace!(
+        "try write buffer with size: {:?}, id: {}",
+        data.len(),
+        message_id.0
+    );
+    write_socket.write_all(&message_id.0.to_le_bytes())?;
+    write_socket.write_all(&(data.len() as u64).to_le_bytes())?;
+    write_socket.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use block::Block;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serde_derive::{Deserialize, Serialize};
+    use serialize::UseSerde;
+
+    use crate::tcp_bridge::{ClientID, ClientToken, TcpConnection};
+
+    use super::SyncTcpConnection;
+
+    #[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
+    struct HelloWorldMessage {
+        msg: String,
+        num: u128,
+    }
+
+    fn make_test_message() -> HelloWorldMessage {
+        HelloWorldMessage {
+            msg: "Hello World!!".into(),
+            num: 0xdeadbeefabcdabcdaabbccddeeff1234,
+        }
+    }
+
+    fn make_blocks(seed: u64) -> Vec<Block> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..1000).map(|_| Block::rand(&mut rng)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_sync_and_async_interop() {
+        let listener = std::net::TcpListener::bind(("localhost", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let sync_side = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut conn =
+                SyncTcpConnection::new_server_side(socket, ClientToken::default()).unwrap();
+            assert_eq!(conn.uid(), ClientID::new(7));
+
+            conn.send_message(12.into(), UseSerde(make_test_message()))
+                .unwrap();
+            // sent out of order by the peer, so 13 has to be buffered
+            let blocks = conn.subscribe_and_get::<Vec<Block>>(14.into()).unwrap();
+            assert_eq!(blocks, make_blocks(1));
+            let msg = conn
+                .subscribe_and_get::<UseSerde<HelloWorldMessage>>(13.into())
+                .unwrap();
+            assert_eq!(msg, make_test_message());
+
+            let received = conn.exchange_message(15.into(), make_blocks(2)).unwrap();
+            assert_eq!(received, make_blocks(3));
+        });
+
+        let socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (conn, registered) =
+            TcpConnection::new_client_side(socket, ClientID::new(7), ClientToken::default());
+        registered.await.unwrap();
+
+        let msg = conn
+            .subscribe_and_get::<UseSerde<HelloWorldMessage>>(12.into())
+            .await
+            .unwrap();
+        assert_eq!(msg, make_test_message());
+        conn.send_message(13.into(), UseSerde(make_test_message()))
+            .unwrap();
+        conn.send_message(14.into(), make_blocks(1)).unwrap();
+        let received = conn
+            .exchange_message(15.into(), make_blocks(3))
+            .await
+            .unwrap();
+        assert_eq!(received, make_blocks(2));
+
+        tokio::task::spawn_blocking(move || sync_side.join().unwrap())
+            .await
+            .unwrap();
+    }
+}
@@ -1,200 +1,549 @@
 use bytemuck::{Pod, Zeroable};
 use std::{
-    collections::HashMap,
-    fmt::Debug,
-    net::SocketAddr,
-    sync::{Arc, Mutex},
+    collections::BTreeMap,
+    fmt::{self, Debug},
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    str::FromStr,
+    sync::{atomic::AtomicUsize, Arc, Mutex},
+    time::Duration,
 };
-use std::sync::atomic::AtomicUsize;
 
 use bytes::Bytes;
+#[cfg(target_endian = "big")]
+use serialize::util::LePod;
 use serialize::{Communicate, UseCast};
+use subtle::ConstantTimeEq;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpListener, TcpStream,
-    },
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
     sync::{
-        mpsc::{self, UnboundedReceiver},
+        mpsc::{self, error::TryRecvError, UnboundedReceiver},
         oneshot,
     },
+    task::JoinHandle,
 };
-use tracing::{debug, info, trace};
+use tracing::{debug, error, info, trace, warn};
 
-use crate::id_tracker::{ExchangeId, RecvId, REGISTER_MESSAGE_ID, SendId};
+use crate::{
+    flush::{FlushPolicy, Unflushed},
+    id_tracker::{
+        ExchangeId, RecvId, SendId, HEARTBEAT_MESSAGE_ID, REGISTER_MESSAGE_ID, VERSION_MESSAGE_ID,
+    },
+    metrics::HEADER_SIZE,
+    netem::{DelayLine, EmulatedLink, NetworkProfile},
+    subscription::{self, Closed, SubscribeBuffer},
+    tls::{ClientTls, ServerTls},
+    version::{write_version, ProtocolVersion, PROTOCOL_VERSION},
+};
 
 type Error = crate::BridgeError;
 type Result<T> = std::result::Result<T, Error>;
 
 const CLIENT_TCP_BUFFER_SIZE: usize = 1024 * 32;
 
-/// Wrapper for TCP Connection that can be shared safely.
-/// Each message will have a message ID, and user can subscribe the message ID
-/// to get an message. For now, the message queue is unbounded.
-#[derive(Debug, Clone)]
-pub struct TcpConnection {
+/// Largest message read from a client connection unless
+/// `TcpConnectionConfig::max_frame_size` says otherwise.
+pub const DEFAULT_MAX_FRAME_SIZE: u64 = 1 << 30;
+
+/// Liveness, reconnection and flush settings of a `TcpConnection`. Liveness
+/// and reconnection are off by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpConnectionConfig {
+    /// Send an empty heartbeat message if nothing is written for this long.
+    pub heartbeat_interval: Option<Duration>,
+    /// Consider the peer dead if no whole message (including heartbeats) is
+    /// read for this long, also if it stalls in the middle of one. Pending
+    /// and later subscriptions then fail with `BridgeError::ConnectionTimedOut`.
+    pub idle_timeout: Option<Duration>,
+    /// Keep every sent message, so that `reconnect` can send it again. The
+    /// server does not acknowledge messages, so they are kept as long as the
+    /// connection.
+    pub keep_sent: bool,
+    /// When the write loop flushes while more messages are queued.
+    pub flush: FlushPolicy,
+    /// Delay the messages read from the peer as on an emulated link, for
+    /// benchmarks. Heartbeats and the version handshake are not delayed.
+    pub network: NetworkProfile,
+    /// Largest message accepted from the peer, `DEFAULT_MAX_FRAME_SIZE` if
+    /// `None`. A larger header fails the read before anything is allocated.
+    pub max_frame_size: Option<u64>,
+}
+
+impl TcpConnectionConfig {
+    fn max_frame_size(&self) -> u64 {
+        self.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE)
+    }
+}
+
+type WriteTask = (SendId, Bytes, oneshot::Sender<()>);
+
+/// The socket of a connection and the loops serving it. A client that
+/// reconnects gets a new link, and keeps its subscriptions.
+#[derive(Debug)]
+struct Link {
     /// User can send message to peer using this mpsc queue. This includes
     /// message id, message content, and a signal sender to indicate complete.
-    write_channel: mpsc::UnboundedSender<(SendId, Bytes, oneshot::Sender<()>)>,
-    /// User can subscribe a message using a message id, and the receiver
-    /// channel will return bytes
-    subscribe_channel: mpsc::UnboundedSender<(RecvId, oneshot::Sender<Bytes>)>,
-    num_bytes_recv: Arc<AtomicUsize>,
+    write_channel: mpsc::UnboundedSender<WriteTask>,
     socket_addr: SocketAddr,
-    uid: ClientID
+    /// The loops, until `close` takes them.
+    read_loop: Option<JoinHandle<()>>,
+    write_loop: Option<JoinHandle<()>>,
 }
 
-struct PendingBuffer {
-    pending_subscribe: HashMap<RecvId, oneshot::Sender<Bytes>>,
-    pending_message: HashMap<RecvId, Bytes>,
+impl Link {
+    fn send(&self, id: SendId, message: Bytes) -> oneshot::Receiver<()> {
+        let (sig_sender, sig_receiver) = oneshot::channel::<()>();
+        self.write_channel
+            .send((id, message, sig_sender))
+            .unwrap_or(());
+        sig_receiver
+    }
+
+    /// Stop both loops. Messages that are not written yet are dropped.
+    fn abort(&self) {
+        for handle in self.read_loop.iter().chain(&self.write_loop) {
+            handle.abort();
+        }
+    }
+
+    /// Stop taking messages, and return the read loop and the write loop.
+    /// The write loop quits once the queued messages are written.
+    fn close(&mut self) -> (Option<JoinHandle<()>>, Option<JoinHandle<()>>) {
+        // the link holds the only sender, so replacing it closes the channel
+        self.write_channel = mpsc::unbounded_channel().0;
+        (self.read_loop.take(), self.write_loop.take())
+    }
 }
 
-impl PendingBuffer {
-    fn new() -> Self {
-        PendingBuffer {
-            pending_subscribe: HashMap::new(),
-            pending_message: HashMap::new(),
+impl Drop for Link {
+    /// Nobody can claim the messages of a dropped link, so stop reading. The
+    /// write loop still writes the queued messages.
+    fn drop(&mut self) {
+        if let Some(read_loop) = &self.read_loop {
+            read_loop.abort();
         }
     }
 }
 
-impl TcpConnection {
-    fn new(socket: TcpStream, uid: ClientID) -> Self {
-        let socket_addr = socket.peer_addr().unwrap();
+type ReadHalf = Box<dyn AsyncRead + Send + Unpin>;
+type WriteHalf = Box<dyn AsyncWrite + Send + Unpin>;
 
+/// Both halves of a plaintext or TLS socket, and the address of the peer.
+pub(crate) struct SplitSocket {
+    read_socket: BufReader<ReadHalf>,
+    write_socket: WriteHalf,
+    socket_addr: SocketAddr,
+}
+
+impl SplitSocket {
+    fn new(socket: TcpStream) -> io::Result<Self> {
+        let socket_addr = socket.peer_addr()?;
         let (read_socket, write_socket) = socket.into_split();
-        let (write_sender, write_receiver) = mpsc::unbounded_channel();
-        let (subscribe_sender, subscribe_receiver) = mpsc::unbounded_channel();
-        let pending_buffer = Arc::new(Mutex::new(PendingBuffer::new()));
-
-        let num_recv_bytes = Arc::new(AtomicUsize::new(0));
-
-        // read loop
-        {
-            let pending_buffer = pending_buffer.clone();
-            let num_bytes_recv = num_recv_bytes.clone();
-            tokio::spawn(async move {
-                let mut read_socket = BufReader::with_capacity(CLIENT_TCP_BUFFER_SIZE, read_socket);
-                loop {
-                    let (message_id, read_buffer) = match read_one_message(&mut read_socket).await {
-                        Ok(message) => message,
-                        Err(e) => {
-                            trace!("read_one_message error: {:?}", e);
-                            break;
-                        }
-                    };
-                    let read_buffer_len = read_buffer.len();
-                    num_bytes_recv.fetch_add(read_buffer_len, std::sync::atomic::Ordering::Relaxed);
-                    {
-                        let mut pending = pending_buffer.lock().unwrap();
-                        // if there is pending subscribe, send the message to pending subscribe
-                        // channel
-                        if let Some(v) = pending.pending_subscribe.remove(&message_id) {
-                            if let Err(_) = v.send(read_buffer) {
-                                debug!("subscribe reader is dead")
-                            };
-                            trace!(
-                                "done read buffer of size: {}, id: {}, satisfy to pending subscribe",
-                                read_buffer_len,
-                                message_id
-                            );
-                            continue;
-                        } else {
-                            pending.pending_message.insert(message_id, read_buffer);
-                            trace!(
-                                "done read buffer of size: {}, id: {}, push to pending message",
-                                read_buffer_len,
-                                message_id
-                            );
-                        }
-                    }
-                }
-            });
+        Ok(Self::from_halves(
+            Box::new(read_socket),
+            Box::new(write_socket),
+            socket_addr,
+        ))
+    }
+
+    fn from_stream<S>(stream: S, socket_addr: SocketAddr) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (read_socket, write_socket) = tokio::io::split(stream);
+        Self::from_halves(Box::new(read_socket), Box::new(write_socket), socket_addr)
+    }
+
+    fn from_halves(
+        read_socket: ReadHalf,
+        write_socket: WriteHalf,
+        socket_addr: SocketAddr,
+    ) -> Self {
+        Self {
+            read_socket: BufReader::with_capacity(CLIENT_TCP_BUFFER_SIZE, read_socket),
+            write_socket,
+            socket_addr,
         }
+    }
 
-        // subscribe loop
-        tokio::spawn(async move {
-            let mut subscribe: UnboundedReceiver<(RecvId, oneshot::Sender<Bytes>)> =
-                subscribe_receiver;
-            while let Some((message_id, callback)) = subscribe.recv().await {
-                let mut pending = pending_buffer.lock().unwrap();
+    /// Run the TLS handshake of a client, checking the certificate of the
+    /// server.
+    async fn connect_tls(socket: TcpStream, tls: &ClientTls) -> io::Result<Self> {
+        let socket_addr = socket.peer_addr()?;
+        let stream = tls.connector().connect(tls.domain(), socket).await?;
+        Ok(Self::from_stream(stream, socket_addr))
+    }
 
-                if let Some(v) = pending.pending_message.remove(&message_id) {
-                    // if there is message pending for this subscribe, get it
-                    trace!("found subscribed data: id={}", message_id.0);
-                    let callback: oneshot::Sender<Bytes> = callback;
-                    if let Err(_) = callback.send(v) {
-                        debug!("subscribe reader is dead");
-                        return;
-                    };
-                    continue;
-                } else {
-                    // if there is not: add them to pending subscription
-                    trace!(
-                        "not found subscribed data: id={}, put to pending subscribe",
-                        message_id.0
-                    );
-                    pending.pending_subscribe.insert(message_id, callback);
-                }
-            }
-            trace!("all holders for the TCP connection is out of scope. subscribe loop quit");
-        });
+    /// Run the TLS handshake of a server with `tls`, or take `socket` as is.
+    async fn accept(socket: TcpStream, tls: Option<&ServerTls>) -> io::Result<Self> {
+        match tls {
+            Some(tls) => {
+                let socket_addr = socket.peer_addr()?;
+                let stream = tls.acceptor().accept(socket).await?;
+                Ok(Self::from_stream(stream, socket_addr))
+            },
+            None => Self::new(socket),
+        }
+    }
+}
 
-        // write loop
-        {
-            let mut write_receiver: UnboundedReceiver<(SendId, Bytes, oneshot::Sender<()>)> =
-                write_receiver;
-            // TODO: we need to return a handle to this to make sure the write loop is
-            // killed when we quit
-            // TODO: we can remove mpsc completely. See MpcConnection.
-            tokio::spawn(async move {
-                let mut write_socket = BufWriter::with_capacity(CLIENT_TCP_BUFFER_SIZE, write_socket);
-                while let Some((message_id, data, complete)) = write_receiver.recv().await {
-                    write_one_message_without_flush(&mut write_socket, message_id, data)
-                        .await
-                        .unwrap();
-                    write_socket.flush().await.unwrap();
-                    complete.send(()).map_or((), |_| {});
-                }
-                debug!("all holders for the TCP connection is out of scope, and there is not remaining data to send, so write loop quit");
-            });
+/// Payload of the registration message: the uid of the client, and the token
+/// it authenticates with.
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub(crate) struct RegistrationMessage {
+    pub(crate) uid: ClientID,
+    pub(crate) token: ClientToken,
+}
+
+#[cfg(target_endian = "big")]
+impl LePod for RegistrationMessage {
+    fn swap_le(&mut self) {
+        self.uid.swap_le()
+    }
+}
+
+/// A socket whose client has sent its registration message with the right
+/// token.
+pub(crate) struct Registration {
+    uid: ClientID,
+    token: ClientToken,
+    socket: SplitSocket,
+}
+
+/// Size of the registration message, including its header.
+const REGISTRATION_SIZE: usize = HEADER_SIZE as usize + std::mem::size_of::<RegistrationMessage>();
+
+impl Registration {
+    /// Wait for the version and the registration message of the client on
+    /// `socket`, over TLS if `tls` is set, and answer with our version. Fails
+    /// with `BridgeError::ConnectionTimedOut` if they do not arrive within the
+    /// idle timeout of `config`, with `BridgeError::VersionMismatch` if the
+    /// client speaks an incompatible wire format, and with
+    /// `BridgeError::WrongToken` if it does not register with `token`.
+    pub(crate) async fn receive(
+        socket: TcpStream,
+        tls: Option<&ServerTls>,
+        token: ClientToken,
+        config: TcpConnectionConfig,
+    ) -> Result<Self> {
+        let mut socket = SplitSocket::accept(socket, tls).await?;
+        let socket_addr = socket.socket_addr;
+        let check = |theirs| {
+            PROTOCOL_VERSION.check(theirs).map_err(|e| {
+                error!("rejected client at {}: {}", socket_addr, e);
+                e
+            })
+        };
+        let (message_id, data) = next_message(&mut socket.read_socket, config).await?;
+        let (message_id, data) = if message_id.0 == VERSION_MESSAGE_ID {
+            // answer even on a mismatch, so the client fails fast as well
+            write_version(&mut socket.write_socket, PROTOCOL_VERSION).await?;
+            check(ProtocolVersion::from_payload(data)?)?;
+            next_message(&mut socket.read_socket, config).await?
+        } else {
+            check(ProtocolVersion::UNVERSIONED)?;
+            (message_id, data)
+        };
+        if message_id.0 != REGISTER_MESSAGE_ID {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected a registration, got message {}", message_id),
+            )
+            .into());
         }
+        let registration = UseCast::<RegistrationMessage>::from_bytes_owned(data)?;
+        if !registration.token.ct_eq(&token) {
+            error!(
+                "rejected client {} at {}: wrong registration token",
+                registration.uid.id, socket_addr
+            );
+            return Err(Error::WrongToken);
+        }
+        Ok(Self {
+            uid: registration.uid,
+            token,
+            socket,
+        })
+    }
+
+    pub(crate) fn uid(&self) -> ClientID {
+        self.uid
+    }
+}
+
+/// Wrapper for TCP Connection that can be shared safely.
+/// Each message will have a message ID, and user can subscribe the message ID
+/// to get an message. For now, the message queue is unbounded.
+#[derive(Debug, Clone)]
+pub struct TcpConnection {
+    link: Arc<Mutex<Link>>,
+    /// Subscriptions and messages that are not claimed yet, shared with the
+    /// read loop.
+    subscribe_buffer: Arc<Mutex<SubscribeBuffer>>,
+    /// Messages sent so far, if `config.keep_sent` is set.
+    sent: Arc<Mutex<BTreeMap<SendId, Bytes>>>,
+    num_bytes_sent: Arc<AtomicUsize>,
+    num_bytes_recv: Arc<AtomicUsize>,
+    config: TcpConnectionConfig,
+    uid: ClientID,
+    /// Token of the registration, sent again by `reconnect`.
+    token: ClientToken,
+    /// TLS of a client side connection, used again by `reconnect`.
+    tls: Option<ClientTls>,
+}
+
+impl TcpConnection {
+    fn new(
+        socket: TcpStream,
+        uid: ClientID,
+        token: ClientToken,
+        config: TcpConnectionConfig,
+    ) -> Self {
+        Self::with_socket(SplitSocket::new(socket).unwrap(), uid, token, config)
+    }
 
+    fn with_socket(
+        socket: SplitSocket,
+        uid: ClientID,
+        token: ClientToken,
+        config: TcpConnectionConfig,
+    ) -> Self {
+        let subscribe_buffer = SubscribeBuffer::new();
+        let epoch = subscribe_buffer.epoch();
+        let subscribe_buffer = Arc::new(Mutex::new(subscribe_buffer));
+        let num_bytes_sent = Arc::new(AtomicUsize::new(0));
+        let num_bytes_recv = Arc::new(AtomicUsize::new(0));
+        let link = spawn_link(
+            socket,
+            &subscribe_buffer,
+            epoch,
+            &num_bytes_sent,
+            &num_bytes_recv,
+            config,
+        );
         Self {
-            write_channel: write_sender,
-            subscribe_channel: subscribe_sender,
-            num_bytes_recv: num_recv_bytes,
-            socket_addr,
-            uid
+            link: Arc::new(Mutex::new(link)),
+            subscribe_buffer,
+            sent: Arc::new(Mutex::new(BTreeMap::new())),
+            num_bytes_sent,
+            num_bytes_recv,
+            config,
+            uid,
+            token,
+            tls: None,
         }
     }
 
-    /// Initialize a new connection with the given socket and uid. Return a connection and a channel indicating if registration message is successfully sent.
-    pub fn new_client_side(socket: TcpStream, uid: ClientID) -> (Self, oneshot::Receiver<()>) {
-        let conn = Self::new(socket, uid);
-        let chan = register_to_server(&conn, uid).unwrap();
+    /// Initialize a new connection with the given socket and uid, registering with `token`. Return a connection and a channel indicating if registration message is successfully sent.
+    pub fn new_client_side(
+        socket: TcpStream,
+        uid: ClientID,
+        token: ClientToken,
+    ) -> (Self, oneshot::Receiver<()>) {
+        Self::new_client_side_with_config(socket, uid, token, TcpConnectionConfig::default())
+    }
+
+    /// Same as `new_client_side`, with heartbeat, idle timeout and
+    /// reconnection settings.
+    pub fn new_client_side_with_config(
+        socket: TcpStream,
+        uid: ClientID,
+        token: ClientToken,
+        config: TcpConnectionConfig,
+    ) -> (Self, oneshot::Receiver<()>) {
+        let conn = Self::new(socket, uid, token, config);
+        let chan = register_to_server(&conn.link.lock().unwrap(), uid, token);
         (conn, chan)
     }
 
-    /// Initialize a new connection with the given socket, receive the registration message, and return a connection asynchronously.
-    pub async fn new_server_side(socket: TcpStream) -> Self {
-        let mut conn = Self::new(socket, ClientID::default());
-        let client_id = conn
-            .subscribe_and_get::<UseCast<ClientID>>(RecvId(REGISTER_MESSAGE_ID))
+    /// Same as `new_client_side`, over TLS with `tls`. Fails if the TLS
+    /// handshake fails, e.g. if the server has no valid certificate for the
+    /// domain of `tls`.
+    pub async fn new_client_side_tls(
+        socket: TcpStream,
+        uid: ClientID,
+        token: ClientToken,
+        tls: &ClientTls,
+    ) -> Result<(Self, oneshot::Receiver<()>)> {
+        Self::new_client_side_tls_with_config(socket, uid, token, tls, Default::default()).await
+    }
+
+    /// Same as `new_client_side_tls`, with the settings of `config`.
+    /// `reconnect` connects over TLS again.
+    pub async fn new_client_side_tls_with_config(
+        socket: TcpStream,
+        uid: ClientID,
+        token: ClientToken,
+        tls: &ClientTls,
+        config: TcpConnectionConfig,
+    ) -> Result<(Self, oneshot::Receiver<()>)> {
+        let socket = SplitSocket::connect_tls(socket, tls).await?;
+        let mut conn = Self::with_socket(socket, uid, token, config);
+        conn.tls = Some(tls.clone());
+        let chan = register_to_server(&conn.link.lock().unwrap(), uid, token);
+        Ok((conn, chan))
+    }
+
+    /// Initialize a new connection with the given socket, receive the registration message, and return a connection asynchronously. Panics if the client does not register with `token`.
+    pub async fn new_server_side(socket: TcpStream, token: ClientToken) -> Self {
+        Self::new_server_side_with_config(socket, token, TcpConnectionConfig::default())
             .await
-            .unwrap();
-        conn.uid = client_id;
+            .unwrap()
+    }
+
+    /// Same as `new_server_side`, with heartbeat and idle timeout settings.
+    /// Fails if the client times out before registering, or registers with
+    /// another token.
+    pub async fn new_server_side_with_config(
+        socket: TcpStream,
+        token: ClientToken,
+        config: TcpConnectionConfig,
+    ) -> Result<Self> {
+        let registration = Registration::receive(socket, None, token, config).await?;
+        Ok(Self::from_registration(registration, config))
+    }
+
+    /// Both ends of a connection of client `uid` over an in-memory pipe
+    /// instead of a socket: the client side, and the server side, which is
+    /// registered already. Must be called within a tokio runtime, which runs
+    /// the loops of both ends.
+    pub fn in_memory_pair(uid: ClientID) -> (Self, Self) {
+        let (client, server) = tokio::io::duplex(CLIENT_TCP_BUFFER_SIZE);
+        let socket_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let end = |stream| {
+            Self::with_socket(
+                SplitSocket::from_stream(stream, socket_addr),
+                uid,
+                ClientToken::default(),
+                TcpConnectionConfig::default(),
+            )
+        };
+        (end(client), end(server))
+    }
+
+    pub(crate) fn from_registration(
+        registration: Registration,
+        config: TcpConnectionConfig,
+    ) -> Self {
+        let conn = Self::with_socket(
+            registration.socket,
+            registration.uid,
+            registration.token,
+            config,
+        );
+        conn.num_bytes_recv
+            .fetch_add(REGISTRATION_SIZE, std::sync::atomic::Ordering::Relaxed);
         conn
     }
 
-    /// Get statistics of how many bytes received from the peer,
+    /// Serve this server side connection on the socket of `registration`
+    /// from now on, if no message has been consumed from it yet. Messages of
+    /// the old socket that are not claimed are dropped, as the client sends
+    /// them again. Returns whether the socket was replaced.
+    pub(crate) fn replace_socket(&self, registration: Registration) -> bool {
+        assert_eq!(registration.uid, self.uid);
+        let mut buffer = self.subscribe_buffer.lock().unwrap();
+        if buffer.is_consumed() {
+            return false;
+        }
+        buffer.clear_unclaimed();
+        let epoch = buffer.next_epoch();
+        // the new read loop cannot deliver before the lock is released
+        let link = spawn_link(
+            registration.socket,
+            &self.subscribe_buffer,
+            epoch,
+            &self.num_bytes_sent,
+            &self.num_bytes_recv,
+            self.config,
+        );
+        self.num_bytes_recv
+            .fetch_add(REGISTRATION_SIZE, std::sync::atomic::Ordering::Relaxed);
+        std::mem::replace(&mut *self.link.lock().unwrap(), link).abort();
+        true
+    }
+
+    /// Connect this client side connection to `addr` again, e.g. after its
+    /// socket dropped, and register with the same uid, over TLS if it was
+    /// created over TLS. Messages kept with
+    /// `TcpConnectionConfig::keep_sent` are sent again in order of their ids,
+    /// before any message sent later. Pending subscriptions keep waiting, but
+    /// messages of the server that were in flight on the old socket are lost.
+    /// Returns once all of them are written.
+    pub async fn reconnect(&self, addr: impl ToSocketAddrs) -> Result<()> {
+        let socket = TcpStream::connect(addr).await?;
+        let socket = match &self.tls {
+            Some(tls) => SplitSocket::connect_tls(socket, tls).await?,
+            None => SplitSocket::new(socket)?,
+        };
+
+        let completes = {
+            let epoch = self.subscribe_buffer.lock().unwrap().next_epoch();
+            let new_link = spawn_link(
+                socket,
+                &self.subscribe_buffer,
+                epoch,
+                &self.num_bytes_sent,
+                &self.num_bytes_recv,
+                self.config,
+            );
+            // holding the lock, so nothing can be sent in between
+            let mut link = self.link.lock().unwrap();
+            link.abort();
+            *link = new_link;
+            let mut completes = vec![register_to_server(&link, self.uid, self.token)];
+            for (&id, message) in self.sent.lock().unwrap().iter() {
+                completes.push(link.send(id, message.clone()));
+            }
+            completes
+        };
+        for complete in completes {
+            complete.await.map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "connection closed while replaying",
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Write the messages that are already sent, close the socket, and wait
+    /// for the read and write loops to quit. Clones of this connection are
+    /// shut down as well: messages sent on them afterwards are dropped.
+    pub async fn shutdown(self) {
+        let (read_loop, write_loop) = self.link.lock().unwrap().close();
+        if let Some(write_loop) = write_loop {
+            if let Err(e) = write_loop.await {
+                debug!("write loop failed: {:?}", e);
+            }
+        }
+        if let Some(read_loop) = read_loop {
+            read_loop.abort();
+            // cancelled, unless the peer has already closed the connection
+            read_loop.await.unwrap_or(());
+        }
+    }
+
+    /// Get statistics of how many bytes received from the peer, including the
+    /// message headers but not counting heartbeats.
     pub fn num_bytes_received(&self) -> usize {
-        self.num_bytes_recv.load(std::sync::atomic::Ordering::Relaxed)
+        self.num_bytes_recv
+            .load(std::sync::atomic::Ordering::Relaxed)
     }
 
+    /// Get statistics of how many bytes sent to the peer, including the
+    /// message headers but not counting heartbeats.
+    pub fn num_bytes_sent(&self) -> usize {
+        self.num_bytes_sent
+            .load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// Address of the peer, of the latest socket if the client reconnected.
     pub fn socket_addr(&self) -> SocketAddr {
-        self.socket_addr
+        self.link.lock().unwrap().socket_addr
     }
 
     pub fn uid(&self) -> ClientID {
@@ -203,18 +552,48 @@ impl TcpConnection {
 
     /// Send message to peer. Return a receiver to get complete state.
     pub fn send_message_bytes(&self, id: SendId, message: Bytes) -> oneshot::Receiver<()> {
-        let (sig_sender, sig_receiver) = oneshot::channel::<()>();
-        self.write_channel
-            .send((id, message, sig_sender))
-            .unwrap_or_else(|_| { /*no-op*/ });
-        sig_receiver
+        let link = self.link.lock().unwrap();
+        if self.config.keep_sent {
+            self.sent.lock().unwrap().insert(id, message.clone());
+        }
+        link.send(id, message)
+    }
+
+    /// Wait for the message with `id`. The subscription is cancelled if the
+    /// returned future is dropped, so the id can be subscribed again.
+    /// Returns `BridgeError::DuplicateSubscription` if another caller is still
+    /// waiting for the same id.
+    pub async fn subscribe_and_get_bytes(&self, id: RecvId) -> Result<Bytes> {
+        subscription::subscribe_and_get_bytes(&self.subscribe_buffer, id).await
+    }
+
+    /// Same as `subscribe_and_get_bytes`, but fails with
+    /// `BridgeError::SubscribeTimeout` if the message does not arrive within
+    /// `timeout`. The id can be subscribed again after a timeout.
+    pub async fn subscribe_and_get_bytes_timeout(
+        &self,
+        id: RecvId,
+        timeout: Duration,
+    ) -> Result<Bytes> {
+        subscription::subscribe_and_get_bytes_timeout(&self.subscribe_buffer, id, timeout).await
+    }
+
+    /// Wait for the server to answer the registration of a client-side
+    /// connection with its version, for at most `timeout` if set. Fails with
+    /// `BridgeError::VersionMismatch` if the server speaks an incompatible
+    /// wire format, in which case it closes the connection.
+    pub async fn wait_accepted(&self, timeout: Option<Duration>) -> Result<ProtocolVersion> {
+        let id = RecvId(VERSION_MESSAGE_ID);
+        let payload = match timeout {
+            Some(timeout) => self.subscribe_and_get_bytes_timeout(id, timeout).await?,
+            None => self.subscribe_and_get_bytes(id).await?,
+        };
+        ProtocolVersion::from_payload(payload)
     }
 
-    pub async fn subscribe_and_get_bytes(&self, id: RecvId) -> Bytes {
-        // create a one-shot channel
-        let (sender, receiver) = oneshot::channel();
-        self.subscribe_channel.send((id, sender)).unwrap();
-        receiver.await.unwrap()
+    /// The message with `id` if it has already arrived, without waiting.
+    pub fn try_get(&self, id: RecvId) -> Option<Bytes> {
+        subscription::try_get(&self.subscribe_buffer, id)
     }
 
     pub fn send_message<M: Communicate>(
@@ -226,7 +605,17 @@ impl TcpConnection {
     }
 
     pub async fn subscribe_and_get<M: Communicate>(&self, id: RecvId) -> Result<M::Deserialized> {
-        let data = self.subscribe_and_get_bytes(id).await;
+        let data = self.subscribe_and_get_bytes(id).await?;
+        let msg = M::from_bytes_owned(data)?;
+        Ok(msg)
+    }
+
+    pub async fn subscribe_and_get_timeout<M: Communicate>(
+        &self,
+        id: RecvId,
+        timeout: Duration,
+    ) -> Result<M::Deserialized> {
+        let data = self.subscribe_and_get_bytes_timeout(id, timeout).await?;
         let msg = M::from_bytes_owned(data)?;
         Ok(msg)
     }
@@ -239,10 +628,279 @@ impl TcpConnection {
         self.send_message(id.send_id, msg)?;
         self.subscribe_and_get::<M>(id.recv_id).await
     }
+
+    /// Same as `exchange_message`, but fails with
+    /// `BridgeError::SubscribeTimeout` if the message of the peer does not
+    /// arrive within `timeout`.
+    pub async fn exchange_message_timeout<M: Communicate>(
+        &self,
+        id: ExchangeId,
+        msg: M,
+        timeout: Duration,
+    ) -> Result<M::Deserialized> {
+        self.send_message(id.send_id, msg)?;
+        self.subscribe_and_get_timeout::<M>(id.recv_id, timeout)
+            .await
+    }
 }
 
-fn register_to_server(conn: &TcpConnection, id: ClientID) -> Result<oneshot::Receiver<()>> {
-    conn.send_message(SendId(REGISTER_MESSAGE_ID), &UseCast(id))
+/// Read the next message on `read_socket` that is not a heartbeat, within the
+/// idle timeout of `config`.
+async fn next_message(
+    read_socket: &mut BufReader<ReadHalf>,
+    config: TcpConnectionConfig,
+) -> Result<(RecvId, Bytes)> {
+    let next = async {
+        loop {
+            let (message_id, data) = read_one_message(read_socket, config.max_frame_size()).await?;
+            if message_id.0 != HEARTBEAT_MESSAGE_ID {
+                return Ok::<_, Error>((message_id, data));
+            }
+        }
+    };
+    match config.idle_timeout {
+        Some(idle_timeout) => tokio::time::timeout(idle_timeout, next)
+            .await
+            .map_err(|_| Error::ConnectionTimedOut)?,
+        None => next.await,
+    }
+}
+
+/// Send our version and the registration. The server answers with its
+/// version, which the read loop checks.
+fn register_to_server(link: &Link, uid: ClientID, token: ClientToken) -> oneshot::Receiver<()> {
+    // messages are written in order, so the registration completes after it
+    drop(link.send(SendId(VERSION_MESSAGE_ID), PROTOCOL_VERSION.to_payload()));
+    let registration = RegistrationMessage { uid, token };
+    link.send(
+        SendId(REGISTER_MESSAGE_ID),
+        UseCast(registration).into_bytes_owned(),
+    )
+}
+
+/// Hand a message from the peer to its subscription, or keep it until then.
+fn deliver_message(pending_buffer: &mut SubscribeBuffer, message_id: RecvId, read_buffer: Bytes) {
+    let read_buffer_len = read_buffer.len();
+    // if there is pending subscribe, send the message to pending subscribe
+    // channel
+    if pending_buffer.deliver(message_id, read_buffer) {
+        trace!(
+            "done read buffer of size: {}, id: {}, satisfy to pending subscribe",
+            read_buffer_len,
+            message_id
+        );
+    } else {
+        trace!(
+            "done read buffer of size: {}, id: {}, push to pending message",
+            read_buffer_len,
+            message_id
+        );
+    }
+}
+
+/// Spawn the read loop and the write loop of a socket. The read loop delivers
+/// to `subscribe_buffer` as long as it is at `epoch`.
+fn spawn_link(
+    socket: SplitSocket,
+    subscribe_buffer: &Arc<Mutex<SubscribeBuffer>>,
+    epoch: u64,
+    num_bytes_sent: &Arc<AtomicUsize>,
+    num_bytes_recv: &Arc<AtomicUsize>,
+    config: TcpConnectionConfig,
+) -> Link {
+    let SplitSocket {
+        read_socket,
+        write_socket,
+        socket_addr,
+    } = socket;
+    let (write_sender, write_receiver) = mpsc::unbounded_channel();
+
+    // read loop
+    let read_loop = {
+        let pending_buffer = subscribe_buffer.clone();
+        let num_bytes_recv = num_bytes_recv.clone();
+        let mut read_socket = read_socket;
+        let network = EmulatedLink::new(config.network);
+        tokio::spawn(async move {
+            // spawned on the first message delayed by the network profile
+            let mut delay_line = None;
+            loop {
+                let message = read_one_message(&mut read_socket, config.max_frame_size());
+                let message = match config.idle_timeout {
+                    // the deadline covers the body too, so a peer that stalls in the middle
+                    // of a message is also dead. The connection is closed then, so cancelling
+                    // a partially read message does not matter.
+                    Some(idle_timeout) => match tokio::time::timeout(idle_timeout, message).await {
+                        Ok(message) => message,
+                        Err(_) => {
+                            warn!("peer is idle for {:?}, close connection", idle_timeout);
+                            let mut pending_buffer = pending_buffer.lock().unwrap();
+                            if pending_buffer.epoch() == epoch {
+                                pending_buffer.close(Closed::TimedOut);
+                            }
+                            break;
+                        },
+                    },
+                    None => message.await,
+                };
+                let (message_id, read_buffer) = match message {
+                    Ok(message) => message,
+                    Err(e) => {
+                        trace!("read_one_message error: {:?}", e);
+                        break;
+                    },
+                };
+                if message_id.0 == HEARTBEAT_MESSAGE_ID {
+                    trace!("received heartbeat");
+                    continue;
+                }
+                if message_id.0 == VERSION_MESSAGE_ID {
+                    let theirs = match ProtocolVersion::from_payload(read_buffer.clone()) {
+                        Ok(theirs) => theirs,
+                        Err(e) => {
+                            trace!("malformed version message: {:?}", e);
+                            break;
+                        },
+                    };
+                    if let Err(e) = PROTOCOL_VERSION.check(theirs) {
+                        error!("{}, close connection", e);
+                        let mut pending_buffer = pending_buffer.lock().unwrap();
+                        if pending_buffer.epoch() == epoch {
+                            pending_buffer.close(Closed::VersionMismatch {
+                                ours: PROTOCOL_VERSION,
+                                theirs,
+                            });
+                        }
+                        break;
+                    }
+                    debug!("peer speaks wire format {}", theirs);
+                    // kept under the id of the handshake, for `wait_accepted`
+                    let mut pending_buffer = pending_buffer.lock().unwrap();
+                    if pending_buffer.epoch() != epoch {
+                        debug!("socket is replaced, read loop quit");
+                        break;
+                    }
+                    pending_buffer.deliver(RecvId(VERSION_MESSAGE_ID), read_buffer);
+                    continue;
+                }
+                let read_buffer_len = read_buffer.len();
+                let delivery_time = network.delivery_time(read_buffer_len);
+                let mut pending = pending_buffer.lock().unwrap();
+                if pending.epoch() != epoch {
+                    debug!("socket is replaced, read loop quit");
+                    break;
+                }
+                num_bytes_recv.fetch_add(
+                    HEADER_SIZE as usize + read_buffer_len,
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+                match delivery_time {
+                    None => deliver_message(&mut pending, message_id, read_buffer),
+                    Some(delivery_time) => {
+                        drop(pending);
+                        let delay_line = delay_line.get_or_insert_with(|| {
+                            let pending_buffer = pending_buffer.clone();
+                            DelayLine::spawn(move |(message_id, read_buffer)| {
+                                let mut pending = pending_buffer.lock().unwrap();
+                                // a reconnected client gets the messages of the new socket only
+                                if pending.epoch() == epoch {
+                                    deliver_message(&mut pending, message_id, read_buffer);
+                                }
+                            })
+                        });
+                        delay_line.push(delivery_time, (message_id, read_buffer));
+                    },
+                }
+            }
+            if let Some(delay_line) = delay_line {
+                delay_line.finish().await;
+            }
+        })
+    };
+
+    // write loop
+    let write_loop = {
+        let mut write_receiver: UnboundedReceiver<WriteTask> = write_receiver;
+        let num_bytes_sent = num_bytes_sent.clone();
+        // TODO: we can remove mpsc completely. See MpcConnection.
+        tokio::spawn(async move {
+            let mut write_socket = BufWriter::with_capacity(CLIENT_TCP_BUFFER_SIZE, write_socket);
+            let mut unflushed = Unflushed::default();
+            loop {
+                let next = match write_receiver.try_recv() {
+                    Ok(next) => Some(next),
+                    Err(TryRecvError::Disconnected) => None,
+                    Err(TryRecvError::Empty) => {
+                        // the queue is empty, so nothing written is left waiting
+                        if unflushed.flush(&mut write_socket).await.is_err() {
+                            debug!("flush error, write loop quit");
+                            return;
+                        }
+                        match config.heartbeat_interval {
+                            Some(interval) => {
+                                match tokio::time::timeout(interval, write_receiver.recv()).await {
+                                    Ok(next) => next,
+                                    Err(_) => {
+                                        trace!("send heartbeat");
+                                        let sent = write_one_message_without_flush(
+                                            &mut write_socket,
+                                            SendId(HEARTBEAT_MESSAGE_ID),
+                                            Bytes::new(),
+                                        )
+                                        .await;
+                                        if sent.is_err() || write_socket.flush().await.is_err() {
+                                            debug!("failed to send heartbeat, write loop quit");
+                                            return;
+                                        }
+                                        continue;
+                                    },
+                                }
+                            },
+                            None => write_receiver.recv().await,
+                        }
+                    },
+                };
+                let Some((message_id, data, complete)) = next else {
+                    break;
+                };
+                let data_len = data.len();
+                let sent =
+                    write_one_message_without_flush(&mut write_socket, message_id, data).await;
+                if sent.is_err() {
+                    debug!("failed to send message {}, write loop quit", message_id);
+                    return;
+                }
+                // like heartbeats, the version handshake is not counted
+                if message_id.0 != VERSION_MESSAGE_ID {
+                    num_bytes_sent.fetch_add(
+                        HEADER_SIZE as usize + data_len,
+                        std::sync::atomic::Ordering::Relaxed,
+                    );
+                }
+                unflushed.push(HEADER_SIZE as usize + data_len, complete);
+                if unflushed.is_due(&config.flush)
+                    && unflushed.flush(&mut write_socket).await.is_err()
+                {
+                    debug!("failed to flush message {}, write loop quit", message_id);
+                    return;
+                }
+            }
+            debug!("all holders for the TCP connection is out of scope, and there is not remaining data to send, so write loop quit");
+            if let Err(e) = unflushed.flush(&mut write_socket).await {
+                debug!("flush error: {:?}", e);
+            }
+            if let Err(e) = write_socket.shutdown().await {
+                debug!("shutdown error: {:?}", e);
+            }
+        })
+    };
+
+    Link {
+        write_channel: write_sender,
+        socket_addr,
+        read_loop: Some(read_loop),
+        write_loop: Some(write_loop),
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Pod, Zeroable)]
@@ -251,6 +909,13 @@ pub struct ClientID {
     pub id: u64,
 }
 
+#[cfg(target_endian = "big")]
+impl LePod for ClientID {
+    fn swap_le(&mut self) {
+        self.id.swap_le()
+    }
+}
+
 impl ClientID {
     pub fn is_odd(&self) -> bool {
         self.id & 1 == 1
@@ -273,6 +938,53 @@ impl ClientID {
     }
 }
 
+/// Pre-shared token that clients send with their registration, so that a
+/// server only admits clients that know it. Clients and servers without a
+/// configured token use the default, all zeros.
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+#[repr(transparent)]
+pub struct ClientToken(pub [u8; 32]);
+
+/// A token is a byte string, which has no byte order.
+#[cfg(target_endian = "big")]
+impl LePod for ClientToken {
+    fn swap_le(&mut self) {}
+}
+
+impl ClientToken {
+    /// Compare in constant time, so that a wrong token does not leak how
+    /// many of its leading bytes are right.
+    pub fn ct_eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+/// Does not print the token, to keep it out of logs.
+impl Debug for ClientToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ClientToken(..)")
+    }
+}
+
+/// Parse 64 hex digits.
+impl FromStr for ClientToken {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut token = [0u8; 32];
+        if s.len() != token.len() * 2 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!(
+                "invalid client token: expected {} hex digits",
+                token.len() * 2
+            ));
+        }
+        for (byte, digits) in token.iter_mut().zip(s.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).unwrap();
+        }
+        Ok(ClientToken(token))
+    }
+}
+
 /// Make two tcp connection on localhost
 pub async fn localhost_pair(port: u16) -> (TcpConnection, TcpConnection) {
     let server_handle = tokio::spawn(async move {
@@ -285,7 +997,7 @@ pub async fn localhost_pair(port: u16) -> (TcpConnection, TcpConnection) {
             port,
             socket.peer_addr().unwrap()
         );
-        let conn = TcpConnection::new_server_side(socket).await;
+        let conn = TcpConnection::new_server_side(socket, ClientToken::default()).await;
         conn
     });
 
@@ -296,25 +1008,34 @@ pub async fn localhost_pair(port: u16) -> (TcpConnection, TcpConnection) {
                 Ok(s) => {
                     socket = s;
                     break;
-                }
+                },
                 Err(_) => {
                     debug!("waiting to connecting in 10ms");
                     tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-                }
+                },
             }
         }
-        let (conn, handle) = TcpConnection::new_client_side(socket, ClientID::default());
+        let (conn, handle) =
+            TcpConnection::new_client_side(socket, ClientID::default(), ClientToken::default());
         handle.await.unwrap();
         conn
     });
 
     let (server_handle, client_handle) = tokio::join!(server_handle, client_handle);
-    (server_handle.expect("server panics"),
-     client_handle.expect("client panics"))
+    (
+        server_handle.expect("server panics"),
+        client_handle.expect("client panics"),
+    )
 }
 
-pub(crate) async fn read_one_message(
-    read_socket: &mut BufReader<OwnedReadHalf>,
+/// Read the header and then exactly `message_size` bytes of one message. EOF
+/// before the end of the message is an error, so a peer that goes away never
+/// leaves a short message behind, and the next message always starts at the
+/// next header. A message larger than `max_size` is refused before its buffer
+/// is allocated.
+pub(crate) async fn read_one_message<R: AsyncRead + Unpin>(
+    read_socket: &mut R,
+    max_size: u64,
 ) -> Result<(RecvId, Bytes)> {
     trace!("try read header");
     // receive header
@@ -327,16 +1048,21 @@ pub(crate) async fn read_one_message(
         message_size,
         message_id
     );
-    let mut read_buffer = bytes::BytesMut::with_capacity(message_size as usize);
-    while read_buffer.len() < read_buffer.capacity() {
-        read_socket.read_buf(&mut read_buffer).await?;
+    if message_size > max_size {
+        return Err(Error::FrameTooLarge {
+            size: message_size,
+            max: max_size,
+        });
     }
+    let mut read_buffer = bytes::BytesMut::new();
+    read_buffer.resize(message_size as usize, 0);
+    read_socket.read_exact(&mut read_buffer).await?;
 
     Ok((message_id.into(), read_buffer.freeze()))
 }
 
-pub(crate) async fn write_one_message_without_flush(
-    write_socket: &mut BufWriter<OwnedWriteHalf>,
+pub(crate) async fn write_one_message_without_flush<W: AsyncWrite + Unpin>(
+    write_socket: &mut W,
     message_id: SendId,
     mut data: Bytes,
 ) -> Result<()> {
@@ -358,13 +1084,28 @@ pub(crate) async fn write_one_message_without_flush(
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
     use serde_derive::{Deserialize, Serialize};
-    use serialize::UseSerde;
+    use serialize::{Communicate, UseCast, UseSerde};
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
     use tracing::info;
 
-    use crate::id_tracker::IdGen;
+    use crate::{
+        id_tracker::{IdGen, SendId, REGISTER_MESSAGE_ID},
+        metrics::HEADER_SIZE,
+        version::{write_version, ProtocolVersion, PROTOCOL_VERSION},
+        BridgeError,
+    };
 
-    use super::localhost_pair;
+    use super::{
+        localhost_pair, read_one_message, write_one_message_without_flush, ClientID, ClientToken,
+        RegistrationMessage, TcpConnection, TcpConnectionConfig, DEFAULT_MAX_FRAME_SIZE,
+    };
 
     #[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Debug)]
     struct HelloWorldMessage {
@@ -381,6 +1122,69 @@ mod tests {
 
     const TEST_PORT: u16 = 6665;
 
+    #[tokio::test]
+    async fn test_read_one_message_back_to_back() {
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        let first = Bytes::from(vec![0xab; 100]);
+        let second = Bytes::from_static(b"second message");
+        let to_send = (first.clone(), second.clone());
+        let write_handle = tokio::spawn(async move {
+            write_one_message_without_flush(&mut writer, 7.into(), to_send.0)
+                .await
+                .unwrap();
+            write_one_message_without_flush(&mut writer, 8.into(), to_send.1)
+                .await
+                .unwrap();
+            writer.flush().await.unwrap();
+        });
+
+        let (id, data) = read_one_message(&mut reader, DEFAULT_MAX_FRAME_SIZE)
+            .await
+            .unwrap();
+        assert_eq!((id, data), (7.into(), first));
+        let (id, data) = read_one_message(&mut reader, DEFAULT_MAX_FRAME_SIZE)
+            .await
+            .unwrap();
+        assert_eq!((id, data), (8.into(), second));
+        write_handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_one_message_truncated() {
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        writer.write_u64_le(7).await.unwrap();
+        writer.write_u64_le(100).await.unwrap();
+        writer.write_all(&[0xab; 40]).await.unwrap();
+        drop(writer);
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            read_one_message(&mut reader, DEFAULT_MAX_FRAME_SIZE),
+        )
+        .await
+        .expect("read_one_message hangs on a truncated message");
+        match result {
+            Err(BridgeError::IoError(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof)
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_one_message_too_large() {
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        writer.write_u64_le(7).await.unwrap();
+        writer.write_u64_le(u64::MAX).await.unwrap();
+
+        match read_one_message(&mut reader, 1024).await {
+            Err(BridgeError::FrameTooLarge { size, max }) => {
+                assert_eq!((size, max), (u64::MAX, 1024))
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_bridge() {
@@ -392,7 +1196,7 @@ mod tests {
         let (server, client) = localhost_pair(TEST_PORT).await;
         let server_handle = tokio::spawn(async move {
             let data = make_test_message();
-            server.send_message(12.into(), &UseSerde(data)).unwrap();
+            server.send_message(12.into(), UseSerde(data)).unwrap();
             info!("Message sent");
             server
         });
@@ -495,6 +1299,398 @@ mod tests {
         assert_eq!(expected4, actual4);
     }
 
+    fn heartbeat_config() -> TcpConnectionConfig {
+        TcpConnectionConfig {
+            heartbeat_interval: Some(Duration::from_millis(100)),
+            idle_timeout: Some(Duration::from_millis(500)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // the peer registers and then never sends anything, not even heartbeats
+        let peer = tokio::spawn(async move {
+            let socket = TcpStream::connect(addr).await.unwrap();
+            let (conn, wait) =
+                TcpConnection::new_client_side(socket, ClientID::new(3), ClientToken::default());
+            wait.await.unwrap();
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            drop(conn);
+        });
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let server = TcpConnection::new_server_side_with_config(
+            socket,
+            ClientToken::default(),
+            heartbeat_config(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(server.uid(), ClientID::new(3));
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            server.subscribe_and_get_bytes(12.into()),
+        )
+        .await
+        .expect("idle peer is not detected");
+        assert!(matches!(result, Err(BridgeError::ConnectionTimedOut)));
+        peer.abort();
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_mid_message() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // the peer registers, sends the header and part of a message, and stalls
+        let peer = tokio::spawn(async move {
+            let mut socket = TcpStream::connect(addr).await.unwrap();
+            write_version(&mut socket, PROTOCOL_VERSION).await.unwrap();
+            let registration = RegistrationMessage {
+                uid: ClientID::new(3),
+                token: ClientToken::default(),
+            };
+            write_one_message_without_flush(
+                &mut socket,
+                SendId(REGISTER_MESSAGE_ID),
+                UseCast(registration).into_bytes_owned(),
+            )
+            .await
+            .unwrap();
+            socket.write_u64_le(12).await.unwrap();
+            socket.write_u64_le(100).await.unwrap();
+            socket.write_all(&[0xab; 40]).await.unwrap();
+            socket.flush().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            drop(socket);
+        });
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let server = TcpConnection::new_server_side_with_config(
+            socket,
+            ClientToken::default(),
+            heartbeat_config(),
+        )
+        .await
+        .unwrap();
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            server.subscribe_and_get_bytes(12.into()),
+        )
+        .await
+        .expect("peer stalled in the middle of a message is not detected");
+        assert!(matches!(result, Err(BridgeError::ConnectionTimedOut)));
+        peer.abort();
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_keeps_connection_alive() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let peer = tokio::spawn(async move {
+            let socket = TcpStream::connect(addr).await.unwrap();
+            let (conn, wait) = TcpConnection::new_client_side_with_config(
+                socket,
+                ClientID::new(3),
+                ClientToken::default(),
+                heartbeat_config(),
+            );
+            wait.await.unwrap();
+            // stay quiet for longer than the idle timeout
+            tokio::time::sleep(Duration::from_millis(800)).await;
+            conn.send_message(12.into(), UseCast(42u64))
+                .unwrap()
+                .await
+                .unwrap();
+            conn
+        });
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let server = TcpConnection::new_server_side_with_config(
+            socket,
+            ClientToken::default(),
+            heartbeat_config(),
+        )
+        .await
+        .unwrap();
+        let received = server
+            .subscribe_and_get::<UseCast<u64>>(12.into())
+            .await
+            .unwrap();
+        assert_eq!(received, 42);
+        // registration and the message, heartbeats are not counted
+        assert_eq!(
+            server.num_bytes_received(),
+            2 * HEADER_SIZE as usize + 40 + 8
+        );
+        drop(peer.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_then_reuse_port() {
+        const PORT: u16 = 6678;
+        for session in 0..2u32 {
+            let (server, client) = localhost_pair(PORT).await;
+            // queued before the shutdown, so it is written
+            let sent = client.send_message(12.into(), vec![session; 1000]).unwrap();
+            let (_, received) = tokio::join!(client.shutdown(), async {
+                let received = server.subscribe_and_get::<Vec<u32>>(12.into()).await;
+                server.shutdown().await;
+                received
+            });
+            assert_eq!(received.unwrap(), vec![session; 1000]);
+            sent.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_byte_counters() {
+        const PORT: u16 = 6675;
+        const NUM_BYTES_SERVER: usize = 1000;
+        const NUM_BYTES_CLIENT: usize = 100_000;
+        let header = HEADER_SIZE as usize;
+
+        let (server, client) = localhost_pair(PORT).await;
+        server
+            .send_message_bytes(12.into(), vec![1u8; NUM_BYTES_SERVER].into())
+            .await
+            .unwrap();
+        client
+            .send_message_bytes(13.into(), vec![2u8; NUM_BYTES_CLIENT].into())
+            .await
+            .unwrap();
+        client.subscribe_and_get_bytes(12.into()).await.unwrap();
+        server.subscribe_and_get_bytes(13.into()).await.unwrap();
+
+        // the client also sent the 40 byte registration message
+        let client_to_server = 2 * header + 40 + NUM_BYTES_CLIENT;
+        let server_to_client = header + NUM_BYTES_SERVER;
+        assert_eq!(client.num_bytes_sent(), client_to_server);
+        assert_eq!(server.num_bytes_received(), client_to_server);
+        assert_eq!(server.num_bytes_sent(), server_to_client);
+        assert_eq!(client.num_bytes_received(), server_to_client);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_message_flushed_behind_busy_queue() {
+        const PORT: u16 = 6679;
+        const NUM_FILLERS: u64 = 100_000;
+        const TARGET: u64 = 7;
+
+        let (server, client) = localhost_pair(PORT).await;
+        // keep the send queue of the server busy with messages nobody awaits
+        let filler = tokio::spawn(async move {
+            for id in 0..NUM_FILLERS {
+                server.send_message_bytes((1000 + id).into(), Bytes::from_static(b"filler"));
+                if id == 100 {
+                    server.send_message_bytes(TARGET.into(), Bytes::from_static(b"target"));
+                }
+                if id % 64 == 0 {
+                    tokio::task::yield_now().await;
+                }
+            }
+            server
+        });
+
+        let target = client
+            .subscribe_and_get_bytes_timeout(TARGET.into(), Duration::from_millis(500))
+            .await
+            .unwrap();
+        assert_eq!(target, Bytes::from_static(b"target"));
+        filler.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_replays_sent_messages() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = TcpConnectionConfig {
+            keep_sent: true,
+            ..Default::default()
+        };
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let (client, registered) = TcpConnection::new_client_side_with_config(
+            socket,
+            ClientID::new(3),
+            ClientToken::default(),
+            config,
+        );
+        registered.await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let lost = TcpConnection::new_server_side(socket, ClientToken::default()).await;
+        for id in [13, 12] {
+            client
+                .send_message(id.into(), UseCast(id))
+                .unwrap()
+                .await
+                .unwrap();
+        }
+        drop(lost);
+
+        let (reconnected, server) = tokio::join!(client.reconnect(addr), async {
+            let (socket, _) = listener.accept().await.unwrap();
+            TcpConnection::new_server_side(socket, ClientToken::default()).await
+        });
+        reconnected.unwrap();
+        assert_eq!(server.uid(), ClientID::new(3));
+        client.send_message(14.into(), UseCast(14u64)).unwrap();
+        for id in [12, 13, 14] {
+            let received = server
+                .subscribe_and_get::<UseCast<u64>>(id.into())
+                .await
+                .unwrap();
+            assert_eq!(received, id);
+        }
+        // the replies of the new server reach the same connection
+        server.send_message(15.into(), UseCast(15u64)).unwrap();
+        let received = client
+            .subscribe_and_get::<UseCast<u64>>(15.into())
+            .await
+            .unwrap();
+        assert_eq!(received, 15);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_timeout_then_retry() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let (client, registered) =
+            TcpConnection::new_client_side(socket, ClientID::new(3), ClientToken::default());
+        let (socket, _) = listener.accept().await.unwrap();
+        let server = TcpConnection::new_server_side(socket, ClientToken::default()).await;
+        registered.await.unwrap();
+
+        let waited = Duration::from_millis(100);
+        let result = server
+            .subscribe_and_get_timeout::<UseCast<u64>>(12.into(), waited)
+            .await;
+        assert!(matches!(
+            result,
+            Err(BridgeError::SubscribeTimeout { id, waited: w }) if id == 12.into() && w == waited
+        ));
+        assert_eq!(server.try_get(12.into()), None);
+
+        client
+            .send_message(12.into(), UseCast(42u64))
+            .unwrap()
+            .await
+            .unwrap();
+        let received = server
+            .subscribe_and_get::<UseCast<u64>>(12.into())
+            .await
+            .unwrap();
+        assert_eq!(received, 42);
+
+        let exchanged = tokio::join!(
+            server.exchange_message_timeout(13.into(), UseCast(1u64), waited),
+            client.exchange_message(13.into(), UseCast(2u64))
+        );
+        assert_eq!((exchanged.0.unwrap(), exchanged.1.unwrap()), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_server_rejects_incompatible_client() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let theirs = ProtocolVersion {
+            major: PROTOCOL_VERSION.major + 1,
+            ..PROTOCOL_VERSION
+        };
+        let peer = tokio::spawn(async move {
+            let mut socket = TcpStream::connect(addr).await.unwrap();
+            write_version(&mut socket, theirs).await.unwrap();
+            socket
+        });
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let result = TcpConnection::new_server_side_with_config(
+            socket,
+            ClientToken::default(),
+            Default::default(),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(BridgeError::VersionMismatch { ours, theirs: t }) if ours == PROTOCOL_VERSION && t == theirs
+        ));
+        peer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_server_rejects_wrong_token() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let peer = tokio::spawn(async move {
+            let socket = TcpStream::connect(addr).await.unwrap();
+            let (conn, registered) =
+                TcpConnection::new_client_side(socket, ClientID::new(3), ClientToken([1; 32]));
+            registered.await.unwrap();
+            conn
+        });
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let result = TcpConnection::new_server_side_with_config(
+            socket,
+            ClientToken([2; 32]),
+            Default::default(),
+        )
+        .await;
+        assert!(matches!(result, Err(BridgeError::WrongToken)));
+        drop(peer.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_client_detects_incompatible_server() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let theirs = ProtocolVersion {
+            major: PROTOCOL_VERSION.major + 1,
+            ..PROTOCOL_VERSION
+        };
+        let peer = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            write_version(&mut socket, theirs).await.unwrap();
+            socket
+        });
+
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let (client, _) =
+            TcpConnection::new_client_side(socket, ClientID::new(3), ClientToken::default());
+        let result = client.subscribe_and_get_bytes(12.into()).await;
+        assert!(matches!(
+            result,
+            Err(BridgeError::VersionMismatch { theirs: t, .. }) if t == theirs
+        ));
+        assert!(matches!(
+            client.wait_accepted(None).await,
+            Err(BridgeError::VersionMismatch { theirs: t, .. }) if t == theirs
+        ));
+        peer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_waits_accepted() {
+        let listener = TcpListener::bind(("localhost", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            TcpConnection::new_server_side(socket, ClientToken::default()).await
+        });
+
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let (client, _) =
+            TcpConnection::new_client_side(socket, ClientID::new(3), ClientToken::default());
+        let accepted = client.wait_accepted(Some(Duration::from_secs(5))).await;
+        assert_eq!(accepted.unwrap(), PROTOCOL_VERSION);
+        assert_eq!(server.await.unwrap().uid(), ClientID::new(3));
+    }
+
     #[cfg(feature = "optional_tests")]
     #[tokio::test]
     #[ignore]
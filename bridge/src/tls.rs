@@ -0,0 +1,99 @@
+//! TLS on the links between clients and servers, authenticating the servers.
+//! The framing of messages, and so the byte counters, are the same as on a
+//! plaintext link. The MPC link between the servers stays plaintext.
+
+use std::{
+    convert::TryFrom,
+    fmt::{self, Debug},
+    sync::Arc,
+};
+
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, RootCertStore, ServerName},
+    TlsAcceptor, TlsConnector,
+};
+
+pub use tokio_rustls::rustls;
+
+type Error = crate::BridgeError;
+type Result<T> = std::result::Result<T, Error>;
+
+/// How a client connects to a server over TLS: the name the certificate of
+/// the server is checked against, and the trusted roots.
+#[derive(Clone)]
+pub struct ClientTls {
+    domain: ServerName,
+    config: Arc<rustls::ClientConfig>,
+}
+
+impl ClientTls {
+    /// Trust the servers whose certificate is signed by one of `roots`, or is
+    /// one of them, e.g. a self-signed certificate, and valid for `domain`.
+    pub fn new(domain: &str, roots: &[Certificate]) -> Result<Self> {
+        let domain = ServerName::try_from(domain)
+            .map_err(|_| Error::InvalidServerName(domain.to_string()))?;
+        let mut store = RootCertStore::empty();
+        for root in roots {
+            store.add(root)?;
+        }
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(store)
+            .with_no_client_auth();
+        Ok(Self::with_config(domain, Arc::new(config)))
+    }
+
+    /// Use a config built by the caller, e.g. with other roots or with client
+    /// certificates.
+    pub fn with_config(domain: ServerName, config: Arc<rustls::ClientConfig>) -> Self {
+        Self { domain, config }
+    }
+
+    pub(crate) fn domain(&self) -> ServerName {
+        self.domain.clone()
+    }
+
+    pub(crate) fn connector(&self) -> TlsConnector {
+        TlsConnector::from(self.config.clone())
+    }
+}
+
+impl Debug for ClientTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientTls")
+            .field("domain", &self.domain)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The certificate chain and key a server presents to its clients.
+#[derive(Clone)]
+pub struct ServerTls {
+    config: Arc<rustls::ServerConfig>,
+}
+
+impl ServerTls {
+    /// Present `chain`, the certificate of the server first, signed with
+    /// `key`.
+    pub fn new(chain: Vec<Certificate>, key: PrivateKey) -> Result<Self> {
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)?;
+        Ok(Self::with_config(Arc::new(config)))
+    }
+
+    pub fn with_config(config: Arc<rustls::ServerConfig>) -> Self {
+        Self { config }
+    }
+
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.config.clone())
+    }
+}
+
+impl Debug for ServerTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerTls").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,233 @@
+//! Version of the wire format, exchanged right after a connection is set up,
+//! so that peers built from incompatible commits fail fast instead of
+//! misreading each other's messages.
+//!
+//! The handshake is a single message with id `VERSION_MESSAGE_ID` in each
+//! direction. It is not counted in the byte counters, like heartbeats.
+
+use std::{
+    fmt::{self, Display},
+    io,
+};
+
+use bytemuck::{Pod, Zeroable};
+use bytes::Bytes;
+#[cfg(target_endian = "big")]
+use serialize::util::LePod;
+use serialize::{Communicate, UseCast};
+use tokio::{
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    id_tracker::{RecvId, VERSION_MESSAGE_ID},
+    metrics::HEADER_SIZE,
+    BridgeError,
+};
+
+/// Version of the wire format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
+#[repr(C)]
+pub struct ProtocolVersion {
+    /// Peers with different majors cannot talk to each other. Bump on any
+    /// change of a message layout or of the order of messages.
+    pub major: u16,
+    /// Bump on compatible changes, e.g. a message that old peers ignore.
+    pub minor: u16,
+    /// Optional features of the sender. Only `MATCHING_FLAGS` are checked by
+    /// the handshake.
+    pub flags: u32,
+}
+
+#[cfg(target_endian = "big")]
+impl LePod for ProtocolVersion {
+    fn swap_le(&mut self) {
+        self.major.swap_le();
+        self.minor.swap_le();
+        self.flags.swap_le();
+    }
+}
+
+/// Set if the seeded shares are expanded with AES instead of ChaCha12, see
+/// `crypto_primitives::block_crypto::prg`.
+pub const FLAG_AES_PRG: u32 = 1;
+
+/// Set if `t_til` of OT verification is sent reduced to GF(2^128), see
+/// `crypto_primitives::cot::server::TTil`.
+pub const FLAG_REDUCED_OT_VERIFY: u32 = 2;
+
+/// Set if one random linear combination of the `w` of each client is opened
+/// in square correlation verification, see
+/// `crypto_primitives::square_corr::COMPRESSED_CORR_VERIFY`.
+pub const FLAG_COMPRESSED_CORR_VERIFY: u32 = 4;
+
+/// Set if the uploads of the clients end with an ed25519 signature, see
+/// `crypto_primitives::message::signed`.
+pub const FLAG_SIGNED_UPLOADS: u32 = 8;
+
+/// Set if the COT seeds of a client are expanded for its uid, see
+/// `crypto_primitives::cot::DOMAIN_SEPARATED_SEEDS`.
+pub const FLAG_DOMAIN_SEPARATED_SEEDS: u32 = 16;
+
+/// Flags that change the values on the wire, so both peers must agree on them.
+pub const MATCHING_FLAGS: u32 = FLAG_AES_PRG
+    | FLAG_REDUCED_OT_VERIFY
+    | FLAG_COMPRESSED_CORR_VERIFY
+    | FLAG_SIGNED_UPLOADS
+    | FLAG_DOMAIN_SEPARATED_SEEDS;
+
+/// Version of this build.
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion {
+    major: 9,
+    minor: 0,
+    flags: if cfg!(feature = "aes-prg") {
+        FLAG_AES_PRG
+    } else {
+        0
+    } | if cfg!(feature = "reduced-ot-verify") {
+        FLAG_REDUCED_OT_VERIFY
+    } else {
+        0
+    } | if cfg!(feature = "compressed-corr-verify") {
+        FLAG_COMPRESSED_CORR_VERIFY
+    } else {
+        0
+    } | if cfg!(feature = "signed-uploads") {
+        FLAG_SIGNED_UPLOADS
+    } else {
+        0
+    } | if cfg!(feature = "domain-separated-seeds") {
+        FLAG_DOMAIN_SEPARATED_SEEDS
+    } else {
+        0
+    },
+};
+
+impl ProtocolVersion {
+    /// Version reported for a peer that sent no version, i.e. a build from
+    /// before the handshake.
+    pub const UNVERSIONED: Self = ProtocolVersion {
+        major: 0,
+        minor: 0,
+        flags: 0,
+    };
+
+    /// Fails with `BridgeError::VersionMismatch` if `self`, our version,
+    /// cannot talk to a peer with version `theirs`.
+    pub fn check(self, theirs: ProtocolVersion) -> Result<(), BridgeError> {
+        if self.major != theirs.major || (self.flags ^ theirs.flags) & MATCHING_FLAGS != 0 {
+            return Err(BridgeError::VersionMismatch { ours: self, theirs });
+        }
+        Ok(())
+    }
+
+    /// Payload of the version message.
+    pub(crate) fn to_payload(self) -> Bytes {
+        UseCast(self).into_bytes_owned()
+    }
+
+    /// Parse the payload of the version message.
+    pub(crate) fn from_payload(payload: Bytes) -> Result<Self, BridgeError> {
+        Ok(UseCast::<ProtocolVersion>::from_bytes_owned(payload)?)
+    }
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{} (flags {:#x})", self.major, self.minor, self.flags)
+    }
+}
+
+/// Send `ours` on `socket` and read the version of the peer, reading exactly
+/// the bytes of its version message, so that the socket can be handed to a
+/// buffered reader afterwards. A peer whose first message is not a version
+/// is `ProtocolVersion::UNVERSIONED`.
+pub(crate) async fn exchange_version(
+    socket: &mut TcpStream,
+    ours: ProtocolVersion,
+) -> Result<ProtocolVersion, BridgeError> {
+    write_version(socket, ours).await?;
+
+    let message_id = RecvId(socket.read_u64_le().await?);
+    let len = socket.read_u64_le().await?;
+    if message_id.0 != VERSION_MESSAGE_ID {
+        return Ok(ProtocolVersion::UNVERSIONED);
+    }
+    let expected_len = std::mem::size_of::<ProtocolVersion>();
+    if len != expected_len as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("version message of {} bytes", len),
+        )
+        .into());
+    }
+    let mut theirs = vec![0u8; expected_len];
+    socket.read_exact(&mut theirs).await?;
+    ProtocolVersion::from_payload(theirs.into())
+}
+
+/// Write the version message of `ours` to `socket` and flush it.
+pub(crate) async fn write_version(
+    socket: &mut (impl AsyncWrite + Unpin),
+    ours: ProtocolVersion,
+) -> io::Result<()> {
+    let payload = ours.to_payload();
+    let mut message = Vec::with_capacity(HEADER_SIZE as usize + payload.len());
+    message.extend_from_slice(&VERSION_MESSAGE_ID.to_le_bytes());
+    message.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    message.extend_from_slice(&payload);
+    socket.write_all(&message).await?;
+    socket.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let ours = ProtocolVersion {
+            major: 1,
+            minor: 0,
+            flags: 0,
+        };
+        let newer_minor = ProtocolVersion { minor: 3, ..ours };
+        assert!(ours.check(newer_minor).is_ok());
+        let unknown_flag = ProtocolVersion {
+            flags: 0x80,
+            ..ours
+        };
+        assert!(ours.check(unknown_flag).is_ok());
+
+        for theirs in [
+            ProtocolVersion::UNVERSIONED,
+            ProtocolVersion { major: 2, ..ours },
+            ProtocolVersion {
+                flags: FLAG_AES_PRG,
+                ..ours
+            },
+            ProtocolVersion {
+                flags: FLAG_REDUCED_OT_VERIFY,
+                ..ours
+            },
+            ProtocolVersion {
+                flags: FLAG_COMPRESSED_CORR_VERIFY,
+                ..ours
+            },
+            ProtocolVersion {
+                flags: FLAG_SIGNED_UPLOADS,
+                ..ours
+            },
+            ProtocolVersion {
+                flags: FLAG_DOMAIN_SEPARATED_SEEDS,
+                ..ours
+            },
+        ] {
+            assert!(matches!(
+                ours.check(theirs),
+                Err(BridgeError::VersionMismatch { .. })
+            ));
+        }
+    }
+}
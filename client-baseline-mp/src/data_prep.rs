@@ -46,3 +46,16 @@ pub fn prepare_message<I: UInt, F: FieldElement>(data: &[I]) -> (Bytes, Bytes) {
 
     (Bytes::from(data_share0), Bytes::from(data_share1))
 }
+
+/// The messages of a prio client to the two servers for the inputs of an ELSA
+/// client, e.g. `bin_utils::client::client_inputs`: each input is decomposed
+/// into its bits, least significant first, and each bit is an element of `F`.
+/// Both systems then aggregate the same data, so that their sums can be
+/// compared. Panics if there are not `gsize` inputs.
+pub fn elsa_inputs_to_prio_messages<I: UInt, F: FieldElement>(
+    inputs: &[I],
+    gsize: usize,
+) -> (Bytes, Bytes) {
+    assert_eq!(inputs.len(), gsize, "expected gsize inputs");
+    prepare_message::<I, F>(inputs)
+}
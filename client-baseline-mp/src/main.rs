@@ -1,23 +1,38 @@
 mod data_prep;
 
-use bin_utils::{client::Options, InputSize};
-use bridge::{client_server::init_meta_clients, end_timer, id_tracker::SendId, start_timer};
+use bin_utils::{
+    client::{client_inputs, Options},
+    InputSize, PrioField,
+};
+use bridge::{
+    client_server::{init_meta_clients, ClientConnectConfig},
+    end_timer,
+    id_tracker::SendId,
+    start_timer,
+};
 use bytes::Bytes;
 use crypto_primitives::uint::UInt;
-use prio::field::Field64;
-use rand::prelude::*;
+use prio::field::{Field128, Field64, FieldElement};
 use rayon::prelude::*;
 use tracing::info;
 
-type F = Field64;
-
-fn prepare_data_message_naive<I: UInt>(options: &Options) -> Vec<(Bytes, Bytes)> {
-    let mut rng = StdRng::from_entropy();
-
-    let data = data_prep::prepare_data::<I, _>(options.gsize, &mut rng);
-    let message = data_prep::prepare_message::<I, F>(&data);
+/// The messages of each client in `options.prio_field`.
+fn prepare_data_message<I: UInt>(options: &Options) -> Vec<(Bytes, Bytes)> {
+    match options.prio_field {
+        PrioField::F64 => prepare_data_message_in::<I, Field64>(options),
+        PrioField::F128 => prepare_data_message_in::<I, Field128>(options),
+    }
+}
 
-    (0..options.num_clients).map(|_| message.clone()).collect()
+/// The messages of each client, on the same inputs as the ELSA clients.
+fn prepare_data_message_in<I: UInt, F: FieldElement>(options: &Options) -> Vec<(Bytes, Bytes)> {
+    (0..options.num_clients as u64)
+        .into_par_iter()
+        .map(|uid| {
+            let data = client_inputs::<I>(uid, options.gsize);
+            data_prep::elsa_inputs_to_prio_messages::<I, F>(&data, options.gsize)
+        })
+        .collect()
 }
 
 async fn main_with_options<I: UInt>(options: Options) {
@@ -30,15 +45,18 @@ async fn main_with_options<I: UInt>(options: Options) {
         options.num_clients, options.server_alice, options.server_bob, options.log_level
     );
     let timer = start_timer!(|| "Preparing data and message");
-    let messages = prepare_data_message_naive::<I>(&options);
+    let messages = prepare_data_message::<I>(&options);
     end_timer!(timer);
     info!("Attempting to connect to server");
     let connections = init_meta_clients(
         options.num_clients,
         &options.server_alice,
         &options.server_bob,
+        options.client_token,
+        ClientConnectConfig::default(),
     )
-    .await;
+    .await
+    .expect("clients failed to connect");
 
     info!("All clients connected!");
 
@@ -74,5 +92,6 @@ async fn main() {
     match options.input_size {
         InputSize::U8 => main_with_options::<u8>(options).await,
         InputSize::U32 => main_with_options::<u32>(options).await,
+        InputSize::U64 => main_with_options::<u64>(options).await,
     };
 }
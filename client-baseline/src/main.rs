@@ -1,6 +1,11 @@
 use crate::protocol::Client;
 use bin_utils::{client::Options, InputSize};
-use bridge::{client_server::init_meta_clients, end_timer, id_tracker::IdGen, start_timer};
+use bridge::{
+    client_server::{init_meta_clients, ClientConnectConfig},
+    end_timer,
+    id_tracker::IdGen,
+    start_timer,
+};
 use crypto_primitives::uint::UInt;
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use rayon::prelude::*;
@@ -51,8 +56,11 @@ async fn main_with_options<I: UInt>(options: Options) {
         options.num_clients,
         &options.server_alice,
         &options.server_bob,
+        options.client_token,
+        ClientConnectConfig::default(),
     )
-    .await;
+    .await
+    .expect("clients failed to connect");
 
     info!("All clients connected! Sending clients data...");
 
@@ -79,5 +87,6 @@ pub async fn main() {
     match &options.input_size {
         InputSize::U8 => main_with_options::<u8>(options).await,
         InputSize::U32 => main_with_options::<u32>(options).await,
+        InputSize::U64 => main_with_options::<u64>(options).await,
     }
 }
@@ -0,0 +1,23 @@
+use bin_utils::{
+    client::{run_clients, Options, Variant},
+    panic_report,
+};
+use tokio::runtime::Runtime;
+
+async fn run() {
+    let options = Options::load_from_args("ELSA Client (Bits)");
+    options.require_uncompressed();
+    tracing_subscriber::fmt()
+        .pretty()
+        .with_max_level(options.log_level)
+        .init();
+    run_clients(&options, Variant::Bits(options.bit_aggregation)).await;
+}
+
+fn main() {
+    panic_report::run_main(|| {
+        Runtime::new()
+            .expect("failed to start tokio runtime")
+            .block_on(run())
+    })
+}
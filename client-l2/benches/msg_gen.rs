@@ -1,12 +1,11 @@
-use bridge::BlackBox;
-use client_l2::protocol::L2Client as Client;
-use client_po2::protocol::SingleRoundClient;
+use bridge::{tcp_bridge::ClientID, BlackBox};
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use crypto_primitives::uint::UInt;
+use crypto_primitives::{cot::client::DEFAULT_SEC_PARAM, uint::UInt};
+use elsa_client::{l2::L2Client as Client, po2::SingleRoundClient};
 use rand::{rngs::StdRng, SeedableRng};
 fn run_msg_gen<I: UInt, C: UInt>(data: &[I]) {
     let mut rng = StdRng::from_entropy();
-    let client = Client::<I, C>::new(data, &mut rng);
+    let client = Client::<I, C>::new(ClientID::new(0), 0, data, &mut rng, DEFAULT_SEC_PARAM);
     client.drop_into_black_box();
 }
 
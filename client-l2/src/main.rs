@@ -1,17 +1,32 @@
-use crate::protocol::L2Client;
-
-use bin_utils::{client::Options, InputSize};
-use client_po2::protocol::start_one_round_client;
-
-mod protocol;
+use bin_utils::{
+    client::{print_estimate, run_clients, Options, Variant},
+    panic_report,
+};
+use crypto_primitives::message::size::Protocol;
+use tokio::runtime::Runtime;
 
+#[allow(clippy::upper_case_acronyms)]
 type CORR = u128;
 
-#[tokio::main]
-pub async fn main() {
+async fn run() {
     let options = Options::load_from_args("ELSA Client (L2)");
-    match options.input_size {
-        InputSize::U8 => start_one_round_client::<u8, L2Client<_, CORR>>(options).await,
-        InputSize::U32 => start_one_round_client::<u32, L2Client<_, CORR>>(options).await,
+    options.require_uncompressed();
+    if options.estimate_only {
+        // the servers share in `u64`
+        print_estimate::<u64, CORR, ()>(&options, Protocol::L2);
+        return;
     }
+    tracing_subscriber::fmt()
+        .pretty()
+        .with_max_level(options.log_level)
+        .init();
+    run_clients(&options, Variant::L2).await;
+}
+
+pub fn main() {
+    panic_report::run_main(|| {
+        Runtime::new()
+            .expect("failed to start tokio runtime")
+            .block_on(run())
+    })
 }
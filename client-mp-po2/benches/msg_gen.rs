@@ -1,20 +1,31 @@
-use bridge::BlackBox;
-use client_mp_po2::protocol::Client;
+use bridge::{tcp_bridge::ClientID, BlackBox};
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use crypto_primitives::{malpriv::client::simulate_ot_verify, uint::UInt};
+use crypto_primitives::{
+    cot::client::DEFAULT_SEC_PARAM, malpriv::client::simulate_ot_verify, uint::UInt,
+};
+use elsa_client::mp_po2::Client;
 use rand::{rngs::StdRng, SeedableRng};
 use sha2::Sha256;
 
 type Hasher = Sha256;
 fn run_msg_gen<I: UInt, A: UInt>(data: &[I]) {
     let mut rng = StdRng::from_entropy();
-    let client = Client::prepare_phase1::<I, _, _>(data, &mut rng, || Hasher::default());
+    let client = Client::prepare_phase1::<I, _, _>(
+        ClientID::new(0),
+        0,
+        data,
+        &mut rng,
+        DEFAULT_SEC_PARAM,
+        Hasher::default,
+    );
     let chi_seed = 0;
     let mut hasher = Hasher::default();
     simulate_ot_verify::<I, A, Hasher>(
+        0,
         &client.prepared_message_b.0.inputs_1,
         &client.prepared_message_b.0.cot,
         chi_seed,
+        DEFAULT_SEC_PARAM,
         &mut hasher,
     );
     hasher.drop_into_black_box();
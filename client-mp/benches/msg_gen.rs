@@ -1,7 +1,9 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
 use crypto_primitives::{
     bits::{batch_make_boolean_shares, BitsLE},
-    cot::client::{num_additional_ot_needed, B2ACOTToAlice, B2ACOTToBob, COTGen},
+    cot::client::{
+        num_additional_ot_needed, B2ACOTToAlice, B2ACOTToBob, COTGen, DEFAULT_SEC_PARAM,
+    },
     malpriv::client::{simulate_a2s, simulate_b2a, simulate_ot_verify, simulate_sqcorr_verify},
     square_corr::{batch_make_sqcorr_shares, SquareCorrShare},
     uint::UInt,
@@ -10,6 +12,7 @@ use rand::{rngs::StdRng, SeedableRng};
 use sha2::Sha256;
 
 type Hasher = Sha256;
+#[allow(clippy::type_complexity, clippy::extra_unused_type_parameters)]
 fn msg_prepare<I: UInt, A: UInt, C: UInt>(
     input: &[I],
 ) -> (
@@ -26,8 +29,8 @@ fn msg_prepare<I: UInt, A: UInt, C: UInt>(
         batch_make_boolean_shares(&mut rng, input.iter().map(|x| x.bits_le()));
     let inputs_0_expanded = inputs_0.expand(gsize);
     let delta = COTGen::sample_delta(&mut rng);
-    let num_additional_cot = num_additional_ot_needed(gsize * I::NUM_BITS as usize);
-    let (cot_s, cot_r) = COTGen::sample_cots(&mut rng, &inputs_1, delta, num_additional_cot);
+    let num_additional_cot = num_additional_ot_needed(gsize * I::NUM_BITS, DEFAULT_SEC_PARAM);
+    let (cot_s, cot_r) = COTGen::sample_cots_for(&mut rng, 0, &inputs_1, delta, num_additional_cot);
 
     // generate correlation
     let (_, _, corr0_expanded, corr1_expanded) = batch_make_sqcorr_shares(&mut rng, gsize * 2);
@@ -56,7 +59,7 @@ fn transcript_emulation<I: UInt, A: UInt, C: UInt>(
     let mut hasher4 = Hasher::default();
     let mut hasher5 = Hasher::default();
     let mut hasher6 = Hasher::default();
-    let (y0, y1) = simulate_b2a::<_, A, _>(&inputs_0, &inputs_1, cot_alice, cot_bob, &mut hasher1);
+    let (y0, y1) = simulate_b2a::<_, A, _>(inputs_0, inputs_1, cot_alice, cot_bob, &mut hasher1);
     simulate_a2s::<I, A, C, _>(
         inputs_0.len(),
         sqcorr_alice,
@@ -66,7 +69,7 @@ fn transcript_emulation<I: UInt, A: UInt, C: UInt>(
         &mut hasher2,
         &mut hasher3,
     );
-    simulate_ot_verify::<I, A, _>(inputs_1, &cot_bob, 0, &mut hasher4);
+    simulate_ot_verify::<I, A, _>(0, inputs_1, cot_bob, 0, DEFAULT_SEC_PARAM, &mut hasher4);
     simulate_sqcorr_verify::<I, A, _, _>(
         inputs_0.len(),
         sqcorr_alice,
@@ -116,6 +119,7 @@ fn msg_gen_benchmark<C: Config>(c: &mut Criterion) {
 }
 
 /// Compare with Prio
+#[allow(dead_code)]
 struct Bench1 {}
 impl Config for Bench1 {
     type I = u32;
@@ -127,6 +131,7 @@ impl Config for Bench1 {
     }
 }
 
+#[allow(dead_code)]
 struct Bench2 {}
 impl Config for Bench2 {
     type I = u8;
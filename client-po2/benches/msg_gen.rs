@@ -1,12 +1,12 @@
-use bridge::BlackBox;
-use client_po2::protocol::{Po2Client as Client, SingleRoundClient};
+use bridge::{tcp_bridge::ClientID, BlackBox};
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use crypto_primitives::uint::UInt;
+use crypto_primitives::{cot::client::DEFAULT_SEC_PARAM, uint::UInt};
+use elsa_client::po2::{Po2Client as Client, SingleRoundClient};
 use rand::{rngs::StdRng, SeedableRng};
 
 fn run_msg_gen<I: UInt>(data: &[I]) {
     let mut rng = StdRng::from_entropy();
-    let client = Client::new(data, &mut rng);
+    let client = Client::new(ClientID::new(0), 0, data, &mut rng, DEFAULT_SEC_PARAM);
     client.drop_into_black_box();
 }
 
@@ -1,13 +1,28 @@
-use crate::protocol::{start_one_round_client, Po2Client};
-use bin_utils::{client::Options, InputSize};
+use bin_utils::{
+    client::{print_estimate, run_clients, Options, Variant},
+    panic_report,
+};
+use crypto_primitives::message::size::Protocol;
+use tokio::runtime::Runtime;
 
-mod protocol;
-
-#[tokio::main]
-pub async fn main() {
+async fn run() {
     let options = Options::load_from_args("ELSA Client (Po2)");
-    match options.input_size {
-        InputSize::U8 => start_one_round_client::<u8, Po2Client<_>>(options).await,
-        InputSize::U32 => start_one_round_client::<u32, Po2Client<_>>(options).await,
+    if options.estimate_only {
+        // the servers share in `u64`, and Po2 has no square correlations
+        print_estimate::<u64, u64, ()>(&options, Protocol::Po2);
+        return;
     }
+    tracing_subscriber::fmt()
+        .pretty()
+        .with_max_level(options.log_level)
+        .init();
+    run_clients(&options, Variant::Po2).await;
+}
+
+pub fn main() {
+    panic_report::run_main(|| {
+        Runtime::new()
+            .expect("failed to start tokio runtime")
+            .block_on(run())
+    })
 }
@@ -0,0 +1,23 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use crypto_primitives::{bits::SeededInputShare, block_crypto::prg::Prg};
+
+#[cfg(not(feature = "bench-smoke"))]
+const SIZE: usize = 1 << 20;
+#[cfg(feature = "bench-smoke")]
+const SIZE: usize = 1 << 12;
+
+fn bench_expand(c: &mut Criterion) {
+    let mut group = c.benchmark_group("expand_u32");
+    group.sample_size(20);
+    for prg in [Prg::ChaCha12, Prg::Aes] {
+        group.bench_with_input(
+            BenchmarkId::new(format!("{:?}", prg), SIZE),
+            &SIZE,
+            |bencher, &size| bencher.iter(|| SeededInputShare(12345).expand_with::<u32>(prg, size)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_expand);
+criterion_main!(benches);
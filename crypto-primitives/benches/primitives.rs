@@ -0,0 +1,242 @@
+//! The primitives that dominate the time of the servers, at the sizes of a
+//! client: `NUM_OTS` COTs, or `GSIZES` inputs of `u32`, i.e. 32 COTs each.
+//! With `--features bench-smoke`, the sizes shrink so that the suite runs in
+//! seconds, e.g. to check that it still builds and runs.
+//!
+//! Baseline medians on a single vCPU of a cloud VM (Intel Xeon, model not
+//! reported), built without `-C target-cpu=native`:
+//!
+//! | benchmark                      | 32k / 10k | 320k / 100k | 3.2M     |
+//! |--------------------------------|-----------|-------------|----------|
+//! | chi/sample_chi                 | 230 µs    | 2.50 ms     | 52.1 ms  |
+//! | chi/random_blocks              | 234 µs    | 2.21 ms     | 22.4 ms  |
+//! | ot_verify_inner_product/shared | 55.0 µs   | 574 µs      | 10.5 ms  |
+//! | ot_verify_inner_product/seeded | 288 µs    | 2.80 ms     | 30.2 ms  |
+//! | cot_to_rot/sender              | 2.69 ms   | 32.6 ms     | 330 ms   |
+//! | cot_to_rot/receiver            | 2.28 ms   | 25.0 ms     | 242 ms   |
+//! | mitccr/hash                    | 660 µs    | 6.27 ms     | 63.6 ms  |
+//! | mitccr/hash_many               | 1.83 ms   | 25.3 ms     | 186 ms   |
+//! | b2a/sender                     | 31.7 ms   | 268 ms      |          |
+//! | b2a/receiver                   | 24.8 ms   | 210 ms      |          |
+//! | square_corr/verify_phase_1     | 24.3 µs   | 513 µs      |          |
+//! | square_corr/verify_phase_2     | 65.6 µs   | 737 µs      |          |
+//!
+//! The columns are `NUM_OTS` for the COT benchmarks and `GSIZES` for the
+//! others. Rerun on the target machine before comparing to these numbers.
+
+use std::{convert::TryInto, time::Duration};
+
+use block::Block;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use crypto_primitives::{
+    b2a::{bit_comp_as_ot_receiver_batch, bit_comp_as_ot_sender_batch},
+    bits::BitsLE,
+    block_crypto::{mitccrh::MiTCCR, rng::BlockRng},
+    cot::{
+        rot::{cot_to_rot_receiver_side, cot_to_rot_sender_side},
+        server::{inner_product, inner_product_chunked, sample_chi, Chi},
+    },
+    square_corr::SquareCorrShare,
+    uint::UInt,
+    ALICE,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use safe_arch::m128i;
+
+/// Number of COTs of a client.
+#[cfg(not(feature = "bench-smoke"))]
+const NUM_OTS: &[usize] = &[32_000, 320_000, 3_200_000];
+#[cfg(feature = "bench-smoke")]
+const NUM_OTS: &[usize] = &[3_200];
+
+/// Number of inputs of a client.
+#[cfg(not(feature = "bench-smoke"))]
+const GSIZES: &[usize] = &[10_000, 100_000];
+#[cfg(feature = "bench-smoke")]
+const GSIZES: &[usize] = &[100];
+
+type I = u32;
+type A = u64;
+type C = u128;
+
+const SEED: u64 = 12345;
+
+fn rand_blocks(rng: &mut StdRng, len: usize) -> Vec<Block> {
+    (0..len).map(|_| Block::rand(rng)).collect()
+}
+
+fn bench_chi(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chi");
+    for &num_ots in NUM_OTS {
+        group.throughput(Throughput::Elements(num_ots as u64));
+        group.bench_with_input(
+            BenchmarkId::new("sample_chi", num_ots),
+            &num_ots,
+            |bencher, &n| bencher.iter(|| sample_chi(n, SEED)),
+        );
+        let mut dest = vec![Block::default(); num_ots];
+        group.bench_with_input(
+            BenchmarkId::new("random_blocks", num_ots),
+            &num_ots,
+            |bencher, _| {
+                let mut rng = BlockRng::new(Some(Block::default()));
+                bencher.iter(|| rng.random_blocks(&mut dest))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_inner_product(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ot_verify_inner_product");
+    let mut rng = StdRng::seed_from_u64(SEED);
+    for &num_ots in NUM_OTS {
+        let qs = rand_blocks(&mut rng, num_ots);
+        let chi = sample_chi(num_ots, SEED);
+        let seeded = Chi::Seeded {
+            num_ots,
+            seed: SEED,
+        };
+        group.throughput(Throughput::Elements(num_ots as u64));
+        group.bench_with_input(
+            BenchmarkId::new("shared", num_ots),
+            &num_ots,
+            |bencher, _| bencher.iter(|| inner_product(&qs, &chi)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("seeded", num_ots),
+            &num_ots,
+            |bencher, _| bencher.iter(|| inner_product_chunked(&qs, &seeded)),
+        );
+    }
+    group.finish();
+}
+
+fn bench_cot_to_rot(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cot_to_rot");
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let delta = Block::rand(&mut rng);
+    for &num_ots in NUM_OTS {
+        let qs = rand_blocks(&mut rng, num_ots);
+        group.throughput(Throughput::Elements(num_ots as u64));
+        group.bench_with_input(
+            BenchmarkId::new("sender", num_ots),
+            &num_ots,
+            |bencher, _| bencher.iter(|| cot_to_rot_sender_side::<A>(&qs, delta)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("receiver", num_ots),
+            &num_ots,
+            |bencher, _| bencher.iter(|| cot_to_rot_receiver_side::<A>(&qs)),
+        );
+    }
+    group.finish();
+}
+
+fn bench_mitccr(c: &mut Criterion) {
+    const BATCH_SIZE: usize = 8;
+    let mut group = c.benchmark_group("mitccr");
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let start_point = m128i::from([0x1234u32, 0x2345, 0x3456, 0x4567]);
+    for &num_ots in NUM_OTS {
+        let mut blocks = rand_blocks(&mut rng, num_ots);
+        group.throughput(Throughput::Elements(num_ots as u64));
+        group.bench_with_input(BenchmarkId::new("hash", num_ots), &num_ots, |bencher, _| {
+            let mut crh = MiTCCR::<BATCH_SIZE>::new(start_point);
+            bencher.iter(|| {
+                for batch in blocks.chunks_exact_mut(BATCH_SIZE) {
+                    crh.hash_block::<1, BATCH_SIZE>(batch.try_into().unwrap());
+                }
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("hash_many", num_ots),
+            &num_ots,
+            |bencher, _| {
+                let mut crh = MiTCCR::<BATCH_SIZE>::new(start_point);
+                bencher.iter(|| crh.hash_many(&mut blocks, 1))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_b2a(c: &mut Criterion) {
+    let mut group = c.benchmark_group("b2a");
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let delta = Block::rand(&mut rng);
+    for &gsize in GSIZES {
+        let inputs = (0..gsize)
+            .map(|_| I::rand(&mut rng).bits_le())
+            .collect::<Vec<BitsLE<I>>>();
+        let cots = rand_blocks(&mut rng, gsize * I::NUM_BITS);
+        let us = (0..gsize * I::NUM_BITS)
+            .map(|_| A::rand(&mut rng))
+            .collect::<Vec<_>>();
+        group.throughput(Throughput::Elements(gsize as u64));
+        group.bench_with_input(BenchmarkId::new("sender", gsize), &gsize, |bencher, _| {
+            bencher.iter(|| bit_comp_as_ot_sender_batch::<I, A>(&inputs, delta, &cots).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("receiver", gsize), &gsize, |bencher, _| {
+            bencher.iter(|| bit_comp_as_ot_receiver_batch::<I, A>(&inputs, &cots, &us).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn bench_square_corr(c: &mut Criterion) {
+    let mut group = c.benchmark_group("square_corr");
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut rand_ring = |len: usize| (0..len).map(|_| C::rand(&mut rng)).collect::<Vec<_>>();
+    for &gsize in GSIZES {
+        let corrs = rand_ring(4 * gsize)
+            .chunks(2)
+            .map(|ac| SquareCorrShare([ac[0], ac[1]]))
+            .collect::<Vec<_>>();
+        let (corrs, sacrificed) = corrs.split_at(gsize);
+        let (t, d) = (rand_ring(gsize), rand_ring(gsize));
+        let mut dest: Vec<C> = vec![0; gsize];
+        group.throughput(Throughput::Elements(gsize as u64));
+        group.bench_with_input(
+            BenchmarkId::new("verify_phase_1", gsize),
+            &gsize,
+            |bencher, _| {
+                bencher.iter(|| {
+                    SquareCorrShare::verify_phase_1(corrs, sacrificed, &t, &mut dest).unwrap()
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("verify_phase_2", gsize),
+            &gsize,
+            |bencher, _| {
+                bencher.iter(|| {
+                    SquareCorrShare::verify_phase_2::<ALICE>(corrs, sacrificed, &t, &d, &mut dest)
+                        .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// A few samples, since the large sizes take up to tens of milliseconds per
+/// iteration, or short runs of the smoke sizes.
+fn config() -> Criterion {
+    let config = Criterion::default().sample_size(10);
+    if cfg!(feature = "bench-smoke") {
+        config
+            .warm_up_time(Duration::from_millis(100))
+            .measurement_time(Duration::from_millis(100))
+    } else {
+        config
+    }
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = bench_chi, bench_inner_product, bench_cot_to_rot, bench_mitccr, bench_b2a,
+        bench_square_corr
+}
+criterion_main!(benches);
@@ -1,7 +1,30 @@
 //! This module contains the A2S (Arithmetic Share to Arithmetic Share of
-//! Squares) protocol implementation.
+//! Squares) protocol implementation, and a bound check on the arithmetic
+//! shares that uses square correlations in the same way.
+//!
+//! # Bound check
+//! To show `x <= B` for a public `B`, the client provides arithmetic shares
+//! of the bits `w_j` of `B - x` (`bound_witness`), each with its own square
+//! correlation. The servers open `w_j - a_j` as in the first round of A2S,
+//! compute shares of `w_j^2 - w_j` and of `sum_j 2^j w_j + x - B`, and open
+//! them. All of them are zero for an honest client, so the openings reveal
+//! nothing, and a zero `w^2 - w` in a ring of `2^l` means `w` is 0 or 1. The
+//! check is sound as long as `x` is known to be smaller than `2^(l-1) - B`,
+//! e.g. because it comes from B2A of a smaller ring, and the square
+//! correlations are verified like the ones of A2S.
+//!
+//! The witness bits of a client are shared like its square correlations:
+//! Alice expands her shares from a seed, and Bob gets his explicitly. Their
+//! correlations follow the ones of the inputs, see `num_squared`.
 
-use crate::{square_corr::SquareCorrShare, uint::UInt, ALICE};
+use crate::{
+    block_crypto::prg::Prg,
+    square_corr::{CorrError, SquareCorrShare},
+    uint::UInt,
+    ALICE,
+};
+use rand::Rng;
+use serialize::{AsUseCast, Communicate, UseCast};
 
 /// First round of A2S: open `x-a`
 /// `xb`: arithmetic share of the `x`
@@ -70,15 +93,245 @@ pub fn batch_a2s_second<C: UInt, const PARTY: bool>(
         .collect()
 }
 
+/// Number of witness bits per element to show that it is at most `bound`.
+#[inline]
+pub fn bound_witness_len<T: UInt>(bound: T) -> usize {
+    bound.wsize().max(1)
+}
+
+/// Client side of the bound check: the bits of `bound - x` in little endian,
+/// or `None` if `x > bound`.
+pub fn bound_witness<T: UInt>(x: T, bound: T) -> Option<Vec<T>> {
+    if x > bound {
+        return None;
+    }
+    let w = bound.wrapping_sub(&x);
+    let witness = (0..bound_witness_len(bound))
+        .map(|j| (w >> j) & T::one())
+        .collect();
+    Some(witness)
+}
+
+/// Witness bits of all inputs `xs` in ring `W`, `bound_witness_len` per
+/// input, or `None` if an input is above `bound`.
+pub fn batch_bound_witness<T: UInt, W: UInt>(xs: &[T], bound: T) -> Option<Vec<W>> {
+    let mut witness = Vec::with_capacity(xs.len() * bound_witness_len(bound));
+    for x in xs {
+        witness.extend(
+            bound_witness(*x, bound)?
+                .into_iter()
+                .map(|w| w.as_uint::<W>()),
+        );
+    }
+    Some(witness)
+}
+
+/// Number of values of a client with `gsize` inputs that need a square
+/// correlation: the inputs, and with a `bound`, the witness bits of each.
+/// Each of them also needs one sacrificed to verify it, so a client sends
+/// `2 * num_squared` correlations.
+#[inline]
+pub fn num_squared<T: UInt>(gsize: usize, bound: Option<T>) -> usize {
+    gsize + bound.map_or(0, |bound| gsize * bound_witness_len(bound))
+}
+
+/// Fails unless a message carries `actual` witness shares for `gsize` inputs
+/// and `bound`.
+fn check_witness_len<T: UInt>(actual: u64, gsize: usize, bound: T) -> Result<(), CorrError> {
+    let expected = (gsize * bound_witness_len(bound)) as u64;
+    if actual != expected {
+        return Err(CorrError::WrongWitnessLen { expected, actual });
+    }
+    Ok(())
+}
+
+/// Shares of the witness bits of a client sent to Alice: a seed to expand
+/// them from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WitnessShareSeedToAlice {
+    pub seed: u64,
+    /// number of shares to expand
+    pub expected_len: u64,
+}
+
+impl WitnessShareSeedToAlice {
+    /// Expand with the PRG of this build, see `Prg::DEFAULT`.
+    pub fn expand<T: UInt>(&self) -> Vec<T> {
+        Prg::DEFAULT.expand(self.seed, self.expected_len as usize)
+    }
+
+    /// Expand the shares for `gsize` inputs and `bound`, or return an error if
+    /// the message is not for them.
+    pub fn expand_checked<T: UInt, B: UInt>(
+        &self,
+        gsize: usize,
+        bound: B,
+    ) -> Result<Vec<T>, CorrError> {
+        check_witness_len(self.expected_len, gsize, bound)?;
+        Ok(self.expand())
+    }
+}
+
+impl Communicate for WitnessShareSeedToAlice {
+    type Deserialized = Self;
+
+    fn size_in_bytes(&self) -> usize {
+        self.seed.use_cast().size_in_bytes() * 2
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, mut dest: W) {
+        self.seed.use_cast().to_bytes(&mut dest);
+        self.expected_len.use_cast().to_bytes(dest);
+    }
+
+    fn from_bytes<R: std::io::Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+        let seed = UseCast::<u64>::from_bytes(&mut bytes)?;
+        let expected_len = UseCast::<u64>::from_bytes(bytes)?;
+        Ok(WitnessShareSeedToAlice { seed, expected_len })
+    }
+}
+
+/// Shares of the witness bits of a client sent to Bob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WitnessSharesToBob<T: UInt> {
+    pub w: Vec<T>,
+}
+
+impl<T: UInt> WitnessSharesToBob<T> {
+    /// The shares for `gsize` inputs and `bound`, or an error if the message
+    /// is not for them.
+    pub fn expand_checked<B: UInt>(&self, gsize: usize, bound: B) -> Result<Vec<T>, CorrError> {
+        check_witness_len(self.w.len() as u64, gsize, bound)?;
+        Ok(self.w.clone())
+    }
+}
+
+impl<T: UInt> Communicate for WitnessSharesToBob<T> {
+    type Deserialized = Self;
+
+    fn size_in_bytes(&self) -> usize {
+        self.w.size_in_bytes()
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, dest: W) {
+        self.w.to_bytes(dest);
+    }
+
+    fn from_bytes<R: std::io::Read>(bytes: R) -> serialize::Result<Self::Deserialized> {
+        Ok(WitnessSharesToBob {
+            w: Vec::<T>::from_bytes(bytes)?,
+        })
+    }
+}
+
+/// Share the witness bits `witness` of a client, e.g. from `bound_witness`.
+///
+/// # Returns
+/// The messages to Alice and to Bob, and the shares of Alice.
+pub fn make_witness_shares<T: UInt, R: Rng>(
+    rng: &mut R,
+    witness: &[T],
+) -> (WitnessShareSeedToAlice, WitnessSharesToBob<T>, Vec<T>) {
+    let to_alice = WitnessShareSeedToAlice {
+        seed: rng.next_u64(),
+        expected_len: witness.len() as u64,
+    };
+    let w0 = to_alice.expand::<T>();
+    let w1 = witness
+        .iter()
+        .zip(&w0)
+        .map(|(w, w0)| w.wrapping_sub(w0))
+        .collect();
+    (to_alice, WitnessSharesToBob { w: w1 }, w0)
+}
+
+/// First round of the bound check: open `w - a` for each witness bit. Same as
+/// `batch_a2s_first`.
+/// `wbs`: shares of the witness bits, `bound_witness_len` per element
+/// `corr_bs`: square correlation shares, one per witness bit
+///
+/// # Returns
+/// Batch of `w-a` shares
+#[inline]
+pub fn batch_bound_check_first<T: UInt>(wbs: &[T], corr_bs: &[SquareCorrShare<T>]) -> Vec<T> {
+    batch_a2s_first(wbs, corr_bs)
+}
+
+/// Second round of the bound check.
+/// `es`: opened `w-a` of the first round
+/// `xbs`: arithmetic shares of `x`
+/// `wbs`, `corr_bs`: same as the first round
+/// `bound`: public bound
+///
+/// # Returns
+/// Shares of the values to open, `bound_witness_len(bound) + 1` per element:
+/// `w_j^2 - w_j` for each bit and then `sum_j 2^j w_j + x - B`.
+pub fn batch_bound_check_second<T: UInt, const PARTY: bool>(
+    es: &[T],
+    xbs: &[T],
+    wbs: &[T],
+    corr_bs: &[SquareCorrShare<T>],
+    bound: T,
+) -> Vec<T> {
+    let num_bits = bound_witness_len(bound);
+    assert_eq!(wbs.len(), xbs.len() * num_bits);
+    assert_eq!(es.len(), wbs.len());
+    assert_eq!(corr_bs.len(), wbs.len());
+
+    let mut checks = Vec::with_capacity(xbs.len() * (num_bits + 1));
+    for (((xb, es), wbs), corr_bs) in xbs
+        .iter()
+        .zip(es.chunks(num_bits))
+        .zip(wbs.chunks(num_bits))
+        .zip(corr_bs.chunks(num_bits))
+    {
+        let mut sum = *xb;
+        for (j, ((e, wb), corr_b)) in es.iter().zip(wbs).zip(corr_bs).enumerate() {
+            let wb_squared = a2s_second::<_, PARTY>(*e, *wb, *corr_b);
+            checks.push(wb_squared.wrapping_sub(wb));
+            sum = sum.wrapping_add(&(*wb << j));
+        }
+        if PARTY == ALICE {
+            sum = sum.wrapping_sub(&bound);
+        }
+        checks.push(sum);
+    }
+    checks
+}
+
+/// Open the values of the second round and decide which elements are within
+/// the bound.
+///
+/// # Returns
+/// Whether each element is accepted, i.e. all of its checks open to zero.
+pub fn batch_bound_check_result<T: UInt>(checks_0: &[T], checks_1: &[T], bound: T) -> Vec<bool> {
+    assert_eq!(checks_0.len(), checks_1.len());
+    checks_0
+        .chunks(bound_witness_len(bound) + 1)
+        .zip(checks_1.chunks(bound_witness_len(bound) + 1))
+        .map(|(c0, c1)| {
+            c0.iter()
+                .zip(c1)
+                .all(|(c0, c1)| c0.wrapping_add(c1).is_zero())
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use crate::{
-        a2s::{batch_a2s_first, batch_a2s_second},
-        square_corr::SquareCorr,
+        a2s::{
+            batch_a2s_first, batch_a2s_second, batch_bound_check_first, batch_bound_check_result,
+            batch_bound_check_second, batch_bound_witness, bound_witness, bound_witness_len,
+            make_witness_shares, WitnessShareSeedToAlice, WitnessSharesToBob,
+        },
+        square_corr::{CorrError, SquareCorr},
         uint::UInt,
+        utils::batch_wrapping_add,
         ALICE, BOB,
     };
     use rand::{rngs::StdRng, SeedableRng};
+    use serialize::Communicate;
 
     fn a2s_for_type<T: UInt, CORR: UInt, const GSIZE: usize>() {
         let mut rng = StdRng::seed_from_u64(12345);
@@ -122,6 +375,101 @@ mod test {
         assert_eq!(x_sq_expected, s_sq_actual);
     }
 
+    /// Run the bound check on both servers with the given witness bits.
+    fn run_bound_check(xs: &[u64], witness: &[u64], bound: u64) -> Vec<bool> {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let (x0, x1) = xs
+            .iter()
+            .map(|x| x.arith_shares(&mut rng))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        let (w0, w1) = witness
+            .iter()
+            .map(|w| w.arith_shares(&mut rng))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        let (corr_0, corr_1) = (0..witness.len())
+            .map(|_| SquareCorr::<u128>::rand(&mut rng).to_shares(&mut rng))
+            .map(|(c0, c1)| (c0.cut::<u64>(), c1.cut::<u64>()))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+
+        let e0 = batch_bound_check_first(&w0, &corr_0);
+        let e1 = batch_bound_check_first(&w1, &corr_1);
+        let e = e0
+            .iter()
+            .zip(e1.iter())
+            .map(|(e0, e1)| e0.wrapping_add(*e1))
+            .collect::<Vec<_>>();
+
+        let checks_0 = batch_bound_check_second::<_, { ALICE }>(&e, &x0, &w0, &corr_0, bound);
+        let checks_1 = batch_bound_check_second::<_, { BOB }>(&e, &x1, &w1, &corr_1, bound);
+        batch_bound_check_result(&checks_0, &checks_1, bound)
+    }
+
+    #[test]
+    fn bound_check() {
+        const BOUND: u64 = 100;
+        let xs = [0u64, 1, 37, 99, 100];
+        let witness = xs
+            .iter()
+            .flat_map(|x| bound_witness(*x, BOUND).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(witness.len(), xs.len() * bound_witness_len(BOUND));
+        assert_eq!(run_bound_check(&xs, &witness, BOUND), vec![true; xs.len()]);
+    }
+
+    #[test]
+    fn bound_check_rejects_out_of_range() {
+        const BOUND: u64 = 100;
+        let num_bits = bound_witness_len(BOUND);
+        assert_eq!(bound_witness(101u64, BOUND), None);
+
+        let xs = [37u64, 101, 200];
+        let mut witness = bound_witness(37, BOUND).unwrap();
+        // `B - x` wraps around, so keep the low bits
+        let w = BOUND.wrapping_sub(101);
+        witness.extend((0..num_bits).map(|j| (w >> j) & 1));
+        // put all of `B - x` in the first "bit"
+        witness.push(BOUND.wrapping_sub(200));
+        witness.extend(vec![0; num_bits - 1]);
+
+        assert_eq!(
+            run_bound_check(&xs, &witness, BOUND),
+            vec![true, false, false]
+        );
+    }
+
+    #[test]
+    fn witness_shares() {
+        const BOUND: u8 = 100;
+        let mut rng = StdRng::seed_from_u64(12345);
+        let xs = [0u8, 37, 100];
+        let witness = batch_bound_witness::<_, u128>(&xs, BOUND).unwrap();
+        assert_eq!(batch_bound_witness::<_, u128>(&[101u8], BOUND), None);
+        let (to_alice, to_bob, w0) = make_witness_shares(&mut rng, &witness);
+
+        let to_alice = WitnessShareSeedToAlice::from_bytes_owned(to_alice.into_bytes_owned())
+            .expect("deserialize");
+        let to_bob = WitnessSharesToBob::<u128>::from_bytes_owned(to_bob.into_bytes_owned())
+            .expect("deserialize");
+        assert_eq!(
+            to_alice.expand_checked::<u128, _>(xs.len(), BOUND),
+            Ok(w0.clone())
+        );
+        let w1 = to_bob.expand_checked(xs.len(), BOUND).unwrap();
+        assert_eq!(batch_wrapping_add(&w0, &w1), witness);
+
+        // the length depends on both the number of inputs and the bound
+        assert_eq!(
+            to_bob.expand_checked(xs.len() + 1, BOUND),
+            Err(CorrError::WrongWitnessLen {
+                expected: 28,
+                actual: 21
+            })
+        );
+        assert!(to_alice
+            .expand_checked::<u128, _>(xs.len(), u8::MAX)
+            .is_err());
+    }
+
     #[test]
     fn a2s() {
         a2s_for_type::<u8, u16, 1000>();
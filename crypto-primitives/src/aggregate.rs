@@ -0,0 +1,243 @@
+//! Aggregation of the arithmetic shares of the clients (B2A or A2S output)
+//! into a share of the aggregate.
+//!
+//! Each client may have a public weight, e.g. its number of local samples for
+//! federated averaging. Multiplying a share by a public weight gives a share
+//! of the weighted input, so each server computes its share of the weighted
+//! sum locally, in the share ring. The mean is the reconstructed weighted sum
+//! divided by the total weight, in the clear.
+//!
+//! The sum wraps around silently in the share ring once it is too large, see
+//! `RingBudget`.
+
+use thiserror::Error;
+
+use crate::uint::UInt;
+
+/// The aggregate of a configuration can wrap around in the share ring.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error(
+    "the aggregate of a total weight of {total_weight} over inputs of {input_bits} bits can wrap around in a ring of {ring_bits} bits, which only holds a total weight of {max_total_weight}"
+)]
+pub struct RingOverflow {
+    pub total_weight: u64,
+    pub max_total_weight: u128,
+    pub input_bits: usize,
+    pub ring_bits: usize,
+}
+
+/// How many clients can be aggregated in a share ring without wrapping
+/// around. Each input is below `2^input_bits`, so the sum of a total weight
+/// of `w` is below `w * 2^input_bits`, which fits in `ring_bits` bits if `w
+/// <= 2^(ring_bits - input_bits)`. This is conservative by less than one
+/// input, e.g. inputs of `u8` into `u64` fit for `2^56` clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingBudget {
+    pub input_bits: usize,
+    pub ring_bits: usize,
+}
+
+impl RingBudget {
+    /// Inputs in `I` aggregated in ring `A`.
+    pub fn new<I: UInt, A: UInt>() -> Self {
+        Self {
+            input_bits: I::NUM_BITS,
+            ring_bits: A::NUM_BITS,
+        }
+    }
+
+    /// Inputs of `input_bits` bits aggregated in a ring of `ring_bits` bits,
+    /// e.g. if the input size is only known at runtime.
+    pub fn with_bits(input_bits: usize, ring_bits: usize) -> Self {
+        Self {
+            input_bits,
+            ring_bits,
+        }
+    }
+
+    /// Inputs below `bound`, e.g. the bound of `UInt::to_bounded_encoding`,
+    /// aggregated in ring `A`.
+    pub fn for_bound<T: UInt, A: UInt>(bound: T) -> Self {
+        Self {
+            input_bits: bound.wsize(),
+            ring_bits: A::NUM_BITS,
+        }
+    }
+
+    /// Largest total weight, e.g. number of clients, whose aggregate cannot
+    /// wrap around. 0 if a single input may not fit.
+    pub fn max_total_weight(&self) -> u128 {
+        match self.ring_bits.checked_sub(self.input_bits) {
+            None => 0,
+            Some(shift) if shift >= 128 => u128::MAX,
+            Some(shift) => 1 << shift,
+        }
+    }
+
+    /// The margin, i.e. how much more weight could be aggregated, or
+    /// `RingOverflow` if the aggregate of `total_weight` can wrap around.
+    pub fn check(&self, total_weight: u64) -> Result<u128, RingOverflow> {
+        let max_total_weight = self.max_total_weight();
+        max_total_weight
+            .checked_sub(u128::from(total_weight))
+            .ok_or(RingOverflow {
+                total_weight,
+                max_total_weight,
+                input_bits: self.input_bits,
+                ring_bits: self.ring_bits,
+            })
+    }
+}
+
+/// Element-wise wrapping sum of `shares[i] * weights[i]`. A client of weight 0
+/// is left out. All shares have the same length.
+pub fn weighted_sum_shares<A: UInt>(shares: &[Vec<A>], weights: &[u64]) -> Vec<A> {
+    assert_eq!(shares.len(), weights.len());
+    weighted_sum(shares.iter().zip(weights.iter().copied()))
+}
+
+/// Same as `weighted_sum_shares`, for shares that are not in one slice.
+pub fn weighted_sum<'a, A: UInt + 'a>(
+    weighted_shares: impl IntoIterator<Item = (&'a Vec<A>, u64)>,
+) -> Vec<A> {
+    let mut weighted_shares = weighted_shares.into_iter().peekable();
+    let len = weighted_shares.peek().map_or(0, |(v, _)| v.len());
+    weighted_shares.filter(|(_, weight)| *weight != 0).fold(
+        vec![A::zero(); len],
+        |mut acc, (v, weight)| {
+            assert_eq!(v.len(), len);
+            let weight = weight.as_uint::<A>();
+            acc.iter_mut()
+                .zip(v)
+                .for_each(|(a, b)| *a = a.wrapping_add(&b.wrapping_mul(&weight)));
+            acc
+        },
+    )
+}
+
+/// Sum of the weights, which the reconstructed weighted sum is divided by to
+/// get the mean.
+pub fn total_weight(weights: &[u64]) -> u64 {
+    weights.iter().sum()
+}
+
+/// Element-wise mean from the reconstructed weighted sum. Only meaningful if
+/// the weighted sum did not wrap around in the share ring.
+pub fn mean<A: UInt>(weighted_sum: &[A], total_weight: u64) -> Vec<f64> {
+    weighted_sum
+        .iter()
+        .map(|x| x.to_f64().unwrap() / total_weight as f64)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{mean, total_weight, weighted_sum_shares, RingBudget, RingOverflow};
+    use crate::uint::UInt;
+
+    type A = u64;
+
+    #[test]
+    fn test_weighted_sum_reconstructs() {
+        const NUM_CLIENTS: usize = 20;
+        const GSIZE: usize = 8;
+        let mut rng = StdRng::seed_from_u64(7);
+        let inputs = (0..NUM_CLIENTS)
+            .map(|_| {
+                (0..GSIZE)
+                    .map(|_| u8::rand(&mut rng) as A)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // client 3 has weight 0, so it is left out
+        let weights = (0..NUM_CLIENTS as u64)
+            .map(|i| if i == 3 { 0 } else { i * 11 + 1 })
+            .collect::<Vec<_>>();
+        let (shares_0, shares_1): (Vec<_>, Vec<_>) = inputs
+            .iter()
+            .map(|x| {
+                x.iter()
+                    .map(|x| x.arith_shares(&mut rng))
+                    .unzip::<_, _, Vec<_>, Vec<_>>()
+            })
+            .unzip();
+
+        let sum_0 = weighted_sum_shares(&shares_0, &weights);
+        let sum_1 = weighted_sum_shares(&shares_1, &weights);
+        let reconstructed = sum_0
+            .iter()
+            .zip(&sum_1)
+            .map(|(a, b)| a.wrapping_add(*b))
+            .collect::<Vec<_>>();
+
+        let expected = (0..GSIZE)
+            .map(|j| {
+                inputs
+                    .iter()
+                    .zip(&weights)
+                    .map(|(x, w)| x[j] * w)
+                    .sum::<A>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(reconstructed, expected);
+
+        let total = total_weight(&weights);
+        let means = mean(&reconstructed, total);
+        for (m, e) in means.iter().zip(&expected) {
+            assert!((m - *e as f64 / total as f64).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_weighted_sum_wraps() {
+        let shares = vec![vec![A::MAX, 1], vec![2, 3]];
+        assert_eq!(weighted_sum_shares(&shares, &[2, 1]), vec![0, 5]);
+        assert_eq!(weighted_sum_shares(&shares, &[0, 0]), vec![0, 0]);
+        assert_eq!(weighted_sum_shares::<A>(&[], &[]), Vec::<A>::new());
+    }
+
+    #[test]
+    fn test_ring_budget_boundary() {
+        for (budget, bits) in [
+            (RingBudget::new::<u8, A>(), 56),
+            (RingBudget::new::<u32, A>(), 32),
+        ] {
+            let max = 1u64 << bits;
+            assert_eq!(budget.max_total_weight(), max as u128);
+            assert_eq!(budget.check(max), Ok(0));
+            assert_eq!(budget.check(max - 1), Ok(1));
+            assert_eq!(
+                budget.check(max + 1),
+                Err(RingOverflow {
+                    total_weight: max + 1,
+                    max_total_weight: max as u128,
+                    input_bits: 64 - bits,
+                    ring_bits: 64,
+                })
+            );
+        }
+        // the largest inputs at the boundary still sum without wrapping
+        let max = 1u64 << 56;
+        assert_eq!(
+            (u8::MAX as u128) * (max as u128),
+            (u8::MAX as A).wrapping_mul(max) as u128
+        );
+
+        assert_eq!(RingBudget::new::<u64, A>().max_total_weight(), 1);
+        assert_eq!(RingBudget::new::<u64, u128>().max_total_weight(), 1 << 64);
+        assert_eq!(RingBudget::new::<u64, u32>().max_total_weight(), 0);
+        assert!(RingBudget::new::<u64, u32>().check(0).is_ok());
+        assert!(RingBudget::new::<u64, u32>().check(1).is_err());
+    }
+
+    #[test]
+    fn test_ring_budget_for_bound() {
+        // inputs below 1000 have 10 bits
+        let budget = RingBudget::for_bound::<u32, A>(1000);
+        assert_eq!(budget.input_bits, 10);
+        assert_eq!(budget.max_total_weight(), 1 << 54);
+        assert_eq!(RingBudget::for_bound::<u32, A>(1 << 10).input_bits, 11);
+    }
+}
@@ -5,11 +5,53 @@
 
 use crate::{
     bitmul::{bit_mul_as_ot_receiver, bit_mul_as_ot_sender},
-    bits::BitsLE,
-    cot::rot::{cot_to_rot_receiver_side, cot_to_rot_sender_side},
+    bits::{BitsLE, PackedBits},
+    cot::rot::{RotStream, OT_BSIZE},
+    error::{check_len, ProtocolError, Result},
     uint::UInt,
 };
 use block::Block;
+use zeroize::Zeroizing;
+
+/// Inputs converted at a time by the whole-batch functions, which bounds
+/// their scratch buffers.
+pub const B2A_CHUNK_ELEMS: usize = 1024;
+
+/// Number of messages the servers split the `us` of one client into, so that
+/// the OT sender sends the first chunks while computing the rest.
+pub const B2A_CHUNKS: usize = 8;
+
+/// Fails with `RingTooSmall` if inputs in `I` do not fit in ring `A`, where
+/// the shift of their highest bit would overflow.
+fn check_ring<I: UInt, A: UInt>() -> Result<()> {
+    if A::NUM_BITS < I::NUM_BITS {
+        return Err(ProtocolError::RingTooSmall {
+            needed: I::NUM_BITS,
+            actual: A::NUM_BITS,
+        });
+    }
+    Ok(())
+}
+
+/// Panicking version of `check_ring` for the per-input functions, which would
+/// otherwise underflow computing the shift. Both sizes are constants, so it
+/// costs nothing once monomorphized.
+#[inline(always)]
+#[track_caller]
+fn assert_ring_fits<I: UInt, A: UInt>() {
+    assert!(
+        A::NUM_BITS >= I::NUM_BITS,
+        "ring of {} bits cannot hold inputs of {} bits",
+        A::NUM_BITS,
+        I::NUM_BITS
+    );
+}
+
+/// Number of inputs in each of the `B2A_CHUNKS` chunks of a client with
+/// `gsize` inputs. The last chunks may be shorter, or empty.
+pub fn b2a_chunk_elems(gsize: usize) -> usize {
+    gsize.div_ceil(B2A_CHUNKS).max(1)
+}
 
 /// `bit_comp_as_ot_sender_single` converts boolean share of one number into
 /// arithmetic share. `B` is boolean share of input ring bounded by L_infinity,
@@ -21,12 +63,15 @@ use block::Block;
 ///
 /// returns:
 /// * `y0s` in ring `A` such that `y0s + y1s = x0s ^ x1s`
+///
+/// Panics if `A` is smaller than `B`, see `check_ring`.
 pub fn bit_comp_as_ot_sender_single<B: UInt, A: UInt>(
     x0s: BitsLE<B>,
     v0s: &[A],
     v1s: &[A],
     us_dest: &mut [A],
 ) -> A {
+    assert_ring_fits::<B, A>();
     debug_assert_eq!(x0s.len(), B::NUM_BITS);
     debug_assert_eq!(v0s.len(), B::NUM_BITS);
     debug_assert_eq!(v1s.len(), B::NUM_BITS);
@@ -61,7 +106,10 @@ pub fn bit_comp_as_ot_sender_single<B: UInt, A: UInt>(
 ///
 /// returns:
 /// * `y1s` such that `y0s + y1s = x0s ^ x1s`
+///
+/// Panics if `A` is smaller than `B`, see `check_ring`.
 pub fn bit_comp_as_ot_receiver_single<B: UInt, A: UInt>(x1s: BitsLE<B>, vs: &[A], us: &[A]) -> A {
+    assert_ring_fits::<B, A>();
     debug_assert_eq!(x1s.len(), B::NUM_BITS);
     debug_assert_eq!(vs.len(), B::NUM_BITS);
     debug_assert_eq!(us.len(), B::NUM_BITS);
@@ -96,30 +144,70 @@ pub fn bit_comp_as_ot_receiver_single<B: UInt, A: UInt>(x1s: BitsLE<B>, vs: &[A]
 /// * `us`: `Vec<A>` of length `N * B::NUM_BITS` that will be sent to OT
 ///   receiver
 ///
-/// # Panics
-/// Panics if length requirements are not met.
+/// Fails with `LengthMismatch` if length requirements are not met, and with
+/// `RingTooSmall` if `A` is smaller than `I`.
 pub fn bit_comp_as_ot_sender_batch<I: UInt, A: UInt>(
     inputs_0: &[BitsLE<I>],
     delta: Block,
     qs: &[Block],
-) -> (Vec<A>, Vec<A>) {
+) -> Result<(Vec<A>, Vec<A>)> {
     let n = inputs_0.len();
+    let mut y0s = Vec::with_capacity(n);
+    let mut us = Vec::with_capacity(n * I::NUM_BITS);
+    bit_comp_as_ot_sender_batch_chunked(inputs_0, delta, qs, B2A_CHUNK_ELEMS, |y0, u| {
+        y0s.extend_from_slice(y0);
+        us.extend_from_slice(u);
+    })?;
+    Ok((y0s, us))
+}
 
-    assert_eq!(qs.len(), n * I::NUM_BITS);
-
-    // convert COT to ROT
-    let (v0s, v1s) = cot_to_rot_sender_side(qs, delta);
-
-    let mut us_dest = vec![A::zero(); n * I::NUM_BITS];
+/// Same as `bit_comp_as_ot_sender_batch`, converting `chunk_elems` inputs at a
+/// time instead of expanding all ROTs at once. `sink` gets the `y0s` and the
+/// `us` of each chunk in order, e.g. to send the `us` to the OT receiver
+/// while the next chunk is computed. Scratch buffers are reused, so memory
+/// is bounded by the chunk size.
+///
+/// Fails as `bit_comp_as_ot_sender_batch`, or with `EmptyInput` if
+/// `chunk_elems` is 0, before `sink` is called.
+pub fn bit_comp_as_ot_sender_batch_chunked<I: UInt, A: UInt>(
+    inputs_0: &[BitsLE<I>],
+    delta: Block,
+    qs: &[Block],
+    chunk_elems: usize,
+    mut sink: impl FnMut(&[A], &[A]),
+) -> Result<()> {
+    check_ring::<I, A>()?;
+    check_len("qs", inputs_0.len() * I::NUM_BITS, qs.len())?;
+    if chunk_elems == 0 {
+        return Err(ProtocolError::EmptyInput { what: "chunks" });
+    }
 
-    let y0s = inputs_0
-        .iter()
-        .zip(v0s.chunks(I::NUM_BITS))
-        .zip(v1s.chunks(I::NUM_BITS))
-        .zip(us_dest.chunks_mut(I::NUM_BITS))
-        .map(|(((x0s, v0s), v1s), u_dest)| bit_comp_as_ot_sender_single(*x0s, v0s, v1s, u_dest))
-        .collect();
-    (y0s, us_dest)
+    let mut rot = RotStream::new();
+    let (mut v0s, mut v1s) = (Vec::new(), Vec::new());
+    let mut y0s = Vec::with_capacity(chunk_elems);
+    let mut us = vec![A::zero(); chunk_elems * I::NUM_BITS];
+
+    for (inputs_0, qs) in inputs_0
+        .chunks(chunk_elems)
+        .zip(qs.chunks(chunk_elems * I::NUM_BITS))
+    {
+        // convert COT to ROT
+        rot.sender_side_into(qs, delta, &mut v0s, &mut v1s);
+        let us = &mut us[..qs.len()];
+        y0s.clear();
+        y0s.extend(
+            inputs_0
+                .iter()
+                .zip(v0s.chunks(I::NUM_BITS))
+                .zip(v1s.chunks(I::NUM_BITS))
+                .zip(us.chunks_mut(I::NUM_BITS))
+                .map(|(((x0s, v0s), v1s), u_dest)| {
+                    bit_comp_as_ot_sender_single(*x0s, v0s, v1s, u_dest)
+                }),
+        );
+        sink(&y0s, us);
+    }
+    Ok(())
 }
 
 /// `bit_comp_as_ot_receiver_batch` converts boolean share of `N` numbers into
@@ -132,38 +220,133 @@ pub fn bit_comp_as_ot_sender_batch<I: UInt, A: UInt>(
 ///
 /// Returns `Vec<T>` of length `N` such that `y0s + y1s = x0s ^ x1s`
 ///
-/// # Panics
-/// Panics if length requirements are not met.
+/// Fails with `LengthMismatch` if length requirements are not met, and with
+/// `RingTooSmall` if `A` is smaller than `B`.
 pub fn bit_comp_as_ot_receiver_batch<B: UInt, A: UInt>(
     inputs_1: &[BitsLE<B>],
     ts: &[Block],
     us: &[A],
-) -> Vec<A> {
+) -> Result<Vec<A>> {
+    check_len("us", inputs_1.len() * B::NUM_BITS, us.len())?;
+    bit_comp_as_ot_receiver_batch_chunked(inputs_1, ts, us.chunks(B2A_CHUNK_ELEMS * B::NUM_BITS))
+}
+
+/// Same as `bit_comp_as_ot_receiver_batch`, consuming the `us` in chunks, e.g.
+/// as they arrive from the OT sender. Each chunk covers whole inputs, and
+/// chunks may have different sizes. The ROTs are expanded one chunk at a
+/// time into a reused scratch buffer.
+///
+/// Fails as `bit_comp_as_ot_receiver_batch`, or with `SplitInput` if a chunk
+/// splits an input.
+pub fn bit_comp_as_ot_receiver_batch_chunked<B: UInt, A: UInt, U: AsRef<[A]>>(
+    inputs_1: &[BitsLE<B>],
+    ts: &[Block],
+    us_chunks: impl IntoIterator<Item = U>,
+) -> Result<Vec<A>> {
+    check_ring::<B, A>()?;
     let n = inputs_1.len();
+    check_len("ts", n * B::NUM_BITS, ts.len())?;
+
+    let mut rot = RotStream::new();
+    let mut vs = Vec::new();
+    let mut y1s = Vec::with_capacity(n);
+    for us in us_chunks {
+        let us = us.as_ref();
+        if us.len() % B::NUM_BITS != 0 {
+            return Err(ProtocolError::SplitInput {
+                what: "chunk of us",
+                num_bits: B::NUM_BITS,
+            });
+        }
+        let (done, len) = (y1s.len(), us.len() / B::NUM_BITS);
+        if done + len > n {
+            return Err(ProtocolError::LengthMismatch {
+                expected: n * B::NUM_BITS,
+                actual: (done + len) * B::NUM_BITS,
+                what: "us",
+            });
+        }
+        // convert COT to ROT
+        rot.receiver_side_into(&ts[done * B::NUM_BITS..(done + len) * B::NUM_BITS], &mut vs);
+        y1s.extend(
+            inputs_1[done..done + len]
+                .iter()
+                .zip(vs.chunks(B::NUM_BITS))
+                .zip(us.chunks(B::NUM_BITS))
+                .map(|((x1s, vs), u)| bit_comp_as_ot_receiver_single(*x1s, vs, u)),
+        );
+    }
+    check_len("us", n * B::NUM_BITS, y1s.len() * B::NUM_BITS)?;
+    Ok(y1s)
+}
 
-    assert_eq!(ts.len(), n * B::NUM_BITS);
-    assert_eq!(us.len(), n * B::NUM_BITS);
+/// `cots` padded with zero blocks to a multiple of `OT_BSIZE`, as
+/// `RotStream` needs. The ROTs of the padding are dropped.
+fn pad_to_ot_bsize(cots: &[Block]) -> Zeroizing<Vec<Block>> {
+    let mut padded = Zeroizing::new(cots.to_vec());
+    padded.resize(cots.len().next_multiple_of(OT_BSIZE), Block::default());
+    padded
+}
 
-    // convert COT to ROT
-    let vs = cot_to_rot_receiver_side(ts);
+/// B2A of single bits into ring `A`, as OT sender: one `bit_mul` per bit
+/// instead of the per-bit loop of `bit_comp_as_ot_sender_single`. As `x0 ^
+/// x1 = x0 + x1 - 2 * x0 * x1`, my share of each bit is `x0 - 2 * y0`, where
+/// `y0 + y1 = x0 * x1`.
+/// * `x0s`: my boolean shares
+/// * `qs`: one COT per bit, where the OT receiver chose its share
+///
+/// Returns my arithmetic shares, and the `us` to send to the OT receiver.
+///
+/// Fails with `LengthMismatch` if there is not one COT per bit.
+pub fn bits_to_arith_as_ot_sender<A: UInt>(
+    x0s: &PackedBits,
+    delta: Block,
+    qs: &[Block],
+) -> Result<(Vec<A>, Vec<A>)> {
+    check_len("qs", x0s.len(), qs.len())?;
+    let (mut v0s, mut v1s) = (Vec::<A>::new(), Vec::new());
+    RotStream::new().sender_side_into(&pad_to_ot_bsize(qs), delta, &mut v0s, &mut v1s);
+    Ok(x0s
+        .iter()
+        .zip(v0s.into_iter().zip(v1s))
+        .map(|(x0, (v0, v1))| {
+            let (y0, u) = bit_mul_as_ot_sender(A::NUM_BITS, x0, v0, v1);
+            (A::from_bool(x0).wrapping_sub(&y0.wrapping_add(&y0)), u)
+        })
+        .unzip())
+}
 
-    inputs_1
+/// Same as `bits_to_arith_as_ot_sender`, as OT receiver with shares `x1s`,
+/// which are the choices of `ts`. `us` come from the OT sender.
+///
+/// Fails with `LengthMismatch` if there is not one COT and one `u` per bit.
+pub fn bits_to_arith_as_ot_receiver<A: UInt>(
+    x1s: &PackedBits,
+    ts: &[Block],
+    us: &[A],
+) -> Result<Vec<A>> {
+    check_len("ts", x1s.len(), ts.len())?;
+    check_len("us", x1s.len(), us.len())?;
+    let mut vs = Vec::new();
+    RotStream::new().receiver_side_into(&pad_to_ot_bsize(ts), &mut vs);
+    Ok(x1s
         .iter()
-        .zip(vs.chunks(B::NUM_BITS))
-        .zip(us.chunks(B::NUM_BITS))
-        .map(|((x1s, vs), u)| bit_comp_as_ot_receiver_single(*x1s, vs, u))
-        .collect()
+        .zip(vs.into_iter().zip(us))
+        .map(|(x1, (v, u))| {
+            let y1 = bit_mul_as_ot_receiver(A::NUM_BITS, x1, v, *u);
+            A::from_bool(x1).wrapping_sub(&y1.wrapping_add(&y1))
+        })
+        .collect())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{
-        bits::PackedBits,
-        cot::{
-            client::{num_additional_ot_needed, COTGen},
-            server::{sample_chi, OTReceiver, OTSender},
-        },
+    use crate::cot::{
+        client::{num_additional_ot_needed, COTGen, DEFAULT_SEC_PARAM},
+        rot::{cot_to_rot_receiver_side, cot_to_rot_sender_side},
+        server::{sample_chi, OTReceiver, OTSender},
+        B2A_QS_PURPOSE,
     };
     use rand::{rngs::StdRng, SeedableRng};
     use serialize::{AsUseCast, Communicate};
@@ -233,7 +416,7 @@ mod tests {
 
         let delta = COTGen::sample_delta(&mut rng);
         let delta = serialize_and_deserialize(delta.use_cast());
-        let num_additional = num_additional_ot_needed(num_bits);
+        let num_additional = num_additional_ot_needed(num_bits, DEFAULT_SEC_PARAM);
         let (msg_to_sender, msg_to_receiver) =
             COTGen::sample_cots(&mut rng, &inputs_1, delta, num_additional);
 
@@ -244,11 +427,12 @@ mod tests {
         let chi = sample_chi(num_bits + num_additional, 99999);
         // OT receiver send
         let (x_til, t_til) = OTReceiver::send_x_til_t_til(
-            &msg_to_receiver.ts,
+            msg_to_receiver.ts(),
             &chi,
             &inputs_1,
             msg_to_receiver.r_seed,
-        );
+        )
+        .unwrap();
 
         // OT sender receive
         let qs = {
@@ -266,11 +450,13 @@ mod tests {
 
         // second round: B2A
         // OT sender send
-        let (y0s, us) = { bit_comp_as_ot_sender_batch::<_, A>(&inputs_0, delta, &qs[..num_bits]) };
+        let (y0s, us) =
+            { bit_comp_as_ot_sender_batch::<_, A>(&inputs_0, delta, &qs[..num_bits]).unwrap() };
         // OT receiver receive
         let y1s = {
             let us = serialize_and_deserialize(us);
-            bit_comp_as_ot_receiver_batch(&inputs_1, &msg_to_receiver.ts[..num_bits], &us)
+            bit_comp_as_ot_receiver_batch(&inputs_1, &msg_to_receiver.ts()[..num_bits], &us)
+                .unwrap()
         };
 
         // y = y0 + y1
@@ -290,5 +476,233 @@ mod tests {
         b2a_end_to_end_template::<u32, u64>();
         b2a_end_to_end_template::<u8, u32>();
         b2a_end_to_end_template::<u8, u64>();
+        b2a_end_to_end_template::<u64, u64>();
+        b2a_end_to_end_template::<u64, u128>();
+    }
+
+    #[test]
+    fn test_bits_to_arith() {
+        const NUM_BITS: usize = 100;
+        let mut rng = StdRng::seed_from_u64(12346);
+        let xs = PackedBits::rand(&mut rng, NUM_BITS);
+        let (x0s, x1s) = xs.to_boolean_shares(&mut rng);
+        let delta = COTGen::sample_delta(&mut rng);
+        let (to_alice, to_bob) = COTGen::sample_cots_using_selected_bits_for(
+            &mut rng,
+            0,
+            x1s.iter(),
+            NUM_BITS,
+            delta,
+            0,
+        );
+        let qs = to_alice
+            .qs_seed
+            .for_client(0, B2A_QS_PURPOSE)
+            .expand(NUM_BITS);
+
+        let (y0s, us) = bits_to_arith_as_ot_sender::<u32>(&x0s, delta, &qs).unwrap();
+        let y1s = bits_to_arith_as_ot_receiver::<u32>(&x1s, to_bob.ts(), &us).unwrap();
+        let ys = y0s
+            .iter()
+            .zip(&y1s)
+            .map(|(y0, y1)| y0.wrapping_add(*y1))
+            .collect::<Vec<_>>();
+        assert_eq!(ys, xs.iter().map(u32::from).collect::<Vec<_>>());
+
+        assert!(matches!(
+            bits_to_arith_as_ot_receiver::<u32>(&x1s, to_bob.ts(), &us[1..]),
+            Err(ProtocolError::LengthMismatch { what: "us", .. })
+        ));
+    }
+
+    #[test]
+    fn test_chunked_matches_whole_batch() {
+        type I = u32;
+        type A = u64;
+        const GSIZE: usize = 2500;
+        let mut rng = StdRng::seed_from_u64(54321);
+        let inputs = (0..GSIZE).map(|_| I::rand(&mut rng)).collect::<Vec<_>>();
+        let (inputs_0, inputs_1) = inputs
+            .iter()
+            .map(|x| x.bits_le().to_boolean_shares(&mut rng))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        let delta = COTGen::sample_delta(&mut rng);
+        let qs = (0..GSIZE * I::NUM_BITS)
+            .map(|_| Block::rand(&mut rng))
+            .collect::<Vec<_>>();
+        let ts = qs
+            .iter()
+            .zip(inputs_1.iter().flat_map(|x1s| x1s.iter()))
+            .map(|(q, c)| if c { q.add_gf(delta) } else { *q })
+            .collect::<Vec<_>>();
+
+        let (y0s, us) = bit_comp_as_ot_sender_batch::<I, A>(&inputs_0, delta, &qs).unwrap();
+        let y1s = bit_comp_as_ot_receiver_batch(&inputs_1, &ts, &us).unwrap();
+        let ys = y0s.iter().zip(&y1s).map(|(y0, y1)| y0.wrapping_add(*y1));
+        assert!(ys.eq(inputs.iter().map(|x| x.as_uint::<A>())));
+
+        // smaller than, not dividing, equal to, and larger than the inputs
+        for chunk_elems in [1, 7, B2A_CHUNK_ELEMS, GSIZE, GSIZE + 1] {
+            let mut chunks = Vec::new();
+            let (mut chunked_y0s, mut chunked_us) = (Vec::new(), Vec::new());
+            bit_comp_as_ot_sender_batch_chunked::<I, A>(
+                &inputs_0,
+                delta,
+                &qs,
+                chunk_elems,
+                |y0, u| {
+                    assert_eq!(u.len(), y0.len() * I::NUM_BITS);
+                    assert!(y0.len() <= chunk_elems);
+                    chunked_y0s.extend_from_slice(y0);
+                    chunked_us.extend_from_slice(u);
+                    chunks.push(u.to_vec());
+                },
+            )
+            .unwrap();
+            assert_eq!(chunks.len(), GSIZE.div_ceil(chunk_elems));
+            assert_eq!(chunked_y0s, y0s, "chunk_elems = {}", chunk_elems);
+            assert_eq!(chunked_us, us, "chunk_elems = {}", chunk_elems);
+            let chunked_y1s =
+                bit_comp_as_ot_receiver_batch_chunked::<I, A, _>(&inputs_1, &ts, &chunks).unwrap();
+            assert_eq!(chunked_y1s, y1s, "chunk_elems = {}", chunk_elems);
+        }
+    }
+
+    /// Boolean shares of random inputs, and matching COTs.
+    struct RandomCots {
+        inputs_0: Vec<BitsLE<u32>>,
+        inputs_1: Vec<BitsLE<u32>>,
+        delta: Block,
+        qs: Vec<Block>,
+        ts: Vec<Block>,
+    }
+
+    fn random_cots(gsize: usize) -> RandomCots {
+        let mut rng = StdRng::seed_from_u64(777);
+        let (inputs_0, inputs_1) = (0..gsize)
+            .map(|_| u32::rand(&mut rng).bits_le().to_boolean_shares(&mut rng))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        let delta = COTGen::sample_delta(&mut rng);
+        let qs = (0..gsize * 32)
+            .map(|_| Block::rand(&mut rng))
+            .collect::<Vec<_>>();
+        let ts = qs
+            .iter()
+            .zip(inputs_1.iter().flat_map(|x1s| x1s.iter()))
+            .map(|(q, c)| if c { q.add_gf(delta) } else { *q })
+            .collect::<Vec<_>>();
+        RandomCots {
+            inputs_0,
+            inputs_1,
+            delta,
+            qs,
+            ts,
+        }
+    }
+
+    #[test]
+    fn test_sender_batch_errors() {
+        let RandomCots {
+            inputs_0,
+            delta,
+            qs,
+            ..
+        } = random_cots(4);
+        assert_eq!(
+            bit_comp_as_ot_sender_batch::<u32, u64>(&inputs_0, delta, &qs[1..]).unwrap_err(),
+            ProtocolError::LengthMismatch {
+                expected: 128,
+                actual: 127,
+                what: "qs"
+            }
+        );
+        assert_eq!(
+            bit_comp_as_ot_sender_batch::<u32, u16>(&inputs_0, delta, &qs).unwrap_err(),
+            ProtocolError::RingTooSmall {
+                needed: 32,
+                actual: 16
+            }
+        );
+        let mut called = false;
+        let empty =
+            bit_comp_as_ot_sender_batch_chunked::<u32, u64>(&inputs_0, delta, &qs, 0, |_, _| {
+                called = true
+            });
+        assert_eq!(
+            empty.unwrap_err(),
+            ProtocolError::EmptyInput { what: "chunks" }
+        );
+        assert!(!called);
+    }
+
+    #[test]
+    #[should_panic(expected = "ring of 32 bits cannot hold inputs of 64 bits")]
+    fn test_single_ring_too_small() {
+        let (vs, us) = (vec![0u32; 64], vec![0u32; 64]);
+        bit_comp_as_ot_receiver_single(u64::MAX.bits_le(), &vs, &us);
+    }
+
+    #[test]
+    fn test_receiver_batch_errors() {
+        let RandomCots {
+            inputs_0,
+            inputs_1,
+            delta,
+            qs,
+            ts,
+        } = random_cots(4);
+        let (_, us) = bit_comp_as_ot_sender_batch::<u32, u64>(&inputs_0, delta, &qs).unwrap();
+        assert_eq!(
+            bit_comp_as_ot_receiver_batch(&inputs_1, &ts, &us[..100]).unwrap_err(),
+            ProtocolError::LengthMismatch {
+                expected: 128,
+                actual: 100,
+                what: "us"
+            }
+        );
+        assert_eq!(
+            bit_comp_as_ot_receiver_batch(&inputs_1, &ts[..64], &us).unwrap_err(),
+            ProtocolError::LengthMismatch {
+                expected: 128,
+                actual: 64,
+                what: "ts"
+            }
+        );
+        let us_16 = vec![0u16; 128];
+        assert_eq!(
+            bit_comp_as_ot_receiver_batch(&inputs_1, &ts, &us_16).unwrap_err(),
+            ProtocolError::RingTooSmall {
+                needed: 32,
+                actual: 16
+            }
+        );
+
+        let chunked = |chunks: &[&[u64]]| {
+            bit_comp_as_ot_receiver_batch_chunked::<u32, u64, _>(&inputs_1, &ts, chunks)
+        };
+        assert_eq!(
+            chunked(&[&us[..40], &us[40..]]).unwrap_err(),
+            ProtocolError::SplitInput {
+                what: "chunk of us",
+                num_bits: 32
+            }
+        );
+        assert_eq!(
+            chunked(&[&us, &us[..32]]).unwrap_err(),
+            ProtocolError::LengthMismatch {
+                expected: 128,
+                actual: 160,
+                what: "us"
+            }
+        );
+        assert_eq!(
+            chunked(&[&us[..64]]).unwrap_err(),
+            ProtocolError::LengthMismatch {
+                expected: 128,
+                actual: 64,
+                what: "us"
+            }
+        );
+        assert!(chunked(&[&us[..64], &us[64..]]).is_ok());
     }
 }
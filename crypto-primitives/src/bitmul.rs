@@ -11,10 +11,13 @@
 //! * Run `one_hot_filter` on dummy value `s`
 //! * Run `decode` on dummy value `y` and `s`
 //! * Run B2A MPC and dummy input shares (gsize / 2 * wsize) `wsize = 32`
+//!
+//! `bounded` runs the one-hot filter and the decoding on real `y` and `s`
+//! of the clients.
 
-use crate::{uint::UInt};
-
-
+#[cfg(feature = "simulation-helpers")]
+use crate::simulation::SimulationOnly;
+use crate::uint::UInt;
 
 /// `bit_mul` returns arithmetic share or `x0 & x1`.
 /// * `j`: ring size to operate on (2^j)
@@ -27,8 +30,13 @@ use crate::{uint::UInt};
 /// * `u`, such that `u = v0 + v1 + x0 mod 2^j`
 pub fn bit_mul_as_ot_sender<T: UInt>(j: usize, x0: bool, v0: T, v1: T) -> (T, T) {
     // treat `x0` as a wrapped u32
-    let x0 = T::from_bool(x0);
+    scalar_mul_as_ot_sender(j, T::from_bool(x0), v0, v1)
+}
 
+/// Same as `bit_mul_as_ot_sender` with `x0` in the ring instead of a bit, so
+/// that `y0 + y1 mod 2^j = x0 * x1` for the bit `x1` of the receiver. The
+/// receiver runs `bit_mul_as_ot_receiver` unchanged.
+pub fn scalar_mul_as_ot_sender<T: UInt>(j: usize, x0: T, v0: T, v1: T) -> (T, T) {
     let y0 = v0.wrapping_neg().modulo_2_power(j);
     let u = v0.wrapping_add(&v1).wrapping_add(&x0).modulo_2_power(j);
 
@@ -170,21 +178,65 @@ impl<'a, T: UInt> AndGate for AndGateUsingOTReceiver<'a, T> {
     }
 }
 
-// /// Simulation AND gate for OT receiver, for clients to generate selected bits.
-// pub struct SimulationAndGateForSelectedBits<'a, T: UInt> {
-//     v0s: &'a [T],
-//     v1s: &'a [T],
-//     pos: usize,
-// }
+/// AND gate of the OT receiver for the client that deals the COTs of the
+/// gates: the choices of the OTs of a gate are the shares of the receiver of
+/// its inputs, which depend on the gates before it. Evaluated after
+/// `AndGateUsingOTSender` on the same ROTs, it picks each choice, records
+/// it, and computes the share of the receiver, so that the client can send
+/// `t = q + choice * delta` to the receiver.
+pub struct AndGateChoosingOTs<'a, T: UInt> {
+    v0s: &'a [T],
+    v1s: &'a [T],
+    us: &'a [bool],
+    choices: Vec<bool>,
+}
+
+impl<'a, T: UInt> AndGateChoosingOTs<'a, T> {
+    pub fn new(v0s: &'a [T], v1s: &'a [T], us: &'a [bool]) -> Self {
+        AndGateChoosingOTs {
+            v0s,
+            v1s,
+            us,
+            choices: Vec::new(),
+        }
+    }
+
+    /// The choices of the OTs, in the order of `v0s`.
+    #[must_use]
+    pub fn done_and_get_choices(self) -> Vec<bool> {
+        self.choices
+    }
+
+    fn bit_mul(&mut self, choice: bool) -> bool {
+        let pos = self.choices.len();
+        let v_selected = if choice { self.v1s[pos] } else { self.v0s[pos] };
+        self.choices.push(choice);
+        bit_mul_bool_as_ot_receiver(choice, v_selected, self.us[pos])
+    }
+}
+
+impl<'a, T: UInt> AndGate for AndGateChoosingOTs<'a, T> {
+    fn and(&mut self, x1: bool, y1: bool) -> bool {
+        // same order as `AndGateUsingOTReceiver`
+        let x0y11 = self.bit_mul(y1);
+        let y0x11 = self.bit_mul(x1);
+        (x1 & y1) ^ x0y11 ^ y0x11
+    }
+}
 
 /// A dummy AND gate of boolean shares, which is incorrect, but useful for
 /// profiling.
-pub struct DummyAndGate;
-
-impl DummyAndGate {
+#[cfg(feature = "simulation-helpers")]
+pub struct DummyAndGate(SimulationOnly);
 
+#[cfg(feature = "simulation-helpers")]
+impl Default for DummyAndGate {
+    fn default() -> Self {
+        DummyAndGate(SimulationOnly::mark())
+    }
 }
 
+#[cfg(feature = "simulation-helpers")]
 impl AndGate for DummyAndGate {
     fn and(&mut self, _x: bool, _y: bool) -> bool {
         false
@@ -202,6 +254,12 @@ pub struct LocalAndGateForBob {
     pos: usize,
 }
 
+impl Default for LocalAndGateForAlice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LocalAndGateForAlice {
     pub fn new() -> Self {
         LocalAndGateForAlice {
@@ -238,13 +296,13 @@ impl AndGate for LocalAndGateForBob {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bits::PackedBits;
     use crate::cot::{
         client::COTGen,
         rot::{cot_to_rot_receiver_side, cot_to_rot_sender_side},
     };
     use itertools::Itertools;
     use rand::{rngs::StdRng, SeedableRng};
-    use crate::bits::PackedBits;
 
     #[test]
     fn test_local_and_gate() {
@@ -287,7 +345,7 @@ mod tests {
             COTGen::sample_cots_using_selected_bits(&mut rng, x1s.iter(), x1s.len(), delta, 128);
         let (v0s, v1s) =
             cot_to_rot_sender_side::<u32>(&client_sender_msg.qs_seed.expand(x1s.len()), delta);
-        let v_selected = cot_to_rot_receiver_side::<u32>(&client_receiver_msg.ts);
+        let v_selected = cot_to_rot_receiver_side::<u32>(client_receiver_msg.ts());
 
         let (x0x10s, us) = x0s
             .iter()
@@ -301,7 +359,8 @@ mod tests {
             .iter()
             .zip(v_selected)
             .zip(us)
-            .map(|((x1, v), u)| bit_mul_bool_as_ot_receiver(x1, v, u)).collect::<PackedBits>();
+            .map(|((x1, v), u)| bit_mul_bool_as_ot_receiver(x1, v, u))
+            .collect::<PackedBits>();
 
         let x0x1_expected = &x0s & &x1s;
         let x0x1_actual = &x0x10s ^ &x0x11s;
@@ -344,8 +403,8 @@ mod tests {
         let us = alice.done_and_get_us();
 
         // bob
-        let cot_selected = client_receiver_msg.ts;
-        let v_selected = cot_to_rot_receiver_side::<u32>(&cot_selected);
+        let cot_selected = client_receiver_msg.ts();
+        let v_selected = cot_to_rot_receiver_side::<u32>(cot_selected);
         let mut bob = AndGateUsingOTReceiver::new(&v_selected, &us);
         let xy1 = x1s
             .iter()
@@ -1,19 +1,30 @@
-use crate::uint::UInt;
+use crate::{block_crypto::prg::Prg, uint::UInt};
 use bytemuck::{Pod, Zeroable};
-use rand::{Rng, SeedableRng};
-use rand_chacha::ChaCha12Rng;
+use rand::Rng;
+#[cfg(target_endian = "big")]
+use serialize::util::LePod;
+use serialize::{AsUseCast, Communicate, UseCast};
+use sha2::{Digest, Sha256};
 use std::{
     borrow::Borrow,
     fmt::{Debug, Display, Formatter},
+    io::{self, Read, Write},
     iter::FromIterator,
-    ops::{BitAnd, BitXor, Not},
+    ops::{BitAnd, BitXor, Not, Range},
 };
+use zeroize::Zeroize;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 #[repr(transparent)]
 /// Individual bits of a `UInt` in little endian order.
 pub struct BitsLE<T: UInt>(pub T);
 
+impl<T: UInt> Zeroize for BitsLE<T> {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 impl<T: UInt> Display for BitsLE<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:x}", self.0)
@@ -30,6 +41,13 @@ unsafe impl<T: UInt> Zeroable for BitsLE<T> {}
 
 unsafe impl<T: UInt> Pod for BitsLE<T> {}
 
+#[cfg(target_endian = "big")]
+impl<T: UInt> LePod for BitsLE<T> {
+    fn swap_le(&mut self) {
+        self.0.swap_le()
+    }
+}
+
 impl<T: UInt> BitsLE<T> {
     /// Get bit at index `i` in little endian order.
     ///
@@ -102,16 +120,136 @@ impl<T: UInt> BitsLE<T> {
 #[repr(transparent)]
 pub struct SeededInputShare(pub u64);
 
+impl Zeroize for SeededInputShare {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 impl SeededInputShare {
+    /// Expand with the PRG of this build, see `Prg::DEFAULT`.
     pub fn expand<T: UInt>(self, size: usize) -> Vec<BitsLE<T>> {
-        let mut rng = ChaCha12Rng::seed_from_u64(self.0);
-        (0..size).map(|_| BitsLE(T::rand(&mut rng))).collect()
+        self.expand_with(Prg::DEFAULT, size)
+    }
+
+    /// Expand with AES in counter mode, whatever the PRG of this build.
+    pub fn expand_aes<T: UInt>(self, size: usize) -> Vec<BitsLE<T>> {
+        self.expand_with(Prg::Aes, size)
+    }
+
+    pub fn expand_with<T: UInt>(self, prg: Prg, size: usize) -> Vec<BitsLE<T>> {
+        prg.expand::<T>(self.0, size)
+            .into_iter()
+            .map(BitsLE)
+            .collect()
     }
 }
 
 unsafe impl Pod for SeededInputShare {}
 unsafe impl Zeroable for SeededInputShare {}
 
+#[cfg(target_endian = "big")]
+impl LePod for SeededInputShare {
+    fn swap_le(&mut self) {
+        self.0.swap_le()
+    }
+}
+
+/// Random salt of the hash of `inputs_1` in a `ShareCommitment`. Only the
+/// server that gets `inputs_1` gets it, so the hash it shows its peer does not
+/// let the peer, which knows `inputs_0`, test guesses of the inputs.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct ShareSalt(pub [u8; 16]);
+
+impl ShareSalt {
+    pub fn sample<R: Rng>(rng: &mut R) -> Self {
+        ShareSalt(rng.gen())
+    }
+}
+
+unsafe impl Pod for ShareSalt {}
+unsafe impl Zeroable for ShareSalt {}
+
+/// Random bytes have no byte order.
+#[cfg(target_endian = "big")]
+impl LePod for ShareSalt {
+    fn swap_le(&mut self) {}
+}
+
+/// `H(salt || inputs_1)`, the part of a `ShareCommitment` the server with
+/// `inputs_1` shows its peer, so that the peer can recompute the commitment
+/// from `inputs_0`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Inputs1Hash(pub [u8; 32]);
+
+impl Inputs1Hash {
+    pub fn new<T: UInt>(salt: ShareSalt, inputs_1: &[BitsLE<T>]) -> Self {
+        let hash = Sha256::new()
+            .chain_update(salt.0)
+            .chain_update(bytemuck::cast_slice::<_, u8>(inputs_1))
+            .finalize();
+        Inputs1Hash(hash.into())
+    }
+}
+
+unsafe impl Pod for Inputs1Hash {}
+unsafe impl Zeroable for Inputs1Hash {}
+
+/// A digest is a byte string, which has no byte order.
+#[cfg(target_endian = "big")]
+impl LePod for Inputs1Hash {
+    fn swap_le(&mut self) {}
+}
+
+/// Commitment `H(uid || session || inputs_0 || H(salt || inputs_1))` to the
+/// boolean shares of a client, sent with both shares. Neither server can open
+/// it alone, so the server with `inputs_1` sends its peer `Inputs1Hash`, and
+/// the server with `inputs_0` recomputes the commitment: a client that sends
+/// shares from two different sharings is caught, even with the same
+/// commitment to both servers.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct ShareCommitment(pub [u8; 32]);
+
+impl ShareCommitment {
+    pub fn new<T: UInt>(
+        uid: u64,
+        session: u64,
+        inputs_0: SeededInputShare,
+        salt: ShareSalt,
+        inputs_1: &[BitsLE<T>],
+    ) -> Self {
+        Self::from_hash(uid, session, inputs_0, Inputs1Hash::new(salt, inputs_1))
+    }
+
+    /// Same as `new`, from the hash of `inputs_1` sent by the other server.
+    pub fn from_hash(
+        uid: u64,
+        session: u64,
+        inputs_0: SeededInputShare,
+        inputs_1_hash: Inputs1Hash,
+    ) -> Self {
+        let commitment = Sha256::new()
+            .chain_update(uid.to_le_bytes())
+            .chain_update(session.to_le_bytes())
+            .chain_update(inputs_0.0.to_le_bytes())
+            .chain_update(inputs_1_hash.0)
+            .finalize();
+        ShareCommitment(commitment.into())
+    }
+}
+
+unsafe impl Pod for ShareCommitment {}
+unsafe impl Zeroable for ShareCommitment {}
+
+/// A digest is a byte string, which has no byte order.
+#[cfg(target_endian = "big")]
+impl LePod for ShareCommitment {
+    fn swap_le(&mut self) {}
+}
+
 /// Return `inputs_0` as PRNG seed, and `inputs_1`.
 pub fn batch_make_boolean_shares<T: UInt, R: Rng, I>(
     rng: &mut R,
@@ -121,15 +259,14 @@ where
     I: Iterator,
     I::Item: Borrow<BitsLE<T>>,
 {
-    let seed = rng.next_u64();
-    let mut rng = ChaCha12Rng::seed_from_u64(seed);
-    let inputs_1 = input
-        .map(|b| {
-            let mask = T::rand(&mut rng).bits_le();
-            *b.borrow() ^ mask
-        })
-        .collect::<Vec<_>>();
-    (SeededInputShare(seed), inputs_1)
+    let inputs_0 = SeededInputShare(rng.next_u64());
+    let mut inputs_1 = input.map(|b| *b.borrow()).collect::<Vec<_>>();
+    let masks = inputs_0.expand(inputs_1.len());
+    inputs_1
+        .iter_mut()
+        .zip(masks)
+        .for_each(|(b, mask)| *b = *b ^ mask);
+    (inputs_0, inputs_1)
 }
 
 impl<T: UInt> BitXor for BitsLE<T> {
@@ -181,6 +318,24 @@ impl PackedBits {
             .take(self.size)
     }
 
+    /// Bits `range` of `self`.
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(range.end <= self.size, "range out of bounds");
+        self.iter().skip(range.start).take(range.len()).collect()
+    }
+
+    /// Bit `i` is bit `i % 32` of `words[i / 32]`. Bits past `size` are
+    /// cleared.
+    pub(crate) fn from_words(size: usize, words: Vec<u32>) -> Self {
+        assert_eq!(words.len(), (size + 31) >> 5);
+        let mut result = Self {
+            size,
+            payload: words.into_iter().map(BitsLE).collect(),
+        };
+        result.adjust_last_byte();
+        result
+    }
+
     pub fn rand<R: Rng>(rng: &mut R, num_bits: usize) -> Self {
         let num_u32 = (num_bits + 31) >> 5;
         let payload = (0..num_u32)
@@ -298,12 +453,57 @@ impl<'a> FromIterator<&'a bool> for PackedBits {
     }
 }
 
+/// The number of bits, then the words. Fails on a number of words that does
+/// not fit the number of bits.
+impl Communicate for PackedBits {
+    type Deserialized = Self;
+
+    fn size_in_bytes(&self) -> usize {
+        (self.size as u64).use_cast().size_in_bytes() + self.payload.size_in_bytes()
+    }
+
+    fn to_bytes<W: Write>(&self, mut dest: W) {
+        (self.size as u64).use_cast().to_bytes(&mut dest);
+        self.payload.to_bytes(dest);
+    }
+
+    fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+        let size = UseCast::<u64>::from_bytes(&mut bytes)? as usize;
+        let payload = Vec::<BitsLE<u32>>::from_bytes(bytes)?;
+        if payload.len() != size.div_ceil(32) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "number of words does not fit the number of bits",
+            )
+            .into());
+        }
+        let mut result = PackedBits { size, payload };
+        result.adjust_last_byte();
+        Ok(result)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{bits::PackedBits, uint::UInt, utils::SliceExt};
     use rand::{rngs::StdRng, Rng, SeedableRng};
 
-    use super::batch_make_boolean_shares;
+    use super::{
+        batch_make_boolean_shares, BitsLE, Inputs1Hash, SeededInputShare, ShareCommitment,
+        ShareSalt,
+    };
+    use serialize::{AsUseCast, Communicate, UseCast};
+
+    #[test]
+    fn test_wire_format_is_le() {
+        let seed = UseCast(SeededInputShare(0x0102_0304_0506_0708)).into_bytes_owned();
+        assert_eq!(
+            seed.as_ref(),
+            [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]
+        );
+        let bits = [BitsLE(0x0102_0304u32)].into_bytes_owned();
+        assert_eq!(bits.as_ref(), [0x04, 0x03, 0x02, 0x01]);
+    }
 
     #[test]
     fn test_consistency() {
@@ -324,9 +524,9 @@ mod tests {
 
     #[test]
     fn test_consistency_2() {
-        let v1 = vec![false, false, true, false, true, false, true, true];
+        let v1 = [false, false, true, false, true, false, true, true];
         let not_v1 = vec![true, true, false, true, false, true, false, false];
-        let v2 = vec![true, false, true, false, true, false, true, false];
+        let v2 = [true, false, true, false, true, false, true, false];
         let v1_and_v2 = vec![false, false, true, false, true, false, true, false];
 
         let v1_vec = v1.iter().cloned().collect::<PackedBits>();
@@ -338,6 +538,20 @@ mod tests {
         assert_eq!(v1_and_v2_vec.iter().collect::<Vec<_>>(), v1_and_v2);
     }
 
+    #[test]
+    fn test_packed_bits_bytes() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let bits = PackedBits::rand(&mut rng, 70);
+        let bytes = (&bits).into_bytes_owned();
+        assert_eq!(bytes.len(), bits.size_in_bytes());
+        assert_eq!(PackedBits::from_bytes_owned(bytes).unwrap(), bits);
+
+        // 70 bits in two words
+        let mut malformed = (70u64).use_cast().into_bytes_owned().to_vec();
+        malformed.extend_from_slice(&[BitsLE(0u32); 2].into_bytes_owned());
+        assert!(PackedBits::from_bytes_owned(malformed.into()).is_err());
+    }
+
     #[test]
     fn make() {
         let mut rng = StdRng::seed_from_u64(12345);
@@ -347,7 +561,50 @@ mod tests {
             .collect::<Vec<_>>();
         let (inputs_0, inputs_1) = batch_make_boolean_shares(&mut rng, inputs.iter());
         let inputs_0 = inputs_0.expand(gsize);
-        let merged = inputs_0.zip_map(&inputs_1, |a, b| (*a ^ *b));
+        let merged = inputs_0.zip_map(&inputs_1, |a, b| *a ^ *b);
         assert_eq!(inputs, merged);
     }
+
+    #[test]
+    fn test_share_commitment() {
+        let mut rng = StdRng::seed_from_u64(288);
+        let inputs = (0..10)
+            .map(|_| rng.gen::<u8>().bits_le())
+            .collect::<Vec<_>>();
+        let (seed, inputs_1) = batch_make_boolean_shares(&mut rng, inputs.iter());
+        let (other_seed, other_inputs_1) = batch_make_boolean_shares(&mut rng, inputs.iter());
+        let salt = ShareSalt::sample(&mut rng);
+        let other_salt = ShareSalt::sample(&mut rng);
+
+        let commitment = ShareCommitment::new(3, 1, seed, salt, &inputs_1);
+        assert_eq!(
+            commitment,
+            ShareCommitment::new(3, 1, seed, salt, &inputs_1)
+        );
+        assert_eq!(
+            commitment,
+            ShareCommitment::from_hash(3, 1, seed, Inputs1Hash::new(salt, &inputs_1))
+        );
+        assert_ne!(
+            commitment,
+            ShareCommitment::new(4, 1, seed, salt, &inputs_1)
+        );
+        assert_ne!(
+            commitment,
+            ShareCommitment::new(3, 2, seed, salt, &inputs_1)
+        );
+        assert_ne!(
+            commitment,
+            ShareCommitment::new(3, 1, seed, other_salt, &inputs_1)
+        );
+        // shares of the same input from two sharings
+        assert_ne!(
+            commitment,
+            ShareCommitment::new(3, 1, other_seed, salt, &inputs_1)
+        );
+        assert_ne!(
+            commitment,
+            ShareCommitment::new(3, 1, seed, salt, &other_inputs_1)
+        );
+    }
 }
@@ -27,13 +27,10 @@ fn ks_rounds(keys_dest: &mut [AESKey], con: m128i, con3: m128i, mask: m128i, r:
     })
 }
 
-// AES key scheduling for 8 keys
+// AES key scheduling for any number of keys, each round over all of them
 // [REF] Implementation of "Fast Garbling of Circuits Under Standard
 // Assumptions" https://eprint.iacr.org/2015/751.pdf
-pub fn aes_opt_key_schedule<const NUM_KEYS: usize>(
-    user_key: &[m128i; NUM_KEYS],
-    keys_dest: &mut [AESKey; NUM_KEYS],
-) {
+pub fn aes_opt_key_schedule(user_key: &[m128i], keys_dest: &mut [AESKey]) {
     assert_eq!(user_key.len(), keys_dest.len());
 
     let mut con = m128i::from([1u32, 1, 1, 1]);
@@ -83,28 +80,40 @@ pub(crate) fn para_enc<const NUM_ENCS: usize, const NUM_KEYS: usize, const INPUT
     keys: &[AESKey; NUM_KEYS],
 ) {
     debug_assert_eq!(blocks.len(), NUM_ENCS * NUM_KEYS);
+    para_enc_slice(blocks, NUM_ENCS, keys)
+}
+
+/// Same as `para_enc`, with the sizes known at runtime. The last key may
+/// encrypt fewer than `num_encs` blocks.
+///
+/// # Panics (debug only)
+///
+/// Panics if `blocks.len() > num_encs * keys.len()`.
+#[inline]
+pub(crate) fn para_enc_slice(blocks: &mut [m128i], num_encs: usize, keys: &[AESKey]) {
+    debug_assert!(blocks.len() <= num_encs * keys.len());
 
     // first, xor key
     blocks
-        .chunks_mut(NUM_ENCS)
+        .chunks_mut(num_encs)
         .zip(keys.iter().map(|k| k.rd_key[0]))
         .for_each(|(bs, k)| {
             bs.iter_mut().for_each(|b| {
-                *b = *b ^ k;
+                *b ^= k;
             })
         });
 
     // for each round, do AES encrypt
     for r in 1..10 {
         blocks
-            .chunks_mut(NUM_ENCS)
+            .chunks_mut(num_encs)
             .zip(keys.iter().map(|k| k.rd_key[r]))
             .for_each(|(bs, k)| bs.iter_mut().for_each(|b| *b = aes_encrypt_m128i(*b, k)))
     }
 
     // last round encryption
     blocks
-        .chunks_mut(NUM_ENCS)
+        .chunks_mut(num_encs)
         .zip(keys.iter().map(|k| k.rd_key[10]))
         .for_each(|(bs, k)| {
             bs.iter_mut()
@@ -113,9 +122,13 @@ pub(crate) fn para_enc<const NUM_ENCS: usize, const NUM_KEYS: usize, const INPUT
 }
 
 pub fn aes_ecb_encrypt_blocks(blocks: &mut [m128i], key: &AESKey) {
-    blocks.iter_mut().for_each(|b|*b = *b ^ key.rd_key[0]);
+    blocks.iter_mut().for_each(|b| *b ^= key.rd_key[0]);
     for j in 1..key.rounds {
-        blocks.iter_mut().for_each(|b| *b = aes_encrypt_m128i(*b, key.rd_key[j as usize]))
+        blocks
+            .iter_mut()
+            .for_each(|b| *b = aes_encrypt_m128i(*b, key.rd_key[j as usize]))
     }
-    blocks.iter_mut().for_each(|b|*b = aes_encrypt_last_m128i(*b, key.rd_key[key.rounds as usize]))
-}
\ No newline at end of file
+    blocks
+        .iter_mut()
+        .for_each(|b| *b = aes_encrypt_last_m128i(*b, key.rd_key[key.rounds as usize]))
+}
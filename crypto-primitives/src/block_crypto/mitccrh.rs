@@ -1,9 +1,13 @@
 //! Adapted from https://github.com/emp-toolkit/emp-tool/blob/master/emp-tool/utils/mitccrh.h
 
-use crate::block_crypto::aes::{aes_opt_key_schedule, para_enc, AESKey};
+use crate::block_crypto::aes::{aes_opt_key_schedule, para_enc, para_enc_slice, AESKey};
 use block::Block;
 use safe_arch::{m128i, set_i64_m128i};
 
+/// Number of batches of keys that `MiTCCR::hash_many` schedules in one call
+/// to `aes_opt_key_schedule`.
+pub const KEY_SCHEDULE_BATCHES: usize = 8;
+
 /// MiTCCR hash function
 ///
 /// Reference: [GKWWY19](https://eprint.iacr.org/2019/1168)
@@ -15,6 +19,21 @@ pub struct MiTCCR<const BATCH_SIZE: usize> {
     // length
     start_point: m128i,
     gid: u64,
+    /// Scratch buffers of `hash_many`, kept to reuse their allocations.
+    many_keys: Vec<m128i>,
+    many_scheduled_keys: Vec<AESKey>,
+    many_tmp: Vec<m128i>,
+}
+
+/// Fill `keys` with the keys of `gid`, `gid + 1`, ..., and return the next
+/// `gid`.
+fn derive_keys(start_point: m128i, mut gid: u64, keys: &mut [m128i]) -> u64 {
+    keys.iter_mut().for_each(|k| {
+        let tmp = set_i64_m128i(gid as i64, 0);
+        gid += 1;
+        *k = start_point ^ tmp;
+    });
+    gid
 }
 
 impl<const BATCH_SIZE: usize> MiTCCR<BATCH_SIZE> {
@@ -24,20 +43,15 @@ impl<const BATCH_SIZE: usize> MiTCCR<BATCH_SIZE> {
             keys: [m128i::default(); BATCH_SIZE],
             start_point,
             gid: 0,
+            many_keys: Vec::new(),
+            many_scheduled_keys: Vec::new(),
+            many_tmp: Vec::new(),
         }
     }
 
     /// renew keys
     pub fn renew_ks(&mut self) {
-        let mut gid = self.gid;
-        let start_point = self.start_point;
-        self.keys.iter_mut().for_each(|k| {
-            let tmp = set_i64_m128i(gid as i64, 0);
-            gid += 1;
-            *k = start_point ^ tmp;
-        });
-        self.gid = gid;
-
+        self.gid = derive_keys(self.start_point, self.gid, &mut self.keys);
         aes_opt_key_schedule(&self.keys, &mut self.scheduled_key);
     }
 
@@ -61,7 +75,7 @@ impl<const BATCH_SIZE: usize> MiTCCR<BATCH_SIZE> {
         para_enc::<H, BATCH_SIZE, INPUT_SIZE>(&mut tmp, &self.scheduled_key);
 
         input.iter_mut().zip(tmp.iter()).for_each(|(a, b)| {
-            *a = *a ^ *b;
+            *a ^= *b;
         });
     }
 
@@ -76,6 +90,51 @@ impl<const BATCH_SIZE: usize> MiTCCR<BATCH_SIZE> {
     ) {
         self.hash::<H, INPUT_SIZE>(bytemuck::cast_mut(input))
     }
+
+    /// Same as calling `hash` on each stride of `BATCH_SIZE * h` blocks of
+    /// `inputs` in turn, where the last stride may be shorter, as if padded.
+    /// The keys of up to `KEY_SCHEDULE_BATCHES` strides are scheduled at a
+    /// time instead of one stride at a time.
+    ///
+    /// # Panics
+    /// If `h` is 0 or does not divide `inputs.len()`.
+    pub fn hash_many(&mut self, inputs: &mut [Block], h: usize) {
+        assert!(
+            h > 0 && inputs.len().is_multiple_of(h),
+            "{} inputs are not a multiple of h = {}",
+            inputs.len(),
+            h
+        );
+        let inputs: &mut [m128i] = bytemuck::cast_slice_mut(inputs);
+        let stride = BATCH_SIZE * h;
+        let num_keys = BATCH_SIZE * KEY_SCHEDULE_BATCHES;
+        self.many_keys.resize(num_keys, m128i::default());
+        self.many_scheduled_keys.resize(num_keys, AESKey::default());
+
+        for group in inputs.chunks_mut(stride * KEY_SCHEDULE_BATCHES) {
+            // a short stride still uses up `BATCH_SIZE` keys, as in `hash`
+            let num_keys = group.len().div_ceil(stride) * BATCH_SIZE;
+            let (keys, scheduled_keys) = (
+                &mut self.many_keys[..num_keys],
+                &mut self.many_scheduled_keys[..num_keys],
+            );
+            self.gid = derive_keys(self.start_point, self.gid, keys);
+            aes_opt_key_schedule(keys, scheduled_keys);
+
+            for (input, keys) in group
+                .chunks_mut(stride)
+                .zip(scheduled_keys.chunks(BATCH_SIZE))
+            {
+                self.many_tmp.clear();
+                self.many_tmp.extend_from_slice(input);
+                para_enc_slice(&mut self.many_tmp, h, keys);
+                input
+                    .iter_mut()
+                    .zip(&self.many_tmp)
+                    .for_each(|(a, b)| *a ^= *b);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -175,4 +234,56 @@ mod tests {
         assert_eq!(b_expected, b, "mismatch: b");
         assert_eq!(c_expected, c, "mismatch: c");
     }
+
+    /// `hash` on each stride of `BATCH_SIZE * H` blocks, padding the last one.
+    fn hash_per_batch<const BATCH_SIZE: usize, const H: usize, const INPUT_SIZE: usize>(
+        crh: &mut MiTCCR<BATCH_SIZE>,
+        inputs: &mut [Block],
+    ) {
+        let mut pad = [Block::default(); INPUT_SIZE];
+        inputs.chunks_mut(INPUT_SIZE).for_each(|stride| {
+            pad[..stride.len()].copy_from_slice(stride);
+            crh.hash_block::<H, INPUT_SIZE>(&mut pad);
+            stride.copy_from_slice(&pad[..stride.len()]);
+        });
+    }
+
+    #[test]
+    fn test_hash_many_matches_per_batch() {
+        const BATCH_SIZE: usize = 8;
+        let mut rng = StdRng::seed_from_u64(0);
+        let crh = MiTCCR::<BATCH_SIZE>::new(Block::rand(&mut rng).0);
+        // a multiple of a stride, and lengths with a short last stride
+        for len in [0, 2, 3000, 4096, 4106] {
+            let inputs = (0..len).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+
+            let (mut expected, mut actual) = (inputs.clone(), inputs.clone());
+            let (mut crh_expected, mut crh_actual) = (crh.clone(), crh.clone());
+            hash_per_batch::<BATCH_SIZE, 1, BATCH_SIZE>(&mut crh_expected, &mut expected);
+            crh_actual.hash_many(&mut actual, 1);
+            assert_eq!(actual, expected, "h = 1, len = {}", len);
+
+            let (mut expected, mut actual) = (inputs.clone(), inputs);
+            hash_per_batch::<BATCH_SIZE, 2, { BATCH_SIZE * 2 }>(&mut crh_expected, &mut expected);
+            // in two calls, split at a stride
+            let (head, tail) = actual.split_at_mut(len.min(1024));
+            crh_actual.hash_many(head, 2);
+            crh_actual.hash_many(tail, 2);
+            assert_eq!(actual, expected, "h = 2, len = {}", len);
+
+            // both used up the same keys
+            let mut next = [Block::default(); BATCH_SIZE];
+            let mut next_expected = next;
+            crh_expected.hash_block::<1, BATCH_SIZE>(&mut next_expected);
+            crh_actual.hash_block::<1, BATCH_SIZE>(&mut next);
+            assert_eq!(next, next_expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not a multiple of h")]
+    fn test_hash_many_rejects_split_input() {
+        let mut crh = MiTCCR::<8>::new(m128i::default());
+        crh.hash_many(&mut [Block::default(); 3], 2);
+    }
 }
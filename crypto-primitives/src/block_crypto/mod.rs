@@ -1,7 +1,5 @@
 //! A Hash function for block, using AES instruction set.
 pub mod aes;
 pub mod mitccrh;
+pub mod prg;
 pub mod rng;
-
-
-
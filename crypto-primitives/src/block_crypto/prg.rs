@@ -0,0 +1,110 @@
+//! Expansion of the 64-bit seeds of the seeded shares (`SeededInputShare`,
+//! `CorrShareSeedToAlice`, `CorrShareSeedToBob`) into vectors of `UInt`s.
+//!
+//! The client and the servers must expand a seed with the same PRG. It is
+//! chosen at compile time: AES in counter mode with the `aes-prg` feature,
+//! ChaCha12 otherwise. The bridge announces the choice in the version
+//! handshake, so that peers built with different PRGs refuse to talk.
+
+use block::Block;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+
+use crate::{block_crypto::rng::BlockRng, uint::UInt};
+
+/// Blocks encrypted per batch by `expand_aes`.
+const AES_CHUNK_BLOCKS: usize = 1024;
+
+/// A PRG to expand seeds with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prg {
+    ChaCha12,
+    Aes,
+}
+
+impl Prg {
+    /// PRG of this build.
+    pub const DEFAULT: Prg = if cfg!(feature = "aes-prg") {
+        Prg::Aes
+    } else {
+        Prg::ChaCha12
+    };
+
+    pub fn expand<T: UInt>(self, seed: u64, size: usize) -> Vec<T> {
+        match self {
+            Prg::ChaCha12 => expand_chacha(seed, size),
+            Prg::Aes => expand_aes(seed, size),
+        }
+    }
+}
+
+/// `size` values of `T::rand` on ChaCha12 seeded with `seed`.
+pub fn expand_chacha<T: UInt>(seed: u64, size: usize) -> Vec<T> {
+    let mut rng = ChaCha12Rng::seed_from_u64(seed);
+    (0..size).map(|_| T::rand(&mut rng)).collect()
+}
+
+/// AES in counter mode keyed by `seed`, i.e. `BlockRng` keyed by
+/// `[seed, 0]`. The `i`-th value is the `i`-th little-endian `T` of the
+/// stream of blocks.
+pub fn expand_aes<T: UInt>(seed: u64, size: usize) -> Vec<T> {
+    let mut rng = BlockRng::new(Some(Block([seed, 0].into())));
+    let mut values = Vec::with_capacity(size);
+    let mut blocks = vec![Block::default(); AES_CHUNK_BLOCKS];
+    while values.len() < size {
+        rng.random_blocks(&mut blocks);
+        let chunk = bytemuck::cast_slice::<_, T>(&blocks);
+        values.extend_from_slice(&chunk[..chunk.len().min(size - values.len())]);
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 0x5eed0fe15a;
+
+    #[test]
+    fn aes_pinned_output() {
+        // both parties must derive identical values, across versions too
+        assert_eq!(
+            expand_aes::<u32>(SEED, 16),
+            [
+                0xdc14ee7c, 0xdf0b59da, 0xa0d39beb, 0xb21cfd14, 0x7236e50d, 0xebea8e27, 0xf66b7007,
+                0xbdd67bcb, 0x721831de, 0xa82119b9, 0xb1961884, 0xb52e3066, 0xadff5be2, 0xde3adc7f,
+                0x2987fcc4, 0x9e617e49
+            ]
+        );
+    }
+
+    #[test]
+    fn chacha_pinned_output() {
+        assert_eq!(
+            expand_chacha::<u32>(SEED, 16),
+            [
+                0x894c58f9, 0x3d40990b, 0x7d5bb54c, 0xd1bb6b95, 0x2fc68d50, 0x0b476fc5, 0x01435dd1,
+                0xecdceb00, 0x8a7258ab, 0x2c241205, 0xeb963aa1, 0xde196976, 0xea0bb3c7, 0x9cb6e5a9,
+                0x3a56897a, 0xa21be370
+            ]
+        );
+    }
+
+    #[test]
+    fn aes_is_prefix_stable() {
+        // the batching of the blocks does not show in the output
+        let long = expand_aes::<u64>(SEED, AES_CHUNK_BLOCKS * 2 + 3);
+        assert_eq!(expand_aes::<u64>(SEED, 5), long[..5]);
+        assert_eq!(
+            expand_aes::<u64>(SEED, AES_CHUNK_BLOCKS * 2),
+            long[..AES_CHUNK_BLOCKS * 2]
+        );
+        assert_eq!(expand_aes::<u16>(SEED, 0), Vec::<u16>::new());
+        assert_ne!(expand_aes::<u64>(SEED + 1, 5), long[..5]);
+
+        // same stream as `BlockRng`
+        let mut block = [Block::default()];
+        BlockRng::new(Some(Block([SEED, 0].into()))).random_blocks(&mut block);
+        assert_eq!(expand_aes::<u128>(SEED, 1), bytemuck::cast_slice(&block));
+    }
+}
@@ -2,14 +2,21 @@
 
 use crate::block_crypto::aes::{aes_ecb_encrypt_blocks, aes_opt_key_schedule, AESKey};
 use block::Block;
-use rand::random;
+use rand::{random, RngCore, SeedableRng};
 use safe_arch::m128i;
 
+/// AES in counter mode. Implements [`RngCore`], where the byte stream is the
+/// concatenation of the blocks of `random_blocks`.
 pub struct BlockRng {
     counter: u64,
     aes: AESKey,
+    /// Unused bytes of the last block drawn by `RngCore` methods.
+    buffer: [u8; BLOCK_SIZE],
+    buffer_pos: usize,
 }
 
+const BLOCK_SIZE: usize = std::mem::size_of::<Block>();
+
 impl BlockRng {
     pub fn new(seed: Option<Block>) -> Self {
         let seed = match seed {
@@ -27,10 +34,15 @@ impl BlockRng {
         Self {
             counter,
             aes: aes[0],
+            buffer: [0; BLOCK_SIZE],
+            buffer_pos: BLOCK_SIZE,
         }
     }
 
+    /// Fill `blocks_dest` with the next blocks. Unused bytes buffered by
+    /// `RngCore` methods are discarded.
     pub fn random_blocks(&mut self, blocks_dest: &mut [Block]) {
+        self.buffer_pos = BLOCK_SIZE;
         const AES_BATCH_SIZE: usize = 8;
         let blocks_dest = bytemuck::cast_slice_mut::<_, m128i>(blocks_dest);
         (0..blocks_dest.len() / AES_BATCH_SIZE).for_each(|i| {
@@ -55,9 +67,64 @@ impl BlockRng {
     }
 }
 
+impl RngCore for BlockRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        // first use the buffered bytes
+        let num_buffered = (BLOCK_SIZE - self.buffer_pos).min(dest.len());
+        let (head, dest) = dest.split_at_mut(num_buffered);
+        head.copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + num_buffered]);
+        self.buffer_pos += num_buffered;
+        if dest.is_empty() {
+            return;
+        }
+
+        // then whole blocks
+        let num_whole = dest.len() / BLOCK_SIZE * BLOCK_SIZE;
+        let (body, tail) = dest.split_at_mut(num_whole);
+        let mut blocks = vec![Block::default(); num_whole / BLOCK_SIZE];
+        self.random_blocks(&mut blocks);
+        body.copy_from_slice(bytemuck::cast_slice(&blocks));
+
+        // and buffer the remainder of one more block
+        if !tail.is_empty() {
+            let mut block = [Block::default()];
+            self.random_blocks(&mut block);
+            self.buffer = bytemuck::cast(block);
+            tail.copy_from_slice(&self.buffer[..tail.len()]);
+            self.buffer_pos = tail.len();
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for BlockRng {
+    type Seed = [u8; BLOCK_SIZE];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        Self::new(Some(Block(bytemuck::cast(seed))))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{bits::PackedBits, square_corr::SquareCorr, uint::UInt};
 
     #[test]
     fn sanity() {
@@ -86,4 +153,59 @@ mod tests {
         assert_eq!(data2[..4], data5[..4]);
         assert_eq!(data3[..4], data6[..4]);
     }
+
+    #[test]
+    fn rng_core_matches_block_stream() {
+        let seed = Block(0x1234567890abcdefu128.into());
+        let mut blocks = [Block::default(); 4];
+        BlockRng::new(Some(seed)).random_blocks(&mut blocks);
+        let expected: &[u8] = bytemuck::cast_slice(&blocks);
+
+        // reads of awkward lengths see the same stream
+        let mut rng = BlockRng::new(Some(seed));
+        let mut actual = [0u8; 64];
+        let (a, rest) = actual.split_at_mut(5);
+        let (b, rest) = rest.split_at_mut(27);
+        let (c, d) = rest.split_at_mut(8);
+        rng.fill_bytes(a);
+        rng.fill_bytes(b);
+        c.copy_from_slice(&rng.next_u64().to_le_bytes());
+        rng.fill_bytes(d);
+        assert_eq!(&actual[..], expected);
+
+        let mut rng = BlockRng::from_seed(bytemuck::cast(seed));
+        assert_eq!(rng.next_u32().to_le_bytes(), expected[..4]);
+    }
+
+    #[test]
+    fn rng_core_pinned_output() {
+        // seeded runs must stay reproducible across versions
+        let mut rng = BlockRng::from_seed(*b"elsa block rng!!");
+        let mut bytes = [0u8; 64];
+        rng.fill_bytes(&mut bytes);
+        assert_eq!(
+            bytes,
+            [
+                0x09, 0x8d, 0xb9, 0xb5, 0x6a, 0x4d, 0x03, 0xe2, 0xf4, 0x5d, 0x02, 0xfc, 0x8f, 0xb9,
+                0x9b, 0xd6, 0x4a, 0x06, 0xc6, 0x67, 0x46, 0x19, 0x41, 0x7b, 0x61, 0x01, 0x9e, 0xf0,
+                0xb5, 0xda, 0xdb, 0x5f, 0x11, 0x0e, 0xf7, 0xf0, 0x06, 0x93, 0x9c, 0xf7, 0xcb, 0x03,
+                0x7f, 0xb9, 0x37, 0xe5, 0xf4, 0x9d, 0xf7, 0xc3, 0x8a, 0xa9, 0x89, 0x9a, 0x45, 0xe9,
+                0x88, 0x2f, 0xe1, 0x9f, 0x53, 0xd1, 0x02, 0x41,
+            ]
+        );
+    }
+
+    #[test]
+    fn drives_samplers() {
+        let mut rng = BlockRng::seed_from_u64(42);
+        let x = u32::rand(&mut rng);
+        let corr = SquareCorr::<u64>::rand(&mut rng);
+        assert_eq!(
+            corr.value().wrapping_mul(corr.value()),
+            corr.value_squared()
+        );
+        assert_eq!(PackedBits::rand(&mut rng, 100).len(), 100);
+        let mut rng2 = BlockRng::seed_from_u64(42);
+        assert_eq!(u32::rand(&mut rng2), x);
+    }
 }
@@ -0,0 +1,659 @@
+//! Range check of the inputs of a client against a public bound `b`, with the
+//! interval encoding of `UInt::to_bounded_encoding`.
+//!
+//! The inputs below `b` fall into one interval per bit set in `b`: interval
+//! `j` holds the inputs that agree with `b` above `k_j`, the position of the
+//! `j`-th bit set in `b` from the top, and have bit `k_j` cleared. An input `x`
+//! of interval `j` is encoded as the one-hot `s = 2^j` and the low `k_j` bits
+//! `y` of `x`, so that `x = P_j + y`, where `P_j` is `b` with bit `k_j` and
+//! the bits below it cleared.
+//!
+//! Besides its boolean shares of the inputs, the client sends boolean shares
+//! of `y` and `s` of every input, and COTs for the AND gates of a circuit
+//! that accepts if and only if every `s` is one-hot and decodes with `y` to
+//! `x`, which proves `x < b`. The servers evaluate it in one round: the
+//! shares of the OT sender do not depend on the receiver, see
+//! `AndGateUsingOTSender`, so Alice sends the `us` of all the gates at once,
+//! and then both open the accept bit.
+//!
+//! The client deals the COTs of the gates, so the accept bit is only sound if
+//! they are consistent. Like the COTs of B2A, they come with additional OTs
+//! of random choices, and are verified with a random linear combination
+//! `chi`: Bob sends `x_til` and `t_til` over the choices he made in the gates,
+//! and Alice checks them against her COTs (`AndOtCheck`) before the accept
+//! bit counts.
+
+use crate::{
+    bitmul::{AndGate, AndGateChoosingOTs, AndGateUsingOTReceiver, AndGateUsingOTSender},
+    bits::{batch_make_boolean_shares, BitsLE, SeededInputShare},
+    cot::{
+        client::{num_additional_ot_needed, B2ACOTToAlice, COTGen},
+        rot::{cot_to_rot_receiver_side, cot_to_rot_sender_side, OT_BSIZE},
+        server::{inner_product_chunked, Chi, ChiChunks, OTReceiver, TTil},
+        COTSeed, ChoiceSeed, BOUNDED_AND_PURPOSE, BOUNDED_R_PURPOSE,
+    },
+    error::{check_len, ProtocolError},
+    uint::UInt,
+    ALICE, BOB,
+};
+use block::{gf::GF2_256, Block};
+use rand::Rng;
+use serialize::{AsUseCast, Communicate, UseCast};
+use std::io::{Read, Write};
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoundError {
+    #[error("the bound must be positive")]
+    Zero,
+    #[error("input {input} is not below the bound {bound}")]
+    OutOfRange { input: u128, bound: u128 },
+}
+
+/// A public bound of the inputs, and the intervals of its encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputBound<I: UInt> {
+    bound: I,
+    /// `k_j` of each interval, from the top.
+    interval_sizes: Vec<usize>,
+    /// `P_j` of each interval.
+    prefixes: Vec<BitsLE<I>>,
+}
+
+impl<I: UInt> InputBound<I> {
+    /// Inputs must be below `bound`.
+    pub fn new(bound: I) -> Result<Self, BoundError> {
+        if bound.is_zero() {
+            return Err(BoundError::Zero);
+        }
+        let interval_sizes = (0..I::NUM_BITS)
+            .rev()
+            .filter(|&k| bound.bits_le().get_bit(k))
+            .collect::<Vec<_>>();
+        let prefixes = interval_sizes
+            .iter()
+            .map(|&k| BitsLE(bound & !(I::max_value() >> (I::NUM_BITS - 1 - k))))
+            .collect();
+        Ok(InputBound {
+            bound,
+            interval_sizes,
+            prefixes,
+        })
+    }
+
+    pub fn bound(&self) -> I {
+        self.bound
+    }
+
+    /// Number of intervals, i.e. of bits set in the bound (`hsize`).
+    pub fn num_intervals(&self) -> usize {
+        self.interval_sizes.len()
+    }
+
+    /// `y` and `s` of `input`, or an error if it is not below the bound.
+    pub fn encode(&self, input: I) -> Result<(BitsLE<I>, BitsLE<I>), BoundError> {
+        if input >= self.bound {
+            return Err(BoundError::OutOfRange {
+                input: input.to_u128().unwrap(),
+                bound: self.bound.to_u128().unwrap(),
+            });
+        }
+        Ok(input.to_bounded_encoding(self.bound))
+    }
+
+    /// Number of AND gates to check `gsize` inputs. For each input: the
+    /// `hsize - 1` gates of the one-hot check, the `wsize - 1` gates of the
+    /// decoding, and one gate per term of the conjunction of the `hsize`
+    /// one-hot terms and the `NUM_BITS` comparisons with the input, except
+    /// for the first term of all.
+    pub fn num_and_gates(&self, gsize: usize) -> usize {
+        let hsize = self.num_intervals();
+        let per_input = (hsize - 1) + self.interval_sizes[0] + hsize + I::NUM_BITS;
+        (gsize * per_input).saturating_sub(1)
+    }
+
+    /// Number of COTs to check `gsize` inputs: two per AND gate, rounded up
+    /// to a multiple of the batch of the ROT hash. The ones after the gates
+    /// are not used.
+    pub fn num_ots(&self, gsize: usize) -> usize {
+        (2 * self.num_and_gates(gsize)).next_multiple_of(OT_BSIZE)
+    }
+
+    /// Number of COTs the client sends to check `gsize` inputs: the ones of
+    /// `num_ots`, then the additional OTs of their verification.
+    pub fn num_verified_ots(&self, gsize: usize, sec_param: usize) -> usize {
+        let num_ots = self.num_ots(gsize);
+        num_ots + num_additional_ot_needed(num_ots, sec_param)
+    }
+
+    /// Coefficients of the verification of the COTs of `gsize` inputs.
+    fn chi(&self, gsize: usize, sec_param: usize, chi_seed: u64) -> Chi {
+        Chi::Seeded {
+            num_ots: self.num_verified_ots(gsize, sec_param),
+            seed: chi_seed,
+        }
+    }
+
+    /// The share of `PARTY` of whether every input `xs[i]` is encoded by
+    /// `ys[i]` and `ss[i]`, evaluated with `gate` on the shares of `PARTY`.
+    fn evaluate<G: AndGate, const PARTY: bool>(
+        &self,
+        gate: &mut G,
+        xs: &[BitsLE<I>],
+        ys: &[BitsLE<I>],
+        ss: &[BitsLE<I>],
+    ) -> bool {
+        // Alice holds the share of the constants
+        let one = PARTY == ALICE;
+        let mut accept = Conjunction::default();
+        let mut s_bits = Vec::with_capacity(self.num_intervals());
+        for ((x, y), s) in xs.iter().zip(ys).zip(ss) {
+            s_bits.clear();
+            s_bits.extend((0..self.num_intervals()).map(|j| s.get_bit(j)));
+
+            // one-hot: `seen` is the OR of the bits so far, and no bit may be
+            // set once it is
+            let mut seen = s_bits[0];
+            for &s_j in &s_bits[1..] {
+                let conflict = gate.and(seen, s_j);
+                seen ^= s_j ^ conflict;
+                accept.push(gate, conflict ^ one);
+            }
+            accept.push(gate, seen);
+
+            // decode `P_j + y` of the interval selected by `s`, bit by bit,
+            // and compare it with `x`
+            for i in 0..I::NUM_BITS {
+                let mut decoded = s_bits
+                    .iter()
+                    .zip(&self.prefixes)
+                    .filter(|(_, prefix)| prefix.get_bit(i))
+                    .fold(false, |bit, (s_j, _)| bit ^ s_j);
+                if i < self.interval_sizes[0] {
+                    // whether the selected interval takes bit `i` from `y`
+                    let from_y = s_bits
+                        .iter()
+                        .zip(&self.interval_sizes)
+                        .filter(|(_, &k)| k > i)
+                        .fold(false, |bit, (s_j, _)| bit ^ s_j);
+                    decoded ^= gate.and(y.get_bit(i), from_y);
+                }
+                accept.push(gate, decoded ^ x.get_bit(i) ^ one);
+            }
+        }
+        accept.finish(one)
+    }
+
+    /// Alice's share of the accept bit, and the `us` of the gates for Bob.
+    fn evaluate_as_alice(
+        &self,
+        inputs_0: SeededInputShare,
+        encoding_0: SeededInputShare,
+        gsize: usize,
+        qs: &[Block],
+        delta: Block,
+    ) -> (bool, Vec<bool>) {
+        let (v0s, v1s) = cot_to_rot_sender_side::<u8>(qs, delta);
+        let encoding_0 = encoding_0.expand::<I>(2 * gsize);
+        let (ys_0, ss_0) = encoding_0.split_at(gsize);
+        let mut gate = AndGateUsingOTSender::new(&v0s, &v1s);
+        let accept = self.evaluate::<_, ALICE>(&mut gate, &inputs_0.expand(gsize), ys_0, ss_0);
+        (accept, gate.done_and_get_us())
+    }
+
+    /// The check of the inputs of a client, whose boolean shares are
+    /// `inputs_0` for Alice and `inputs_1` for Bob, encoded as `encodings`,
+    /// e.g. with `encode`. The servers reject a client whose encodings do not
+    /// encode its inputs below the bound. The COTs and the choices of their
+    /// additional OTs are expanded for client `uid`, see `bind_to` of the
+    /// messages.
+    ///
+    /// # Panics
+    /// If there is not one encoding per input.
+    pub fn sample_check<R: Rng>(
+        &self,
+        rng: &mut R,
+        uid: u64,
+        inputs_0: SeededInputShare,
+        inputs_1: &[BitsLE<I>],
+        encodings: &[(BitsLE<I>, BitsLE<I>)],
+        sec_param: usize,
+    ) -> (BoundedCheckToAlice, BoundedCheckToBob<I>) {
+        let gsize = inputs_1.len();
+        assert_eq!(encodings.len(), gsize, "one encoding per input");
+        let ys = encodings.iter().map(|(y, _)| y);
+        let ss = encodings.iter().map(|(_, s)| s);
+        let (encoding_0, encoding_1) = batch_make_boolean_shares(rng, ys.chain(ss));
+
+        let delta = COTGen::sample_delta(rng);
+        let qs_seed = COTSeed(Block::rand(rng));
+        // not `expand`, which counts the expansions of the OT sender
+        let num_ots = self.num_ots(gsize);
+        let qs = Zeroizing::new(
+            qs_seed
+                .for_client(uid, BOUNDED_AND_PURPOSE)
+                .generate(self.num_verified_ots(gsize, sec_param)),
+        );
+        let (gate_qs, _) = qs.split_at(num_ots);
+        let (_, us) = self.evaluate_as_alice(inputs_0, encoding_0, gsize, gate_qs, delta);
+
+        // the choices are the shares of Bob of the inputs of each gate,
+        // which depend on the `us` of the gates before it
+        let (v0s, v1s) = cot_to_rot_sender_side::<u8>(gate_qs, delta);
+        let (ys_1, ss_1) = encoding_1.split_at(gsize);
+        let mut gate = AndGateChoosingOTs::new(&v0s, &v1s, &us);
+        self.evaluate::<_, BOB>(&mut gate, inputs_1, ys_1, ss_1);
+        let r_seed = ChoiceSeed(rng.gen());
+        let r = r_seed
+            .for_client(uid, BOUNDED_R_PURPOSE)
+            .expand(qs.len() - num_ots);
+        let choices = gate_choices(gate.done_and_get_choices(), num_ots);
+        let and_ts = qs
+            .iter()
+            .zip(choices.into_iter().chain(r.iter()))
+            .map(|(q, choice)| if choice { q.add_gf(delta) } else { *q })
+            .collect();
+
+        (
+            BoundedCheckToAlice {
+                encoding_0,
+                and_cot: B2ACOTToAlice::new(delta, qs_seed),
+            },
+            BoundedCheckToBob {
+                encoding_1,
+                and_ts,
+                r_seed,
+            },
+        )
+    }
+
+    /// Alice's side of the check of `gsize` inputs, whose shares she has as
+    /// `inputs_0`: her share of the accept bit, the `us` to send to Bob, and
+    /// the verification of the COTs, with the coefficients of `chi_seed`,
+    /// which waits for the message of Bob.
+    pub fn check_as_alice(
+        &self,
+        inputs_0: SeededInputShare,
+        gsize: usize,
+        check: &BoundedCheckToAlice,
+        sec_param: usize,
+        chi_seed: u64,
+    ) -> (bool, Vec<bool>, AndOtCheck) {
+        let chi = self.chi(gsize, sec_param, chi_seed);
+        let qs = Zeroizing::new(check.and_cot.qs_seed.expand(chi.num_ots()));
+        let delta = check.and_cot.delta;
+        let ot_check = AndOtCheck {
+            q_til: inner_product_chunked(&qs, &chi),
+            delta,
+        };
+        let (gate_qs, _) = qs.split_at(self.num_ots(gsize));
+        let (accept, us) =
+            self.evaluate_as_alice(inputs_0, check.encoding_0, gsize, gate_qs, delta);
+        (accept, us, ot_check)
+    }
+
+    /// Bob's side of the check of `inputs_1`, with the `us` of Alice: his
+    /// share of the accept bit, and `x_til` and `t_til` of the verification
+    /// of the COTs with the coefficients of `chi_seed`, for Alice. Fails if
+    /// the encodings, the COTs or the `us` are not for `inputs_1.len()`
+    /// inputs.
+    pub fn check_as_bob(
+        &self,
+        inputs_1: &[BitsLE<I>],
+        check: &BoundedCheckToBob<I>,
+        us: &[bool],
+        sec_param: usize,
+        chi_seed: u64,
+    ) -> Result<(bool, (Block, TTil)), ProtocolError> {
+        let gsize = inputs_1.len();
+        let num_ots = self.num_ots(gsize);
+        let num_verified = self.num_verified_ots(gsize, sec_param);
+        check_len("encodings", 2 * gsize, check.encoding_1.len())?;
+        check_len("AND gate OTs", num_verified, check.and_ts.len())?;
+        check_len("AND gate us", 2 * self.num_and_gates(gsize), us.len())?;
+        let (gate_ts, _) = check.and_ts.split_at(num_ots);
+        let v_selected = cot_to_rot_receiver_side::<u8>(gate_ts);
+        let (ys_1, ss_1) = check.encoding_1.split_at(gsize);
+        let mut gate = RecordingChoices::new(AndGateUsingOTReceiver::new(&v_selected, us));
+        let accept = self.evaluate::<_, BOB>(&mut gate, inputs_1, ys_1, ss_1);
+
+        let mut choices = gate_choices(gate.choices, num_ots);
+        choices.extend(check.r_seed.expand(num_verified - num_ots).iter());
+        let chi = self.chi(gsize, sec_param, chi_seed);
+        let ot_check = OTReceiver::send_x_til_t_til_with_choices(&check.and_ts, &chi, &choices);
+        choices.zeroize();
+        Ok((accept, ot_check?))
+    }
+}
+
+/// The choices of the gates, then `false` for the OTs after the gates, up to
+/// `num_ots`.
+fn gate_choices(mut choices: Vec<bool>, num_ots: usize) -> Vec<bool> {
+    choices.resize(num_ots, false);
+    choices
+}
+
+/// Alice's side of the verification of the COTs of the gates.
+pub struct AndOtCheck {
+    q_til: GF2_256,
+    delta: Block,
+}
+
+impl AndOtCheck {
+    /// Whether `x_til` and `t_til` of Bob are consistent with my COTs, see
+    /// `OTSender::verify_and_get_cot`.
+    pub fn verify(&self, x_til: Block, t_til: TTil) -> bool {
+        t_til.matches(self.q_til.add_gf(self.delta.mul_gf_no_reduction(x_til)))
+    }
+}
+
+/// An AND gate of the OT receiver that records the choices of its OTs, `y`
+/// then `x` of each gate, i.e. the shares of the receiver of its inputs.
+struct RecordingChoices<G> {
+    gate: G,
+    choices: Vec<bool>,
+}
+
+impl<G: AndGate> RecordingChoices<G> {
+    fn new(gate: G) -> Self {
+        Self {
+            gate,
+            choices: Vec::new(),
+        }
+    }
+}
+
+impl<G: AndGate> AndGate for RecordingChoices<G> {
+    fn and(&mut self, x: bool, y: bool) -> bool {
+        self.choices.extend([y, x]);
+        self.gate.and(x, y)
+    }
+}
+
+/// Running AND of shared bits, with one gate per bit after the first.
+#[derive(Default)]
+struct Conjunction(Option<bool>);
+
+impl Conjunction {
+    fn push<G: AndGate>(&mut self, gate: &mut G, bit: bool) {
+        self.0 = Some(match self.0 {
+            Some(acc) => gate.and(acc, bit),
+            None => bit,
+        });
+    }
+
+    /// The conjunction, or the share of `true` without any bit.
+    fn finish(self, one: bool) -> bool {
+        self.0.unwrap_or(one)
+    }
+}
+
+/// Part of the message to Alice for the range check.
+#[derive(Debug, Clone)]
+pub struct BoundedCheckToAlice {
+    /// Seed of the shares of `y` of every input, followed by those of `s`.
+    pub encoding_0: SeededInputShare,
+    /// COTs of the AND gates, with Alice as OT sender.
+    pub and_cot: B2ACOTToAlice,
+}
+
+impl BoundedCheckToAlice {
+    /// Replace the seed of the COTs by the one that client `uid` expanded,
+    /// like `B2ACOTToAlice::bind_to`.
+    pub fn bind_to(&mut self, uid: u64) {
+        self.and_cot.qs_seed = self.and_cot.qs_seed.for_client(uid, BOUNDED_AND_PURPOSE);
+    }
+}
+
+impl Communicate for BoundedCheckToAlice {
+    type Deserialized = Self;
+
+    fn size_in_bytes(&self) -> usize {
+        self.encoding_0.use_cast().size_in_bytes() + self.and_cot.size_in_bytes()
+    }
+
+    fn to_bytes<W: Write>(&self, mut dest: W) {
+        self.encoding_0.use_cast().to_bytes(&mut dest);
+        self.and_cot.to_bytes(dest);
+    }
+
+    fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+        let encoding_0 = UseCast::from_bytes(&mut bytes)?;
+        let and_cot = B2ACOTToAlice::from_bytes(bytes)?;
+        Ok(BoundedCheckToAlice {
+            encoding_0,
+            and_cot,
+        })
+    }
+}
+
+/// Part of the message to Bob for the range check. Wiped when dropped.
+#[derive(Debug, Clone)]
+pub struct BoundedCheckToBob<I: UInt> {
+    /// Shares of `y` of every input, followed by those of `s`.
+    pub encoding_1: Vec<BitsLE<I>>,
+    /// COTs of the AND gates, with Bob as OT receiver, then the additional
+    /// OTs of their verification.
+    pub and_ts: Vec<Block>,
+    /// Seed of the choices of the additional OTs.
+    pub r_seed: ChoiceSeed,
+}
+
+impl<I: UInt> BoundedCheckToBob<I> {
+    /// Replace the choice seed by the one that client `uid` expanded, like
+    /// `B2ACOTToBob::bind_to`.
+    pub fn bind_to(&mut self, uid: u64) {
+        self.r_seed = self.r_seed.for_client(uid, BOUNDED_R_PURPOSE);
+    }
+}
+
+impl<I: UInt> Zeroize for BoundedCheckToBob<I> {
+    fn zeroize(&mut self) {
+        self.encoding_1.iter_mut().for_each(|bits| bits.0.zeroize());
+        self.and_ts.zeroize();
+        self.r_seed.zeroize();
+    }
+}
+
+impl<I: UInt> Drop for BoundedCheckToBob<I> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl<I: UInt> ZeroizeOnDrop for BoundedCheckToBob<I> {}
+
+impl<I: UInt> Communicate for BoundedCheckToBob<I> {
+    type Deserialized = Self;
+
+    fn size_in_bytes(&self) -> usize {
+        self.encoding_1.size_in_bytes()
+            + self.and_ts.size_in_bytes()
+            + self.r_seed.use_cast().size_in_bytes()
+    }
+
+    fn to_bytes<W: Write>(&self, mut dest: W) {
+        self.encoding_1.to_bytes(&mut dest);
+        self.and_ts.to_bytes(&mut dest);
+        self.r_seed.use_cast().to_bytes(dest);
+    }
+
+    fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+        let encoding_1 = Vec::from_bytes(&mut bytes)?;
+        let and_ts = Vec::from_bytes(&mut bytes)?;
+        let r_seed = UseCast::<ChoiceSeed>::from_bytes(bytes)?;
+        Ok(BoundedCheckToBob {
+            encoding_1,
+            and_ts,
+            r_seed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bits::batch_make_boolean_shares, cot::client::DEFAULT_SEC_PARAM};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    const CHI_SEED: u64 = 42;
+
+    /// AND of plaintext bits, to evaluate the circuit as Alice holding both
+    /// shares.
+    struct PlainAndGate;
+
+    impl AndGate for PlainAndGate {
+        fn and(&mut self, x: bool, y: bool) -> bool {
+            x & y
+        }
+    }
+
+    fn accepts(bound: &InputBound<u8>, x: u8, y: u8, s: u8) -> bool {
+        bound.evaluate::<_, ALICE>(&mut PlainAndGate, &[BitsLE(x)], &[BitsLE(y)], &[BitsLE(s)])
+    }
+
+    #[test]
+    fn test_circuit() {
+        for b in [1u8, 2, 11, 64, 100, 128, 255] {
+            let bound = InputBound::new(b).unwrap();
+            for x in 0..b {
+                let (y, s) = bound.encode(x).unwrap();
+                assert!(accepts(&bound, x, y.0, s.0), "{} < {}", x, b);
+            }
+            assert_eq!(
+                bound.encode(b),
+                Err(BoundError::OutOfRange {
+                    input: b as u128,
+                    bound: b as u128
+                })
+            );
+            // no encoding passes for an input out of range
+            for x in b..=u8::MAX {
+                for s in 0..1u16 << bound.num_intervals() {
+                    for y in 0..=u8::MAX {
+                        assert!(!accepts(&bound, x, y, s as u8), "{} >= {}", x, b);
+                    }
+                }
+            }
+        }
+        assert_eq!(InputBound::new(0u8), Err(BoundError::Zero));
+    }
+
+    /// Run the check of `inputs` encoded as the encodings of `encoded`, with
+    /// the COTs of Bob changed by `tamper`. Return the accept bit, and
+    /// whether the COTs pass verification.
+    fn run_check(
+        rng: &mut StdRng,
+        bound: &InputBound<u8>,
+        inputs: &[u8],
+        encoded: &[u8],
+        tamper: impl FnOnce(&mut Vec<Block>),
+    ) -> (bool, bool) {
+        let (inputs_0, inputs_1) =
+            batch_make_boolean_shares(rng, inputs.iter().map(|x| x.bits_le()));
+        let encodings = encoded
+            .iter()
+            .map(|&x| bound.encode(x).unwrap())
+            .collect::<Vec<_>>();
+        let (mut to_alice, mut to_bob) =
+            bound.sample_check(rng, 3, inputs_0, &inputs_1, &encodings, DEFAULT_SEC_PARAM);
+        to_alice.bind_to(3);
+        to_bob.bind_to(3);
+        tamper(&mut to_bob.and_ts);
+        let (accept_0, us, ot_check) = bound.check_as_alice(
+            inputs_0,
+            inputs.len(),
+            &to_alice,
+            DEFAULT_SEC_PARAM,
+            CHI_SEED,
+        );
+        assert_eq!(us.len(), 2 * bound.num_and_gates(inputs.len()));
+        assert_eq!(
+            to_bob.and_ts.len(),
+            bound.num_verified_ots(inputs.len(), DEFAULT_SEC_PARAM)
+        );
+        let (accept_1, (x_til, t_til)) = bound
+            .check_as_bob(&inputs_1, &to_bob, &us, DEFAULT_SEC_PARAM, CHI_SEED)
+            .unwrap();
+        (accept_0 ^ accept_1, ot_check.verify(x_til, t_til))
+    }
+
+    #[test]
+    fn test_check() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let bound = InputBound::new(64u8).unwrap();
+        let inputs = (0..10).map(|_| rng.gen_range(0..64)).collect::<Vec<u8>>();
+        let mut run = |inputs: &[u8], encoded: &[u8]| {
+            let (accept, verified) = run_check(&mut rng, &bound, inputs, encoded, |_| {});
+            assert!(verified);
+            accept
+        };
+
+        assert!(run(&inputs, &inputs));
+        // an input out of range, with the encoding of another one
+        let mut out_of_range = inputs.clone();
+        out_of_range[3] = 64 + inputs[3];
+        assert!(!run(&out_of_range, &inputs));
+        // an encoding of another input in range
+        assert!(!run(&inputs, &[&inputs[1..], &inputs[..1]].concat()));
+    }
+
+    #[test]
+    fn test_check_verifies_cots() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let bound = InputBound::new(64u8).unwrap();
+        let inputs = (0..10).map(|_| rng.gen_range(0..64)).collect::<Vec<u8>>();
+        // flipping the choice of a gate OT, or of an additional OT, makes the
+        // COTs inconsistent
+        let last = bound.num_verified_ots(inputs.len(), DEFAULT_SEC_PARAM) - 1;
+        for i in [0, 5, last] {
+            let delta_guess = Block::rand(&mut rng);
+            let (_, verified) = run_check(&mut rng, &bound, &inputs, &inputs, |ts| {
+                ts[i] = ts[i].add_gf(delta_guess)
+            });
+            assert!(!verified, "OT {}", i);
+        }
+    }
+
+    #[test]
+    fn test_check_wrong_lengths() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let bound = InputBound::new(64u8).unwrap();
+        let inputs = [1u8, 2, 3];
+        let (inputs_0, inputs_1) =
+            batch_make_boolean_shares(&mut rng, inputs.iter().map(|x| x.bits_le()));
+        let encodings = inputs.map(|x| bound.encode(x).unwrap());
+        let (to_alice, mut to_bob) = bound.sample_check(
+            &mut rng,
+            0,
+            inputs_0,
+            &inputs_1,
+            &encodings,
+            DEFAULT_SEC_PARAM,
+        );
+        let (_, us, _) = bound.check_as_alice(
+            inputs_0,
+            inputs.len(),
+            &to_alice,
+            DEFAULT_SEC_PARAM,
+            CHI_SEED,
+        );
+
+        assert!(matches!(
+            bound.check_as_bob(&inputs_1, &to_bob, &us[1..], DEFAULT_SEC_PARAM, CHI_SEED),
+            Err(ProtocolError::LengthMismatch {
+                what: "AND gate us",
+                ..
+            })
+        ));
+        to_bob.and_ts.pop();
+        assert!(matches!(
+            bound.check_as_bob(&inputs_1, &to_bob, &us, DEFAULT_SEC_PARAM, CHI_SEED),
+            Err(ProtocolError::LengthMismatch {
+                what: "AND gate OTs",
+                ..
+            })
+        ));
+    }
+}
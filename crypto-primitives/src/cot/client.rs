@@ -1,34 +1,74 @@
 //! Client side algorithms for generating ROT.
 
-use crate::{bits::BitsLE, uint::UInt};
+use crate::{
+    bits::{BitsLE, PackedBits},
+    uint::UInt,
+};
 use block::Block;
 use rand::Rng;
 use serialize::{AsUseCast, Communicate, UseCast};
 use std::{
-    io::{Read, Write},
+    io::{self, Read, Write},
     mem::size_of,
 };
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-use super::{COTSeed, ChoiceSeed};
+use super::{
+    dims::{DimsError, ProtocolDims},
+    COTSeed, ChoiceSeed, B2A_QS_PURPOSE, B2A_R_PURPOSE,
+};
 
 /// Generate ROT.
 pub struct COTGen {}
 
+/// Statistical security parameter used when none is specified.
+pub const DEFAULT_SEC_PARAM: usize = 40;
+
+/// Computational security parameter, i.e. the bit length of an OT block.
+const COMP_SEC_PARAM: usize = 128;
+
+/// Batches up to `2^MIN_LOG_NUM_OT` OTs are charged as if they had exactly that
+/// many OTs, so that the parameters do not change with small `gsize`.
+const MIN_LOG_NUM_OT: usize = 26;
+
 /// Given `gsize` and `wsize`, we need `gsize * wsize` OTs and some additional
 /// OTs for verification. This function returns the number of additional OTs
 /// needed for verification.
-/// `num_ot_used`: `gsize * wsize`
-pub fn num_additional_ot_needed(_num_ot_used: usize) -> usize {
-    194
+///
+/// The random tail must hide the receiver's choice bits from a single
+/// `GF(2^128)` linear combination (`COMP_SEC_PARAM` bits), and the check must
+/// fail with probability `2^-sec_param` even after a union bound over all the
+/// OTs in the batch (`log2(num_ot_used)` bits).
+/// * `num_ot_used`: `gsize * wsize`
+/// * `sec_param`: statistical security parameter, e.g. `DEFAULT_SEC_PARAM`
+pub fn num_additional_ot_needed(num_ot_used: usize, sec_param: usize) -> usize {
+    let log_num_ot = num_ot_used.next_power_of_two().trailing_zeros() as usize;
+    COMP_SEC_PARAM + sec_param + log_num_ot.max(MIN_LOG_NUM_OT)
 }
 
 #[derive(Clone, Debug, Default)]
-/// For B2A, Alice is always the OT sender.
+/// For B2A, Alice is always the OT sender. Wiped when dropped.
 pub struct B2ACOTToAlice {
     pub delta: Block,
     pub qs_seed: COTSeed,
 }
 
+impl Zeroize for B2ACOTToAlice {
+    fn zeroize(&mut self) {
+        self.delta.zeroize();
+        self.qs_seed.zeroize();
+    }
+}
+
+impl Drop for B2ACOTToAlice {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for B2ACOTToAlice {}
+
 impl Communicate for B2ACOTToAlice {
     type Deserialized = Self;
 
@@ -52,40 +92,143 @@ impl B2ACOTToAlice {
     pub fn new(delta: Block, qs_seed: COTSeed) -> Self {
         B2ACOTToAlice { delta, qs_seed }
     }
+
+    /// Replace the seed by the one that client `uid` expanded, see
+    /// `COTSeed::for_client`. The servers bind the COTs of a client once
+    /// they hashed its messages, before expanding them.
+    pub fn bind_to(&mut self, uid: u64) {
+        self.qs_seed = self.qs_seed.for_client(uid, B2A_QS_PURPOSE);
+    }
 }
 
 #[derive(Clone, Debug, Default)]
-/// For B2A, Bob is always the OT receiver.
+/// For B2A, Bob is always the OT receiver. Wiped when dropped.
+///
+/// The OTs are `ts_main || ts_tail`: `ts_main` has the choices of the inputs,
+/// and `ts_tail` the random choices of `r_seed`, e.g. the additional OTs of
+/// verification. They are stored contiguously, since OT verification covers
+/// both segments with one inner product.
 pub struct B2ACOTToBob {
     pub r_seed: ChoiceSeed,
-    pub ts: Vec<Block>,
+    ts: Vec<Block>,
+    /// Number of OTs of `ts_main`, at most `ts.len()`.
+    split: usize,
+}
+
+impl Zeroize for B2ACOTToBob {
+    fn zeroize(&mut self) {
+        self.r_seed.zeroize();
+        self.ts.zeroize();
+        self.split = 0;
+    }
+}
+
+impl Drop for B2ACOTToBob {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
 }
 
+impl ZeroizeOnDrop for B2ACOTToBob {}
+
+/// The choice seed, the number of OTs of `ts_main`, then all OTs. Fails on a
+/// split after the last OT.
 impl Communicate for B2ACOTToBob {
     type Deserialized = Self;
 
     fn size_in_bytes(&self) -> usize {
-        size_of::<ChoiceSeed>() + self.ts.len() * size_of::<Block>()
+        size_of::<ChoiceSeed>() + size_of::<u64>() + self.ts.size_in_bytes()
     }
 
     fn to_bytes<W: Write>(&self, mut dest: W) {
         self.r_seed.use_cast().to_bytes(&mut dest);
+        (self.split as u64).use_cast().to_bytes(&mut dest);
         self.ts.to_bytes(&mut dest);
     }
 
     fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
         let r_seed = UseCast::<ChoiceSeed>::from_bytes(&mut bytes)?;
+        let split = UseCast::<u64>::from_bytes(&mut bytes)?;
         let ts = <Vec<Block>>::from_bytes(&mut bytes)?;
-        Ok(B2ACOTToBob { r_seed, ts })
+        if split > ts.len() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "split of the OTs is after the last OT",
+            )
+            .into());
+        }
+        Ok(B2ACOTToBob {
+            r_seed,
+            ts,
+            split: split as usize,
+        })
     }
 }
 
 impl B2ACOTToBob {
-    pub fn new(r_seed: ChoiceSeed, ts: Vec<Block>) -> Self {
-        B2ACOTToBob { r_seed, ts }
+    /// COTs `ts`, whose last `num_tail` OTs are `ts_tail`.
+    ///
+    /// # Panics
+    /// Panics if `ts` has fewer than `num_tail` OTs.
+    pub fn new(r_seed: ChoiceSeed, ts: Vec<Block>, num_tail: usize) -> Self {
+        let split = ts
+            .len()
+            .checked_sub(num_tail)
+            .expect("tail is longer than the OTs");
+        B2ACOTToBob { r_seed, ts, split }
+    }
+
+    /// Same as `B2ACOTToAlice::bind_to`, for the choice seed.
+    pub fn bind_to(&mut self, uid: u64) {
+        self.r_seed = self.r_seed.for_client(uid, B2A_R_PURPOSE);
+    }
+
+    /// All OTs, `ts_main || ts_tail`, as verified by
+    /// `OTReceiver::send_x_til_t_til`.
+    pub fn ts(&self) -> &[Block] {
+        &self.ts
+    }
+
+    /// Keep the first `len` OTs, cutting the tail first, e.g. to test
+    /// servers with a client that is short of OTs.
+    pub fn truncate(&mut self, len: usize) {
+        self.ts.truncate(len);
+        self.split = self.split.min(len);
+    }
+
+    /// OTs of the inputs, as split by the client.
+    pub fn main(&self) -> &[Block] {
+        &self.ts[..self.split]
+    }
+
+    /// OTs of the choices of `r_seed`, as split by the client.
+    pub fn tail(&self) -> &[Block] {
+        &self.ts[self.split..]
+    }
+
+    /// The OTs split before the last `n`, e.g. the `num_additional` of
+    /// `ProtocolDims`. Servers split by their own dims instead of trusting
+    /// `main` and `tail`, once the number of OTs is checked.
+    ///
+    /// # Panics
+    /// Panics if there are fewer than `n` OTs.
+    pub fn split_tail(&self, n: usize) -> (&[Block], &[Block]) {
+        let split = self
+            .ts
+            .len()
+            .checked_sub(n)
+            .expect("tail is longer than the OTs");
+        self.ts.split_at(split)
     }
 }
 
+/// For reverse B2A, on the inputs of a client after its split, Alice is the
+/// OT receiver, with her boolean shares as choices.
+pub type B2ACOTToAliceReverse = B2ACOTToBob;
+
+/// For reverse B2A, Bob is the OT sender.
+pub type B2ACOTToBobReverse = B2ACOTToAlice;
+
 impl COTGen {
     /// Sample Delta
     pub fn sample_delta<R: Rng>(rng: &mut R) -> Block {
@@ -105,38 +248,70 @@ impl COTGen {
     ///
     /// * `Q`: sampled uniformly at random.
     /// * `T = Q + input_1 * delta` where choice is in `{0, 1}`, in `choices`.
+    ///
     /// In addition, this function will sample `Q'` and `T'` where choice is
     /// random.
+    ///
     /// * `Q'`: sampled uniformly at random.
     /// * `T' = Q' + choice * delta` where choice is in `{0, 1}`, in `r`
     ///   (randomly generated).
     ///
     /// This function will return a seed to generate `Q||Q'` and delta for OT
     /// sender; a seed to generate `r`, and `T||T'` for OT receiver.
+    ///
+    /// The seeds are expanded as sent, as in the legacy protocol. Clients use
+    /// `sample_cots_for`.
     pub fn sample_cots<R: Rng, T: UInt>(
         rng: &mut R,
         inputs_1: &[BitsLE<T>],
         delta: Block,
         num_additional: usize,
+    ) -> (B2ACOTToAlice, B2ACOTToBob) {
+        Self::sample_cots_with(rng, None, inputs_1, delta, num_additional)
+    }
+
+    /// Same as `sample_cots`, for client `uid`: the seeds are expanded as the
+    /// servers expand them once bound to `uid`, see `B2ACOTToAlice::bind_to`.
+    pub fn sample_cots_for<R: Rng, T: UInt>(
+        rng: &mut R,
+        uid: u64,
+        inputs_1: &[BitsLE<T>],
+        delta: Block,
+        num_additional: usize,
+    ) -> (B2ACOTToAlice, B2ACOTToBob) {
+        Self::sample_cots_with(rng, Some(uid), inputs_1, delta, num_additional)
+    }
+
+    fn sample_cots_with<R: Rng, T: UInt>(
+        rng: &mut R,
+        uid: Option<u64>,
+        inputs_1: &[BitsLE<T>],
+        delta: Block,
+        num_additional: usize,
     ) -> (B2ACOTToAlice, B2ACOTToBob) {
         let cot_rng_seed = COTSeed(Block::rand(rng));
         let choice_rng_seed = ChoiceSeed(rng.next_u64());
+        let (qs_seed, r_seed) = match uid {
+            Some(uid) => (
+                cot_rng_seed.for_client(uid, B2A_QS_PURPOSE),
+                choice_rng_seed.for_client(uid, B2A_R_PURPOSE),
+            ),
+            None => (cot_rng_seed, choice_rng_seed),
+        };
 
         let choices = inputs_1.iter().flat_map(|x| x.iter());
 
-        let r = choice_rng_seed.expand(num_additional);
+        let dims = ProtocolDims::with_num_additional::<T>(inputs_1.len(), num_additional)
+            .expect("invalid number of OTs");
+        let r = r_seed.expand(dims.num_additional());
 
         let choices = choices.chain(r.iter());
 
-        let ts = cot_rng_seed.expand_selected(
-            inputs_1.len() * T::NUM_BITS + num_additional,
-            delta,
-            choices,
-        );
+        let ts = qs_seed.expand_selected(dims.num_total(), delta, choices);
 
         (
             B2ACOTToAlice::new(delta, cot_rng_seed),
-            B2ACOTToBob::new(choice_rng_seed, ts),
+            B2ACOTToBob::new(choice_rng_seed, ts, dims.num_additional()),
         )
     }
 
@@ -153,8 +328,10 @@ impl COTGen {
     ///
     /// * `Q`: sampled uniformly at random.
     /// * `T = Q + input_1 * delta` where choice is in `{0, 1}`, in `choices`.
+    ///
     /// In addition, this function will sample `Q'` and `T'` where choice is
     /// random.
+    ///
     /// * `Q'`: sampled uniformly at random.
     /// * `T' = Q' + choice * delta` where choice is in `{0, 1}`, in `r`
     ///   (randomly generated).
@@ -167,13 +344,61 @@ impl COTGen {
         num_choice_bits: usize,
         delta: Block,
         num_additional: usize,
+    ) -> (B2ACOTToAlice, B2ACOTToBob) {
+        Self::sample_cots_using_selected_bits_with(
+            rng,
+            None,
+            choice_bits,
+            num_choice_bits,
+            delta,
+            num_additional,
+        )
+    }
+
+    /// Same as `sample_cots_using_selected_bits`, for client `uid`, as
+    /// `sample_cots_for`.
+    pub fn sample_cots_using_selected_bits_for<R: Rng>(
+        rng: &mut R,
+        uid: u64,
+        choice_bits: impl IntoIterator<Item = bool>,
+        num_choice_bits: usize,
+        delta: Block,
+        num_additional: usize,
+    ) -> (B2ACOTToAlice, B2ACOTToBob) {
+        Self::sample_cots_using_selected_bits_with(
+            rng,
+            Some(uid),
+            choice_bits,
+            num_choice_bits,
+            delta,
+            num_additional,
+        )
+    }
+
+    fn sample_cots_using_selected_bits_with<R: Rng>(
+        rng: &mut R,
+        uid: Option<u64>,
+        choice_bits: impl IntoIterator<Item = bool>,
+        num_choice_bits: usize,
+        delta: Block,
+        num_additional: usize,
     ) -> (B2ACOTToAlice, B2ACOTToBob) {
         let cot_rng_seed = COTSeed(Block::rand(rng));
         let choice_rng_seed = ChoiceSeed(rng.next_u64());
+        let (qs_seed, r_seed) = match uid {
+            Some(uid) => (
+                cot_rng_seed.for_client(uid, B2A_QS_PURPOSE),
+                choice_rng_seed.for_client(uid, B2A_R_PURPOSE),
+            ),
+            None => (cot_rng_seed, choice_rng_seed),
+        };
 
-        let qs = cot_rng_seed.expand(num_choice_bits + num_additional);
+        let num_cots = num_choice_bits
+            .checked_add(num_additional)
+            .expect("number of OTs overflows");
+        let qs = qs_seed.generate(num_cots);
 
-        let r = choice_rng_seed.expand(num_additional);
+        let r = r_seed.expand(num_additional);
 
         let choices = choice_bits.into_iter().chain(r.iter());
 
@@ -185,7 +410,310 @@ impl COTGen {
 
         (
             B2ACOTToAlice::new(delta, cot_rng_seed),
-            B2ACOTToBob::new(choice_rng_seed, ts),
+            B2ACOTToBob::new(choice_rng_seed, ts, num_additional),
         )
     }
 }
+
+/// Error of a COT pair whose OTs do not match the choices, see
+/// [`validate_cot_pair`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CotInconsistency {
+    #[error(transparent)]
+    Dims(#[from] DimsError),
+    #[error("OT receiver has {actual} OTs, but {expected} are needed")]
+    WrongNumOts { expected: usize, actual: usize },
+    #[error("delta is zero, so the OTs reveal the choices")]
+    ZeroDelta,
+    #[error("OT of bit {bit} of input {input} does not match the choice")]
+    InputMismatch { input: usize, bit: usize },
+    #[error("additional OT {index} does not match the choice seed")]
+    AdditionalMismatch { index: usize },
+}
+
+/// Check the COTs of a client before sending them: the OT receiver must have
+/// the OTs of `inputs_1` and the additional OTs that `sec_param` needs, and
+/// each of them must be `q + choice * delta`, with the choices `inputs_1`
+/// followed by the bits of `r_seed`. The client knows both sides, so this is
+/// a self-check, not a check a server can run. It costs as much as sampling
+/// the COTs. The COTs are the ones of client `uid`, see
+/// `COTGen::sample_cots_for`.
+pub fn validate_cot_pair<I: UInt>(
+    uid: u64,
+    to_alice: &B2ACOTToAlice,
+    to_bob: &B2ACOTToBob,
+    inputs_1: &[BitsLE<I>],
+    sec_param: usize,
+) -> Result<(), CotInconsistency> {
+    let dims = ProtocolDims::new::<I>(inputs_1.len(), sec_param)?;
+    if to_bob.ts().len() != dims.num_total() {
+        return Err(CotInconsistency::WrongNumOts {
+            expected: dims.num_total(),
+            actual: to_bob.ts().len(),
+        });
+    }
+    if to_alice.delta == Block::default() {
+        return Err(CotInconsistency::ZeroDelta);
+    }
+    // not `expand`, which counts the expansions of the OT sender
+    let qs_seed = to_alice.qs_seed.for_client(uid, B2A_QS_PURPOSE);
+    let qs = Zeroizing::new(qs_seed.generate(dims.num_total()));
+    let r = to_bob
+        .r_seed
+        .for_client(uid, B2A_R_PURPOSE)
+        .expand(dims.num_additional());
+    let choices = inputs_1.iter().flat_map(|x| x.iter()).chain(r.iter());
+    for (i, ((q, t), choice)) in qs.iter().zip(to_bob.ts()).zip(choices).enumerate() {
+        let expected = if choice { q.add_gf(to_alice.delta) } else { *q };
+        if *t != expected {
+            return Err(if i < dims.num_ot() {
+                CotInconsistency::InputMismatch {
+                    input: i / I::NUM_BITS,
+                    bit: i % I::NUM_BITS,
+                }
+            } else {
+                CotInconsistency::AdditionalMismatch {
+                    index: i - dims.num_ot(),
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Same as `validate_cot_pair`, for COTs of single bits without additional
+/// OTs, as sampled by `COTGen::sample_cots_using_selected_bits_for`: one OT
+/// per bit of `choices`. A mismatch is reported on bit 0 of its input.
+pub fn validate_bit_cots(
+    uid: u64,
+    to_alice: &B2ACOTToAlice,
+    to_bob: &B2ACOTToBob,
+    choices: &PackedBits,
+) -> Result<(), CotInconsistency> {
+    if to_bob.ts().len() != choices.len() {
+        return Err(CotInconsistency::WrongNumOts {
+            expected: choices.len(),
+            actual: to_bob.ts().len(),
+        });
+    }
+    if to_alice.delta == Block::default() {
+        return Err(CotInconsistency::ZeroDelta);
+    }
+    let qs_seed = to_alice.qs_seed.for_client(uid, B2A_QS_PURPOSE);
+    let qs = Zeroizing::new(qs_seed.generate(choices.len()));
+    for (input, ((q, t), choice)) in qs.iter().zip(to_bob.ts()).zip(choices.iter()).enumerate() {
+        let expected = if choice { q.add_gf(to_alice.delta) } else { *q };
+        if *t != expected {
+            return Err(CotInconsistency::InputMismatch { input, bit: 0 });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use block::Block;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serialize::{AsUseCast, Communicate};
+    use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+    use super::{
+        num_additional_ot_needed, validate_cot_pair, B2ACOTToAlice, B2ACOTToBob, COTGen,
+        CotInconsistency, DEFAULT_SEC_PARAM,
+    };
+    use crate::{
+        bits::{batch_make_boolean_shares, BitsLE},
+        cot::{dims::ProtocolDims, COTSeed, ChoiceSeed, DOMAIN_SEPARATED_SEEDS},
+    };
+
+    const UID: u64 = 3;
+
+    fn assert_zeroize_on_drop<T: ZeroizeOnDrop>() {}
+
+    #[test]
+    fn test_cots_are_wiped() {
+        assert_zeroize_on_drop::<B2ACOTToAlice>();
+        assert_zeroize_on_drop::<B2ACOTToBob>();
+
+        let mut rng = StdRng::seed_from_u64(12345);
+        let (_, inputs_1) =
+            batch_make_boolean_shares(&mut rng, [BitsLE(0x12u8), BitsLE(0x34)].iter());
+        let delta = COTGen::sample_delta(&mut rng);
+        let (mut cot_alice, mut cot_bob) = COTGen::sample_cots(&mut rng, &inputs_1, delta, 10);
+        assert_ne!(cot_alice.delta, Block::default());
+        assert_ne!(cot_bob.ts().len(), 0);
+
+        cot_alice.zeroize();
+        cot_bob.zeroize();
+        assert_eq!(cot_alice.delta, Block::default());
+        assert_eq!(cot_alice.qs_seed.0, Block::default());
+        assert_eq!(cot_bob.r_seed.0, 0);
+        assert!(cot_bob.ts().is_empty());
+
+        let mut seeds = (COTSeed(Block::rand(&mut rng)), ChoiceSeed(7));
+        seeds.0.zeroize();
+        seeds.1.zeroize();
+        assert_eq!((seeds.0 .0, seeds.1 .0), (Block::default(), 0));
+    }
+
+    #[test]
+    fn test_expanded_qs_in_zeroizing() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let seed = COTSeed(Block::rand(&mut rng));
+        let qs = seed.generate(100);
+        let ptr = qs.as_ptr();
+        // wrapping moves the vector, and the slice APIs take it by deref
+        let qs = Zeroizing::new(qs);
+        assert_eq!(qs.as_ptr(), ptr);
+        let slice: &[Block] = &qs;
+        assert_eq!(slice, &seed.generate(100)[..]);
+    }
+
+    #[test]
+    fn test_num_additional_ot_default() {
+        for num_ot in [0, 1, 8, 1000, 62000 * 8, 800000 * 32] {
+            assert_eq!(num_additional_ot_needed(num_ot, DEFAULT_SEC_PARAM), 194);
+        }
+    }
+
+    #[test]
+    fn test_num_additional_ot_monotone() {
+        let num_ots = (0..40).map(|i| 1usize << i).collect::<Vec<_>>();
+        for sec_param in [20, 40, 64, 80] {
+            for w in num_ots.windows(2) {
+                assert!(
+                    num_additional_ot_needed(w[0], sec_param)
+                        <= num_additional_ot_needed(w[1], sec_param)
+                );
+            }
+        }
+        for num_ot in num_ots {
+            for sec_param in 1..128 {
+                assert!(
+                    num_additional_ot_needed(num_ot, sec_param)
+                        < num_additional_ot_needed(num_ot, sec_param + 1)
+                );
+            }
+        }
+        // larger batches need more OTs once past the default bound
+        assert!(
+            num_additional_ot_needed(1 << 30, DEFAULT_SEC_PARAM)
+                > num_additional_ot_needed(1 << 20, DEFAULT_SEC_PARAM)
+        );
+    }
+
+    #[test]
+    fn test_validate_cot_pair() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        let inputs = (0..6u8).map(|x| BitsLE(x * 41)).collect::<Vec<_>>();
+        let (_, mut inputs_1) = batch_make_boolean_shares(&mut rng, inputs.iter());
+        let delta = COTGen::sample_delta(&mut rng);
+        let num_additional = num_additional_ot_needed(6 * 8, DEFAULT_SEC_PARAM);
+        let (cot_alice, mut cot_bob) =
+            COTGen::sample_cots_for(&mut rng, UID, &inputs_1, delta, num_additional);
+        assert_eq!(
+            validate_cot_pair(UID, &cot_alice, &cot_bob, &inputs_1, DEFAULT_SEC_PARAM),
+            Ok(())
+        );
+        // validating does not count as an expansion of the OT sender
+        assert_eq!(cot_alice.qs_seed.expansion_count(), 0);
+
+        // OTs for other choices: bit 3 of input 4 flipped
+        inputs_1[4].0 ^= 1 << 3;
+        assert_eq!(
+            validate_cot_pair(UID, &cot_alice, &cot_bob, &inputs_1, DEFAULT_SEC_PARAM),
+            Err(CotInconsistency::InputMismatch { input: 4, bit: 3 })
+        );
+        inputs_1[4].0 ^= 1 << 3;
+
+        // not enough additional OTs for the security parameter
+        assert_eq!(
+            validate_cot_pair(UID, &cot_alice, &cot_bob, &inputs_1, DEFAULT_SEC_PARAM + 1),
+            Err(CotInconsistency::WrongNumOts {
+                expected: 6 * 8 + num_additional + 1,
+                actual: 6 * 8 + num_additional,
+            })
+        );
+
+        // choice seed that does not match the additional OTs
+        let r_seed = cot_bob.r_seed;
+        cot_bob.r_seed = ChoiceSeed(r_seed.0 ^ 1);
+        assert!(matches!(
+            validate_cot_pair(UID, &cot_alice, &cot_bob, &inputs_1, DEFAULT_SEC_PARAM),
+            Err(CotInconsistency::AdditionalMismatch { .. })
+        ));
+        cot_bob.r_seed = r_seed;
+
+        // truncated OTs
+        cot_bob.truncate(cot_bob.ts().len() - 1);
+        assert_eq!(
+            validate_cot_pair(UID, &cot_alice, &cot_bob, &inputs_1, DEFAULT_SEC_PARAM),
+            Err(CotInconsistency::WrongNumOts {
+                expected: 6 * 8 + num_additional,
+                actual: 6 * 8 + num_additional - 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_bound_cots_agree() {
+        let mut rng = StdRng::seed_from_u64(54321);
+        let inputs = (0..6u8).map(|x| BitsLE(x * 41)).collect::<Vec<_>>();
+        let (_, inputs_1) = batch_make_boolean_shares(&mut rng, inputs.iter());
+        let delta = COTGen::sample_delta(&mut rng);
+        let dims = ProtocolDims::new::<u8>(6, DEFAULT_SEC_PARAM).unwrap();
+        let (mut cot_alice, mut cot_bob) =
+            COTGen::sample_cots_for(&mut rng, UID, &inputs_1, delta, dims.num_additional());
+        assert!(validate_cot_pair(UID, &cot_alice, &cot_bob, &inputs_1, DEFAULT_SEC_PARAM).is_ok());
+        // the COTs of another client only match without domain separation
+        assert_eq!(
+            validate_cot_pair(UID + 1, &cot_alice, &cot_bob, &inputs_1, DEFAULT_SEC_PARAM).is_ok(),
+            !DOMAIN_SEPARATED_SEEDS
+        );
+
+        // the servers expand the seeds once bound to the uid of the client
+        cot_alice.bind_to(UID);
+        cot_bob.bind_to(UID);
+        let qs = cot_alice.qs_seed.expand(dims.num_total());
+        let r = cot_bob.r_seed.expand(dims.num_additional());
+        let choices = inputs_1.iter().flat_map(|x| x.iter()).chain(r.iter());
+        for ((q, t), choice) in qs.iter().zip(cot_bob.ts()).zip(choices) {
+            assert_eq!(*t, if choice { q.add_gf(delta) } else { *q });
+        }
+    }
+
+    #[test]
+    fn test_cot_tail_round_trip() {
+        let mut rng = StdRng::seed_from_u64(777);
+        let inputs = (0..6u8).map(|x| BitsLE(x * 41)).collect::<Vec<_>>();
+        let (_, inputs_1) = batch_make_boolean_shares(&mut rng, inputs.iter());
+        let delta = COTGen::sample_delta(&mut rng);
+        let dims = ProtocolDims::new::<u8>(6, DEFAULT_SEC_PARAM).unwrap();
+        let (_, cot_bob) =
+            COTGen::sample_cots_for(&mut rng, UID, &inputs_1, delta, dims.num_additional());
+        assert_eq!(cot_bob.main().len(), dims.num_ot());
+        assert_eq!(cot_bob.tail().len(), dims.num_additional());
+        assert_eq!(
+            cot_bob.split_tail(dims.num_additional()),
+            (cot_bob.main(), cot_bob.tail())
+        );
+
+        // the OTs are sent as before, after the split
+        let bytes = (&cot_bob).into_bytes_owned();
+        assert_eq!(bytes.len(), cot_bob.size_in_bytes());
+        let mut unsplit = Vec::new();
+        cot_bob.r_seed.use_cast().to_bytes(&mut unsplit);
+        cot_bob.ts().to_vec().to_bytes(&mut unsplit);
+        assert_eq!(&bytes[..8], &unsplit[..8]);
+        assert_eq!(&bytes[16..], &unsplit[8..]);
+
+        let back = B2ACOTToBob::from_bytes(&bytes[..]).unwrap();
+        assert_eq!(back.r_seed.0, cot_bob.r_seed.0);
+        assert_eq!((back.main(), back.tail()), (cot_bob.main(), cot_bob.tail()));
+
+        // a split after the last OT
+        let mut bytes = bytes.to_vec();
+        bytes[8..16].copy_from_slice(&(cot_bob.ts().len() as u64 + 1).to_le_bytes());
+        assert!(B2ACOTToBob::from_bytes(&bytes[..]).is_err());
+    }
+}
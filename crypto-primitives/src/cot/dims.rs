@@ -0,0 +1,161 @@
+//! Number of OTs used by one client.
+//!
+//! The OTs of a client are `num_ot` OTs whose choice bits are the input bits,
+//! followed by `num_additional` OTs with random choice bits that hide the
+//! inputs in OT verification. With a small `gsize`, the additional region is
+//! larger than the input region, so neither length can be derived from the
+//! other by subtraction. Slice boundaries should come from `ProtocolDims`
+//! instead, which is validated once when it is constructed.
+
+use crate::{cot::client::num_additional_ot_needed, uint::UInt};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DimsError {
+    #[error("{gsize} inputs of {num_bits} bits need more OTs than fit in usize")]
+    Overflow { gsize: usize, num_bits: usize },
+    #[error("{num_total} OTs cannot hold the {num_ot} OTs of the inputs")]
+    TooFewOts { num_ot: usize, num_total: usize },
+}
+
+/// Sizes of the OTs of one client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolDims {
+    gsize: usize,
+    num_ot: usize,
+    num_additional: usize,
+}
+
+impl ProtocolDims {
+    /// Sizes for `gsize` inputs in ring `I`, with the additional OTs needed
+    /// for statistical security parameter `sec_param`.
+    pub fn new<I: UInt>(gsize: usize, sec_param: usize) -> Result<Self, DimsError> {
+        let num_ot = Self::num_input_ots::<I>(gsize)?;
+        let num_additional = num_additional_ot_needed(num_ot, sec_param);
+        Self::with_num_additional::<I>(gsize, num_additional)
+    }
+
+    /// Sizes for `gsize` inputs in ring `I`, followed by `num_additional` OTs.
+    pub fn with_num_additional<I: UInt>(
+        gsize: usize,
+        num_additional: usize,
+    ) -> Result<Self, DimsError> {
+        let num_ot = Self::num_input_ots::<I>(gsize)?;
+        num_ot
+            .checked_add(num_additional)
+            .ok_or(DimsError::Overflow {
+                gsize,
+                num_bits: I::NUM_BITS,
+            })?;
+        Ok(ProtocolDims {
+            gsize,
+            num_ot,
+            num_additional,
+        })
+    }
+
+    /// Sizes for `gsize` inputs in ring `I`, where the OTs after the input
+    /// region fill up `num_total` OTs.
+    pub fn from_num_total<I: UInt>(gsize: usize, num_total: usize) -> Result<Self, DimsError> {
+        let num_ot = Self::num_input_ots::<I>(gsize)?;
+        let num_additional = num_total
+            .checked_sub(num_ot)
+            .ok_or(DimsError::TooFewOts { num_ot, num_total })?;
+        Ok(ProtocolDims {
+            gsize,
+            num_ot,
+            num_additional,
+        })
+    }
+
+    fn num_input_ots<I: UInt>(gsize: usize) -> Result<usize, DimsError> {
+        gsize.checked_mul(I::NUM_BITS).ok_or(DimsError::Overflow {
+            gsize,
+            num_bits: I::NUM_BITS,
+        })
+    }
+
+    /// Number of inputs.
+    #[inline]
+    pub fn gsize(&self) -> usize {
+        self.gsize
+    }
+
+    /// Number of OTs whose choice bits are the input bits.
+    #[inline]
+    pub fn num_ot(&self) -> usize {
+        self.num_ot
+    }
+
+    /// Number of OTs with random choice bits, after the input region.
+    #[inline]
+    pub fn num_additional(&self) -> usize {
+        self.num_additional
+    }
+
+    /// Number of OTs in both regions, which is also the length of `chi`.
+    #[inline]
+    pub fn num_total(&self) -> usize {
+        // cannot overflow, checked by the constructors
+        self.num_ot + self.num_additional
+    }
+
+    /// The input region of `ots`, or `None` if `ots` is too short.
+    #[inline]
+    pub fn input_region<'a, T>(&self, ots: &'a [T]) -> Option<&'a [T]> {
+        ots.get(..self.num_ot)
+    }
+
+    /// The additional region of `ots`, or `None` if `ots` is too short.
+    #[inline]
+    pub fn additional_region<'a, T>(&self, ots: &'a [T]) -> Option<&'a [T]> {
+        ots.get(self.num_ot..self.num_total())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DimsError, ProtocolDims};
+    use crate::cot::client::DEFAULT_SEC_PARAM;
+
+    #[test]
+    fn test_regions_at_small_gsize() {
+        let dims = ProtocolDims::new::<u8>(1, DEFAULT_SEC_PARAM).unwrap();
+        assert_eq!(dims.num_ot(), 8);
+        assert!(dims.num_ot() < dims.num_additional());
+        assert_eq!(dims.num_total(), 8 + dims.num_additional());
+
+        let ots = (0..dims.num_total()).collect::<Vec<_>>();
+        assert_eq!(dims.input_region(&ots).unwrap(), &ots[..8]);
+        assert_eq!(dims.additional_region(&ots).unwrap(), &ots[8..]);
+        assert_eq!(dims.input_region(&ots[..7]), None);
+        assert_eq!(dims.additional_region(&ots[..dims.num_total() - 1]), None);
+
+        assert_eq!(
+            ProtocolDims::from_num_total::<u8>(1, dims.num_total()),
+            Ok(dims)
+        );
+        let empty = ProtocolDims::from_num_total::<u8>(1, 8).unwrap();
+        assert_eq!(empty.num_additional(), 0);
+        assert_eq!(empty.additional_region(&ots[..8]).unwrap(), &[] as &[usize]);
+    }
+
+    #[test]
+    fn test_invalid_dims() {
+        assert_eq!(
+            ProtocolDims::from_num_total::<u32>(10, 319),
+            Err(DimsError::TooFewOts {
+                num_ot: 320,
+                num_total: 319
+            })
+        );
+        assert!(matches!(
+            ProtocolDims::new::<u64>(usize::MAX / 32, DEFAULT_SEC_PARAM),
+            Err(DimsError::Overflow { .. })
+        ));
+        assert!(matches!(
+            ProtocolDims::with_num_additional::<u8>(usize::MAX / 8, 8),
+            Err(DimsError::Overflow { .. })
+        ));
+    }
+}
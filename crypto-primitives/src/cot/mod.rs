@@ -2,26 +2,83 @@
 //! There are two server parties (Alice and Bob) and one client.
 //! For load balancing,
 
-use crate::{bits::PackedBits, block_crypto::rng::BlockRng};
+use crate::{
+    bits::PackedBits,
+    block_crypto::{
+        aes::{aes_ecb_encrypt_blocks, aes_opt_key_schedule, AESKey},
+        rng::BlockRng,
+    },
+};
 use block::Block;
 use bytemuck::{Pod, Zeroable};
 use rand::{rngs::StdRng, SeedableRng};
+use safe_arch::m128i;
+#[cfg(target_endian = "big")]
+use serialize::util::LePod;
 use serialize::{AsUseCast, Communicate, UseCast};
-use std::io::{Read, Write};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::TryInto,
+    io::{Read, Write},
+};
+use zeroize::Zeroize;
 
 pub mod client;
+pub mod dims;
 pub mod naive_rot;
 pub mod rot;
 pub mod server;
+pub mod verify;
+
+/// Whether the seeds of the COTs of a client are expanded for its uid, see
+/// [`COTSeed::for_client`]. Set by the `domain-separated-seeds` feature,
+/// which changes the COTs, so both servers and the clients must agree on it.
+pub const DOMAIN_SEPARATED_SEEDS: bool = cfg!(feature = "domain-separated-seeds");
+
+/// Purpose of the seed of `Q||Q'` of B2A.
+pub const B2A_QS_PURPOSE: &str = "elsa b2a qs";
+
+/// Purpose of the seed of the choices `r` of the additional OTs of B2A.
+pub const B2A_R_PURPOSE: &str = "elsa b2a r";
+
+/// Purpose of the seed of the COTs of the AND gates of a bounded check.
+pub const BOUNDED_AND_PURPOSE: &str = "elsa bounded and";
+
+/// Purpose of the seed of the choices `r` of the additional OTs of a bounded
+/// check.
+pub const BOUNDED_R_PURPOSE: &str = "elsa bounded r";
+
+/// First 8 bytes of the hash of `purpose`.
+fn purpose_tag(purpose: &str) -> u64 {
+    let hash = Sha256::digest(purpose.as_bytes());
+    u64::from_le_bytes(hash[..8].try_into().unwrap())
+}
 
 /// A seed to randomly generate COT deterministically.
 #[derive(Debug, Clone, Copy, Pod, Zeroable, Default)]
 #[repr(transparent)]
 pub struct COTSeed(pub Block);
 
+impl Zeroize for COTSeed {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 impl COTSeed {
-    #[allow(clippy::uninit_vec)]
+    /// COTs of the OT sender (`Q||Q'`). Expanding is costly, so a party that
+    /// receives the seed expands it once and keeps the result. In tests,
+    /// every call is counted, see `expansion_count`.
     pub fn expand(&self, num_cots: usize) -> Vec<Block> {
+        #[cfg(any(test, feature = "count-expansions"))]
+        expansions::record(self);
+        self.generate(num_cots)
+    }
+
+    /// Same as `expand`, but not counted: for the party that samples the
+    /// seed, which expands it to generate the COTs of the OT receiver.
+    #[allow(clippy::uninit_vec)]
+    pub(crate) fn generate(&self, num_cots: usize) -> Vec<Block> {
         let mut cot_rng = BlockRng::new(Some(self.0));
         // safety: `Block` is a primitive type, and has no destructors
         let mut qs = Vec::with_capacity(num_cots);
@@ -32,18 +89,91 @@ impl COTSeed {
         qs
     }
 
+    /// COTs of the OT receiver (`T||T'`), where `select` are the choices.
+    /// Used to generate the COTs, so not counted as an expansion.
     pub fn expand_selected(
         &self,
         num_cots: usize,
         delta: Block,
         select: impl IntoIterator<Item = bool>,
     ) -> Vec<Block> {
-        let qs = self.expand(num_cots);
+        let qs = self.generate(num_cots);
         qs.into_iter()
             .zip(select)
             .map(|(q, choice)| if choice { q.add_gf(delta) } else { q })
             .collect()
     }
+
+    /// Seed of the stream of client `uid` for `purpose`: `(uid, tag)`
+    /// encrypted with this seed as the AES key, where `tag` is the hash of
+    /// `purpose`. The stream of `expand` encrypts `(counter, 0)` instead, so
+    /// the same seed sent by two clients, or for two purposes, gives
+    /// unrelated COTs.
+    pub fn derive(&self, uid: u64, purpose: &'static str) -> COTSeed {
+        let mut aes = [AESKey::default()];
+        aes_opt_key_schedule(&[self.0 .0], &mut aes);
+        let mut block = [m128i::from([uid, purpose_tag(purpose)])];
+        aes_ecb_encrypt_blocks(&mut block, &aes[0]);
+        COTSeed(Block(block[0]))
+    }
+
+    /// Same as `expand`, on the stream of client `uid` for `purpose`, see
+    /// `derive`.
+    pub fn expand_for(&self, uid: u64, purpose: &'static str, num_cots: usize) -> Vec<Block> {
+        self.derive(uid, purpose).expand(num_cots)
+    }
+
+    /// The seed that the client `uid` and the servers expand for `purpose`:
+    /// `derive(uid, purpose)` with [`DOMAIN_SEPARATED_SEEDS`], this seed
+    /// otherwise.
+    pub fn for_client(&self, uid: u64, purpose: &'static str) -> COTSeed {
+        if DOMAIN_SEPARATED_SEEDS {
+            self.derive(uid, purpose)
+        } else {
+            *self
+        }
+    }
+
+    /// Number of times `expand` is called on this seed in this process.
+    #[cfg(any(test, feature = "count-expansions"))]
+    pub fn expansion_count(&self) -> usize {
+        expansions::count(self)
+    }
+}
+
+/// Per-seed count of `COTSeed::expand`, to catch a seed expanded twice by the
+/// same party in tests. Only in tests and with `count-expansions`, never in
+/// the binaries, as it grows with every seed and serializes the expansions.
+/// Seeds are counted by their hash, so no seed is kept.
+#[cfg(any(test, feature = "count-expansions"))]
+mod expansions {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use sha2::{Digest, Sha256};
+
+    use super::COTSeed;
+
+    static COUNTS: Mutex<Option<HashMap<[u8; 32], usize>>> = Mutex::new(None);
+
+    fn key(seed: &COTSeed) -> [u8; 32] {
+        Sha256::digest(bytemuck::bytes_of(&seed.0)).into()
+    }
+
+    pub(super) fn record(seed: &COTSeed) {
+        let mut counts = COUNTS.lock().unwrap_or_else(|e| e.into_inner());
+        *counts
+            .get_or_insert_with(HashMap::new)
+            .entry(key(seed))
+            .or_default() += 1;
+    }
+
+    pub(super) fn count(seed: &COTSeed) -> usize {
+        let counts = COUNTS.lock().unwrap_or_else(|e| e.into_inner());
+        counts
+            .as_ref()
+            .and_then(|counts| counts.get(&key(seed)).copied())
+            .unwrap_or(0)
+    }
 }
 
 impl Communicate for COTSeed {
@@ -67,10 +197,80 @@ impl Communicate for COTSeed {
 #[repr(transparent)]
 pub struct ChoiceSeed(pub u64);
 
+impl Zeroize for ChoiceSeed {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+#[cfg(target_endian = "big")]
+impl LePod for ChoiceSeed {
+    fn swap_le(&mut self) {
+        self.0.swap_le()
+    }
+}
+
 impl ChoiceSeed {
     pub fn expand(&self, r_size: usize) -> PackedBits {
         let mut choice_rng = StdRng::seed_from_u64(self.0);
-        let r = PackedBits::rand(&mut choice_rng, r_size);
-        r
+
+        PackedBits::rand(&mut choice_rng, r_size)
+    }
+
+    /// Seed of the choices of client `uid` for `purpose`: the first 8 bytes
+    /// of the hash of this seed, `uid` and `purpose`.
+    pub fn derive(&self, uid: u64, purpose: &'static str) -> ChoiceSeed {
+        let hash = Sha256::new()
+            .chain_update(self.0.to_le_bytes())
+            .chain_update(uid.to_le_bytes())
+            .chain_update(purpose.as_bytes())
+            .finalize();
+        ChoiceSeed(u64::from_le_bytes(hash[..8].try_into().unwrap()))
+    }
+
+    /// Same as `expand`, on the choices of client `uid` for `purpose`, see
+    /// `derive`.
+    pub fn expand_for(&self, uid: u64, purpose: &'static str, r_size: usize) -> PackedBits {
+        self.derive(uid, purpose).expand(r_size)
+    }
+
+    /// Same as [`COTSeed::for_client`].
+    pub fn for_client(&self, uid: u64, purpose: &'static str) -> ChoiceSeed {
+        if DOMAIN_SEPARATED_SEEDS {
+            self.derive(uid, purpose)
+        } else {
+            *self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use block::Block;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{COTSeed, ChoiceSeed, B2A_QS_PURPOSE, B2A_R_PURPOSE, BOUNDED_AND_PURPOSE};
+
+    #[test]
+    fn test_expand_for_separates_clients() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        // the same seed submitted by two clients, e.g. seeded from small integers
+        let seed = COTSeed(Block::rand(&mut rng));
+        let qs_3 = seed.expand_for(3, B2A_QS_PURPOSE, 100);
+        assert_eq!(qs_3, seed.expand_for(3, B2A_QS_PURPOSE, 100));
+        assert_eq!(qs_3, seed.derive(3, B2A_QS_PURPOSE).expand(100));
+        for other in [
+            seed.expand_for(4, B2A_QS_PURPOSE, 100),
+            seed.expand_for(3, BOUNDED_AND_PURPOSE, 100),
+            seed.expand(100),
+        ] {
+            assert!(qs_3.iter().zip(&other).all(|(a, b)| a != b));
+        }
+
+        let seed = ChoiceSeed(7);
+        let r_3 = seed.expand_for(3, B2A_R_PURPOSE, 1000);
+        assert_eq!(r_3, seed.derive(3, B2A_R_PURPOSE).expand(1000));
+        assert_ne!(r_3, seed.expand_for(4, B2A_R_PURPOSE, 1000));
+        assert_ne!(r_3, seed.expand(1000));
     }
 }
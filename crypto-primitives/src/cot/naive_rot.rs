@@ -3,12 +3,12 @@
 use crate::{
     bits::PackedBits,
     cot::{
-        rot::{cot_to_rot_receiver_side, cot_to_rot_sender_side},
-        server::{inner_product, inner_product_with_boolean_scalar, OTSender},
+        rot::{cot_to_bit_rot_receiver_side, cot_to_bit_rot_sender_side},
+        server::{inner_product, inner_product_with_boolean_scalar, OTSender, TTil},
         COTSeed, ChoiceSeed,
     },
 };
-use block::{gf::GF2_256, Block};
+use block::Block;
 use std::ops::Range;
 
 pub struct NaiveCOTsForSender {
@@ -17,8 +17,8 @@ pub struct NaiveCOTsForSender {
 }
 
 pub struct NaiveROTsForSender {
-    pub v0: Vec<bool>,
-    pub v1: Vec<bool>,
+    pub v0: PackedBits,
+    pub v1: PackedBits,
 }
 
 impl NaiveCOTsForSender {
@@ -28,16 +28,13 @@ impl NaiveCOTsForSender {
         &self,
         chi: &[Block],
         x_til: Block,
-        t_til: GF2_256,
+        t_til: TTil,
     ) -> (Vec<Block>, bool) {
         OTSender::verify_and_get_cot(self.qs_seed, chi, self.delta, x_til, t_til)
     }
 
     pub fn to_rot(&self, qs: &[Block]) -> NaiveROTsForSender {
-
-        let raw = cot_to_rot_sender_side::<u8>(qs, self.delta);
-        let v0 = raw.0.into_iter().map(|x| x & 1 == 1).collect();
-        let v1 = raw.1.into_iter().map(|x| x & 1 == 1).collect();
+        let (v0, v1) = cot_to_bit_rot_sender_side(qs, self.delta);
         NaiveROTsForSender { v0, v1 }
     }
 }
@@ -45,12 +42,7 @@ impl NaiveCOTsForSender {
 impl NaiveROTsForSender {
     /// return `v0[range]`, `v1[range]`
     pub fn get_range(&self, range: Range<usize>) -> (PackedBits, PackedBits) {
-        let v0 = self.v0[range.clone()]
-            .iter()
-            .copied()
-            .collect::<PackedBits>();
-        let v1 = self.v1[range].iter().copied().collect::<PackedBits>();
-        (v0, v1)
+        (self.v0.slice(range.clone()), self.v1.slice(range))
     }
 }
 
@@ -63,14 +55,14 @@ pub struct NaiveCOTsForReceiver {
 
 pub struct NaiveROTsForReceiver {
     /// selected rots
-    pub v: Vec<bool>,
+    pub v: PackedBits,
     /// the select bits
-    pub vb: Vec<bool>,
+    pub vb: PackedBits,
 }
 
 impl NaiveCOTsForReceiver {
     /// send `x_til` and `t_til` to the sender for verification
-    pub fn send_x_til_and_t_til(&self, chi: &[Block]) -> (Block, GF2_256) {
+    pub fn send_x_til_and_t_til(&self, chi: &[Block]) -> (Block, TTil) {
         assert_eq!(chi.len(), self.ts.len());
         // generate x_hat
         let x_hat = self.choice_seed.expand(chi.len());
@@ -78,13 +70,12 @@ impl NaiveCOTsForReceiver {
         let x_til = inner_product_with_boolean_scalar(x_hat.iter(), chi);
         let t_til = inner_product(&self.ts, chi);
 
-        (x_til, t_til)
+        (x_til, TTil::new(t_til))
     }
 
     pub fn to_rot(&self, num_rots: usize) -> NaiveROTsForReceiver {
-        let raw = cot_to_rot_receiver_side::<u8>(&self.ts[..num_rots]);
-        let v = raw.into_iter().map(|x| x & 1 == 1).collect();
-        let vb = self.choice_seed.expand(num_rots).iter().collect();
+        let v = cot_to_bit_rot_receiver_side(&self.ts[..num_rots]);
+        let vb = self.choice_seed.expand(num_rots);
         NaiveROTsForReceiver { v, vb }
     }
 }
@@ -92,12 +83,7 @@ impl NaiveCOTsForReceiver {
 impl NaiveROTsForReceiver {
     /// return `v[range]`, `vb[range]`
     pub fn get_range(&self, range: Range<usize>) -> (PackedBits, PackedBits) {
-        let v = self.v[range.clone()]
-            .iter()
-            .copied()
-            .collect::<PackedBits>();
-        let vb = self.vb[range].iter().copied().collect::<PackedBits>();
-        (v, vb)
+        (self.v.slice(range.clone()), self.vb.slice(range))
     }
 }
 
@@ -108,7 +94,7 @@ pub struct NaiveCOTAlice {
 
 impl NaiveCOTAlice {
     /// generate x_til and t_til for reverse pool
-    pub fn generate_verify_message(&self, chi: &[Block]) -> (Block, GF2_256) {
+    pub fn generate_verify_message(&self, chi: &[Block]) -> (Block, TTil) {
         self.reverse.send_x_til_and_t_til(chi)
     }
 
@@ -116,7 +102,7 @@ impl NaiveCOTAlice {
         &self,
         chi: &[Block],
         x_til: Block,
-        t_til: GF2_256,
+        t_til: TTil,
     ) -> (Vec<Block>, bool) {
         self.straight.verify_and_get_cots(chi, x_til, t_til)
     }
@@ -135,12 +121,17 @@ pub struct NaiveROTAlice {
 
 impl NaiveROTAlice {
     /// return `v0[range]`, `v1[range]` from straight pool, and `w[range]`, `wb[range]` from reverse pool
-    pub fn get_range(&self, range: Range<usize>) -> ((PackedBits, PackedBits), (PackedBits, PackedBits)) {
-        (self.straight.get_range(range.clone()), self.reverse.get_range(range))
+    pub fn get_range(
+        &self,
+        range: Range<usize>,
+    ) -> ((PackedBits, PackedBits), (PackedBits, PackedBits)) {
+        (
+            self.straight.get_range(range.clone()),
+            self.reverse.get_range(range),
+        )
     }
 }
 
-
 pub struct NaiveCOTBob {
     pub straight: NaiveCOTsForReceiver,
     pub reverse: NaiveCOTsForSender,
@@ -148,7 +139,7 @@ pub struct NaiveCOTBob {
 
 impl NaiveCOTBob {
     /// generate x_til and t_til for straight pool
-    pub fn generate_verify_message(&self, chi: &[Block]) -> (Block, GF2_256) {
+    pub fn generate_verify_message(&self, chi: &[Block]) -> (Block, TTil) {
         self.straight.send_x_til_and_t_til(chi)
     }
 
@@ -156,7 +147,7 @@ impl NaiveCOTBob {
         &self,
         chi: &[Block],
         x_til: Block,
-        t_til: GF2_256,
+        t_til: TTil,
     ) -> (Vec<Block>, bool) {
         self.reverse.verify_and_get_cots(chi, x_til, t_til)
     }
@@ -175,15 +166,20 @@ pub struct NaiveROTBob {
 
 impl NaiveROTBob {
     /// return `v[range]`, `vb[range]` from straight pool, and `w0[range]`, `w1[range]` from reverse pool
-    pub fn get_range(&self, range: Range<usize>) -> ((PackedBits, PackedBits), (PackedBits, PackedBits)) {
-        (self.straight.get_range(range.clone()), self.reverse.get_range(range))
+    pub fn get_range(
+        &self,
+        range: Range<usize>,
+    ) -> ((PackedBits, PackedBits), (PackedBits, PackedBits)) {
+        (
+            self.straight.get_range(range.clone()),
+            self.reverse.get_range(range),
+        )
     }
 }
 
-
 pub mod clients {
     use crate::cot::{
-        client::num_additional_ot_needed,
+        client::{num_additional_ot_needed, DEFAULT_SEC_PARAM},
         naive_rot::{NaiveCOTAlice, NaiveCOTBob, NaiveCOTsForReceiver, NaiveCOTsForSender},
         COTSeed, ChoiceSeed,
     };
@@ -194,7 +190,7 @@ pub mod clients {
         rng: &mut R,
         num_cots_in_each_pool: usize,
     ) -> (NaiveCOTsForSender, NaiveCOTsForReceiver) {
-        let additional = num_additional_ot_needed(num_cots_in_each_pool);
+        let additional = num_additional_ot_needed(num_cots_in_each_pool, DEFAULT_SEC_PARAM);
         // generate straight pool
         let delta = Block::rand(rng);
         let qs_seed = COTSeed(Block::rand(rng));
@@ -227,16 +223,18 @@ pub mod clients {
 
 #[cfg(test)]
 mod tests {
+    use crate::cot::client::{num_additional_ot_needed, DEFAULT_SEC_PARAM};
     use crate::cot::naive_rot::clients::generate_naive_cots;
+    use crate::cot::naive_rot::{NaiveCOTsForReceiver, NaiveCOTsForSender};
     use block::Block;
     use rand::{rngs::StdRng, SeedableRng};
-    use crate::cot::client::num_additional_ot_needed;
-    use crate::cot::naive_rot::{NaiveCOTsForReceiver, NaiveCOTsForSender};
 
-    fn check_naive_cot_consistency(send: &NaiveCOTsForSender, recv: &NaiveCOTsForReceiver){
+    fn check_naive_cot_consistency(send: &NaiveCOTsForSender, recv: &NaiveCOTsForReceiver) {
         let num_cots = recv.ts.len();
         let choices = recv.choice_seed.expand(num_cots);
-        let expected = send.qs_seed.expand_selected(num_cots, send.delta, choices.iter());
+        let expected = send
+            .qs_seed
+            .expand_selected(num_cots, send.delta, choices.iter());
         let actual = recv.ts.clone();
         assert_eq!(expected, actual);
     }
@@ -251,7 +249,9 @@ mod tests {
         check_naive_cot_consistency(&cot_bob.reverse, &cot_alice.reverse);
 
         // verify
-        let chi = (0..(SIZE + num_additional_ot_needed(SIZE))).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+        let chi = (0..(SIZE + num_additional_ot_needed(SIZE, DEFAULT_SEC_PARAM)))
+            .map(|_| Block::rand(&mut rng))
+            .collect::<Vec<_>>();
         let msg_alice = cot_alice.generate_verify_message(&chi);
         let msg_bob = cot_bob.generate_verify_message(&chi);
         let (qs_straight_alice, verify_result_alice) =
@@ -275,26 +275,31 @@ mod tests {
         assert_eq!(rot_bob.reverse.v0.len(), SIZE);
         assert_eq!(rot_bob.reverse.v1.len(), SIZE);
 
-
         // straight pool
-        for i in 0..SIZE {
-            let bob_val = rot_bob.straight.v[i];
-            let alice_val = if rot_bob.straight.vb[i] {
-                rot_alice.straight.v1[i]
-            } else {
-                rot_alice.straight.v0[i]
-            };
+        let (v, vb) = (&rot_bob.straight.v, &rot_bob.straight.vb);
+        let (v0, v1) = (&rot_alice.straight.v0, &rot_alice.straight.v1);
+        for (i, (((bob_val, b), a0), a1)) in v
+            .iter()
+            .zip(vb.iter())
+            .zip(v0.iter())
+            .zip(v1.iter())
+            .enumerate()
+        {
+            let alice_val = if b { a1 } else { a0 };
             assert_eq!(bob_val, alice_val, "at: {}", i);
         }
 
         // reverse pool
-        for i in 0..SIZE {
-            let alice_val = rot_alice.reverse.v[i];
-            let bob_val = if rot_alice.reverse.vb[i] {
-                rot_bob.reverse.v1[i]
-            } else {
-                rot_bob.reverse.v0[i]
-            };
+        let (v, vb) = (&rot_alice.reverse.v, &rot_alice.reverse.vb);
+        let (v0, v1) = (&rot_bob.reverse.v0, &rot_bob.reverse.v1);
+        for (i, (((alice_val, b), b0), b1)) in v
+            .iter()
+            .zip(vb.iter())
+            .zip(v0.iter())
+            .zip(v1.iter())
+            .enumerate()
+        {
+            let bob_val = if b { b1 } else { b0 };
             assert_eq!(alice_val, bob_val, "at: {}", i);
         }
     }
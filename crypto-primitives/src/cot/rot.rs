@@ -1,64 +1,258 @@
 //! Suppose we have COT as `q` and `t = q + select_bit * delta`. This module
 //! provides function to convert `COT` to trimmed `ROT`.
-use crate::{block_crypto::mitccrh::MiTCCR, uint::UInt};
+use crate::{
+    bits::PackedBits,
+    block_crypto::mitccrh::{MiTCCR, KEY_SCHEDULE_BATCHES},
+    uint::UInt,
+};
 use block::Block;
-use bytemuck::Zeroable;
-use safe_arch::m128i;
+use safe_arch::{get_i32_from_m128i_s, m128i};
+
+pub mod server_generated;
 
 /// Start point for MitCCR Hash. This start point is arbitrary. Just make sure
 /// it's consistent.
 const START_POINT: [u32; 4] = [0x1234, 0x2345, 0x3456, 0x4567];
 /// Batch size for COT to ROT conversion.
-const OT_BSIZE: usize = 8;
+pub(crate) const OT_BSIZE: usize = 8;
+/// OTs hashed per call to `MiTCCR::hash_many`, so that it schedules all the
+/// keys of a call at once.
+const PIECE_SIZE: usize = OT_BSIZE * KEY_SCHEDULE_BATCHES;
 
 /// Suppose I'm OT sender and I have vector `q`. This function calculates ROT of
 /// `q` and `q + delta` and trim them to ring size.
 pub fn cot_to_rot_sender_side<T: UInt>(q: &[Block], delta: Block) -> (Vec<T>, Vec<T>) {
-    // in our application, `q` is always aligned to `OT_BSIZE` because `T::NUM_BITS % OT_BSIZE == 0`
-    // if assertion failed, that means we probably included extra OT here
-    assert_eq!(q.len() % OT_BSIZE, 0, "q is not aligned to OT_BSIZE");
+    let mut data_0 = Vec::with_capacity(q.len());
+    let mut data_1 = Vec::with_capacity(q.len());
+    RotStream::new().sender_side_into(q, delta, &mut data_0, &mut data_1);
+    (data_0, data_1)
+}
+
+/// Suppose I'm OT receiver and I have vector `t = q + select_bit * delta`. This function
+/// calculates ROT of `t` and trim it to ring size.
+pub fn cot_to_rot_receiver_side<T: UInt>(t: &[Block]) -> Vec<T> {
+    let mut data = Vec::with_capacity(t.len());
+    RotStream::new().receiver_side_into(t, &mut data);
+    data
+}
+
+/// Converts a long `q` (or `t`) to ROTs in consecutive pieces, with the same
+/// result as `cot_to_rot_sender_side` (or `cot_to_rot_receiver_side`) on the
+/// whole of it. The hash is tweaked by the position of each OT, so a stream
+/// must only be fed the pieces of one vector, in order.
+pub struct RotStream {
+    crh: MiTCCR<OT_BSIZE>,
+}
+
+impl Default for RotStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RotStream {
+    pub fn new() -> Self {
+        Self {
+            crh: MiTCCR::new(START_POINT.into()),
+        }
+    }
+
+    /// Replace the contents of `data_0` and `data_1` with the ROTs of the
+    /// next piece `q`, so that their buffers are reused across pieces.
+    pub fn sender_side_into<T: UInt>(
+        &mut self,
+        q: &[Block],
+        delta: Block,
+        data_0: &mut Vec<T>,
+        data_1: &mut Vec<T>,
+    ) {
+        // in our application, `q` is always aligned to `OT_BSIZE` because `T::NUM_BITS % OT_BSIZE == 0`
+        // if assertion failed, that means we probably included extra OT here
+        assert_eq!(q.len() % OT_BSIZE, 0, "q is not aligned to OT_BSIZE");
+
+        let mut pad = [Block::default(); PIECE_SIZE * 2];
+        data_0.clear();
+        data_1.clear();
+
+        q.chunks(PIECE_SIZE).for_each(|qs| {
+            let pad = &mut pad[..qs.len() * 2];
+            qs.iter().zip(pad.chunks_mut(2)).for_each(|(q, p)| {
+                p[0] = *q;
+                p[1] = q.add_gf(delta);
+            });
+            self.crh.hash_many(pad, 2);
+            pad.chunks(2).for_each(|p| {
+                data_0.push(T::from_rot(p[0].0));
+                data_1.push(T::from_rot(p[1].0));
+            });
+        });
+    }
+
+    /// Replace the contents of `data` with the ROTs of the next piece `t`.
+    pub fn receiver_side_into<T: UInt>(&mut self, t: &[Block], data: &mut Vec<T>) {
+        // in our application, `t` is always aligned to `OT_BSIZE` because `T::NUM_BITS % OT_BSIZE == 0`
+        // if assertion failed, that means we probably included extra OT here
+        assert_eq!(t.len() % OT_BSIZE, 0, "t is not aligned to OT_BSIZE");
+
+        let mut pad = [Block::default(); PIECE_SIZE];
+        data.clear();
+
+        t.chunks(PIECE_SIZE).for_each(|ts| {
+            let pad = &mut pad[..ts.len()];
+            pad.copy_from_slice(ts);
+            self.crh.hash_many(pad, 1);
+            data.extend(pad.iter().map(|p| T::from_rot(p.0)));
+        });
+    }
+}
 
+/// Lowest bit of a hashed block, i.e. of `u8::from_rot(block)`.
+#[inline]
+fn rot_bit(block: m128i) -> u32 {
+    (get_i32_from_m128i_s(block) & 1) as u32
+}
+
+/// Set bit `i` of the little-endian words of a `PackedBits`.
+#[inline]
+fn set_word_bit(words: &mut [u32], i: usize, bit: u32) {
+    words[i >> 5] |= bit << (i & 31);
+}
+
+/// Same as `cot_to_rot_sender_side::<u8>`, keeping only the lowest bit of
+/// each ROT, which is all the bit multiplications need. The bits are packed
+/// as the hashes come out, without a vector of one `u8` per ROT. `q` does
+/// not need to be aligned to `OT_BSIZE`.
+pub fn cot_to_bit_rot_sender_side(q: &[Block], delta: Block) -> (PackedBits, PackedBits) {
     let mut crh = MiTCCR::<OT_BSIZE>::new(START_POINT.into());
 
-    const PAD_SIZE: usize = OT_BSIZE * 2;
-    let mut pad = [m128i::zeroed(); PAD_SIZE];
-    let mut data_0 = Vec::<T>::with_capacity(q.len());
-    let mut data_1 = Vec::<T>::with_capacity(q.len());
+    let mut pad = [Block::default(); PIECE_SIZE * 2];
+    let num_words = (q.len() + 31) >> 5;
+    let mut words_0 = vec![0u32; num_words];
+    let mut words_1 = vec![0u32; num_words];
 
-    q.chunks_exact(OT_BSIZE).for_each(|qs| {
-        // each qs is of size OT_BSIZE, let's cast it to array
+    q.chunks(PIECE_SIZE).enumerate().for_each(|(piece, qs)| {
+        let pad = &mut pad[..qs.len() * 2];
         qs.iter().zip(pad.chunks_mut(2)).for_each(|(q, p)| {
-            p[0] = q.0;
-            p[1] = q.add_gf(delta).0;
+            p[0] = *q;
+            p[1] = q.add_gf(delta);
         });
-        crh.hash::<2, PAD_SIZE>(&mut pad);
-        // we take `qs.len()` to address padding
-        pad.chunks_mut(2).for_each(|p| {
-            data_0.push(T::from_rot(p[0]));
-            data_1.push(T::from_rot(p[1]));
+        crh.hash_many(pad, 2);
+        pad.chunks(2).enumerate().for_each(|(j, p)| {
+            let i = piece * PIECE_SIZE + j;
+            set_word_bit(&mut words_0, i, rot_bit(p[0].0));
+            set_word_bit(&mut words_1, i, rot_bit(p[1].0));
         });
     });
 
-    (data_0, data_1)
+    (
+        PackedBits::from_words(q.len(), words_0),
+        PackedBits::from_words(q.len(), words_1),
+    )
 }
 
-/// Suppose I'm OT receiver and I have vector `t = q + select_bit * delta`. This function
-/// calculates ROT of `t` and trim it to ring size.
-pub fn cot_to_rot_receiver_side<T: UInt>(t: &[Block]) -> Vec<T> {
-    // in our application, `t` is always aligned to `OT_BSIZE` because `T::NUM_BITS % OT_BSIZE == 0`
-    // if assertion failed, that means we probably included extra OT here
-    assert_eq!(t.len() % OT_BSIZE, 0, "t is not aligned to OT_BSIZE");
-
+/// Same as `cot_to_rot_receiver_side::<u8>`, keeping only the lowest bit of
+/// each ROT, packed. `t` does not need to be aligned to `OT_BSIZE`.
+pub fn cot_to_bit_rot_receiver_side(t: &[Block]) -> PackedBits {
     let mut crh = MiTCCR::<OT_BSIZE>::new(START_POINT.into());
-    const PAD_SIZE: usize = OT_BSIZE;
-    let mut pad = [m128i::zeroed(); PAD_SIZE];
-    let mut data = Vec::<T>::with_capacity(t.len());
-
-    t.chunks(OT_BSIZE).for_each(|qs|{
-        pad.copy_from_slice(bytemuck::cast_slice(qs));
-        crh.hash::<1, PAD_SIZE>(&mut pad);
-        data.extend(pad.iter().map(|p| T::from_rot(*p)));
+    let mut pad = [Block::default(); PIECE_SIZE];
+    let mut words = vec![0u32; (t.len() + 31) >> 5];
+
+    t.chunks(PIECE_SIZE).enumerate().for_each(|(piece, ts)| {
+        let pad = &mut pad[..ts.len()];
+        pad.copy_from_slice(ts);
+        crh.hash_many(pad, 1);
+        pad.iter().enumerate().for_each(|(j, p)| {
+            set_word_bit(&mut words, piece * PIECE_SIZE + j, rot_bit(p.0));
+        });
     });
 
-    data
-}
\ No newline at end of file
+    PackedBits::from_words(t.len(), words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn test_bit_rot_matches_per_element() {
+        const SIZE: usize = 1000;
+        let mut rng = StdRng::seed_from_u64(12345);
+        let delta = Block::rand(&mut rng);
+        let qs = (0..SIZE).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+        let choices = (0..SIZE).map(|_| rng.gen::<bool>()).collect::<Vec<_>>();
+        let ts = qs
+            .iter()
+            .zip(&choices)
+            .map(|(q, &c)| if c { q.add_gf(delta) } else { *q })
+            .collect::<Vec<_>>();
+
+        let (v0, v1) = cot_to_bit_rot_sender_side(&qs, delta);
+        let v = cot_to_bit_rot_receiver_side(&ts);
+        let (expected_0, expected_1) = cot_to_rot_sender_side::<u8>(&qs, delta);
+        let expected = cot_to_rot_receiver_side::<u8>(&ts);
+        let lowest_bits = |x: Vec<u8>| x.into_iter().map(|x| x & 1 == 1).collect::<PackedBits>();
+        assert_eq!(v0, lowest_bits(expected_0));
+        assert_eq!(v1, lowest_bits(expected_1));
+        assert_eq!(v, lowest_bits(expected));
+
+        // the receiver gets the ROT of its choice
+        for (((v, v0), v1), c) in v.iter().zip(v0.iter()).zip(v1.iter()).zip(choices) {
+            assert_eq!(v, if c { v1 } else { v0 });
+        }
+
+        // unaligned lengths give a prefix of the same bits
+        for len in [0, 1, 7, 33, SIZE - 3] {
+            let (u0, u1) = cot_to_bit_rot_sender_side(&qs[..len], delta);
+            assert_eq!(u0, v0.slice(0..len));
+            assert_eq!(u1, v1.slice(0..len));
+            assert_eq!(cot_to_bit_rot_receiver_side(&ts[..len]), v.slice(0..len));
+        }
+    }
+
+    #[test]
+    fn test_rot_matches_per_batch_hash() {
+        const SIZE: usize = 4000;
+        let mut rng = StdRng::seed_from_u64(54321);
+        let delta = Block::rand(&mut rng);
+        let qs = (0..SIZE).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+
+        // one `MiTCCR::hash` per `OT_BSIZE` OTs
+        let mut crh = MiTCCR::<OT_BSIZE>::new(START_POINT.into());
+        let (mut expected_0, mut expected_1) = (Vec::new(), Vec::new());
+        for qs in qs.chunks(OT_BSIZE) {
+            let mut pad = [m128i::default(); OT_BSIZE * 2];
+            qs.iter().zip(pad.chunks_mut(2)).for_each(|(q, p)| {
+                p[0] = q.0;
+                p[1] = q.add_gf(delta).0;
+            });
+            crh.hash::<2, { OT_BSIZE * 2 }>(&mut pad);
+            expected_0.extend(pad.iter().step_by(2).map(|p| u64::from_rot(*p)));
+            expected_1.extend(pad.iter().skip(1).step_by(2).map(|p| u64::from_rot(*p)));
+        }
+        let mut crh = MiTCCR::<OT_BSIZE>::new(START_POINT.into());
+        let mut expected = Vec::new();
+        for qs in qs.chunks(OT_BSIZE) {
+            let mut pad = [m128i::default(); OT_BSIZE];
+            pad.copy_from_slice(bytemuck::cast_slice(qs));
+            crh.hash::<1, OT_BSIZE>(&mut pad);
+            expected.extend(pad.iter().map(|p| u64::from_rot(*p)));
+        }
+
+        assert_eq!(
+            cot_to_rot_sender_side::<u64>(&qs, delta),
+            (expected_0, expected_1)
+        );
+        assert_eq!(cot_to_rot_receiver_side::<u64>(&qs), expected);
+
+        // a stream fed in pieces gives the same
+        let mut stream = RotStream::new();
+        let mut data = Vec::new();
+        let mut streamed = Vec::new();
+        for ts in qs.chunks(24) {
+            stream.receiver_side_into::<u64>(ts, &mut data);
+            streamed.extend_from_slice(&data);
+        }
+        assert_eq!(streamed, expected);
+    }
+}
@@ -0,0 +1,210 @@
+//! COTs generated by the two servers between themselves, instead of by the
+//! clients. This is for deployments where clients only send boolean shares.
+//!
+//! The servers start from random OTs (ROT): the sender has random `(m0, m1)`,
+//! and the receiver has a random choice `c` and `m_c`. Two steps turn them
+//! into what `bit_comp_as_ot_sender_batch` and `bit_comp_as_ot_receiver_batch`
+//! expect:
+//!
+//! 1. ROT to COT: the sender picks `delta` and sends `d = m0 + m1 + delta`.
+//!    With `q = m0` and `t = m_c + c * d`, we have `t = q + c * delta`.
+//! 2. Choice flips: the receiver sends `e = c + x` for its input bits `x`, and
+//!    the sender uses `q + e * delta` instead of `q`, so that
+//!    `t = q + x * delta`.
+//!
+//! Both messages only depend on the ROTs, so they are sent at the same time.
+//!
+//! No secure source of ROTs is implemented: there is no OT extension between
+//! the servers (e.g. EMP Ferret or IKNP) yet, as the EMP bindings are not part
+//! of the workspace and only return bit ROTs. The only source is
+//! `from_shared_seed`, which is **not secure** and compiled only with the
+//! `simulation-helpers` feature, so this mode is a cost simulation for now.
+
+use crate::{bits::BitsLE, uint::UInt};
+#[cfg(any(test, feature = "simulation-helpers"))]
+use crate::{
+    cot::{COTSeed, ChoiceSeed},
+    simulation::SimulationOnly,
+};
+use block::Block;
+#[cfg(any(test, feature = "simulation-helpers"))]
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Sender side of random OTs.
+pub struct ROTsForSender {
+    pub m0: Vec<Block>,
+    pub m1: Vec<Block>,
+}
+
+/// Receiver side of random OTs.
+pub struct ROTsForReceiver {
+    pub choices: Vec<bool>,
+    /// `m0` or `m1` of the sender, selected by `choices`
+    pub mc: Vec<Block>,
+}
+
+#[cfg(any(test, feature = "simulation-helpers"))]
+fn expand_shared_seed(seed: u64, count: usize) -> (Vec<Block>, Vec<Block>, Vec<bool>) {
+    SimulationOnly::mark();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let m0 = COTSeed(Block::rand(&mut rng)).expand(count);
+    let m1 = COTSeed(Block::rand(&mut rng)).expand(count);
+    let choices = ChoiceSeed(rng.gen()).expand(count).iter().collect();
+    (m0, m1, choices)
+}
+
+impl ROTsForSender {
+    /// `count` ROTs expanded from a seed that the receiver also knows.
+    ///
+    /// **Not secure**: both servers learn both sides. This stands in for an
+    /// OT extension between the servers (e.g. EMP Ferret or IKNP), which is
+    /// not implemented, so that the rest of the server-generated COT mode can
+    /// be run and measured.
+    #[cfg(any(test, feature = "simulation-helpers"))]
+    pub fn from_shared_seed(seed: u64, count: usize) -> Self {
+        let (m0, m1, _) = expand_shared_seed(seed, count);
+        Self { m0, m1 }
+    }
+}
+
+impl ROTsForReceiver {
+    /// Receiver side of `ROTsForSender::from_shared_seed`. Not secure either.
+    #[cfg(any(test, feature = "simulation-helpers"))]
+    pub fn from_shared_seed(seed: u64, count: usize) -> Self {
+        let (m0, m1, choices) = expand_shared_seed(seed, count);
+        let mc = m0
+            .into_iter()
+            .zip(m1)
+            .zip(&choices)
+            .map(|((m0, m1), &c)| if c { m1 } else { m0 })
+            .collect();
+        Self { choices, mc }
+    }
+}
+
+/// COTs of the OT sender, in the shape of `B2ACOTToAlice` after expansion.
+pub struct ServerCOTsForSender {
+    pub delta: Block,
+    pub qs: Vec<Block>,
+}
+
+impl ServerCOTsForSender {
+    /// Return my COTs, and the correction `d` to send to the receiver.
+    pub fn from_rots(rots: ROTsForSender, delta: Block) -> (Self, Vec<Block>) {
+        let correction = rots
+            .m0
+            .iter()
+            .zip(&rots.m1)
+            .map(|(m0, m1)| m0.add_gf(*m1).add_gf(delta))
+            .collect();
+        (Self { delta, qs: rots.m0 }, correction)
+    }
+
+    /// Apply the choice flips sent by the receiver, so that the choices of
+    /// the receiver become its input bits.
+    ///
+    /// # Panics
+    /// Panics if `flips` does not cover all OTs.
+    pub fn apply_flips<T: UInt>(&mut self, flips: &[BitsLE<T>]) {
+        assert_eq!(flips.len() * T::NUM_BITS, self.qs.len());
+        let delta = self.delta;
+        self.qs
+            .iter_mut()
+            .zip(flips.iter().flat_map(|x| x.iter()))
+            .filter(|(_, e)| *e)
+            .for_each(|(q, _)| *q = q.add_gf(delta));
+    }
+}
+
+/// COTs of the OT receiver, in the shape of `B2ACOTToBob::ts`.
+pub struct ServerCOTsForReceiver {
+    pub choices: Vec<bool>,
+    pub ts: Vec<Block>,
+}
+
+impl ServerCOTsForReceiver {
+    /// # Panics
+    /// Panics if `correction` does not have one block per OT.
+    pub fn from_rots(rots: ROTsForReceiver, correction: &[Block]) -> Self {
+        assert_eq!(correction.len(), rots.mc.len());
+        let ts = rots
+            .mc
+            .into_iter()
+            .zip(&rots.choices)
+            .zip(correction)
+            .map(|((m, &c), d)| if c { m.add_gf(*d) } else { m })
+            .collect();
+        Self {
+            choices: rots.choices,
+            ts,
+        }
+    }
+}
+
+/// Choice flips `e = c + x` of the receiver, packed like the inputs.
+///
+/// # Panics
+/// Panics if `choices` does not have one bit per input bit.
+pub fn choice_flips<T: UInt>(choices: &[bool], inputs: &[BitsLE<T>]) -> Vec<BitsLE<T>> {
+    assert_eq!(choices.len(), inputs.len() * T::NUM_BITS);
+    inputs
+        .iter()
+        .zip(choices.chunks(T::NUM_BITS))
+        .map(|(x, c)| *x ^ BitsLE::from_booleans(c))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::b2a::{bit_comp_as_ot_receiver_batch, bit_comp_as_ot_sender_batch};
+
+    const SEED: u64 = 0x0123;
+
+    #[test]
+    fn test_rot_to_cot() {
+        const COUNT: usize = 1000;
+        let mut rng = StdRng::seed_from_u64(1);
+        let delta = Block::rand(&mut rng);
+        let (sender, correction) =
+            ServerCOTsForSender::from_rots(ROTsForSender::from_shared_seed(SEED, COUNT), delta);
+        let receiver = ServerCOTsForReceiver::from_rots(
+            ROTsForReceiver::from_shared_seed(SEED, COUNT),
+            &correction,
+        );
+        assert!(receiver.choices.iter().any(|c| *c));
+        assert!(receiver.choices.iter().any(|c| !*c));
+        for ((q, t), c) in sender.qs.iter().zip(&receiver.ts).zip(&receiver.choices) {
+            let expected = if *c { q.add_gf(delta) } else { *q };
+            assert_eq!(*t, expected);
+        }
+    }
+
+    #[test]
+    fn test_b2a_with_server_cots() {
+        const GSIZE: usize = 50;
+        let mut rng = StdRng::seed_from_u64(2);
+        let inputs = (0..GSIZE).map(|_| rng.gen::<u32>()).collect::<Vec<_>>();
+        let (inputs_0, inputs_1): (Vec<_>, Vec<_>) = inputs
+            .iter()
+            .map(|x| BitsLE(*x).to_boolean_shares(&mut rng))
+            .unzip();
+
+        let count = GSIZE * u32::NUM_BITS;
+        let delta = Block::rand(&mut rng);
+        let (mut sender, correction) =
+            ServerCOTsForSender::from_rots(ROTsForSender::from_shared_seed(SEED, count), delta);
+        let receiver = ServerCOTsForReceiver::from_rots(
+            ROTsForReceiver::from_shared_seed(SEED, count),
+            &correction,
+        );
+        sender.apply_flips(&choice_flips(&receiver.choices, &inputs_1));
+
+        let (y0s, us) =
+            bit_comp_as_ot_sender_batch::<u32, u64>(&inputs_0, sender.delta, &sender.qs).unwrap();
+        let y1s = bit_comp_as_ot_receiver_batch::<u32, u64>(&inputs_1, &receiver.ts, &us).unwrap();
+        for ((y0, y1), x) in y0s.iter().zip(&y1s).zip(&inputs) {
+            assert_eq!(y0.wrapping_add(*y1), *x as u64);
+        }
+    }
+}
@@ -1,25 +1,193 @@
 //! Server side code for ROT
 
 use crate::{
-    bits::{BitsLE},
+    bits::{BitsLE, PackedBits},
     block_crypto::rng::BlockRng,
     cot::COTSeed,
+    error::{check_len, Result},
     uint::UInt,
 };
 use block::{gf::GF2_256, Block};
+use serialize::{AsUseCast, Communicate, UseCast};
+use std::{
+    cell::RefCell,
+    io::{Read, Write},
+    mem::{self, size_of},
+    sync::Arc,
+};
 
-
-use super::ChoiceSeed;
+use super::{dims::ProtocolDims, ChoiceSeed};
 
 /// Freshly sample coefficients for OT Verification.
 #[inline]
 pub fn sample_chi(num_ots: usize, shared_seed: u64) -> Vec<Block> {
-    let mut rng = BlockRng::new(Some(Block([shared_seed, 0].into())));
     let mut chi = vec![Block::default(); num_ots];
-    rng.random_blocks(&mut chi);
+    sample_chi_into(num_ots, shared_seed, &mut chi);
     chi
 }
 
+/// Same as [`sample_chi`], into the first `num_ots` blocks of `dest`, e.g. a
+/// buffer reused across clients.
+pub fn sample_chi_into(num_ots: usize, shared_seed: u64, dest: &mut [Block]) {
+    chi_rng(shared_seed).random_blocks(&mut dest[..num_ots]);
+}
+
+fn chi_rng(shared_seed: u64) -> BlockRng {
+    BlockRng::new(Some(Block([shared_seed, 0].into())))
+}
+
+/// Number of blocks of a seeded [`Chi`] generated at a time: 64 KiB, small
+/// enough to stay in cache while the inner products consume them.
+pub const CHI_CHUNK_SIZE: usize = 1 << 12;
+
+thread_local! {
+    /// Buffer of the chunks of seeded chis, reused by all clients verified on
+    /// this thread.
+    static CHI_SCRATCH: RefCell<Vec<Block>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Coefficients of the OT verification of one client.
+#[derive(Clone, Debug)]
+pub enum Chi {
+    /// Sampled once, e.g. shared by all clients.
+    Shared(Arc<Vec<Block>>),
+    /// The coefficients of `sample_chi(num_ots, seed)`, generated a chunk at
+    /// a time while the inner products consume them, so that they never
+    /// exist at once.
+    Seeded { num_ots: usize, seed: u64 },
+}
+
+/// Coefficients of OT verification, visited a chunk at a time by the inner
+/// products.
+pub trait ChiChunks {
+    fn num_ots(&self) -> usize;
+
+    /// Call `f` on consecutive chunks of the coefficients, in order.
+    fn for_each_chunk(&self, f: impl FnMut(&[Block]));
+}
+
+impl ChiChunks for [Block] {
+    fn num_ots(&self) -> usize {
+        self.len()
+    }
+
+    fn for_each_chunk(&self, mut f: impl FnMut(&[Block])) {
+        f(self)
+    }
+}
+
+impl ChiChunks for Vec<Block> {
+    fn num_ots(&self) -> usize {
+        self.len()
+    }
+
+    fn for_each_chunk(&self, mut f: impl FnMut(&[Block])) {
+        f(self)
+    }
+}
+
+impl ChiChunks for Chi {
+    fn num_ots(&self) -> usize {
+        match self {
+            Chi::Shared(chi) => chi.len(),
+            Chi::Seeded { num_ots, .. } => *num_ots,
+        }
+    }
+
+    /// The chunks of a seeded chi are generated into a buffer of this thread.
+    fn for_each_chunk(&self, mut f: impl FnMut(&[Block])) {
+        let (num_ots, seed) = match self {
+            Chi::Shared(chi) => return f(chi),
+            Chi::Seeded { num_ots, seed } => (*num_ots, *seed),
+        };
+        // taken out, so that `f` may use a seeded chi as well
+        let mut scratch = CHI_SCRATCH.with(|scratch| mem::take(&mut *scratch.borrow_mut()));
+        scratch.resize(CHI_CHUNK_SIZE.min(num_ots), Block::default());
+        // the rng is a counter, so generating in chunks gives the same blocks
+        let mut rng = chi_rng(seed);
+        let mut remaining = num_ots;
+        while remaining > 0 {
+            let chunk = &mut scratch[..remaining.min(CHI_CHUNK_SIZE)];
+            rng.random_blocks(chunk);
+            f(chunk);
+            remaining -= chunk.len();
+        }
+        CHI_SCRATCH.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            if scratch.len() > cell.len() {
+                *cell = scratch;
+            }
+        });
+    }
+}
+
+/// Whether the OT receiver sends `t_til` reduced to GF(2^128), see [`TTil`].
+/// Set by the `reduced-ot-verify` feature, which changes the transcript, so
+/// both servers and the clients must agree on it.
+pub const REDUCED_OT_VERIFY: bool = cfg!(feature = "reduced-ot-verify");
+
+/// `t_til` of OT verification, as sent by the OT receiver: the unreduced
+/// inner product in GF(2^256), or its reduction to GF(2^128), which is 16
+/// bytes smaller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TTil {
+    Full(GF2_256),
+    Reduced(Block),
+}
+
+impl TTil {
+    /// Size on the wire of the form selected by [`REDUCED_OT_VERIFY`].
+    pub const WIRE_SIZE: usize = if REDUCED_OT_VERIFY {
+        size_of::<Block>()
+    } else {
+        size_of::<GF2_256>()
+    };
+
+    /// `t_til` in the form selected by [`REDUCED_OT_VERIFY`].
+    pub fn new(t_til: GF2_256) -> Self {
+        if REDUCED_OT_VERIFY {
+            TTil::Reduced(t_til.reduce())
+        } else {
+            TTil::Full(t_til)
+        }
+    }
+
+    /// Whether `self` equals `expected`, reduced if `self` is.
+    pub fn matches(self, expected: GF2_256) -> bool {
+        match self {
+            TTil::Full(t_til) => t_til == expected,
+            TTil::Reduced(t_til) => t_til == expected.reduce(),
+        }
+    }
+}
+
+impl Communicate for TTil {
+    type Deserialized = Self;
+
+    fn size_in_bytes(&self) -> usize {
+        match self {
+            TTil::Full(t_til) => t_til.size_in_bytes(),
+            TTil::Reduced(t_til) => t_til.use_cast().size_in_bytes(),
+        }
+    }
+
+    fn to_bytes<W: Write>(&self, dest: W) {
+        match self {
+            TTil::Full(t_til) => t_til.to_bytes(dest),
+            TTil::Reduced(t_til) => t_til.use_cast().to_bytes(dest),
+        }
+    }
+
+    /// Reads the form selected by [`REDUCED_OT_VERIFY`].
+    fn from_bytes<R: Read>(bytes: R) -> serialize::Result<Self::Deserialized> {
+        Ok(if REDUCED_OT_VERIFY {
+            TTil::Reduced(UseCast::<Block>::from_bytes(bytes)?)
+        } else {
+            TTil::Full(GF2_256::from_bytes(bytes)?)
+        })
+    }
+}
+
 pub struct OTReceiver {}
 
 impl OTReceiver {
@@ -30,34 +198,86 @@ impl OTReceiver {
     /// x_til = x_hat.dot(chi)
     /// t_til = ts.dot(chi)
     /// ```
+    /// `t_til` is reduced if [`REDUCED_OT_VERIFY`] is set.
     /// * `chi`: random coefficients for OT
     /// * `xs`: boolean share for inputs
     /// * `ts`: received OT
     /// * `r_seed`: random seed for to generate r, for x_hat
     ///
-    /// Returns `x_til` and `t_til`
-    #[must_use]
-    pub fn send_x_til_t_til<B: UInt>(
+    /// Returns `x_til` and `t_til`. Fails with `Dims` if `chi` is shorter
+    /// than the input bits, and with `LengthMismatch` if `ts` does not have
+    /// one OT per coefficient of `chi`.
+    pub fn send_x_til_t_til<B: UInt, X: ChiChunks + ?Sized>(
         ts: &[Block],
-        chi: &[Block],
+        chi: &X,
         inputs_1: &[BitsLE<B>],
         r_seed: ChoiceSeed,
-    ) -> (Block, GF2_256) {
-        // sanity check: chi and ts should have same length, length of xs should be <=
-        // length of chi
-        assert_eq!(chi.len(), ts.len());
-        assert!(inputs_1.len() <= chi.len());
+    ) -> Result<(Block, TTil)> {
+        // chi should cover the input bits. With small `gsize`, most of chi is
+        // for `r`.
+        let dims = ProtocolDims::from_num_total::<B>(inputs_1.len(), chi.num_ots())?;
+        // checked before `r` is expanded
+        check_len("ts", chi.num_ots(), ts.len())?;
+        let r = r_seed.expand(dims.num_additional());
+        Self::send_x_til_t_til_with_r(ts, chi, inputs_1, &r)
+    }
+
+    /// Same as [`Self::send_x_til_t_til`], with the choice bits `r` of the
+    /// additional OTs already expanded from their seed. `r` does not depend
+    /// on `chi`, so it can be expanded before `chi` is known.
+    pub fn send_x_til_t_til_with_r<B: UInt, X: ChiChunks + ?Sized>(
+        ts: &[Block],
+        chi: &X,
+        inputs_1: &[BitsLE<B>],
+        r: &PackedBits,
+    ) -> Result<(Block, TTil)> {
+        // chi and ts should have same length, and x_hat should have one bit
+        // per OT
+        check_len("ts", chi.num_ots(), ts.len())?;
+        check_len(
+            "choice bits",
+            chi.num_ots(),
+            inputs_1.len() * B::NUM_BITS + r.len(),
+        )?;
 
         // generate x_hat
-        let r_size = chi.len() - inputs_1.len() * B::NUM_BITS;
-        let r = r_seed.expand(r_size);
-        let x_hat = inputs_1.iter().map(|x| x.iter()).flatten().chain(r.iter());
+        let x_hat = inputs_1.iter().flat_map(|x| x.iter()).chain(r.iter());
+        Ok(Self::x_til_t_til(ts, chi, x_hat))
+    }
 
-        let x_til = inner_product_with_boolean_scalar(x_hat, chi);
+    /// Same as [`Self::send_x_til_t_til_with_r`], with the choice bits of all
+    /// OTs given at once, e.g. when they are not the bits of the inputs.
+    /// Fails with `LengthMismatch` unless there is one OT and one choice per
+    /// coefficient of `chi`.
+    pub fn send_x_til_t_til_with_choices<X: ChiChunks + ?Sized>(
+        ts: &[Block],
+        chi: &X,
+        choices: &[bool],
+    ) -> Result<(Block, TTil)> {
+        check_len("ts", chi.num_ots(), ts.len())?;
+        check_len("choice bits", chi.num_ots(), choices.len())?;
+        Ok(Self::x_til_t_til(ts, chi, choices.iter().copied()))
+    }
 
-        let t_til = inner_product(ts, chi);
+    fn x_til_t_til<X: ChiChunks + ?Sized>(
+        ts: &[Block],
+        chi: &X,
+        mut x_hat: impl Iterator<Item = bool>,
+    ) -> (Block, TTil) {
+        // both inner products are sums over the OTs, so they are summed up
+        // one chunk of chi at a time
+        let mut x_til = Block::default();
+        let mut t_til = GF2_256::default();
+        let mut ts = ts;
+        chi.for_each_chunk(|chi| {
+            let (ts_chunk, rest) = ts.split_at(chi.len());
+            let x_hat_chunk = x_hat.by_ref().take(chi.len());
+            x_til = x_til.add_gf(inner_product_with_boolean_scalar(x_hat_chunk, chi));
+            t_til = t_til.add_gf(inner_product(ts_chunk, chi));
+            ts = rest;
+        });
 
-        (x_til, t_til)
+        (x_til, TTil::new(t_til))
     }
 }
 
@@ -71,60 +291,70 @@ impl OTSender {
     /// rhs = q_til + delta * x_til
     /// return lhs == rhs
     /// ```
+    /// If `t_til` is reduced, `rhs` is reduced before the comparison.
     ///
     /// Return `qs`, which is first COT.
     ///
     /// # Panics
     /// This function panics if verification fails.
-    pub fn verify_and_get_cot(
+    pub fn verify_and_get_cot<X: ChiChunks + ?Sized>(
         qs_seed: COTSeed,
-        chi: &[Block],
+        chi: &X,
         delta: Block,
         x_til: Block,
-        t_til: GF2_256,
+        t_til: TTil,
     ) -> (Vec<Block>, bool) {
-        let num_cots = chi.len();
+        let num_cots = chi.num_ots();
         let qs = qs_seed.expand(num_cots);
         // sanity check: chi and qs should have same length
-        let q_til = inner_product(&qs, chi);
-        let lhs = t_til;
+        let q_til = inner_product_chunked(&qs, chi);
         let rhs = q_til.add_gf(delta.mul_gf_no_reduction(x_til));
 
-        (qs, lhs == rhs)
+        (qs, t_til.matches(rhs))
     }
 }
 
 /// Calculate `a.dot(b)` where `a` is a vector of booleans in packed format, and
 /// `b` is a slice of GF(2^128) blocks.
 pub fn inner_product_with_boolean_scalar(a: impl Iterator<Item = bool>, b: &[Block]) -> Block {
-    a.zip(b).fold(Block::default(), |prev, (left, right)| {
-        if left {
-            prev.add_gf(*right)
-        } else {
-            prev
-        }
-    })
+    Block::sum_selected(a, b)
 }
 
 pub fn inner_product(a: &[Block], b: &[Block]) -> GF2_256 {
     debug_assert_eq!(a.len(), b.len());
-    a.iter()
-        .zip(b)
-        .fold(GF2_256::default(), |prev, (left, right)| {
-            prev.add_gf(left.mul_gf_no_reduction(*right))
-        })
+    Block::inner_product_no_reduction(a, b)
+}
+
+/// Same as [`inner_product`] with `chi`, one chunk of `chi` at a time.
+pub fn inner_product_chunked<X: ChiChunks + ?Sized>(a: &[Block], chi: &X) -> GF2_256 {
+    assert_eq!(a.len(), chi.num_ots());
+    let mut sum = GF2_256::default();
+    let mut a = a;
+    chi.for_each_chunk(|chi| {
+        let (a_chunk, rest) = a.split_at(chi.len());
+        sum = sum.add_gf(inner_product(a_chunk, chi));
+        a = rest;
+    });
+    sum
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
         cot::{
-            client::{num_additional_ot_needed, COTGen},
-            server::{sample_chi, OTReceiver, OTSender},
+            client::{num_additional_ot_needed, B2ACOTToBob, COTGen, DEFAULT_SEC_PARAM},
+            dims::DimsError,
+            server::{
+                inner_product, inner_product_chunked, sample_chi, sample_chi_into, Chi, ChiChunks,
+                OTReceiver, OTSender, TTil, CHI_CHUNK_SIZE,
+            },
         },
+        error::ProtocolError,
         uint::UInt,
     };
+    use block::Block;
     use rand::{rngs::StdRng, Rng, SeedableRng};
+    use serialize::Communicate;
 
     #[test]
     fn verify_end_to_end() {
@@ -133,7 +363,7 @@ mod tests {
         let inputs_1 = (0..1024)
             .map(|_| rng.gen::<u32>().bits_le())
             .collect::<Vec<_>>(); // known by clients and OT receiver
-        let num_additional_ots = num_additional_ot_needed(inputs_1.len());
+        let num_additional_ots = num_additional_ot_needed(inputs_1.len(), DEFAULT_SEC_PARAM);
 
         // client samples COTs
         let delta = COTGen::sample_delta(&mut rng);
@@ -141,20 +371,188 @@ mod tests {
             COTGen::sample_cots(&mut rng, &inputs_1, delta, num_additional_ots);
 
         // both server share the same chi
-        let chi = sample_chi(
-            inputs_1.len() * u32::NUM_BITS + num_additional_ots,
-            1234567,
-        );
+        let chi = sample_chi(inputs_1.len() * u32::NUM_BITS + num_additional_ots, 1234567);
 
         // OT receiver knows the choice bits (which is the same as its input boolean
         // share)
         let (x_til, t_til) =
-            OTReceiver::send_x_til_t_til(&msg_to_rx.ts, &chi, &inputs_1, msg_to_rx.r_seed);
+            OTReceiver::send_x_til_t_til(msg_to_rx.ts(), &chi, &inputs_1, msg_to_rx.r_seed)
+                .unwrap();
 
         // OT sender verifies the COT using OT receiver's message
         let (_, b) = OTSender::verify_and_get_cot(msg_to_cx.qs_seed, &chi, delta, x_til, t_til);
-        assert!(b)
+        assert!(b);
+
+        // the client generates the COTs without expanding the seed of the OT
+        // sender, which expands it once
+        assert_eq!(msg_to_cx.qs_seed.expansion_count(), 1);
+    }
+
+    #[test]
+    fn verify_covers_main_and_tail() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let inputs_1 = (0..16)
+            .map(|_| rng.gen::<u8>().bits_le())
+            .collect::<Vec<_>>();
+        let num_additional_ots = num_additional_ot_needed(inputs_1.len(), DEFAULT_SEC_PARAM);
+        let delta = COTGen::sample_delta(&mut rng);
+        let (msg_to_cx, msg_to_rx) =
+            COTGen::sample_cots(&mut rng, &inputs_1, delta, num_additional_ots);
+        let msg_to_rx = B2ACOTToBob::from_bytes(&msg_to_rx.into_bytes_owned()[..]).unwrap();
+        let chi = sample_chi(inputs_1.len() * u8::NUM_BITS + num_additional_ots, 8);
+
+        // the servers split by their own dims, which is the split of the client
+        let (main, tail) = msg_to_rx.split_tail(num_additional_ots);
+        assert_eq!((main, tail), (msg_to_rx.main(), msg_to_rx.tail()));
+        let (chi_main, chi_tail) = chi.split_at(main.len());
+        let (x_til, t_til) =
+            OTReceiver::send_x_til_t_til(msg_to_rx.ts(), &chi, &inputs_1, msg_to_rx.r_seed)
+                .unwrap();
+        assert!(t_til.matches(inner_product(main, chi_main).add_gf(inner_product(tail, chi_tail))));
+        let (_, b) = OTSender::verify_and_get_cot(msg_to_cx.qs_seed, &chi, delta, x_til, t_til);
+        assert!(b);
+
+        // a wrong OT of the tail fails as one of the inputs does
+        let mut ts = msg_to_rx.ts().to_vec();
+        let last = ts.len() - 1;
+        ts[last] = ts[last].add_gf(Block(1u128.into()));
+        let (x_til, t_til) =
+            OTReceiver::send_x_til_t_til(&ts, &chi, &inputs_1, msg_to_rx.r_seed).unwrap();
+        let (_, b) = OTSender::verify_and_get_cot(msg_to_cx.qs_seed, &chi, delta, x_til, t_til);
+        assert!(!b);
+    }
+
+    #[test]
+    fn verify_reduced_and_full() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let inputs_1 = (0..16)
+            .map(|_| rng.gen::<u8>().bits_le())
+            .collect::<Vec<_>>();
+        let num_additional_ots = num_additional_ot_needed(inputs_1.len(), DEFAULT_SEC_PARAM);
+        let delta = COTGen::sample_delta(&mut rng);
+        let (msg_to_cx, msg_to_rx) =
+            COTGen::sample_cots(&mut rng, &inputs_1, delta, num_additional_ots);
+        let chi = sample_chi(inputs_1.len() * u8::NUM_BITS + num_additional_ots, 7);
+
+        let (x_til, _) =
+            OTReceiver::send_x_til_t_til(msg_to_rx.ts(), &chi, &inputs_1, msg_to_rx.r_seed)
+                .unwrap();
+        let t_til = inner_product(msg_to_rx.ts(), &chi);
+        let tampered = t_til.add_gf(Block(1u128.into()).mul_gf_no_reduction(Block(1u128.into())));
+        for (t_til, expected) in [
+            (TTil::Full(t_til), true),
+            (TTil::Reduced(t_til.reduce()), true),
+            (TTil::Full(tampered), false),
+            (TTil::Reduced(tampered.reduce()), false),
+        ] {
+            let (_, b) = OTSender::verify_and_get_cot(msg_to_cx.qs_seed, &chi, delta, x_til, t_til);
+            assert_eq!(b, expected);
+        }
 
-        // should not panic
+        let full = TTil::Full(t_til).size_in_bytes();
+        assert_eq!(TTil::Reduced(t_til.reduce()).size_in_bytes() + 16, full);
+        assert_eq!(TTil::new(t_til).size_in_bytes(), TTil::WIRE_SIZE);
+        assert_eq!(
+            TTil::from_bytes(&TTil::new(t_til).into_bytes_owned()[..]).unwrap(),
+            TTil::new(t_til)
+        );
+    }
+
+    #[test]
+    fn seeded_chi_matches_sampled() {
+        const SEED: u64 = 0xc41;
+        // not a multiple of the chunk size
+        let num_ots = 3 * CHI_CHUNK_SIZE + 5;
+        let chi = sample_chi(num_ots, SEED);
+        let seeded = Chi::Seeded {
+            num_ots,
+            seed: SEED,
+        };
+
+        let mut reused = vec![Block::default(); num_ots + 7];
+        sample_chi_into(num_ots, SEED, &mut reused);
+        assert_eq!(&reused[..num_ots], &chi[..]);
+
+        let mut streamed = Vec::new();
+        seeded.for_each_chunk(|chunk| {
+            assert!(chunk.len() <= CHI_CHUNK_SIZE);
+            streamed.extend_from_slice(chunk);
+        });
+        assert_eq!(streamed, chi);
+
+        let mut rng = StdRng::seed_from_u64(2);
+        let a = (0..num_ots)
+            .map(|_| Block(rng.gen::<u128>().into()))
+            .collect::<Vec<_>>();
+        assert_eq!(inner_product_chunked(&a, &seeded), inner_product(&a, &chi));
+    }
+
+    #[test]
+    fn verify_with_seeded_chi() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let inputs_1 = (0..2000)
+            .map(|_| rng.gen::<u32>().bits_le())
+            .collect::<Vec<_>>();
+        let num_additional_ots = num_additional_ot_needed(inputs_1.len(), DEFAULT_SEC_PARAM);
+        let delta = COTGen::sample_delta(&mut rng);
+        let (msg_to_cx, msg_to_rx) =
+            COTGen::sample_cots(&mut rng, &inputs_1, delta, num_additional_ots);
+        let num_ots = inputs_1.len() * u32::NUM_BITS + num_additional_ots;
+        assert!(num_ots > CHI_CHUNK_SIZE);
+        let chi = sample_chi(num_ots, 11);
+        let seeded = Chi::Seeded { num_ots, seed: 11 };
+
+        // bit-identical to the materialized chi
+        let (x_til, t_til) =
+            OTReceiver::send_x_til_t_til(msg_to_rx.ts(), &seeded, &inputs_1, msg_to_rx.r_seed)
+                .unwrap();
+        assert_eq!(
+            (x_til, t_til),
+            OTReceiver::send_x_til_t_til(msg_to_rx.ts(), &chi, &inputs_1, msg_to_rx.r_seed)
+                .unwrap()
+        );
+        let (_, b) = OTSender::verify_and_get_cot(msg_to_cx.qs_seed, &seeded, delta, x_til, t_til);
+        assert!(b);
+    }
+
+    #[test]
+    fn receiver_message_length_errors() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let inputs_1 = (0..16)
+            .map(|_| rng.gen::<u8>().bits_le())
+            .collect::<Vec<_>>();
+        let num_additional_ots = num_additional_ot_needed(inputs_1.len(), DEFAULT_SEC_PARAM);
+        let delta = COTGen::sample_delta(&mut rng);
+        let (_, msg_to_rx) = COTGen::sample_cots(&mut rng, &inputs_1, delta, num_additional_ots);
+        let num_ots = inputs_1.len() * u8::NUM_BITS + num_additional_ots;
+        let chi = sample_chi(num_ots, 5);
+
+        let short_chi = &chi[..100];
+        assert_eq!(
+            OTReceiver::send_x_til_t_til(msg_to_rx.ts(), short_chi, &inputs_1, msg_to_rx.r_seed)
+                .unwrap_err(),
+            ProtocolError::Dims(DimsError::TooFewOts {
+                num_ot: 128,
+                num_total: 100
+            })
+        );
+        let short_ts = &msg_to_rx.ts()[1..];
+        assert_eq!(
+            OTReceiver::send_x_til_t_til(short_ts, &chi, &inputs_1, msg_to_rx.r_seed).unwrap_err(),
+            ProtocolError::LengthMismatch {
+                expected: num_ots,
+                actual: num_ots - 1,
+                what: "ts"
+            }
+        );
+        let r = msg_to_rx.r_seed.expand(num_additional_ots - 1);
+        assert_eq!(
+            OTReceiver::send_x_til_t_til_with_r(msg_to_rx.ts(), &chi, &inputs_1, &r).unwrap_err(),
+            ProtocolError::LengthMismatch {
+                expected: num_ots,
+                actual: num_ots - 1,
+                what: "choice bits"
+            }
+        );
     }
 }
@@ -0,0 +1,250 @@
+//! OT verification as a two-party protocol without I/O: the OT receiver sends
+//! one message, and the OT sender checks it. The servers only carry the
+//! message over the network.
+
+use std::mem;
+
+use block::Block;
+
+use crate::{bits::BitsLE, error::Result, uint::UInt};
+
+use super::{
+    server::{ChiChunks, OTReceiver, OTSender, TTil},
+    COTSeed, ChoiceSeed,
+};
+
+/// Result of OT verification for the OT sender.
+#[derive(Debug)]
+pub struct VerifyOutcome {
+    /// The COTs of the OT sender, expanded from the seed of the client.
+    pub qs: Vec<Block>,
+    /// Whether the message of the OT receiver passed the check.
+    pub ok: bool,
+}
+
+enum SenderState<X> {
+    AwaitingMessage {
+        qs_seed: COTSeed,
+        delta: Block,
+        chi: X,
+    },
+    Done,
+}
+
+/// OT sender side of OT verification: it waits for the message of the OT
+/// receiver, then checks it once.
+pub struct OtVerifySender<X> {
+    state: SenderState<X>,
+}
+
+impl<X: ChiChunks> OtVerifySender<X> {
+    pub fn new(qs_seed: COTSeed, delta: Block, chi: X) -> Self {
+        Self {
+            state: SenderState::AwaitingMessage {
+                qs_seed,
+                delta,
+                chi,
+            },
+        }
+    }
+
+    /// Check the message of the OT receiver, see
+    /// [`OTSender::verify_and_get_cot`].
+    ///
+    /// # Panics
+    /// If a message is already received.
+    pub fn receive(&mut self, x_til: Block, t_til: TTil) -> VerifyOutcome {
+        match mem::replace(&mut self.state, SenderState::Done) {
+            SenderState::AwaitingMessage {
+                qs_seed,
+                delta,
+                chi,
+            } => {
+                let (qs, ok) = OTSender::verify_and_get_cot(qs_seed, &chi, delta, x_til, t_til);
+                VerifyOutcome { qs, ok }
+            },
+            SenderState::Done => panic!("OT verification already received a message"),
+        }
+    }
+
+    /// Whether the message of the OT receiver is received.
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, SenderState::Done)
+    }
+}
+
+/// OT receiver side of OT verification. Its only message is computed by
+/// `new`, see [`OTReceiver::send_x_til_t_til`], which also says when it
+/// fails.
+#[derive(Clone, Copy, Debug)]
+pub struct OtVerifyReceiver {
+    x_til: Block,
+    t_til: TTil,
+}
+
+impl OtVerifyReceiver {
+    pub fn new<B: UInt, X: ChiChunks + ?Sized>(
+        ts: &[Block],
+        chi: &X,
+        inputs_1: &[BitsLE<B>],
+        r_seed: ChoiceSeed,
+    ) -> Result<Self> {
+        let (x_til, t_til) = OTReceiver::send_x_til_t_til(ts, chi, inputs_1, r_seed)?;
+        Ok(Self { x_til, t_til })
+    }
+
+    /// `x_til` and `t_til`, for the OT sender.
+    pub fn message(&self) -> (Block, TTil) {
+        (self.x_til, self.t_til)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use block::Block;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{
+        bits::BitsLE,
+        cot::{
+            client::{
+                num_additional_ot_needed, B2ACOTToAlice, B2ACOTToBob, COTGen, DEFAULT_SEC_PARAM,
+            },
+            server::{sample_chi, Chi},
+            verify::{OtVerifyReceiver, OtVerifySender},
+        },
+        error::ProtocolError,
+        uint::UInt,
+    };
+
+    struct Setup {
+        inputs_1: Vec<BitsLE<u16>>,
+        delta: Block,
+        to_sender: B2ACOTToAlice,
+        to_receiver: B2ACOTToBob,
+        chi: Chi,
+    }
+
+    fn setup(seed: u64) -> Setup {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let inputs_1 = (0..40)
+            .map(|_| rng.gen::<u16>().bits_le())
+            .collect::<Vec<_>>();
+        let num_additional = num_additional_ot_needed(inputs_1.len(), DEFAULT_SEC_PARAM);
+        let delta = COTGen::sample_delta(&mut rng);
+        let (to_sender, to_receiver) =
+            COTGen::sample_cots(&mut rng, &inputs_1, delta, num_additional);
+        let chi = Chi::Seeded {
+            num_ots: inputs_1.len() * u16::NUM_BITS + num_additional,
+            seed: seed ^ 0x5eed,
+        };
+        Setup {
+            inputs_1,
+            delta,
+            to_sender,
+            to_receiver,
+            chi,
+        }
+    }
+
+    #[test]
+    fn honest_receiver_passes() {
+        let s = setup(1);
+        let receiver = OtVerifyReceiver::new(
+            s.to_receiver.ts(),
+            &s.chi,
+            &s.inputs_1,
+            s.to_receiver.r_seed,
+        )
+        .unwrap();
+        let mut sender = OtVerifySender::new(s.to_sender.qs_seed, s.delta, s.chi.clone());
+        assert!(!sender.is_done());
+
+        let (x_til, t_til) = receiver.message();
+        let outcome = sender.receive(x_til, t_til);
+        assert!(outcome.ok);
+        assert!(sender.is_done());
+        // the COTs of both parties are correlated by `delta` on the choices
+        assert_eq!(outcome.qs.len(), s.to_receiver.ts().len());
+        let choices = s.inputs_1.iter().flat_map(|x| x.iter());
+        for ((q, t), choice) in outcome.qs.iter().zip(s.to_receiver.ts()).zip(choices) {
+            let expected = if choice { q.add_gf(s.delta) } else { *q };
+            assert_eq!(*t, expected);
+        }
+    }
+
+    #[test]
+    fn shared_and_seeded_chi_agree() {
+        let s = setup(2);
+        let shared = Chi::Shared(sample_chi(s.to_receiver.ts().len(), 2 ^ 0x5eed).into());
+        let seeded = OtVerifyReceiver::new(
+            s.to_receiver.ts(),
+            &s.chi,
+            &s.inputs_1,
+            s.to_receiver.r_seed,
+        )
+        .unwrap();
+        let (x_til, t_til) = seeded.message();
+        let mut sender = OtVerifySender::new(s.to_sender.qs_seed, s.delta, shared);
+        assert!(sender.receive(x_til, t_til).ok);
+    }
+
+    #[test]
+    fn tampered_ts_fail() {
+        let s = setup(3);
+        let mut ts = s.to_receiver.ts().to_vec();
+        ts[5] = ts[5].add_gf(Block(1u128.into()));
+        let receiver =
+            OtVerifyReceiver::new(&ts, &s.chi, &s.inputs_1, s.to_receiver.r_seed).unwrap();
+        let mut sender = OtVerifySender::new(s.to_sender.qs_seed, s.delta, s.chi.clone());
+
+        let (x_til, t_til) = receiver.message();
+        assert!(!sender.receive(x_til, t_til).ok);
+    }
+
+    #[test]
+    fn tampered_inputs_fail() {
+        let s = setup(4);
+        let mut inputs_1 = s.inputs_1.clone();
+        inputs_1[0] = inputs_1[0].set_bit(0, !inputs_1[0].get_bit(0));
+        let receiver =
+            OtVerifyReceiver::new(s.to_receiver.ts(), &s.chi, &inputs_1, s.to_receiver.r_seed)
+                .unwrap();
+        let mut sender = OtVerifySender::new(s.to_sender.qs_seed, s.delta, s.chi.clone());
+
+        let (x_til, t_til) = receiver.message();
+        assert!(!sender.receive(x_til, t_til).ok);
+    }
+
+    #[test]
+    #[should_panic(expected = "already received")]
+    fn second_message_panics() {
+        let s = setup(5);
+        let receiver = OtVerifyReceiver::new(
+            s.to_receiver.ts(),
+            &s.chi,
+            &s.inputs_1,
+            s.to_receiver.r_seed,
+        )
+        .unwrap();
+        let mut sender = OtVerifySender::new(s.to_sender.qs_seed, s.delta, s.chi.clone());
+        let (x_til, t_til) = receiver.message();
+        sender.receive(x_til, t_til);
+        sender.receive(x_til, t_til);
+    }
+
+    #[test]
+    fn short_ts_fail() {
+        let s = setup(6);
+        let ts = &s.to_receiver.ts()[..s.to_receiver.ts().len() - 2];
+        let err = OtVerifyReceiver::new(ts, &s.chi, &s.inputs_1, s.to_receiver.r_seed).unwrap_err();
+        assert_eq!(
+            err,
+            ProtocolError::LengthMismatch {
+                expected: ts.len() + 2,
+                actual: ts.len(),
+                what: "ts"
+            }
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! Errors of the batch functions of this crate on inputs that do not fit
+//! together, e.g. a malformed client message. The per-element functions
+//! they call in their hot loops only check their inputs in debug builds.
+
+use thiserror::Error;
+
+use crate::cot::dims::DimsError;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolError {
+    #[error("expected {expected} {what}, got {actual}")]
+    LengthMismatch {
+        expected: usize,
+        actual: usize,
+        what: &'static str,
+    },
+    #[error("{what} must not be empty")]
+    EmptyInput { what: &'static str },
+    #[error("{what} split an input of {num_bits} bits")]
+    SplitInput { what: &'static str, num_bits: usize },
+    #[error("ring of {actual} bits cannot hold inputs of {needed} bits")]
+    RingTooSmall { needed: usize, actual: usize },
+    #[error(transparent)]
+    Dims(#[from] DimsError),
+}
+
+pub type Result<T> = std::result::Result<T, ProtocolError>;
+
+/// Fails with `LengthMismatch` if `actual` is not `expected`.
+pub(crate) fn check_len(what: &'static str, expected: usize, actual: usize) -> Result<()> {
+    if expected != actual {
+        return Err(ProtocolError::LengthMismatch {
+            expected,
+            actual,
+            what,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,235 @@
+//! Golden tests of the wire format: one instance of every client message,
+//! built from fixed seeds, is serialized and compared against the hex
+//! fixtures in `golden/`. A failure means the bytes on the wire changed, so
+//! servers and clients built before and after the change cannot talk to each
+//! other; bump `bridge::version::PROTOCOL_VERSION` if that is intended.
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the fixtures after an intended
+//! change.
+
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use serialize::Communicate;
+use sha2::Sha256;
+
+use crate::{
+    bits::{batch_make_boolean_shares, BitsLE, SeededInputShare, ShareCommitment, ShareSalt},
+    cot::client::{B2ACOTToAlice, B2ACOTToBob, COTGen},
+    malpriv::{derive_phase_seeds, PhaseSeeds},
+    message::{
+        l2::{ClientL2MsgToAlice, ClientL2MsgToBob},
+        po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
+    },
+    square_corr::{batch_make_sqcorr_shares, CorrShareSeedToAlice, CorrShareSeedToBob},
+    uint::UInt,
+};
+
+type I = u8;
+type C = u128;
+
+const GSIZE: usize = 2;
+/// Far fewer than a real client samples, to keep the fixtures short.
+const NUM_ADDITIONAL_OTS: usize = 2;
+const BYTES_PER_LINE: usize = 32;
+
+/// Pieces of the messages of a client, from a fixed seed.
+struct Parts {
+    inputs_0: SeededInputShare,
+    inputs_1: Vec<BitsLE<I>>,
+    commitment: ShareCommitment,
+    salt: ShareSalt,
+    cot_alice: B2ACOTToAlice,
+    cot_bob: B2ACOTToBob,
+    corr_alice: CorrShareSeedToAlice,
+    corr_bob: CorrShareSeedToBob<C>,
+}
+
+fn parts() -> Parts {
+    // ChaCha is reproducible across platforms and versions, unlike `StdRng`
+    let mut rng = ChaCha12Rng::seed_from_u64(0x901d);
+    let input = (0..GSIZE).map(|_| I::rand(&mut rng)).collect::<Vec<_>>();
+    let (inputs_0, inputs_1) =
+        batch_make_boolean_shares(&mut rng, input.iter().map(|x| x.bits_le()));
+    let delta = COTGen::sample_delta(&mut rng);
+    let (cot_alice, cot_bob) = COTGen::sample_cots(&mut rng, &inputs_1, delta, NUM_ADDITIONAL_OTS);
+    let (corr_alice, corr_bob, ..) = batch_make_sqcorr_shares::<C, _>(&mut rng, GSIZE * 2);
+    // sampled last, so that the fixtures of the other parts do not change
+    let salt = ShareSalt::sample(&mut rng);
+    let commitment = ShareCommitment::new(7, 3, inputs_0, salt, &inputs_1);
+    Parts {
+        inputs_0,
+        inputs_1,
+        commitment,
+        salt,
+        cot_alice,
+        cot_bob,
+        corr_alice,
+        corr_bob,
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2 + bytes.len() / BYTES_PER_LINE + 1);
+    for line in bytes.chunks(BYTES_PER_LINE) {
+        for byte in line {
+            write!(hex, "{:02x}", byte).unwrap();
+        }
+        hex.push('\n');
+    }
+    hex
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("golden")
+        .join(format!("{}.hex", name))
+}
+
+/// Compare the serialization of `msg` with the fixture `name`, or rewrite the
+/// fixture if `UPDATE_GOLDEN` is set.
+fn check_golden<M: Communicate>(name: &str, msg: &M) {
+    let mut bytes = Vec::new();
+    msg.to_bytes(&mut bytes);
+    assert_eq!(bytes.len(), msg.size_in_bytes(), "{}: size_in_bytes", name);
+    let actual = to_hex(&bytes);
+    let path = fixture_path(name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|e| {
+        panic!(
+            "cannot read {}: {}; run with UPDATE_GOLDEN=1 to create it",
+            path.display(),
+            e
+        )
+    });
+    if expected == actual {
+        return;
+    }
+    let mut diff = String::new();
+    let (expected, actual) = (
+        expected.lines().collect::<Vec<_>>(),
+        actual.lines().collect::<Vec<_>>(),
+    );
+    for line in 0..expected.len().max(actual.len()) {
+        let (e, a) = (expected.get(line), actual.get(line));
+        if e != a {
+            let offset = line * BYTES_PER_LINE;
+            writeln!(diff, "  byte {:#06x}:", offset).unwrap();
+            writeln!(diff, "    - {}", e.unwrap_or(&"<missing>")).unwrap();
+            writeln!(diff, "    + {}", a.unwrap_or(&"<missing>")).unwrap();
+        }
+    }
+    panic!(
+        "wire format of {} changed ({} bytes expected, {} bytes now):\n{}\
+         bump PROTOCOL_VERSION if this is intended, and run with UPDATE_GOLDEN=1 \
+         to update {}",
+        name,
+        expected.iter().map(|l| l.len() / 2).sum::<usize>(),
+        bytes.len(),
+        diff,
+        path.display()
+    );
+}
+
+#[test]
+fn golden_cot() {
+    let parts = parts();
+    check_golden("b2a_cot_to_alice", &parts.cot_alice);
+    check_golden("b2a_cot_to_bob", &parts.cot_bob);
+}
+
+#[test]
+fn golden_square_corr() {
+    let parts = parts();
+    check_golden("corr_share_seed_to_alice", &parts.corr_alice);
+    check_golden("corr_share_seed_to_bob", &parts.corr_bob);
+}
+
+#[test]
+fn golden_po2() {
+    let parts = parts();
+    check_golden(
+        "client_po2_msg_to_alice",
+        &ClientPo2MsgToAlice::new(GSIZE, parts.inputs_0, parts.commitment, parts.cot_alice),
+    );
+    check_golden(
+        "client_po2_msg_to_bob",
+        &ClientPo2MsgToBob::new(parts.inputs_1, parts.commitment, parts.salt, parts.cot_bob),
+    );
+}
+
+#[test]
+fn golden_l2() {
+    let parts = parts();
+    check_golden(
+        "client_l2_msg_to_alice",
+        &ClientL2MsgToAlice::new(
+            GSIZE,
+            parts.inputs_0,
+            parts.commitment,
+            parts.cot_alice,
+            parts.corr_alice,
+        ),
+    );
+    check_golden(
+        "client_l2_msg_to_bob",
+        &ClientL2MsgToBob::new(
+            parts.inputs_1,
+            parts.commitment,
+            parts.salt,
+            parts.cot_bob,
+            parts.corr_bob,
+        ),
+    );
+}
+
+/// The seeds a client and the servers derive from the phase 1 messages of the
+/// golden client, with fixed digests in place of the simulated transcripts.
+/// A failure means clients and servers built before and after the change
+/// derive different seeds and fail hash verification.
+#[test]
+fn golden_phase_seeds() {
+    let parts = parts();
+    let msg_alice = (
+        ClientL2MsgToAlice::new(
+            GSIZE,
+            parts.inputs_0,
+            parts.commitment,
+            parts.cot_alice,
+            parts.corr_alice,
+        ),
+        vec![0xa1u8; 32],
+    );
+    let msg_bob = (
+        ClientL2MsgToBob::new(
+            parts.inputs_1,
+            parts.commitment,
+            parts.salt,
+            parts.cot_bob,
+            parts.corr_bob,
+        ),
+        vec![0xb1u8; 32],
+        vec![0xb2u8; 32],
+    );
+    let seeds_alice = derive_phase_seeds(Sha256::default(), &msg_alice);
+    let seeds_bob = derive_phase_seeds(Sha256::default(), &msg_bob);
+    assert_eq!(
+        seeds_alice,
+        PhaseSeeds {
+            chi_seed: 0x349d_69d0_b221_5c29,
+            t_seed: 0xafb3_9403_809f_c24d,
+        }
+    );
+    assert_eq!(
+        seeds_bob,
+        PhaseSeeds {
+            chi_seed: 0x12bc_c736_4d75_2d78,
+            t_seed: 0xe1c0_f194_f10e_1b80,
+        }
+    );
+}
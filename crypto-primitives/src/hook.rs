@@ -0,0 +1,276 @@
+//! Completion markers of the phases that the servers run concurrently, e.g.
+//! OT verification of the clients of Alice while B2A of the clients of Bob is
+//! underway.
+//!
+//! A `Hook` is created when a phase starts and must be `done` when it
+//! completes. A named hook also records both instants into a `HookRegistry`,
+//! whose `Timeline` shows how much the phases overlapped, which is what the
+//! pipelining of the servers is for.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Width of the bars of `Timeline`, in characters.
+const TIMELINE_WIDTH: usize = 40;
+
+static GLOBAL_HOOKS: HookRegistry = HookRegistry::new();
+
+/// `Hook` serves as a reminder to finish a phase: if it is dropped without
+/// `done`, it panics.
+#[must_use = "call `done` when the phase completes"]
+pub struct Hook {
+    named: Option<Named>,
+    done: bool,
+}
+
+struct Named {
+    name: &'static str,
+    created: Instant,
+    registry: &'static HookRegistry,
+}
+
+impl Hook {
+    /// A hook that records nothing.
+    #[inline]
+    pub fn new() -> Self {
+        Hook {
+            named: None,
+            done: false,
+        }
+    }
+
+    /// A hook of the phase `name`, recorded into `HookRegistry::global` when
+    /// it is done.
+    pub fn new_named(name: &'static str) -> Self {
+        HookRegistry::global().hook(name)
+    }
+
+    /// The phase is complete. A named hook records its duration, and logs it
+    /// at debug level.
+    #[inline]
+    pub fn done(mut self) {
+        self.done = true;
+        if let Some(named) = &self.named {
+            let record = HookRecord {
+                name: named.name,
+                created: named.created,
+                done: Instant::now(),
+            };
+            tracing::debug!("[{}] done in {:?}", record.name, record.duration());
+            named.registry.record(record);
+        }
+    }
+}
+
+impl Default for Hook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Hook {
+    #[inline]
+    fn drop(&mut self) {
+        if !self.done {
+            panic!("internal error: Hook dropped without being done");
+        }
+    }
+}
+
+/// A named hook that is done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookRecord {
+    pub name: &'static str,
+    pub created: Instant,
+    pub done: Instant,
+}
+
+impl HookRecord {
+    pub fn duration(&self) -> Duration {
+        self.done - self.created
+    }
+}
+
+/// Collects the records of named hooks. The servers use the `global` one; a
+/// test can use its own, in a `static`.
+#[derive(Debug, Default)]
+pub struct HookRegistry {
+    records: Mutex<Vec<HookRecord>>,
+}
+
+impl HookRegistry {
+    pub const fn new() -> Self {
+        HookRegistry {
+            records: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The registry of `Hook::new_named`.
+    pub fn global() -> &'static HookRegistry {
+        &GLOBAL_HOOKS
+    }
+
+    /// A hook of the phase `name`, recorded into this registry when it is
+    /// done.
+    pub fn hook(&'static self, name: &'static str) -> Hook {
+        Hook {
+            named: Some(Named {
+                name,
+                created: Instant::now(),
+                registry: self,
+            }),
+            done: false,
+        }
+    }
+
+    fn record(&self, record: HookRecord) {
+        self.records.lock().unwrap().push(record);
+    }
+
+    /// The hooks done since the last call, in the order they were done.
+    pub fn take(&self) -> Timeline {
+        Timeline(std::mem::take(&mut *self.records.lock().unwrap()))
+    }
+}
+
+/// Records of hooks, in the order they were done. Displayed as a table with
+/// a bar per phase, from the creation of the first hook to the completion of
+/// the last one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timeline(pub Vec<HookRecord>);
+
+impl Timeline {
+    /// The first record of the phase `name`.
+    pub fn get(&self, name: &str) -> Option<&HookRecord> {
+        self.0.iter().find(|record| record.name == name)
+    }
+
+    /// How long the phases `a` and `b` ran at the same time. `None` if one of
+    /// them was not recorded.
+    pub fn overlap(&self, a: &str, b: &str) -> Option<Duration> {
+        let (a, b) = (self.get(a)?, self.get(b)?);
+        let start = a.created.max(b.created);
+        let end = a.done.min(b.done);
+        Some(end.saturating_duration_since(start))
+    }
+
+    fn bounds(&self) -> Option<(Instant, Instant)> {
+        let start = self.0.iter().map(|record| record.created).min()?;
+        let end = self.0.iter().map(|record| record.done).max()?;
+        Some((start, end))
+    }
+}
+
+impl Display for Timeline {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (start, end) = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return writeln!(f, "no phases recorded"),
+        };
+        let total = (end - start).as_secs_f64().max(f64::MIN_POSITIVE);
+        let column = |instant: Instant| {
+            let offset = (instant - start).as_secs_f64() / total;
+            ((offset * TIMELINE_WIDTH as f64).round() as usize).min(TIMELINE_WIDTH)
+        };
+        let name_width = self.0.iter().map(|r| r.name.len()).max().unwrap_or(0);
+        writeln!(
+            f,
+            "{:<name_width$} {:>10} {:>10}",
+            "phase",
+            "start ms",
+            "end ms",
+            name_width = name_width
+        )?;
+        for record in &self.0 {
+            let from = column(record.created).min(TIMELINE_WIDTH - 1);
+            let to = column(record.done).max(from + 1);
+            writeln!(
+                f,
+                "{:<name_width$} {:>10.3} {:>10.3} |{}{}{}|",
+                record.name,
+                (record.created - start).as_secs_f64() * 1e3,
+                (record.done - start).as_secs_f64() * 1e3,
+                " ".repeat(from),
+                "#".repeat(to - from),
+                " ".repeat(TIMELINE_WIDTH - to),
+                name_width = name_width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::{Hook, HookRegistry};
+
+    /// Phases of a server round: OT verification of Alice and B2A of Bob run
+    /// concurrently, then B2A of Alice.
+    fn simulate_round(registry: &'static HookRegistry) {
+        let ot_alice = registry.hook("OT Verify Alice");
+        let b2a_bob = registry.hook("B2A Bob");
+        let b2a_bob = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(30));
+            b2a_bob.done();
+        });
+        thread::sleep(Duration::from_millis(20));
+        ot_alice.done();
+        let b2a_alice = registry.hook("B2A Alice");
+        thread::sleep(Duration::from_millis(20));
+        b2a_alice.done();
+        b2a_bob.join().unwrap();
+    }
+
+    #[test]
+    fn test_registry_records_every_hook() {
+        static REGISTRY: HookRegistry = HookRegistry::new();
+        simulate_round(&REGISTRY);
+
+        let timeline = REGISTRY.take();
+        let names = timeline.0.iter().map(|r| r.name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["OT Verify Alice", "B2A Bob", "B2A Alice"]);
+        for record in &timeline.0 {
+            assert!(record.done >= record.created);
+            assert!(record.duration() >= Duration::from_millis(20));
+        }
+        // recorded in the order they were done
+        for pair in timeline.0.windows(2) {
+            assert!(pair[0].done <= pair[1].done);
+        }
+
+        assert!(timeline.overlap("OT Verify Alice", "B2A Bob").unwrap() > Duration::ZERO);
+        assert_eq!(
+            timeline.overlap("OT Verify Alice", "B2A Alice"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(timeline.overlap("OT Verify Alice", "OT Verify Bob"), None);
+
+        let table = timeline.to_string();
+        assert_eq!(table.lines().count(), 4);
+        for name in names {
+            assert!(table.contains(name));
+        }
+        assert!(REGISTRY.take().0.is_empty());
+    }
+
+    #[test]
+    fn test_unnamed_hook_records_nothing() {
+        static REGISTRY: HookRegistry = HookRegistry::new();
+        Hook::new().done();
+        let hook = REGISTRY.hook("named");
+        hook.done();
+        assert_eq!(REGISTRY.take().0.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Hook dropped without being done")]
+    fn test_forgotten_hook_panics() {
+        let hook = Hook::new();
+        drop(hook);
+    }
+}
@@ -4,13 +4,22 @@
 pub mod utils;
 
 pub mod a2s;
+pub mod aggregate;
 pub mod b2a;
 pub mod bitmul;
 pub mod bits;
 pub mod block_crypto;
+pub mod bounded;
 pub mod cot;
+pub mod error;
+// the fixtures hold ChaCha12 expansions
+#[cfg(all(test, not(feature = "aes-prg")))]
+mod golden;
+mod hook;
 pub mod malpriv;
 pub mod message;
+pub mod self_test;
+pub mod simulation;
 pub mod square_corr;
 pub mod uint;
 
@@ -1,6 +1,6 @@
 //! Support for malicious privacy by using local computation of transcripts.
 
-use serialize::Communicate;
+use serialize::{Communicate, UseCast};
 use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
 
 /// Hash for multiple messages.
@@ -21,9 +21,7 @@ impl MessageHash for () {
         let _ = msg;
     }
 
-    fn digest(self) -> Self::Output {
-        ()
-    }
+    fn digest(self) -> Self::Output {}
 }
 
 macro_rules! impl_msg_hash{
@@ -48,23 +46,91 @@ macro_rules! impl_msg_hash{
 
 impl_msg_hash!(Sha224, Sha256, Sha384, Sha512);
 
+/// Domain separation tag of [`derive_phase_seeds`].
+pub const PHASE_SEEDS_DOMAIN: &[u8] = b"elsa/mp/phase-seeds";
+
+/// Version of [`derive_phase_seeds`], absorbed after the tag. Bump it, and the
+/// major of `bridge::version::PROTOCOL_VERSION`, on any change of the
+/// derivation.
+pub const PHASE_SEEDS_VERSION: u8 = 1;
+
+/// Seeds of phase 2 derived from the phase 1 message of a client to one
+/// server. Each server holds the share derived from its own message, and the
+/// seeds are the XOR of both, see [`PhaseSeeds::combine`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhaseSeeds {
+    /// Seed of chi of OT verification.
+    pub chi_seed: u64,
+    /// Seed of `t` of square correlation verification.
+    pub t_seed: u64,
+}
+
+impl PhaseSeeds {
+    /// Combine the shares derived from the messages to Alice and to Bob.
+    pub fn combine(self, other: Self) -> Self {
+        // XXX: ideally, we should hash the two and get a new seed here, but for
+        // now we just use XOR for simplicity
+        Self {
+            chi_seed: self.chi_seed ^ other.chi_seed,
+            t_seed: self.t_seed ^ other.t_seed,
+        }
+    }
+}
+
+/// Fiat-Shamir: derive the seeds of phase 2 from `phase1_msg` by absorbing
+/// [`PHASE_SEEDS_DOMAIN`], [`PHASE_SEEDS_VERSION`] and the message into a fresh
+/// `hasher`. The first 16 bytes of the digest are the two seeds, little
+/// endian. Clients and servers must derive the same seeds, otherwise every
+/// client fails hash verification.
+pub fn derive_phase_seeds<H, M>(hasher: H, phase1_msg: &M) -> PhaseSeeds
+where
+    H: MessageHash<Output = Vec<u8>>,
+    M: Communicate,
+{
+    derive_phase_seeds_with_domain(hasher, PHASE_SEEDS_DOMAIN, phase1_msg)
+}
+
+fn derive_phase_seeds_with_domain<H, M>(mut hasher: H, domain: &[u8], phase1_msg: &M) -> PhaseSeeds
+where
+    H: MessageHash<Output = Vec<u8>>,
+    M: Communicate,
+{
+    hasher.absorb(&domain.to_vec());
+    hasher.absorb(&UseCast(PHASE_SEEDS_VERSION));
+    hasher.absorb(phase1_msg);
+    let hash = hasher.digest();
+    assert!(hash.len() >= 16, "digest is too short for two seeds");
+    // XXX:This is for a proof for concept, as the entropy is only 64 bits
+    let seed = |i: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hash[8 * i..8 * (i + 1)]);
+        u64::from_le_bytes(bytes)
+    };
+    PhaseSeeds {
+        chi_seed: seed(0),
+        t_seed: seed(1),
+    }
+}
+
 pub mod client {
     use crate::{
-        a2s::batch_a2s_first,
+        a2s::{batch_a2s_first, batch_bound_check_first, batch_bound_check_second},
         b2a::{bit_comp_as_ot_receiver_batch, bit_comp_as_ot_sender_batch},
-        bits::BitsLE,
+        bits::{BitsLE, PackedBits},
         cot::{
             client::{num_additional_ot_needed, B2ACOTToAlice, B2ACOTToBob},
             server::{sample_chi, OTReceiver},
+            B2A_R_PURPOSE,
         },
         malpriv::MessageHash,
         square_corr::SquareCorrShare,
         uint::UInt,
-        utils::SliceExt,
+        utils::batch_wrapping_add,
         ALICE, BOB,
     };
     use rand::{rngs::StdRng, SeedableRng};
     use serialize::AsUseCast;
+    use zeroize::Zeroizing;
 
     /// Simulate B2A on both sides, hashing sent message using `hasher`.
     ///
@@ -85,13 +151,27 @@ pub mod client {
     {
         let gsize = inputs_0.len();
         assert_eq!(inputs_1.len(), gsize);
-        let num_ot = gsize * I::NUM_BITS as usize;
-        let qs = cot_alice.qs_seed.expand(num_ot);
-        let qs = &qs[..num_ot];
-        let ts = &cot_bob.ts[..num_ot];
-
-        let (y0, us) = bit_comp_as_ot_sender_batch::<I, A>(&inputs_0, cot_alice.delta, &qs);
-        let y1 = bit_comp_as_ot_receiver_batch(&inputs_1, &ts, &us);
+        let num_ot = gsize * I::NUM_BITS;
+        let ts = &cot_bob.ts()[..num_ot];
+        // the COTs of Alice are the ones of Bob without the correlation, so
+        // the seed of Alice is not expanded again
+        let qs = Zeroizing::new(
+            ts.iter()
+                .zip(inputs_1.iter().flat_map(|x| x.iter()))
+                .map(|(t, choice)| {
+                    if choice {
+                        t.add_gf(cot_alice.delta)
+                    } else {
+                        *t
+                    }
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let (y0, us) = bit_comp_as_ot_sender_batch::<I, A>(inputs_0, cot_alice.delta, &qs)
+            .expect("the client has one COT per input bit");
+        let y1 = bit_comp_as_ot_receiver_batch(inputs_1, ts, &us)
+            .expect("the client has one COT per input bit");
         hasher_ab.absorb(&us);
         (y0, y1)
     }
@@ -123,33 +203,132 @@ pub mod client {
             .map(|x| x.cut())
             .collect::<Vec<_>>();
 
-        let e0 = batch_a2s_first(y0, &corr0);
-        let e1 = batch_a2s_first(y1, &corr1);
+        let e0 = batch_a2s_first(y0, corr0);
+        let e1 = batch_a2s_first(y1, corr1);
 
         hasher_ab.absorb(&e0);
         hasher_ba.absorb(&e1);
+    }
+
+    /// Simulate the bound check that follows A2S on both sides, hashing sent
+    /// messages into the hashers of `simulate_a2s`. `w0` and `w1` are the
+    /// shares of the witness bits, whose square correlations follow the ones
+    /// of the `gsize` inputs.
+    #[allow(clippy::too_many_arguments)]
+    pub fn simulate_bound_check<A, C, H>(
+        gsize: usize,
+        sqcorr_alice: &[SquareCorrShare<C>],
+        sqcorr_bob: &[SquareCorrShare<C>],
+        y0: &[A],
+        y1: &[A],
+        w0: &[C],
+        w1: &[C],
+        bound: A,
+        hasher_ab: &mut H,
+        hasher_ba: &mut H,
+    ) where
+        A: UInt,
+        C: UInt,
+        H: MessageHash,
+    {
+        assert_eq!(w0.len(), w1.len());
+        let witness_corr = gsize..gsize + w0.len();
+
+        let cut = |corr: &[SquareCorrShare<C>]| {
+            corr[witness_corr.clone()]
+                .iter()
+                .map(|x| x.cut())
+                .collect::<Vec<SquareCorrShare<A>>>()
+        };
+        let (corr0, corr1) = (cut(sqcorr_alice), cut(sqcorr_bob));
+        let w0 = w0.iter().map(|w| w.as_uint()).collect::<Vec<A>>();
+        let w1 = w1.iter().map(|w| w.as_uint()).collect::<Vec<A>>();
+
+        let e0 = batch_bound_check_first(&w0, &corr0);
+        let e1 = batch_bound_check_first(&w1, &corr1);
+        hasher_ab.absorb(&e0);
+        hasher_ba.absorb(&e1);
 
-        // transcript for secure comparison is ignored here
+        let e = batch_wrapping_add(&e0, &e1);
+        let checks0 = batch_bound_check_second::<_, ALICE>(&e, y0, &w0, &corr0, bound);
+        let checks1 = batch_bound_check_second::<_, BOB>(&e, y1, &w1, &corr1, bound);
+        hasher_ab.absorb(&checks0);
+        hasher_ba.absorb(&checks1);
+    }
+
+    /// Part of the OT verification of the OT receiver that does not depend on
+    /// the chi seed, see [`precompute_ot_verify`].
+    #[derive(Clone, Debug)]
+    pub struct OtVerifyPrecomputed {
+        /// Choice bits of the additional OTs.
+        r: PackedBits,
+    }
+
+    /// Expand the choice bits of the additional OTs, and absorb `sec_param`
+    /// into `hasher_ba`. Together with [`finish_ot_verify`], this is the same
+    /// as [`simulate_ot_verify`], but can run before the chi seed is known.
+    /// `cot` are the COTs of client `uid`.
+    pub fn precompute_ot_verify<I, H>(
+        uid: u64,
+        inputs_1: &[BitsLE<I>],
+        cot: &B2ACOTToBob,
+        sec_param: usize,
+        hasher_ba: &mut H,
+    ) -> OtVerifyPrecomputed
+    where
+        I: UInt,
+        H: MessageHash,
+    {
+        let num_ot = inputs_1.len() * I::NUM_BITS;
+        let num_additional_ot = num_additional_ot_needed(num_ot, sec_param);
+        hasher_ba.absorb(&(sec_param as u64).use_cast());
+        OtVerifyPrecomputed {
+            r: cot
+                .r_seed
+                .for_client(uid, B2A_R_PURPOSE)
+                .expand(num_additional_ot),
+        }
+    }
+
+    /// The part of [`simulate_ot_verify`] that depends on `chi_seed`.
+    pub fn finish_ot_verify<I, H>(
+        precomputed: &OtVerifyPrecomputed,
+        inputs_1: &[BitsLE<I>],
+        cot: &B2ACOTToBob,
+        chi_seed: u64,
+        hasher_ba: &mut H,
+    ) where
+        I: UInt,
+        H: MessageHash,
+    {
+        let num_ot = inputs_1.len() * I::NUM_BITS;
+        let chi = sample_chi(num_ot + precomputed.r.len(), chi_seed);
+        let (x_til, t_til) =
+            OTReceiver::send_x_til_t_til_with_r(cot.ts(), &chi, inputs_1, &precomputed.r)
+                .expect("the client samples one COT per coefficient of chi");
+        hasher_ba.absorb(&(x_til.use_cast(), t_til));
     }
 
     /// Simulate OT verification on both sides. (Simulation not needed for
     /// Alice)
+    ///
+    /// `sec_param` is absorbed before the OT verification message, so that
+    /// both servers are bound to the same number of additional OTs. `cot`
+    /// are the COTs of client `uid`.
     pub fn simulate_ot_verify<I, A, H>(
+        uid: u64,
         inputs_1: &[BitsLE<I>],
         cot: &B2ACOTToBob,
         chi_seed: u64,
+        sec_param: usize,
         hasher_ba: &mut H,
     ) where
         I: UInt,
         A: UInt,
         H: MessageHash,
     {
-        let num_ot = inputs_1.len() * I::NUM_BITS as usize;
-        let num_additional_ot = num_additional_ot_needed(num_ot);
-        let chi = sample_chi(num_ot + num_additional_ot, chi_seed);
-        let (x_til, t_til) = OTReceiver::send_x_til_t_til(&cot.ts, &chi, &inputs_1, cot.r_seed);
-
-        hasher_ba.absorb(&(x_til.use_cast(), t_til));
+        let precomputed = precompute_ot_verify(uid, inputs_1, cot, sec_param, hasher_ba);
+        finish_ot_verify(&precomputed, inputs_1, cot, chi_seed, hasher_ba);
     }
 
     /// Simulate square correlation verification on both sides.
@@ -181,23 +360,47 @@ pub mod client {
 
         let t = (0..gsize).map(|_| C::rand(&mut t_rng)).collect::<Vec<_>>();
 
-        SquareCorrShare::verify_phase_1(corr_0, sacr_0, &t, &mut d0);
-        SquareCorrShare::verify_phase_1(corr_1, sacr_1, &t, &mut d1);
+        // the lengths are checked above
+        SquareCorrShare::verify_phase_1(corr_0, sacr_0, &t, &mut d0).unwrap();
+        SquareCorrShare::verify_phase_1(corr_1, sacr_1, &t, &mut d1).unwrap();
 
         // println!("d0: {:x?}, d1: {:x?}", d0, d1);
 
         hasher_ab.absorb(&d0);
         hasher_ba.absorb(&d1);
 
-        let d = d0.zip_map(&d1, |x, y| x.wrapping_add(y));
-
-        let mut w0 = vec![C::zero(); gsize];
-        let mut w1 = vec![C::zero(); gsize];
+        let d = batch_wrapping_add(&d0, &d1);
 
-        SquareCorrShare::verify_phase_2::<{ ALICE }>(corr_0, sacr_0, &t, &d, &mut w0);
-        SquareCorrShare::verify_phase_2::<{ BOB }>(corr_1, sacr_1, &t, &d, &mut w1);
+        let w0 =
+            SquareCorrShare::verify_phase_2_shares::<{ ALICE }>(corr_0, sacr_0, &t, &d, t_seed)
+                .unwrap();
+        let w1 = SquareCorrShare::verify_phase_2_shares::<{ BOB }>(corr_1, sacr_1, &t, &d, t_seed)
+            .unwrap();
 
         hasher_ab.absorb(&w0);
         hasher_ba.absorb(&w1); // TODO change back
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serialize::UseCast;
+    use sha2::Sha256;
+
+    use super::{derive_phase_seeds, derive_phase_seeds_with_domain, PHASE_SEEDS_DOMAIN};
+
+    #[test]
+    fn test_phase_seeds_depend_on_domain() {
+        let msg = (UseCast(0x1234u64), vec![1u8, 2, 3]);
+        let seeds = derive_phase_seeds(Sha256::default(), &msg);
+        assert_eq!(
+            seeds,
+            derive_phase_seeds_with_domain(Sha256::default(), PHASE_SEEDS_DOMAIN, &msg)
+        );
+        let other = derive_phase_seeds_with_domain(Sha256::default(), b"elsa/mp/other", &msg);
+        assert_ne!(seeds.chi_seed, other.chi_seed);
+        assert_ne!(seeds.t_seed, other.t_seed);
+        let other = derive_phase_seeds(Sha256::default(), &(UseCast(0x1235u64), vec![1u8, 2, 3]));
+        assert_ne!(seeds, other);
+    }
+}
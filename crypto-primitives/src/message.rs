@@ -3,76 +3,156 @@
 /// Messages used in power of 2 protocol
 pub mod po2 {
     use crate::{
-        bits::{BitsLE, SeededInputShare},
+        bits::{BitsLE, SeededInputShare, ShareCommitment, ShareSalt},
         cot::{
-            client::{num_additional_ot_needed, B2ACOTToAlice, B2ACOTToBob},
+            client::{B2ACOTToAlice, B2ACOTToAliceReverse, B2ACOTToBob, B2ACOTToBobReverse},
+            dims::ProtocolDims,
             ChoiceSeed,
         },
         uint::UInt,
     };
+    #[cfg(feature = "simulation-helpers")]
+    use crate::{
+        cot::client::{num_additional_ot_needed, DEFAULT_SEC_PARAM},
+        simulation::SimulationOnly,
+    };
     use block::Block;
     use serialize::{AsUseCast, Communicate, UseCast};
-    use std::io::{Read, Write};
+    use std::{
+        io::{Read, Write},
+        mem::size_of,
+    };
 
     #[derive(Debug, Clone)]
     pub struct ClientPo2MsgToAlice {
+        /// Number of inputs. Bob learns it from the length of `inputs_1`, but
+        /// Alice only gets a seed.
+        pub gsize: u64,
         pub inputs_0: SeededInputShare,
+        /// Same as the commitment sent to Bob.
+        pub commitment: ShareCommitment,
         pub cot: B2ACOTToAlice, /* TODO: we need to add some extra OT for secure comparison simulation */
     }
 
     impl ClientPo2MsgToAlice {
-        pub fn new(inputs_0_seed: SeededInputShare, cot: B2ACOTToAlice) -> Self {
+        pub fn new(
+            gsize: usize,
+            inputs_0_seed: SeededInputShare,
+            commitment: ShareCommitment,
+            cot: B2ACOTToAlice,
+        ) -> Self {
             ClientPo2MsgToAlice {
+                gsize: gsize as u64,
                 inputs_0: inputs_0_seed,
+                commitment,
                 cot,
             }
         }
+
+        /// Bind the COTs to client `uid`, see `B2ACOTToAlice::bind_to`.
+        pub fn bind_to(&mut self, uid: u64) {
+            self.cot.bind_to(uid);
+        }
     }
 
     impl Communicate for ClientPo2MsgToAlice {
         type Deserialized = Self;
 
         fn size_in_bytes(&self) -> usize {
-            self.inputs_0.use_cast().size_in_bytes() + self.cot.size_in_bytes()
+            self.gsize.use_cast().size_in_bytes()
+                + self.inputs_0.use_cast().size_in_bytes()
+                + self.commitment.use_cast().size_in_bytes()
+                + self.cot.size_in_bytes()
         }
 
         fn to_bytes<W: Write>(&self, mut dest: W) {
+            self.gsize.use_cast().to_bytes(&mut dest);
             self.inputs_0.use_cast().to_bytes(&mut dest);
+            self.commitment.use_cast().to_bytes(&mut dest);
             self.cot.to_bytes(&mut dest);
         }
 
         fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+            let gsize = UseCast::from_bytes(&mut bytes)?;
             let inputs_0 = UseCast::from_bytes(&mut bytes)?;
+            let commitment = UseCast::from_bytes(&mut bytes)?;
             let cot = B2ACOTToAlice::from_bytes(&mut bytes)?;
-            Ok(ClientPo2MsgToAlice { inputs_0, cot })
+            Ok(ClientPo2MsgToAlice {
+                gsize,
+                inputs_0,
+                commitment,
+                cot,
+            })
         }
     }
 
     #[derive(Debug, Clone)]
     pub struct ClientPo2MsgToBob<T: UInt> {
         pub inputs_1: Vec<BitsLE<T>>,
+        /// Same as the commitment sent to Alice.
+        pub commitment: ShareCommitment,
+        /// Salt of `inputs_1` in `commitment`, which Alice never sees.
+        pub salt: ShareSalt,
         pub cot: B2ACOTToBob,
     }
 
     impl<T: UInt> ClientPo2MsgToBob<T> {
-        pub fn new(inputs_1: Vec<BitsLE<T>>, cot: B2ACOTToBob) -> Self {
-            ClientPo2MsgToBob { inputs_1, cot }
+        pub fn new(
+            inputs_1: Vec<BitsLE<T>>,
+            commitment: ShareCommitment,
+            salt: ShareSalt,
+            cot: B2ACOTToBob,
+        ) -> Self {
+            ClientPo2MsgToBob {
+                inputs_1,
+                commitment,
+                salt,
+                cot,
+            }
+        }
+
+        /// Size in bytes of a message for `dims`, without building one. Use
+        /// this for estimates instead of sizing a dummy message.
+        pub fn size_in_bytes_for(dims: ProtocolDims) -> usize {
+            size_of::<u64>()
+                + dims.gsize() * size_of::<BitsLE<T>>()
+                + size_of::<ShareCommitment>()
+                + size_of::<ShareSalt>()
+                + size_of::<ChoiceSeed>()
+                + 2 * size_of::<u64>()
+                + dims.num_total() * size_of::<Block>()
         }
 
+        #[cfg(feature = "simulation-helpers")]
         pub fn dummy(gsize: usize) -> Self {
             Self::dummy_with_custom_num_ots(
                 gsize,
-                gsize * T::NUM_BITS + num_additional_ot_needed(gsize * T::NUM_BITS),
+                gsize * T::NUM_BITS
+                    + num_additional_ot_needed(gsize * T::NUM_BITS, DEFAULT_SEC_PARAM),
             )
         }
 
+        #[cfg(feature = "simulation-helpers")]
         pub fn dummy_with_custom_num_ots(gsize: usize, num_ots: usize) -> Self {
+            SimulationOnly::mark();
             let inputs_1 = vec![BitsLE(T::zero()); gsize];
+            let num_additional = num_additional_ot_needed(num_ots, DEFAULT_SEC_PARAM);
             let cot = B2ACOTToBob::new(
                 ChoiceSeed(0),
-                vec![Block::default(); num_ots + num_additional_ot_needed(num_ots)],
+                vec![Block::default(); num_ots + num_additional],
+                num_additional,
             );
-            ClientPo2MsgToBob::new(inputs_1, cot)
+            ClientPo2MsgToBob::new(
+                inputs_1,
+                ShareCommitment::default(),
+                ShareSalt::default(),
+                cot,
+            )
+        }
+
+        /// Bind the COTs to client `uid`, see `B2ACOTToBob::bind_to`.
+        pub fn bind_to(&mut self, uid: u64) {
+            self.cot.bind_to(uid);
         }
     }
 
@@ -80,28 +160,152 @@ pub mod po2 {
         type Deserialized = Self;
 
         fn size_in_bytes(&self) -> usize {
-            self.inputs_1.size_in_bytes() + self.cot.size_in_bytes()
+            self.inputs_1.size_in_bytes()
+                + self.commitment.use_cast().size_in_bytes()
+                + self.salt.use_cast().size_in_bytes()
+                + self.cot.size_in_bytes()
         }
 
         fn to_bytes<W: Write>(&self, mut dest: W) {
             self.inputs_1.to_bytes(&mut dest);
+            self.commitment.use_cast().to_bytes(&mut dest);
+            self.salt.use_cast().to_bytes(&mut dest);
             self.cot.to_bytes(&mut dest);
         }
 
         fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
             let inputs_0 = Vec::from_bytes(&mut bytes)?;
+            let commitment = UseCast::from_bytes(&mut bytes)?;
+            let salt = UseCast::from_bytes(&mut bytes)?;
             let cot = B2ACOTToBob::from_bytes(&mut bytes)?;
             Ok(ClientPo2MsgToBob {
                 inputs_1: inputs_0,
+                commitment,
+                salt,
                 cot,
             })
         }
     }
+
+    /// Message to Alice of a client that splits its inputs at `split`: Alice
+    /// is OT sender for the inputs before it, with `po2_msg.cot`, and OT
+    /// receiver for the others, with `reverse`. `po2_msg.gsize` counts all
+    /// inputs.
+    #[derive(Debug, Clone)]
+    pub struct ClientPo2SplitMsgToAlice {
+        pub po2_msg: ClientPo2MsgToAlice,
+        pub split: u64,
+        pub reverse: B2ACOTToAliceReverse,
+    }
+
+    impl ClientPo2SplitMsgToAlice {
+        pub fn new(
+            po2_msg: ClientPo2MsgToAlice,
+            split: usize,
+            reverse: B2ACOTToAliceReverse,
+        ) -> Self {
+            ClientPo2SplitMsgToAlice {
+                po2_msg,
+                split: split as u64,
+                reverse,
+            }
+        }
+
+        /// Bind the COTs of both B2As to client `uid`.
+        pub fn bind_to(&mut self, uid: u64) {
+            self.po2_msg.bind_to(uid);
+            self.reverse.bind_to(uid);
+        }
+    }
+
+    impl Communicate for ClientPo2SplitMsgToAlice {
+        type Deserialized = Self;
+
+        fn size_in_bytes(&self) -> usize {
+            self.po2_msg.size_in_bytes()
+                + self.split.use_cast().size_in_bytes()
+                + self.reverse.size_in_bytes()
+        }
+
+        fn to_bytes<W: Write>(&self, mut dest: W) {
+            self.po2_msg.to_bytes(&mut dest);
+            self.split.use_cast().to_bytes(&mut dest);
+            self.reverse.to_bytes(&mut dest);
+        }
+
+        fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+            let po2_msg = ClientPo2MsgToAlice::from_bytes(&mut bytes)?;
+            let split = UseCast::from_bytes(&mut bytes)?;
+            let reverse = B2ACOTToAliceReverse::from_bytes(&mut bytes)?;
+            Ok(ClientPo2SplitMsgToAlice {
+                po2_msg,
+                split,
+                reverse,
+            })
+        }
+    }
+
+    /// Message to Bob of a client that splits its inputs, see
+    /// `ClientPo2SplitMsgToAlice`. `po2_msg.cot` only has the OTs of the
+    /// inputs before `split`.
+    #[derive(Debug, Clone)]
+    pub struct ClientPo2SplitMsgToBob<T: UInt> {
+        pub po2_msg: ClientPo2MsgToBob<T>,
+        pub split: u64,
+        pub reverse: B2ACOTToBobReverse,
+    }
+
+    impl<T: UInt> ClientPo2SplitMsgToBob<T> {
+        pub fn new(
+            po2_msg: ClientPo2MsgToBob<T>,
+            split: usize,
+            reverse: B2ACOTToBobReverse,
+        ) -> Self {
+            ClientPo2SplitMsgToBob {
+                po2_msg,
+                split: split as u64,
+                reverse,
+            }
+        }
+
+        /// Bind the COTs of both B2As to client `uid`.
+        pub fn bind_to(&mut self, uid: u64) {
+            self.po2_msg.bind_to(uid);
+            self.reverse.bind_to(uid);
+        }
+    }
+
+    impl<T: UInt> Communicate for ClientPo2SplitMsgToBob<T> {
+        type Deserialized = Self;
+
+        fn size_in_bytes(&self) -> usize {
+            self.po2_msg.size_in_bytes()
+                + self.split.use_cast().size_in_bytes()
+                + self.reverse.size_in_bytes()
+        }
+
+        fn to_bytes<W: Write>(&self, mut dest: W) {
+            self.po2_msg.to_bytes(&mut dest);
+            self.split.use_cast().to_bytes(&mut dest);
+            self.reverse.to_bytes(&mut dest);
+        }
+
+        fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+            let po2_msg = ClientPo2MsgToBob::from_bytes(&mut bytes)?;
+            let split = UseCast::from_bytes(&mut bytes)?;
+            let reverse = B2ACOTToBobReverse::from_bytes(&mut bytes)?;
+            Ok(ClientPo2SplitMsgToBob {
+                po2_msg,
+                split,
+                reverse,
+            })
+        }
+    }
 }
 
 pub mod l2 {
     use crate::{
-        bits::{BitsLE, SeededInputShare},
+        bits::{BitsLE, SeededInputShare, ShareCommitment, ShareSalt},
         cot::client::{B2ACOTToAlice, B2ACOTToBob},
         malpriv::MessageHash,
         message::po2,
@@ -119,12 +323,14 @@ pub mod l2 {
 
     impl ClientL2MsgToAlice {
         pub fn new(
+            gsize: usize,
             inputs_0_seed: SeededInputShare,
+            commitment: ShareCommitment,
             cot: B2ACOTToAlice,
             square_corr: CorrShareSeedToAlice,
         ) -> Self {
             ClientL2MsgToAlice {
-                po2_msg: po2::ClientPo2MsgToAlice::new(inputs_0_seed, cot),
+                po2_msg: po2::ClientPo2MsgToAlice::new(gsize, inputs_0_seed, commitment, cot),
                 square_corr,
             }
         }
@@ -166,11 +372,13 @@ pub mod l2 {
     impl<I: UInt, C: UInt> ClientL2MsgToBob<I, C> {
         pub fn new(
             inputs_1: Vec<BitsLE<I>>,
+            commitment: ShareCommitment,
+            salt: ShareSalt,
             cot: B2ACOTToBob,
             square_corr: CorrShareSeedToBob<C>,
         ) -> Self {
             ClientL2MsgToBob {
-                po2_msg: po2::ClientPo2MsgToBob::new(inputs_1, cot),
+                po2_msg: po2::ClientPo2MsgToBob::new(inputs_1, commitment, salt, cot),
                 square_corr,
             }
         }
@@ -221,3 +429,737 @@ pub mod l2 {
         <H as MessageHash>::Output,
     );
 }
+
+/// Messages of the power of 2 protocol with the range check of `bounded`
+pub mod bounded {
+    use crate::{
+        bounded::{BoundedCheckToAlice, BoundedCheckToBob},
+        message::po2,
+        uint::UInt,
+    };
+    use serialize::Communicate;
+    use std::io::{Read, Write};
+
+    #[derive(Debug, Clone)]
+    pub struct ClientBoundedMsgToAlice {
+        pub po2_msg: po2::ClientPo2MsgToAlice,
+        pub check: BoundedCheckToAlice,
+    }
+
+    impl ClientBoundedMsgToAlice {
+        /// Bind the COTs of B2A and of the check to client `uid`.
+        pub fn bind_to(&mut self, uid: u64) {
+            self.po2_msg.bind_to(uid);
+            self.check.bind_to(uid);
+        }
+    }
+
+    impl Communicate for ClientBoundedMsgToAlice {
+        type Deserialized = Self;
+
+        fn size_in_bytes(&self) -> usize {
+            self.po2_msg.size_in_bytes() + self.check.size_in_bytes()
+        }
+
+        fn to_bytes<W: Write>(&self, mut dest: W) {
+            self.po2_msg.to_bytes(&mut dest);
+            self.check.to_bytes(&mut dest);
+        }
+
+        fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+            let po2_msg = po2::ClientPo2MsgToAlice::from_bytes(&mut bytes)?;
+            let check = BoundedCheckToAlice::from_bytes(&mut bytes)?;
+            Ok(ClientBoundedMsgToAlice { po2_msg, check })
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct ClientBoundedMsgToBob<I: UInt> {
+        pub po2_msg: po2::ClientPo2MsgToBob<I>,
+        pub check: BoundedCheckToBob<I>,
+    }
+
+    impl<I: UInt> ClientBoundedMsgToBob<I> {
+        /// Bind the COTs of B2A and the choices of the check to client `uid`.
+        pub fn bind_to(&mut self, uid: u64) {
+            self.po2_msg.bind_to(uid);
+            self.check.bind_to(uid);
+        }
+    }
+
+    impl<I: UInt> Communicate for ClientBoundedMsgToBob<I> {
+        type Deserialized = Self;
+
+        fn size_in_bytes(&self) -> usize {
+            self.po2_msg.size_in_bytes() + self.check.size_in_bytes()
+        }
+
+        fn to_bytes<W: Write>(&self, mut dest: W) {
+            self.po2_msg.to_bytes(&mut dest);
+            self.check.to_bytes(&mut dest);
+        }
+
+        fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+            let po2_msg = po2::ClientPo2MsgToBob::from_bytes(&mut bytes)?;
+            let check = BoundedCheckToBob::from_bytes(&mut bytes)?;
+            Ok(ClientBoundedMsgToBob { po2_msg, check })
+        }
+    }
+}
+
+/// Messages of the bits protocol, for clients whose inputs are single bits,
+/// e.g. the coordinates of a frequency sketch. There is no B2A over whole
+/// inputs and no OT verification, so clients are assumed semi-honest.
+pub mod bits {
+    use crate::{
+        bits::PackedBits,
+        cot::client::{B2ACOTToAlice, B2ACOTToBob},
+    };
+    use serialize::{AsUseCast, Communicate, UseCast};
+    use std::{
+        io::{self, Read, Write},
+        str::FromStr,
+    };
+
+    /// How the servers aggregate the bits of the clients. Clients and
+    /// servers must agree on it.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum BitAggregation {
+        /// XOR of each bit over the clients, e.g. for parity sketches. Each
+        /// server XORs its boolean shares locally, and clients send no COTs.
+        Xor,
+        /// Number of clients with each bit set, in `u32` counters. Clients
+        /// send one COT per bit, for a B2A of one `bit_mul` per bit.
+        Sum,
+    }
+
+    impl FromStr for BitAggregation {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "xor" => Ok(BitAggregation::Xor),
+                "sum" => Ok(BitAggregation::Sum),
+                _ => Err(format!(
+                    "unknown bit aggregation {:?}, expected xor or sum",
+                    s
+                )),
+            }
+        }
+    }
+
+    /// Size of `cot` with its tag.
+    fn optional_size<M: Communicate>(cot: &Option<M>) -> usize {
+        1 + cot.as_ref().map_or(0, |cot| cot.size_in_bytes())
+    }
+
+    fn optional_to_bytes<M: Communicate, W: Write>(cot: &Option<M>, mut dest: W) {
+        (cot.is_some() as u8).use_cast().to_bytes(&mut dest);
+        if let Some(cot) = cot {
+            cot.to_bytes(dest);
+        }
+    }
+
+    fn optional_from_bytes<M: Communicate<Deserialized = M>, R: Read>(
+        mut bytes: R,
+    ) -> serialize::Result<Option<M>> {
+        match UseCast::<u8>::from_bytes(&mut bytes)? {
+            0 => Ok(None),
+            1 => Ok(Some(M::from_bytes(bytes)?)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid tag of the COTs").into()),
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct ClientBitsMsgToAlice {
+        pub shares: PackedBits,
+        /// COTs of the B2A of `shares`, for `BitAggregation::Sum` only.
+        pub cot: Option<B2ACOTToAlice>,
+    }
+
+    impl ClientBitsMsgToAlice {
+        /// Bind the COTs to client `uid`, see `B2ACOTToAlice::bind_to`.
+        pub fn bind_to(&mut self, uid: u64) {
+            if let Some(cot) = &mut self.cot {
+                cot.bind_to(uid);
+            }
+        }
+    }
+
+    impl Communicate for ClientBitsMsgToAlice {
+        type Deserialized = Self;
+
+        fn size_in_bytes(&self) -> usize {
+            self.shares.size_in_bytes() + optional_size(&self.cot)
+        }
+
+        fn to_bytes<W: Write>(&self, mut dest: W) {
+            self.shares.to_bytes(&mut dest);
+            optional_to_bytes(&self.cot, dest);
+        }
+
+        fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+            let shares = PackedBits::from_bytes(&mut bytes)?;
+            let cot = optional_from_bytes(bytes)?;
+            Ok(ClientBitsMsgToAlice { shares, cot })
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct ClientBitsMsgToBob {
+        /// Also the choices of the COTs.
+        pub shares: PackedBits,
+        /// COTs of the B2A of `shares`, for `BitAggregation::Sum` only.
+        pub cot: Option<B2ACOTToBob>,
+    }
+
+    impl ClientBitsMsgToBob {
+        /// Bind the COTs to client `uid`, see `B2ACOTToBob::bind_to`.
+        pub fn bind_to(&mut self, uid: u64) {
+            if let Some(cot) = &mut self.cot {
+                cot.bind_to(uid);
+            }
+        }
+    }
+
+    impl Communicate for ClientBitsMsgToBob {
+        type Deserialized = Self;
+
+        fn size_in_bytes(&self) -> usize {
+            self.shares.size_in_bytes() + optional_size(&self.cot)
+        }
+
+        fn to_bytes<W: Write>(&self, mut dest: W) {
+            self.shares.to_bytes(&mut dest);
+            optional_to_bytes(&self.cot, dest);
+        }
+
+        fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+            let shares = PackedBits::from_bytes(&mut bytes)?;
+            let cot = optional_from_bytes(bytes)?;
+            Ok(ClientBitsMsgToBob { shares, cot })
+        }
+    }
+}
+
+/// Uploads signed with the long-term key of the client, so that the servers
+/// only accept them from registered identities. Without `signed-uploads`,
+/// uploads are sent as is.
+#[cfg(feature = "signed-uploads")]
+pub mod signed {
+    use bytes::Bytes;
+    use ed25519_dalek::Signer;
+    pub use ed25519_dalek::{
+        Signature, SignatureError, SigningKey, VerifyingKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH,
+        SIGNATURE_LENGTH,
+    };
+    use serialize::Communicate;
+    use std::io::{self, Read, Write};
+
+    /// `msg` and the signature of its bytes, sent as the tuple
+    /// `(msg, signature)`. The signature is the last `SIGNATURE_LENGTH` bytes,
+    /// so the receiver verifies the bytes of `msg` as received, before
+    /// deserializing them.
+    #[derive(Clone)]
+    pub struct SignedMessage<M> {
+        pub msg: M,
+        pub signature: Signature,
+    }
+
+    impl<M: Communicate> SignedMessage<M> {
+        pub fn sign(key: &SigningKey, msg: M) -> Self {
+            let mut bytes = Vec::with_capacity(msg.size_in_bytes());
+            msg.to_bytes(&mut bytes);
+            let signature = key.sign(&bytes);
+            SignedMessage { msg, signature }
+        }
+    }
+
+    impl SignedMessage<Bytes> {
+        /// Strict verification, so that a message has a single valid
+        /// signature under `key`.
+        pub fn verify(&self, key: &VerifyingKey) -> Result<(), SignatureError> {
+            key.verify_strict(&self.msg, &self.signature)
+        }
+    }
+
+    impl<M: Communicate> Communicate for SignedMessage<M> {
+        /// The bytes of the message, which are only deserialized once
+        /// verified.
+        type Deserialized = SignedMessage<Bytes>;
+
+        fn size_in_bytes(&self) -> usize {
+            (&self.msg, self.signature.to_bytes()).size_in_bytes()
+        }
+
+        fn to_bytes<W: Write>(&self, dest: W) {
+            (&self.msg, self.signature.to_bytes()).to_bytes(dest)
+        }
+
+        fn from_bytes<R: Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
+            let mut buf = Vec::new();
+            bytes.read_to_end(&mut buf)?;
+            Self::from_bytes_owned(buf.into())
+        }
+
+        fn from_bytes_owned(mut bytes: Bytes) -> serialize::Result<Self::Deserialized> {
+            let msg_len = bytes
+                .len()
+                .checked_sub(SIGNATURE_LENGTH)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            let mut signature = [0u8; SIGNATURE_LENGTH];
+            signature.copy_from_slice(&bytes.split_off(msg_len));
+            Ok(SignedMessage {
+                msg: bytes,
+                signature: Signature::from_bytes(&signature),
+            })
+        }
+    }
+}
+
+/// Sizes of the messages of a round, computed from the message formats
+/// without building any message, for capacity planning.
+pub mod size {
+    use std::mem::size_of;
+
+    use block::Block;
+    use serialize::Communicate;
+
+    use crate::{
+        b2a::B2A_CHUNKS,
+        bits::{BitsLE, SeededInputShare, ShareCommitment, ShareSalt},
+        cot::{dims::ProtocolDims, server::TTil, COTSeed, ChoiceSeed},
+        malpriv::MessageHash,
+        square_corr::COMPRESSED_CORR_VERIFY,
+        uint::UInt,
+    };
+
+    /// Size of the id and length in front of every message on the wire.
+    pub const MESSAGE_HEADER_SIZE: usize = 16;
+
+    /// Size of the message count of a batch, which packs several messages in
+    /// one frame, each with its own id and length.
+    pub const BATCH_COUNT_SIZE: usize = 8;
+
+    /// Size of a serialized `Vec` of `len` elements of `T`.
+    fn vec_size<T>(len: usize) -> usize {
+        size_of::<u64>() + len * size_of::<T>()
+    }
+
+    /// Protocol of a round, named after its client binary.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Protocol {
+        /// `client-po2` and `server-po2`.
+        Po2,
+        /// `client-l2` and `server-l2`.
+        L2,
+        /// `client-mp` and `server-mp`.
+        Mp,
+    }
+
+    /// Size in bytes of each part of the message of one client to one server.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct ClientMsgSize {
+        /// Share of the inputs, a seed and the number of inputs for Alice.
+        pub inputs: usize,
+        pub commitment: usize,
+        /// Correlated OTs, including the COT ts for Bob.
+        pub cot: usize,
+        /// Square correlation seeds, including the `c` shares for Bob.
+        pub sqcorr: usize,
+        /// Transcript hashes.
+        pub hashes: usize,
+    }
+
+    impl ClientMsgSize {
+        /// Size of the message, without the message header.
+        pub fn total(&self) -> usize {
+            self.inputs + self.commitment + self.cot + self.sqcorr + self.hashes
+        }
+    }
+
+    /// Size of the messages of one client.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct MessageSizeReport {
+        pub protocol: Protocol,
+        pub dims: ProtocolDims,
+        /// Message to the OT sender.
+        pub to_alice: ClientMsgSize,
+        /// Message to the OT receiver.
+        pub to_bob: ClientMsgSize,
+    }
+
+    impl MessageSizeReport {
+        /// Upload of one client to both servers, without message headers.
+        pub fn total(&self) -> usize {
+            self.to_alice.total() + self.to_bob.total()
+        }
+    }
+
+    /// Size of the messages of a client of `protocol` with inputs in ring
+    /// `I`, correlations in ring `C`, and transcript hash `H`. `H` is only
+    /// used by [`Protocol::Mp`].
+    pub fn estimate_sizes<I, C, H>(protocol: Protocol, dims: ProtocolDims) -> MessageSizeReport
+    where
+        I: UInt,
+        C: UInt,
+        H: MessageHash + Default,
+    {
+        let mut to_alice = ClientMsgSize {
+            inputs: size_of::<u64>() + size_of::<SeededInputShare>(),
+            commitment: size_of::<ShareCommitment>(),
+            cot: size_of::<Block>() + size_of::<COTSeed>(),
+            ..Default::default()
+        };
+        let mut to_bob = ClientMsgSize {
+            inputs: vec_size::<BitsLE<I>>(dims.gsize()),
+            // the commitment and the salt of `inputs_1`
+            commitment: size_of::<ShareCommitment>() + size_of::<ShareSalt>(),
+            // the choice seed, the split of the OTs, then the OTs
+            cot: size_of::<ChoiceSeed>() + size_of::<u64>() + vec_size::<Block>(dims.num_total()),
+            ..Default::default()
+        };
+        if protocol != Protocol::Po2 {
+            // seeds of a and c, and the number of correlations, which is one
+            // per input and one sacrificed per input
+            to_alice.sqcorr = 3 * size_of::<u64>();
+            to_bob.sqcorr = 2 * size_of::<u64>() + vec_size::<C>(2 * dims.gsize());
+        }
+        if protocol == Protocol::Mp {
+            let digest = H::default().digest().size_in_bytes();
+            // Alice: A2S BA, and in phase 2, OT verify BA and square
+            // correlation verify BA. Bob: B2A AB, A2S AB, and in phase 2,
+            // square correlation verify AB.
+            to_alice.hashes = 3 * digest;
+            to_bob.hashes = 3 * digest;
+        }
+        MessageSizeReport {
+            protocol,
+            dims,
+            to_alice,
+            to_bob,
+        }
+    }
+
+    /// MPC traffic of one round, in bytes sent by both servers together,
+    /// including message headers.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct MpcSizeEstimate {
+        /// Checks of the share commitments of all clients: a digest per
+        /// client, then whether to reject it.
+        pub commitments: usize,
+        /// Seeds of `chi`, and of `t` in the malicious protocol.
+        pub seeds: usize,
+        /// `x_til` and `t_til` of each client.
+        pub ot_verify: usize,
+        /// OT corrections of each client, in `B2A_CHUNKS` messages.
+        pub b2a: usize,
+        /// Clients that failed on either server: before the protocol, and
+        /// also after B2A in the malicious protocol.
+        pub failures: usize,
+        /// Openings of `d` and `w` of each client. The malicious protocol
+        /// sends them in a batch per opening.
+        pub sqcorr_verify: usize,
+        /// Openings of `e` of each client, in one batch in the malicious
+        /// protocol.
+        pub a2s: usize,
+    }
+
+    impl MpcSizeEstimate {
+        pub fn total(&self) -> usize {
+            self.commitments
+                + self.seeds
+                + self.ot_verify
+                + self.b2a
+                + self.failures
+                + self.sqcorr_verify
+                + self.a2s
+        }
+    }
+
+    /// MPC traffic of the server pipeline of `protocol` for `num_clients`
+    /// clients that all pass, with inputs in ring `I`, arithmetic shares in
+    /// ring `A` and correlations in ring `C`. Messages outside the pipeline,
+    /// like the release of the aggregate, are not counted.
+    pub fn estimate_mpc_sizes<I, A, C>(
+        protocol: Protocol,
+        dims: ProtocolDims,
+        num_clients: usize,
+    ) -> MpcSizeEstimate
+    where
+        I: UInt,
+        A: UInt,
+        C: UInt,
+    {
+        let header = MESSAGE_HEADER_SIZE;
+        // one message per client, or one exchange, where both servers send
+        let per_client = |size: usize| num_clients * (header + size);
+        let exchange = |size: usize| 2 * (header + size);
+        // one message per client in a batch, where both servers send
+        let batch_exchange =
+            |size: usize| exchange(BATCH_COUNT_SIZE + num_clients * (header + size));
+
+        let mut estimate = MpcSizeEstimate {
+            commitments: exchange(vec_size::<ShareCommitment>(num_clients))
+                + exchange(vec_size::<u8>(num_clients)),
+            ot_verify: per_client(size_of::<Block>() + TTil::WIRE_SIZE),
+            b2a: num_clients
+                * (B2A_CHUNKS * (header + vec_size::<A>(0)) + dims.num_ot() * size_of::<A>()),
+            failures: exchange(vec_size::<u8>(num_clients)),
+            ..Default::default()
+        };
+        estimate.seeds = match protocol {
+            // sampled jointly by the servers
+            Protocol::Po2 | Protocol::L2 => exchange(size_of::<u64>()),
+            // one chi seed and one t seed per client, shared by the client
+            Protocol::Mp => 2 * exchange(vec_size::<u64>(num_clients)),
+        };
+        let num_w_shares = if COMPRESSED_CORR_VERIFY {
+            1
+        } else {
+            dims.gsize()
+        };
+        match protocol {
+            Protocol::Po2 => {},
+            Protocol::L2 => {
+                estimate.sqcorr_verify = num_clients
+                    * (exchange(vec_size::<C>(dims.gsize()))
+                        + exchange(vec_size::<C>(num_w_shares)));
+                estimate.a2s = num_clients * exchange(vec_size::<A>(dims.gsize()));
+            },
+            Protocol::Mp => {
+                estimate.sqcorr_verify = batch_exchange(vec_size::<C>(dims.gsize()))
+                    + batch_exchange(vec_size::<C>(num_w_shares));
+                estimate.a2s = batch_exchange(vec_size::<A>(dims.gsize()));
+                estimate.failures += exchange(vec_size::<u8>(num_clients));
+            },
+        }
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use block::Block;
+    use rand::{rngs::StdRng, SeedableRng};
+    use serialize::Communicate;
+    use sha2::Sha256;
+
+    use super::{
+        l2::{ClientL2MsgToAlice, ClientL2MsgToBob},
+        po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
+        size::{estimate_mpc_sizes, estimate_sizes, MessageSizeReport, Protocol},
+    };
+    use crate::{
+        bits::{batch_make_boolean_shares, BitsLE, ShareCommitment, ShareSalt},
+        cot::{
+            client::{B2ACOTToBob, COTGen, DEFAULT_SEC_PARAM},
+            dims::ProtocolDims,
+            ChoiceSeed,
+        },
+        malpriv::MessageHash,
+        square_corr::{batch_make_sqcorr_shares, COMPRESSED_CORR_VERIFY},
+        uint::UInt,
+    };
+
+    type I = u16;
+    type A = u64;
+    type C = u128;
+
+    fn serialized_len<M: Communicate>(msg: M) -> usize {
+        let len = msg.size_in_bytes();
+        assert_eq!(msg.into_bytes_owned().len(), len);
+        len
+    }
+
+    fn digest() -> Vec<u8> {
+        Sha256::default().digest()
+    }
+
+    /// Serialized size of the messages to Alice and Bob of a real client.
+    fn client_msg_sizes(protocol: Protocol, dims: ProtocolDims) -> (usize, usize) {
+        let mut rng = StdRng::seed_from_u64(1);
+        let input = (0..dims.gsize())
+            .map(|_| I::rand(&mut rng))
+            .collect::<Vec<_>>();
+        let (input_0, input_1) =
+            batch_make_boolean_shares(&mut rng, input.iter().map(|x| x.bits_le()));
+        let salt = ShareSalt::sample(&mut rng);
+        let commitment = ShareCommitment::new(0, 0, input_0, salt, &input_1);
+        let delta = COTGen::sample_delta(&mut rng);
+        let (cot_s, cot_r) = COTGen::sample_cots(&mut rng, &input_1, delta, dims.num_additional());
+        if protocol == Protocol::Po2 {
+            return (
+                serialized_len(ClientPo2MsgToAlice::new(
+                    dims.gsize(),
+                    input_0,
+                    commitment,
+                    cot_s,
+                )),
+                serialized_len(ClientPo2MsgToBob::new(input_1, commitment, salt, cot_r)),
+            );
+        }
+        let (corr_0, corr_1, ..) = batch_make_sqcorr_shares::<C, _>(&mut rng, dims.gsize() * 2);
+        let msg_alice = ClientL2MsgToAlice::new(dims.gsize(), input_0, commitment, cot_s, corr_0);
+        let msg_bob = ClientL2MsgToBob::new(input_1, commitment, salt, cot_r, corr_1);
+        if protocol == Protocol::L2 {
+            return (serialized_len(msg_alice), serialized_len(msg_bob));
+        }
+        (
+            serialized_len(((msg_alice, digest()), (digest(), digest()))),
+            serialized_len(((msg_bob, digest(), digest()), digest())),
+        )
+    }
+
+    #[test]
+    fn test_estimate_sizes_match_messages() {
+        for gsize in [1, 10, 1000] {
+            let dims = ProtocolDims::new::<I>(gsize, DEFAULT_SEC_PARAM).unwrap();
+            for protocol in [Protocol::Po2, Protocol::L2, Protocol::Mp] {
+                let report = estimate_sizes::<I, C, Sha256>(protocol, dims);
+                assert_eq!(
+                    (report.to_alice.total(), report.to_bob.total()),
+                    client_msg_sizes(protocol, dims),
+                    "{:?} with gsize {}",
+                    protocol,
+                    gsize
+                );
+            }
+        }
+    }
+
+    // pinned, so that a change of a message format shows up in review
+    #[test]
+    fn test_estimate_sizes_pinned() {
+        let dims = ProtocolDims::new::<u8>(1000, DEFAULT_SEC_PARAM).unwrap();
+        let report = |protocol| estimate_sizes::<u8, C, Sha256>(protocol, dims);
+        let MessageSizeReport {
+            to_alice, to_bob, ..
+        } = report(Protocol::Mp);
+        assert_eq!(dims.num_total(), 8194);
+        assert_eq!(
+            (to_alice.inputs, to_alice.commitment, to_alice.cot),
+            (16, 32, 32)
+        );
+        assert_eq!((to_alice.sqcorr, to_alice.hashes), (24, 120));
+        assert_eq!(
+            (to_bob.inputs, to_bob.commitment, to_bob.cot),
+            (1008, 48, 131_128)
+        );
+        assert_eq!((to_bob.sqcorr, to_bob.hashes), (32_024, 120));
+        assert_eq!(report(Protocol::Po2).total(), 132_264);
+        assert_eq!(report(Protocol::L2).total(), 164_312);
+        assert_eq!(report(Protocol::Mp).total(), 164_552);
+
+        let mpc = |protocol| estimate_mpc_sizes::<u8, A, C>(protocol, dims, 100).total();
+        assert_eq!(mpc(Protocol::Po2), 6_432_592);
+        if COMPRESSED_CORR_VERIFY {
+            assert_eq!(mpc(Protocol::L2), 11_250_192);
+            assert_eq!(mpc(Protocol::Mp), 11_253_832);
+        } else {
+            assert_eq!(mpc(Protocol::L2), 14_446_992);
+            assert_eq!(mpc(Protocol::Mp), 14_450_632);
+        }
+    }
+
+    #[test]
+    fn test_bits_msgs_round_trip() {
+        use super::bits::{ClientBitsMsgToAlice, ClientBitsMsgToBob};
+        use crate::bits::PackedBits;
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let (shares_0, shares_1) = PackedBits::rand(&mut rng, 45).to_boolean_shares(&mut rng);
+        let delta = COTGen::sample_delta(&mut rng);
+        let (cot_s, cot_r) = COTGen::sample_cots_using_selected_bits_for(
+            &mut rng,
+            0,
+            shares_1.iter(),
+            shares_1.len(),
+            delta,
+            0,
+        );
+        for cots in [None, Some((cot_s, cot_r))] {
+            let (cot_s, cot_r) = cots.unzip();
+            let to_alice = ClientBitsMsgToAlice {
+                shares: shares_0.clone(),
+                cot: cot_s,
+            };
+            let bytes = to_alice.clone().into_bytes_owned();
+            assert_eq!(bytes.len(), to_alice.size_in_bytes());
+            let back = ClientBitsMsgToAlice::from_bytes_owned(bytes).unwrap();
+            assert_eq!(back.shares, to_alice.shares);
+            assert_eq!(
+                back.cot.map(|cot| (cot.delta, cot.qs_seed.0)),
+                to_alice.cot.map(|cot| (cot.delta, cot.qs_seed.0))
+            );
+
+            let to_bob = ClientBitsMsgToBob {
+                shares: shares_1.clone(),
+                cot: cot_r,
+            };
+            let mut bytes = to_bob.clone().into_bytes_owned().to_vec();
+            assert_eq!(bytes.len(), to_bob.size_in_bytes());
+            let back = ClientBitsMsgToBob::from_bytes_owned(bytes.clone().into()).unwrap();
+            assert_eq!(back.shares, to_bob.shares);
+            assert_eq!(
+                back.cot.as_ref().map(|cot| cot.ts()),
+                to_bob.cot.as_ref().map(|cot| cot.ts())
+            );
+
+            // the tag of the COTs is the byte after the shares
+            bytes[to_bob.shares.size_in_bytes()] = 2;
+            assert!(ClientBitsMsgToBob::from_bytes_owned(bytes.into()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_po2_msg_to_bob_size_in_bytes_for() {
+        let dims = ProtocolDims::new::<u16>(10, DEFAULT_SEC_PARAM).unwrap();
+        let msg = ClientPo2MsgToBob::<u16>::new(
+            vec![BitsLE(0); dims.gsize()],
+            ShareCommitment::default(),
+            ShareSalt::default(),
+            B2ACOTToBob::new(
+                ChoiceSeed(0),
+                vec![Block::default(); dims.num_total()],
+                dims.num_additional(),
+            ),
+        );
+        assert_eq!(
+            ClientPo2MsgToBob::<u16>::size_in_bytes_for(dims),
+            serialized_len(msg)
+        );
+    }
+
+    #[cfg(feature = "signed-uploads")]
+    #[test]
+    fn test_signed_message() {
+        use super::signed::{SignedMessage, SigningKey, SIGNATURE_LENGTH};
+        use bytes::Bytes;
+
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let msg = (0..100u64).collect::<Vec<_>>();
+        let bytes = SignedMessage::sign(&key, msg.clone()).into_bytes_owned();
+        assert_eq!(bytes.len(), serialized_len(msg.clone()) + SIGNATURE_LENGTH);
+
+        let signed = SignedMessage::<Bytes>::from_bytes_owned(bytes.clone()).unwrap();
+        signed.verify(&key.verifying_key()).unwrap();
+        assert_eq!(Vec::<u64>::from_bytes_owned(signed.msg).unwrap(), msg);
+
+        // a bit flip in the message or in the signature
+        for idx in [3, bytes.len() - 1] {
+            let mut flipped = bytes.to_vec();
+            flipped[idx] ^= 1;
+            let signed = SignedMessage::<Bytes>::from_bytes_owned(flipped.into()).unwrap();
+            assert!(signed.verify(&key.verifying_key()).is_err());
+        }
+
+        // signed by another client
+        let other = SigningKey::from_bytes(&[2; 32]);
+        let signed = SignedMessage::<Bytes>::from_bytes_owned(bytes).unwrap();
+        assert!(signed.verify(&other.verifying_key()).is_err());
+
+        let short = Bytes::from(vec![0; SIGNATURE_LENGTH - 1]);
+        assert!(SignedMessage::<Bytes>::from_bytes_owned(short).is_err());
+    }
+}
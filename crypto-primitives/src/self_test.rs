@@ -0,0 +1,464 @@
+//! Self benchmarks of the critical kernels, to check that a host is fast
+//! enough before a big run.
+//!
+//! Each kernel calls the same function used by the protocol. Measured rates
+//! are compared against a reference [`HardwareProfile`], and are used to
+//! project the duration of the compute-heavy phases of a server.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use block::Block;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sha2::Sha256;
+
+use crate::{
+    bits::SeededInputShare,
+    block_crypto::mitccrh::MiTCCR,
+    cot::{client::num_additional_ot_needed, server::inner_product},
+    malpriv::MessageHash,
+    utils::batch_xor,
+};
+
+/// A benchmarked kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kernel {
+    /// MiTCCR hashing, in blocks per second.
+    MiTCCR,
+    /// GF(2^128) inner product (carry-less multiplication), in products per
+    /// second.
+    InnerProduct,
+    /// ChaCha expansion of seeded input shares, in `u32` elements per second.
+    ChaChaExpand,
+    /// SHA-256 transcript hashing, in bytes per second.
+    Sha256,
+    /// XOR of two large `u64` slices, in `u64` words per second.
+    MemoryXor,
+    /// Writing a recording of client messages to disk, as `--dump-clients`
+    /// does, in bytes per second. Measured by the binaries, see
+    /// `bin_utils::server`.
+    DiskWrite,
+}
+
+/// The kernels measured by `run_benchmarks`.
+pub const CPU_KERNELS: [Kernel; 5] = [
+    Kernel::MiTCCR,
+    Kernel::InnerProduct,
+    Kernel::ChaChaExpand,
+    Kernel::Sha256,
+    Kernel::MemoryXor,
+];
+
+pub const ALL_KERNELS: [Kernel; 6] = [
+    Kernel::MiTCCR,
+    Kernel::InnerProduct,
+    Kernel::ChaChaExpand,
+    Kernel::Sha256,
+    Kernel::MemoryXor,
+    Kernel::DiskWrite,
+];
+
+impl Kernel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kernel::MiTCCR => "MiTCCR hash",
+            Kernel::InnerProduct => "clmul inner product",
+            Kernel::ChaChaExpand => "ChaCha expansion",
+            Kernel::Sha256 => "SHA-256",
+            Kernel::MemoryXor => "memory XOR",
+            Kernel::DiskWrite => "disk write",
+        }
+    }
+
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Kernel::MiTCCR => "blocks/s",
+            Kernel::InnerProduct => "products/s",
+            Kernel::ChaChaExpand => "elements/s",
+            Kernel::Sha256 => "bytes/s",
+            Kernel::MemoryXor => "words/s",
+            Kernel::DiskWrite => "bytes/s",
+        }
+    }
+}
+
+/// Expected single-thread throughput of each kernel on a class of hardware.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HardwareProfile {
+    pub name: &'static str,
+    pub mitccr: f64,
+    pub inner_product: f64,
+    pub chacha_expand: f64,
+    pub sha256: f64,
+    pub memory_xor: f64,
+    pub disk_write: f64,
+}
+
+impl HardwareProfile {
+    pub fn rate(&self, kernel: Kernel) -> f64 {
+        match kernel {
+            Kernel::MiTCCR => self.mitccr,
+            Kernel::InnerProduct => self.inner_product,
+            Kernel::ChaChaExpand => self.chacha_expand,
+            Kernel::Sha256 => self.sha256,
+            Kernel::MemoryXor => self.memory_xor,
+            Kernel::DiskWrite => self.disk_write,
+        }
+    }
+
+    /// Build a profile from measured rates.
+    pub fn from_measured(name: &'static str, measured: &[Measurement]) -> Self {
+        let rate = |kernel| {
+            measured
+                .iter()
+                .find(|m| m.kernel == kernel)
+                .map(|m| m.rate)
+                .unwrap_or(0.)
+        };
+        Self {
+            name,
+            mitccr: rate(Kernel::MiTCCR),
+            inner_product: rate(Kernel::InnerProduct),
+            chacha_expand: rate(Kernel::ChaChaExpand),
+            sha256: rate(Kernel::Sha256),
+            memory_xor: rate(Kernel::MemoryXor),
+            disk_write: rate(Kernel::DiskWrite),
+        }
+    }
+}
+
+/// Server class hardware with AES-NI and CLMUL.
+pub const SERVER_AES_NI: HardwareProfile = HardwareProfile {
+    name: "server-aes-ni",
+    mitccr: 4.0e7,
+    inner_product: 1.0e8,
+    chacha_expand: 2.0e8,
+    sha256: 4.0e8,
+    memory_xor: 2.0e8,
+    disk_write: 1.0e9,
+};
+
+/// Laptop or small cloud instance with AES-NI and CLMUL.
+pub const LAPTOP_AES_NI: HardwareProfile = HardwareProfile {
+    name: "laptop-aes-ni",
+    mitccr: 2.0e7,
+    inner_product: 5.0e7,
+    chacha_expand: 1.0e8,
+    sha256: 2.0e8,
+    memory_xor: 1.0e8,
+    disk_write: 3.0e8,
+};
+
+pub const REFERENCE_PROFILES: [HardwareProfile; 2] = [SERVER_AES_NI, LAPTOP_AES_NI];
+
+pub fn find_profile(name: &str) -> Option<HardwareProfile> {
+    REFERENCE_PROFILES.iter().find(|p| p.name == name).copied()
+}
+
+/// Measured throughput of a kernel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub kernel: Kernel,
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assessment {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// A kernel passes at 80% of the reference rate and fails below 50%.
+const PASS_RATIO: f64 = 0.8;
+const WARN_RATIO: f64 = 0.5;
+
+impl Assessment {
+    pub fn of(measured: f64, reference: f64) -> Self {
+        let ratio = measured / reference;
+        if ratio >= PASS_RATIO {
+            Assessment::Pass
+        } else if ratio >= WARN_RATIO {
+            Assessment::Warn
+        } else {
+            Assessment::Fail
+        }
+    }
+}
+
+impl Display for Assessment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Assessment::Pass => "PASS",
+            Assessment::Warn => "WARN",
+            Assessment::Fail => "FAIL",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Throughput of `f`, which processes `num_items` items of `kernel`.
+pub fn measure(kernel: Kernel, num_items: usize, mut f: impl FnMut()) -> Measurement {
+    let start = Instant::now();
+    f();
+    let elapsed = start.elapsed().max(Duration::from_nanos(1));
+    Measurement {
+        kernel,
+        rate: num_items as f64 / elapsed.as_secs_f64(),
+    }
+}
+
+/// Run the `CPU_KERNELS` once on about `size` items each. Single threaded.
+pub fn run_benchmarks(size: usize) -> Vec<Measurement> {
+    const MITCCR_BATCH: usize = 8;
+    let mut rng = StdRng::seed_from_u64(0);
+    let size = size.max(MITCCR_BATCH);
+
+    let mitccr = {
+        let mut crh = MiTCCR::<MITCCR_BATCH>::new(Block::rand(&mut rng).0);
+        let mut input = [Block::default(); MITCCR_BATCH];
+        input.iter_mut().for_each(|x| *x = Block::rand(&mut rng));
+        let num_batches = size / MITCCR_BATCH;
+        measure(Kernel::MiTCCR, num_batches * MITCCR_BATCH, || {
+            for _ in 0..num_batches {
+                crh.hash_block::<1, MITCCR_BATCH>(&mut input);
+            }
+            black_box(&input);
+        })
+    };
+
+    let inner_product = {
+        let a = (0..size).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+        let b = (0..size).map(|_| Block::rand(&mut rng)).collect::<Vec<_>>();
+        measure(Kernel::InnerProduct, size, || {
+            black_box(inner_product(&a, &b));
+        })
+    };
+
+    let chacha_expand = {
+        let share = SeededInputShare(rng.gen());
+        measure(Kernel::ChaChaExpand, size, || {
+            black_box(share.expand::<u32>(size));
+        })
+    };
+
+    let sha256 = {
+        let data = (0..size).map(|_| rng.gen::<u8>()).collect::<Vec<_>>();
+        measure(Kernel::Sha256, size, || {
+            let mut hasher = Sha256::default();
+            hasher.absorb(&data);
+            black_box(hasher.digest());
+        })
+    };
+
+    let memory_xor = {
+        let a = (0..size).map(|_| rng.gen::<u64>()).collect::<Vec<_>>();
+        let b = (0..size).map(|_| rng.gen::<u64>()).collect::<Vec<_>>();
+        measure(Kernel::MemoryXor, size, || {
+            black_box(batch_xor(&a, &b));
+        })
+    };
+
+    vec![mitccr, inner_product, chacha_expand, sha256, memory_xor]
+}
+
+/// Number of items used by each kernel in a self test.
+pub const SELF_TEST_SIZE: usize = 1 << 22;
+
+/// Projected single-thread duration of a server phase.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseProjection {
+    pub phase: &'static str,
+    pub duration: Duration,
+}
+
+/// Project the compute time of each phase for `num_clients` clients, each
+/// with `gsize` inputs of `num_bits` bits, assuming the rates of `profile`.
+/// Communication and parallelism are not taken into account.
+pub fn project_phases(
+    profile: &HardwareProfile,
+    gsize: usize,
+    num_bits: usize,
+    num_clients: usize,
+    sec_param: usize,
+) -> Vec<PhaseProjection> {
+    let num_ot = gsize * num_bits;
+    let num_ot_with_additional = num_ot + num_additional_ot_needed(num_ot, sec_param);
+    let project = |phase, num_items: usize, kernel| PhaseProjection {
+        phase,
+        duration: Duration::from_secs_f64((num_clients * num_items) as f64 / profile.rate(kernel)),
+    };
+    vec![
+        project("expand input shares", gsize, Kernel::ChaChaExpand),
+        project("OT verify", num_ot_with_additional, Kernel::InnerProduct),
+        project("B2A", num_ot, Kernel::MiTCCR),
+        project(
+            "transcript hash",
+            num_ot * std::mem::size_of::<Block>(),
+            Kernel::Sha256,
+        ),
+    ]
+}
+
+/// Measured kernel compared with its reference rate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelReport {
+    pub kernel: Kernel,
+    pub measured: f64,
+    pub reference: f64,
+    pub assessment: Assessment,
+}
+
+/// Result of a self test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestReport {
+    pub profile: &'static str,
+    pub kernels: Vec<KernelReport>,
+    pub phases: Vec<PhaseProjection>,
+}
+
+impl SelfTestReport {
+    pub fn new(
+        measured: &[Measurement],
+        reference: &HardwareProfile,
+        gsize: usize,
+        num_bits: usize,
+        num_clients: usize,
+        sec_param: usize,
+    ) -> Self {
+        let kernels = measured
+            .iter()
+            .map(|m| {
+                let reference = reference.rate(m.kernel);
+                KernelReport {
+                    kernel: m.kernel,
+                    measured: m.rate,
+                    reference,
+                    assessment: Assessment::of(m.rate, reference),
+                }
+            })
+            .collect();
+        let phases = project_phases(
+            &HardwareProfile::from_measured("measured", measured),
+            gsize,
+            num_bits,
+            num_clients,
+            sec_param,
+        );
+        Self {
+            profile: reference.name,
+            kernels,
+            phases,
+        }
+    }
+
+    /// The worst assessment among all kernels.
+    pub fn assessment(&self) -> Assessment {
+        self.kernels
+            .iter()
+            .map(|k| k.assessment)
+            .fold(Assessment::Pass, |worst, a| match (worst, a) {
+                (Assessment::Fail, _) | (_, Assessment::Fail) => Assessment::Fail,
+                (Assessment::Warn, _) | (_, Assessment::Warn) => Assessment::Warn,
+                _ => Assessment::Pass,
+            })
+    }
+}
+
+impl Display for SelfTestReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "self test against profile {}:", self.profile)?;
+        for k in &self.kernels {
+            writeln!(
+                f,
+                "  [{}] {:<20} {:>12.3e} {} (reference {:.3e})",
+                k.assessment,
+                k.kernel.name(),
+                k.measured,
+                k.kernel.unit(),
+                k.reference
+            )?;
+        }
+        writeln!(f, "projected single-thread compute time:")?;
+        for p in &self.phases {
+            writeln!(f, "  {:<20} {:?}", p.phase, p.duration)?;
+        }
+        write!(f, "overall: {}", self.assessment())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::cot::client::DEFAULT_SEC_PARAM;
+
+    const FAKE_PROFILE: HardwareProfile = HardwareProfile {
+        name: "fake",
+        mitccr: 1000.,
+        inner_product: 2000.,
+        chacha_expand: 100.,
+        sha256: 16000.,
+        memory_xor: 1.,
+        disk_write: 1.,
+    };
+
+    #[test]
+    fn test_self_test_report() {
+        let measured = run_benchmarks(1 << 10);
+        assert_eq!(
+            measured.iter().map(|m| m.kernel).collect::<Vec<_>>(),
+            CPU_KERNELS
+        );
+        assert!(measured.iter().all(|m| m.rate > 0.));
+
+        let report = SelfTestReport::new(&measured, &SERVER_AES_NI, 100, 8, 10, DEFAULT_SEC_PARAM);
+        assert_eq!(report.kernels.len(), CPU_KERNELS.len());
+        assert_eq!(report.phases.len(), 4);
+        assert!(report.to_string().contains("server-aes-ni"));
+    }
+
+    #[test]
+    fn test_projection() {
+        // 100 inputs of 8 bits: 800 OTs, and 128 + 40 + 26 additional OTs
+        let phases = project_phases(&FAKE_PROFILE, 100, 8, 10, DEFAULT_SEC_PARAM);
+        let secs = phases
+            .iter()
+            .map(|p| (p.phase, p.duration))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            secs,
+            vec![
+                ("expand input shares", Duration::from_secs(10)),
+                ("OT verify", Duration::from_secs_f64(4.97)),
+                ("B2A", Duration::from_secs(8)),
+                ("transcript hash", Duration::from_secs(8)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assessment() {
+        assert_eq!(Assessment::of(90., 100.), Assessment::Pass);
+        assert_eq!(Assessment::of(60., 100.), Assessment::Warn);
+        assert_eq!(Assessment::of(10., 100.), Assessment::Fail);
+
+        let measured = ALL_KERNELS
+            .iter()
+            .map(|&kernel| Measurement {
+                kernel,
+                rate: FAKE_PROFILE.rate(kernel),
+            })
+            .collect::<Vec<_>>();
+        let mut report = SelfTestReport::new(&measured, &FAKE_PROFILE, 1, 8, 1, DEFAULT_SEC_PARAM);
+        assert_eq!(report.assessment(), Assessment::Pass);
+        report.kernels[1].assessment = Assessment::Warn;
+        assert_eq!(report.assessment(), Assessment::Warn);
+        report.kernels[3].assessment = Assessment::Fail;
+        assert_eq!(report.assessment(), Assessment::Fail);
+    }
+}
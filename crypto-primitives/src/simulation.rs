@@ -0,0 +1,115 @@
+//! Helpers that are insecure by design, for tests and simulations only: the
+//! dummy AND gate, dummy client messages, a fixed seed of chi, dummy shares
+//! of known inputs, the ROTs of the servers from a shared seed in
+//! `cot::rot::server_generated`, and the simulation of square correlations
+//! generated by the servers in `square_corr::server_gen`, which only models
+//! their cost.
+//!
+//! They are compiled only with the `simulation-helpers` feature, which the
+//! servers enable for their simulation modes (e.g. `no-comm`) and never by
+//! default. Each of them creates a [`SimulationOnly`] marker when it is
+//! constructed, so a run can tell afterwards that it used one.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(any(test, feature = "simulation-helpers"))]
+use rand::{rngs::StdRng, SeedableRng};
+#[cfg(any(test, feature = "simulation-helpers"))]
+use rayon::prelude::*;
+
+#[cfg(any(test, feature = "simulation-helpers"))]
+use crate::uint::UInt;
+
+/// Whether the simulation helpers of this crate are compiled in.
+pub const HELPERS_COMPILED: bool = cfg!(feature = "simulation-helpers");
+
+static HELPER_CONSTRUCTED: AtomicBool = AtomicBool::new(false);
+
+/// Created by every simulation helper of this crate. Creating one records
+/// that a simulation helper was constructed in this process.
+#[derive(Debug, Clone, Copy)]
+pub struct SimulationOnly(());
+
+impl SimulationOnly {
+    #[cfg_attr(not(feature = "simulation-helpers"), allow(dead_code))]
+    pub(crate) fn mark() -> Self {
+        HELPER_CONSTRUCTED.store(true, Ordering::Relaxed);
+        SimulationOnly(())
+    }
+}
+
+/// Whether a simulation helper of this crate was constructed in this process.
+pub fn helper_constructed() -> bool {
+    HELPER_CONSTRUCTED.load(Ordering::Relaxed)
+}
+
+/// Seed of chi shared by servers that do not communicate. Clients that know
+/// it in advance can pass OT verification with inconsistent OTs.
+#[cfg(feature = "simulation-helpers")]
+pub fn fixed_chi_seed() -> u64 {
+    SimulationOnly::mark();
+    123456
+}
+
+/// Shares of `num_clients` clients of `gsize` inputs each, in place of the
+/// B2A outputs of servers that skip OT. The server with global role
+/// `is_alice` gets its share, and two servers given the same `seed` hold
+/// shares of inputs that are all ones, so that the phases after B2A can be
+/// checked. Client `i` is seeded with `seed + i`.
+#[cfg(any(test, feature = "simulation-helpers"))]
+pub fn make_consistent_dummy_shares<A: UInt>(
+    seed: u64,
+    num_clients: usize,
+    gsize: usize,
+    is_alice: bool,
+) -> Vec<Vec<A>> {
+    SimulationOnly::mark();
+    (0..num_clients as u64)
+        .into_par_iter()
+        .map(|i| {
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(i));
+            (0..gsize)
+                .map(|_| {
+                    let (alice, bob) = A::one().arith_shares(&mut rng);
+                    if is_alice {
+                        alice
+                    } else {
+                        bob
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{helper_constructed, make_consistent_dummy_shares, SimulationOnly};
+
+    #[test]
+    fn test_construction_is_recorded() {
+        SimulationOnly::mark();
+        assert!(helper_constructed());
+    }
+
+    #[test]
+    fn test_dummy_shares_reconstruct_to_ones() {
+        let alice = make_consistent_dummy_shares::<u32>(7, 3, 5, true);
+        let bob = make_consistent_dummy_shares::<u32>(7, 3, 5, false);
+        assert_eq!(alice.len(), 3);
+        for (alice, bob) in alice.iter().zip(&bob) {
+            assert_eq!(alice.len(), 5);
+            // the shares are random, not the plaintext itself
+            assert_ne!(alice, &vec![1; 5]);
+            let sum = alice
+                .iter()
+                .zip(bob)
+                .map(|(a, b)| a.wrapping_add(*b))
+                .collect::<Vec<_>>();
+            assert_eq!(sum, vec![1; 5]);
+        }
+        // other clients and seeds get other shares
+        assert_ne!(alice[0], alice[1]);
+        assert_ne!(alice, make_consistent_dummy_shares::<u32>(8, 3, 5, true));
+    }
+}
@@ -1,9 +1,29 @@
 //! Square Correlation
-use crate::{uint::UInt, ALICE};
+use crate::{
+    block_crypto::prg::Prg,
+    error::{check_len, ProtocolError},
+    uint::UInt,
+    ALICE,
+};
 use bytemuck::{Pod, Zeroable};
-use rand::{Rng, SeedableRng};
-use rand_chacha::ChaCha12Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+#[cfg(target_endian = "big")]
+use serialize::util::LePod;
 use serialize::{AsUseCast, Communicate, UseCast};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+// insecure as the servers run it, see its docs
+#[cfg(any(test, feature = "simulation-helpers"))]
+pub mod server_gen;
+
+/// Whether the servers open one random linear combination of the `w` of a
+/// client instead of all of them, see
+/// [`SquareCorrShare::verify_phase_2_compressed`]. Set by the
+/// `compressed-corr-verify` feature, which changes the transcript, so both
+/// servers and the clients must agree on it.
+pub const COMPRESSED_CORR_VERIFY: bool = cfg!(feature = "compressed-corr-verify");
 
 #[derive(Copy, Clone, Debug)]
 #[repr(transparent)]
@@ -14,6 +34,12 @@ pub struct SquareCorr<T: UInt>(pub [T; 2]);
 #[repr(transparent)]
 pub struct SquareCorrShare<T: UInt>(pub [T; 2]);
 
+impl<T: UInt> Zeroize for SquareCorrShare<T> {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
 impl<T: UInt> SquareCorr<T> {
     #[inline]
     pub fn new(value: T) -> Self {
@@ -94,6 +120,14 @@ impl<T: UInt> SquareCorrShare<T> {
 
     /// Verify correctness of `correlations` using `sacrificed` correlations.
     ///
+    /// Each correlation needs its own sacrificed correlation. Opening
+    /// `t_i a_i - a'` for several `i` with a shared `a'` leaks `a_i - a_j`,
+    /// and a random linear combination of correlations is not a square
+    /// correlation, because the cross terms `a_i a_j` are unknown. If the
+    /// error `e = c - a^2` of a correlation has 2-adic valuation `v` in a ring
+    /// of `l` bits, a uniform `t` gives `t^2 e = e'` with probability at most
+    /// `4 * 2^(-(l - v) / 2)`.
+    ///
     /// #Phase 1
     /// ## Input:
     /// * `t`: public randomness
@@ -101,15 +135,25 @@ impl<T: UInt> SquareCorrShare<T> {
     /// * `d_b`: a share of `ta - a'`
     /// ## Next Step:
     /// exchange `d_b` to open `d`, and go to phase 2.
-    pub fn verify_phase_1(correlations: &[Self], sacrificed: &[Self], t: &[T], db_dest: &mut [T]) {
-        assert_eq!(correlations.len(), db_dest.len());
-        assert_eq!(correlations.len(), sacrificed.len());
-        assert_eq!(correlations.len(), t.len());
+    ///
+    /// Fails with `LengthMismatch` unless all slices have one element per
+    /// correlation.
+    pub fn verify_phase_1(
+        correlations: &[Self],
+        sacrificed: &[Self],
+        t: &[T],
+        db_dest: &mut [T],
+    ) -> Result<(), ProtocolError> {
+        let n = correlations.len();
+        check_len("sacrificed correlations", n, sacrificed.len())?;
+        check_len("t", n, t.len())?;
+        check_len("d", n, db_dest.len())?;
 
         for i in 0..correlations.len() {
             let d = correlations[i].open_d(t[i], &sacrificed[i]);
             db_dest[i] = d;
         }
+        Ok(())
     }
 
     /// Verify correctness of `correlations` using `sacrificed` correlations.
@@ -122,22 +166,94 @@ impl<T: UInt> SquareCorrShare<T> {
     /// * `w_b`: a share of `te - e'`
     /// ## Next Step:
     /// exchange `w_b` to open `w`, and check `w` is zero.
+    ///
+    /// Fails as `verify_phase_1`.
     pub fn verify_phase_2<const PARTY: bool>(
         correlations: &[Self],
         sacrificed: &[Self],
         t: &[T],
         d: &[T],
         w_dest: &mut [T],
-    ) {
-        assert_eq!(correlations.len(), w_dest.len());
-        assert_eq!(correlations.len(), sacrificed.len());
-        assert_eq!(correlations.len(), t.len());
-        assert_eq!(correlations.len(), d.len());
+    ) -> Result<(), ProtocolError> {
+        Self::check_phase_2_lens(correlations, sacrificed, t, d)?;
+        check_len("w", correlations.len(), w_dest.len())?;
 
         for i in 0..correlations.len() {
             let w = correlations[i].open_w::<PARTY>(t[i], &sacrificed[i], d[i]);
             w_dest[i] = w;
         }
+        Ok(())
+    }
+
+    fn check_phase_2_lens(
+        correlations: &[Self],
+        sacrificed: &[Self],
+        t: &[T],
+        d: &[T],
+    ) -> Result<(), ProtocolError> {
+        let n = correlations.len();
+        check_len("sacrificed correlations", n, sacrificed.len())?;
+        check_len("t", n, t.len())?;
+        check_len("d", n, d.len())
+    }
+
+    /// Coefficients of the random linear combination of
+    /// `verify_phase_2_compressed`. They are derived from `t_seed` and the
+    /// opened `d`, so they are only fixed after `d` is.
+    pub fn rlc_coeffs(t_seed: u64, d: &[T]) -> Vec<T> {
+        let seed = Sha256::new()
+            .chain_update(t_seed.to_le_bytes())
+            .chain_update(bytemuck::cast_slice::<_, u8>(d))
+            .finalize();
+        let mut rng = StdRng::from_seed(seed.into());
+        (0..d.len()).map(|_| T::rand(&mut rng)).collect()
+    }
+
+    /// Same as `verify_phase_2`, but return my share of `sum_i r_i w_i` for
+    /// the coefficients `r` of `rlc_coeffs`, so that one ring element is
+    /// opened instead of one per correlation. A correlation with error `w_i`
+    /// of 2-adic valuation `v` passes with probability at most `2^(v - l)`
+    /// over the coefficients, in addition to the bound of `verify_phase_2`.
+    pub fn verify_phase_2_compressed<const PARTY: bool>(
+        correlations: &[Self],
+        sacrificed: &[Self],
+        t: &[T],
+        d: &[T],
+        rlc_coeffs: &[T],
+    ) -> Result<T, ProtocolError> {
+        Self::check_phase_2_lens(correlations, sacrificed, t, d)?;
+        check_len("coefficients", correlations.len(), rlc_coeffs.len())?;
+
+        Ok((0..correlations.len()).fold(T::zero(), |acc, i| {
+            let w = correlations[i].open_w::<PARTY>(t[i], &sacrificed[i], d[i]);
+            acc.wrapping_add(&rlc_coeffs[i].wrapping_mul(&w))
+        }))
+    }
+
+    /// My shares of `w` as sent in phase 2: one per correlation, or their
+    /// combination if [`COMPRESSED_CORR_VERIFY`] is set. `w` is zero, i.e.
+    /// all correlations pass, if the shares of both parties sum to zero.
+    pub fn verify_phase_2_shares<const PARTY: bool>(
+        correlations: &[Self],
+        sacrificed: &[Self],
+        t: &[T],
+        d: &[T],
+        t_seed: u64,
+    ) -> Result<Vec<T>, ProtocolError> {
+        if COMPRESSED_CORR_VERIFY {
+            let rlc_coeffs = Self::rlc_coeffs(t_seed, d);
+            Ok(vec![Self::verify_phase_2_compressed::<PARTY>(
+                correlations,
+                sacrificed,
+                t,
+                d,
+                &rlc_coeffs,
+            )?])
+        } else {
+            let mut wb = vec![T::zero(); correlations.len()];
+            Self::verify_phase_2::<PARTY>(correlations, sacrificed, t, d, &mut wb)?;
+            Ok(wb)
+        }
     }
 
     #[inline]
@@ -161,83 +277,208 @@ unsafe impl<T: UInt> Zeroable for SquareCorrShare<T> {}
 
 unsafe impl<T: UInt> Pod for SquareCorrShare<T> {}
 
-#[derive(Debug, Clone, Copy)]
+#[cfg(target_endian = "big")]
+impl<T: UInt> LePod for SquareCorr<T> {
+    fn swap_le(&mut self) {
+        self.0.swap_le()
+    }
+}
+
+#[cfg(target_endian = "big")]
+impl<T: UInt> LePod for SquareCorrShare<T> {
+    fn swap_le(&mut self) {
+        self.0.swap_le()
+    }
+}
+
+/// Sent as its two values, without the length prefix of a slice.
+impl<T: UInt> Communicate for SquareCorr<T> {
+    type Deserialized = Self;
+
+    fn size_in_bytes(&self) -> usize {
+        self.0.size_in_bytes()
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, dest: W) {
+        self.0.to_bytes(dest)
+    }
+
+    fn from_bytes<R: std::io::Read>(bytes: R) -> serialize::Result<Self::Deserialized> {
+        Ok(SquareCorr(<[T; 2]>::from_bytes(bytes)?))
+    }
+}
+
+/// Sent as its two values, without the length prefix of a slice.
+impl<T: UInt> Communicate for SquareCorrShare<T> {
+    type Deserialized = Self;
+
+    fn size_in_bytes(&self) -> usize {
+        self.0.size_in_bytes()
+    }
+
+    fn to_bytes<W: std::io::Write>(&self, dest: W) {
+        self.0.to_bytes(dest)
+    }
+
+    fn from_bytes<R: std::io::Read>(bytes: R) -> serialize::Result<Self::Deserialized> {
+        Ok(SquareCorrShare(<[T; 2]>::from_bytes(bytes)?))
+    }
+}
+
+/// Error of a square correlation message whose length does not match the
+/// group size, or of a pair of messages that are not correlated, see
+/// [`validate_sqcorr_pair`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CorrError {
+    #[error("message declares {actual} correlations, but {expected} are needed")]
+    WrongExpectedLen { expected: u64, actual: u64 },
+    #[error("message declares {expected} correlations, but carries {actual} shares of c")]
+    WrongNumShares { expected: u64, actual: usize },
+    #[error("correlation {index} is not a square: a^2 != c")]
+    NotSquare { index: usize },
+    #[error("message carries {actual} shares of bound witness bits, but {expected} are needed")]
+    WrongWitnessLen { expected: u64, actual: u64 },
+}
+
+/// `gsize` values to square, e.g. inputs, need one correlation each, and one
+/// sacrificed to verify it. See `a2s::num_squared` for the witness bits of a
+/// bound check.
+fn check_expected_len(expected_len: u64, gsize: usize) -> Result<(), CorrError> {
+    let expected = gsize as u64 * 2;
+    if expected_len != expected {
+        return Err(CorrError::WrongExpectedLen {
+            expected,
+            actual: expected_len,
+        });
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct CorrShareSeedToAlice {
     pub a_seed: u64,
     pub c_seed: u64,
+    /// number of correlations to expand
+    pub expected_len: u64,
 }
 
 impl CorrShareSeedToAlice {
-    pub fn expand<T: UInt>(&self, size: usize) -> Vec<SquareCorrShare<T>> {
-        let mut rng_a = ChaCha12Rng::seed_from_u64(self.a_seed);
-        let mut rng_c = ChaCha12Rng::seed_from_u64(self.c_seed);
-        (0..size)
-            .map(|_| {
-                let (a, c) = (T::rand(&mut rng_a), T::rand(&mut rng_c));
-                SquareCorrShare([a, c])
-            })
+    /// Expand with the PRG of this build, see `Prg::DEFAULT`.
+    pub fn expand<T: UInt>(&self) -> Vec<SquareCorrShare<T>> {
+        self.expand_with(Prg::DEFAULT)
+    }
+
+    /// Expand with AES in counter mode, whatever the PRG of this build.
+    pub fn expand_aes<T: UInt>(&self) -> Vec<SquareCorrShare<T>> {
+        self.expand_with(Prg::Aes)
+    }
+
+    pub fn expand_with<T: UInt>(&self, prg: Prg) -> Vec<SquareCorrShare<T>> {
+        let len = self.expected_len as usize;
+        prg.expand(self.a_seed, len)
+            .into_iter()
+            .zip(prg.expand(self.c_seed, len))
+            .map(|(a, c)| SquareCorrShare([a, c]))
             .collect()
     }
+
+    /// Expand the correlations for `gsize` inputs, or return an error if the
+    /// message is not for `gsize` inputs.
+    pub fn expand_checked<T: UInt>(
+        &self,
+        gsize: usize,
+    ) -> Result<Vec<SquareCorrShare<T>>, CorrError> {
+        check_expected_len(self.expected_len, gsize)?;
+        Ok(self.expand())
+    }
 }
 
 impl Communicate for CorrShareSeedToAlice {
     type Deserialized = Self;
 
     fn size_in_bytes(&self) -> usize {
-        self.a_seed.use_cast().size_in_bytes() * 2
+        self.a_seed.use_cast().size_in_bytes() * 3
     }
 
     fn to_bytes<W: std::io::Write>(&self, mut dest: W) {
         self.a_seed.use_cast().to_bytes(&mut dest);
-        self.c_seed.use_cast().to_bytes(dest);
+        self.c_seed.use_cast().to_bytes(&mut dest);
+        self.expected_len.use_cast().to_bytes(dest);
     }
 
     fn from_bytes<R: std::io::Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
         let a_seed = UseCast::<u64>::from_bytes(&mut bytes)?;
-        let c_seed = UseCast::<u64>::from_bytes(bytes)?;
-        Ok(CorrShareSeedToAlice { a_seed, c_seed })
+        let c_seed = UseCast::<u64>::from_bytes(&mut bytes)?;
+        let expected_len = UseCast::<u64>::from_bytes(bytes)?;
+        Ok(CorrShareSeedToAlice {
+            a_seed,
+            c_seed,
+            expected_len,
+        })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CorrShareSeedToBob<T: UInt> {
     pub a_seed: u64,
+    /// number of correlations, which is also the length of `c`
+    pub expected_len: u64,
     pub c: Vec<T>,
 }
 
 impl<T: UInt> CorrShareSeedToBob<T> {
+    /// Expand with the PRG of this build, see `Prg::DEFAULT`.
     pub fn expand(&self) -> Vec<SquareCorrShare<T>> {
-        let mut rng_a = ChaCha12Rng::seed_from_u64(self.a_seed);
-        self.c
-            .iter()
-            .map(|c| {
-                let a = T::rand(&mut rng_a);
-                SquareCorrShare([a, *c])
-            })
+        Prg::DEFAULT
+            .expand(self.a_seed, self.c.len())
+            .into_iter()
+            .zip(&self.c)
+            .map(|(a, c)| SquareCorrShare([a, *c]))
             .collect()
     }
+
+    /// Expand the correlations for `gsize` inputs, or return an error if the
+    /// message is not for `gsize` inputs, or `c` does not have the declared
+    /// length.
+    pub fn expand_checked(&self, gsize: usize) -> Result<Vec<SquareCorrShare<T>>, CorrError> {
+        check_expected_len(self.expected_len, gsize)?;
+        if self.c.len() as u64 != self.expected_len {
+            return Err(CorrError::WrongNumShares {
+                expected: self.expected_len,
+                actual: self.c.len(),
+            });
+        }
+        Ok(self.expand())
+    }
 }
 
 impl<T: UInt> Communicate for CorrShareSeedToBob<T> {
     type Deserialized = Self;
 
     fn size_in_bytes(&self) -> usize {
-        self.a_seed.use_cast().size_in_bytes() + self.c.size_in_bytes()
+        self.a_seed.use_cast().size_in_bytes() * 2 + self.c.size_in_bytes()
     }
 
     fn to_bytes<W: std::io::Write>(&self, mut dest: W) {
         self.a_seed.use_cast().to_bytes(&mut dest);
+        self.expected_len.use_cast().to_bytes(&mut dest);
         self.c.to_bytes(dest);
     }
 
     fn from_bytes<R: std::io::Read>(mut bytes: R) -> serialize::Result<Self::Deserialized> {
         let a_seed = UseCast::<u64>::from_bytes(&mut bytes)?;
+        let expected_len = UseCast::<u64>::from_bytes(&mut bytes)?;
         let c_seed = Vec::<T>::from_bytes(bytes)?;
-        Ok(CorrShareSeedToBob { a_seed, c: c_seed })
+        Ok(CorrShareSeedToBob {
+            a_seed,
+            expected_len,
+            c: c_seed,
+        })
     }
 }
 
 /// Create new correlation shares with size
+#[allow(clippy::type_complexity)]
 pub fn batch_make_sqcorr_shares<T: UInt, R: Rng>(
     rng: &mut R,
     size: usize,
@@ -250,20 +491,17 @@ pub fn batch_make_sqcorr_shares<T: UInt, R: Rng>(
     let a0_seed = rng.next_u64();
     let a1_seed = rng.next_u64();
     let c0_seed = rng.next_u64();
-    let mut a0_rng = ChaCha12Rng::seed_from_u64(a0_seed);
-    let mut a1_rng = ChaCha12Rng::seed_from_u64(a1_seed);
-    let mut c0_rng = ChaCha12Rng::seed_from_u64(c0_seed);
-    let a0c0 = (0..size)
-        .map(|_| {
-            let a = T::rand(&mut a0_rng);
-            let c = T::rand(&mut c0_rng);
-            SquareCorrShare([a, c])
-        })
+    let prg = Prg::DEFAULT;
+    let a0c0 = prg
+        .expand::<T>(a0_seed, size)
+        .into_iter()
+        .zip(prg.expand(c0_seed, size))
+        .map(|(a, c)| SquareCorrShare([a, c]))
         .collect::<Vec<_>>();
     let (c1, a1c1) = a0c0
         .iter()
-        .map(|SquareCorrShare([a0, c0])| {
-            let a1 = T::rand(&mut a1_rng);
+        .zip(prg.expand::<T>(a1_seed, size))
+        .map(|(SquareCorrShare([a0, c0]), a1)| {
             let a = a0.wrapping_add(&a1);
             let c = a.wrapping_mul(&a);
             let c1 = c.wrapping_sub(c0);
@@ -274,9 +512,11 @@ pub fn batch_make_sqcorr_shares<T: UInt, R: Rng>(
         CorrShareSeedToAlice {
             a_seed: a0_seed,
             c_seed: c0_seed,
+            expected_len: size as u64,
         },
         CorrShareSeedToBob {
             a_seed: a1_seed,
+            expected_len: size as u64,
             c: c1,
         },
         a0c0,
@@ -284,25 +524,53 @@ pub fn batch_make_sqcorr_shares<T: UInt, R: Rng>(
     )
 }
 
+/// Check the square correlations of a client for `gsize` inputs before
+/// sending them: both messages must be for `gsize` inputs, and the sum of the
+/// shares of each correlation must be `(a, a^2)`. The client knows both
+/// shares, so this is a self-check, not a check a server can run.
+pub fn validate_sqcorr_pair<T: UInt>(
+    to_alice: &CorrShareSeedToAlice,
+    to_bob: &CorrShareSeedToBob<T>,
+    gsize: usize,
+) -> Result<(), CorrError> {
+    let corr_0 = to_alice.expand_checked::<T>(gsize)?;
+    let corr_1 = to_bob.expand_checked(gsize)?;
+    for (index, (SquareCorrShare([a0, c0]), SquareCorrShare([a1, c1]))) in
+        corr_0.iter().zip(&corr_1).enumerate()
+    {
+        let a = a0.wrapping_add(a1);
+        if a.wrapping_mul(&a) != c0.wrapping_add(c1) {
+            return Err(CorrError::NotSquare { index });
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
-        square_corr::{batch_make_sqcorr_shares, SquareCorrShare},
+        error::ProtocolError,
+        square_corr::{
+            batch_make_sqcorr_shares, validate_sqcorr_pair, CorrError, CorrShareSeedToAlice,
+            CorrShareSeedToBob, SquareCorr, SquareCorrShare,
+        },
         uint::UInt,
+        utils::batch_wrapping_add,
         ALICE, BOB,
     };
-    use rand::{rngs::StdRng, SeedableRng};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use serialize::Communicate;
 
     fn correlations_template<T: UInt>() {
         const SIZE: usize = 1000;
         let mut rng = StdRng::seed_from_u64(12345);
 
         let (corr_0, corr_1, corr_0e, corr_1e) = batch_make_sqcorr_shares(&mut rng, SIZE);
-        let (corr_0, corr_1) = (corr_0.expand::<T>(SIZE), corr_1.expand());
+        let (corr_0, corr_1) = (corr_0.expand::<T>(), corr_1.expand());
         assert_eq!(corr_0, corr_0e);
         assert_eq!(corr_1, corr_1e);
         let (sacr_0, sacr_1, sacr_0e, sacr_1e) = batch_make_sqcorr_shares(&mut rng, SIZE);
-        let (sacr_0, sacr_1) = (sacr_0.expand(SIZE), sacr_1.expand());
+        let (sacr_0, sacr_1) = (sacr_0.expand(), sacr_1.expand());
         assert_eq!(sacr_0, sacr_0e);
         assert_eq!(sacr_1, sacr_1e);
 
@@ -312,8 +580,8 @@ mod tests {
             .chain(sacr_0.iter())
             .zip(corr_1.iter().chain(sacr_1.iter()))
         {
-            let a = a0.wrapping_add(&a1);
-            let c = c0.wrapping_add(&c1);
+            let a = a0.wrapping_add(a1);
+            let c = c0.wrapping_add(c1);
             let a_sq = a.wrapping_mul(&a);
             assert_eq!(a_sq, c);
         }
@@ -323,26 +591,18 @@ mod tests {
         let mut d0 = vec![T::zero(); SIZE];
         let mut d1 = vec![T::zero(); SIZE];
 
-        SquareCorrShare::verify_phase_1(&corr_0, &sacr_0, &t, &mut d0);
-        SquareCorrShare::verify_phase_1(&corr_1, &sacr_1, &t, &mut d1);
+        SquareCorrShare::verify_phase_1(&corr_0, &sacr_0, &t, &mut d0).unwrap();
+        SquareCorrShare::verify_phase_1(&corr_1, &sacr_1, &t, &mut d1).unwrap();
 
-        let d = d0
-            .iter()
-            .zip(d1.iter())
-            .map(|(d0, d1)| d0.wrapping_add(d1))
-            .collect::<Vec<_>>();
+        let d = batch_wrapping_add(&d0, &d1);
 
         let mut w0 = vec![T::zero(); SIZE];
         let mut w1 = vec![T::zero(); SIZE];
 
-        SquareCorrShare::verify_phase_2::<{ ALICE }>(&corr_0, &sacr_0, &t, &d, &mut w0);
-        SquareCorrShare::verify_phase_2::<{ BOB }>(&corr_1, &sacr_1, &t, &d, &mut w1);
+        SquareCorrShare::verify_phase_2::<{ ALICE }>(&corr_0, &sacr_0, &t, &d, &mut w0).unwrap();
+        SquareCorrShare::verify_phase_2::<{ BOB }>(&corr_1, &sacr_1, &t, &d, &mut w1).unwrap();
 
-        let w = w0
-            .iter()
-            .zip(w1.iter())
-            .map(|(w0, w1)| w0.wrapping_add(w1))
-            .collect::<Vec<_>>();
+        let w = batch_wrapping_add(&w0, &w1);
 
         // check w is all zero
         for w in w.iter() {
@@ -354,4 +614,220 @@ mod tests {
     fn correlation_u128() {
         correlations_template::<u128>();
     }
+
+    /// Opened `w` of `verify_phase_2_compressed` for `size` random
+    /// correlations, where the `c` share of Alice of correlation `i` (counting
+    /// the sacrificed ones after the others) is off by `delta` if `corrupt` is
+    /// `Some((i, delta))`.
+    fn compressed_w<T: UInt>(rng: &mut StdRng, size: usize, corrupt: Option<(usize, T)>) -> T {
+        let (mut shares_0, shares_1) = (0..2 * size)
+            .map(|_| SquareCorr::<T>::rand(rng).to_shares(rng))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        if let Some((i, delta)) = corrupt {
+            shares_0[i].0[1] = shares_0[i].0[1].wrapping_add(&delta);
+        }
+        let (corr_0, sacr_0) = shares_0.split_at(size);
+        let (corr_1, sacr_1) = shares_1.split_at(size);
+        let t = (0..size).map(|_| T::rand(rng)).collect::<Vec<_>>();
+        let t_seed = rng.gen::<u64>();
+
+        let mut d0 = vec![T::zero(); size];
+        let mut d1 = vec![T::zero(); size];
+        SquareCorrShare::verify_phase_1(corr_0, sacr_0, &t, &mut d0).unwrap();
+        SquareCorrShare::verify_phase_1(corr_1, sacr_1, &t, &mut d1).unwrap();
+        let d = batch_wrapping_add(&d0, &d1);
+
+        let rlc_coeffs = SquareCorrShare::rlc_coeffs(t_seed, &d);
+        let w0 = SquareCorrShare::verify_phase_2_compressed::<{ ALICE }>(
+            corr_0,
+            sacr_0,
+            &t,
+            &d,
+            &rlc_coeffs,
+        )
+        .unwrap();
+        let w1 = SquareCorrShare::verify_phase_2_compressed::<{ BOB }>(
+            corr_1,
+            sacr_1,
+            &t,
+            &d,
+            &rlc_coeffs,
+        )
+        .unwrap();
+        w0.wrapping_add(&w1)
+    }
+
+    #[test]
+    fn compressed_verify_passes_honest() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        for size in [1, 10, 1000] {
+            assert_eq!(compressed_w::<u64>(&mut rng, size, None), 0);
+            assert_eq!(compressed_w::<u128>(&mut rng, size, None), 0);
+        }
+    }
+
+    #[test]
+    fn compressed_verify_detects_corruption() {
+        const SIZE: usize = 50;
+        let mut rng = StdRng::seed_from_u64(12345);
+        for _ in 0..1000 {
+            let i = rng.gen_range(0..2 * SIZE);
+            let delta = rng.gen_range(1..=u64::MAX);
+            assert_ne!(compressed_w::<u64>(&mut rng, SIZE, Some((i, delta))), 0);
+            let delta = rng.gen_range(1..=u128::MAX);
+            assert_ne!(compressed_w::<u128>(&mut rng, SIZE, Some((i, delta))), 0);
+        }
+    }
+
+    #[test]
+    fn rlc_coeffs_depend_on_d() {
+        let d = [1u64, 2, 3];
+        let coeffs = SquareCorrShare::rlc_coeffs(7, &d);
+        assert_eq!(coeffs, SquareCorrShare::rlc_coeffs(7, &d));
+        assert_ne!(coeffs, SquareCorrShare::rlc_coeffs(7, &[1, 2, 4]));
+        assert_ne!(coeffs, SquareCorrShare::rlc_coeffs(8, &d));
+    }
+
+    #[test]
+    fn seed_messages_round_trip() {
+        const GSIZE: usize = 10;
+        let mut rng = StdRng::seed_from_u64(12345);
+        let (to_alice, to_bob, corr_0, corr_1) =
+            batch_make_sqcorr_shares::<u64, _>(&mut rng, GSIZE * 2);
+
+        let to_alice_de =
+            CorrShareSeedToAlice::from_bytes_owned(to_alice.into_bytes_owned()).unwrap();
+        let to_bob_de =
+            CorrShareSeedToBob::<u64>::from_bytes_owned(to_bob.clone().into_bytes_owned()).unwrap();
+        assert_eq!(to_alice_de, to_alice);
+        assert_eq!(to_bob_de, to_bob);
+        assert_eq!(to_alice_de.expand_checked::<u64>(GSIZE).unwrap(), corr_0);
+        assert_eq!(to_bob_de.expand_checked(GSIZE).unwrap(), corr_1);
+    }
+
+    #[test]
+    fn corr_round_trip() {
+        let share = SquareCorrShare([3u32, 9]);
+        let bytes = share.into_bytes_owned();
+        assert_eq!(bytes.as_ref(), &[3, 0, 0, 0, 9, 0, 0, 0]);
+        assert_eq!(
+            SquareCorrShare::<u32>::from_bytes_owned(bytes).unwrap(),
+            share
+        );
+
+        let corr = SquareCorr::new(5u64);
+        let bytes = corr.into_bytes_owned();
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(
+            SquareCorr::<u64>::from_bytes_owned(bytes).unwrap().0,
+            [5, 25]
+        );
+        assert!(SquareCorr::<u64>::from_bytes(&[0u8; 15][..]).is_err());
+    }
+
+    #[test]
+    fn expand_checked_rejects_wrong_length() {
+        const GSIZE: usize = 10;
+        let mut rng = StdRng::seed_from_u64(12345);
+        let (to_alice, mut to_bob, ..) = batch_make_sqcorr_shares::<u64, _>(&mut rng, GSIZE * 2);
+
+        // message for another group size
+        let wrong_len = CorrError::WrongExpectedLen {
+            expected: GSIZE as u64 * 2 + 2,
+            actual: GSIZE as u64 * 2,
+        };
+        assert_eq!(
+            to_alice.expand_checked::<u64>(GSIZE + 1),
+            Err(wrong_len.clone())
+        );
+        assert_eq!(to_bob.expand_checked(GSIZE + 1), Err(wrong_len));
+
+        // `c` shorter than declared
+        to_bob.c.pop();
+        assert_eq!(
+            to_bob.expand_checked(GSIZE),
+            Err(CorrError::WrongNumShares {
+                expected: GSIZE as u64 * 2,
+                actual: GSIZE * 2 - 1,
+            })
+        );
+
+        // `expected_len` no longer matches `c` after deserialization
+        to_bob.expected_len -= 1;
+        let to_bob =
+            CorrShareSeedToBob::<u64>::from_bytes_owned(to_bob.into_bytes_owned()).unwrap();
+        assert!(matches!(
+            to_bob.expand_checked(GSIZE),
+            Err(CorrError::WrongExpectedLen { .. })
+        ));
+    }
+
+    #[test]
+    fn validate_pair_detects_bad_correlation() {
+        const GSIZE: usize = 10;
+        let mut rng = StdRng::seed_from_u64(12345);
+        let (to_alice, mut to_bob, ..) = batch_make_sqcorr_shares::<u128, _>(&mut rng, GSIZE * 2);
+        assert_eq!(validate_sqcorr_pair(&to_alice, &to_bob, GSIZE), Ok(()));
+        assert!(matches!(
+            validate_sqcorr_pair(&to_alice, &to_bob, GSIZE + 1),
+            Err(CorrError::WrongExpectedLen { .. })
+        ));
+
+        to_bob.c[7] = to_bob.c[7].wrapping_add(1);
+        assert_eq!(
+            validate_sqcorr_pair(&to_alice, &to_bob, GSIZE),
+            Err(CorrError::NotSquare { index: 7 })
+        );
+    }
+
+    #[test]
+    fn verify_length_errors() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let shares = (0..8)
+            .map(|_| SquareCorr::<u64>::rand(&mut rng).to_shares(&mut rng).0)
+            .collect::<Vec<_>>();
+        let (corr, sacr) = shares.split_at(4);
+        let t = vec![1u64; 4];
+        let mismatch = |what, actual| ProtocolError::LengthMismatch {
+            expected: 4,
+            actual,
+            what,
+        };
+
+        let mut d = vec![0u64; 4];
+        assert_eq!(
+            SquareCorrShare::verify_phase_1(corr, &sacr[1..], &t, &mut d).unwrap_err(),
+            mismatch("sacrificed correlations", 3)
+        );
+        assert_eq!(
+            SquareCorrShare::verify_phase_1(corr, sacr, &t[..2], &mut d).unwrap_err(),
+            mismatch("t", 2)
+        );
+        assert_eq!(
+            SquareCorrShare::verify_phase_1(corr, sacr, &t, &mut d[..3]).unwrap_err(),
+            mismatch("d", 3)
+        );
+
+        let mut w = vec![0u64; 4];
+        assert_eq!(
+            SquareCorrShare::verify_phase_2::<{ ALICE }>(corr, sacr, &t, &d[1..], &mut w)
+                .unwrap_err(),
+            mismatch("d", 3)
+        );
+        assert_eq!(
+            SquareCorrShare::verify_phase_2::<{ BOB }>(corr, sacr, &t, &d, &mut w[..1])
+                .unwrap_err(),
+            mismatch("w", 1)
+        );
+        assert_eq!(
+            SquareCorrShare::verify_phase_2_compressed::<{ ALICE }>(corr, sacr, &t, &d, &t[..3])
+                .unwrap_err(),
+            mismatch("coefficients", 3)
+        );
+        assert_eq!(
+            SquareCorrShare::verify_phase_2_shares::<{ BOB }>(corr, &sacr[..3], &t, &d, 0)
+                .unwrap_err(),
+            mismatch("sacrificed correlations", 3)
+        );
+    }
 }
@@ -0,0 +1,183 @@
+//! Square correlations generated by the two servers between themselves,
+//! instead of by the clients, e.g. to measure what the correlations of the
+//! clients save.
+//!
+//! Alice samples `a0` and Bob `a1`, and `(a0 + a1)^2 = a0^2 + a1^2 + 2 a0 a1`.
+//! The squares are local, and the servers get shares of the cross term
+//! `a0 a1` with one COT per bit of `a1` (Gilboa): bit `i` of `a1` is the
+//! choice of Bob, and the OT multiplies it by `a0` modulo `2^(l - i)`, see
+//! `bitmul::scalar_mul_as_ot_sender`. Shifted by `i`, the products sum to
+//! `a0 a1` modulo `2^l`.
+//!
+//! The COTs are the ones of `cot::rot::server_generated`, with the bits of
+//! `a1` as the inputs of Bob. The servers expand their ROTs with
+//! `ROTsForSender::from_shared_seed`, so each of them can recompute the
+//! shares of the other: the correlations are not secret to either server,
+//! even a semi-honest one. This is **not secure**: it is a simulation that
+//! models the cost of server-generated correlations for benchmarks, with no
+//! secure counterpart implemented, so it is compiled only with the
+//! `simulation-helpers` feature.
+
+use crate::{
+    b2a::B2A_CHUNK_ELEMS,
+    bitmul::{bit_mul_as_ot_receiver, scalar_mul_as_ot_sender},
+    bits::BitsLE,
+    cot::rot::RotStream,
+    error::{check_len, Result},
+    simulation::SimulationOnly,
+    square_corr::SquareCorrShare,
+    uint::UInt,
+};
+use block::Block;
+use rand::Rng;
+
+/// My share of the correlation of `a`, given my share `z` of the cross term.
+fn corr_share<T: UInt>(a: T, z: T) -> SquareCorrShare<T> {
+    SquareCorrShare([a, a.wrapping_mul(&a).wrapping_add(&z.wrapping_add(&z))])
+}
+
+/// `count` random shares of `a`. The shares of Bob are his inputs to the
+/// COTs.
+pub fn sample_shares<T: UInt, R: Rng>(rng: &mut R, count: usize) -> Vec<T> {
+    (0..count).map(|_| T::rand(rng)).collect()
+}
+
+/// Generate the correlations of my shares `a0s`, assuming I'm OT sender with
+/// correlation `delta` and one COT `qs` per bit of `a0s`.
+///
+/// Returns:
+/// * my shares of the correlations
+/// * `us`, one per COT, to send to the OT receiver
+///
+/// Fails with `LengthMismatch` if there is not one COT per bit.
+pub fn gen_as_ot_sender<T: UInt>(
+    a0s: &[T],
+    delta: Block,
+    qs: &[Block],
+) -> Result<(Vec<SquareCorrShare<T>>, Vec<T>)> {
+    check_len("qs", a0s.len() * T::NUM_BITS, qs.len())?;
+    SimulationOnly::mark();
+
+    let mut rot = RotStream::new();
+    let (mut v0s, mut v1s) = (Vec::new(), Vec::new());
+    let mut corrs = Vec::with_capacity(a0s.len());
+    let mut us = vec![T::zero(); qs.len()];
+    for ((a0s, qs), us) in a0s
+        .chunks(B2A_CHUNK_ELEMS)
+        .zip(qs.chunks(B2A_CHUNK_ELEMS * T::NUM_BITS))
+        .zip(us.chunks_mut(B2A_CHUNK_ELEMS * T::NUM_BITS))
+    {
+        // convert COT to ROT
+        rot.sender_side_into(qs, delta, &mut v0s, &mut v1s);
+        corrs.extend(
+            a0s.iter()
+                .zip(v0s.chunks(T::NUM_BITS))
+                .zip(v1s.chunks(T::NUM_BITS))
+                .zip(us.chunks_mut(T::NUM_BITS))
+                .map(|(((a0, v0s), v1s), us)| {
+                    let mut z = T::zero();
+                    for (i, ((v0, v1), u_dest)) in v0s.iter().zip(v1s).zip(us).enumerate() {
+                        let (y0, u) = scalar_mul_as_ot_sender(T::NUM_BITS - i, *a0, *v0, *v1);
+                        *u_dest = u;
+                        // z += y0 * 2^i
+                        z = z.wrapping_add(&(y0 << i));
+                    }
+                    corr_share(*a0, z)
+                }),
+        );
+    }
+    Ok((corrs, us))
+}
+
+/// Generate the correlations of my shares `a1s`, assuming I'm OT receiver
+/// whose choices are the bits of `a1s`, with COTs `ts` and the `us` sent by
+/// the OT sender. Returns my shares of the correlations.
+///
+/// Fails with `LengthMismatch` unless there is one COT and one `u` per bit.
+pub fn gen_as_ot_receiver<T: UInt>(
+    a1s: &[T],
+    ts: &[Block],
+    us: &[T],
+) -> Result<Vec<SquareCorrShare<T>>> {
+    check_len("ts", a1s.len() * T::NUM_BITS, ts.len())?;
+    check_len("us", a1s.len() * T::NUM_BITS, us.len())?;
+    SimulationOnly::mark();
+
+    let mut rot = RotStream::new();
+    let mut vs = Vec::new();
+    let mut corrs = Vec::with_capacity(a1s.len());
+    for ((a1s, ts), us) in a1s
+        .chunks(B2A_CHUNK_ELEMS)
+        .zip(ts.chunks(B2A_CHUNK_ELEMS * T::NUM_BITS))
+        .zip(us.chunks(B2A_CHUNK_ELEMS * T::NUM_BITS))
+    {
+        // convert COT to ROT
+        rot.receiver_side_into(ts, &mut vs);
+        corrs.extend(
+            a1s.iter()
+                .zip(vs.chunks(T::NUM_BITS))
+                .zip(us.chunks(T::NUM_BITS))
+                .map(|((a1, vs), us)| {
+                    let mut z = T::zero();
+                    for (i, ((x1, v), u)) in BitsLE(*a1).iter().zip(vs).zip(us).enumerate() {
+                        let y1 = bit_mul_as_ot_receiver(T::NUM_BITS - i, x1, *v, *u);
+                        // z += y1 * 2^i
+                        z = z.wrapping_add(&(y1 << i));
+                    }
+                    corr_share(*a1, z)
+                }),
+        );
+    }
+    Ok(corrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cot::rot::server_generated::{
+        choice_flips, ROTsForReceiver, ROTsForSender, ServerCOTsForReceiver, ServerCOTsForSender,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn server_gen_template<T: UInt>() {
+        // not a multiple of the chunk size
+        const SIZE: usize = B2A_CHUNK_ELEMS + 100;
+        const SEED: u64 = 0x4567;
+        let mut rng = StdRng::seed_from_u64(3);
+        let a0s = sample_shares::<T, _>(&mut rng, SIZE);
+        let a1s = sample_shares::<T, _>(&mut rng, SIZE);
+
+        let count = SIZE * T::NUM_BITS;
+        let delta = Block::rand(&mut rng);
+        let (mut sender, correction) =
+            ServerCOTsForSender::from_rots(ROTsForSender::from_shared_seed(SEED, count), delta);
+        let receiver = ServerCOTsForReceiver::from_rots(
+            ROTsForReceiver::from_shared_seed(SEED, count),
+            &correction,
+        );
+        let inputs = a1s.iter().map(|a| BitsLE(*a)).collect::<Vec<_>>();
+        sender.apply_flips(&choice_flips(&receiver.choices, &inputs));
+
+        let (corrs_0, us) = gen_as_ot_sender(&a0s, sender.delta, &sender.qs).unwrap();
+        let corrs_1 = gen_as_ot_receiver(&a1s, &receiver.ts, &us).unwrap();
+        assert_eq!(corrs_0.len(), SIZE);
+        for ((c0, c1), (a0, a1)) in corrs_0.iter().zip(&corrs_1).zip(a0s.iter().zip(&a1s)) {
+            assert_eq!((c0.a(), c1.a()), (*a0, *a1));
+            let a = a0.wrapping_add(a1);
+            assert_eq!(c0.c().wrapping_add(&c1.c()), a.wrapping_mul(&a));
+        }
+
+        assert!(gen_as_ot_receiver(&a1s, &receiver.ts, &us[1..]).is_err());
+        assert!(gen_as_ot_sender(&a0s[1..], sender.delta, &sender.qs).is_err());
+    }
+
+    #[test]
+    fn test_server_gen_u64() {
+        server_gen_template::<u64>();
+    }
+
+    #[test]
+    fn test_server_gen_u128() {
+        server_gen_template::<u128>();
+    }
+}
@@ -5,11 +5,14 @@ use bytemuck::{Pod, Zeroable};
 use num_traits::{PrimInt, Unsigned, WrappingAdd, WrappingMul, WrappingNeg, WrappingSub};
 use rand::Rng;
 use safe_arch::{get_i32_from_m128i_s, get_i64_from_m128i_s, m128i};
+use serialize::util::LePod;
 use std::{
     any::Any,
+    convert::TryFrom,
     fmt::{Binary, Debug, Display, LowerHex, UpperHex},
     iter::Sum,
 };
+use zeroize::Zeroize;
 
 pub trait UInt:
     Unsigned
@@ -24,14 +27,16 @@ pub trait UInt:
     + Any
     + Debug
     + Pod
+    + LePod
     + Zeroable
     + Display
     + Binary
     + LowerHex
     + UpperHex
+    + Zeroize
 {
     const NUM_BITS: usize;
-    
+
     fn rand<R: Rng>(rng: &mut R) -> Self;
     /// Generate a random number at range range.0..range.1
     fn rand_range<R: Rng>(rng: &mut R, range: (Self, Self)) -> Self;
@@ -45,12 +50,14 @@ pub trait UInt:
         BitsLE(self)
     }
 
-    /// `self % (2^bit_length)`
+    /// `self % (2^bit_length)`, i.e. the lower `bit_length` bits of `self`.
+    /// Defined for every `bit_length`: from `NUM_BITS` on, `self` is returned
+    /// unchanged.
     #[must_use]
     fn modulo_2_power(self, bit_length: usize) -> Self;
 
     /// generate arithmetic shares of `self`
-    fn arith_shares<R: Rng>(self, rng: &mut R) -> (Self, Self){
+    fn arith_shares<R: Rng>(self, rng: &mut R) -> (Self, Self) {
         let s0 = Self::rand(rng);
         let s1 = self.wrapping_sub(&s0);
         (s0, s1)
@@ -86,8 +93,8 @@ pub trait UInt:
         // we will need to keep the size of y the same no matter which interval our
         // input value lies in for security reasons.
 
-        let y = BitsLE(self & ((Self::one() << current_interval_size as usize) - Self::one()));
-        let which_interval = ((Self::one() << (current_interval_size as usize)) - Self::one())
+        let y = BitsLE(self & ((Self::one() << current_interval_size) - Self::one()));
+        let which_interval = ((Self::one() << current_interval_size) - Self::one())
             .not()
             .bitand(bound)
             .count_ones()
@@ -100,14 +107,14 @@ pub trait UInt:
     fn wsize(self) -> usize {
         Self::NUM_BITS - self.leading_zeros() as usize
     }
-    
+
     /// Cut `Self` to `T`. If `T` has fewer bits than `Self`, take the lower bits.
     #[inline]
-    fn as_uint<T: UInt>(self) -> T{
-        if T::NUM_BITS < Self::NUM_BITS{
+    fn as_uint<T: UInt>(self) -> T {
+        if T::NUM_BITS < Self::NUM_BITS {
             let t = self.modulo_2_power(T::NUM_BITS);
             T::from(t).unwrap()
-        }else{
+        } else {
             T::from(self).unwrap()
         }
     }
@@ -116,7 +123,6 @@ pub trait UInt:
 impl UInt for u16 {
     const NUM_BITS: usize = u16::BITS as usize;
 
-
     fn rand<R: Rng>(rng: &mut R) -> Self {
         rng.gen()
     }
@@ -134,10 +140,14 @@ impl UInt for u16 {
     }
 
     fn modulo_2_power(self, bit_length: usize) -> Self {
-        self & ((1 << bit_length) - 1)
+        match u32::try_from(bit_length)
+            .ok()
+            .and_then(|b| Self::checked_shl(1, b))
+        {
+            Some(power) => self & (power - 1),
+            None => self,
+        }
     }
-
-
 }
 
 impl UInt for u32 {
@@ -160,7 +170,13 @@ impl UInt for u32 {
     }
 
     fn modulo_2_power(self, bit_length: usize) -> Self {
-        self & ((1 << bit_length) - 1)
+        match u32::try_from(bit_length)
+            .ok()
+            .and_then(|b| Self::checked_shl(1, b))
+        {
+            Some(power) => self & (power - 1),
+            None => self,
+        }
     }
 }
 
@@ -184,7 +200,13 @@ impl UInt for u64 {
     }
 
     fn modulo_2_power(self, bit_length: usize) -> Self {
-        self & ((1 << bit_length) - 1)
+        match u32::try_from(bit_length)
+            .ok()
+            .and_then(|b| Self::checked_shl(1, b))
+        {
+            Some(power) => self & (power - 1),
+            None => self,
+        }
     }
 }
 
@@ -208,11 +230,17 @@ impl UInt for u8 {
     }
 
     fn modulo_2_power(self, bit_length: usize) -> Self {
-        self & ((1 << bit_length) - 1)
+        match u32::try_from(bit_length)
+            .ok()
+            .and_then(|b| Self::checked_shl(1, b))
+        {
+            Some(power) => self & (power - 1),
+            None => self,
+        }
     }
 }
 
-impl UInt for u128{
+impl UInt for u128 {
     const NUM_BITS: usize = u128::BITS as usize;
 
     fn rand<R: Rng>(rng: &mut R) -> Self {
@@ -232,6 +260,47 @@ impl UInt for u128{
     }
 
     fn modulo_2_power(self, bit_length: usize) -> Self {
-        self & ((1 << bit_length) - 1)
+        match u32::try_from(bit_length)
+            .ok()
+            .and_then(|b| Self::checked_shl(1, b))
+        {
+            Some(power) => self & (power - 1),
+            None => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UInt;
+
+    fn check_modulo_2_power_boundaries<T: UInt>() {
+        let max = T::max_value();
+        let pattern = T::from(0xa5u8).unwrap().rotate_right(1);
+        for x in [T::zero(), T::one(), max, pattern] {
+            assert_eq!(x.modulo_2_power(0), T::zero());
+            assert_eq!(x.modulo_2_power(1), x & T::one());
+            assert_eq!(x.modulo_2_power(T::NUM_BITS - 1), x & (max >> 1));
+            assert_eq!(x.modulo_2_power(T::NUM_BITS), x);
+            assert_eq!(x.modulo_2_power(T::NUM_BITS + 1), x);
+            assert_eq!(x.modulo_2_power(usize::MAX), x);
+        }
+    }
+
+    #[test]
+    fn test_modulo_2_power_boundaries() {
+        check_modulo_2_power_boundaries::<u8>();
+        check_modulo_2_power_boundaries::<u16>();
+        check_modulo_2_power_boundaries::<u32>();
+        check_modulo_2_power_boundaries::<u64>();
+        check_modulo_2_power_boundaries::<u128>();
+    }
+
+    #[test]
+    fn test_as_uint() {
+        assert_eq!(u128::MAX.as_uint::<u64>(), u64::MAX);
+        assert_eq!(0x1234u16.as_uint::<u8>(), 0x34);
+        assert_eq!(u64::MAX.as_uint::<u64>(), u64::MAX);
+        assert_eq!(u8::MAX.as_uint::<u128>(), 0xff);
     }
 }
@@ -1,3 +1,6 @@
+pub use crate::hook::{Hook, HookRecord, HookRegistry, Timeline};
+use crate::uint::UInt;
+use rayon::prelude::*;
 use std::{ops::Deref, sync::Arc};
 #[macro_export]
 macro_rules! const_assert {
@@ -13,19 +16,84 @@ macro_rules! const_assert {
     };
 }
 
+/// Slices shorter than this are not split by `par_zip_map`, the overhead of
+/// rayon would dominate.
+pub const PAR_MIN_LEN: usize = 1 << 12;
+
+#[inline]
+#[track_caller]
+fn assert_same_len(a: usize, b: usize) {
+    assert!(a == b, "length mismatch: {} != {}", a, b);
+}
+
+/// Element-wise operations on pairs of slices. All of them panic, with both
+/// lengths, if the slices have different lengths.
 pub trait SliceExt<T> {
+    /// `f` of each pair of elements.
     fn zip_map<V, F: Fn(&T, &T) -> V>(&self, other: &Self, f: F) -> Vec<V>;
+
+    /// Like `zip_map`, but write into `dest`, which has the same length.
+    fn zip_map_into<V, F: Fn(&T, &T) -> V>(&self, other: &Self, dest: &mut [V], f: F);
+
+    /// Like `zip_map`, but with rayon. Slices shorter than `PAR_MIN_LEN` are
+    /// mapped on a single thread.
+    fn par_zip_map<V: Send, F: Fn(&T, &T) -> V + Sync + Send>(&self, other: &Self, f: F) -> Vec<V>
+    where
+        T: Sync;
 }
 
 impl<T> SliceExt<T> for [T] {
     #[inline]
+    #[track_caller]
     fn zip_map<V, F: Fn(&T, &T) -> V>(&self, other: &Self, f: F) -> Vec<V> {
-        assert_eq!(self.len(), other.len());
+        assert_same_len(self.len(), other.len());
         self.iter()
             .zip(other.iter())
             .map(|(a, b)| f(a, b))
             .collect()
     }
+
+    #[inline]
+    #[track_caller]
+    fn zip_map_into<V, F: Fn(&T, &T) -> V>(&self, other: &Self, dest: &mut [V], f: F) {
+        assert_same_len(self.len(), other.len());
+        assert_same_len(self.len(), dest.len());
+        dest.iter_mut()
+            .zip(self.iter().zip(other.iter()))
+            .for_each(|(d, (a, b))| *d = f(a, b));
+    }
+
+    #[track_caller]
+    fn par_zip_map<V: Send, F: Fn(&T, &T) -> V + Sync + Send>(&self, other: &Self, f: F) -> Vec<V>
+    where
+        T: Sync,
+    {
+        assert_same_len(self.len(), other.len());
+        self.par_iter()
+            .zip(other.par_iter())
+            .with_min_len(PAR_MIN_LEN)
+            .map(|(a, b)| f(a, b))
+            .collect()
+    }
+}
+
+/// Element-wise wrapping sum of two slices of the same length.
+#[track_caller]
+pub fn batch_wrapping_add<T: UInt>(a: &[T], b: &[T]) -> Vec<T> {
+    a.zip_map(b, |a, b| a.wrapping_add(b))
+}
+
+/// Element-wise wrapping difference `a - b` of two slices of the same length.
+#[track_caller]
+pub fn batch_wrapping_sub<T: UInt>(a: &[T], b: &[T]) -> Vec<T> {
+    a.zip_map(b, |a, b| a.wrapping_sub(b))
+}
+
+/// Element-wise XOR of two slices of the same length, e.g. of the two shares
+/// of the seeds of the clients.
+#[track_caller]
+pub fn batch_xor<T: UInt>(a: &[T], b: &[T]) -> Vec<T> {
+    a.zip_map(b, |a, b| *a ^ *b)
 }
 
 pub struct IndexedArc<T> {
@@ -66,33 +134,6 @@ impl<T> Deref for IndexedArc<T> {
     }
 }
 
-/// `Hook` serves as a reminder to clean up unfinished tasks.
-/// If `Hook` is dropped but is not done, it will panic.
-pub struct Hook {
-    done: bool,
-}
-
-impl Hook {
-    #[must_use]
-    #[inline]
-    pub fn new() -> Self {
-        Hook { done: false }
-    }
-    #[inline]
-    pub fn done(mut self) {
-        self.done = true;
-    }
-}
-
-impl Drop for Hook {
-    #[inline]
-    fn drop(&mut self) {
-        if !self.done {
-            panic!("internal error: Hook dropped without being done");
-        }
-    }
-}
-
 #[inline]
 pub fn log_verify_status(num_verified: usize, num_total: usize, name: &str) {
     if num_verified == num_total {
@@ -107,16 +148,117 @@ pub fn log_verify_status(num_verified: usize, num_total: usize, name: &str) {
     }
 }
 
-pub fn bytes_to_seed_pairs(bytes: &[u8]) -> (u64, u64) {
-    // XXX:This is for a proof for concept, as the entropy is only 64 bits
-    let mut seed1 = [0u8; 8];
-    let mut seed2 = [0u8; 8];
-    seed1.copy_from_slice(&bytes[0..8]);
-    seed2.copy_from_slice(&bytes[8..16]);
-    (u64::from_le_bytes(seed1), u64::from_le_bytes(seed2))
+/// Items whose `rejected` flag is not set. Both have the same length.
+pub fn without_rejected<T>(
+    items: impl IntoIterator<Item = T>,
+    rejected: impl IntoIterator<Item = bool>,
+) -> Vec<T> {
+    items
+        .into_iter()
+        .zip(rejected)
+        .filter(|(_, rejected)| !rejected)
+        .map(|(item, _)| item)
+        .collect()
+}
+
+/// How to sum vectors of different lengths, e.g. the inputs of clients with
+/// different `gsize`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnequalLengths {
+    /// Shorter vectors are padded with zeros to the longest one.
+    #[default]
+    ZeroPad,
+    /// Only the prefix that all vectors have is summed.
+    Truncate,
+}
+
+/// Element-wise wrapping sum of vectors whose lengths may differ, see
+/// `UnequalLengths`.
+pub fn batch_sum_unequal<'a, T: UInt + 'a>(
+    vectors: impl IntoIterator<Item = &'a Vec<T>> + Clone,
+    policy: UnequalLengths,
+) -> Vec<T> {
+    let lengths = vectors.clone().into_iter().map(|v| v.len());
+    let len = match policy {
+        UnequalLengths::ZeroPad => lengths.max(),
+        UnequalLengths::Truncate => lengths.min(),
+    }
+    .unwrap_or(0);
+    vectors
+        .into_iter()
+        .fold(vec![T::zero(); len], |mut acc, v| {
+            acc.iter_mut()
+                .zip(v)
+                .for_each(|(a, b)| *a = a.wrapping_add(b));
+            acc
+        })
+}
+
+/// Element-wise wrapping sum of vectors of the same length.
+pub fn batch_sum<'a, T: UInt + 'a>(vectors: impl IntoIterator<Item = &'a Vec<T>>) -> Vec<T> {
+    let mut vectors = vectors.into_iter().peekable();
+    let len = vectors.peek().map_or(0, |v| v.len());
+    vectors.fold(vec![T::zero(); len], |mut acc, v| {
+        assert_eq!(v.len(), len);
+        acc.iter_mut()
+            .zip(v)
+            .for_each(|(a, b)| *a = a.wrapping_add(b));
+        acc
+    })
 }
 
-pub fn batch_xor(a: &[u64], b: &[u64]) -> Vec<u64> {
-    assert_eq!(a.len(), b.len());
-    a.iter().zip(b.iter()).map(|(a, b)| a ^ b).collect()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn rand_vec<T: UInt>(rng: &mut StdRng, len: usize) -> Vec<T> {
+        (0..len).map(|_| T::rand(rng)).collect()
+    }
+
+    fn check_against_naive<T: UInt>(rng: &mut StdRng, len: usize) {
+        let a = rand_vec::<T>(rng, len);
+        let b = rand_vec::<T>(rng, len);
+        let naive = |f: fn(T, T) -> T| -> Vec<T> { (0..len).map(|i| f(a[i], b[i])).collect() };
+
+        assert_eq!(batch_wrapping_add(&a, &b), naive(|x, y| x.wrapping_add(&y)));
+        assert_eq!(batch_wrapping_sub(&a, &b), naive(|x, y| x.wrapping_sub(&y)));
+        assert_eq!(batch_xor(&a, &b), naive(|x, y| x ^ y));
+
+        let mut dest = vec![T::zero(); len];
+        a.zip_map_into(&b, &mut dest, |x, y| x.wrapping_mul(y));
+        assert_eq!(dest, naive(|x, y| x.wrapping_mul(&y)));
+
+        assert_eq!(
+            a.par_zip_map(&b, |x, y| x.wrapping_add(y)),
+            batch_wrapping_add(&a, &b)
+        );
+    }
+
+    #[test]
+    fn test_against_naive() {
+        let mut rng = StdRng::seed_from_u64(12345);
+        for len in [0, 1, 7, PAR_MIN_LEN - 1, PAR_MIN_LEN, 3 * PAR_MIN_LEN + 5] {
+            check_against_naive::<u8>(&mut rng, len);
+            check_against_naive::<u32>(&mut rng, len);
+            check_against_naive::<u64>(&mut rng, len);
+            check_against_naive::<u128>(&mut rng, len);
+        }
+        for _ in 0..32 {
+            let len = rng.gen_range(0..4 * PAR_MIN_LEN);
+            check_against_naive::<u64>(&mut rng, len);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "length mismatch: 3 != 2")]
+    fn test_length_mismatch() {
+        batch_wrapping_add(&[1u64, 2, 3], &[1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "length mismatch: 2 != 3")]
+    fn test_dest_length_mismatch() {
+        [1u64, 2].zip_map_into(&[1, 2], &mut [0; 3], |a, b| a + b);
+    }
 }
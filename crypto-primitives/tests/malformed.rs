@@ -0,0 +1,90 @@
+//! Mutations of valid client messages must be rejected without panicking or
+//! allocating much more than their length, see `serialize::fuzz`.
+
+use bytes::Bytes;
+use crypto_primitives::{
+    bits::{batch_make_boolean_shares, ShareCommitment, ShareSalt},
+    cot::client::{num_additional_ot_needed, COTGen, DEFAULT_SEC_PARAM},
+    message::{
+        l2::{ClientL2MsgToAlice, ClientL2MsgToBob},
+        po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
+    },
+    square_corr::{batch_make_sqcorr_shares, CorrShareSeedToAlice, CorrShareSeedToBob},
+    uint::UInt,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use serialize::{
+    fuzz::{check_malformed, CountingAlloc},
+    Communicate,
+};
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+type I = u8;
+type C = u128;
+
+/// Random mutations per seed, on top of the exhaustive ones.
+const NUM_RANDOM: usize = 500;
+
+/// Valid messages of one client with `gsize` inputs, like `L2Client`.
+fn l2_msgs(gsize: usize, seed: u64) -> (ClientL2MsgToAlice, ClientL2MsgToBob<I, C>) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let input = (0..gsize).map(|_| I::rand(&mut rng)).collect::<Vec<_>>();
+    let (input_0, input_1) = batch_make_boolean_shares(&mut rng, input.iter().map(|x| x.bits_le()));
+    let salt = ShareSalt::sample(&mut rng);
+    let commitment = ShareCommitment::new(0, 0, input_0, salt, &input_1);
+    let delta = COTGen::sample_delta(&mut rng);
+    let num_additional = num_additional_ot_needed(gsize * I::NUM_BITS, DEFAULT_SEC_PARAM);
+    let (cot_s, cot_r) = COTGen::sample_cots(&mut rng, &input_1, delta, num_additional);
+    let (corr_0, corr_1, ..) = batch_make_sqcorr_shares(&mut rng, gsize * 2);
+    (
+        ClientL2MsgToAlice::new(gsize, input_0, commitment, cot_s, corr_0),
+        ClientL2MsgToBob::new(input_1, commitment, salt, cot_r, corr_1),
+    )
+}
+
+/// Seeds for each message type, from clients with no input and with a few.
+fn seeds<M: Communicate>(
+    part: impl Fn(ClientL2MsgToAlice, ClientL2MsgToBob<I, C>) -> M,
+) -> Vec<Bytes> {
+    [0, 1, 3]
+        .iter()
+        .map(|&gsize| {
+            let (to_alice, to_bob) = l2_msgs(gsize, 0x5eed + gsize as u64);
+            part(to_alice, to_bob).into_bytes_owned()
+        })
+        .collect()
+}
+
+#[test]
+fn test_po2_malformed() {
+    check_malformed::<ClientPo2MsgToAlice>(&seeds(|a, _| a.po2_msg), NUM_RANDOM, 1);
+    check_malformed::<ClientPo2MsgToBob<I>>(&seeds(|_, b| b.po2_msg), NUM_RANDOM, 2);
+}
+
+#[test]
+fn test_l2_malformed() {
+    check_malformed::<ClientL2MsgToAlice>(&seeds(|a, _| a), NUM_RANDOM, 3);
+    check_malformed::<ClientL2MsgToBob<I, C>>(&seeds(|_, b| b), NUM_RANDOM, 4);
+}
+
+#[test]
+fn test_cot_malformed() {
+    check_malformed::<crypto_primitives::cot::client::B2ACOTToAlice>(
+        &seeds(|a, _| a.po2_msg.cot),
+        NUM_RANDOM,
+        5,
+    );
+    check_malformed::<crypto_primitives::cot::client::B2ACOTToBob>(
+        &seeds(|_, b| b.po2_msg.cot),
+        NUM_RANDOM,
+        6,
+    );
+}
+
+#[test]
+fn test_sqcorr_malformed() {
+    check_malformed::<CorrShareSeedToAlice>(&seeds(|a, _| a.square_corr), NUM_RANDOM, 7);
+    check_malformed::<CorrShareSeedToBob<C>>(&seeds(|_, b| b.square_corr), NUM_RANDOM, 8);
+}
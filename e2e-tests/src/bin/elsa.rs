@@ -0,0 +1,80 @@
+use std::{path::PathBuf, process};
+
+use clap::{Arg, Command};
+use e2e_tests::demo::{self, DemoConfig};
+
+#[tokio::main]
+async fn main() {
+    let matches = Command::new("elsa")
+        .version("0.1")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("demo")
+                .about("run one round on a small dataset with both servers in this process, and verify what it wrote")
+                .arg(
+                    Arg::new("num_clients")
+                        .short('n')
+                        .long("num-clients")
+                        .takes_value(true)
+                        .default_value("200")
+                        .help("number of clients, at least 4"),
+                )
+                .arg(
+                    Arg::new("gsize")
+                        .short('g')
+                        .long("gsize")
+                        .takes_value(true)
+                        .default_value("16")
+                        .help("number of inputs"),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("seed of the dataset"),
+                )
+                .arg(
+                    Arg::new("out_dir")
+                        .short('o')
+                        .long("out-dir")
+                        .takes_value(true)
+                        .help("where to write the artifacts, a fresh temporary directory by default"),
+                ),
+        )
+        .get_matches();
+
+    let matches = matches.subcommand_matches("demo").unwrap();
+    let num_clients = matches.value_of_t_or_exit::<usize>("num_clients");
+    let gsize = matches.value_of_t_or_exit::<usize>("gsize");
+    let seed = matches.value_of_t_or_exit::<u64>("seed");
+    if num_clients < 4 {
+        eprintln!("the demo needs at least 4 clients");
+        process::exit(2);
+    }
+    let dir = matches.value_of("out_dir").map_or_else(
+        || std::env::temp_dir().join(format!("elsa-demo-{}", process::id())),
+        PathBuf::from,
+    );
+
+    let config = DemoConfig::new(num_clients, gsize, seed);
+    println!(
+        "running {} clients with {} inputs each, misbehaving: {:?}",
+        num_clients,
+        gsize,
+        config.misbehaving()
+    );
+    if let Err(e) = demo::run(&config, &dir).await {
+        eprintln!("cannot write to {}: {}", dir.display(), e);
+        process::exit(1);
+    }
+    println!("artifacts in {}", dir.display());
+
+    let checks = demo::verify(&dir);
+    for check in &checks {
+        println!("{}", check);
+    }
+    if !checks.iter().all(|c| c.passed()) {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,152 @@
+//! Bits protocol: clients with single-bit inputs, submitted with
+//! `elsa-client`, to server-po2 with `--protocol bits`, aggregated by XOR and
+//! by count.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use bin_utils::client::client_round_bits;
+use bridge::{
+    client_server::{ClientSession, PartitionPolicy},
+    mpc_conn::MpcConnection,
+    tcp_bridge::{ClientID, ClientToken},
+};
+use crypto_primitives::{bits::PackedBits, message::bits::BitAggregation};
+use elsa_client::{ClientConfig, ElsaClient, InputRing, Variant};
+use rand::{rngs::StdRng, SeedableRng};
+use server_po2::bits::{self, BitsData, BitsOutput, BitsShare};
+use tokio::net::TcpListener;
+
+use crate::harness::run_round_with_clients;
+
+const NUM_CLIENTS: usize = 8;
+const NUM_BITS: usize = 100;
+
+async fn run_server(
+    is_alice: bool,
+    aggregation: BitAggregation,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+) -> BitsOutput {
+    let session = ClientSession::accept(
+        is_alice,
+        PartitionPolicy::default(),
+        listener,
+        NUM_CLIENTS,
+        ClientToken::default(),
+    )
+    .await;
+    let data = BitsData::fetch_round(&session, 0).await;
+    bits::run_round(0, NUM_BITS, is_alice, aggregation, &data, &peer).await
+}
+
+/// Submit the bits of every client, with client 3 sending one bit less if
+/// `short_client`.
+async fn submit_bits(
+    aggregation: BitAggregation,
+    short_client: bool,
+    addr_alice: SocketAddr,
+    addr_bob: SocketAddr,
+) {
+    let submitted = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let mut input = client_round_bits(uid as u64, 0, NUM_BITS)
+                .into_iter()
+                .map(u8::from)
+                .collect::<Vec<_>>();
+            if short_client && uid == 3 {
+                input.pop();
+            }
+            let client = ElsaClient::new(ClientConfig {
+                uid: ClientID::new(uid as u64),
+                self_check: true,
+                ..ClientConfig::new(
+                    Variant::Bits(aggregation),
+                    InputRing::U8,
+                    input.len(),
+                    addr_alice.to_string(),
+                    addr_bob.to_string(),
+                )
+            });
+            let prepared = client
+                .prepare_with_rng(&input, &mut StdRng::seed_from_u64(uid as u64))
+                .unwrap();
+            tokio::spawn(client.submit(prepared))
+        })
+        .collect::<Vec<_>>();
+    for submitted in submitted {
+        submitted.await.unwrap().unwrap();
+    }
+}
+
+/// Number of clients `uids` with each bit set.
+fn plaintext_counts(uids: impl IntoIterator<Item = usize>) -> Vec<u32> {
+    uids.into_iter().fold(vec![0; NUM_BITS], |mut counts, uid| {
+        for (count, bit) in counts
+            .iter_mut()
+            .zip(client_round_bits(uid as u64, 0, NUM_BITS))
+        {
+            *count += bit as u32;
+        }
+        counts
+    })
+}
+
+async fn run_bits_round(aggregation: BitAggregation, short_client: bool) -> [BitsOutput; 2] {
+    let [alice, bob] = run_round_with_clients(
+        |is_alice, listener, peer| run_server(is_alice, aggregation, listener, peer),
+        |addr_alice, addr_bob| submit_bits(aggregation, short_client, addr_alice, addr_bob),
+    )
+    .await;
+    [alice.output, bob.output]
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_bits_sum() {
+    let [alice, bob] = run_bits_round(BitAggregation::Sum, false).await;
+    assert!(alice.rejected_clients.is_empty());
+    assert!(bob.rejected_clients.is_empty());
+    let (BitsShare::Sum(alice), BitsShare::Sum(bob)) = (alice.share, bob.share) else {
+        panic!("not the shares of a sum");
+    };
+    let counts = alice
+        .iter()
+        .zip(&bob)
+        .map(|(a, b)| a.wrapping_add(*b))
+        .collect::<Vec<_>>();
+    assert_eq!(counts, plaintext_counts(0..NUM_CLIENTS));
+    // some bits are set by some clients only
+    assert!(counts.iter().any(|c| 0 < *c && *c < NUM_CLIENTS as u32));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_bits_xor() {
+    let [alice, bob] = run_bits_round(BitAggregation::Xor, false).await;
+    let (BitsShare::Xor(alice), BitsShare::Xor(bob)) = (alice.share, bob.share) else {
+        panic!("not the shares of a XOR");
+    };
+    let parity = plaintext_counts(0..NUM_CLIENTS)
+        .iter()
+        .map(|c| c % 2 == 1)
+        .collect::<PackedBits>();
+    assert_eq!(&alice ^ &bob, parity);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_bits_sum_rejects_wrong_length() {
+    let [alice, bob] = run_bits_round(BitAggregation::Sum, true).await;
+    for output in [&alice, &bob] {
+        assert_eq!(output.rejected_clients, vec![ClientID::new(3)]);
+    }
+    let (BitsShare::Sum(alice), BitsShare::Sum(bob)) = (alice.share, bob.share) else {
+        panic!("not the shares of a sum");
+    };
+    let counts = alice
+        .iter()
+        .zip(&bob)
+        .map(|(a, b)| a.wrapping_add(*b))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        counts,
+        plaintext_counts((0..NUM_CLIENTS).filter(|uid| *uid != 3))
+    );
+}
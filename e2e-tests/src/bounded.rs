@@ -0,0 +1,176 @@
+//! Range check of the inputs against a public bound: clients prepared with
+//! `elsa-client`, and both servers running the check over a real MPC
+//! connection. No server pipeline runs it yet, so the client messages are
+//! serialized and deserialized instead of sent over TCP.
+
+use bridge::{id_tracker::IdGen, mpc_conn::MpcConnection, tcp_bridge::ClientID};
+use crypto_primitives::{
+    bounded::{BoundError, InputBound},
+    cot::client::DEFAULT_SEC_PARAM,
+    message::bounded::{ClientBoundedMsgToAlice, ClientBoundedMsgToBob},
+};
+use elsa_client::bounded::BoundedClient;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serialize::Communicate;
+use server_protocol::{bounded_check_alice, bounded_check_bob, sample_chi_seed, ClientFailure};
+
+use crate::{
+    fixture::{GSIZE, SESSION},
+    harness::{bind_localhost, serialize, I},
+};
+
+const BOUND: I = 64;
+
+/// Messages of client `uid` with inputs below `BOUND`. If `out_of_range`,
+/// input 5 is moved out of range, but keeps the encoding of its value in
+/// range.
+fn client_msgs(
+    uid: usize,
+    out_of_range: bool,
+) -> (ClientBoundedMsgToAlice, ClientBoundedMsgToBob<I>) {
+    let bound = InputBound::new(BOUND).unwrap();
+    let mut rng = StdRng::seed_from_u64(uid as u64);
+    let mut input = (0..GSIZE)
+        .map(|_| rng.gen_range(0..BOUND))
+        .collect::<Vec<_>>();
+    let encodings = input
+        .iter()
+        .map(|&x| bound.encode(x).unwrap())
+        .collect::<Vec<_>>();
+    if out_of_range {
+        input[5] += BOUND;
+    }
+    let client = BoundedClient::with_encodings(
+        ClientID::new(uid as u64),
+        SESSION,
+        &input,
+        &bound,
+        &encodings,
+        &mut rng,
+        DEFAULT_SEC_PARAM,
+    );
+    let mut to_alice =
+        ClientBoundedMsgToAlice::from_bytes_owned(serialize(&client.prepared_message_0)).unwrap();
+    let mut to_bob =
+        ClientBoundedMsgToBob::from_bytes_owned(serialize(&client.prepared_message_1)).unwrap();
+    to_alice.bind_to(uid as u64);
+    to_bob.bind_to(uid as u64);
+    (to_alice, to_bob)
+}
+
+/// Run the check on the messages of each client on both servers, and return
+/// the results of Alice and of Bob.
+async fn check_clients(
+    msgs: Vec<(ClientBoundedMsgToAlice, ClientBoundedMsgToBob<I>)>,
+) -> Vec<(Result<bool, ClientFailure>, Result<bool, ClientFailure>)> {
+    let bound = InputBound::new(BOUND).unwrap();
+    let (listener, addr) = bind_localhost().await;
+    let (peer_alice, peer_bob) = tokio::join!(
+        MpcConnection::new_as_alice_with_listener(listener, 2),
+        MpcConnection::new_as_bob(addr, 2)
+    );
+    let (mut ids_alice, mut ids_bob) = (IdGen::new(), IdGen::new());
+    // after the client messages are received
+    let (chi_seed_alice, chi_seed_bob) = tokio::join!(
+        sample_chi_seed(ids_alice.next_exchange_id(), &peer_alice),
+        sample_chi_seed(ids_bob.next_exchange_id(), &peer_bob)
+    );
+
+    let mut results = Vec::new();
+    for (msg_alice, msg_bob) in msgs {
+        let alice_ids = (
+            ids_alice.next_send_id(),
+            ids_alice.next_recv_id(),
+            ids_alice.next_exchange_id(),
+        );
+        let bob_ids = (
+            ids_bob.next_recv_id(),
+            ids_bob.next_send_id(),
+            ids_bob.next_exchange_id(),
+        );
+        results.push(tokio::join!(
+            bounded_check_alice(
+                alice_ids,
+                &bound,
+                &msg_alice,
+                DEFAULT_SEC_PARAM,
+                chi_seed_alice,
+                &peer_alice
+            ),
+            bounded_check_bob(
+                bob_ids,
+                &bound,
+                &msg_bob,
+                DEFAULT_SEC_PARAM,
+                chi_seed_bob,
+                &peer_bob
+            )
+        ));
+    }
+    results
+}
+
+#[tokio::test]
+async fn accepts_inputs_in_range_only() {
+    let out_of_range = [false, false, true, false];
+    let msgs = out_of_range
+        .iter()
+        .enumerate()
+        .map(|(uid, &out_of_range)| client_msgs(uid, out_of_range))
+        .collect();
+    let results = check_clients(msgs).await;
+    for (uid, (accept_alice, accept_bob)) in results.into_iter().enumerate() {
+        assert_eq!(accept_alice.unwrap(), !out_of_range[uid], "client {}", uid);
+        assert_eq!(accept_bob.unwrap(), !out_of_range[uid], "client {}", uid);
+    }
+}
+
+#[tokio::test]
+async fn fails_clients_with_inconsistent_cots() {
+    const BAD_UID: usize = 1;
+
+    let msgs = (0..3)
+        .map(|uid| {
+            let (msg_alice, mut msg_bob) = client_msgs(uid, false);
+            if uid == BAD_UID {
+                // a COT of the first gate that does not match the one of
+                // Alice for either choice
+                let ts = &mut msg_bob.check.and_ts;
+                ts[0] = ts[1];
+            }
+            (msg_alice, msg_bob)
+        })
+        .collect();
+    let results = check_clients(msgs).await;
+    for (uid, (accept_alice, accept_bob)) in results.into_iter().enumerate() {
+        if uid == BAD_UID {
+            assert!(matches!(accept_alice, Err(ClientFailure::OtVerify)));
+            assert!(matches!(accept_bob, Err(ClientFailure::OtVerify)));
+        } else {
+            assert!(accept_alice.unwrap(), "client {}", uid);
+            assert!(accept_bob.unwrap(), "client {}", uid);
+        }
+    }
+}
+
+#[test]
+fn refuses_to_encode_inputs_out_of_range() {
+    let bound = InputBound::new(BOUND).unwrap();
+    let mut rng = StdRng::seed_from_u64(0);
+    let input = [3, BOUND + 1, 7];
+    let client = BoundedClient::prepare_bounded(
+        ClientID::new(0),
+        SESSION,
+        &input,
+        &bound,
+        &mut rng,
+        DEFAULT_SEC_PARAM,
+    );
+    assert_eq!(
+        client.err(),
+        Some(BoundError::OutOfRange {
+            input: (BOUND + 1) as u128,
+            bound: BOUND as u128
+        })
+    );
+}
@@ -0,0 +1,565 @@
+//! `elsa demo`: one round of the MP protocol on a small deterministic
+//! dataset, followed by a verification pass over everything the round wrote.
+//!
+//! The dataset is derived from a seed, so the verification pass recomputes
+//! the ground truth instead of trusting a file. A few clients misbehave on
+//! purpose, each in a way that a different check of the servers catches:
+//! * they send different share commitments to the two servers, or
+//! * they send fewer OTs to their OT receiver than the round needs.
+//!
+//! Artifacts in the output directory:
+//! * `demo.json`: the [`DemoConfig`], enough to regenerate the dataset.
+//! * `alice/` and `bob/`, one per server:
+//!   * `clients.dump`: the client messages as received, see
+//!     `bridge::client_dump`.
+//!   * `aggregate.bin`: the share of the aggregate, only if it is released.
+//!   * `report.json`: the [`ServerReport`] of the round.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bin_utils::signing::ClientKeys;
+use bridge::{
+    client_dump::ClientRecording,
+    client_server::PartitionPolicy,
+    mpc_conn::MpcConnection,
+    tcp_bridge::{ClientID, ClientToken},
+};
+use bytes::Bytes;
+use crypto_primitives::{cot::client::DEFAULT_SEC_PARAM, uint::UInt, utils::batch_sum};
+use elsa_client::mp::Client;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serialize::Communicate;
+use server_mp::{
+    client_msg::ClientData,
+    pipeline::{self, PipelineOutput},
+    release::{self, Release},
+    utils::IdPool,
+    webhook::{RoundSummary, Withheld},
+};
+use sha2::Sha256;
+use tokio::net::TcpListener;
+
+use crate::harness::{reconstruct, run_round, serialize, A, C, I};
+
+const SESSION: u64 = 1;
+const CONFIG_FILE: &str = "demo.json";
+const DUMP_FILE: &str = "clients.dump";
+const AGGREGATE_FILE: &str = "aggregate.bin";
+const REPORT_FILE: &str = "report.json";
+
+/// Dataset of a demo round.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DemoConfig {
+    pub num_clients: usize,
+    pub gsize: usize,
+    /// Seed of the inputs and the randomness of all clients.
+    pub seed: u64,
+    /// Minimum number of included clients to release the aggregate.
+    pub min_included: usize,
+    /// Clients that send different share commitments to the two servers.
+    pub commitment_mismatch: Vec<u64>,
+    /// Clients that send too few OTs to their OT receiver.
+    pub short_ots: Vec<u64>,
+}
+
+impl DemoConfig {
+    /// A round of `num_clients` clients with `gsize` inputs each, of which
+    /// two misbehave, one in each way. Needs at least 4 clients.
+    pub fn new(num_clients: usize, gsize: usize, seed: u64) -> Self {
+        assert!(num_clients >= 4, "the demo needs at least 4 clients");
+        DemoConfig {
+            num_clients,
+            gsize,
+            seed,
+            min_included: num_clients / 2,
+            commitment_mismatch: vec![1],
+            short_ots: vec![num_clients as u64 - 2],
+        }
+    }
+
+    /// Misbehaving clients, in uid order.
+    pub fn misbehaving(&self) -> Vec<u64> {
+        let mut uids = [&self.commitment_mismatch[..], &self.short_ots[..]].concat();
+        uids.sort_unstable();
+        uids.dedup();
+        uids
+    }
+
+    /// Input of every client, and the rng it uses for its messages, in uid
+    /// order.
+    fn clients(&self) -> impl Iterator<Item = (Vec<I>, StdRng)> + '_ {
+        let mut master = StdRng::seed_from_u64(self.seed);
+        (0..self.num_clients).map(move |_| {
+            let mut rng = StdRng::from_rng(&mut master).unwrap();
+            let input = (0..self.gsize).map(|_| I::rand(&mut rng)).collect();
+            (input, rng)
+        })
+    }
+
+    /// Sum of the inputs of the clients that behave, in ring `A`.
+    pub fn ground_truth(&self) -> Vec<A> {
+        let misbehaving = self.misbehaving();
+        let inputs = self
+            .clients()
+            .enumerate()
+            .filter(|(uid, _)| misbehaving.binary_search(&(*uid as u64)).is_err())
+            .map(|(_, (input, _))| input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        batch_sum(&inputs)
+    }
+
+    /// Message of each client to its OT sender and to its OT receiver.
+    fn client_msgs(&self) -> Vec<(Bytes, Bytes)> {
+        self.clients()
+            .enumerate()
+            .map(|(uid, (input, mut rng))| {
+                let mut client = Client::<I, C, Sha256>::prepare_message::<A, _, _>(
+                    ClientID::new(uid as u64),
+                    SESSION,
+                    &input,
+                    &mut rng,
+                    DEFAULT_SEC_PARAM,
+                    Sha256::default,
+                );
+                let po2_msg_bob = &mut client.msg_bob.0 .0.po2_msg;
+                if self.commitment_mismatch.contains(&(uid as u64)) {
+                    po2_msg_bob.commitment.0[0] ^= 1;
+                }
+                if self.short_ots.contains(&(uid as u64)) {
+                    po2_msg_bob.cot.truncate(po2_msg_bob.cot.ts().len() - 1);
+                }
+                (serialize(&client.msg_alice), serialize(&client.msg_bob))
+            })
+            .collect()
+    }
+}
+
+/// What one server reports about its round.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerReport {
+    pub summary: RoundSummary,
+    /// Clients that failed on either server, in uid order.
+    pub failed_clients: Vec<u64>,
+    pub mpc_bytes_sent: usize,
+    pub mpc_bytes_received: usize,
+}
+
+fn server_dir(dir: &Path, is_alice: bool) -> PathBuf {
+    dir.join(if is_alice { "alice" } else { "bob" })
+}
+
+fn io_error(e: impl Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+async fn run_server(
+    is_alice: bool,
+    config: Arc<DemoConfig>,
+    dump_path: String,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+) -> (PipelineOutput<A>, Release<A>) {
+    let data = ClientData::<I, C, Sha256>::fetch_with_listener(
+        is_alice,
+        PartitionPolicy::default(),
+        listener,
+        config.num_clients,
+        ClientToken::default(),
+        config.gsize,
+        None,
+        Sha256::default,
+        &ClientKeys::default(),
+        Some(dump_path),
+    )
+    .await
+    .with_session(SESSION);
+    let output = pipeline::run::<I, A, C, _, _>(
+        config.gsize,
+        DEFAULT_SEC_PARAM,
+        is_alice,
+        &data,
+        &peer,
+        Sha256::default,
+        false,
+    )
+    .await;
+    let ids = IdPool::build(data.num_clients_as_alice(), data.num_clients_as_bob());
+    let release = release::release(
+        &output,
+        None,
+        config.min_included,
+        ids.exchange_release,
+        &peer,
+    )
+    .await
+    .expect("cannot agree on the release of the aggregate");
+    (output, release)
+}
+
+/// Run the round of `config` with both servers in this process, and write
+/// the artifacts to `dir`.
+pub async fn run(config: &DemoConfig, dir: &Path) -> io::Result<()> {
+    for is_alice in [true, false] {
+        fs::create_dir_all(server_dir(dir, is_alice))?;
+    }
+    fs::write(
+        dir.join(CONFIG_FILE),
+        serde_json::to_vec_pretty(config).map_err(io_error)?,
+    )?;
+
+    let config = Arc::new(config.clone());
+    let runs = run_round(
+        &PartitionPolicy::default(),
+        config.client_msgs(),
+        |is_alice, listener, peer| {
+            let dump_path = server_dir(dir, is_alice).join(DUMP_FILE);
+            run_server(
+                is_alice,
+                config.clone(),
+                dump_path.to_string_lossy().into_owned(),
+                listener,
+                peer,
+            )
+        },
+    )
+    .await;
+
+    // the counters are read after both servers are done, so every message is
+    // counted on both sides
+    for (run, is_alice) in runs.iter().zip([true, false]) {
+        let (output, release) = &run.output;
+        let dir = server_dir(dir, is_alice);
+        let withheld = match release {
+            Release::Released(aggregate) => {
+                fs::write(dir.join(AGGREGATE_FILE), serialize(aggregate))?;
+                None
+            },
+            &Release::Withheld {
+                num_included,
+                min_included,
+            } => Some(Withheld::BelowThreshold {
+                num_included,
+                min_included,
+            }),
+        };
+        let report = ServerReport {
+            summary: RoundSummary {
+                num_clients: config.num_clients,
+                num_accepted: output.num_included(),
+                num_failed: output.failed_clients.len(),
+                b2a_time: output.b2a_time,
+                corr_verify_time: output.corr_verify_time,
+                a2s_time: output.a2s_time,
+                hash_verify_time: output.hash_verify_time,
+                withheld,
+                simulation_helpers_used: bin_utils::simulation::helper_constructed(),
+            },
+            failed_clients: output.failed_clients.iter().map(|uid| uid.id).collect(),
+            mpc_bytes_sent: run.peer.num_bytes_sent(),
+            mpc_bytes_received: run.peer.num_bytes_received(),
+        };
+        fs::write(
+            dir.join(REPORT_FILE),
+            serde_json::to_vec_pretty(&report).map_err(io_error)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// One property of the artifacts, and whether it holds.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub result: Result<(), String>,
+}
+
+impl Check {
+    fn new(name: &'static str, result: Result<(), String>) -> Self {
+        Check { name, result }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.result {
+            Ok(()) => write!(f, "[pass] {}", self.name),
+            Err(e) => write!(f, "[FAIL] {}: {}", self.name, e),
+        }
+    }
+}
+
+/// Fail with `msg` unless `cond` holds.
+fn ensure(cond: bool, msg: impl FnOnce() -> String) -> Result<(), String> {
+    if cond {
+        Ok(())
+    } else {
+        Err(msg())
+    }
+}
+
+/// Artifacts of one server.
+struct ServerArtifacts {
+    name: &'static str,
+    is_alice: bool,
+    report: ServerReport,
+    aggregate: Option<Vec<A>>,
+    dump_path: PathBuf,
+}
+
+impl ServerArtifacts {
+    fn load(dir: &Path, is_alice: bool) -> Result<Self, String> {
+        let name = if is_alice { "alice" } else { "bob" };
+        let dir = server_dir(dir, is_alice);
+        let report = fs::read(dir.join(REPORT_FILE)).map_err(|e| format!("{}: {}", name, e))?;
+        let report = serde_json::from_slice(&report).map_err(|e| format!("{}: {}", name, e))?;
+        let aggregate = match fs::read(dir.join(AGGREGATE_FILE)) {
+            Ok(bytes) => Some(
+                Vec::<A>::from_bytes_owned(bytes.into()).map_err(|e| format!("{}: {}", name, e))?,
+            ),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("{}: {}", name, e)),
+        };
+        Ok(ServerArtifacts {
+            name,
+            is_alice,
+            report,
+            aggregate,
+            dump_path: dir.join(DUMP_FILE),
+        })
+    }
+}
+
+/// Run `check` on both servers, failing with the first error.
+fn for_both(
+    servers: &[ServerArtifacts; 2],
+    check: impl Fn(&ServerArtifacts) -> Result<(), String>,
+) -> Result<(), String> {
+    servers
+        .iter()
+        .try_for_each(|s| check(s).map_err(|e| format!("{}: {}", s.name, e)))
+}
+
+fn check_dump(config: &DemoConfig, server: &ServerArtifacts) -> Result<(), String> {
+    let recording = ClientRecording::load(&server.dump_path).map_err(|e| e.to_string())?;
+    let mut uids = recording
+        .alice
+        .iter()
+        .chain(&recording.bob)
+        .map(|(uid, _)| uid.id)
+        .collect::<Vec<_>>();
+    uids.sort_unstable();
+    ensure(
+        uids == (0..config.num_clients as u64).collect::<Vec<_>>(),
+        || format!("{} of {} clients recorded", uids.len(), config.num_clients),
+    )?;
+    // the servers accept every message they fetch, so the recorded messages
+    // must replay without a panic
+    let data = panic::catch_unwind(AssertUnwindSafe(|| {
+        ClientData::<I, C, Sha256>::from_recording(
+            server.is_alice,
+            PartitionPolicy::default(),
+            recording,
+            config.gsize,
+            None,
+            Sha256::default,
+            &ClientKeys::default(),
+        )
+    }))
+    .map_err(|_| "recorded messages do not replay".to_string())?;
+    ensure(data.num_clients() == config.num_clients, || {
+        format!("{} clients replayed", data.num_clients())
+    })
+}
+
+/// Check every property of the artifacts in `dir`, written by [`run`].
+pub fn verify(dir: &Path) -> Vec<Check> {
+    let loaded = fs::read(dir.join(CONFIG_FILE))
+        .map_err(|e| format!("{}: {}", CONFIG_FILE, e))
+        .and_then(|c| {
+            serde_json::from_slice::<DemoConfig>(&c).map_err(|e| format!("{}: {}", CONFIG_FILE, e))
+        })
+        .and_then(|config| {
+            let alice = ServerArtifacts::load(dir, true)?;
+            let bob = ServerArtifacts::load(dir, false)?;
+            Ok((config, [alice, bob]))
+        });
+    let (config, servers) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => return vec![Check::new("artifacts are readable", Err(e))],
+    };
+    let [alice, bob] = &servers;
+
+    let mut checks = vec![Check::new("artifacts are readable", Ok(()))];
+    checks.push(Check::new(
+        "servers agree on the failed clients",
+        ensure(
+            alice.report.failed_clients == bob.report.failed_clients,
+            || {
+                format!(
+                    "alice: {:?}, bob: {:?}",
+                    alice.report.failed_clients, bob.report.failed_clients
+                )
+            },
+        ),
+    ));
+    checks.push(Check::new(
+        "exactly the misbehaving clients failed",
+        for_both(&servers, |s| {
+            ensure(s.report.failed_clients == config.misbehaving(), || {
+                format!(
+                    "failed {:?}, misbehaving {:?}",
+                    s.report.failed_clients,
+                    config.misbehaving()
+                )
+            })
+        }),
+    ));
+    checks.push(Check::new(
+        "summary counts add up",
+        for_both(&servers, |s| {
+            let summary = &s.report.summary;
+            ensure(summary.num_clients == config.num_clients, || {
+                format!("{} clients in summary", summary.num_clients)
+            })?;
+            ensure(
+                summary.num_accepted + summary.num_failed == summary.num_clients,
+                || {
+                    format!(
+                        "{} accepted + {} failed != {} clients",
+                        summary.num_accepted, summary.num_failed, summary.num_clients
+                    )
+                },
+            )?;
+            ensure(summary.num_failed == s.report.failed_clients.len(), || {
+                format!(
+                    "{} failed in summary, {} listed",
+                    summary.num_failed,
+                    s.report.failed_clients.len()
+                )
+            })
+        }),
+    ));
+    checks.push(Check::new(
+        "aggregate is released by both servers",
+        for_both(&servers, |s| {
+            ensure(s.report.summary.withheld.is_none(), || {
+                format!("withheld: {:?}", s.report.summary.withheld)
+            })?;
+            ensure(s.aggregate.is_some(), || format!("no {}", AGGREGATE_FILE))
+        }),
+    ));
+    checks.push(Check::new(
+        "aggregate equals the ground truth",
+        match (&alice.aggregate, &bob.aggregate) {
+            (Some(share_alice), Some(share_bob)) => {
+                let truth = config.ground_truth();
+                ensure(share_alice.len() == truth.len(), || {
+                    format!("{} shares for {} inputs", share_alice.len(), truth.len())
+                })
+                .and_then(|_| {
+                    ensure(share_bob.len() == truth.len(), || {
+                        format!("{} shares for {} inputs", share_bob.len(), truth.len())
+                    })
+                })
+                .and_then(|_| {
+                    let aggregate = reconstruct(share_alice, share_bob);
+                    let num_wrong = aggregate.iter().zip(&truth).filter(|(a, t)| a != t).count();
+                    ensure(num_wrong == 0, || {
+                        format!("{} of {} entries differ", num_wrong, truth.len())
+                    })
+                })
+            },
+            _ => Err("aggregate is not released".to_string()),
+        },
+    ));
+    checks.push(Check::new(
+        "phase times are positive",
+        for_both(&servers, |s| {
+            let summary = &s.report.summary;
+            [
+                ("OT + B2A", summary.b2a_time),
+                ("correlation verify", summary.corr_verify_time),
+                ("A2S", summary.a2s_time),
+                ("hash verify", summary.hash_verify_time),
+            ]
+            .into_iter()
+            .try_for_each(|(phase, time)| ensure(time > 0., || format!("{} took {}", phase, time)))
+        }),
+    ));
+    checks.push(Check::new(
+        "MPC traffic is symmetric",
+        ensure(
+            alice.report.mpc_bytes_sent == bob.report.mpc_bytes_received
+                && bob.report.mpc_bytes_sent == alice.report.mpc_bytes_received
+                && alice.report.mpc_bytes_sent > 0
+                && bob.report.mpc_bytes_sent > 0,
+            || {
+                format!(
+                    "alice sent {}, bob received {}; bob sent {}, alice received {}",
+                    alice.report.mpc_bytes_sent,
+                    bob.report.mpc_bytes_received,
+                    bob.report.mpc_bytes_sent,
+                    alice.report.mpc_bytes_received
+                )
+            },
+        ),
+    ));
+    checks.push(Check::new(
+        "client dumps replay to every client",
+        for_both(&servers, |s| check_dump(&config, s)),
+    ));
+    checks.push(Check::new(
+        "no simulation helper was used",
+        for_both(&servers, |s| {
+            ensure(!s.report.summary.simulation_helpers_used, || {
+                "simulation helpers were used".to_string()
+            })
+        }),
+    ));
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{run, server_dir, verify, DemoConfig, AGGREGATE_FILE};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_demo() {
+        let dir = std::env::temp_dir().join(format!("elsa-demo-test-{}", std::process::id()));
+        let config = DemoConfig::new(12, 8, 3);
+        run(&config, &dir).await.unwrap();
+
+        let checks = verify(&dir);
+        for check in &checks {
+            assert!(check.passed(), "{}", check);
+        }
+        assert_eq!(checks.len(), 10);
+
+        // a share that does not add up to the ground truth is caught
+        let path = server_dir(&dir, true).join(AGGREGATE_FILE);
+        let mut aggregate = fs::read(&path).unwrap();
+        *aggregate.last_mut().unwrap() ^= 1;
+        fs::write(&path, aggregate).unwrap();
+        let failed = verify(&dir)
+            .into_iter()
+            .filter(|c| !c.passed())
+            .map(|c| c.name)
+            .collect::<Vec<_>>();
+        assert_eq!(failed, ["aggregate equals the ground truth"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
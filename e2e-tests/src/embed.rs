@@ -0,0 +1,107 @@
+//! The library client of `elsa-client`, embedded like in an application,
+//! against in-process servers.
+
+use std::net::SocketAddr;
+
+use bridge::{client_server::PartitionPolicy, tcp_bridge::ClientID};
+use crypto_primitives::{message::size::Protocol, utils::batch_sum};
+use elsa_client::{ClientConfig, ElsaClient, InputRing, Variant};
+use server_po2::client_msg::MsgFormat;
+
+use crate::{
+    fixture::{assert_client_msg_sizes, client_input, plaintext_sum, GSIZE, NUM_CLIENTS, SESSION},
+    harness::{reconstruct, run_round_with_clients},
+    mp, po2,
+};
+
+/// Submit the input of every client with fresh randomness, so that the COT
+/// seeds differ from the other rounds, and check the receipts.
+async fn submit_all(
+    variant: Variant,
+    protocol: Protocol,
+    policy: &PartitionPolicy,
+    addr_alice: SocketAddr,
+    addr_bob: SocketAddr,
+) {
+    let mut msgs = Vec::with_capacity(NUM_CLIENTS);
+    let submitted = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let client = ElsaClient::new(ClientConfig {
+                uid: ClientID::new(uid as u64),
+                session: SESSION,
+                partition: policy.clone(),
+                ..ClientConfig::new(
+                    variant,
+                    InputRing::U8,
+                    GSIZE,
+                    addr_alice.to_string(),
+                    addr_bob.to_string(),
+                )
+            });
+            let (input, _) = client_input(uid, protocol);
+            let prepared = client.prepare(&input).unwrap();
+            msgs.push((
+                prepared.to_ot_sender.clone(),
+                prepared.to_ot_receiver.clone(),
+            ));
+            tokio::spawn(client.submit(prepared))
+        })
+        .collect::<Vec<_>>();
+    assert_client_msg_sizes(&msgs, protocol);
+
+    for (uid, submitted) in submitted.into_iter().enumerate() {
+        let receipt = submitted.await.unwrap().unwrap();
+        assert_eq!(receipt.uid, ClientID::new(uid as u64));
+        assert_eq!(receipt.bytes_to_ot_sender, msgs[uid].0.len());
+        assert_eq!(receipt.bytes_to_ot_receiver, msgs[uid].1.len());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_embedded_po2_round() {
+    let policy = PartitionPolicy::default();
+    let runs = run_round_with_clients(
+        |is_alice, listener, peer| {
+            po2::run_server(
+                is_alice,
+                policy.clone(),
+                MsgFormat::default(),
+                listener,
+                peer,
+            )
+        },
+        |addr_alice, addr_bob| {
+            submit_all(Variant::Po2, Protocol::Po2, &policy, addr_alice, addr_bob)
+        },
+    )
+    .await;
+
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(
+            &batch_sum(&alice.output.arith_shares),
+            &batch_sum(&bob.output.arith_shares)
+        ),
+        plaintext_sum()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_embedded_mp_round() {
+    // the clients must follow the partition of the servers
+    let policy = PartitionPolicy::Ratio(1, 3);
+    let runs = run_round_with_clients(
+        |is_alice, listener, peer| mp::run_server(is_alice, policy.clone(), listener, peer),
+        |addr_alice, addr_bob| submit_all(Variant::Mp, Protocol::Mp, &policy, addr_alice, addr_bob),
+    )
+    .await;
+
+    for run in &runs {
+        assert!(run.output.failed_clients.is_empty());
+    }
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(&alice.output.aggregate(), &bob.output.aggregate()),
+        plaintext_sum()
+    );
+}
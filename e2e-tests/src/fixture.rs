@@ -0,0 +1,134 @@
+//! Dataset of the tests: a few honest clients with deterministic inputs.
+
+use bridge::{client_server::PartitionPolicy, tcp_bridge::ClientID};
+use bytes::Bytes;
+use crypto_primitives::{
+    a2s::batch_bound_witness,
+    cot::{client::DEFAULT_SEC_PARAM, dims::ProtocolDims, COTSeed},
+    message::size::{estimate_mpc_sizes, estimate_sizes, Protocol},
+    uint::UInt,
+    utils::batch_sum,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sha2::Sha256;
+
+use crate::harness::{ServerRun, A, C, I};
+
+pub(crate) const GSIZE: usize = 64;
+pub(crate) const NUM_CLIENTS: usize = 4;
+pub(crate) const SESSION: u64 = 7;
+const MASTER_SEED: u64 = 0xe2e;
+
+/// Input of client `uid`, and the rng it uses for its messages of
+/// `protocol`. The input is the same for every protocol, the randomness is
+/// not, so that rounds running side by side never share a COT seed.
+pub(crate) fn client_input(uid: usize, protocol: Protocol) -> (Vec<I>, StdRng) {
+    let mut rng = StdRng::seed_from_u64(MASTER_SEED + uid as u64);
+    let input = (0..GSIZE).map(|_| I::rand(&mut rng)).collect();
+    let rng = StdRng::seed_from_u64(rng.gen::<u64>() ^ protocol as u64);
+    (input, rng)
+}
+
+/// Bound of the inputs in the rounds with a bound check.
+pub(crate) const BOUND: I = 200;
+/// Client whose first input is above `BOUND` in the rounds with a bound
+/// check. It sends the witness of an input at the bound instead.
+pub(crate) const OUT_OF_BOUND_UID: usize = 1;
+
+/// Input of client `uid` for a round with a bound check, the witness bits it
+/// sends, and its rng. The input is `client_input` clamped to `BOUND`, except
+/// for `OUT_OF_BOUND_UID`.
+pub(crate) fn bounded_client_input(uid: usize, protocol: Protocol) -> (Vec<I>, Vec<C>, StdRng) {
+    let (input, rng) = client_input(uid, protocol);
+    let mut input = input.into_iter().map(|x| x.min(BOUND)).collect::<Vec<_>>();
+    let witness = batch_bound_witness::<I, C>(&input, BOUND).unwrap();
+    if uid == OUT_OF_BOUND_UID {
+        input[0] = I::MAX;
+    }
+    (input, witness, rng)
+}
+
+/// Sum of the inputs of the clients of a round with a bound check, without
+/// `OUT_OF_BOUND_UID`.
+pub(crate) fn plaintext_bounded_sum() -> Vec<A> {
+    let inputs = (0..NUM_CLIENTS)
+        .filter(|uid| *uid != OUT_OF_BOUND_UID)
+        .map(|uid| {
+            let (input, ..) = bounded_client_input(uid, Protocol::Po2);
+            input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    batch_sum(&inputs)
+}
+
+/// The OT sender expands the COT seed of each client exactly once: B2A
+/// reuses the COTs of OT verification, and clients generate their COTs
+/// without expanding it. `seeds` are as sent by the clients, in uid order,
+/// and are bound to the uids as the servers bind them.
+pub(crate) fn assert_seeds_expanded_once(seeds: &[COTSeed]) {
+    for (uid, seed) in seeds.iter().enumerate() {
+        assert_eq!(
+            seed.for_client(uid as u64, crypto_primitives::cot::B2A_QS_PURPOSE)
+                .expansion_count(),
+            1,
+            "COT seed of client {} is not expanded exactly once",
+            uid
+        );
+    }
+}
+
+/// Sum of the inputs of all clients in ring `A`.
+pub(crate) fn plaintext_sum() -> Vec<A> {
+    plaintext_sum_of(0..NUM_CLIENTS)
+}
+
+/// Sum of the inputs of clients `uids` in ring `A`.
+pub(crate) fn plaintext_sum_of(uids: impl IntoIterator<Item = usize>) -> Vec<A> {
+    let inputs = uids
+        .into_iter()
+        .map(|uid| {
+            let (input, _) = client_input(uid, Protocol::Po2);
+            input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    batch_sum(&inputs)
+}
+
+/// Number of clients where the server with global role `is_alice` is OT
+/// sender.
+pub(crate) fn num_as_ot_sender(policy: &PartitionPolicy, is_alice: bool) -> usize {
+    (0..NUM_CLIENTS)
+        .filter(|&uid| policy.is_ot_sender(is_alice, ClientID::new(uid as u64)))
+        .count()
+}
+
+/// Each server received exactly what the other one sent.
+pub(crate) fn assert_mpc_comm_symmetric<O>([alice, bob]: &[ServerRun<O>; 2]) {
+    assert_eq!(alice.peer.num_bytes_sent(), bob.peer.num_bytes_received());
+    assert_eq!(alice.peer.num_bytes_received(), bob.peer.num_bytes_sent());
+    assert!(alice.peer.num_bytes_sent() > 0);
+    assert!(bob.peer.num_bytes_sent() > 0);
+}
+
+fn dims() -> ProtocolDims {
+    ProtocolDims::new::<I>(GSIZE, DEFAULT_SEC_PARAM).unwrap()
+}
+
+/// Client messages `(to OT sender, to OT receiver)` have the estimated sizes.
+pub(crate) fn assert_client_msg_sizes(msgs: &[(Bytes, Bytes)], protocol: Protocol) {
+    let report = estimate_sizes::<I, C, Sha256>(protocol, dims());
+    for (to_sender, to_receiver) in msgs {
+        assert_eq!(to_sender.len(), report.to_alice.total());
+        assert_eq!(to_receiver.len(), report.to_bob.total());
+    }
+}
+
+/// The MPC traffic of a round where every client passes is the estimated
+/// one.
+pub(crate) fn assert_mpc_comm_estimated<O>([alice, _]: &[ServerRun<O>; 2], protocol: Protocol) {
+    let estimate = estimate_mpc_sizes::<I, A, C>(protocol, dims(), NUM_CLIENTS);
+    assert_eq!(
+        alice.peer.num_bytes_sent() + alice.peer.num_bytes_received(),
+        estimate.total()
+    );
+}
@@ -0,0 +1,144 @@
+//! Rounds where each server runs the tasks of one client at a time, see
+//! `ClientGate`. The clients use fresh randomness, so that the COT seeds
+//! differ from the other rounds.
+
+use std::future::Future;
+
+use bin_utils::runtime::ClientGate;
+use bridge::{client_server::PartitionPolicy, tcp_bridge::ClientID};
+use crypto_primitives::{
+    cot::client::DEFAULT_SEC_PARAM, message::size::Protocol, utils::batch_sum,
+};
+use elsa_client::{
+    l2::L2Client,
+    mp::Client,
+    po2::{Po2Client, SingleRoundClient},
+};
+use rand::{rngs::StdRng, SeedableRng};
+use server_po2::client_msg::MsgFormat;
+use sha2::Sha256;
+
+use crate::{
+    fixture::{client_input, plaintext_sum, NUM_CLIENTS, SESSION},
+    harness::{reconstruct, run_round, serialize, A, C, I},
+    l2, mp, po2,
+};
+
+/// Run `server` with a gate of its own, as each server process has one.
+fn one_in_flight<F: Future>(server: F) -> impl Future<Output = F::Output> {
+    ClientGate::new(1).scope(server)
+}
+
+/// Input and fresh rng of client `uid`.
+fn fresh_client_input(uid: usize, protocol: Protocol) -> (Vec<I>, StdRng) {
+    let (input, rng) = client_input(uid, protocol);
+    (input, StdRng::from_rng(rng).unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_po2_round_one_client_in_flight() {
+    let policy = PartitionPolicy::default();
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = fresh_client_input(uid, Protocol::Po2);
+            let client = Po2Client::<I>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (
+                serialize(&client.prepared_message_0),
+                serialize(&client.prepared_message_1),
+            )
+        })
+        .collect();
+
+    let [alice, bob] = run_round(&policy, msgs, |is_alice, listener, peer| {
+        one_in_flight(po2::run_server(
+            is_alice,
+            policy.clone(),
+            MsgFormat::default(),
+            listener,
+            peer,
+        ))
+    })
+    .await;
+    assert_eq!(
+        reconstruct(
+            &batch_sum(&alice.output.arith_shares),
+            &batch_sum(&bob.output.arith_shares)
+        ),
+        plaintext_sum()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_l2_round_one_client_in_flight() {
+    let policy = PartitionPolicy::default();
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = fresh_client_input(uid, Protocol::L2);
+            let client = L2Client::<I, C>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (
+                serialize(&client.prepared_message_0),
+                serialize(&client.prepared_message_1),
+            )
+        })
+        .collect();
+
+    let [alice, bob] = run_round(&policy, msgs, |is_alice, listener, peer| {
+        one_in_flight(l2::run_server(
+            is_alice,
+            policy.clone(),
+            listener,
+            peer,
+            false,
+        ))
+    })
+    .await;
+    assert_eq!(alice.output.num_sqcorr_verified, NUM_CLIENTS);
+    assert_eq!(
+        reconstruct(&alice.output.aggregate(), &bob.output.aggregate()),
+        plaintext_sum()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_mp_round_one_client_in_flight() {
+    let policy = PartitionPolicy::default();
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = fresh_client_input(uid, Protocol::Mp);
+            let client = Client::<I, C, Sha256>::prepare_message::<A, _, _>(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+                Sha256::default,
+            );
+            (serialize(&client.msg_alice), serialize(&client.msg_bob))
+        })
+        .collect();
+
+    let [alice, bob] = run_round(&policy, msgs, |is_alice, listener, peer| {
+        one_in_flight(mp::run_server(is_alice, policy.clone(), listener, peer))
+    })
+    .await;
+    for output in [&alice.output, &bob.output] {
+        assert!(output.failed_clients.is_empty());
+        assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS));
+    }
+    assert_eq!(
+        reconstruct(&alice.output.aggregate(), &bob.output.aggregate()),
+        plaintext_sum()
+    );
+}
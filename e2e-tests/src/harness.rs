@@ -0,0 +1,134 @@
+//! Clients and servers of one round, connected over localhost.
+
+use std::{future::Future, net::SocketAddr, sync::Arc};
+
+use bridge::{
+    client_server::{init_meta_clients, ClientConnectConfig, PartitionPolicy},
+    id_tracker::SendId,
+    mpc_conn::MpcConnection,
+    tcp_bridge::{ClientID, ClientToken},
+};
+use bytes::Bytes;
+use crypto_primitives::utils::SliceExt;
+use serialize::Communicate;
+use tokio::net::TcpListener;
+
+/// Input ring, arithmetic share ring and correlation ring of both protocols.
+pub(crate) type I = u8;
+pub(crate) type A = u64;
+pub(crate) type C = u128;
+
+/// Add up the shares of the two servers.
+pub(crate) fn reconstruct(share_alice: &[A], share_bob: &[A]) -> Vec<A> {
+    share_alice.zip_map(share_bob, |a, b| a.wrapping_add(*b))
+}
+
+pub(crate) fn serialize<M: Communicate>(msg: &M) -> Bytes {
+    let mut bytes = Vec::with_capacity(msg.size_in_bytes());
+    msg.to_bytes(&mut bytes);
+    bytes.into()
+}
+
+/// Output of one server, and its connection to the peer.
+pub(crate) struct ServerRun<O> {
+    pub output: O,
+    pub peer: Arc<MpcConnection>,
+}
+
+pub(crate) async fn bind_localhost() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    (listener, addr)
+}
+
+/// Connect all clients to both servers, and send message `msgs[uid] = (to OT
+/// sender, to OT receiver)` of each client.
+async fn send_client_msgs(
+    addr_alice: SocketAddr,
+    addr_bob: SocketAddr,
+    policy: &PartitionPolicy,
+    msgs: Vec<(Bytes, Bytes)>,
+) {
+    let connections = init_meta_clients(
+        msgs.len(),
+        addr_alice,
+        addr_bob,
+        ClientToken::default(),
+        ClientConnectConfig::default(),
+    )
+    .await
+    .unwrap();
+    let mut sent = Vec::with_capacity(2 * msgs.len());
+    for (uid, ((to_sender, to_receiver), (alice, bob))) in
+        msgs.into_iter().zip(connections).enumerate()
+    {
+        let (ot_sender, ot_receiver) = if policy.alice_is_ot_sender(ClientID::new(uid as u64)) {
+            (alice, bob)
+        } else {
+            (bob, alice)
+        };
+        sent.push(ot_sender.send_message_bytes(SendId::FIRST, to_sender));
+        sent.push(ot_receiver.send_message_bytes(SendId::FIRST, to_receiver));
+    }
+    for sent in sent {
+        sent.await
+            .expect("connection closed before the message was sent");
+    }
+}
+
+/// Run one round. The clients send `msgs` to the two servers, and each server
+/// runs `server(is_alice, client listener, peer)`. Returns the runs of Alice
+/// and Bob.
+pub(crate) async fn run_round<O, S, F>(
+    policy: &PartitionPolicy,
+    msgs: Vec<(Bytes, Bytes)>,
+    server: S,
+) -> [ServerRun<O>; 2]
+where
+    S: Fn(bool, TcpListener, Arc<MpcConnection>) -> F,
+    F: Future<Output = O> + Send + 'static,
+    O: Send + 'static,
+{
+    run_round_with_clients(server, |addr_alice, addr_bob| {
+        send_client_msgs(addr_alice, addr_bob, policy, msgs)
+    })
+    .await
+}
+
+/// Same as `run_round`, but the clients are `clients(address of Alice,
+/// address of Bob)`.
+pub(crate) async fn run_round_with_clients<O, S, F, C, G>(
+    server: S,
+    clients: C,
+) -> [ServerRun<O>; 2]
+where
+    S: Fn(bool, TcpListener, Arc<MpcConnection>) -> F,
+    F: Future<Output = O> + Send + 'static,
+    O: Send + 'static,
+    C: FnOnce(SocketAddr, SocketAddr) -> G,
+    G: Future<Output = ()>,
+{
+    let (mpc_listener, mpc_addr) = bind_localhost().await;
+    let (peer_alice, peer_bob) = tokio::join!(
+        MpcConnection::new_as_alice_with_listener(mpc_listener, 2),
+        MpcConnection::new_as_bob(mpc_addr, 2)
+    );
+    let (listener_alice, addr_alice) = bind_localhost().await;
+    let (listener_bob, addr_bob) = bind_localhost().await;
+
+    let spawn = |is_alice: bool, listener: TcpListener, peer: MpcConnection| {
+        let peer = Arc::new(peer);
+        let handle = tokio::spawn(server(is_alice, listener, peer.clone()));
+        async move {
+            ServerRun {
+                output: handle.await.unwrap(),
+                peer,
+            }
+        }
+    };
+    let alice = spawn(true, listener_alice, peer_alice);
+    let bob = spawn(false, listener_bob, peer_bob);
+    let clients = clients(addr_alice, addr_bob);
+    let (alice, bob, ()) = tokio::join!(alice, bob, clients);
+    [alice, bob]
+}
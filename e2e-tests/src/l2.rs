@@ -0,0 +1,287 @@
+//! Semi-honest L2 protocol: client-l2 to server-l2.
+
+use std::sync::Arc;
+
+use bridge::{
+    client_server::PartitionPolicy,
+    mpc_conn::MpcConnection,
+    tcp_bridge::{ClientID, ClientToken},
+};
+use crypto_primitives::{cot::client::DEFAULT_SEC_PARAM, message::size::Protocol};
+use elsa_client::{l2::L2Client, po2::SingleRoundClient};
+use server_l2::{
+    client_msg::ClientData,
+    pipeline::{self, PipelineOutput},
+};
+use tokio::net::TcpListener;
+
+use crate::{
+    fixture::{
+        assert_client_msg_sizes, assert_mpc_comm_estimated, assert_mpc_comm_symmetric,
+        assert_seeds_expanded_once, bounded_client_input, client_input, num_as_ot_sender,
+        plaintext_bounded_sum, plaintext_sum, plaintext_sum_of, BOUND, GSIZE, NUM_CLIENTS,
+        OUT_OF_BOUND_UID, SESSION,
+    },
+    harness::{reconstruct, run_round, serialize, A, C, I},
+};
+
+pub(crate) async fn run_server(
+    is_alice: bool,
+    policy: PartitionPolicy,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+    simulate_server_corr: bool,
+) -> PipelineOutput<A> {
+    run_server_with_bound(is_alice, policy, listener, peer, None, simulate_server_corr).await
+}
+
+async fn run_server_with_bound(
+    is_alice: bool,
+    policy: PartitionPolicy,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+    bound: Option<I>,
+    simulate_server_corr: bool,
+) -> PipelineOutput<A> {
+    let data = ClientData::<I, C>::fetch_with_listener(
+        is_alice,
+        policy,
+        listener,
+        NUM_CLIENTS,
+        ClientToken::default(),
+        GSIZE,
+        bound,
+        None,
+    )
+    .await
+    .with_session(SESSION);
+    assert_eq!(data.num_clients(), NUM_CLIENTS);
+    pipeline::run::<I, A, C>(
+        GSIZE,
+        DEFAULT_SEC_PARAM,
+        is_alice,
+        &data,
+        &peer,
+        None,
+        simulate_server_corr,
+    )
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_l2_round() {
+    let policy = PartitionPolicy::default();
+    let (msgs, seeds): (Vec<_>, Vec<_>) = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = client_input(uid, Protocol::L2);
+            let client = L2Client::<I, C>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            let seed = client.prepared_message_0.cot().qs_seed;
+            (
+                (
+                    serialize(&client.prepared_message_0),
+                    serialize(&client.prepared_message_1),
+                ),
+                seed,
+            )
+        })
+        .unzip();
+    assert_client_msg_sizes(&msgs, Protocol::L2);
+
+    let runs = run_round(&policy, msgs, |is_alice, listener, peer| {
+        run_server(is_alice, policy.clone(), listener, peer, false)
+    })
+    .await;
+
+    for (run, is_alice) in runs.iter().zip([true, false]) {
+        let output = &run.output;
+        assert!(output.rejected_clients.is_empty());
+        assert_eq!(output.num_ot_verified, num_as_ot_sender(&policy, is_alice));
+        assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS);
+    }
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(&alice.output.aggregate(), &bob.output.aggregate()),
+        plaintext_sum()
+    );
+    assert_mpc_comm_symmetric(&runs);
+    assert_mpc_comm_estimated(&runs, Protocol::L2);
+    assert_seeds_expanded_once(&seeds);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_l2_round_rejects_out_of_bound() {
+    let policy = PartitionPolicy::default();
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, witness, mut rng) = bounded_client_input(uid, Protocol::L2);
+            let client = L2Client::<I, C>::with_witness(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                Some(&witness),
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (client.msg_to_ot_sender(), client.msg_to_ot_receiver(false))
+        })
+        .collect();
+
+    let runs = run_round(&policy, msgs, |is_alice, listener, peer| {
+        run_server_with_bound(is_alice, policy.clone(), listener, peer, Some(BOUND), false)
+    })
+    .await;
+
+    for run in &runs {
+        let output = &run.output;
+        assert_eq!(
+            output.rejected_clients,
+            vec![ClientID::new(OUT_OF_BOUND_UID as u64)]
+        );
+        assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS);
+        assert_eq!(output.num_bound_verified, Some(NUM_CLIENTS - 1));
+    }
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(&alice.output.aggregate(), &bob.output.aggregate()),
+        plaintext_bounded_sum()
+    );
+    assert_mpc_comm_symmetric(&runs);
+}
+
+/// Client whose square correlations to its OT receiver claim one
+/// correlation too many.
+const BAD_CORR_UID: usize = 2;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_l2_round_fails_bad_sqcorr_seed() {
+    let policy = PartitionPolicy::default();
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = client_input(uid, Protocol::L2);
+            let mut client = L2Client::<I, C>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            if uid == BAD_CORR_UID {
+                client.prepared_message_1.square_corr.expected_len += 1;
+            }
+            (
+                serialize(&client.prepared_message_0),
+                serialize(&client.prepared_message_1),
+            )
+        })
+        .collect();
+
+    let runs = run_round(&policy, msgs, |is_alice, listener, peer| {
+        let policy = policy.clone();
+        async move {
+            let data = ClientData::<I, C>::fetch_with_listener(
+                is_alice,
+                policy.clone(),
+                listener,
+                NUM_CLIENTS,
+                ClientToken::default(),
+                GSIZE,
+                None,
+                None,
+            )
+            .await
+            .with_session(SESSION);
+            // only the OT receiver of the client sees its bad correlations
+            let receiver_is_alice = !policy.alice_is_ot_sender(ClientID::new(BAD_CORR_UID as u64));
+            let expected_failed = if is_alice == receiver_is_alice {
+                vec![ClientID::new(BAD_CORR_UID as u64)]
+            } else {
+                Vec::new()
+            };
+            assert_eq!(data.failed, expected_failed);
+            pipeline::run::<I, A, C>(
+                GSIZE,
+                DEFAULT_SEC_PARAM,
+                is_alice,
+                &data,
+                &peer,
+                None,
+                false,
+            )
+            .await
+        }
+    })
+    .await;
+
+    for run in &runs {
+        let output = &run.output;
+        assert_eq!(
+            output.rejected_clients,
+            vec![ClientID::new(BAD_CORR_UID as u64)]
+        );
+        assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS - 1);
+    }
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(&alice.output.aggregate(), &bob.output.aggregate()),
+        plaintext_sum_of((0..NUM_CLIENTS).filter(|uid| *uid != BAD_CORR_UID))
+    );
+}
+
+#[cfg(feature = "simulation-helpers")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_l2_round_with_simulated_server_corr() {
+    let policy = PartitionPolicy::default();
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = client_input(uid, Protocol::L2);
+            let client = L2Client::<I, C>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (
+                serialize(&client.prepared_message_0),
+                serialize(&client.prepared_message_1),
+            )
+        })
+        .collect();
+
+    let runs = run_round(&policy, msgs, |is_alice, listener, peer| {
+        run_server(is_alice, policy.clone(), listener, peer, true)
+    })
+    .await;
+
+    // the correlations of the servers pass the same check as the ones of
+    // the clients
+    for run in &runs {
+        assert_eq!(run.output.num_sqcorr_verified, NUM_CLIENTS);
+    }
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(&alice.output.aggregate(), &bob.output.aggregate()),
+        plaintext_sum()
+    );
+    for (uid, (share_alice, share_bob)) in alice
+        .output
+        .square_shares
+        .iter()
+        .zip(&bob.output.square_shares)
+        .enumerate()
+    {
+        let (input, _) = client_input(uid, Protocol::L2);
+        let squares = input
+            .iter()
+            .map(|x| (*x as A).wrapping_mul(*x as A))
+            .collect::<Vec<_>>();
+        assert_eq!(reconstruct(share_alice, share_bob), squares);
+    }
+    assert_mpc_comm_symmetric(&runs);
+}
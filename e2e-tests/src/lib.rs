@@ -0,0 +1,36 @@
+//! End-to-end runs of the protocols in one process: clients prepare their
+//! messages with `elsa-client` and send them over TCP to two in-process
+//! servers, which fetch them and run the server pipelines against each other.
+//! This catches breakage across crates, e.g. a message layout changed on one
+//! side only, that the tests of each crate miss.
+//!
+//! Every socket is bound to a free port of localhost, so the tests can run in
+//! parallel with the rest of the workspace. The same harness runs
+//! `elsa demo`, see [`demo`].
+
+#[cfg(test)]
+mod bits;
+#[cfg(test)]
+mod bounded;
+pub mod demo;
+#[cfg(test)]
+mod embed;
+#[cfg(test)]
+mod fixture;
+#[cfg(test)]
+mod gate;
+mod harness;
+#[cfg(test)]
+mod l2;
+#[cfg(test)]
+mod malformed;
+#[cfg(test)]
+mod mp;
+#[cfg(test)]
+mod mp_po2;
+#[cfg(test)]
+mod po2;
+#[cfg(test)]
+mod session;
+#[cfg(test)]
+mod tls;
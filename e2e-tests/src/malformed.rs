@@ -0,0 +1,174 @@
+//! Malformed client messages, mutated by `serialize::fuzz`, fail only their
+//! client in `ClientData::from_recording` of each server, instead of the
+//! server.
+
+use bin_utils::signing::ClientKeys;
+use bridge::{client_dump::ClientRecording, client_server::PartitionPolicy, tcp_bridge::ClientID};
+use bytes::Bytes;
+use crypto_primitives::{cot::client::DEFAULT_SEC_PARAM, message::size::Protocol};
+use elsa_client::{l2::L2Client, mp::Client, po2::Po2Client, po2::SingleRoundClient};
+use rand::{rngs::StdRng, SeedableRng};
+use serialize::fuzz::mutations;
+use sha2::Sha256;
+
+use crate::{
+    fixture::{client_input, GSIZE, NUM_CLIENTS, SESSION},
+    harness::{serialize, A, C, I},
+};
+
+/// Client whose messages are mutated.
+const BAD_UID: usize = 1;
+/// Random mutations per message, on top of the exhaustive ones.
+const NUM_RANDOM: usize = 50;
+/// Mutations checked per message, spread over all of them, as each check
+/// deserializes the messages of all clients.
+const NUM_CHECKED: usize = 300;
+
+/// Messages of all clients to the server with global role `is_alice`, with
+/// the one of `BAD_UID` replaced by `bad`.
+fn recording(
+    policy: &PartitionPolicy,
+    is_alice: bool,
+    msgs: &[(Bytes, Bytes)],
+    bad: &Bytes,
+) -> ClientRecording {
+    let mut recording = ClientRecording::default();
+    for (uid, (to_sender, to_receiver)) in msgs.iter().enumerate() {
+        let id = ClientID::new(uid as u64);
+        let is_sender = policy.is_ot_sender(is_alice, id);
+        let msg = match (uid == BAD_UID, is_sender) {
+            (true, _) => bad.clone(),
+            (false, true) => to_sender.clone(),
+            (false, false) => to_receiver.clone(),
+        };
+        if is_sender {
+            recording.alice.push((id, msg));
+        } else {
+            recording.bob.push((id, msg));
+        }
+    }
+    recording
+}
+
+/// Run `from_recording(is_alice, recording)`, which returns the uids of the
+/// parsed and of the failed clients, on mutations of both messages of
+/// `BAD_UID`. Only that client may fail, and the truncations must.
+fn check_from_recording<F>(msgs: &[(Bytes, Bytes)], from_recording: F)
+where
+    F: Fn(bool, PartitionPolicy, ClientRecording) -> (Vec<ClientID>, Vec<ClientID>),
+{
+    let policy = PartitionPolicy::default();
+    let bad_uid = ClientID::new(BAD_UID as u64);
+    let mut rng = StdRng::seed_from_u64(0x6d616c);
+    for is_alice in [true, false] {
+        let (to_sender, to_receiver) = &msgs[BAD_UID];
+        let seed = if policy.is_ot_sender(is_alice, bad_uid) {
+            to_sender
+        } else {
+            to_receiver
+        };
+        let mutated = mutations(seed, NUM_RANDOM, &mut rng);
+        let step = (mutated.len() / NUM_CHECKED).max(1);
+        let mut num_failed = 0;
+        for bad in mutated.into_iter().step_by(step) {
+            let recording = recording(&policy, is_alice, msgs, &bad.into());
+            let (uids, failed) = from_recording(is_alice, policy.clone(), recording);
+            let good = (0..NUM_CLIENTS)
+                .filter(|uid| *uid != BAD_UID)
+                .map(|uid| ClientID::new(uid as u64));
+            assert!(good.into_iter().all(|uid| uids.contains(&uid)));
+            assert_eq!(uids.len() + failed.len(), NUM_CLIENTS);
+            if failed == vec![bad_uid] {
+                num_failed += 1;
+            } else {
+                assert!(failed.is_empty());
+            }
+        }
+        assert!(num_failed > 0);
+    }
+}
+
+#[test]
+fn test_po2_from_recording_malformed() {
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = client_input(uid, Protocol::Po2);
+            let client = Po2Client::<I>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (
+                serialize(&client.prepared_message_0),
+                serialize(&client.prepared_message_1),
+            )
+        })
+        .collect::<Vec<_>>();
+    check_from_recording(&msgs, |is_alice, policy, recording| {
+        let data = server_po2::client_msg::ClientData::<I>::from_recording(
+            is_alice,
+            policy,
+            Default::default(),
+            recording,
+        );
+        (data.uids, data.failed)
+    });
+}
+
+#[test]
+fn test_l2_from_recording_malformed() {
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = client_input(uid, Protocol::L2);
+            let client = L2Client::<I, C>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (
+                serialize(&client.prepared_message_0),
+                serialize(&client.prepared_message_1),
+            )
+        })
+        .collect::<Vec<_>>();
+    check_from_recording(&msgs, |is_alice, policy, recording| {
+        let data = server_l2::client_msg::ClientData::<I, C>::from_recording(
+            is_alice, policy, recording, GSIZE, None,
+        );
+        (data.uids, data.failed)
+    });
+}
+
+#[test]
+fn test_mp_from_recording_malformed() {
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = client_input(uid, Protocol::Mp);
+            let client = Client::<I, C, Sha256>::prepare_message::<A, _, _>(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+                Sha256::default,
+            );
+            (serialize(&client.msg_alice), serialize(&client.msg_bob))
+        })
+        .collect::<Vec<_>>();
+    check_from_recording(&msgs, |is_alice, policy, recording| {
+        let data = server_mp::client_msg::ClientData::<I, C, Sha256>::from_recording(
+            is_alice,
+            policy,
+            recording,
+            GSIZE,
+            None,
+            Sha256::default,
+            &ClientKeys::default(),
+        );
+        (data.uids, data.failed)
+    });
+}
@@ -0,0 +1,191 @@
+//! Malicious privacy protocol: client-mp to server-mp.
+
+use std::sync::Arc;
+
+use bin_utils::{client::client_rngs, signing::ClientKeys};
+use bridge::{
+    client_server::PartitionPolicy,
+    mpc_conn::MpcConnection,
+    tcp_bridge::{ClientID, ClientToken},
+};
+use crypto_primitives::{
+    cot::client::DEFAULT_SEC_PARAM, malpriv::MessageHash, message::size::Protocol,
+};
+use elsa_client::mp::Client;
+use server_mp::{
+    client_msg::ClientData,
+    pipeline::{self, PipelineOutput},
+};
+use sha2::Sha256;
+use tokio::net::TcpListener;
+
+use crate::{
+    fixture::{
+        assert_client_msg_sizes, assert_mpc_comm_estimated, assert_mpc_comm_symmetric,
+        assert_seeds_expanded_once, bounded_client_input, client_input, num_as_ot_sender,
+        plaintext_bounded_sum, plaintext_sum, BOUND, GSIZE, NUM_CLIENTS, OUT_OF_BOUND_UID, SESSION,
+    },
+    harness::{reconstruct, run_round, serialize, A, C, I},
+};
+
+pub(crate) async fn run_server(
+    is_alice: bool,
+    policy: PartitionPolicy,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+) -> PipelineOutput<A> {
+    run_server_with_bound(is_alice, policy, listener, peer, None).await
+}
+
+async fn run_server_with_bound(
+    is_alice: bool,
+    policy: PartitionPolicy,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+    bound: Option<I>,
+) -> PipelineOutput<A> {
+    let data = ClientData::<I, C, Sha256>::fetch_with_listener(
+        is_alice,
+        policy,
+        listener,
+        NUM_CLIENTS,
+        ClientToken::default(),
+        GSIZE,
+        bound,
+        Sha256::default,
+        &ClientKeys::default(),
+        None,
+    )
+    .await
+    .with_session(SESSION);
+    assert_eq!(data.num_clients(), NUM_CLIENTS);
+    pipeline::run::<I, A, C, _, _>(
+        GSIZE,
+        DEFAULT_SEC_PARAM,
+        is_alice,
+        &data,
+        &peer,
+        Sha256::default,
+        false,
+    )
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_mp_round() {
+    let policy = PartitionPolicy::default();
+    let (msgs, seeds): (Vec<_>, Vec<_>) = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = client_input(uid, Protocol::Mp);
+            let client = Client::<I, C, Sha256>::prepare_message::<A, _, _>(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+                Sha256::default,
+            );
+            let seed = client.msg_alice.0 .0.cot().qs_seed;
+            (
+                (serialize(&client.msg_alice), serialize(&client.msg_bob)),
+                seed,
+            )
+        })
+        .unzip();
+    assert_client_msg_sizes(&msgs, Protocol::Mp);
+
+    let runs = run_round(&policy, msgs, |is_alice, listener, peer| {
+        run_server(is_alice, policy.clone(), listener, peer)
+    })
+    .await;
+
+    for (run, is_alice) in runs.iter().zip([true, false]) {
+        let output = &run.output;
+        let num_as_sender = num_as_ot_sender(&policy, is_alice);
+        assert!(output.failed_clients.is_empty());
+        assert_eq!(output.num_ot_verified, num_as_sender);
+        assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS);
+        assert_eq!(output.num_b2a_hash_verified, NUM_CLIENTS - num_as_sender);
+        assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS));
+        assert_eq!(output.num_ot_hash_verified, num_as_sender);
+        assert_eq!(output.num_sqcorr_hash_verified, Some(NUM_CLIENTS));
+    }
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(&alice.output.aggregate(), &bob.output.aggregate()),
+        plaintext_sum()
+    );
+    assert_mpc_comm_symmetric(&runs);
+    assert_mpc_comm_estimated(&runs, Protocol::Mp);
+    assert_seeds_expanded_once(&seeds);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_mp_round_rejects_out_of_bound() {
+    let policy = PartitionPolicy::default();
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, witness, mut rng) = bounded_client_input(uid, Protocol::Mp);
+            let client = Client::<I, C, Sha256>::prepare_with_witness::<A, _, _>(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                Some((BOUND, &witness)),
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+                Sha256::default,
+            );
+            (client.msg_to_alice(), client.msg_to_bob())
+        })
+        .collect();
+
+    let runs = run_round(&policy, msgs, |is_alice, listener, peer| {
+        run_server_with_bound(is_alice, policy.clone(), listener, peer, Some(BOUND))
+    })
+    .await;
+
+    for run in &runs {
+        let output = &run.output;
+        assert_eq!(
+            output.failed_clients,
+            vec![ClientID::new(OUT_OF_BOUND_UID as u64)]
+        );
+        assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS);
+        assert_eq!(output.num_bound_verified, Some(NUM_CLIENTS - 1));
+        // the transcripts of the others, with their bound checks, match
+        assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS - 1));
+        assert_eq!(output.num_sqcorr_hash_verified, Some(NUM_CLIENTS - 1));
+    }
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(&alice.output.aggregate(), &bob.output.aggregate()),
+        plaintext_bounded_sum()
+    );
+    assert_mpc_comm_symmetric(&runs);
+}
+
+/// Digest of the messages of all clients, with the rngs of `--seed seed`.
+fn digest_of_seeded_clients(seed: u64) -> Vec<u8> {
+    let mut transcript = Sha256::default();
+    for (uid, mut rng) in client_rngs(Some(seed), NUM_CLIENTS).into_iter().enumerate() {
+        let (input, _) = client_input(uid, Protocol::Mp);
+        let client = Client::<I, C, Sha256>::prepare_message::<A, _, _>(
+            ClientID::new(uid as u64),
+            SESSION,
+            &input,
+            &mut rng,
+            DEFAULT_SEC_PARAM,
+            Sha256::default,
+        );
+        transcript.absorb(&client.msg_alice);
+        transcript.absorb(&client.msg_bob);
+    }
+    transcript.digest()
+}
+
+#[test]
+fn test_seeded_clients_are_reproducible() {
+    let digest = digest_of_seeded_clients(42);
+    assert_eq!(digest_of_seeded_clients(42), digest);
+    assert_ne!(digest_of_seeded_clients(43), digest);
+}
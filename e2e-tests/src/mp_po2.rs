@@ -0,0 +1,160 @@
+//! Two-phase malicious privacy protocol: client-mp-po2 to server-mp-po2,
+//! with a client that drops out between the two phases.
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bridge::{
+    client_server::PartitionPolicy,
+    id_tracker::SendId,
+    mpc_conn::MpcConnection,
+    tcp_bridge::{ClientID, ClientToken, TcpConnection},
+};
+use crypto_primitives::{cot::client::DEFAULT_SEC_PARAM, message::size::Protocol};
+use elsa_client::{ClientConfig, ElsaClient, InputRing, Variant};
+use server_mp_po2::{
+    client_msg::ClientData,
+    pipeline::{self, PipelineOutput},
+    utils::IdPool,
+};
+use server_protocol as mpc;
+use sha2::Sha256;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{
+    fixture::{client_input, num_as_ot_sender, plaintext_sum_of, GSIZE, NUM_CLIENTS, SESSION},
+    harness::{reconstruct, run_round_with_clients, A, I},
+};
+
+/// Client that sends phase 1, and never phase 2.
+const DROPPED: usize = 1;
+const PHASE2_TIMEOUT: Duration = Duration::from_millis(500);
+
+async fn run_server(
+    is_alice: bool,
+    policy: PartitionPolicy,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+) -> (Vec<ClientID>, PipelineOutput<A>) {
+    let chi_seed = mpc::sample_chi_seed(IdPool::exchange_chi_seed(), &peer).await;
+    let data = ClientData::<I, Sha256>::fetch_with_listener(
+        is_alice,
+        policy,
+        listener,
+        NUM_CLIENTS,
+        ClientToken::default(),
+        chi_seed,
+        Some(PHASE2_TIMEOUT),
+    )
+    .await
+    .with_session(SESSION);
+    let (data, ids) = pipeline::agree_on_clients(is_alice, data, &peer).await;
+    assert_eq!(data.uids.len(), NUM_CLIENTS);
+    let output = pipeline::run::<I, A, _, _>(
+        GSIZE,
+        DEFAULT_SEC_PARAM,
+        is_alice,
+        &data,
+        ids,
+        chi_seed,
+        &peer,
+        Sha256::default,
+    )
+    .await;
+    (data.dropped, output)
+}
+
+/// Submit the input of every client but `DROPPED`, which only sends its
+/// phase 1 message. Its connections are put in `held`, so that they stay
+/// open after the clients are done.
+async fn submit_with_dropout(
+    policy: &PartitionPolicy,
+    addr_alice: SocketAddr,
+    addr_bob: SocketAddr,
+    held: &Mutex<Vec<TcpConnection>>,
+) {
+    let mut submitted = Vec::new();
+    for uid in 0..NUM_CLIENTS {
+        let client = ElsaClient::new(ClientConfig {
+            uid: ClientID::new(uid as u64),
+            session: SESSION,
+            partition: policy.clone(),
+            ..ClientConfig::new(
+                Variant::MpPo2,
+                InputRing::U8,
+                GSIZE,
+                addr_alice.to_string(),
+                addr_bob.to_string(),
+            )
+        });
+        let (input, _) = client_input(uid, Protocol::Mp);
+        let prepared = client.prepare(&input).unwrap();
+        if uid != DROPPED {
+            submitted.push(tokio::spawn(client.submit(prepared)));
+            continue;
+        }
+        let uid = ClientID::new(uid as u64);
+        let (addr_sender, addr_receiver) = if policy.alice_is_ot_sender(uid) {
+            (addr_alice, addr_bob)
+        } else {
+            (addr_bob, addr_alice)
+        };
+        for (addr, msg) in [
+            (addr_sender, prepared.to_ot_sender.clone()),
+            (addr_receiver, prepared.to_ot_receiver.clone()),
+        ] {
+            let socket = TcpStream::connect(addr).await.unwrap();
+            let (conn, registered) =
+                TcpConnection::new_client_side(socket, uid, ClientToken::default());
+            registered.await.unwrap();
+            conn.send_message_bytes(SendId::FIRST, msg).await.unwrap();
+            held.lock().unwrap().push(conn);
+        }
+    }
+    for submitted in submitted {
+        submitted.await.unwrap().unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_mp_po2_round_with_dropout() {
+    let policy = PartitionPolicy::default();
+    let held = Mutex::new(Vec::new());
+    let round = run_round_with_clients(
+        |is_alice, listener, peer| run_server(is_alice, policy.clone(), listener, peer),
+        |addr_alice, addr_bob| submit_with_dropout(&policy, addr_alice, addr_bob, &held),
+    );
+    let runs = tokio::time::timeout(PHASE2_TIMEOUT * 20, round)
+        .await
+        .expect("the round waits for the dropped client");
+
+    let dropped = ClientID::new(DROPPED as u64);
+    for (run, is_alice) in runs.iter().zip([true, false]) {
+        let (dropped_clients, output) = &run.output;
+        assert_eq!(dropped_clients, &[dropped]);
+        assert!(output.arith_shares[DROPPED].is_none());
+
+        let num_as_sender = num_as_ot_sender(&policy, is_alice);
+        let (sender_dropped, receiver_dropped) = if policy.is_ot_sender(is_alice, dropped) {
+            (1, 0)
+        } else {
+            (0, 1)
+        };
+        assert_eq!(output.num_ot_verified, num_as_sender - sender_dropped);
+        assert_eq!(output.num_ot_hash_verified, num_as_sender - sender_dropped);
+        assert_eq!(
+            output.num_b2a_hash_verified,
+            NUM_CLIENTS - num_as_sender - receiver_dropped
+        );
+    }
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(&alice.output.1.aggregate(), &bob.output.1.aggregate()),
+        plaintext_sum_of((0..NUM_CLIENTS).filter(|&uid| uid != DROPPED))
+    );
+    // the dropped client kept its connections open the whole round
+    assert_eq!(held.lock().unwrap().len(), 2);
+}
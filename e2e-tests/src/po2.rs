@@ -0,0 +1,580 @@
+//! Semi-honest Po2 protocol: client-po2 to server-po2, with a client whose
+//! connection drops in the middle of phase 1.
+
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+
+use bridge::{
+    client_server::{ClientSession, ClientsPool, PartitionPolicy},
+    id_tracker::{RecvId, SendId},
+    metrics::HEADER_SIZE,
+    mpc_conn::MpcConnection,
+    tcp_bridge::{ClientID, ClientToken, TcpConnection, TcpConnectionConfig},
+};
+use bytes::Bytes;
+use crypto_primitives::{
+    cot::client::DEFAULT_SEC_PARAM,
+    message::size::Protocol,
+    uint::UInt,
+    utils::{batch_sum, UnequalLengths},
+};
+use elsa_client::po2::{Po2Client, Po2SplitClient, SingleRoundClient};
+use rand::{rngs::StdRng, SeedableRng};
+use serialize::Lz4Compressed;
+use server_po2::{
+    client_msg::{ClientData, MsgFormat},
+    pipeline::{self, PipelineOutput},
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use crate::{
+    fixture::{
+        assert_seeds_expanded_once, client_input, plaintext_sum, GSIZE, NUM_CLIENTS, SESSION,
+    },
+    harness::{bind_localhost, reconstruct, run_round, run_round_with_clients, serialize, A, I},
+};
+
+pub(crate) async fn run_server(
+    is_alice: bool,
+    policy: PartitionPolicy,
+    format: MsgFormat,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+) -> PipelineOutput<A> {
+    let data = ClientData::<I>::fetch_with_listener(
+        is_alice,
+        policy,
+        listener,
+        NUM_CLIENTS,
+        ClientToken::default(),
+        format,
+        None,
+    )
+    .await
+    .with_session(SESSION);
+    assert_eq!(
+        data.num_clients_as_alice() + data.num_clients_as_bob(),
+        NUM_CLIENTS
+    );
+    pipeline::run::<I, A>(GSIZE, DEFAULT_SEC_PARAM, is_alice, &data, &peer, false).await
+}
+
+/// Forward one connection to `server`, and close both sides after `limit`
+/// bytes of the client, like a link that drops in the middle of a message.
+/// The task returns the number of bytes forwarded.
+async fn dropping_proxy(server: SocketAddr, limit: u64) -> (SocketAddr, JoinHandle<u64>) {
+    let (listener, addr) = bind_localhost().await;
+    let forward = tokio::spawn(async move {
+        let (client, _) = listener.accept().await.unwrap();
+        let mut server = TcpStream::connect(server).await.unwrap();
+        let mut client = client.take(limit);
+        tokio::io::copy(&mut client, &mut server).await.unwrap()
+    });
+    (addr, forward)
+}
+
+/// Client 0 sends its message to the OT receiver through a link that drops
+/// halfway, reconnects, and sends it again. The other clients are honest.
+async fn send_client_msgs_with_drop(
+    addr_alice: SocketAddr,
+    addr_bob: SocketAddr,
+    policy: &PartitionPolicy,
+    msgs: Vec<(Bytes, Bytes)>,
+) {
+    let mut sent = Vec::new();
+    for (uid, (to_sender, to_receiver)) in msgs.into_iter().enumerate() {
+        let uid = ClientID::new(uid as u64);
+        let (addr_sender, addr_receiver) = if policy.alice_is_ot_sender(uid) {
+            (addr_alice, addr_bob)
+        } else {
+            (addr_bob, addr_alice)
+        };
+        let connect = |addr: SocketAddr, config: TcpConnectionConfig| async move {
+            let socket = TcpStream::connect(addr).await.unwrap();
+            let (conn, registered) = TcpConnection::new_client_side_with_config(
+                socket,
+                uid,
+                ClientToken::default(),
+                config,
+            );
+            registered.await.unwrap();
+            conn
+        };
+        let ot_sender = connect(addr_sender, TcpConnectionConfig::default()).await;
+        sent.push(ot_sender.send_message_bytes(SendId::FIRST, to_sender));
+        if uid.id != 0 {
+            let ot_receiver = connect(addr_receiver, TcpConnectionConfig::default()).await;
+            sent.push(ot_receiver.send_message_bytes(SendId::FIRST, to_receiver));
+            continue;
+        }
+
+        // registration, and the header and half of the message
+        let limit = 2 * HEADER_SIZE + 8 + to_receiver.len() as u64 / 2;
+        let (addr_proxy, forward) = dropping_proxy(addr_receiver, limit).await;
+        let config = TcpConnectionConfig {
+            keep_sent: true,
+            ..Default::default()
+        };
+        let ot_receiver = connect(addr_proxy, config).await;
+        // may or may not fail, depending on when the client sees the drop
+        let _ = ot_receiver
+            .send_message_bytes(SendId::FIRST, to_receiver)
+            .await;
+        assert_eq!(forward.await.unwrap(), limit);
+        ot_receiver.reconnect(addr_receiver).await.unwrap();
+    }
+    for sent in sent {
+        sent.await
+            .expect("connection closed before the message was sent");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_po2_round_with_reconnect() {
+    let policy = PartitionPolicy::default();
+    let (msgs, seeds): (Vec<_>, Vec<_>) = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = client_input(uid, Protocol::Po2);
+            let client = Po2Client::<I>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            let seed = client.prepared_message_0.cot.qs_seed;
+            (
+                (
+                    serialize(&client.prepared_message_0),
+                    serialize(&client.prepared_message_1),
+                ),
+                seed,
+            )
+        })
+        .unzip();
+
+    let runs = run_round_with_clients(
+        |is_alice, listener, peer| {
+            run_server(
+                is_alice,
+                policy.clone(),
+                MsgFormat::default(),
+                listener,
+                peer,
+            )
+        },
+        |addr_alice, addr_bob| send_client_msgs_with_drop(addr_alice, addr_bob, &policy, msgs),
+    )
+    .await;
+
+    for run in &runs {
+        assert!(run.output.rejected_clients.is_empty());
+        assert_eq!(run.output.arith_shares.len(), NUM_CLIENTS);
+    }
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(
+            &batch_sum(&alice.output.arith_shares),
+            &batch_sum(&bob.output.arith_shares)
+        ),
+        plaintext_sum()
+    );
+    // a reconnected client is served its messages once
+    assert_seeds_expanded_once(&seeds);
+}
+
+/// Connect clients `order` to both servers one after the other, and send
+/// their messages `msgs[uid]`.
+async fn send_client_msgs_in_order(
+    addr_alice: SocketAddr,
+    addr_bob: SocketAddr,
+    policy: &PartitionPolicy,
+    msgs: Vec<(Bytes, Bytes)>,
+    order: &[usize],
+) {
+    let mut sent = Vec::new();
+    for &uid in order {
+        let (to_sender, to_receiver) = msgs[uid].clone();
+        let uid = ClientID::new(uid as u64);
+        let (addr_sender, addr_receiver) = if policy.alice_is_ot_sender(uid) {
+            (addr_alice, addr_bob)
+        } else {
+            (addr_bob, addr_alice)
+        };
+        for (addr, msg) in [(addr_sender, to_sender), (addr_receiver, to_receiver)] {
+            let socket = TcpStream::connect(addr).await.unwrap();
+            let (conn, registered) =
+                TcpConnection::new_client_side(socket, uid, ClientToken::default());
+            registered.await.unwrap();
+            sent.push(conn.send_message_bytes(SendId::FIRST, msg));
+        }
+    }
+    for sent in sent {
+        sent.await
+            .expect("connection closed before the message was sent");
+    }
+}
+
+/// Clients connect out of uid order, and each server gets the messages of
+/// its clients in reverse order. The shares of each client are still its
+/// own, since the servers join the messages by uid.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_po2_round_with_scrambled_clients() {
+    // Alice is OT sender for uids 0, 1 and 3
+    let policy = PartitionPolicy::Ratio(2, 1);
+    let inputs_and_msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, rng) = client_input(uid, Protocol::Po2);
+            // not the seeds of the round with reconnect, which counts their
+            // expansions
+            let mut rng = StdRng::from_rng(rng).unwrap();
+            let client = Po2Client::<I>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            let msgs = (
+                serialize(&client.prepared_message_0),
+                serialize(&client.prepared_message_1),
+            );
+            (input, msgs)
+        })
+        .collect::<Vec<_>>();
+    let (inputs, msgs): (Vec<_>, Vec<_>) = inputs_and_msgs.into_iter().unzip();
+
+    let runs = run_round_with_clients(
+        |is_alice, listener, peer| {
+            let policy = policy.clone();
+            async move {
+                let session = ClientSession::accept(
+                    is_alice,
+                    policy.clone(),
+                    listener,
+                    NUM_CLIENTS,
+                    ClientToken::default(),
+                )
+                .await;
+                let mut recording = session.fetch_round(0).await.unwrap();
+                recording.alice.reverse();
+                recording.bob.reverse();
+                let data = ClientData::<I>::from_recording(
+                    is_alice,
+                    policy,
+                    MsgFormat::default(),
+                    recording,
+                )
+                .with_session(SESSION);
+                pipeline::run::<I, A>(GSIZE, DEFAULT_SEC_PARAM, is_alice, &data, &peer, false).await
+            }
+        },
+        |addr_alice, addr_bob| {
+            send_client_msgs_in_order(addr_alice, addr_bob, &policy, msgs, &[2, 0, 3, 1])
+        },
+    )
+    .await;
+
+    let [alice, bob] = &runs;
+    for run in &runs {
+        assert!(run.output.rejected_clients.is_empty());
+    }
+    for (uid, input) in inputs.iter().enumerate() {
+        assert_eq!(
+            reconstruct(
+                &alice.output.arith_shares[uid],
+                &bob.output.arith_shares[uid]
+            ),
+            input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>(),
+            "client {}",
+            uid
+        );
+    }
+    assert_eq!(
+        reconstruct(
+            &batch_sum(&alice.output.arith_shares),
+            &batch_sum(&bob.output.arith_shares)
+        ),
+        plaintext_sum()
+    );
+}
+
+/// The servers get the messages of the clients from in-memory pools, so the
+/// clients need no sockets.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_po2_round_with_in_memory_clients() {
+    let policy = PartitionPolicy::default();
+    let inputs_and_msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, rng) = client_input(uid, Protocol::Po2);
+            // not the seeds of the round with reconnect, which counts their
+            // expansions
+            let mut rng = StdRng::from_rng(rng).unwrap();
+            let client = Po2Client::<I>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (
+                input,
+                (client.msg_to_ot_sender(), client.msg_to_ot_receiver(false)),
+            )
+        })
+        .collect::<Vec<_>>();
+    let (inputs, msgs): (Vec<_>, Vec<_>) = inputs_and_msgs.into_iter().unzip();
+    // the message of each client to the server with global role `is_alice`
+    let msgs_to = |is_alice: bool| {
+        msgs.iter()
+            .enumerate()
+            .map(|(uid, (to_sender, to_receiver))| {
+                let uid = ClientID::new(uid as u64);
+                let msg = if policy.is_ot_sender(is_alice, uid) {
+                    to_sender
+                } else {
+                    to_receiver
+                };
+                (uid, BTreeMap::from([(RecvId::FIRST, msg.clone())]))
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+
+    let runs = run_round_with_clients(
+        |is_alice, _, peer| {
+            let pool = ClientsPool::in_memory(msgs_to(is_alice));
+            let session = ClientSession::from_pool(is_alice, policy.clone(), pool);
+            async move {
+                let data = ClientData::<I>::fetch_round(&session, 0, MsgFormat::default(), None)
+                    .await
+                    .with_session(SESSION);
+                pipeline::run::<I, A>(GSIZE, DEFAULT_SEC_PARAM, is_alice, &data, &peer, false).await
+            }
+        },
+        |_, _| async {},
+    )
+    .await;
+
+    let [alice, bob] = &runs;
+    for run in &runs {
+        assert!(run.output.rejected_clients.is_empty());
+    }
+    for (uid, input) in inputs.iter().enumerate() {
+        assert_eq!(
+            reconstruct(
+                &alice.output.arith_shares[uid],
+                &bob.output.arith_shares[uid]
+            ),
+            input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>(),
+            "client {}",
+            uid
+        );
+    }
+    assert_eq!(
+        reconstruct(
+            &batch_sum(&alice.output.arith_shares),
+            &batch_sum(&bob.output.arith_shares)
+        ),
+        plaintext_sum()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_po2_round_compressed() {
+    let policy = PartitionPolicy::default();
+    let msgs = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, rng) = client_input(uid, Protocol::Po2);
+            // not the seeds of the round with reconnect, which counts their
+            // expansions
+            let mut rng = StdRng::from_rng(rng).unwrap();
+            let client = Po2Client::<I>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (
+                serialize(&client.prepared_message_0),
+                serialize(&Lz4Compressed::new(&client.prepared_message_1)),
+            )
+        })
+        .collect();
+
+    let runs = run_round(&policy, msgs, |is_alice, listener, peer| {
+        let format = MsgFormat {
+            compressed: true,
+            ..Default::default()
+        };
+        run_server(is_alice, policy.clone(), format, listener, peer)
+    })
+    .await;
+
+    let [alice, bob] = &runs;
+    assert_eq!(
+        reconstruct(
+            &batch_sum(&alice.output.arith_shares),
+            &batch_sum(&bob.output.arith_shares)
+        ),
+        plaintext_sum()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_po2_round_with_different_gsizes() {
+    const GSIZES: [usize; 3] = [10, 17, 32];
+    let policy = PartitionPolicy::default();
+    let mut rng = StdRng::seed_from_u64(0x95);
+    let inputs = GSIZES
+        .iter()
+        .map(|&gsize| (0..gsize).map(|_| I::rand(&mut rng)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let msgs = inputs
+        .iter()
+        .enumerate()
+        .map(|(uid, input)| {
+            let client = Po2Client::<I>::new(
+                ClientID::new(uid as u64),
+                SESSION,
+                input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (
+                serialize(&client.prepared_message_0),
+                serialize(&client.prepared_message_1),
+            )
+        })
+        .collect();
+
+    let runs = run_round(&policy, msgs, |is_alice, listener, peer| {
+        let policy = policy.clone();
+        async move {
+            let data = ClientData::<I>::fetch_with_listener(
+                is_alice,
+                policy,
+                listener,
+                GSIZES.len(),
+                ClientToken::default(),
+                MsgFormat::default(),
+                None,
+            )
+            .await
+            .with_session(SESSION);
+            let max_gsize = GSIZES[GSIZES.len() - 1];
+            pipeline::run::<I, A>(max_gsize, DEFAULT_SEC_PARAM, is_alice, &data, &peer, false).await
+        }
+    })
+    .await;
+
+    let [alice, bob] = &runs;
+    for run in &runs {
+        assert!(run.output.rejected_clients.is_empty());
+        let lengths = run.output.arith_shares.iter().map(Vec::len);
+        assert_eq!(lengths.collect::<Vec<_>>(), GSIZES);
+    }
+    // shorter inputs are padded with zeros
+    let mut expected = vec![0; GSIZES[GSIZES.len() - 1]];
+    for input in &inputs {
+        for (sum, x) in expected.iter_mut().zip(input) {
+            *sum += x.as_uint::<A>();
+        }
+    }
+    let aggregate = |output: &PipelineOutput<A>| output.aggregate(UnequalLengths::ZeroPad);
+    assert_eq!(
+        reconstruct(&aggregate(&alice.output), &aggregate(&bob.output)),
+        expected
+    );
+    let truncated = |output: &PipelineOutput<A>| output.aggregate(UnequalLengths::Truncate);
+    assert_eq!(
+        reconstruct(&truncated(&alice.output), &truncated(&bob.output)),
+        expected[..GSIZES[0]]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_po2_round_with_split_inputs() {
+    // an odd split of an odd number of inputs, so that the two directions
+    // have different numbers of OTs, and neither is a whole chunk
+    const SPLIT_GSIZE: usize = 101;
+    const SPLIT: usize = 33;
+    let policy = PartitionPolicy::default();
+    let mut rng = StdRng::seed_from_u64(0x5b1);
+    let inputs = (0..NUM_CLIENTS)
+        .map(|_| {
+            (0..SPLIT_GSIZE)
+                .map(|_| I::rand(&mut rng))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let msgs = inputs
+        .iter()
+        .enumerate()
+        .map(|(uid, input)| {
+            let client = Po2SplitClient::<I>::with_split(
+                ClientID::new(uid as u64),
+                SESSION,
+                input,
+                SPLIT,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            );
+            (
+                serialize(&client.prepared_message_0),
+                serialize(&client.prepared_message_1),
+            )
+        })
+        .collect();
+
+    let runs = run_round(&policy, msgs, |is_alice, listener, peer| {
+        let policy = policy.clone();
+        async move {
+            let format = MsgFormat {
+                split: true,
+                ..Default::default()
+            };
+            let data = ClientData::<I>::fetch_with_listener(
+                is_alice,
+                policy,
+                listener,
+                NUM_CLIENTS,
+                ClientToken::default(),
+                format,
+                None,
+            )
+            .await
+            .with_session(SESSION);
+            pipeline::run::<I, A>(
+                SPLIT_GSIZE,
+                DEFAULT_SEC_PARAM,
+                is_alice,
+                &data,
+                &peer,
+                false,
+            )
+            .await
+        }
+    })
+    .await;
+
+    for run in &runs {
+        assert!(run.output.rejected_clients.is_empty());
+        // each server is OT sender of every client, on one side of the split
+        assert_eq!(run.output.num_ot_verified, Some(NUM_CLIENTS));
+    }
+    let [alice, bob] = &runs;
+    for ((share_alice, share_bob), input) in alice
+        .output
+        .arith_shares
+        .iter()
+        .zip(&bob.output.arith_shares)
+        .zip(&inputs)
+    {
+        let expected = input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>();
+        assert_eq!(reconstruct(share_alice, share_bob), expected);
+    }
+}
@@ -0,0 +1,237 @@
+//! Sessions of several rounds: the clients connect once and submit new inputs
+//! in each round, and the servers run every round over the same connections.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use bin_utils::{
+    client::client_round_inputs,
+    server::{MpcCommSnapshot, RunReport},
+    signing::ClientKeys,
+};
+use bridge::{
+    client_server::{ClientSession, PartitionPolicy},
+    mpc_conn::MpcConnection,
+    tcp_bridge::{ClientID, ClientToken},
+};
+use crypto_primitives::{cot::client::DEFAULT_SEC_PARAM, uint::UInt, utils::batch_sum};
+use elsa_client::{ClientConfig, ElsaClient, InputRing, Variant};
+use sha2::Sha256;
+use tokio::net::TcpListener;
+
+use crate::{
+    fixture::{GSIZE, NUM_CLIENTS, SESSION},
+    harness::{reconstruct, run_round_with_clients, ServerRun, A, C, I},
+};
+
+const ROUNDS: u64 = 3;
+
+/// Sum of the inputs of all clients in round `round`, in ring `A`.
+fn plaintext_round_sum(round: u64) -> Vec<A> {
+    let inputs = (0..NUM_CLIENTS as u64)
+        .map(|uid| {
+            client_round_inputs::<I>(uid, round, GSIZE)
+                .iter()
+                .map(|x| x.as_uint::<A>())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    batch_sum(&inputs)
+}
+
+/// Connect every client once, and submit its inputs of all rounds right
+/// away, so that the servers receive a round before they are done with the
+/// previous one.
+async fn submit_rounds(
+    variant: Variant,
+    policy: &PartitionPolicy,
+    addr_alice: SocketAddr,
+    addr_bob: SocketAddr,
+) {
+    let submitted = (0..NUM_CLIENTS as u64)
+        .map(|uid| {
+            let client = ElsaClient::new(ClientConfig {
+                uid: ClientID::new(uid),
+                session: SESSION,
+                partition: policy.clone(),
+                ..ClientConfig::new(
+                    variant,
+                    InputRing::U8,
+                    GSIZE,
+                    addr_alice.to_string(),
+                    addr_bob.to_string(),
+                )
+            });
+            tokio::spawn(async move {
+                let registered = client.connect().await.unwrap();
+                for round in 0..ROUNDS {
+                    let input = client_round_inputs::<I>(uid, round, GSIZE);
+                    let prepared = client.prepare(&input).unwrap();
+                    registered.submit_round(round, prepared).await.unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for submitted in submitted {
+        submitted.await.unwrap();
+    }
+}
+
+/// Output of each round of a session, with the traffic of the round.
+type SessionRuns<O> = Vec<(O, RunReport)>;
+
+async fn run_po2_session(
+    is_alice: bool,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+) -> SessionRuns<server_po2::pipeline::PipelineOutput<A>> {
+    use server_po2::{
+        client_msg::{ClientData, MsgFormat},
+        pipeline,
+    };
+
+    let session = ClientSession::accept(
+        is_alice,
+        PartitionPolicy::default(),
+        listener,
+        NUM_CLIENTS,
+        ClientToken::default(),
+    )
+    .await
+    .with_rounds(ROUNDS);
+    let mut runs = Vec::new();
+    for round in 0..ROUNDS {
+        let comm_before = MpcCommSnapshot::take(&peer);
+        let data = ClientData::<I>::fetch_round(&session, round, MsgFormat::default(), None)
+            .await
+            .with_session(SESSION);
+        let output = pipeline::run_round::<I, A>(
+            round,
+            GSIZE,
+            DEFAULT_SEC_PARAM,
+            is_alice,
+            &data,
+            &peer,
+            false,
+        )
+        .await;
+        let mut report = RunReport {
+            round: Some(round),
+            client_comm: data.comm_alice + data.comm_bob,
+            ..Default::default()
+        };
+        comm_before.report_since(&mut report, &peer);
+        runs.push((output, report));
+    }
+    runs
+}
+
+async fn run_mp_session(
+    is_alice: bool,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+) -> SessionRuns<server_mp::pipeline::PipelineOutput<A>> {
+    use server_mp::{client_msg::ClientData, pipeline};
+
+    let session = ClientSession::accept(
+        is_alice,
+        PartitionPolicy::default(),
+        listener,
+        NUM_CLIENTS,
+        ClientToken::default(),
+    )
+    .await
+    .with_rounds(ROUNDS);
+    let mut runs = Vec::new();
+    for round in 0..ROUNDS {
+        let comm_before = MpcCommSnapshot::take(&peer);
+        let data = ClientData::<I, C, Sha256>::fetch_round(
+            &session,
+            round,
+            GSIZE,
+            None,
+            Sha256::default,
+            &ClientKeys::default(),
+            None,
+        )
+        .await
+        .with_session(SESSION);
+        let output = pipeline::run_round::<I, A, C, _, _>(
+            round,
+            GSIZE,
+            DEFAULT_SEC_PARAM,
+            is_alice,
+            &data,
+            &peer,
+            Sha256::default,
+            false,
+        )
+        .await;
+        let mut report = RunReport {
+            round: Some(round),
+            client_comm: data.comm_alice + data.comm_bob,
+            ..Default::default()
+        };
+        comm_before.report_since(&mut report, &peer);
+        runs.push((output, report));
+    }
+    runs
+}
+
+/// Every round has traffic of its own, with the clients and with the peer.
+fn assert_comm_per_round<O>(runs: &[ServerRun<SessionRuns<O>>; 2]) {
+    for run in runs {
+        for (_, report) in &run.output {
+            assert!(report.client_comm > 0);
+            assert!(report.mpc_comm_sent > 0);
+            assert!(report.mpc_comm_recv > 0);
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_po2_session() {
+    let policy = PartitionPolicy::default();
+    let runs = run_round_with_clients(run_po2_session, |addr_alice, addr_bob| {
+        submit_rounds(Variant::Po2, &policy, addr_alice, addr_bob)
+    })
+    .await;
+
+    let [alice, bob] = &runs;
+    assert_eq!(alice.output.len(), ROUNDS as usize);
+    for (round, ((alice, _), (bob, _))) in alice.output.iter().zip(&bob.output).enumerate() {
+        assert_eq!(
+            reconstruct(
+                &batch_sum(&alice.arith_shares),
+                &batch_sum(&bob.arith_shares)
+            ),
+            plaintext_round_sum(round as u64),
+            "wrong aggregate in round {}",
+            round
+        );
+    }
+    assert_ne!(plaintext_round_sum(0), plaintext_round_sum(1));
+    assert_comm_per_round(&runs);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_mp_session() {
+    let policy = PartitionPolicy::default();
+    let runs = run_round_with_clients(run_mp_session, |addr_alice, addr_bob| {
+        submit_rounds(Variant::Mp, &policy, addr_alice, addr_bob)
+    })
+    .await;
+
+    let [alice, bob] = &runs;
+    assert_eq!(alice.output.len(), ROUNDS as usize);
+    for (round, ((alice, _), (bob, _))) in alice.output.iter().zip(&bob.output).enumerate() {
+        assert!(alice.failed_clients.is_empty());
+        assert!(bob.failed_clients.is_empty());
+        assert_eq!(
+            reconstruct(&alice.aggregate(), &bob.aggregate()),
+            plaintext_round_sum(round as u64),
+            "wrong aggregate in round {}",
+            round
+        );
+    }
+    assert_comm_per_round(&runs);
+}
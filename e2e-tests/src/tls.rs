@@ -0,0 +1,128 @@
+//! Po2 round with the clients connected to both servers over TLS, with a
+//! self-signed certificate.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use bridge::{
+    client_server::{ClientSession, PartitionPolicy},
+    metrics::HEADER_SIZE,
+    mpc_conn::MpcConnection,
+    tcp_bridge::{ClientID, ClientToken},
+    tls::{
+        rustls::{Certificate, PrivateKey},
+        ClientTls, ServerTls,
+    },
+};
+use crypto_primitives::{
+    cot::client::DEFAULT_SEC_PARAM, message::size::Protocol, utils::batch_sum,
+};
+use elsa_client::{ClientConfig, ElsaClient, InputRing, Variant};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use server_po2::{
+    client_msg::{ClientData, MsgFormat},
+    pipeline::{self, PipelineOutput},
+};
+use tokio::net::TcpListener;
+
+use crate::{
+    fixture::{client_input, plaintext_sum, GSIZE, NUM_CLIENTS, SESSION},
+    harness::{reconstruct, run_round_with_clients, A, I},
+};
+
+/// Output of a server, and the bytes it received from the clients.
+async fn run_server(
+    is_alice: bool,
+    tls: ServerTls,
+    listener: TcpListener,
+    peer: Arc<MpcConnection>,
+) -> (PipelineOutput<A>, usize) {
+    let session = ClientSession::accept_with_tls(
+        is_alice,
+        PartitionPolicy::default(),
+        listener,
+        NUM_CLIENTS,
+        ClientToken::default(),
+        Some(tls),
+    )
+    .await;
+    let data = ClientData::<I>::fetch_round(&session, 0, MsgFormat::default(), None)
+        .await
+        .with_session(SESSION);
+    let output =
+        pipeline::run::<I, A>(GSIZE, DEFAULT_SEC_PARAM, is_alice, &data, &peer, false).await;
+    (output, data.comm_alice + data.comm_bob)
+}
+
+/// Submit the input of every client over TLS. Returns the bytes the clients
+/// send to both servers, as counted by the servers: with the registrations,
+/// but without the version messages.
+async fn submit_over_tls(tls: ClientTls, addr_alice: SocketAddr, addr_bob: SocketAddr) -> usize {
+    let registration = HEADER_SIZE as usize + 40;
+    let submitted = (0..NUM_CLIENTS)
+        .map(|uid| {
+            let (input, mut rng) = client_input(uid, Protocol::Po2);
+            // not the seeds of the other Po2 rounds, which count their
+            // expansions
+            let mut rng = StdRng::seed_from_u64(rng.gen::<u64>() ^ 0x715);
+            let client = ElsaClient::new(ClientConfig {
+                uid: ClientID::new(uid as u64),
+                session: SESSION,
+                tls: Some(tls.clone()),
+                ..ClientConfig::new(
+                    Variant::Po2,
+                    InputRing::U8,
+                    GSIZE,
+                    addr_alice.to_string(),
+                    addr_bob.to_string(),
+                )
+            });
+            let prepared = client.prepare_with_rng(&input, &mut rng).unwrap();
+            let sent = 2 * registration
+                + 2 * HEADER_SIZE as usize
+                + prepared.to_ot_sender.len()
+                + prepared.to_ot_receiver.len();
+            tokio::spawn(async move {
+                client.submit(prepared).await.unwrap();
+                sent
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut total = 0;
+    for submitted in submitted {
+        total += submitted.await.unwrap();
+    }
+    total
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_po2_round_over_tls() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let der = Certificate(cert.serialize_der().unwrap());
+    let key = PrivateKey(cert.serialize_private_key_der());
+    let server_tls = ServerTls::new(vec![der.clone()], key).unwrap();
+    let client_tls = ClientTls::new("localhost", &[der]).unwrap();
+
+    let mut client_comm = 0;
+    let client_comm_mut = &mut client_comm;
+    let runs = run_round_with_clients(
+        |is_alice, listener, peer| run_server(is_alice, server_tls.clone(), listener, peer),
+        |addr_alice, addr_bob| async move {
+            *client_comm_mut = submit_over_tls(client_tls, addr_alice, addr_bob).await;
+        },
+    )
+    .await;
+
+    let [alice, bob] = &runs;
+    let ((alice, comm_alice), (bob, comm_bob)) = (&alice.output, &bob.output);
+    assert!(alice.rejected_clients.is_empty());
+    assert!(bob.rejected_clients.is_empty());
+    assert_eq!(
+        reconstruct(
+            &batch_sum(&alice.arith_shares),
+            &batch_sum(&bob.arith_shares)
+        ),
+        plaintext_sum()
+    );
+    // the counters see the messages, not the TLS records
+    assert_eq!(comm_alice + comm_bob, client_comm);
+}
@@ -0,0 +1,17 @@
+//! A round with a compute pool of one thread. The pool is global to the
+//! process, so this runs in its own test binary.
+
+use bin_utils::runtime::install_compute_pool;
+use e2e_tests::demo::{self, DemoConfig};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_demo_with_one_compute_thread() {
+    assert_eq!(install_compute_pool(Some(1)).unwrap(), 1);
+
+    let dir = std::env::temp_dir().join(format!("elsa-one-thread-test-{}", std::process::id()));
+    demo::run(&DemoConfig::new(8, 8, 5), &dir).await.unwrap();
+    for check in demo::verify(&dir) {
+        assert!(check.passed(), "{}", check);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,75 @@
+use bridge::tcp_bridge::ClientID;
+use bytes::Bytes;
+use crypto_primitives::{
+    bits::PackedBits,
+    cot::client::{validate_bit_cots, COTGen},
+    message::bits::{BitAggregation, ClientBitsMsgToAlice, ClientBitsMsgToBob},
+};
+use rand::Rng;
+use serialize::Communicate;
+
+/// Client of the bits protocol, whose inputs are single bits, see
+/// `crypto_primitives::message::bits`.
+pub struct BitsClient {
+    pub prepared_message_0: ClientBitsMsgToAlice,
+    pub prepared_message_1: ClientBitsMsgToBob,
+}
+
+impl BitsClient {
+    /// Prepare the messages of client `uid` with inputs `bits`, for servers
+    /// that aggregate by `aggregation`. Only `BitAggregation::Sum` needs
+    /// COTs, one per bit, with the shares of Bob as choices.
+    pub fn new<R: Rng>(
+        uid: ClientID,
+        bits: &PackedBits,
+        aggregation: BitAggregation,
+        rng: &mut R,
+    ) -> Self {
+        let (shares_0, shares_1) = bits.to_boolean_shares(rng);
+        let (cot_s, cot_r) = match aggregation {
+            BitAggregation::Xor => (None, None),
+            BitAggregation::Sum => {
+                let delta = COTGen::sample_delta(rng);
+                let (cot_s, cot_r) = COTGen::sample_cots_using_selected_bits_for(
+                    rng,
+                    uid.id,
+                    shares_1.iter(),
+                    shares_1.len(),
+                    delta,
+                    0,
+                );
+                (Some(cot_s), Some(cot_r))
+            },
+        };
+        BitsClient {
+            prepared_message_0: ClientBitsMsgToAlice {
+                shares: shares_0,
+                cot: cot_s,
+            },
+            prepared_message_1: ClientBitsMsgToBob {
+                shares: shares_1,
+                cot: cot_r,
+            },
+        }
+    }
+
+    /// The message to Alice, the OT sender.
+    pub fn msg_to_ot_sender(&self) -> Bytes {
+        (&self.prepared_message_0).into_bytes_owned()
+    }
+
+    /// The message to Bob, the OT receiver.
+    pub fn msg_to_ot_receiver(&self) -> Bytes {
+        (&self.prepared_message_1).into_bytes_owned()
+    }
+
+    /// Check that the COTs of the messages of client `uid`, if any, match the
+    /// shares of Bob, see `ClientConfig::self_check`.
+    pub fn self_check(&self, uid: ClientID) -> crate::Result<()> {
+        let msg_bob = &self.prepared_message_1;
+        if let (Some(cot_s), Some(cot_r)) = (&self.prepared_message_0.cot, &msg_bob.cot) {
+            validate_bit_cots(uid.id, cot_s, cot_r, &msg_bob.shares)?;
+        }
+        Ok(())
+    }
+}
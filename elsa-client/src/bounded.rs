@@ -0,0 +1,86 @@
+use bridge::tcp_bridge::ClientID;
+use bytes::Bytes;
+use crypto_primitives::{
+    bits::BitsLE,
+    bounded::{BoundError, InputBound},
+    message::bounded::{ClientBoundedMsgToAlice, ClientBoundedMsgToBob},
+    uint::UInt,
+};
+use rand::Rng;
+use serialize::Communicate;
+
+use crate::po2::{Po2Client, SingleRoundClient};
+
+/// Client of the power of 2 protocol that also proves that its inputs are
+/// below a public bound, see `crypto_primitives::bounded`.
+pub struct BoundedClient<I: UInt> {
+    pub prepared_message_0: ClientBoundedMsgToAlice,
+    pub prepared_message_1: ClientBoundedMsgToBob<I>,
+}
+
+impl<I: UInt> BoundedClient<I> {
+    /// Prepare the messages of client `uid` in `session`. Fails if an input
+    /// is not below `bound`.
+    pub fn prepare_bounded<R: Rng>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        bound: &InputBound<I>,
+        rng: &mut R,
+        sec_param: usize,
+    ) -> Result<Self, BoundError> {
+        let encodings = input
+            .iter()
+            .map(|&x| bound.encode(x))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::with_encodings(
+            uid, session, input, bound, &encodings, rng, sec_param,
+        ))
+    }
+
+    /// Same as `prepare_bounded`, with the given encodings of the inputs,
+    /// which the servers reject unless they encode the inputs. For testing.
+    ///
+    /// # Panics
+    /// If there is not one encoding per input.
+    pub fn with_encodings<R: Rng>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        bound: &InputBound<I>,
+        encodings: &[(BitsLE<I>, BitsLE<I>)],
+        rng: &mut R,
+        sec_param: usize,
+    ) -> Self {
+        let po2 = Po2Client::new(uid, session, input, rng, sec_param);
+        let (po2_msg_0, po2_msg_1) = (po2.prepared_message_0, po2.prepared_message_1);
+        let (check_0, check_1) = bound.sample_check(
+            rng,
+            uid.id,
+            po2_msg_0.inputs_0,
+            &po2_msg_1.inputs_1,
+            encodings,
+            sec_param,
+        );
+        BoundedClient {
+            prepared_message_0: ClientBoundedMsgToAlice {
+                po2_msg: po2_msg_0,
+                check: check_0,
+            },
+            prepared_message_1: ClientBoundedMsgToBob {
+                po2_msg: po2_msg_1,
+                check: check_1,
+            },
+        }
+    }
+
+    /// The message to Alice, the OT sender.
+    pub fn msg_to_ot_sender(&self) -> Bytes {
+        (&self.prepared_message_0).into_bytes_owned()
+    }
+
+    /// The message to Bob, the OT receiver.
+    pub fn msg_to_ot_receiver(&self) -> Bytes {
+        (&self.prepared_message_1).into_bytes_owned()
+    }
+}
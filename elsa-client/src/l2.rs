@@ -0,0 +1,114 @@
+use bridge::tcp_bridge::ClientID;
+use bytes::Bytes;
+use crypto_primitives::{
+    a2s::{make_witness_shares, WitnessShareSeedToAlice, WitnessSharesToBob},
+    bits::{batch_make_boolean_shares, ShareCommitment, ShareSalt},
+    cot::client::{
+        num_additional_ot_needed, validate_cot_pair, B2ACOTToAlice, B2ACOTToBob, COTGen,
+    },
+    message::l2::{ClientL2MsgToAlice, ClientL2MsgToBob},
+    square_corr::{batch_make_sqcorr_shares, validate_sqcorr_pair},
+    uint::UInt,
+};
+use rand::Rng;
+use serialize::Communicate;
+
+use crate::po2::SingleRoundClient;
+
+/// Client on input ring `I`, and correlation ring `C`
+pub struct L2Client<I: UInt, C: UInt> {
+    pub prepared_message_0: ClientL2MsgToAlice,
+    pub prepared_message_1: ClientL2MsgToBob<I, C>,
+    /// Shares of the witness bits of the bound check, sent after the messages,
+    /// if the servers check a bound.
+    pub witness: Option<(WitnessShareSeedToAlice, WitnessSharesToBob<C>)>,
+}
+
+impl<I: UInt, C: UInt> L2Client<I, C> {
+    /// Prepare the messages of client `uid` in `session`, with the witness
+    /// bits of the bound check if any, see `a2s::batch_bound_witness`. Each
+    /// witness bit gets square correlations like an input.
+    pub fn with_witness<R: Rng>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        witness: Option<&[C]>,
+        rng: &mut R,
+        sec_param: usize,
+    ) -> Self {
+        let gsize = input.len();
+        let (input_0, input_1) = batch_make_boolean_shares(rng, input.iter().map(|x| x.bits_le()));
+        let delta = COTGen::sample_delta(rng);
+        let num_additional_cot = num_additional_ot_needed(gsize * I::NUM_BITS, sec_param);
+        let (cot_s, cot_r) = if cfg!(feature = "no-ot") {
+            (B2ACOTToAlice::default(), B2ACOTToBob::default())
+        } else {
+            COTGen::sample_cots_for(rng, uid.id, &input_1, delta, num_additional_cot)
+        };
+
+        // generate correlation, the ones of the witness bits after the inputs
+        let num_squared = gsize + witness.map_or(0, <[C]>::len);
+        let (corr_0, corr_1, ..) = batch_make_sqcorr_shares(rng, num_squared * 2);
+        let witness = witness.map(|witness| {
+            let (to_alice, to_bob, _) = make_witness_shares(rng, witness);
+            (to_alice, to_bob)
+        });
+
+        let salt = ShareSalt::sample(rng);
+        let commitment = ShareCommitment::new(uid.id, session, input_0, salt, &input_1);
+        let msg0 = ClientL2MsgToAlice::new(gsize, input_0, commitment, cot_s, corr_0);
+        let msg1 = ClientL2MsgToBob::new(input_1, commitment, salt, cot_r, corr_1);
+
+        L2Client {
+            prepared_message_0: msg0,
+            prepared_message_1: msg1,
+            witness,
+        }
+    }
+}
+
+impl<I: UInt, C: UInt> SingleRoundClient<I> for L2Client<I, C> {
+    fn new<R: Rng>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        rng: &mut R,
+        sec_param: usize,
+    ) -> Self {
+        Self::with_witness(uid, session, input, None, rng, sec_param)
+    }
+
+    fn msg_to_ot_sender(&self) -> Bytes {
+        match &self.witness {
+            Some((to_alice, _)) => (&self.prepared_message_0, to_alice).into_bytes_owned(),
+            None => (&self.prepared_message_0).into_bytes_owned(),
+        }
+    }
+
+    fn msg_to_ot_receiver(&self, compress: bool) -> Bytes {
+        assert!(!compress, "server-l2 does not decompress client messages");
+        match &self.witness {
+            Some((_, to_bob)) => (&self.prepared_message_1, to_bob).into_bytes_owned(),
+            None => (&self.prepared_message_1).into_bytes_owned(),
+        }
+    }
+
+    fn self_check(&self, uid: ClientID, sec_param: usize) -> crate::Result<()> {
+        let (msg_alice, msg_bob) = (&self.prepared_message_0, &self.prepared_message_1);
+        let inputs_1 = msg_bob.inputs_1();
+        // without OTs, there is nothing to check
+        if !cfg!(feature = "no-ot") {
+            validate_cot_pair(uid.id, msg_alice.cot(), msg_bob.cot(), inputs_1, sec_param)?;
+        }
+        let num_witness = self
+            .witness
+            .as_ref()
+            .map_or(0, |(_, to_bob)| to_bob.w.len());
+        validate_sqcorr_pair(
+            &msg_alice.square_corr,
+            &msg_bob.square_corr,
+            inputs_1.len() + num_witness,
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,903 @@
+//! ELSA clients as a library, for applications that submit their inputs to a
+//! pair of ELSA servers, e.g. a federated learning framework. The client
+//! binaries are thin wrappers around it.
+//!
+//! A client is set up with a [`ClientConfig`], prepares its messages from its
+//! inputs, and then submits them:
+//!
+//! ```no_run
+//! # async fn run() -> elsa_client::Result<()> {
+//! use bridge::tcp_bridge::ClientID;
+//! use elsa_client::{ClientConfig, ElsaClient, InputRing, Variant};
+//!
+//! let config = ClientConfig {
+//!     uid: ClientID::new(3),
+//!     ..ClientConfig::new(Variant::Po2, InputRing::U8, 4, "alice:6666", "bob:6667")
+//! };
+//! let client = ElsaClient::new(config);
+//! let prepared = client.prepare(&[1u8, 2, 3, 4])?;
+//! let receipt = client.submit(prepared).await?;
+//! assert_eq!(receipt.uid, ClientID::new(3));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! For servers that run several rounds of a session, e.g. one per training
+//! round, a client connects once with [`ElsaClient::connect`] and submits the
+//! inputs of each round with [`RegisteredClient::submit_round`].
+//!
+//! The protocol of each variant is in its own module, for callers that want
+//! the messages without the connection, e.g. the end-to-end tests.
+
+use std::{fmt::Debug, time::Duration};
+
+use bridge::{
+    client_server::{tcp_connect_timeout, PartitionPolicy},
+    id_tracker::{RecvId, SendId},
+    tcp_bridge::{ClientID, ClientToken, TcpConnection},
+    tls::ClientTls,
+    BridgeError,
+};
+use bytes::Bytes;
+#[cfg(feature = "signed-uploads")]
+use crypto_primitives::message::signed::{SignedMessage, SigningKey};
+use crypto_primitives::{
+    a2s::batch_bound_witness, bits::PackedBits, const_assert, cot::client::DEFAULT_SEC_PARAM,
+    uint::UInt,
+};
+pub use crypto_primitives::{
+    aggregate::{RingBudget, RingOverflow},
+    cot::client::CotInconsistency,
+    message::bits::BitAggregation,
+    square_corr::CorrError,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+#[cfg(feature = "signed-uploads")]
+use serialize::Communicate;
+use serialize::UseCast;
+use sha2::Sha256;
+use thiserror::Error;
+use tracing::debug;
+
+pub mod bits;
+pub mod bounded;
+pub mod l2;
+pub mod mp;
+pub mod mp_po2;
+pub mod po2;
+
+use crate::{
+    bits::BitsClient,
+    l2::L2Client,
+    po2::{Po2Client, Po2SplitClient, SingleRoundClient},
+};
+
+#[allow(clippy::upper_case_acronyms)]
+type ARITH = u64;
+#[allow(clippy::upper_case_acronyms)]
+type CORR = u128;
+const_assert!(CORR::NUM_BITS > ARITH::NUM_BITS);
+// B2A shifts the highest bit of an input within `ARITH`
+const_assert!(ARITH::NUM_BITS >= InputRing::U64.num_bits());
+
+/// Transcript hash of the malicious privacy variants.
+type Hasher = Sha256;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    /// The inputs do not match the config, e.g. a value out of the input
+    /// ring.
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+    /// The config asks for something the variant does not support.
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+    /// The server cannot be reached within `ClientConfig::connect_timeout`,
+    /// or the TLS handshake with it fails.
+    #[error("cannot connect to server {addr}: {source}")]
+    Connect { addr: String, source: BridgeError },
+    /// The server did not accept the registration, e.g. because it speaks an
+    /// incompatible wire format.
+    #[error("server {addr} rejected the registration: {source}")]
+    Rejected { addr: String, source: BridgeError },
+    /// The connection closed before a message was written.
+    #[error("connection to server {addr} closed before the message was sent")]
+    Closed { addr: String },
+    /// The OT sender did not start phase 2 within
+    /// `ClientConfig::phase2_timeout`.
+    #[error("phase 2 did not start within {0:?}")]
+    Phase2Timeout(Duration),
+    /// Phase 2 failed otherwise, e.g. the OT sender closed the connection.
+    #[error("phase 2 failed: {0}")]
+    Phase2(BridgeError),
+    /// The prepared COTs do not match the inputs, with
+    /// `ClientConfig::self_check`.
+    #[error("self-check of the COTs failed: {0}")]
+    CotSelfCheck(#[from] CotInconsistency),
+    /// The prepared square correlations are not squares, with
+    /// `ClientConfig::self_check`.
+    #[error("self-check of the square correlations failed: {0}")]
+    SqCorrSelfCheck(#[from] CorrError),
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// Protocol of the client. It must match the servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// Semi-honest, power-of-two bounded inputs (server-po2).
+    Po2,
+    /// Semi-honest, L2 norm bounded inputs (server-l2).
+    L2,
+    /// Malicious privacy, L2 norm bounded inputs (server-mp).
+    Mp,
+    /// Malicious privacy, power-of-two bounded inputs (server-mp-po2). The
+    /// only variant with a second phase.
+    MpPo2,
+    /// Semi-honest, single-bit inputs aggregated by XOR or by count
+    /// (server-po2 with `--protocol bits`). The input ring is ignored.
+    Bits(BitAggregation),
+}
+
+/// Ring of the inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputRing {
+    U8,
+    U32,
+    U64,
+}
+
+impl InputRing {
+    pub const fn num_bits(&self) -> usize {
+        match self {
+            InputRing::U8 => 8,
+            InputRing::U32 => 32,
+            InputRing::U64 => 64,
+        }
+    }
+
+    /// How many clients the servers can aggregate without wrapping around,
+    /// e.g. `InputRing::U8.ring_budget().check(num_clients)`.
+    pub fn ring_budget(&self) -> RingBudget {
+        RingBudget::with_bits(self.num_bits(), ARITH::NUM_BITS)
+    }
+}
+
+/// What a client sends, and where.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub variant: Variant,
+    pub input_ring: InputRing,
+    /// Number of inputs.
+    pub gsize: usize,
+    /// Address of server slice b=0.
+    pub server_alice: String,
+    /// Address of server slice b=1.
+    pub server_bob: String,
+    /// Registered with both servers. The clients of a round must have
+    /// distinct uids.
+    pub uid: ClientID,
+    /// Token to register with, the same as on the servers.
+    pub token: ClientToken,
+    /// Session id bound into the share commitments.
+    pub session: u64,
+    /// Statistical security parameter for OT verification.
+    pub sec_param: usize,
+    /// Which server is OT sender, the same as on the servers.
+    pub partition: PartitionPolicy,
+    /// Compress the message to the OT receiver with LZ4. Po2 only, and the
+    /// servers must decompress.
+    pub compress: bool,
+    /// Split the inputs at this index: the OT sender converts the inputs
+    /// before it, and the OT receiver the others, as OT sender. Po2 only,
+    /// and the servers must expect split messages.
+    pub split: Option<usize>,
+    /// How long to keep retrying to connect to a server, and then to wait for
+    /// it to accept the registration. `None` waits forever.
+    pub connect_timeout: Option<Duration>,
+    /// How long to wait for the OT sender to start phase 2, i.e. until all
+    /// clients of the round have sent phase 1. `None` waits forever.
+    pub phase2_timeout: Option<Duration>,
+    /// Connect to both servers over TLS, checking their certificates. `None`
+    /// connects in plaintext.
+    pub tls: Option<ClientTls>,
+    /// Check the COTs and square correlations of the messages when preparing
+    /// them, before anything is sent. Costs about as much as preparing.
+    pub self_check: bool,
+    /// Bound of every input that the servers check after A2S, the same as
+    /// on the servers. The messages carry the witness bits of the check, so
+    /// preparing fails if an input is above it. L2 and Mp only.
+    pub bound: Option<u64>,
+    /// Sign the uploads with this key, which the servers know by uid. Mp
+    /// only, and required with `signed-uploads`.
+    #[cfg(feature = "signed-uploads")]
+    pub signing_key: Option<SigningKey>,
+}
+
+impl ClientConfig {
+    /// Client 0 of session 0, with the defaults of the client binaries, and
+    /// timeouts of 30 seconds to connect and 10 minutes for phase 2.
+    pub fn new(
+        variant: Variant,
+        input_ring: InputRing,
+        gsize: usize,
+        server_alice: impl Into<String>,
+        server_bob: impl Into<String>,
+    ) -> Self {
+        ClientConfig {
+            variant,
+            input_ring,
+            gsize,
+            server_alice: server_alice.into(),
+            server_bob: server_bob.into(),
+            uid: ClientID::new(0),
+            token: ClientToken::default(),
+            session: 0,
+            sec_param: DEFAULT_SEC_PARAM,
+            partition: PartitionPolicy::default(),
+            compress: false,
+            split: None,
+            connect_timeout: Some(Duration::from_secs(30)),
+            phase2_timeout: Some(Duration::from_secs(600)),
+            tls: None,
+            self_check: false,
+            bound: None,
+            #[cfg(feature = "signed-uploads")]
+            signing_key: None,
+        }
+    }
+
+    /// Addresses of the OT sender and the OT receiver of this client.
+    fn ot_sender_and_receiver(&self) -> (&str, &str) {
+        if self.partition.alice_is_ot_sender(self.uid) {
+            (&self.server_alice, &self.server_bob)
+        } else {
+            (&self.server_bob, &self.server_alice)
+        }
+    }
+}
+
+/// Serialized messages of a client, ready to be submitted.
+pub struct PreparedMessages {
+    pub to_ot_sender: Bytes,
+    pub to_ot_receiver: Bytes,
+    /// Reply to the chi seed of the OT sender in phase 2, for `MpPo2`.
+    phase2: Option<Box<dyn FnOnce(u64) -> Bytes + Send>>,
+}
+
+impl PreparedMessages {
+    fn single_round<I: UInt, C: SingleRoundClient<I>>(
+        client: C,
+        config: &ClientConfig,
+    ) -> Result<Self> {
+        if config.self_check {
+            client.self_check(config.uid, config.sec_param)?;
+        }
+        Ok(PreparedMessages {
+            to_ot_sender: client.msg_to_ot_sender(),
+            to_ot_receiver: client.msg_to_ot_receiver(config.compress),
+            phase2: None,
+        })
+    }
+
+    /// Sign both messages with `key`, as `SignedMessage`s. Single round only.
+    #[cfg(feature = "signed-uploads")]
+    fn signed(self, key: &SigningKey) -> Self {
+        assert!(self.phase2.is_none(), "phase 2 replies are not signed");
+        PreparedMessages {
+            to_ot_sender: SignedMessage::sign(key, self.to_ot_sender).into_bytes_owned(),
+            to_ot_receiver: SignedMessage::sign(key, self.to_ot_receiver).into_bytes_owned(),
+            phase2: None,
+        }
+    }
+}
+
+impl Debug for PreparedMessages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreparedMessages")
+            .field("to_ot_sender", &self.to_ot_sender.len())
+            .field("to_ot_receiver", &self.to_ot_receiver.len())
+            .field("phase2", &self.phase2.is_some())
+            .finish()
+    }
+}
+
+/// What a client sent in a successful submission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubmitReceipt {
+    pub uid: ClientID,
+    /// Bytes of the messages to the OT sender, including phase 2, and to the
+    /// OT receiver, without headers.
+    pub bytes_to_ot_sender: usize,
+    pub bytes_to_ot_receiver: usize,
+}
+
+/// One client, see the [crate documentation](crate).
+#[derive(Debug, Clone)]
+pub struct ElsaClient {
+    config: ClientConfig,
+}
+
+impl ElsaClient {
+    pub fn new(config: ClientConfig) -> Self {
+        ElsaClient { config }
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Prepare the messages of `inputs`, with fresh randomness.
+    pub fn prepare(&self, inputs: &[impl Into<u64> + Copy]) -> Result<PreparedMessages> {
+        self.prepare_with_rng(inputs, &mut StdRng::from_entropy())
+    }
+
+    /// Same as `prepare`, with the randomness of `rng`, e.g. to reproduce the
+    /// messages of a run.
+    pub fn prepare_with_rng<R: Rng>(
+        &self,
+        inputs: &[impl Into<u64> + Copy],
+        rng: &mut R,
+    ) -> Result<PreparedMessages> {
+        let config = &self.config;
+        if config.compress && config.variant != Variant::Po2 {
+            return Err(ClientError::InvalidConfig(format!(
+                "compression is only supported by Po2, not {:?}",
+                config.variant
+            )));
+        }
+        if config.split.is_some() && config.variant != Variant::Po2 {
+            return Err(ClientError::InvalidConfig(format!(
+                "split inputs are only supported by Po2, not {:?}",
+                config.variant
+            )));
+        }
+        if config.bound.is_some() && !matches!(config.variant, Variant::L2 | Variant::Mp) {
+            return Err(ClientError::InvalidConfig(format!(
+                "bounds are only supported by L2 and Mp, not {:?}",
+                config.variant
+            )));
+        }
+        if let Some(split) = config.split.filter(|split| *split > config.gsize) {
+            return Err(ClientError::InvalidConfig(format!(
+                "split {} after the last of {} inputs",
+                split, config.gsize
+            )));
+        }
+        #[cfg(feature = "signed-uploads")]
+        let signing_key = match (&config.signing_key, config.variant) {
+            (Some(key), Variant::Mp) => key,
+            (None, _) => {
+                return Err(ClientError::InvalidConfig(
+                    "signed uploads need a signing key".to_string(),
+                ))
+            },
+            (Some(_), variant) => {
+                return Err(ClientError::InvalidConfig(format!(
+                    "signed uploads are only supported by Mp, not {:?}",
+                    variant
+                )))
+            },
+        };
+        if inputs.len() != config.gsize {
+            return Err(ClientError::InvalidInput(format!(
+                "expected {} inputs, got {}",
+                config.gsize,
+                inputs.len()
+            )));
+        }
+        if let Variant::Bits(aggregation) = config.variant {
+            return self.prepare_bits(inputs, aggregation, rng);
+        }
+        let prepared = match config.input_ring {
+            InputRing::U8 => self.prepare_in::<u8, _>(inputs, rng),
+            InputRing::U32 => self.prepare_in::<u32, _>(inputs, rng),
+            InputRing::U64 => self.prepare_in::<u64, _>(inputs, rng),
+        }?;
+        #[cfg(feature = "signed-uploads")]
+        let prepared = prepared.signed(signing_key);
+        Ok(prepared)
+    }
+
+    fn prepare_bits<R: Rng>(
+        &self,
+        inputs: &[impl Into<u64> + Copy],
+        aggregation: BitAggregation,
+        rng: &mut R,
+    ) -> Result<PreparedMessages> {
+        let bits = inputs
+            .iter()
+            .map(|&x| match x.into() {
+                0 => Ok(false),
+                1 => Ok(true),
+                x => Err(ClientError::InvalidInput(format!("{} is not a bit", x))),
+            })
+            .collect::<Result<PackedBits>>()?;
+        let client = BitsClient::new(self.config.uid, &bits, aggregation, rng);
+        if self.config.self_check {
+            client.self_check(self.config.uid)?;
+        }
+        Ok(PreparedMessages {
+            to_ot_sender: client.msg_to_ot_sender(),
+            to_ot_receiver: client.msg_to_ot_receiver(),
+            phase2: None,
+        })
+    }
+
+    fn prepare_in<I: UInt, R: Rng>(
+        &self,
+        inputs: &[impl Into<u64> + Copy],
+        rng: &mut R,
+    ) -> Result<PreparedMessages> {
+        let input = inputs
+            .iter()
+            .map(|&x| {
+                let x: u64 = x.into();
+                I::from(x).ok_or_else(|| {
+                    ClientError::InvalidInput(format!("{} does not fit in {} bits", x, I::NUM_BITS))
+                })
+            })
+            .collect::<Result<Vec<I>>>()?;
+        let config = &self.config;
+        let ClientConfig {
+            uid,
+            session,
+            sec_param,
+            split,
+            self_check,
+            ..
+        } = *config;
+        let bound = config
+            .bound
+            .map(|bound| {
+                I::from(bound).ok_or_else(|| {
+                    ClientError::InvalidConfig(format!(
+                        "bound {} does not fit in {} bits",
+                        bound,
+                        I::NUM_BITS
+                    ))
+                })
+            })
+            .transpose()?;
+        let witness = bound
+            .map(|bound| {
+                batch_bound_witness::<I, CORR>(&input, bound).ok_or_else(|| {
+                    ClientError::InvalidInput(format!("an input is above the bound {}", bound))
+                })
+            })
+            .transpose()?;
+        let prepared = match config.variant {
+            Variant::Po2 => match split {
+                Some(split) => PreparedMessages::single_round(
+                    Po2SplitClient::with_split(uid, session, &input, split, rng, sec_param),
+                    config,
+                )?,
+                None => PreparedMessages::single_round(
+                    Po2Client::new(uid, session, &input, rng, sec_param),
+                    config,
+                )?,
+            },
+            Variant::L2 => PreparedMessages::single_round(
+                L2Client::<I, CORR>::with_witness(
+                    uid,
+                    session,
+                    &input,
+                    witness.as_deref(),
+                    rng,
+                    sec_param,
+                ),
+                config,
+            )?,
+            Variant::Mp => {
+                let client = mp::Client::<I, CORR, Hasher>::prepare_with_witness::<ARITH, _, _>(
+                    uid,
+                    session,
+                    &input,
+                    bound.zip(witness.as_deref()),
+                    rng,
+                    sec_param,
+                    Hasher::default,
+                );
+                if self_check {
+                    client.self_check(uid, sec_param)?;
+                }
+                PreparedMessages {
+                    to_ot_sender: client.msg_to_alice(),
+                    to_ot_receiver: client.msg_to_bob(),
+                    phase2: None,
+                }
+            },
+            Variant::MpPo2 => {
+                let client = mp_po2::Client::<I, Hasher>::prepare_phase1::<ARITH, _, _>(
+                    uid,
+                    session,
+                    &input,
+                    rng,
+                    sec_param,
+                    Hasher::default,
+                );
+                if self_check {
+                    client.self_check(uid, sec_param)?;
+                }
+                PreparedMessages {
+                    to_ot_sender: client.msg_to_alice(),
+                    to_ot_receiver: client.msg_to_bob(),
+                    phase2: Some(Box::new(move |chi_seed| client.phase2_reply(chi_seed))),
+                }
+            },
+            Variant::Bits(_) => unreachable!("bits have no input ring, see `prepare_bits`"),
+        };
+        Ok(prepared)
+    }
+
+    /// Connect to both servers and register. Servers wait for all clients of
+    /// a round to register, so a caller with many clients should connect all
+    /// of them before submitting any.
+    pub async fn connect(&self) -> Result<RegisteredClient> {
+        let config = &self.config;
+        let (addr_sender, addr_receiver) = config.ot_sender_and_receiver();
+        let (ot_sender, ot_receiver) = tokio::try_join!(
+            connect_and_register(
+                addr_sender,
+                config.uid,
+                config.token,
+                config.connect_timeout,
+                config.tls.as_ref()
+            ),
+            connect_and_register(
+                addr_receiver,
+                config.uid,
+                config.token,
+                config.connect_timeout,
+                config.tls.as_ref()
+            )
+        )?;
+        Ok(RegisteredClient {
+            uid: config.uid,
+            ot_sender: (addr_sender.to_string(), ot_sender),
+            ot_receiver: (addr_receiver.to_string(), ot_receiver),
+            phase2_timeout: config.phase2_timeout,
+        })
+    }
+
+    /// Connect, register, and send `prepared`, including phase 2 for
+    /// `MpPo2`.
+    pub async fn submit(self, prepared: PreparedMessages) -> Result<SubmitReceipt> {
+        self.connect().await?.submit(prepared).await
+    }
+}
+
+async fn connect_and_register(
+    addr: &str,
+    uid: ClientID,
+    token: ClientToken,
+    timeout: Option<Duration>,
+    tls: Option<&ClientTls>,
+) -> Result<TcpConnection> {
+    let connect_failed = |source| ClientError::Connect {
+        addr: addr.to_string(),
+        source,
+    };
+    let socket = tcp_connect_timeout(addr, timeout)
+        .await
+        .map_err(connect_failed)?;
+    let (conn, registered) = match tls {
+        Some(tls) => TcpConnection::new_client_side_tls(socket, uid, token, tls)
+            .await
+            .map_err(connect_failed)?,
+        None => TcpConnection::new_client_side(socket, uid, token),
+    };
+    let rejected = |source| ClientError::Rejected {
+        addr: addr.to_string(),
+        source,
+    };
+    if registered.await.is_err() {
+        return Err(ClientError::Closed {
+            addr: addr.to_string(),
+        });
+    }
+    let version = conn.wait_accepted(timeout).await.map_err(rejected)?;
+    debug!("client {} registered at {} ({})", uid.id, addr, version);
+    Ok(conn)
+}
+
+/// A client connected to both servers, see [`ElsaClient::connect`].
+pub struct RegisteredClient {
+    uid: ClientID,
+    ot_sender: (String, TcpConnection),
+    ot_receiver: (String, TcpConnection),
+    phase2_timeout: Option<Duration>,
+}
+
+impl RegisteredClient {
+    /// Send `prepared`, and run phase 2 for `MpPo2`.
+    pub async fn submit(self, prepared: PreparedMessages) -> Result<SubmitReceipt> {
+        self.submit_round(0, prepared).await
+    }
+
+    /// Same as `submit`, for round `round` of a session, keeping the
+    /// connections for the next rounds. `prepared` must be prepared for this
+    /// round, with fresh randomness.
+    pub async fn submit_round(
+        &self,
+        round: u64,
+        prepared: PreparedMessages,
+    ) -> Result<SubmitReceipt> {
+        let PreparedMessages {
+            to_ot_sender,
+            to_ot_receiver,
+            phase2,
+        } = prepared;
+        let (addr_sender, ot_sender) = &self.ot_sender;
+        let (addr_receiver, ot_receiver) = &self.ot_receiver;
+        let mut receipt = SubmitReceipt {
+            uid: self.uid,
+            bytes_to_ot_sender: to_ot_sender.len(),
+            bytes_to_ot_receiver: to_ot_receiver.len(),
+        };
+        let sent_sender = ot_sender.send_message_bytes(SendId::FIRST.in_round(round), to_ot_sender);
+        let sent_receiver =
+            ot_receiver.send_message_bytes(SendId::FIRST.in_round(round), to_ot_receiver);
+        for (sent, addr) in [(sent_sender, addr_sender), (sent_receiver, addr_receiver)] {
+            if sent.await.is_err() {
+                return Err(ClientError::Closed { addr: addr.clone() });
+            }
+        }
+
+        if let Some(reply) = phase2 {
+            let chi_seed = match self.phase2_timeout {
+                Some(timeout) => {
+                    ot_sender
+                        .subscribe_and_get_timeout::<UseCast<u64>>(
+                            RecvId::FIRST.in_round(round),
+                            timeout,
+                        )
+                        .await
+                },
+                None => {
+                    ot_sender
+                        .subscribe_and_get::<UseCast<u64>>(RecvId::FIRST.in_round(round))
+                        .await
+                },
+            };
+            let chi_seed = chi_seed.map_err(|e| match e {
+                BridgeError::SubscribeTimeout { waited, .. } => ClientError::Phase2Timeout(waited),
+                e => ClientError::Phase2(e),
+            })?;
+            let reply = reply(chi_seed);
+            receipt.bytes_to_ot_sender += reply.len();
+            if ot_sender
+                .send_message_bytes(SendId::SECOND.in_round(round), reply)
+                .await
+                .is_err()
+            {
+                return Err(ClientError::Closed {
+                    addr: addr_sender.clone(),
+                });
+            }
+        }
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bridge::{
+        client_server::ClientsPool,
+        id_tracker::{RecvId, SendId},
+        tcp_bridge::{ClientID, ClientToken},
+    };
+    use crypto_primitives::cot::client::{CotInconsistency, DEFAULT_SEC_PARAM};
+    use rand::{rngs::StdRng, SeedableRng};
+    use serialize::UseCast;
+    use tokio::net::TcpListener;
+
+    use super::{
+        mp_po2,
+        po2::{Po2Client, SingleRoundClient},
+        BitAggregation, ClientConfig, ClientError, ElsaClient, Hasher, InputRing, Variant, ARITH,
+    };
+
+    fn config(variant: Variant, alice: String, bob: String) -> ClientConfig {
+        ClientConfig {
+            connect_timeout: Some(Duration::from_millis(300)),
+            phase2_timeout: Some(Duration::from_millis(300)),
+            ..ClientConfig::new(variant, InputRing::U8, 4, alice, bob)
+        }
+    }
+
+    #[test]
+    fn test_prepare_checks_inputs() {
+        let mut client = ElsaClient::new(config(Variant::Po2, String::new(), String::new()));
+        assert!(client.prepare(&[1u8, 2, 3, 4]).is_ok());
+        assert!(matches!(
+            client.prepare(&[1u8, 2, 3]),
+            Err(ClientError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            client.prepare(&[1u32, 2, 3, 256]),
+            Err(ClientError::InvalidInput(_))
+        ));
+        client.config.input_ring = InputRing::U32;
+        assert!(client.prepare(&[1u32, 2, 3, 256]).is_ok());
+        assert!(matches!(
+            client.prepare(&[1u64, 2, 3, 1 << 32]),
+            Err(ClientError::InvalidInput(_))
+        ));
+        client.config.input_ring = InputRing::U64;
+        assert!(client.prepare(&[1u64, 2, 3, u64::MAX]).is_ok());
+        client.config.input_ring = InputRing::U32;
+
+        client.config.split = Some(5);
+        assert!(matches!(
+            client.prepare(&[1u8, 2, 3, 4]),
+            Err(ClientError::InvalidConfig(_))
+        ));
+        client.config.split = Some(1);
+        assert!(client.prepare(&[1u8, 2, 3, 4]).is_ok());
+
+        client.config.compress = true;
+        assert!(client.prepare(&[1u8, 2, 3, 4]).is_ok());
+        client.config.variant = Variant::L2;
+        assert!(matches!(
+            client.prepare(&[1u8, 2, 3, 4]),
+            Err(ClientError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_prepare_bits() {
+        for aggregation in [BitAggregation::Xor, BitAggregation::Sum] {
+            let mut client = ElsaClient::new(config(
+                Variant::Bits(aggregation),
+                String::new(),
+                String::new(),
+            ));
+            client.config.self_check = true;
+            assert!(client.prepare(&[1u8, 0, 0, 1]).is_ok());
+            assert!(matches!(
+                client.prepare(&[1u8, 2, 0, 1]),
+                Err(ClientError::InvalidInput(_))
+            ));
+        }
+        // the COTs make the messages of `Sum` larger
+        let prepare = |aggregation| {
+            ElsaClient::new(config(
+                Variant::Bits(aggregation),
+                String::new(),
+                String::new(),
+            ))
+            .prepare(&[1u8, 0, 0, 1])
+            .unwrap()
+        };
+        let (xor, sum) = (prepare(BitAggregation::Xor), prepare(BitAggregation::Sum));
+        assert!(xor.to_ot_sender.len() < sum.to_ot_sender.len());
+        assert!(xor.to_ot_receiver.len() < sum.to_ot_receiver.len());
+    }
+
+    #[test]
+    fn test_prepare_self_check() {
+        for variant in [Variant::Po2, Variant::L2, Variant::Mp, Variant::MpPo2] {
+            let mut client = ElsaClient::new(config(variant, String::new(), String::new()));
+            client.config.self_check = true;
+            client.config.input_ring = InputRing::U32;
+            assert!(client.prepare(&[1u32, 2, 3, 1 << 31]).is_ok());
+        }
+        let mut client = ElsaClient::new(config(Variant::Po2, String::new(), String::new()));
+        client.config.self_check = true;
+        client.config.split = Some(1);
+        assert!(client.prepare(&[1u8, 2, 3, 4]).is_ok());
+
+        // the OT receiver gets a flipped choice bit after the COTs are sampled
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut po2 = Po2Client::<u8>::new(
+            ClientID::new(0),
+            0,
+            &[1, 2, 3, 4],
+            &mut rng,
+            DEFAULT_SEC_PARAM,
+        );
+        po2.prepared_message_1.inputs_1[2].0 ^= 1;
+        assert!(matches!(
+            po2.self_check(ClientID::new(0), DEFAULT_SEC_PARAM),
+            Err(ClientError::CotSelfCheck(CotInconsistency::InputMismatch {
+                input: 2,
+                bit: 0
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_ring_budget() {
+        assert_eq!(InputRing::U8.ring_budget().max_total_weight(), 1 << 56);
+        assert!(InputRing::U32.ring_budget().check(1 << 32).is_ok());
+        assert!(InputRing::U32.ring_budget().check((1 << 32) + 1).is_err());
+        assert!(InputRing::U64.ring_budget().check(2).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        // reserve a port, and make sure nobody listens on it
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let client = ElsaClient::new(config(Variant::Po2, addr.clone(), addr));
+        let prepared = client.prepare(&[1u8, 2, 3, 4]).unwrap();
+        assert!(matches!(
+            client.submit(prepared).await,
+            Err(ClientError::Connect { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_phase2_timeout() {
+        let listener_alice = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener_bob = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = ElsaClient::new(config(
+            Variant::MpPo2,
+            listener_alice.local_addr().unwrap().to_string(),
+            listener_bob.local_addr().unwrap().to_string(),
+        ));
+        // servers that accept the client, and never start phase 2
+        let servers = tokio::spawn(async move {
+            tokio::join!(
+                ClientsPool::new(1, listener_alice, ClientToken::default()),
+                ClientsPool::new(1, listener_bob, ClientToken::default())
+            )
+        });
+        let prepared = client.prepare(&[1u8, 2, 3, 4]).unwrap();
+        let submitted = client.submit(prepared).await;
+        assert!(matches!(submitted, Err(ClientError::Phase2Timeout(_))));
+        let (alice, bob) = servers.await.unwrap();
+        assert_eq!(alice.clients[0].uid(), ClientID::new(0));
+        assert_eq!(bob.clients[0].uid(), ClientID::new(0));
+    }
+
+    #[tokio::test]
+    async fn test_phase2_reply() {
+        const CHI_SEED: u64 = 0x1234_5678;
+        let input = [1u8, 2, 3, 4];
+        let listener_alice = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener_bob = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = ElsaClient::new(config(
+            Variant::MpPo2,
+            listener_alice.local_addr().unwrap().to_string(),
+            listener_bob.local_addr().unwrap().to_string(),
+        ));
+        let prepared = client
+            .prepare_with_rng(&input, &mut StdRng::seed_from_u64(1))
+            .unwrap();
+        let expected = mp_po2::Client::<u8, Hasher>::prepare_phase1::<ARITH, _, _>(
+            ClientID::new(0),
+            0,
+            &input,
+            &mut StdRng::seed_from_u64(1),
+            DEFAULT_SEC_PARAM,
+            Hasher::default,
+        )
+        .phase2_reply(CHI_SEED);
+
+        // Alice is OT sender of client 0, and starts phase 2 right away
+        let ot_sender = tokio::spawn(async move {
+            let pool = ClientsPool::new(1, listener_alice, ClientToken::default()).await;
+            let client = &pool.clients[0];
+            let phase1 = client.subscribe_and_get_bytes(RecvId::FIRST).await.unwrap();
+            client
+                .send_message(SendId::FIRST, UseCast(CHI_SEED))
+                .unwrap();
+            let reply = client
+                .subscribe_and_get_bytes(RecvId::SECOND)
+                .await
+                .unwrap();
+            (phase1, reply)
+        });
+        let ot_receiver = tokio::spawn(ClientsPool::new(1, listener_bob, ClientToken::default()));
+        let receipt = client.submit(prepared).await.unwrap();
+        let (phase1, reply) = ot_sender.await.unwrap();
+        assert_eq!(reply, expected);
+        assert_eq!(receipt.bytes_to_ot_sender, phase1.len() + reply.len());
+        ot_receiver.await.unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+use bridge::tcp_bridge::ClientID;
+use bytes::Bytes;
+use crypto_primitives::{
+    a2s::{make_witness_shares, WitnessShareSeedToAlice, WitnessSharesToBob},
+    bits::{batch_make_boolean_shares, ShareCommitment, ShareSalt},
+    cot::client::{num_additional_ot_needed, validate_cot_pair, COTGen},
+    malpriv::{
+        client::{
+            simulate_a2s, simulate_b2a, simulate_bound_check, simulate_ot_verify,
+            simulate_sqcorr_verify,
+        },
+        derive_phase_seeds, MessageHash, PhaseSeeds,
+    },
+    message::l2::{ClientL2MsgToAlice, ClientL2MsgToBob, ClientMPMsgToAlice, ClientMPMsgToBob},
+    square_corr::{batch_make_sqcorr_shares, validate_sqcorr_pair},
+    uint::UInt,
+};
+use rand::Rng;
+use serialize::Communicate;
+
+/// Client on input ring `I`, and correlation ring `C`
+pub struct Client<I: UInt, C: UInt, H: MessageHash> {
+    pub msg_alice: ClientMPMsgToAlice<H>, // phase 1 and phase 2
+    pub msg_bob: ClientMPMsgToBob<I, C, H>,
+    /// Shares of the witness bits of the bound check, sent after the messages,
+    /// if the servers check a bound.
+    pub witness: Option<(WitnessShareSeedToAlice, WitnessSharesToBob<C>)>,
+}
+
+impl<I: UInt, C: UInt, H: MessageHash<Output = Vec<u8>>> Client<I, C, H> {
+    /// use Fiat-Shamir to combine two messages of client `uid` in `session`
+    pub fn prepare_message<A: UInt, R: Rng, F>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        rng: &mut R,
+        sec_param: usize,
+        hasher: F,
+    ) -> Self
+    where
+        F: Fn() -> H,
+    {
+        Self::prepare_with_witness::<A, _, _>(uid, session, input, None, rng, sec_param, hasher)
+    }
+
+    /// Same as `prepare_message`, with a `bound` and the witness bits of the
+    /// bound check if the servers check one, see `a2s::batch_bound_witness`.
+    /// The transcript of the check is hashed with the one of A2S.
+    pub fn prepare_with_witness<A: UInt, R: Rng, F>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        bound: Option<(I, &[C])>,
+        rng: &mut R,
+        sec_param: usize,
+        hasher: F,
+    ) -> Self
+    where
+        F: Fn() -> H,
+    {
+        let mut hasher_b2a_ab = hasher(); // hasher of message sent from alice to bob
+        let mut hasher_a2s_ab = hasher(); // hasher of message sent from alice to bob
+        let mut hasher_a2s_ba = hasher(); // hasher of message sent from bob to alice
+
+        let gsize = input.len();
+        let (inputs_0, inputs_1) =
+            batch_make_boolean_shares(rng, input.iter().map(|x| x.bits_le()));
+        let inputs_0_expanded = inputs_0.expand(gsize);
+        let delta = COTGen::sample_delta(rng);
+        let num_additional_cot = num_additional_ot_needed(gsize * I::NUM_BITS, sec_param);
+        let (cot_s, cot_r) =
+            COTGen::sample_cots_for(rng, uid.id, &inputs_1, delta, num_additional_cot);
+
+        // generate correlation, the ones of the witness bits after the inputs
+        let num_squared = gsize + bound.map_or(0, |(_, witness)| witness.len());
+        let (corr0, corr1, sqcorr_a, sqcorr_b) = batch_make_sqcorr_shares(rng, num_squared * 2);
+        let witness = bound.map(|(bound, witness)| {
+            let (to_alice, to_bob, w0) = make_witness_shares(rng, witness);
+            (bound, to_alice, to_bob, w0)
+        });
+
+        let salt = ShareSalt::sample(rng);
+        let commitment = ShareCommitment::new(uid.id, session, inputs_0, salt, &inputs_1);
+        let msg_alice = ClientL2MsgToAlice::new(gsize, inputs_0, commitment, cot_s, corr0);
+        let msg_bob = ClientL2MsgToBob::new(inputs_1, commitment, salt, cot_r, corr1);
+
+        // simulate B2A and A2S and get transcript
+        let (y0, y1) = simulate_b2a::<I, A, H>(
+            &inputs_0_expanded,
+            &msg_bob.po2_msg.inputs_1,
+            msg_alice.cot(),
+            msg_bob.cot(),
+            &mut hasher_b2a_ab,
+        );
+        simulate_a2s::<I, A, C, _>(
+            gsize,
+            &sqcorr_a,
+            &sqcorr_b,
+            &y0,
+            &y1,
+            &mut hasher_a2s_ab,
+            &mut hasher_a2s_ba,
+        );
+        if let Some((bound, _, to_bob, w0)) = &witness {
+            simulate_bound_check(
+                gsize,
+                &sqcorr_a,
+                &sqcorr_b,
+                &y0,
+                &y1,
+                w0,
+                &to_bob.w,
+                bound.as_uint::<A>(),
+                &mut hasher_a2s_ab,
+                &mut hasher_a2s_ba,
+            );
+        }
+
+        let msg_phase1_a = (msg_alice, hasher_a2s_ba.digest());
+        let msg_phase1_b = (msg_bob, hasher_b2a_ab.digest(), hasher_a2s_ab.digest());
+
+        let PhaseSeeds { chi_seed, t_seed } = derive_phase_seeds(hasher(), &msg_phase1_a)
+            .combine(derive_phase_seeds(hasher(), &msg_phase1_b));
+
+        // Phase 2
+        let mut hasher_ot_ba = hasher();
+        let mut hasher_sqcorr_ab = hasher();
+        let mut hasher_sqcorr_ba = hasher();
+
+        // verification
+        simulate_ot_verify::<I, A, H>(
+            uid.id,
+            &msg_phase1_b.0.po2_msg.inputs_1,
+            msg_phase1_b.0.cot(),
+            chi_seed,
+            sec_param,
+            &mut hasher_ot_ba,
+        );
+        simulate_sqcorr_verify::<I, A, C, H>(
+            num_squared,
+            &sqcorr_a,
+            &sqcorr_b,
+            t_seed,
+            &mut hasher_sqcorr_ab,
+            &mut hasher_sqcorr_ba,
+        );
+
+        let msg_phase2_a = (hasher_ot_ba.digest(), hasher_sqcorr_ba.digest());
+        let msg_phase2_b = hasher_sqcorr_ab.digest();
+
+        Self {
+            msg_alice: (msg_phase1_a, msg_phase2_a),
+            msg_bob: (msg_phase1_b, msg_phase2_b),
+            witness: witness.map(|(_, to_alice, to_bob, _)| (to_alice, to_bob)),
+        }
+    }
+
+    /// The upload to Alice, followed by the witness shares if any.
+    pub fn msg_to_alice(&self) -> Bytes {
+        match &self.witness {
+            Some((to_alice, _)) => (&self.msg_alice, to_alice).into_bytes_owned(),
+            None => (&self.msg_alice).into_bytes_owned(),
+        }
+    }
+
+    /// The upload to Bob, followed by the witness shares if any.
+    pub fn msg_to_bob(&self) -> Bytes {
+        match &self.witness {
+            Some((_, to_bob)) => (&self.msg_bob, to_bob).into_bytes_owned(),
+            None => (&self.msg_bob).into_bytes_owned(),
+        }
+    }
+
+    /// Check that the COTs and square correlations of the messages of client
+    /// `uid` match the inputs, see `ClientConfig::self_check`.
+    pub fn self_check(&self, uid: ClientID, sec_param: usize) -> crate::Result<()> {
+        let (msg_alice, msg_bob) = (&self.msg_alice.0 .0, &self.msg_bob.0 .0);
+        let inputs_1 = msg_bob.inputs_1();
+        validate_cot_pair(uid.id, msg_alice.cot(), msg_bob.cot(), inputs_1, sec_param)?;
+        let num_witness = self
+            .witness
+            .as_ref()
+            .map_or(0, |(_, to_bob)| to_bob.w.len());
+        validate_sqcorr_pair(
+            &msg_alice.square_corr,
+            &msg_bob.square_corr,
+            inputs_1.len() + num_witness,
+        )?;
+        Ok(())
+    }
+    // no need to receive from bob
+}
@@ -0,0 +1,169 @@
+use bridge::tcp_bridge::ClientID;
+use bytes::Bytes;
+use crypto_primitives::{
+    bits::{batch_make_boolean_shares, ShareCommitment, ShareSalt},
+    cot::client::{num_additional_ot_needed, validate_cot_pair, COTGen},
+    malpriv::{
+        client::{finish_ot_verify, precompute_ot_verify, simulate_b2a, OtVerifyPrecomputed},
+        MessageHash,
+    },
+    message::po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
+    uint::UInt,
+};
+use rand::Rng;
+use serialize::Communicate;
+
+/// Client on input ring `I`, and correlation ring `C`
+pub struct Client<I: UInt, H: MessageHash> {
+    /// Po2 message
+    pub prepared_message_a: ClientPo2MsgToAlice,
+    /// Po2 message, hash_ab for B2A
+    pub prepared_message_b: (ClientPo2MsgToBob<I>, H::Output),
+    /// OT verification transcript, up to the part that depends on the chi
+    /// seed of phase 2
+    hasher_ot_ba: H,
+    ot_verify: OtVerifyPrecomputed,
+}
+
+impl<I: UInt, H: MessageHash> Client<I, H> {
+    /// Prepare the phase 1 messages of client `uid` in `session`, and the part
+    /// of the phase 2 transcript that does not depend on the server.
+    pub fn prepare_phase1<A: UInt, R: Rng, F>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        rng: &mut R,
+        sec_param: usize,
+        hasher: F,
+    ) -> Self
+    where
+        F: Fn() -> H,
+    {
+        let mut hasher_b2a_ab = hasher(); // hasher of message sent from alice to bob
+        let mut hasher_ot_ba = hasher(); // hasher of message sent from bob to alice
+
+        let gsize = input.len();
+        let (input_0, input_1) = batch_make_boolean_shares(rng, input.iter().map(|x| x.bits_le()));
+        let delta = COTGen::sample_delta(rng);
+        let num_additional_cot = num_additional_ot_needed(gsize * I::NUM_BITS, sec_param);
+        let (cot_s, cot_r) =
+            COTGen::sample_cots_for(rng, uid.id, &input_1, delta, num_additional_cot);
+
+        let input_0_expanded = input_0.expand(gsize);
+
+        // simulate B2A and A2S and get transcript
+        let _ = simulate_b2a::<I, A, H>(
+            &input_0_expanded,
+            &input_1,
+            &cot_s,
+            &cot_r,
+            &mut hasher_b2a_ab,
+        );
+
+        // everything of the phase 2 transcript that is known before the chi
+        // seed, so that phase 2 only waits for the server
+        let ot_verify =
+            precompute_ot_verify(uid.id, &input_1, &cot_r, sec_param, &mut hasher_ot_ba);
+
+        let salt = ShareSalt::sample(rng);
+        let commitment = ShareCommitment::new(uid.id, session, input_0, salt, &input_1);
+        let msg_alice = ClientPo2MsgToAlice::new(gsize, input_0, commitment, cot_s);
+        let msg_bob = ClientPo2MsgToBob::new(input_1, commitment, salt, cot_r);
+        Client {
+            prepared_message_a: msg_alice,
+            prepared_message_b: (msg_bob, hasher_b2a_ab.digest()),
+            hasher_ot_ba,
+            ot_verify,
+        }
+    }
+
+    /// Check that the COTs of the messages of client `uid` match the inputs,
+    /// see `ClientConfig::self_check`.
+    pub fn self_check(&self, uid: ClientID, sec_param: usize) -> crate::Result<()> {
+        let msg_bob = &self.prepared_message_b.0;
+        validate_cot_pair(
+            uid.id,
+            &self.prepared_message_a.cot,
+            &msg_bob.cot,
+            &msg_bob.inputs_1,
+            sec_param,
+        )?;
+        Ok(())
+    }
+
+    /// The phase 1 message to Alice, i.e. the OT sender.
+    pub fn msg_to_alice(&self) -> Bytes {
+        (&self.prepared_message_a).into_bytes_owned()
+    }
+
+    /// The phase 1 message to Bob, i.e. the OT receiver.
+    pub fn msg_to_bob(&self) -> Bytes {
+        (&self.prepared_message_b).into_bytes_owned()
+    }
+
+    /// Hash of the OT verification message that Bob sends to Alice, given the
+    /// chi seed of Alice.
+    pub fn ot_verify_digest(self, chi_seed: u64) -> H::Output {
+        let Client {
+            prepared_message_b,
+            mut hasher_ot_ba,
+            ot_verify,
+            ..
+        } = self;
+        let msg_bob = &prepared_message_b.0;
+        finish_ot_verify(
+            &ot_verify,
+            &msg_bob.inputs_1,
+            &msg_bob.cot,
+            chi_seed,
+            &mut hasher_ot_ba,
+        );
+        hasher_ot_ba.digest()
+    }
+
+    /// The phase 2 message to Alice, given her chi seed.
+    pub fn phase2_reply(self, chi_seed: u64) -> Bytes {
+        self.ot_verify_digest(chi_seed).into_bytes_owned()
+    }
+    // no need to receive from bob
+}
+
+#[cfg(test)]
+mod tests {
+    use bridge::tcp_bridge::ClientID;
+    use crypto_primitives::{
+        cot::client::DEFAULT_SEC_PARAM,
+        malpriv::{client::simulate_ot_verify, MessageHash},
+        uint::UInt,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+    use sha2::Sha256;
+
+    use super::Client;
+
+    #[test]
+    fn test_precomputed_ot_verify_digest_is_unchanged() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let input = (0..20).map(|_| u32::rand(&mut rng)).collect::<Vec<_>>();
+        let client = Client::<u32, Sha256>::prepare_phase1::<u64, _, _>(
+            ClientID::new(3),
+            1,
+            &input,
+            &mut rng,
+            DEFAULT_SEC_PARAM,
+            Sha256::default,
+        );
+        let chi_seed = 0x1234_5678;
+
+        let mut expected = Sha256::default();
+        simulate_ot_verify::<u32, u64, _>(
+            3,
+            &client.prepared_message_b.0.inputs_1,
+            &client.prepared_message_b.0.cot,
+            chi_seed,
+            DEFAULT_SEC_PARAM,
+            &mut expected,
+        );
+        assert_eq!(client.ot_verify_digest(chi_seed), expected.digest());
+    }
+}
@@ -0,0 +1,187 @@
+use bridge::tcp_bridge::ClientID;
+use bytes::Bytes;
+use crypto_primitives::{
+    bits::{batch_make_boolean_shares, BitsLE, ShareCommitment, ShareSalt},
+    cot::client::{num_additional_ot_needed, validate_cot_pair, COTGen},
+    message::po2::{
+        ClientPo2MsgToAlice, ClientPo2MsgToBob, ClientPo2SplitMsgToAlice, ClientPo2SplitMsgToBob,
+    },
+    uint::UInt,
+};
+use rand::Rng;
+use serialize::{Communicate, Lz4Compressed};
+
+/// A client that sends one message to each server, and nothing after.
+pub trait SingleRoundClient<I: UInt>: Sync + Send {
+    /// Prepare the messages of client `uid` in `session`.
+    fn new<R: Rng>(uid: ClientID, session: u64, input: &[I], rng: &mut R, sec_param: usize)
+        -> Self;
+    /// The message to the OT sender.
+    fn msg_to_ot_sender(&self) -> Bytes;
+    /// The message to the OT receiver, compressed with `Lz4Compressed` if
+    /// `compress`.
+    fn msg_to_ot_receiver(&self, compress: bool) -> Bytes;
+    /// Check that the correlations of the messages of client `uid` match the
+    /// inputs, see `ClientConfig::self_check`.
+    fn self_check(&self, uid: ClientID, sec_param: usize) -> crate::Result<()>;
+}
+
+/// Client on input ring `I`, and correlation ring `C`
+pub struct Po2Client<I: UInt> {
+    pub prepared_message_0: ClientPo2MsgToAlice,
+    pub prepared_message_1: ClientPo2MsgToBob<I>,
+}
+
+impl<I: UInt> SingleRoundClient<I> for Po2Client<I> {
+    fn new<R: Rng>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        rng: &mut R,
+        sec_param: usize,
+    ) -> Self {
+        let gsize = input.len();
+        let (input_0, input_1) = batch_make_boolean_shares(rng, input.iter().map(|x| x.bits_le()));
+        let delta = COTGen::sample_delta(rng);
+        let num_additional_cot = num_additional_ot_needed(gsize * I::NUM_BITS, sec_param);
+        let (cot_s, cot_r) =
+            COTGen::sample_cots_for(rng, uid.id, &input_1, delta, num_additional_cot);
+
+        let salt = ShareSalt::sample(rng);
+        let commitment = ShareCommitment::new(uid.id, session, input_0, salt, &input_1);
+        let prepared_message_0 = ClientPo2MsgToAlice::new(gsize, input_0, commitment, cot_s);
+        let prepared_message_1 = ClientPo2MsgToBob::new(input_1, commitment, salt, cot_r);
+        Po2Client {
+            prepared_message_0,
+            prepared_message_1,
+        }
+    }
+
+    fn msg_to_ot_sender(&self) -> Bytes {
+        (&self.prepared_message_0).into_bytes_owned()
+    }
+
+    fn msg_to_ot_receiver(&self, compress: bool) -> Bytes {
+        if compress {
+            Lz4Compressed::new(&self.prepared_message_1).into_bytes_owned()
+        } else {
+            (&self.prepared_message_1).into_bytes_owned()
+        }
+    }
+
+    fn self_check(&self, uid: ClientID, sec_param: usize) -> crate::Result<()> {
+        let msg_bob = &self.prepared_message_1;
+        validate_cot_pair(
+            uid.id,
+            &self.prepared_message_0.cot,
+            &msg_bob.cot,
+            &msg_bob.inputs_1,
+            sec_param,
+        )?;
+        Ok(())
+    }
+}
+
+/// Client that splits its inputs, so that each server is OT sender for some
+/// of them, see `ClientPo2SplitMsgToAlice`. The servers must expect split
+/// messages.
+pub struct Po2SplitClient<I: UInt> {
+    pub prepared_message_0: ClientPo2SplitMsgToAlice,
+    pub prepared_message_1: ClientPo2SplitMsgToBob<I>,
+}
+
+impl<I: UInt> Po2SplitClient<I> {
+    /// Same as `new`, with Alice as OT sender for the first `split` inputs,
+    /// and Bob for the others.
+    ///
+    /// # Panics
+    /// If `split` is more than the number of inputs.
+    pub fn with_split<R: Rng>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        split: usize,
+        rng: &mut R,
+        sec_param: usize,
+    ) -> Self {
+        let gsize = input.len();
+        assert!(split <= gsize, "split {} after the last input", split);
+        let (input_0, input_1) = batch_make_boolean_shares(rng, input.iter().map(|x| x.bits_le()));
+        let mut sample_cots = |choices: &[BitsLE<I>]| {
+            let delta = COTGen::sample_delta(rng);
+            let num_additional_cot =
+                num_additional_ot_needed(choices.len() * I::NUM_BITS, sec_param);
+            COTGen::sample_cots_for(rng, uid.id, choices, delta, num_additional_cot)
+        };
+        // Bob chooses with his shares before the split, and Alice with hers
+        // after it
+        let (cot_s, cot_r) = sample_cots(&input_1[..split]);
+        let (reverse_s, reverse_r) = sample_cots(&input_0.expand::<I>(gsize)[split..]);
+
+        let salt = ShareSalt::sample(rng);
+        let commitment = ShareCommitment::new(uid.id, session, input_0, salt, &input_1);
+        let prepared_message_0 = ClientPo2SplitMsgToAlice::new(
+            ClientPo2MsgToAlice::new(gsize, input_0, commitment, cot_s),
+            split,
+            reverse_r,
+        );
+        let prepared_message_1 = ClientPo2SplitMsgToBob::new(
+            ClientPo2MsgToBob::new(input_1, commitment, salt, cot_r),
+            split,
+            reverse_s,
+        );
+        Po2SplitClient {
+            prepared_message_0,
+            prepared_message_1,
+        }
+    }
+}
+
+impl<I: UInt> SingleRoundClient<I> for Po2SplitClient<I> {
+    /// Split the inputs in the middle.
+    fn new<R: Rng>(
+        uid: ClientID,
+        session: u64,
+        input: &[I],
+        rng: &mut R,
+        sec_param: usize,
+    ) -> Self {
+        Self::with_split(uid, session, input, input.len() / 2, rng, sec_param)
+    }
+
+    fn msg_to_ot_sender(&self) -> Bytes {
+        (&self.prepared_message_0).into_bytes_owned()
+    }
+
+    fn msg_to_ot_receiver(&self, compress: bool) -> Bytes {
+        if compress {
+            Lz4Compressed::new(&self.prepared_message_1).into_bytes_owned()
+        } else {
+            (&self.prepared_message_1).into_bytes_owned()
+        }
+    }
+
+    /// Checks the COTs of both directions, Alice choosing with her shares
+    /// after the split.
+    fn self_check(&self, uid: ClientID, sec_param: usize) -> crate::Result<()> {
+        let (msg_alice, msg_bob) = (&self.prepared_message_0, &self.prepared_message_1);
+        let split = msg_alice.split as usize;
+        let inputs_1 = &msg_bob.po2_msg.inputs_1;
+        validate_cot_pair(
+            uid.id,
+            &msg_alice.po2_msg.cot,
+            &msg_bob.po2_msg.cot,
+            &inputs_1[..split],
+            sec_param,
+        )?;
+        let inputs_0 = msg_alice.po2_msg.inputs_0.expand::<I>(inputs_1.len());
+        validate_cot_pair(
+            uid.id,
+            &msg_bob.reverse,
+            &msg_alice.reverse,
+            &inputs_0[split..],
+            sec_param,
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! LZ4 compression of messages. Worth it for messages with structure, e.g.
+//! those of the Prio baseline, and not for the random blocks of the COTs.
+
+use crate::{
+    util::{ReadUtil, WriteUtil},
+    Communicate, Result,
+};
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+};
+
+/// A literal run of LZ4 expands to at most this many bytes per compressed
+/// byte, so a larger uncompressed length is malformed.
+const MAX_EXPANSION: u64 = 256;
+
+/// `M` compressed with LZ4 when built, so that its size is known before it is
+/// sent. Deserialized as `M`. On the wire, the length of the uncompressed
+/// bytes and the length of the compressed bytes come first.
+pub struct Lz4Compressed<M: Communicate> {
+    uncompressed_len: u64,
+    compressed: Vec<u8>,
+    _msg: PhantomData<fn() -> M>,
+}
+
+impl<M: Communicate> Lz4Compressed<M> {
+    pub fn new(msg: &M) -> Self {
+        let mut bytes = Vec::with_capacity(msg.size_in_bytes());
+        msg.to_bytes(&mut bytes);
+        Self {
+            uncompressed_len: bytes.len() as u64,
+            compressed: lz4_flex::block::compress(&bytes),
+            _msg: PhantomData,
+        }
+    }
+}
+
+fn malformed(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+impl<M: Communicate> Communicate for Lz4Compressed<M> {
+    type Deserialized = M::Deserialized;
+
+    fn size_in_bytes(&self) -> usize {
+        2 * std::mem::size_of::<u64>() + self.compressed.len()
+    }
+
+    fn to_bytes<W: Write>(&self, mut dest: W) {
+        dest.write_pod(&self.uncompressed_len).unwrap();
+        dest.write_pod(&(self.compressed.len() as u64)).unwrap();
+        dest.write_all(&self.compressed).unwrap();
+    }
+
+    fn from_bytes<R: Read>(mut bytes: R) -> Result<Self::Deserialized> {
+        let uncompressed_len = bytes.read_pod::<u64>()?;
+        let compressed_len = bytes.read_pod::<u64>()?;
+        if uncompressed_len > compressed_len.saturating_mul(MAX_EXPANSION) {
+            return Err(malformed("uncompressed length beyond the LZ4 ratio").into());
+        }
+        // the lengths are not trusted to allocate before reading
+        let mut compressed = Vec::new();
+        bytes.take(compressed_len).read_to_end(&mut compressed)?;
+        if compressed.len() as u64 != compressed_len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let uncompressed = lz4_flex::block::decompress(&compressed, uncompressed_len as usize)?;
+        if uncompressed.len() as u64 != uncompressed_len {
+            return Err(malformed("uncompressed length does not match").into());
+        }
+        M::from_bytes(uncompressed.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UseCast;
+
+    fn round_trip<M: Communicate>(msg: &Lz4Compressed<M>) -> Result<M::Deserialized> {
+        let mut bytes = Vec::new();
+        msg.to_bytes(&mut bytes);
+        assert_eq!(bytes.len(), msg.size_in_bytes());
+        Lz4Compressed::<M>::from_bytes(bytes.as_slice())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // a counter is compressible, its bytes repeat with a short period
+        let msg = (0..4096u64).map(|i| i % 16).collect::<Vec<_>>();
+        let compressed = Lz4Compressed::new(&msg);
+        assert_eq!(round_trip(&compressed).unwrap(), msg);
+        assert!(compressed.size_in_bytes() * 10 < msg.size_in_bytes());
+
+        let msg = (UseCast(7u32), Vec::<u8>::new());
+        assert_eq!(round_trip(&Lz4Compressed::new(&msg)).unwrap(), (7, vec![]));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let msg = Lz4Compressed::new(&vec![0u8; 1000]);
+        let mut bytes = Vec::new();
+        msg.to_bytes(&mut bytes);
+
+        // truncated
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(Lz4Compressed::<Vec<u8>>::from_bytes(truncated).is_err());
+
+        // claims more bytes than LZ4 can expand to
+        let mut huge = bytes.clone();
+        huge[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Lz4Compressed::<Vec<u8>>::from_bytes(huge.as_slice()).is_err());
+
+        // claims fewer bytes than it decompresses to
+        let mut short = bytes;
+        short[..8].copy_from_slice(&10u64.to_le_bytes());
+        assert!(Lz4Compressed::<Vec<u8>>::from_bytes(short.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Harness for malformed messages: mutate valid serialized messages, and
+//! check that `Communicate::from_bytes` on them neither panics nor allocates
+//! more than a bounded multiple of the input length. Servers deserialize
+//! client bytes directly, so both would let one client take a server down.
+//!
+//! Allocations are counted by `CountingAlloc`, which the test binary must
+//! install as its global allocator:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOC: serialize::fuzz::CountingAlloc = serialize::fuzz::CountingAlloc;
+//! ```
+
+use crate::Communicate;
+use bytes::Bytes;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+};
+
+/// Deserializing `n` bytes may allocate at most `ALLOC_FACTOR * n +
+/// ALLOC_SLACK` bytes. A `Vec` read element by element doubles its capacity,
+/// and the output may hold a copy of the input.
+pub const ALLOC_FACTOR: usize = 4;
+pub const ALLOC_SLACK: usize = 1024;
+
+/// Values written over 8-byte windows of a message, aimed at length prefixes.
+const LENGTHS: [u64; 6] = [0, 1 << 20, 1 << 32, u64::MAX / 16, u64::MAX / 2, u64::MAX];
+
+thread_local! {
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+    static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+fn record(delta: isize) {
+    // TLS is gone while a thread exits, and its allocations do not matter
+    let _ = LIVE.try_with(|live| {
+        let now = live.get() + delta;
+        live.set(now);
+        let _ = PEAK.try_with(|peak| peak.set(peak.get().max(now)));
+    });
+}
+
+/// System allocator that keeps per-thread counts of live bytes, see
+/// `peak_alloc`.
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        record(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+/// Run `f`, and return its result and the peak number of bytes it allocated
+/// on this thread on top of what was live before. Always 0 unless
+/// `CountingAlloc` is the global allocator.
+pub fn peak_alloc<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let start = LIVE.with(|live| live.get());
+    PEAK.with(|peak| peak.set(start));
+    let result = f();
+    let peak = PEAK.with(|peak| peak.get());
+    (result, (peak - start).max(0) as usize)
+}
+
+/// Malformed variants of `seed`: every truncation, every 8-byte window
+/// overwritten with each of `LENGTHS`, and `num_random` random mutations.
+pub fn mutations(seed: &[u8], num_random: usize, rng: &mut StdRng) -> Vec<Vec<u8>> {
+    let mut result = (0..seed.len())
+        .map(|len| seed[..len].to_vec())
+        .collect::<Vec<_>>();
+    for start in 0..seed.len().saturating_sub(7) {
+        for value in LENGTHS {
+            let mut bytes = seed.to_vec();
+            bytes[start..start + 8].copy_from_slice(&value.to_le_bytes());
+            result.push(bytes);
+        }
+    }
+    result.extend((0..num_random).map(|_| mutate(seed, rng)));
+    result
+}
+
+/// One random mutation of `seed`.
+fn mutate(seed: &[u8], rng: &mut StdRng) -> Vec<u8> {
+    let mut bytes = seed.to_vec();
+    for _ in 0..rng.gen_range(1..=4) {
+        let at = rng.gen_range(0..=bytes.len());
+        match rng.gen_range(0..5) {
+            // flip a bit
+            0 if at < bytes.len() => bytes[at] ^= 1 << rng.gen_range(0..8),
+            // overwrite a byte
+            1 if at < bytes.len() => bytes[at] = rng.gen(),
+            // insert random bytes
+            2 => {
+                let junk = (0..rng.gen_range(1..=16)).map(|_| rng.gen::<u8>());
+                bytes.splice(at..at, junk);
+            },
+            // delete a range
+            3 => {
+                let end = rng.gen_range(at..=bytes.len());
+                bytes.drain(at..end);
+            },
+            // truncate
+            _ => bytes.truncate(at),
+        }
+    }
+    bytes
+}
+
+/// Deserialize `bytes` as `M`, and panic if that panics or allocates more than
+/// the bound. Return whether it succeeded.
+pub fn check_bytes<M: Communicate>(bytes: &[u8]) -> bool {
+    let (result, peak) =
+        peak_alloc(|| panic::catch_unwind(AssertUnwindSafe(|| M::from_bytes(bytes))));
+    let result = result.unwrap_or_else(|_| {
+        panic!(
+            "from_bytes panicked on {} bytes: {:02x?}",
+            bytes.len(),
+            bytes
+        )
+    });
+    let bound = ALLOC_FACTOR * bytes.len() + ALLOC_SLACK;
+    assert!(
+        peak <= bound,
+        "from_bytes allocated {} bytes for {} bytes of input: {:02x?}",
+        peak,
+        bytes.len(),
+        bytes
+    );
+    result.is_ok()
+}
+
+/// Check every seed, which must deserialize, and its mutations, see
+/// `check_bytes`. Seeds should be valid serialized messages, so that the
+/// mutations reach deep into the format.
+pub fn check_malformed<M: Communicate>(seeds: &[Bytes], num_random: usize, rng_seed: u64) {
+    let mut rng = StdRng::seed_from_u64(rng_seed);
+    for seed in seeds {
+        assert!(check_bytes::<M>(seed), "seed does not deserialize");
+        for bytes in mutations(seed, num_random, &mut rng) {
+            check_bytes::<M>(&bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{util::ReadUtil, UseCast};
+    use std::io::{Read, Write};
+
+    #[global_allocator]
+    static ALLOC: CountingAlloc = CountingAlloc;
+
+    fn seed<M: Communicate>(msg: M) -> Bytes {
+        msg.into_bytes_owned()
+    }
+
+    #[test]
+    fn test_peak_alloc_counts() {
+        let (v, peak) = peak_alloc(|| vec![0u8; 1 << 20]);
+        assert!(peak >= v.len());
+        let ((), peak) = peak_alloc(|| ());
+        assert_eq!(peak, 0);
+    }
+
+    /// Allocates whatever its length prefix says.
+    struct Preallocating;
+
+    impl Communicate for Preallocating {
+        type Deserialized = Vec<u8>;
+
+        fn size_in_bytes(&self) -> usize {
+            0
+        }
+
+        fn to_bytes<W: Write>(&self, dest: W) {
+            let _ = dest;
+        }
+
+        fn from_bytes<R: Read>(mut bytes: R) -> crate::Result<Self::Deserialized> {
+            let len = bytes.read_pod::<u64>()?;
+            let mut result = Vec::with_capacity(len as usize);
+            bytes.read_to_end(&mut result)?;
+            Ok(result)
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "allocated")]
+    fn test_detects_over_allocation() {
+        check_bytes::<Preallocating>(&(1u64 << 20).to_le_bytes());
+    }
+
+    #[test]
+    fn test_vec_malformed() {
+        let seeds = [
+            seed(Vec::<u64>::new()),
+            seed(vec![7u64]),
+            seed((0..100u64).collect::<Vec<_>>()),
+        ];
+        check_malformed::<Vec<u64>>(&seeds, 200, 1);
+        check_malformed::<Vec<u8>>(&[seed(vec![1u8, 2, 3])], 200, 2);
+    }
+
+    #[test]
+    fn test_tuple_malformed() {
+        let seeds = [seed((vec![1u32, 2, 3], UseCast(4u64)))];
+        check_malformed::<(Vec<u32>, UseCast<u64>)>(&seeds, 200, 3);
+        let seeds = [seed((UseCast(1u8), vec![2u16; 10], vec![3u128; 2]))];
+        check_malformed::<(UseCast<u8>, Vec<u16>, Vec<u128>)>(&seeds, 200, 4);
+    }
+}
@@ -1,7 +1,10 @@
+pub mod compress;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
 pub mod util;
 
-use crate::util::{ReadUtil, WriteUtil};
-use bytemuck::Pod;
+pub use crate::compress::Lz4Compressed;
+use crate::util::{LePod, ReadUtil, WriteUtil};
 use bytes::{BufMut, Bytes, BytesMut};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
@@ -18,6 +21,8 @@ pub enum Error {
     IoError(#[from] std::io::Error),
     #[error("received malformed message: {0}")]
     ReceivedMalformedMessage(bytemuck::PodCastError),
+    #[error("cannot decompress message: {0}")]
+    DecompressError(#[from] lz4_flex::block::DecompressError),
 }
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -79,19 +84,20 @@ impl<T: Serialize + DeserializeOwned + Send + Sync + Any> Communicate for UseSer
     }
 }
 
-pub struct UseCast<T: Pod + Send + Sync + Any>(pub T);
+/// Sent as the raw bytes of `T`, in little-endian order, see `LePod`.
+pub struct UseCast<T: LePod + Send + Sync + Any>(pub T);
 
-pub trait AsUseCast: Pod + Send + Sync + Any {
+pub trait AsUseCast: LePod + Send + Sync + Any {
     fn use_cast(self) -> UseCast<Self>;
 }
 
-impl<T: Pod + Send + Sync + Any> AsUseCast for T {
+impl<T: LePod + Send + Sync + Any> AsUseCast for T {
     fn use_cast(self) -> UseCast<Self> {
         UseCast(self)
     }
 }
 
-impl<T: Pod + Send + Sync + Any> Communicate for UseCast<T> {
+impl<T: LePod + Send + Sync + Any> Communicate for UseCast<T> {
     type Deserialized = T;
 
     fn size_in_bytes(&self) -> usize {
@@ -99,62 +105,56 @@ impl<T: Pod + Send + Sync + Any> Communicate for UseCast<T> {
     }
 
     fn to_bytes<W: Write>(&self, mut dest: W) {
-        dest.write_pod(&self.0).unwrap()
+        dest.write_pod_le(&self.0).unwrap()
     }
 
     fn from_bytes<R: Read>(mut bytes: R) -> Result<Self::Deserialized> {
-        Ok(bytes.read_pod()?)
+        Ok(bytes.read_pod_le()?)
     }
 }
 
-impl<T: Pod + Send + Sync + Any> Communicate for [T] {
+impl<T: LePod + Send + Sync + Any> Communicate for [T] {
     type Deserialized = Vec<T>;
 
     fn size_in_bytes(&self) -> usize {
-        std::mem::size_of::<u64>() + std::mem::size_of::<T>() * self.len()
+        std::mem::size_of::<u64>() + std::mem::size_of_val(self)
     }
 
     fn to_bytes<W: Write>(&self, mut dest: W) {
-        let raw = bytemuck::cast_slice::<_, u8>(self);
-        debug_assert_eq!(raw.len(), self.len() * std::mem::size_of::<T>());
-        dest.write_pod(&(self.len() as u64)).unwrap();
-        dest.write_all(raw).unwrap();
+        dest.write_pod_le(&(self.len() as u64)).unwrap();
+        dest.write_pod_slice_le(self).unwrap();
     }
 
     fn from_bytes<R: Read>(mut bytes: R) -> Result<Self::Deserialized> {
-        let len = bytes.read_pod::<u64>()?;
+        let len = bytes.read_pod_le::<u64>()?;
         let result = (0..len)
-            .map(|_| Ok(bytes.read_pod::<T>()?))
+            .map(|_| Ok(bytes.read_pod_le::<T>()?))
             .collect::<Result<Vec<T>>>()?;
         Ok(result)
     }
 }
 
-// impl<T: Communicate, const SIZE: usize> Communicate for [T; SIZE]
-// where T::Deserialized: Default + Copy
-// {
-//     type Deserialized = [T::Deserialized; SIZE];
-//
-//     fn size_in_bytes(&self) -> usize {
-//         self.iter().map(|x| x.size_in_bytes()).sum()
-//     }
-//
-//     fn to_bytes<W: Write>(&self, mut dest: W) {
-//         for x in self.iter() {
-//             x.to_bytes(&mut dest);
-//         }
-//     }
-//
-//     fn from_bytes<R: Read>(mut bytes: R) -> Result<Self::Deserialized> {
-//         let mut result = [T::Deserialized::default(); SIZE];
-//         for x in result.iter_mut() {
-//             *x = T::from_bytes(&mut bytes)?;
-//         }
-//         Ok(result)
-//     }
-// }
-
-impl<T: Pod + Send + Sync + Any> Communicate for Vec<T> {
+/// Unlike `[T]`, no length is sent: both sides know `SIZE`, so e.g. a pair of
+/// digests costs exactly its bytes. A short input is an `IoError`.
+impl<T: LePod + Send + Sync + Any, const SIZE: usize> Communicate for [T; SIZE] {
+    type Deserialized = [T; SIZE];
+
+    fn size_in_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
+    fn to_bytes<W: Write>(&self, mut dest: W) {
+        dest.write_pod_slice_le(self).unwrap();
+    }
+
+    fn from_bytes<R: Read>(mut bytes: R) -> Result<Self::Deserialized> {
+        let mut result = [T::zeroed(); SIZE];
+        bytes.read_pod_slice_le(&mut result)?;
+        Ok(result)
+    }
+}
+
+impl<T: LePod + Send + Sync + Any> Communicate for Vec<T> {
     type Deserialized = Vec<T>;
 
     fn size_in_bytes(&self) -> usize {
@@ -242,3 +242,68 @@ impl Communicate for () {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<M: Communicate>(msg: &M) -> (Vec<u8>, M::Deserialized) {
+        let mut bytes = Vec::new();
+        msg.to_bytes(&mut bytes);
+        assert_eq!(bytes.len(), msg.size_in_bytes());
+        let deserialized = M::from_bytes(bytes.as_slice()).unwrap();
+        (bytes, deserialized)
+    }
+
+    #[test]
+    fn test_array_round_trip() {
+        let msg = [1u64, 2, 3, u64::MAX];
+        let (bytes, deserialized) = round_trip(&msg);
+        assert_eq!(deserialized, msg);
+        // no length prefix, each element as le
+        let expected = msg.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+        assert_eq!(bytes, expected);
+
+        let (bytes, deserialized) = round_trip(&[0u32; 0]);
+        assert!(bytes.is_empty());
+        assert_eq!(deserialized, [0u32; 0]);
+    }
+
+    #[test]
+    fn test_nested_array_round_trip() {
+        let msg = ([7u8, 9], vec![1u32, 2]);
+        let (bytes, deserialized) = round_trip(&msg);
+        assert_eq!(deserialized, msg);
+        assert_eq!(
+            bytes,
+            [
+                &[7u8, 9][..],
+                &2u64.to_le_bytes(),
+                &1u32.to_le_bytes(),
+                &2u32.to_le_bytes(),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_use_cast_is_le() {
+        let (bytes, deserialized) = round_trip(&UseCast(0x0102_0304_0506_0708u64));
+        assert_eq!(deserialized, 0x0102_0304_0506_0708);
+        assert_eq!(bytes, [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]);
+
+        // the length prefix is a little-endian u64
+        let (bytes, deserialized) = round_trip(&vec![0x0102u16]);
+        assert_eq!(deserialized, [0x0102]);
+        assert_eq!(bytes, [1, 0, 0, 0, 0, 0, 0, 0, 0x02, 0x01]);
+    }
+
+    #[test]
+    fn test_short_array() {
+        let bytes = [0u8; 31];
+        match <[u64; 4]>::from_bytes(&bytes[..]) {
+            Err(Error::IoError(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
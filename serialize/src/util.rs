@@ -1,25 +1,160 @@
-use std::io::{Read, Write};
 use bytemuck::Pod;
+use std::io::{Read, Write};
+
+/// A `Pod` type with a fixed little-endian wire format.
+///
+/// On little-endian targets every `Pod` type is `LePod`, and writing one is a
+/// plain memcpy. On big-endian targets only the types below implement it, by
+/// byte swapping each primitive field, so sending a type whose fields cannot
+/// be swapped is a compile error instead of a silently corrupted message.
+pub trait LePod: Pod {
+    /// Convert between the native and the little-endian representation. This
+    /// is an involution, and the identity on little-endian targets.
+    fn swap_le(&mut self);
+}
+
+#[cfg(target_endian = "little")]
+impl<T: Pod> LePod for T {
+    #[inline]
+    fn swap_le(&mut self) {}
+}
+
+#[cfg(target_endian = "big")]
+macro_rules! impl_le_pod_primitive {
+    ($($ty: ty), +) => {
+        $(
+            impl LePod for $ty {
+                #[inline]
+                fn swap_le(&mut self) {
+                    *self = self.swap_bytes();
+                }
+            }
+        )+
+    };
+}
+
+#[cfg(target_endian = "big")]
+impl_le_pod_primitive!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
 
-pub trait WriteUtil{
+#[cfg(target_endian = "big")]
+impl<T: LePod, const SIZE: usize> LePod for [T; SIZE]
+where
+    [T; SIZE]: Pod,
+{
+    #[inline]
+    fn swap_le(&mut self) {
+        self.iter_mut().for_each(LePod::swap_le);
+    }
+}
+
+pub trait WriteUtil {
     fn write_pod<T: Pod>(&mut self, thing: &T) -> std::io::Result<()>;
+
+    /// Write `thing` in little-endian order.
+    fn write_pod_le<T: LePod>(&mut self, thing: &T) -> std::io::Result<()>;
+
+    /// Write all elements of `things` in little-endian order, without a
+    /// length.
+    fn write_pod_slice_le<T: LePod>(&mut self, things: &[T]) -> std::io::Result<()>;
 }
 
-pub trait ReadUtil{
+pub trait ReadUtil {
     fn read_pod<T: Pod>(&mut self) -> std::io::Result<T>;
+
+    /// Read a `T` written by `write_pod_le`.
+    fn read_pod_le<T: LePod>(&mut self) -> std::io::Result<T>;
+
+    /// Fill `things` with elements written by `write_pod_slice_le`.
+    fn read_pod_slice_le<T: LePod>(&mut self, things: &mut [T]) -> std::io::Result<()>;
 }
 
-impl<W: Write> WriteUtil for W{
-    fn write_pod<T: Pod>(&mut self, thing: &T) -> std::io::Result<()>{
+impl<W: Write> WriteUtil for W {
+    fn write_pod<T: Pod>(&mut self, thing: &T) -> std::io::Result<()> {
         let bytes = bytemuck::bytes_of(thing);
-        self.write_all(&bytes)
+        self.write_all(bytes)
+    }
+
+    #[inline]
+    fn write_pod_le<T: LePod>(&mut self, thing: &T) -> std::io::Result<()> {
+        if cfg!(target_endian = "little") {
+            self.write_pod(thing)
+        } else {
+            let mut swapped = *thing;
+            swapped.swap_le();
+            self.write_pod(&swapped)
+        }
+    }
+
+    fn write_pod_slice_le<T: LePod>(&mut self, things: &[T]) -> std::io::Result<()> {
+        if cfg!(target_endian = "little") {
+            self.write_all(bytemuck::cast_slice::<_, u8>(things))
+        } else {
+            things.iter().try_for_each(|thing| self.write_pod_le(thing))
+        }
     }
 }
 
-impl<R: Read> ReadUtil for R{
-    fn read_pod<T: Pod>(&mut self) -> std::io::Result<T>{
+impl<R: Read> ReadUtil for R {
+    fn read_pod<T: Pod>(&mut self) -> std::io::Result<T> {
         let mut result = T::zeroed();
         self.read_exact(bytemuck::bytes_of_mut(&mut result))?;
         Ok(result)
     }
-}
\ No newline at end of file
+
+    #[inline]
+    fn read_pod_le<T: LePod>(&mut self) -> std::io::Result<T> {
+        let mut result = self.read_pod::<T>()?;
+        result.swap_le();
+        Ok(result)
+    }
+
+    fn read_pod_slice_le<T: LePod>(&mut self, things: &mut [T]) -> std::io::Result<()> {
+        self.read_exact(bytemuck::cast_slice_mut::<_, u8>(things))?;
+        things.iter_mut().for_each(LePod::swap_le);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn le_bytes<T: LePod + PartialEq + std::fmt::Debug>(thing: T) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.write_pod_le(&thing).unwrap();
+        assert_eq!(bytes.as_slice().read_pod_le::<T>().unwrap(), thing);
+        bytes
+    }
+
+    #[test]
+    fn test_primitives_are_le() {
+        assert_eq!(le_bytes(0xabu8), [0xab]);
+        assert_eq!(le_bytes(0x0102u16), [0x02, 0x01]);
+        assert_eq!(le_bytes(0x0102_0304u32), [0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(
+            le_bytes(0x0102_0304_0506_0708u64),
+            [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01]
+        );
+        assert_eq!(
+            le_bytes(0x0102_0304_0506_0708_090a_0b0c_0d0e_0f10u128),
+            [
+                0x10, 0x0f, 0x0e, 0x0d, 0x0c, 0x0b, 0x0a, 0x09, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03,
+                0x02, 0x01
+            ]
+        );
+        assert_eq!(le_bytes(-2i32), [0xfe, 0xff, 0xff, 0xff]);
+        assert_eq!(le_bytes([0x0102u16, 0x0304]), [0x02, 0x01, 0x04, 0x03]);
+    }
+
+    #[test]
+    fn test_slice_is_le() {
+        let things = [0x0102u16, 0x0304, 0x0506];
+        let mut bytes = Vec::new();
+        bytes.write_pod_slice_le(&things).unwrap();
+        assert_eq!(bytes, [0x02, 0x01, 0x04, 0x03, 0x06, 0x05]);
+
+        let mut read = [0u16; 3];
+        bytes.as_slice().read_pod_slice_le(&mut read).unwrap();
+        assert_eq!(read, things);
+    }
+}
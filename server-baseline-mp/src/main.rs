@@ -1,91 +1,214 @@
-use crate::server::basic_server;
-use bin_utils::{server::Options, InputSize};
+use crate::server::{basic_server, BaselineField};
+use bin_utils::{
+    interrupt,
+    server::{LegacyCsv, Options},
+    InputSize, PrioField,
+};
 use bridge::{client_server::ClientsPool, mpc_conn::MpcConnection};
 use clap::Arg;
 use crypto_primitives::uint::UInt;
-use prio::field::Field64;
+use prio::field::{Field128, Field64};
+use std::str::FromStr;
 use tokio::net::TcpListener;
+use tracing::info;
 
 mod server;
 
-type F = Field64;
-fn eval_at() -> F {
-    F::from(12123)
+const LEGACY_CSV: LegacyCsv = LegacyCsv {
+    header: "client comm, MPC comm, client time, skip ,mpc message prepare, mpc verify, skip, skip",
+    mpc_comm_sent: false,
+    phases: [
+        Some("client"),
+        None,
+        Some("mpc_prepare"),
+        Some("mpc_verify"),
+        None,
+        None,
+    ],
+};
+
+fn eval_at<F: BaselineField>() -> F {
+    F::from_u64(12123)
+}
+
+/// Number of client messages the servers verify at a time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BatchSize {
+    Fixed(usize),
+    /// Two batches per thread of the compute pool.
+    Auto,
+}
+
+impl BatchSize {
+    fn resolve(self, num_msgs: usize, num_threads: usize) -> usize {
+        match self {
+            BatchSize::Fixed(size) => size,
+            BatchSize::Auto => num_msgs.div_ceil(2 * num_threads.max(1)).max(1),
+        }
+    }
+}
+
+impl FromStr for BatchSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(BatchSize::Auto),
+            _ => match s.parse::<usize>() {
+                Ok(size) if size > 0 => Ok(BatchSize::Fixed(size)),
+                _ => Err(format!("invalid batch size: {}", s)),
+            },
+        }
+    }
 }
 
 struct CustomOptions {
-    pub batch_size: usize,
+    pub batch_size: BatchSize,
+    pub field: PrioField,
+    /// Print the aggregate, e.g. to compare it with the one of ELSA.
+    pub print_aggregate: bool,
+}
+
+/// The sum of each input over the clients, from the sum of each of its bits,
+/// wrapping as the `u64` aggregate of the ELSA servers.
+fn recompose<I: UInt, F: BaselineField>(bit_sums: &[F]) -> Vec<u64> {
+    bit_sums
+        .chunks(I::NUM_BITS)
+        .map(|bits| {
+            bits.iter()
+                .enumerate()
+                .fold(0u64, |sum, (j, bit)| sum.wrapping_add(bit.to_u64() << j))
+        })
+        .collect()
 }
 
-async fn main_with_options<I: UInt>(options: Options<CustomOptions>) {
+async fn main_with_field<I: UInt>(options: Options<CustomOptions>) {
+    match options.custom_args.field {
+        PrioField::F64 => main_with_options::<I, Field64>(options).await,
+        PrioField::F128 => main_with_options::<I, Field128>(options).await,
+    }
+}
+
+async fn main_with_options<I: UInt, F: BaselineField>(options: Options<CustomOptions>) {
     tracing_subscriber::fmt()
         .pretty()
         .with_max_level(options.log_level)
         .init();
-    let listener = TcpListener::bind(("0.0.0.0", options.client_port))
+    let listener = TcpListener::bind(options.client_listen_addr())
         .await
         .unwrap();
     // accepts clients connection
-    let clients = ClientsPool::new(options.num_clients, listener).await;
+    let clients = ClientsPool::new(options.num_clients, listener, options.client_token).await;
+    options.metrics.set_clients_connected(clients.num_of_clients());
 
     // connect to peer
     let peer = if !options.is_alice() {
         // I'm Bob and need a complete address of alice.
-        MpcConnection::new_as_bob(&options.mpc_addr, options.num_mpc_sockets).await
+        MpcConnection::new_as_bob_from(
+            options.mpc_bind_addr,
+            &options.mpc_addr,
+            options.num_mpc_sockets,
+        )
+        .await
     } else {
         // I'm Alice and I need a port number of alice.
         let mpc_addr =
-            u16::from_str_radix(&options.mpc_addr, 10).expect("invalid mpc_addr as port");
-        MpcConnection::new_as_alice(mpc_addr, options.num_mpc_sockets).await
+            options.mpc_addr.parse::<u16>().expect("invalid mpc_addr as port");
+        MpcConnection::new_as_alice(options.mpc_bind_addr, mpc_addr, options.num_mpc_sockets).await
     };
+    let _metrics_server = options.serve_metrics(&peer).await;
 
-    let (_, _, stat) = basic_server::<I, F>(
+    let batch_size = options
+        .custom_args
+        .batch_size
+        .resolve(options.num_clients, rayon::current_num_threads());
+    let round = basic_server::<I, F>(
         options.is_bob,
         &clients,
         options.gsize,
-        options.custom_args.batch_size,
-        peer,
+        batch_size,
+        peer.clone(),
         eval_at(),
-    )
-    .await;
-    let client_comm = clients.num_bytes_received_from_all();
-    println!(
-        "client comm, MPC comm, client time, skip ,mpc message prepare, mpc verify, skip, skip"
     );
-    println!(
-        "{}, {}, {}, {}, {}, {}, {}, {}",
-        client_comm,
-        stat.mpc_comm,
-        stat.client_time,
-        0f64,
-        stat.mpc_prepare,
-        stat.mpc_verify,
-        0f64,
-        0f64
+    let (_, _, bit_sums, stat) = match interrupt::run_interruptible(round, &peer).await {
+        Ok(done) => done,
+        Err(interrupted) => {
+            let report = options.new_report("server-baseline-mp");
+            options.exit_interrupted(report, interrupted, &peer, &LEGACY_CSV)
+        },
+    };
+    let aggregate = recompose::<I, F>(&bit_sums);
+    info!(
+        "aggregate of {} clients with a valid proof, out of {}",
+        stat.num_valid,
+        clients.num_of_clients()
     );
+    if options.custom_args.print_aggregate {
+        println!("aggregate: {:?}", aggregate);
+    }
+
+    let mut report = options.new_report("server-baseline-mp");
+    report.client_comm = clients.num_bytes_received_from_all();
+    report.mpc_comm_recv = stat.mpc_comm;
+    report
+        .phase("client", stat.client_time)
+        .phase("mpc_prepare", stat.mpc_prepare)
+        .phase("mpc_verify", stat.mpc_verify)
+        .phase("mpc_aggregate", stat.mpc_aggregate)
+        .verified("snip", stat.num_valid);
+    options.emit_report(report, &LEGACY_CSV);
 }
 
 #[tokio::main]
 async fn main() {
     let options = Options::load_from_args_custom(
         "server-baseline-mp",
-        [Arg::new("batch")
-            .long("batch")
-            .takes_value(true)
-            .help("batch size")
-            .default_value("1024")],
+        [
+            Arg::new("batch")
+                .long("batch")
+                .takes_value(true)
+                .help("batch size, or auto for two batches per thread")
+                .default_value("1024"),
+            Arg::new("field")
+                .long("field")
+                .takes_value(true)
+                .help("prime field of prio: 64 or 128 bits (the clients need the same --field)")
+                .default_value("64"),
+            Arg::new("print_aggregate")
+                .long("print-aggregate")
+                .help("print the aggregate of the clients with a valid proof"),
+        ],
         |m| {
-            let batch_size = m
-                .value_of("batch")
-                .unwrap()
-                .parse::<usize>()
-                .expect("invalid batch size");
+            let batch_size = m.value_of("batch").unwrap().parse::<BatchSize>().unwrap();
+            let field = m.value_of("field").unwrap().parse::<PrioField>().unwrap();
 
-            CustomOptions { batch_size }
+            CustomOptions {
+                batch_size,
+                field,
+                print_aggregate: m.is_present("print_aggregate"),
+            }
         },
     );
+    options.install_compute_pool().unwrap();
     match options.input_size {
-        InputSize::U8 => main_with_options::<u8>(options).await,
-        InputSize::U32 => main_with_options::<u32>(options).await,
+        InputSize::U8 => main_with_field::<u8>(options).await,
+        InputSize::U32 => main_with_field::<u32>(options).await,
+        InputSize::U64 => main_with_field::<u64>(options).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BatchSize;
+
+    #[test]
+    fn test_batch_size() {
+        assert_eq!("auto".parse::<BatchSize>(), Ok(BatchSize::Auto));
+        assert_eq!("512".parse::<BatchSize>(), Ok(BatchSize::Fixed(512)));
+        assert!("0".parse::<BatchSize>().is_err());
+        assert_eq!(BatchSize::Fixed(512).resolve(10_000, 8), 512);
+        // ceil(10_000 / 16)
+        assert_eq!(BatchSize::Auto.resolve(10_000, 8), 625);
+        assert_eq!(BatchSize::Auto.resolve(0, 8), 1);
     }
 }
@@ -5,21 +5,113 @@ use bridge::{
 use prio::{encrypt::*, field::*, server::*};
 
 use bridge::id_tracker::RecvId;
+use bytes::Bytes;
 use crypto_primitives::uint::UInt;
 use rayon::prelude::*;
 use serialize::UseSerde;
 use tracing::info;
 
+/// The prime fields the baseline runs in, see `bin_utils::PrioField`.
+pub trait BaselineField: FieldElement + Send + Sync {
+    fn from_u64(x: u64) -> Self;
+
+    /// `self` as an integer in `[0, p)`, truncated to `u64`. Sums of bits
+    /// over the clients always fit.
+    fn to_u64(self) -> u64;
+}
+
+impl BaselineField for Field64 {
+    fn from_u64(x: u64) -> Self {
+        Field64::from(x)
+    }
+
+    fn to_u64(self) -> u64 {
+        u64::from(self)
+    }
+}
+
+impl BaselineField for Field128 {
+    fn from_u64(x: u64) -> Self {
+        Field128::from(u128::from(x))
+    }
+
+    fn to_u64(self) -> u64 {
+        u128::from(self) as u64
+    }
+}
+
+// Warning: Do not hardcode this in production case. This is only for testing.
+// Each server should have unique pair of alice and bob private key, but here they are using the same, just
+// for code simplicity.
+fn alice_priv_key() -> PrivateKey {
+    PrivateKey::from_base64(
+        "BNNOqoU54GPo+1gTPv+hCgA9U2ZCKd76yOMrWa1xTWgeb4LhF\
+             LMQIQoRwDVaW64g/WTdcxT4rDULoycUNFB60LER6hPEHg/ObBnRPV1rwS3nj9Bj0tbjVPPyL9p8QW8B+w==",
+    )
+    .unwrap()
+}
+
+fn bob_priv_key() -> PrivateKey {
+    PrivateKey::from_base64(
+        "BIl6j+J6dYttxALdjISDv6ZI4/VWVEhUzaS05LgrsfswmbLOgN\
+             t9HUC2E0w+9RqZx3XMkdEHBHfNuCSMpOwofVSq3TfyKwn0NrftKisKKVSaTOt5seJ67P5QL4hxgPWvxw==",
+    )
+    .unwrap()
+}
+
 pub struct Statistics {
     pub client_comm: usize,
     pub client_time: f64,
     pub mpc_comm: usize,
     pub mpc_prepare: f64,
     pub mpc_verify: f64,
+    pub mpc_aggregate: f64,
+    /// Number of clients whose proof is valid, and are in the aggregate.
+    pub num_valid: usize,
+}
+
+/// My share of the sum of the inputs in `msgs` whose proof is valid, as the
+/// first server of the clients if `is_first`, and the number of these
+/// clients. `first` and `second` are the verification messages of each client
+/// from the first and the second server.
+fn aggregate_valid<F>(
+    dim: usize,
+    is_first: bool,
+    priv_key: &PrivateKey,
+    msgs: &[Bytes],
+    first: &[VerificationMessage<F>],
+    second: &[VerificationMessage<F>],
+) -> (Vec<F>, usize)
+where
+    F: FieldElement + Send + Sync,
+{
+    msgs.par_iter()
+        .zip(first)
+        .zip(second)
+        .fold(
+            || (Server::new(dim, is_first, priv_key.clone()).unwrap(), 0),
+            |(mut sv, num_valid), ((msg, v1), v2)| {
+                let valid = sv.aggregate(&msg[..], v1, v2).unwrap();
+                (sv, num_valid + valid as usize)
+            },
+        )
+        .map(|(sv, num_valid)| (sv.total_shares().to_vec(), num_valid))
+        .reduce(
+            || (vec![F::zero(); dim], 0),
+            |(mut total, num_valid), (other, other_valid)| {
+                for (t, o) in total.iter_mut().zip(other) {
+                    *t += o;
+                }
+                (total, num_valid + other_valid)
+            },
+        )
 }
 
 /// Basic version of the FL server.
 /// server id is 0 if b is false, otherwise it is 1.
+/// Returns my verification messages, the ones of the peer, and the sum of
+/// each input bit over the clients with a valid proof, which is revealed to
+/// both servers.
 pub async fn basic_server<I, F>(
     is_bob: bool,
     clients: &ClientsPool,
@@ -30,11 +122,12 @@ pub async fn basic_server<I, F>(
 ) -> (
     Vec<VerificationMessage<F>>,
     Vec<VerificationMessage<F>>,
+    Vec<F>,
     Statistics,
 )
 where
     I: UInt,
-    F: FieldElement + Send + Sync,
+    F: BaselineField,
 {
     let mut id = IdGen::new();
     // track the message id with client, and message id with peer
@@ -53,30 +146,19 @@ where
     println!(
         "Client messages size: {}x({}, {})",
         client_messages.len(),
-        client_messages[0].len(),
-        client_messages[1].len()
+        client_messages[0].1.len(),
+        client_messages[1].1.len()
     );
     let client_time = end_timer!(timer).elapsed().as_secs_f64();
 
     let timer = start_timer!(|| "Server prepare verification messages");
 
-    // Warning: Do not hardcode this in production case. This is only for testing.
-    // Each server should have unique pair of alice and bob private key, but here they are using the same, just
-    // for code simplicity.
-    let bob_priv_key = PrivateKey::from_base64(
-        "BIl6j+J6dYttxALdjISDv6ZI4/VWVEhUzaS05LgrsfswmbLOgN\
-             t9HUC2E0w+9RqZx3XMkdEHBHfNuCSMpOwofVSq3TfyKwn0NrftKisKKVSaTOt5seJ67P5QL4hxgPWvxw==",
-    )
-    .unwrap();
-    let alice_priv_key = PrivateKey::from_base64(
-        "BNNOqoU54GPo+1gTPv+hCgA9U2ZCKd76yOMrWa1xTWgeb4LhF\
-             LMQIQoRwDVaW64g/WTdcxT4rDULoycUNFB60LER6hPEHg/ObBnRPV1rwS3nj9Bj0tbjVPPyL9p8QW8B+w==",
-    )
-    .unwrap();
+    let bob_priv_key = bob_priv_key();
+    let alice_priv_key = alice_priv_key();
 
     let (mut msgs_as_alice, mut msgs_as_bob) = (Vec::new(), Vec::new());
-    for (idx, msg) in client_messages.into_iter().enumerate() {
-        match (idx % 2 == 0, is_bob) {
+    for (uid, msg) in client_messages {
+        match (uid.is_even(), is_bob) {
             (true, false) => msgs_as_alice.push(msg),
             (true, true) => msgs_as_bob.push(msg),
             (false, false) => msgs_as_bob.push(msg),
@@ -138,16 +220,118 @@ where
         .collect::<Vec<_>>();
 
     let mpc_verify = end_timer!(timer).elapsed().as_secs_f64();
+    // the aggregate is only opened to cross-check the sums, so its traffic is
+    // left out of the comparison
+    let mpc_comm = peer.num_bytes_received();
+
+    let timer = start_timer!(|| "Server aggregate valid inputs");
+    // the peer lists its clients as alice first, which are mine as bob
+    let num_as_alice = msgs_as_alice.len();
+    let (peer_as_alice, peer_as_bob) = peer_verif_messages.split_at(msgs_as_bob.len());
+    let (local_as_alice, local_as_bob) = local_verif_messages.split_at(num_as_alice);
+    let (total_as_alice, valid_as_alice) = aggregate_valid(
+        dim,
+        true,
+        &alice_priv_key,
+        &msgs_as_alice,
+        local_as_alice,
+        peer_as_bob,
+    );
+    let (total_as_bob, valid_as_bob) = aggregate_valid(
+        dim,
+        false,
+        &bob_priv_key,
+        &msgs_as_bob,
+        peer_as_alice,
+        local_as_bob,
+    );
+    let my_total = total_as_alice
+        .into_iter()
+        .zip(total_as_bob)
+        .map(|(a, b)| a + b)
+        .collect::<Vec<_>>();
+    let peer_total = peer
+        .exchange_message(id.next_exchange_id(), &UseSerde(my_total.clone()))
+        .await
+        .unwrap();
+    let bit_sums = my_total
+        .into_iter()
+        .zip(peer_total)
+        .map(|(mine, theirs)| mine + theirs)
+        .collect::<Vec<_>>();
+    let mpc_aggregate = end_timer!(timer).elapsed().as_secs_f64();
 
     (
         local_verif_messages,
         peer_verif_messages,
+        bit_sums,
         Statistics {
             client_comm: clients.num_bytes_received_from_all(),
             client_time,
-            mpc_comm: peer.num_bytes_received(),
+            mpc_comm,
             mpc_prepare,
             mpc_verify,
+            mpc_aggregate,
+            num_valid: valid_as_alice + valid_as_bob,
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recompose;
+    use client_baseline_mp::data_prep::prepare_message;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Aggregate the messages of a few clients as both servers, without the
+    /// network, and compare the sums with the plaintext.
+    fn aggregate_in<F: BaselineField>() {
+        const GSIZE: usize = 5;
+        const NUM_CLIENTS: usize = 6;
+        let dim = GSIZE * u8::NUM_BITS;
+        let mut rng = StdRng::seed_from_u64(12345);
+        let inputs = (0..NUM_CLIENTS)
+            .map(|_| (0..GSIZE).map(|_| rng.gen::<u8>()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let (msgs_alice, msgs_bob): (Vec<_>, Vec<_>) = inputs
+            .iter()
+            .map(|data| prepare_message::<u8, F>(data))
+            .unzip();
+
+        let eval_at = F::from_u64(12123);
+        let verify = |is_first, priv_key: &PrivateKey, msgs: &[Bytes]| {
+            msgs.iter()
+                .map(|msg| {
+                    let mut sv = Server::new(dim, is_first, priv_key.clone()).unwrap();
+                    sv.generate_verification_message(eval_at, &msg[..]).unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let (alice_key, bob_key) = (alice_priv_key(), bob_priv_key());
+        let v_alice = verify(true, &alice_key, &msgs_alice);
+        let v_bob = verify(false, &bob_key, &msgs_bob);
+
+        let (total_alice, valid_alice) =
+            aggregate_valid(dim, true, &alice_key, &msgs_alice, &v_alice, &v_bob);
+        let (total_bob, valid_bob) =
+            aggregate_valid(dim, false, &bob_key, &msgs_bob, &v_alice, &v_bob);
+        assert_eq!((valid_alice, valid_bob), (NUM_CLIENTS, NUM_CLIENTS));
+
+        let bit_sums = total_alice
+            .into_iter()
+            .zip(total_bob)
+            .map(|(a, b)| a + b)
+            .collect::<Vec<_>>();
+        let expected = (0..GSIZE)
+            .map(|i| inputs.iter().map(|data| u64::from(data[i])).sum::<u64>())
+            .collect::<Vec<_>>();
+        assert_eq!(recompose::<u8, F>(&bit_sums), expected);
+    }
+
+    #[test]
+    fn test_aggregate_in_both_fields() {
+        aggregate_in::<Field64>();
+        aggregate_in::<Field128>();
+    }
+}
@@ -1,5 +1,9 @@
 use crate::protocol::prio_ring_sim_server;
-use bin_utils::{server::Options, InputSize};
+use bin_utils::{
+    interrupt,
+    server::{LegacyCsv, Options},
+    InputSize,
+};
 use bindings::ROTMode;
 use bridge::{
     client_server::ClientsPool, end_timer, id_tracker::IdGen, mpc_conn::MpcConnection, start_timer,
@@ -10,16 +14,23 @@ use crypto_primitives::{
     bits::{BitsLE, SeededInputShare},
     uint::UInt,
 };
-use rand::{rngs::StdRng, SeedableRng};
 use rayon::prelude::*;
 use serialize::UseCast;
 use tokio::net::TcpListener;
 use tracing::info;
 
 mod protocol;
+
+const LEGACY_CSV: LegacyCsv = LegacyCsv {
+    header: "client comm, MPC comm, client time, skip ,mpc, skip, skip, skip",
+    mpc_comm_sent: true,
+    phases: [Some("client"), None, Some("mpc"), None, None, None],
+};
+
 struct CustomOptions {
     mode: ROTMode,
     rot_port: i32,
+    rot_parallelism: Option<usize>,
 }
 
 async fn main_with_options<I: UInt>(options: Options<CustomOptions>) {
@@ -27,79 +38,97 @@ async fn main_with_options<I: UInt>(options: Options<CustomOptions>) {
         .pretty()
         .with_max_level(options.log_level)
         .init();
-    let listener = TcpListener::bind(("0.0.0.0", options.client_port))
+    let listener = TcpListener::bind(options.client_listen_addr())
         .await
         .unwrap();
     // accepts clients connection
-    let clients = ClientsPool::new(options.num_clients, listener).await;
+    let clients = ClientsPool::new(options.num_clients, listener, options.client_token).await;
+    options.metrics.set_clients_connected(clients.num_of_clients());
 
     let (clients_alice, clients_bob) = clients.split(options.is_alice());
 
     // connect to peer
     let peer = if !options.is_alice() {
         // I'm Bob and need a complete address of alice.
-        MpcConnection::new_as_bob(&options.mpc_addr, options.num_mpc_sockets).await
+        MpcConnection::new_as_bob_from(
+            options.mpc_bind_addr,
+            &options.mpc_addr,
+            options.num_mpc_sockets,
+        )
+        .await
     } else {
         // I'm Alice and I need a port number of alice.
-        let mpc_addr =
-            u16::from_str_radix(&options.mpc_addr, 10).expect("invalid mpc_addr as port");
-        MpcConnection::new_as_alice(mpc_addr, options.num_mpc_sockets).await
+        let mpc_addr = options
+            .mpc_addr
+            .parse::<u16>()
+            .expect("invalid mpc_addr as port");
+        MpcConnection::new_as_alice(options.mpc_bind_addr, mpc_addr, options.num_mpc_sockets).await
     };
+    let _metrics_server = options.serve_metrics(&peer).await;
 
-    let timer = start_timer!(|| "C->S");
+    let round = async {
+        let timer = start_timer!(|| "C->S");
 
-    let alice_shares = clients_alice
-        .subscribe_and_get::<UseCast<SeededInputShare>>(IdGen::new().next_recv_id())
-        .await
-        .unwrap();
-    let alice_shares = alice_shares
-        .into_par_iter()
-        .map(|x| x.expand::<I>(options.gsize))
-        .collect::<Vec<_>>();
-    alice_shares.drop_into_black_box();
-    let bob_shares = clients_bob
-        .subscribe_and_get::<Vec<BitsLE<I>>>(IdGen::new().next_recv_id())
-        .await
-        .unwrap();
-    bob_shares.drop_into_black_box();
+        let alice_shares = clients_alice
+            .subscribe_and_get::<UseCast<SeededInputShare>>(IdGen::new().next_recv_id())
+            .await
+            .unwrap();
+        let alice_shares = alice_shares
+            .into_par_iter()
+            .map(|(_, x)| x.expand::<I>(options.gsize))
+            .collect::<Vec<_>>();
+        alice_shares.drop_into_black_box();
+        let bob_shares = clients_bob
+            .subscribe_and_get::<Vec<BitsLE<I>>>(IdGen::new().next_recv_id())
+            .await
+            .unwrap();
+        bob_shares.drop_into_black_box();
 
-    let client_time = end_timer!(timer).elapsed().as_secs_f64();
+        let client_time = end_timer!(timer).elapsed().as_secs_f64();
 
-    info!(
-        "Number of bytes received from clients: {}",
-        clients.num_bytes_received_from_all()
-    );
+        info!(
+            "Number of bytes received from clients: {}",
+            clients.num_bytes_received_from_all()
+        );
 
-    let mut rng = StdRng::from_entropy();
-    let timer = start_timer!(|| "MPC");
-    let mpc_comm = prio_ring_sim_server::<I, u64, _>(
-        &mut rng,
-        clients.num_of_clients(),
-        peer,
-        (options.custom_args.rot_port
+        let mut rng = options.local_rng();
+        let rot_ports = (options.custom_args.rot_port
             ..options.custom_args.rot_port + options.num_mpc_sockets as i32)
             .step_by(2)
-            .collect(),
-        options.gsize,
-        options.custom_args.mode,
-    )
-    .await;
-    let mpc_time = end_timer!(timer).elapsed().as_secs_f64();
+            .collect::<Vec<_>>();
+        let rot_parallelism = options
+            .custom_args
+            .rot_parallelism
+            .unwrap_or(rot_ports.len());
+        let timer = start_timer!(|| "MPC");
+        let mpc_comm = prio_ring_sim_server::<I, u64, _>(
+            &mut rng,
+            clients.num_of_clients(),
+            peer.clone(),
+            rot_ports,
+            options.gsize,
+            options.custom_args.mode,
+            rot_parallelism,
+        )
+        .await;
+        let mpc_time = end_timer!(timer).elapsed().as_secs_f64();
+        (client_time, mpc_comm, mpc_time)
+    };
+    let (client_time, mpc_comm, mpc_time) = match interrupt::run_interruptible(round, &peer).await {
+        Ok(done) => done,
+        Err(interrupted) => {
+            let report = options.new_report("server-baseline");
+            options.exit_interrupted(report, interrupted, &peer, &LEGACY_CSV)
+        },
+    };
 
     info!("Number of bytes sent to peer: {}", mpc_comm);
 
-    println!("client comm, MPC comm, client time, skip ,mpc, skip, skip, skip");
-    println!(
-        "{}, {}, {}, {}, {}, {}, {}, {}",
-        clients.num_bytes_received_from_all(),
-        mpc_comm,
-        client_time,
-        0f64,
-        mpc_time,
-        0f64,
-        0f64,
-        0f64
-    );
+    let mut report = options.new_report("server-baseline");
+    report.client_comm = clients.num_bytes_received_from_all();
+    report.mpc_comm_sent = mpc_comm;
+    report.phase("client", client_time).phase("mpc", mpc_time);
+    options.emit_report(report, &LEGACY_CSV);
 }
 
 #[tokio::main]
@@ -117,6 +146,10 @@ pub async fn main() {
                 .help("port used for ROT")
                 .takes_value(true)
                 .default_value("8999"),
+            Arg::new("rot_parallelism")
+                .long("rot-parallelism")
+                .help("maximum number of ROT sessions run at once (default: all)")
+                .takes_value(true),
         ],
         |matches| {
             let mode = if matches.is_present("ferret") {
@@ -126,11 +159,24 @@ pub async fn main() {
             };
             let rot_port = i32::from_str_radix(matches.value_of("rot_port").unwrap(), 10).unwrap();
 
-            CustomOptions { mode, rot_port }
+            let rot_parallelism = matches.value_of("rot_parallelism").map(|n| {
+                n.parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .expect("invalid rot-parallelism")
+            });
+
+            CustomOptions {
+                mode,
+                rot_port,
+                rot_parallelism,
+            }
         },
     );
+    options.install_compute_pool().unwrap();
     match options.input_size {
         InputSize::U8 => main_with_options::<u8>(options).await,
         InputSize::U32 => main_with_options::<u32>(options).await,
+        InputSize::U64 => main_with_options::<u64>(options).await,
     }
 }
@@ -2,9 +2,81 @@ use bindings::{get_rot_emp_dummy, ROTMode, RotConfig};
 use bridge::{end_timer, id_tracker::IdGen, mpc_conn::MpcConnection, start_timer};
 use crypto_primitives::uint::UInt;
 use rand::{prelude::*, Rng};
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::Semaphore;
 use tracing::info;
 
+/// Delay between starting the Alice side of a ROT session, which listens, and
+/// the Bob side, which connects. EMP retries the connection, so this only
+/// avoids needless retries.
+const ROT_LISTEN_DELAY: Duration = Duration::from_millis(10);
+
+/// Run one EMP ROT session per port, at most `parallelism` at a time, and
+/// return the `(alice, bob)` results in the order of `ports`. On each port,
+/// this server is Alice and listens, and is Bob and connects to the same port
+/// of `peer_addr`, where the peer is Alice.
+///
+/// Sessions are started in the order of `ports`, so if the peer does the same,
+/// a session only waits for a session of the peer that has been started, and
+/// the bound cannot deadlock.
+pub async fn run_rot_sessions<T, F>(
+    ports: &[i32],
+    peer_addr: &str,
+    parallelism: usize,
+    rot: F,
+) -> Vec<(T, T)>
+where
+    T: Send + 'static,
+    F: Fn(RotConfig) -> T + Send + Sync + 'static,
+{
+    let rot = Arc::new(rot);
+    let permits = Arc::new(Semaphore::new(parallelism.max(1)));
+    let num_done = Arc::new(AtomicUsize::new(0));
+    let num_sessions = ports.len();
+
+    let mut handles = Vec::with_capacity(num_sessions);
+    for &port in ports {
+        let permit = permits.clone().acquire_owned().await.unwrap();
+        info!("ROT session on port {} started", port);
+
+        let alice = {
+            let rot = rot.clone();
+            tokio::task::spawn_blocking(move || rot(RotConfig::Alice(port)))
+        };
+        tokio::time::sleep(ROT_LISTEN_DELAY).await;
+        let bob = {
+            let rot = rot.clone();
+            let peer_addr = CString::new(peer_addr).unwrap();
+            tokio::task::spawn_blocking(move || rot(RotConfig::Bob(peer_addr, port)))
+        };
+
+        let num_done = num_done.clone();
+        handles.push(tokio::spawn(async move {
+            let result = (alice.await.unwrap(), bob.await.unwrap());
+            drop(permit);
+            let done = num_done.fetch_add(1, Ordering::Relaxed) + 1;
+            info!(
+                "ROT session on port {} done ({}/{})",
+                port, done, num_sessions
+            );
+            result
+        }));
+    }
+
+    let mut results = Vec::with_capacity(num_sessions);
+    for handle in handles {
+        results.push(handle.await.unwrap());
+    }
+    results
+}
+
 /// FL Server that uses Ferret ROT to generate beaver triples.
 /// server id is 0 if b is false, otherwise it is 1.
 pub async fn prio_ring_sim_server<I: UInt, A: UInt, R: Rng>(
@@ -14,6 +86,7 @@ pub async fn prio_ring_sim_server<I: UInt, A: UInt, R: Rng>(
     rot_ports: Vec<i32>,
     gsize: usize,
     rot_mode: ROTMode,
+    rot_parallelism: usize,
 ) -> usize {
     // track the message id with client, and message id with peer
     let mut peer_id_gen = IdGen::new();
@@ -24,36 +97,12 @@ pub async fn prio_ring_sim_server<I: UInt, A: UInt, R: Rng>(
     let num_ots_needed_for_each_clients = I::NUM_BITS as usize * gsize;
     let total_ots_needed = num_clients * num_ots_needed_for_each_clients;
     let num_ots_for_each_port = total_ots_needed / rot_ports.len();
-    let rot_handles = rot_ports
-        .clone()
-        .into_iter()
-        .map(|port| {
-            let handle1 = tokio::task::spawn_blocking(move || {
-                get_rot_emp_dummy(
-                    (num_ots_for_each_port / 2) as i64,
-                    &RotConfig::Alice(port),
-                    rot_mode,
-                )
-            });
-            let peer_cloned = peer.clone();
-            let handle2 = tokio::task::spawn_blocking(move || {
-                let peer_addr = peer_cloned.ip_addr().to_string();
-                get_rot_emp_dummy(
-                    (num_ots_for_each_port / 2) as i64,
-                    &RotConfig::Bob(CString::new(peer_addr.as_str()).unwrap(), port),
-                    rot_mode,
-                )
-            });
-            (handle1, handle2)
-        })
-        .collect::<Vec<_>>();
-
-    let mut total_sent: u64 = 0;
-    for (handle1, handle2) in rot_handles {
-        let n1 = handle1.await.unwrap();
-        let n2 = handle2.await.unwrap();
-        total_sent += n1 + n2;
-    }
+    let peer_addr = peer.ip_addr().to_string();
+    let sessions = run_rot_sessions(&rot_ports, &peer_addr, rot_parallelism, move |config| {
+        get_rot_emp_dummy((num_ots_for_each_port / 2) as i64, &config, rot_mode)
+    })
+    .await;
+    let total_sent: u64 = sessions.iter().map(|(n1, n2)| n1 + n2).sum();
 
     end_timer!(timer);
 
@@ -90,3 +139,39 @@ pub async fn prio_ring_sim_server<I: UInt, A: UInt, R: Rng>(
 
     total_bytes
 }
+
+#[cfg(test)]
+mod tests {
+    use bindings::{get_rot_emp, ROTMode};
+
+    use super::run_rot_sessions;
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn test_concurrent_rot_sessions() {
+        let ports = [32010, 32012];
+        let count = 32 * 10000;
+
+        // both sides of each session run here, so this server is its own peer.
+        // IKNP, because both sides of a port would share `data/<port>`
+        let sessions = run_rot_sessions(&ports, "127.0.0.1", 2, move |config| {
+            get_rot_emp(count, &config, ROTMode::IKNP)
+        })
+        .await;
+
+        let mut num_ots = 0;
+        for ((m0, m1, _), (m, choice, _)) in sessions {
+            assert_eq!(m0.len(), count as usize);
+            assert_eq!(m.len(), count as usize);
+            (0..count as usize).for_each(|i| {
+                if choice[i] {
+                    assert_eq!(m[i], m1[i]);
+                } else {
+                    assert_eq!(m[i], m0[i]);
+                }
+            });
+            num_ots += m.len();
+        }
+        assert_eq!(num_ots, ports.len() * count as usize);
+    }
+}
@@ -1,22 +1,60 @@
 //! Client interaction
-use bridge::{client_server::ClientsPool, end_timer, id_tracker::RecvId, start_timer};
+use bridge::{
+    client_dump::ClientRecording,
+    client_server::{join_uids, with_uids, ClientsPool, PartitionPolicy},
+    end_timer,
+    id_tracker::RecvId,
+    start_timer,
+    tcp_bridge::{ClientID, ClientToken},
+};
 use crypto_primitives::{
+    a2s::{num_squared, WitnessShareSeedToAlice, WitnessSharesToBob},
+    bits::{Inputs1Hash, ShareCommitment},
     message::{
         l2::{ClientL2MsgToAlice, ClientL2MsgToBob},
         po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
     },
     square_corr::SquareCorrShare,
     uint::UInt,
+    utils::without_rejected,
 };
 use rayon::prelude::*;
-use std::sync::Arc;
+use server_protocol::{self as mpc, ClientFailure, HeldShare};
+use std::{net::SocketAddr, sync::Arc};
 use tokio::net::TcpListener;
+use tracing::warn;
 
 pub struct ClientData<I: UInt, C: UInt> {
+    /// Uids of all clients, in order.
+    pub uids: Vec<ClientID>,
+    /// Uids of the clients of `po2_msgs_alice`, in the same order.
+    pub uids_alice: Vec<ClientID>,
+    /// Uids of the clients of `po2_msgs_bob`, in the same order.
+    pub uids_bob: Vec<ClientID>,
+    /// Decides which server is OT sender for each client.
+    pub policy: PartitionPolicy,
+
     pub po2_msgs_alice: Arc<[ClientPo2MsgToAlice]>,
     pub po2_msgs_bob: Arc<[ClientPo2MsgToBob<I>]>,
+    /// Share commitments sent by the clients, in uid order. See
+    /// `server_protocol::exchange_commitments`.
+    pub commitments: Vec<ShareCommitment>,
+    /// Session the clients bind into their commitments, 0 unless set by
+    /// `with_session`.
+    pub session: u64,
 
     pub sqcorr: Arc<[Vec<SquareCorrShare<C>>]>,
+    /// Bound of every input, checked after A2S, or `None` to check nothing.
+    /// The messages of the clients are for it.
+    pub bound: Option<I>,
+    /// Shares of the witness bits of the bound check, in uid order, empty
+    /// without a bound. Their square correlations follow the ones of the
+    /// inputs in `sqcorr`, see `a2s::num_squared`.
+    pub witness: Arc<[Vec<C>]>,
+    /// Clients whose message cannot be deserialized or expanded, in uid
+    /// order. They are in none of the fields above, and are left out on both
+    /// servers by `pipeline::run`.
+    pub failed: Vec<ClientID>,
 
     pub comm_alice: usize,
     pub comm_bob: usize,
@@ -37,10 +75,50 @@ impl<I: UInt, C: UInt> ClientData<I, C> {
         self.num_clients_as_alice() + self.num_clients_as_bob()
     }
 
-    pub async fn fetch(is_alice: bool, port: u16, num_clients: usize, gsize: usize) -> Self {
-        let listener = TcpListener::bind(("0.0.0.0", port)).await.unwrap();
+    /// Accept `num_clients` clients that register with `token`, and receive
+    /// their messages, for `gsize` inputs at most `bound`. If `dump_path` is
+    /// given, the raw messages are also saved there for `replay`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn fetch(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        addr: SocketAddr,
+        num_clients: usize,
+        token: ClientToken,
+        gsize: usize,
+        bound: Option<I>,
+        dump_path: Option<String>,
+    ) -> Self {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        Self::fetch_with_listener(
+            is_alice,
+            policy,
+            listener,
+            num_clients,
+            token,
+            gsize,
+            bound,
+            dump_path,
+        )
+        .await
+    }
+
+    /// Same as `fetch`, accepting the clients from a bound listener.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn fetch_with_listener(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        listener: TcpListener,
+        num_clients: usize,
+        token: ClientToken,
+        gsize: usize,
+        bound: Option<I>,
+        dump_path: Option<String>,
+    ) -> Self {
         // accepts clients connection
-        let clients = ClientsPool::new(num_clients, listener).await;
+        let clients = ClientsPool::new(num_clients, listener, token)
+            .await
+            .with_policy(policy);
         // load balancing: split the clients pool and ALICE pool and BOB pool, notice
         // that this "Bob" is different from the "bob"
         // for global server role.  Alice is OT sender, Bob is OT receiver.
@@ -48,70 +126,207 @@ impl<I: UInt, C: UInt> ClientData<I, C> {
 
         let timer = start_timer!(|| "Client Phase 1");
 
-        let alice_msg = {
-            let clients_alice = clients_alice.clone();
-            tokio::spawn(async move {
-                clients_alice
-                    .subscribe_and_get::<ClientL2MsgToAlice>(RecvId::FIRST)
-                    .await
-                    .unwrap()
-            })
-        };
-        let bob_msg = {
-            let clients_bob = clients_bob.clone();
-            tokio::spawn(async move {
-                clients_bob
-                    .subscribe_and_get::<ClientL2MsgToBob<I, C>>(RecvId::FIRST)
-                    .await
-                    .unwrap()
-            })
-        };
-        let (alice_msg, bob_msg) = tokio::join!(alice_msg, bob_msg);
-        let (alice_msg, bob_msg) = (alice_msg.unwrap(), bob_msg.unwrap());
+        let recording = ClientRecording::fetch(&clients_alice, &clients_bob, RecvId::FIRST)
+            .await
+            .unwrap();
+        if let Some(path) = dump_path {
+            recording.dump(path).unwrap();
+        }
+        let mut data =
+            Self::from_recording(is_alice, clients.policy.clone(), recording, gsize, bound);
 
-        let mut po2_msgs_alice = Vec::with_capacity(alice_msg.len());
-        let mut sqcorr_alice = Vec::with_capacity(alice_msg.len());
+        data.time = end_timer!(timer).elapsed().as_secs_f64();
 
-        for m in alice_msg {
-            po2_msgs_alice.push(m.po2_msg);
-            sqcorr_alice.push(m.square_corr);
-        }
+        data.comm_alice = clients_alice.num_bytes_received_from_all();
+        data.comm_bob = clients_bob.num_bytes_received_from_all();
+        data
+    }
+
+    /// Load client messages saved by `fetch`, without accepting any client.
+    pub fn replay(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        path: &str,
+        gsize: usize,
+        bound: Option<I>,
+    ) -> Self {
+        Self::from_recording(
+            is_alice,
+            policy,
+            ClientRecording::load(path).unwrap(),
+            gsize,
+            bound,
+        )
+    }
+
+    /// Deserialize and expand the client messages, which carry the witness
+    /// bits of `bound` if any. A client whose message cannot be is logged and
+    /// added to `failed`. Communication is the size of the messages, and time
+    /// is zero. Panics if the recording is not split by `policy`.
+    pub fn from_recording(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        recording: ClientRecording,
+        gsize: usize,
+        bound: Option<I>,
+    ) -> Self {
+        assert!(
+            recording.matches_policy(is_alice, &policy),
+            "client messages are not split by partition policy {}",
+            policy
+        );
+        let comm_alice = recording.num_bytes_alice();
+        let comm_bob = recording.num_bytes_bob();
+        // the correlations of the witness bits follow the ones of the inputs
+        let num_squared = num_squared(gsize, bound);
 
-        let sqcorr_alice = sqcorr_alice
+        let parsed_alice = recording
+            .alice
             .into_par_iter()
-            .map(|v| v.expand(gsize * 2))
+            .map(|(uid, m)| {
+                let parsed = mpc::parse_upload::<ClientL2MsgToAlice, WitnessShareSeedToAlice>(
+                    &m,
+                    bound.is_some(),
+                )
+                .map_err(ClientFailure::from)
+                .and_then(|(mut m, witness)| {
+                    m.po2_msg.bind_to(uid.id);
+                    let sqcorr = m.square_corr.expand_checked(num_squared)?;
+                    let witness = match (witness, bound) {
+                        (Some(witness), Some(bound)) => witness.expand_checked(gsize, bound)?,
+                        _ => Vec::new(),
+                    };
+                    Ok((m.po2_msg, (sqcorr, witness)))
+                });
+                (uid, parsed)
+            })
             .collect::<Vec<_>>();
+        let (uids_alice, msgs_alice, failed_alice) = mpc::split_parsed(parsed_alice);
+        let (po2_msgs_alice, sqcorr_alice) = msgs_alice.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
 
-        let po2_msgs_alice = Arc::<[_]>::from(po2_msgs_alice.into_boxed_slice());
-
-        let mut po2_msgs_bob = Vec::with_capacity(bob_msg.len());
-        let mut sqcorr_bob = Vec::with_capacity(bob_msg.len());
-        for m in bob_msg {
-            po2_msgs_bob.push(m.po2_msg);
-            sqcorr_bob.push(m.square_corr);
-        }
-        let sqcorr_bob = sqcorr_bob
+        let parsed_bob = recording
+            .bob
             .into_par_iter()
-            .map(|v| v.expand())
+            .map(|(uid, m)| {
+                let parsed = mpc::parse_upload::<ClientL2MsgToBob<I, C>, WitnessSharesToBob<C>>(
+                    &m,
+                    bound.is_some(),
+                )
+                .map_err(ClientFailure::from)
+                .and_then(|(mut m, witness)| {
+                    m.po2_msg.bind_to(uid.id);
+                    let sqcorr = m.square_corr.expand_checked(num_squared)?;
+                    let witness = match (witness, bound) {
+                        (Some(witness), Some(bound)) => witness.expand_checked(gsize, bound)?,
+                        _ => Vec::new(),
+                    };
+                    Ok((m.po2_msg, (sqcorr, witness)))
+                });
+                (uid, parsed)
+            })
             .collect::<Vec<_>>();
+        let (uids_bob, msgs_bob, failed_bob) = mpc::split_parsed(parsed_bob);
+        let (po2_msgs_bob, sqcorr_bob) = msgs_bob.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
 
-        let po2_msgs_bob = Arc::<[_]>::from(po2_msgs_bob);
-        let sqcorr = Arc::<_>::from(ClientsPool::merge_msg(
-            is_alice,
-            sqcorr_alice.into_iter(),
-            sqcorr_bob.into_iter(),
-        ));
-        let time = end_timer!(timer).elapsed().as_secs_f64();
+        let mut failed = Vec::with_capacity(failed_alice.len() + failed_bob.len());
+        for (uid, failure) in failed_alice.into_iter().chain(failed_bob) {
+            warn!("client {} failed: {}", uid.id, failure);
+            failed.push(uid);
+        }
+        failed.sort_unstable();
+        let mut uids = [&uids_alice[..], &uids_bob[..]].concat();
+        uids.sort_unstable();
 
-        let comm_alice = clients_alice.num_bytes_received_from_all();
-        let comm_bob = clients_bob.num_bytes_received_from_all();
+        let commitments = policy
+            .merge_msg(
+                is_alice,
+                with_uids(&uids_alice, po2_msgs_alice.iter().map(|m| m.commitment)),
+                with_uids(&uids_bob, po2_msgs_bob.iter().map(|m| m.commitment)),
+            )
+            .into_values()
+            .collect();
+        let (sqcorr, witness) = policy
+            .merge_msg(
+                is_alice,
+                with_uids(&uids_alice, sqcorr_alice),
+                with_uids(&uids_bob, sqcorr_bob),
+            )
+            .into_values()
+            .unzip::<_, _, Vec<_>, Vec<_>>();
         Self {
-            po2_msgs_alice,
-            po2_msgs_bob,
-            sqcorr,
+            uids,
+            uids_alice,
+            uids_bob,
+            policy,
+            po2_msgs_alice: po2_msgs_alice.into(),
+            po2_msgs_bob: po2_msgs_bob.into(),
+            commitments,
+            session: 0,
+            sqcorr: sqcorr.into(),
+            bound,
+            witness: witness.into(),
+            failed,
             comm_alice,
             comm_bob,
-            time,
+            time: 0.,
+        }
+    }
+
+    /// Same clients, whose commitments are bound to `session`.
+    pub fn with_session(mut self, session: u64) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// The share of each client I hold, in uid order, to check its
+    /// commitment with `server_protocol::exchange_commitments`.
+    pub fn held_shares(&self, is_alice: bool) -> Vec<HeldShare> {
+        self.policy
+            .merge_msg(
+                is_alice,
+                with_uids(
+                    &self.uids_alice,
+                    self.po2_msgs_alice
+                        .iter()
+                        .map(|m| HeldShare::Seed(m.inputs_0)),
+                ),
+                with_uids(
+                    &self.uids_bob,
+                    self.po2_msgs_bob
+                        .iter()
+                        .map(|m| HeldShare::Inputs1(Inputs1Hash::new(m.salt, &m.inputs_1))),
+                ),
+            )
+            .into_values()
+            .collect()
+    }
+
+    /// Only the clients that are not `rejected`, which is in uid order.
+    /// Communication, time and the failed clients are kept.
+    pub fn without_clients(&self, is_alice: bool, rejected: &[bool]) -> Self {
+        let rejected = rejected.iter().copied();
+        let (rejected_a, rejected_b) = self
+            .policy
+            .split_iter(is_alice, with_uids(&self.uids, rejected.clone()));
+        let rejected_a = join_uids(&self.uids_alice, rejected_a);
+        let rejected_b = join_uids(&self.uids_bob, rejected_b);
+        Self {
+            uids: without_rejected(self.uids.iter().copied(), rejected.clone()),
+            uids_alice: without_rejected(self.uids_alice.iter().copied(), rejected_a.clone()),
+            uids_bob: without_rejected(self.uids_bob.iter().copied(), rejected_b.clone()),
+            policy: self.policy.clone(),
+            po2_msgs_alice: without_rejected(self.po2_msgs_alice.iter().cloned(), rejected_a)
+                .into(),
+            po2_msgs_bob: without_rejected(self.po2_msgs_bob.iter().cloned(), rejected_b).into(),
+            commitments: without_rejected(self.commitments.iter().copied(), rejected.clone()),
+            session: self.session,
+            sqcorr: without_rejected(self.sqcorr.iter().cloned(), rejected.clone()).into(),
+            bound: self.bound,
+            witness: without_rejected(self.witness.iter().cloned(), rejected).into(),
+            failed: self.failed.clone(),
+            comm_alice: self.comm_alice,
+            comm_bob: self.comm_bob,
+            time: self.time,
         }
     }
 }
@@ -1,2 +1,3 @@
 pub mod client_msg;
-pub mod mpc;
+pub mod pipeline;
+pub mod utils;
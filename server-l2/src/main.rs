@@ -1,286 +1,243 @@
-use crate::{client_msg::ClientData, utils::IdPool};
-use bin_utils::server::{InputSize, Options};
-use bridge::{
-    client_server::ClientsPool, end_timer, mpc_conn::MpcConnection, start_timer, BlackBox,
+use bin_utils::{
+    interrupt, panic_report,
+    runtime::run_on,
+    server::{
+        AggregationOptions, BoundOptions, ClientRecordOptions, InputSize, LegacyCsv, Options,
+    },
+    simulation,
 };
-use crypto_primitives::{
-    cot::{client::num_additional_ot_needed, server::sample_chi},
-    uint::UInt,
-    utils::{iter_arc, log_verify_status, Hook},
-    ALICE, BOB,
+use bridge::{
+    mpc_conn::{MpcConnection, Priority},
+    BlackBox,
 };
-use rand::{rngs::StdRng, SeedableRng};
-use rayon::prelude::*;
-use std::sync::Arc;
-use tokio::runtime::Runtime;
-use tracing::warn;
-
-mod client_msg;
-mod mpc;
-mod utils;
+use clap::{Arg, ArgMatches};
+use crypto_primitives::{aggregate::total_weight, const_assert, uint::UInt};
+use server_l2::{client_msg::ClientData, pipeline};
+use tokio::runtime::Handle;
+use tracing::info;
+use zeroize::Zeroize;
 
 type A = u64;
+// B2A shifts the highest bit of an input within `A`
+const_assert!(A::NUM_BITS >= InputSize::WIDEST.num_bits());
 type C = u128;
 
-const CHI_SEED: u64 = 123456;
+const LEGACY_CSV: LegacyCsv = LegacyCsv {
+    header: "client comm, MPC comm, client phase 1, client phase 2, OT + B2A, Correlation verify, A2S, Hash verify",
+    mpc_comm_sent: false,
+    phases: [
+        Some("client"),
+        None,
+        Some("b2a"),
+        Some("corr_verify"),
+        Some("a2s"),
+        None,
+    ],
+};
+
+/// Command line options of this server, on top of `Options`.
+struct L2Options {
+    record: ClientRecordOptions,
+    aggregation: AggregationOptions,
+    bound: BoundOptions,
+    simulate_server_corr: bool,
+}
 
-async fn main_with_option<I: UInt>(options: Options) {
+impl L2Options {
+    fn args<'a>() -> impl IntoIterator<Item = Arg<'a>> {
+        ClientRecordOptions::args()
+            .into_iter()
+            .chain(AggregationOptions::args())
+            .chain(BoundOptions::args())
+            .chain(cfg!(feature = "simulation-helpers").then(|| {
+                simulation::insecure_arg(
+                    "simulate_server_corr",
+                    "simulate-server-corr",
+                    "INSECURE, benchmarking only: simulate square correlations generated by the servers, with OTs from a seed both know, to model their cost, and ignore the ones sent by clients",
+                )
+            }))
+    }
+
+    fn parse(matches: &ArgMatches) -> Self {
+        Self {
+            record: ClientRecordOptions::parse(matches),
+            aggregation: AggregationOptions::parse(matches),
+            bound: BoundOptions::parse(matches),
+            simulate_server_corr: cfg!(feature = "simulation-helpers")
+                && matches.is_present("simulate_server_corr"),
+        }
+    }
+}
+
+async fn main_with_option<I: UInt>(options: Options<L2Options>, client_runtime: Handle) {
     tracing_subscriber::fmt()
         .pretty()
         .with_max_level(options.log_level)
         .init();
+    options.log_threads();
+    if options.custom_args.simulate_server_corr {
+        simulation::warn_insecure_flag("simulate-server-corr");
+    }
 
     // connect to peer
-    let peer = if !cfg!(feature = "no-comm") {
-        if options.is_bob {
-            // I'm Bob and need a complete address of alice.
-            MpcConnection::new_as_bob(&options.mpc_addr, options.num_mpc_sockets).await
-        } else {
-            // I'm Alice and I need a port number of alice.
-            let mpc_addr =
-                u16::from_str_radix(&options.mpc_addr, 10).expect("invalid mpc_addr as port");
-            MpcConnection::new_as_alice(mpc_addr, options.num_mpc_sockets).await
-        }
+    #[cfg(not(feature = "no-comm"))]
+    let peer = if options.is_bob {
+        // I'm Bob and need a complete address of alice.
+        MpcConnection::new_as_bob_from(
+            options.mpc_bind_addr,
+            &options.mpc_addr,
+            options.num_mpc_sockets,
+        )
+        .await
     } else {
-        warn!("no-comm feature is enabled, so no communication with peers");
+        // I'm Alice and I need a port number of alice.
+        let mpc_addr = options
+            .mpc_addr
+            .parse::<u16>()
+            .expect("invalid mpc_addr as port");
+        MpcConnection::new_as_alice(options.mpc_bind_addr, mpc_addr, options.num_mpc_sockets).await
+    };
+    #[cfg(feature = "no-comm")]
+    let peer = {
+        tracing::warn!("no-comm feature is enabled, so no communication with peers");
         MpcConnection::dummy()
     };
-
-    let client_data = ClientData::<I, C>::fetch(
-        options.is_alice(),
-        options.client_port,
-        options.num_clients,
-        options.gsize,
-    )
-    .await;
-
-    // manage message ids
-    // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
-    let ids = IdPool::build(
-        client_data.num_clients_as_alice(),
-        client_data.num_clients_as_bob(),
-    );
-
-    let timer = start_timer!(|| "OT Verify + B2A");
-
-    let (alice_arith_shares, bob_arith_shares) = if !cfg!(feature = "no-ot") {
-        // first, sample chi that is used to generate all OTs
-        let num_ot = options.gsize * I::NUM_BITS as usize;
-        let num_additional_ot = num_additional_ot_needed(num_ot);
-        let chi = Arc::new(sample_chi(num_ot + num_additional_ot, CHI_SEED));
-
-        // OT Verify Alice Receive (Start)
-        let ot_alice_hook = Hook::new();
-        let ot_ba_handles = iter_arc(&client_data.po2_msgs_alice)
-            .zip(ids.otverify_a)
-            .map(|(c_msg, id)| {
-                let peer = peer.clone();
-                let chi = chi.clone();
-                tokio::spawn(
-                    async move { mpc::ot_verify_alice::<I>(id, &c_msg.cot, chi, peer).await },
-                )
-            })
-            .collect::<Vec<_>>();
-
-        // OT verify Bob send (Start)
-
-        let ot_bob_hook = Hook::new();
-        let otverify_bob_handles = {
-            let peer = peer.clone();
-            let chi = chi.clone();
-            let c_msg = client_data.po2_msgs_bob.clone();
-            tokio::task::spawn_blocking(move || {
-                c_msg
-                    .par_iter()
-                    .zip(ids.otverify_b)
-                    .map(|(c_msg, id)| {
-                        mpc::ot_verify_bob(id, c_msg, &peer, chi.clone(), options.gsize)
-                    })
-                    .collect::<Vec<_>>()
-            })
-        };
-
-        // B2A Bob Receive (Start)
-        let b2a_bob_hook = Hook::new();
-        let b2a_bob_handles = iter_arc(&client_data.po2_msgs_bob)
-            .zip(ids.b2a_b)
-            .map(|(c_msg, id)| {
-                let peer = peer.clone();
-                tokio::spawn(async move { mpc::b2a_bob::<_, A>(id, &*c_msg, peer).await })
-            })
-            .collect::<Vec<_>>();
-
-        // OT Verify Alice Receive (Complete)
-        let mut qs_per_client = Vec::with_capacity(client_data.num_clients_as_alice());
-        let mut num_verified_success = 0;
-        for alice_handle in ot_ba_handles {
-            let (qs, v) = alice_handle.await.unwrap();
-            qs_per_client.push(qs);
-            num_verified_success += v as usize;
-        }
-        log_verify_status(
-            num_verified_success,
-            client_data.num_clients_as_alice(),
-            "OT Verify Alice",
-        );
-        ot_alice_hook.done();
-
-        // B2A Alice Send (Start)
-        let b2a_alice_hook = Hook::new();
-        let b2a_alice_handles = tokio::task::block_in_place(|| {
-            client_data
-                .po2_msgs_alice
-                .par_iter()
-                .zip(qs_per_client)
-                .zip(ids.b2a_a)
-                .map(|((c_msg, qs), id)| {
-                    mpc::b2a_alice::<I, A>(id, options.gsize, c_msg, &qs, &peer)
-                })
-                .collect::<Vec<_>>()
-        });
-
-        // B2A Bob Receive (Complete)
-        let mut bob_arith_shares = Vec::with_capacity(client_data.num_clients_as_bob());
-        for bob_handle in b2a_bob_handles {
-            let bob_arith_share = bob_handle.await.unwrap();
-            bob_arith_shares.push(bob_arith_share);
-        }
-        b2a_bob_hook.done();
-
-        // B2A Alice Send (Complete)
-        let mut alice_arith_shares = Vec::with_capacity(client_data.num_clients_as_alice());
-        for (s, handle) in b2a_alice_handles {
-            handle.await.unwrap();
-            alice_arith_shares.push(s);
-        }
-        b2a_alice_hook.done();
-
-        // OT Verify Bob Send (Complete)
-        for handle in otverify_bob_handles
-            .await
-            .expect("OT Verify on Bob part failed")
-        {
-            handle.await.unwrap();
+    let peer = peer.with_network_profile(options.mpc_network);
+    let _metrics_server = options.serve_metrics(&peer).await;
+
+    let bound = options.custom_args.bound.bound_in::<I, A>();
+    let round = async {
+        let client_data = match &options.custom_args.record.replay_clients {
+            Some(path) => ClientData::<I, C>::replay(
+                options.is_alice(),
+                options.partition.clone(),
+                path,
+                options.gsize,
+                bound,
+            ),
+            None => {
+                let fetch = ClientData::<I, C>::fetch(
+                    options.is_alice(),
+                    options.partition.clone(),
+                    options.client_listen_addr(),
+                    options.num_clients,
+                    options.client_token,
+                    options.gsize,
+                    bound,
+                    options.custom_args.record.dump_clients.clone(),
+                );
+                run_on(&client_runtime, fetch).await
+            },
         }
-        ot_bob_hook.done();
-
-        (alice_arith_shares, bob_arith_shares)
-    } else {
-        let alice_arith_shares = (0..client_data.num_clients_as_alice())
-            .into_par_iter()
-            .map(|_| {
-                let mut dummy_rng = StdRng::from_entropy();
-                (0..options.gsize)
-                    .map(|_| A::rand(&mut dummy_rng))
-                    .collect::<Vec<_>>()
-            })
-            .collect::<Vec<_>>();
-        let bob_arith_shares = (0..client_data.num_clients_as_bob())
-            .into_par_iter()
-            .map(|_| {
-                let mut dummy_rng = StdRng::from_entropy();
-                (0..options.gsize)
-                    .map(|_| A::rand(&mut dummy_rng))
-                    .collect::<Vec<_>>()
-            })
-            .collect::<Vec<_>>();
-        (alice_arith_shares, bob_arith_shares)
+        .with_session(options.session);
+        options
+            .metrics
+            .set_clients_connected(client_data.uids.len() + client_data.failed.len());
+
+        // refuse to run the round if a client has no weight
+        let weights = options
+            .custom_args
+            .aggregation
+            .weights_of(&client_data.uids);
+
+        let output = options
+            .client_gate()
+            .scope(pipeline::run::<I, A, C>(
+                options.gsize,
+                options.sec_param,
+                options.is_alice(),
+                &client_data,
+                &peer,
+                options.seed,
+                options.custom_args.simulate_server_corr,
+            ))
+            .await;
+        (client_data, weights, output)
+    };
+    let (client_data, weights, mut output) = match interrupt::run_interruptible(round, &peer).await
+    {
+        Ok(done) => done,
+        Err(interrupted) => {
+            let report = options.new_report("server-l2");
+            options.exit_interrupted(report, interrupted, &peer, &LEGACY_CSV)
+        },
     };
 
-    let b2a_time = end_timer!(timer).elapsed().as_secs_f64();
-
-    let timer = start_timer!(|| "SqCorr Verify");
-    // sanity checks: length check
-    assert_eq!(client_data.sqcorr.len(), options.num_clients);
-    assert!(client_data
-        .sqcorr
-        .iter()
-        .all(|corrs| corrs.len() == options.gsize * 2));
-    // SqCorr Verify
-    let sqcorr_handles = iter_arc(&client_data.sqcorr)
-        .zip(ids.sqcorr)
-        .map(|(corr, id)| {
-            let peer = peer.clone();
-            tokio::spawn(async move {
-                if !options.is_bob {
-                    mpc::corr_verify::<_, ALICE>(id.0, id.1, options.gsize, &*corr, peer).await
-                } else {
-                    mpc::corr_verify::<_, BOB>(id.0, id.1, options.gsize, &*corr, peer).await
-                }
-            })
-        })
-        .collect::<Vec<_>>();
-
-    let mut num_verified_success = 0;
-    for sqcorr_handle in sqcorr_handles {
-        let result = sqcorr_handle.await.unwrap();
-        num_verified_success += if result == options.gsize { 1 } else { 0 };
-    }
-
-    log_verify_status(
-        num_verified_success,
-        client_data.num_clients(),
-        "SqCorr Verify",
-    );
-
-    let corr_verify_time = end_timer!(timer).elapsed().as_secs_f64();
-
-    let timer = start_timer!(|| "A2S");
-    // A2S
-    let arith_shares = ClientsPool::merge_msg(
-        options.is_alice(),
-        alice_arith_shares.into_iter(),
-        bob_arith_shares.into_iter(),
+    let aggregate = match &weights {
+        Some(weights) => {
+            let accepted = output.accepted_weights(&client_data.uids, weights);
+            info!(
+                "my share of the weighted sum of {} clients, of total weight {} (the mean is the aggregate divided by it)",
+                accepted.iter().filter(|w| **w != 0).count(),
+                total_weight(&accepted)
+            );
+            output.weighted_aggregate(&client_data.uids, weights)
+        },
+        None => output.aggregate(),
+    };
+    aggregate.drop_into_black_box();
+    // the shares of the inputs and their squares are not needed any more
+    output.arith_shares.zeroize();
+    output.square_shares.zeroize();
+
+    info!(
+        "MPC send queue wait: control: {}; bulk: {}",
+        peer.queue_wait_stats(Priority::Control),
+        peer.queue_wait_stats(Priority::Bulk)
     );
-    let a2s_handles = iter_arc(&client_data.sqcorr)
-        .zip(arith_shares)
-        .zip(ids.a2s)
-        .map(|((corr, xs), id)| {
-            let peer = peer.clone();
-            tokio::spawn(async move {
-                if !options.is_bob {
-                    mpc::a2s::<A, C, { ALICE }>(id, &xs, &*corr, peer).await
-                } else {
-                    mpc::a2s::<_, _, { BOB }>(id, &xs, &*corr, peer).await
-                }
-            })
-        })
-        .collect::<Vec<_>>();
 
-    for handle in a2s_handles {
-        handle.await.unwrap().drop_into_black_box()
+    simulation::warn_if_constructed();
+    let mut report = options.new_report("server-l2");
+    report.client_comm = client_data.comm_alice + client_data.comm_bob;
+    report.mpc_comm_sent = peer.num_bytes_sent();
+    report.mpc_comm_recv = peer.num_bytes_received();
+    report
+        .phase("client", client_data.time)
+        .phase(
+            "b2a",
+            if cfg!(feature = "no-ot") {
+                0f64
+            } else {
+                output.b2a_time
+            },
+        )
+        .phase("corr_verify", output.corr_verify_time)
+        .phase("a2s", output.a2s_time)
+        .verified("ot", output.num_ot_verified)
+        .verified("sqcorr", output.num_sqcorr_verified);
+    if let Some(num_verified) = output.num_bound_verified {
+        report.verified("bound", num_verified);
     }
-
-    let a2s_time = end_timer!(timer).elapsed().as_secs_f64();
-
-    println!("client comm, MPC comm, client phase 1, client phase 2, OT + B2A, Correlation verify, A2S, Hash verify");
-    println!(
-        "{}, {}, {}, {}, {}, {}, {}, {}",
-        client_data.comm_alice + client_data.comm_bob,
-        peer.num_bytes_received(),
-        client_data.time,
-        0f64,
-        if cfg!(feature = "no-ot") {
-            0f64
-        } else {
-            b2a_time
-        },
-        corr_verify_time,
-        a2s_time,
-        0f64
-    );
+    if options.custom_args.simulate_server_corr {
+        report.phase("sqcorr_gen", output.sqcorr_gen_time);
+    }
+    options.emit_report(report, &LEGACY_CSV);
 }
 
 pub fn main() {
-    let runtime = Runtime::new().unwrap();
-    runtime.block_on(async {
-        let options = Options::load_from_args("ELSA Server L2");
+    panic_report::run_main(|| {
+        let options =
+            Options::load_from_args_custom("ELSA Server L2", L2Options::args(), L2Options::parse);
+        options.install_compute_pool().unwrap();
+        if options.run_self_test() {
+            return;
+        }
+        options.check_ring_budget::<A>(
+            options
+                .custom_args
+                .aggregation
+                .max_total_weight(options.num_clients),
+        );
+        let runtimes = options.runtimes().unwrap();
         match options.input_size {
             InputSize::U8 => {
-                main_with_option::<u8>(options).await;
+                runtimes.block_on(main_with_option::<u8>(options, runtimes.client().clone()))
             },
             InputSize::U32 => {
-                main_with_option::<u32>(options).await;
+                runtimes.block_on(main_with_option::<u32>(options, runtimes.client().clone()))
+            },
+            InputSize::U64 => {
+                runtimes.block_on(main_with_option::<u64>(options, runtimes.client().clone()))
             },
         }
     })
@@ -0,0 +1,593 @@
+//! Server side of the L2 protocol after the client messages are received,
+//! from OT verification to A2S, and the bound check of the inputs if the
+//! clients are fetched with a bound. The square correlations come from the
+//! clients, or with `simulate_server_corr`, from an insecure simulation of
+//! correlations generated by the servers, which only models their cost.
+
+use crate::{client_msg::ClientData, utils::IdPool};
+use bin_utils::{
+    panic_report::expect_joined,
+    runtime::{block_on_compute, spawn_gated},
+};
+use bridge::{
+    client_server::with_uids,
+    end_timer,
+    id_tracker::{RecvId, SendId},
+    mpc_conn::MpcConnection,
+    start_timer,
+    tcp_bridge::ClientID,
+};
+use crypto_primitives::{
+    a2s::num_squared,
+    aggregate::weighted_sum_shares,
+    cot::{
+        dims::ProtocolDims,
+        server::{sample_chi, Chi},
+    },
+    square_corr::SquareCorrShare,
+    uint::UInt,
+    utils::{batch_sum, iter_arc, log_verify_status, without_rejected, Hook},
+    ALICE, BOB,
+};
+use rayon::prelude::*;
+use server_protocol::{self as mpc, ClientFailure};
+use std::sync::Arc;
+use tracing::warn;
+use zeroize::Zeroizing;
+
+/// Seed of the random linear combination in SqCorr Verify.
+const T_SEED: u64 = 0x12345678; // TODO: make this a parameter
+
+/// Seed of the ROTs of the square correlations of client `uid` is
+/// `ROT_SEED + uid`. See `ROTsForSender::from_shared_seed`.
+#[cfg(feature = "simulation-helpers")]
+const ROT_SEED: u64 = 765432;
+
+/// Shares held by one server, and verification results. Per-client vectors
+/// are in uid order, without the rejected clients.
+pub struct PipelineOutput<A: UInt> {
+    /// B2A output: arithmetic shares of the inputs.
+    pub arith_shares: Vec<Vec<A>>,
+    /// A2S output: arithmetic shares of the squared inputs.
+    pub square_shares: Vec<Vec<A>>,
+    /// Clients that failed on either server before the protocol, e.g. whose
+    /// message cannot be deserialized, or that sent different share
+    /// commitments to the two servers, in uid order. They are rejected before
+    /// B2A. With a bound, the clients with an input out of the bound are
+    /// rejected after A2S, on both servers as the checks are opened.
+    pub rejected_clients: Vec<ClientID>,
+    /// Number of clients where I'm OT sender whose OTs are verified.
+    pub num_ot_verified: usize,
+    /// Number of clients whose square correlations are verified.
+    pub num_sqcorr_verified: usize,
+    /// Number of clients whose inputs are all within the bound, if there is
+    /// one.
+    pub num_bound_verified: Option<usize>,
+
+    /// Time to generate the square correlations, zero unless they are
+    /// generated by the servers.
+    pub sqcorr_gen_time: f64,
+    pub b2a_time: f64,
+    pub corr_verify_time: f64,
+    pub a2s_time: f64,
+}
+
+impl<A: UInt> PipelineOutput<A> {
+    /// My share of the sum of the inputs of all clients that are not
+    /// rejected.
+    pub fn aggregate(&self) -> Vec<A> {
+        batch_sum(&self.arith_shares)
+    }
+
+    /// Weights of the clients that are not rejected, from the weights of all
+    /// clients `uids` in uid order.
+    pub fn accepted_weights(&self, uids: &[ClientID], weights: &[u64]) -> Vec<u64> {
+        assert_eq!(uids.len(), weights.len());
+        uids.iter()
+            .zip(weights)
+            .filter(|(uid, _)| !self.rejected_clients.contains(uid))
+            .map(|(_, weight)| *weight)
+            .collect()
+    }
+
+    /// My share of the weighted sum of the inputs of all clients that are not
+    /// rejected, from the weights of all clients `uids` in uid order. Clients
+    /// of weight 0 are left out.
+    pub fn weighted_aggregate(&self, uids: &[ClientID], weights: &[u64]) -> Vec<A> {
+        weighted_sum_shares(&self.arith_shares, &self.accepted_weights(uids, weights))
+    }
+}
+
+/// Shares of inputs that are all ones, in place of the B2A outputs with
+/// `no-ot`, split into clients where I'm OT sender and OT receiver. OT
+/// verification is skipped.
+#[cfg(feature = "no-ot")]
+fn dummy_b2a_outputs<I: UInt, A: UInt, C: UInt>(
+    client_data: &ClientData<I, C>,
+    gsize: usize,
+    is_alice: bool,
+    seed: Option<u64>,
+) -> (Vec<Vec<A>>, Vec<Vec<A>>, usize) {
+    let shares = crypto_primitives::simulation::make_consistent_dummy_shares(
+        seed.unwrap_or_default(),
+        client_data.num_clients(),
+        gsize,
+        is_alice,
+    );
+    let (alice, bob) = client_data
+        .policy
+        .split_iter(is_alice, with_uids(&client_data.uids, shares));
+    (
+        bridge::client_server::join_uids(&client_data.uids_alice, alice),
+        bridge::client_server::join_uids(&client_data.uids_bob, bob),
+        0,
+    )
+}
+
+#[cfg(not(feature = "no-ot"))]
+fn dummy_b2a_outputs<I: UInt, A: UInt, C: UInt>(
+    _client_data: &ClientData<I, C>,
+    _gsize: usize,
+    _is_alice: bool,
+    _seed: Option<u64>,
+) -> (Vec<Vec<A>>, Vec<Vec<A>>, usize) {
+    unreachable!("dummy shares are only used with no-ot")
+}
+
+/// Square correlations of each client, in uid order, generated with `peer`
+/// instead of taken from the client: two per value to square, like the ones
+/// of the clients. I'm OT sender for the clients where I'm OT sender in B2A.
+/// Not secure, see `square_corr::server_gen`.
+#[cfg(feature = "simulation-helpers")]
+async fn server_sqcorr<I: UInt, C: UInt>(
+    num_squared: usize,
+    is_alice: bool,
+    client_data: &ClientData<I, C>,
+    peer: &MpcConnection,
+    ids_a: Vec<(SendId, RecvId, SendId)>,
+    ids_b: Vec<(RecvId, SendId, RecvId)>,
+) -> Arc<[Vec<SquareCorrShare<C>>]> {
+    use crypto_primitives::cot::rot::server_generated::{ROTsForReceiver, ROTsForSender};
+
+    let count = 2 * num_squared;
+    let num_rots = count * C::NUM_BITS;
+    let rot_seed = |uid: &ClientID| ROT_SEED + uid.id;
+
+    // both directions exchange messages, so the tasks are not gated, see
+    // `spawn_gated`
+    let alice_handles = client_data
+        .uids_alice
+        .iter()
+        .zip(ids_a)
+        .map(|(uid, ids)| {
+            let (peer, rot_seed) = (peer.clone(), rot_seed(uid));
+            tokio::spawn(async move {
+                let rots = ROTsForSender::from_shared_seed(rot_seed, num_rots);
+                mpc::server_sqcorr_alice::<C>(ids, count, rots, peer).await
+            })
+        })
+        .collect::<Vec<_>>();
+    let bob_handles = client_data
+        .uids_bob
+        .iter()
+        .zip(ids_b)
+        .map(|(uid, ids)| {
+            let (peer, rot_seed) = (peer.clone(), rot_seed(uid));
+            tokio::spawn(async move {
+                let rots = ROTsForReceiver::from_shared_seed(rot_seed, num_rots);
+                mpc::server_sqcorr_bob::<C>(ids, count, rots, peer)
+                    .await
+                    .unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut sqcorr_alice = Vec::with_capacity(client_data.num_clients_as_alice());
+    for handle in alice_handles {
+        sqcorr_alice.push(expect_joined(handle.await));
+    }
+    let mut sqcorr_bob = Vec::with_capacity(client_data.num_clients_as_bob());
+    for handle in bob_handles {
+        sqcorr_bob.push(expect_joined(handle.await));
+    }
+    client_data
+        .policy
+        .merge_msg(
+            is_alice,
+            with_uids(&client_data.uids_alice, sqcorr_alice),
+            with_uids(&client_data.uids_bob, sqcorr_bob),
+        )
+        .into_values()
+        .collect()
+}
+
+#[cfg(not(feature = "simulation-helpers"))]
+async fn server_sqcorr<I: UInt, C: UInt>(
+    _num_squared: usize,
+    _is_alice: bool,
+    _client_data: &ClientData<I, C>,
+    _peer: &MpcConnection,
+    _ids_a: Vec<(SendId, RecvId, SendId)>,
+    _ids_b: Vec<(RecvId, SendId, RecvId)>,
+) -> Arc<[Vec<SquareCorrShare<C>>]> {
+    unreachable!("the servers only generate square correlations with simulation-helpers")
+}
+
+/// Run the protocol with `peer` on received client messages. `is_alice` is
+/// the global role of this server. `seed` seeds the dummy shares of
+/// `no-ot`, which must be the same on both servers, 0 by default. If
+/// `simulate_server_corr` is set, the square correlations of the clients are
+/// ignored, and the servers simulate generating them from a seed both know,
+/// which is not secure, see `square_corr::server_gen`.
+pub async fn run<I: UInt, A: UInt, C: UInt>(
+    gsize: usize,
+    sec_param: usize,
+    is_alice: bool,
+    client_data: &ClientData<I, C>,
+    peer: &MpcConnection,
+    seed: Option<u64>,
+    simulate_server_corr: bool,
+) -> PipelineOutput<A> {
+    // both servers leave out the clients that failed on either, before the
+    // message ids of the others are allocated
+    let (failed_on_peer, failed_clients) = mpc::exchange_failed_uids(
+        IdPool::exchange_failed(),
+        &client_data.uids,
+        &client_data.failed,
+        peer,
+    )
+    .await;
+    let parsed_data;
+    let client_data = if failed_on_peer.contains(&true) {
+        for (uid, _) in client_data
+            .uids
+            .iter()
+            .zip(&failed_on_peer)
+            .filter(|(_, f)| **f)
+        {
+            warn!("client {} failed: {}", uid.id, ClientFailure::FailedOnPeer);
+        }
+        parsed_data = client_data.without_clients(is_alice, &failed_on_peer);
+        &parsed_data
+    } else {
+        client_data
+    };
+
+    // manage message ids
+    // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
+    let ids = IdPool::build(
+        client_data.num_clients_as_alice(),
+        client_data.num_clients_as_bob(),
+    );
+
+    // both servers reject the same clients, so the message ids of the others
+    // still match
+    let rejected = mpc::exchange_commitments(
+        ids.exchange_commitments,
+        client_data.session,
+        &client_data.uids,
+        &client_data.commitments,
+        &client_data.held_shares(is_alice),
+        peer,
+    )
+    .await;
+    let rejected_clients = client_data
+        .uids
+        .iter()
+        .zip(&rejected)
+        .filter(|(_, r)| **r)
+        .map(|(uid, _)| *uid)
+        .collect::<Vec<_>>();
+    let accepted_data;
+    let client_data = if rejected_clients.is_empty() {
+        client_data
+    } else {
+        for uid in &rejected_clients {
+            warn!("client {} rejected: share commitments do not match", uid.id);
+        }
+        accepted_data = client_data.without_clients(is_alice, &rejected);
+        &accepted_data
+    };
+
+    // the inputs, and the witness bits of the bound check if any
+    let num_squared = num_squared(gsize, client_data.bound);
+
+    let timer = start_timer!(|| "SqCorr Gen");
+    let generated_sqcorr = if simulate_server_corr {
+        let (ids_a, ids_b) = (ids.sqcorr_gen_a, ids.sqcorr_gen_b);
+        Some(server_sqcorr(num_squared, is_alice, client_data, peer, ids_a, ids_b).await)
+    } else {
+        None
+    };
+    let sqcorr = generated_sqcorr.as_ref().unwrap_or(&client_data.sqcorr);
+    let sqcorr_gen_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let timer = start_timer!(|| "OT Verify + B2A");
+
+    let (alice_arith_shares, bob_arith_shares, num_ot_verified) = if !cfg!(feature = "no-ot") {
+        // first, sample chi that is used to generate all OTs
+        let dims = ProtocolDims::new::<I>(gsize, sec_param).expect("invalid protocol dimensions");
+        let chi_seed = mpc::sample_chi_seed(ids.exchange_chi_seed, peer).await;
+        let chi = Chi::Shared(Arc::new(sample_chi(dims.num_total(), chi_seed)));
+
+        // OT Verify Alice Receive (Start)
+        let ot_alice_hook = Hook::new_named("OT Verify Alice");
+        let ot_ba_handles = iter_arc(&client_data.po2_msgs_alice)
+            .zip(ids.otverify_a)
+            .map(|(c_msg, id)| {
+                let peer = peer.clone();
+                let chi = chi.clone();
+                spawn_gated(async move {
+                    mpc::ot_verify_alice(id, &c_msg.cot, chi, sec_param, peer, &mut ())
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // OT verify Bob send (Start)
+
+        let ot_bob_hook = Hook::new_named("OT Verify Bob");
+        let otverify_bob_handles = {
+            let peer = peer.clone();
+            let chi = chi.clone();
+            let c_msg = client_data.po2_msgs_bob.clone();
+            tokio::task::spawn_blocking(move || {
+                c_msg
+                    .par_iter()
+                    .zip(ids.otverify_b)
+                    .map(|(c_msg, id)| {
+                        mpc::ot_verify_bob(id, c_msg, &peer, chi.clone(), dims).unwrap()
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        // B2A Bob Receive (Start)
+        let b2a_bob_hook = Hook::new_named("B2A Bob");
+        let b2a_bob_handles = iter_arc(&client_data.po2_msgs_bob)
+            .zip(ids.b2a_b)
+            .map(|(c_msg, id)| {
+                let peer = peer.clone();
+                spawn_gated(async move {
+                    let (inputs_1, ts) = (&c_msg.inputs_1, c_msg.cot.ts());
+                    mpc::b2a_bob::<_, A, _>(&id, dims, inputs_1, ts, peer, &mut ())
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // OT Verify Alice Receive (Complete)
+        let mut qs_per_client = Vec::with_capacity(client_data.num_clients_as_alice());
+        let mut num_verified_success = 0;
+        for alice_handle in ot_ba_handles {
+            let (qs, v) = expect_joined(alice_handle.await);
+            // wiped once B2A is done
+            qs_per_client.push(Zeroizing::new(qs));
+            num_verified_success += v as usize;
+        }
+        log_verify_status(
+            num_verified_success,
+            client_data.num_clients_as_alice(),
+            "OT Verify Alice",
+        );
+        ot_alice_hook.done();
+
+        // B2A Alice Send (Start)
+        let b2a_alice_hook = Hook::new_named("B2A Alice");
+        let b2a_alice_handles = block_on_compute(|| {
+            client_data
+                .po2_msgs_alice
+                .par_iter()
+                .zip(qs_per_client)
+                .zip(ids.b2a_a)
+                .map(|((c_msg, qs), id)| {
+                    let (inputs_0, delta) = (c_msg.inputs_0, c_msg.cot.delta);
+                    mpc::b2a_alice::<I, A>(&id, dims, inputs_0, delta, &qs, peer).unwrap()
+                })
+                .collect::<Vec<_>>()
+        });
+
+        // B2A Bob Receive (Complete)
+        let mut bob_arith_shares = Vec::with_capacity(client_data.num_clients_as_bob());
+        for bob_handle in b2a_bob_handles {
+            let bob_arith_share = expect_joined(bob_handle.await);
+            bob_arith_shares.push(bob_arith_share);
+        }
+        b2a_bob_hook.done();
+
+        // B2A Alice Send (Complete)
+        let mut alice_arith_shares = Vec::with_capacity(client_data.num_clients_as_alice());
+        for (s, handle) in b2a_alice_handles {
+            handle
+                .await
+                .expect("MPC connection closed before the message was sent");
+            alice_arith_shares.push(s);
+        }
+        b2a_alice_hook.done();
+
+        // OT Verify Bob Send (Complete)
+        for handle in expect_joined(otverify_bob_handles.await) {
+            handle
+                .await
+                .expect("MPC connection closed before the message was sent");
+        }
+        ot_bob_hook.done();
+
+        (alice_arith_shares, bob_arith_shares, num_verified_success)
+    } else {
+        dummy_b2a_outputs(client_data, gsize, is_alice, seed)
+    };
+
+    let b2a_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let timer = start_timer!(|| "SqCorr Verify");
+    // sanity checks: length check
+    assert_eq!(sqcorr.len(), client_data.num_clients());
+    assert!(sqcorr.iter().all(|corrs| corrs.len() == num_squared * 2));
+    // SqCorr Verify
+    let sqcorr_handles = iter_arc(sqcorr)
+        .zip(ids.sqcorr)
+        .map(|(corr, id)| {
+            let peer = peer.clone();
+            spawn_gated(async move {
+                let (id1, id2) = id;
+                if is_alice {
+                    mpc::corr_verify::<_, ALICE, _>(
+                        id1,
+                        id2,
+                        num_squared,
+                        &corr,
+                        T_SEED,
+                        peer,
+                        &mut (),
+                    )
+                    .await
+                } else {
+                    mpc::corr_verify::<_, BOB, _>(
+                        id1,
+                        id2,
+                        num_squared,
+                        &corr,
+                        T_SEED,
+                        peer,
+                        &mut (),
+                    )
+                    .await
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut num_sqcorr_verified = 0;
+    for sqcorr_handle in sqcorr_handles {
+        let result = expect_joined(sqcorr_handle.await);
+        num_sqcorr_verified += if result == mpc::num_corr_checks(num_squared) {
+            1
+        } else {
+            0
+        };
+    }
+
+    log_verify_status(
+        num_sqcorr_verified,
+        client_data.num_clients(),
+        "SqCorr Verify",
+    );
+
+    let corr_verify_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let timer = start_timer!(|| "A2S");
+    // A2S
+    let mut arith_shares = client_data
+        .policy
+        .merge_msg(
+            is_alice,
+            with_uids(&client_data.uids_alice, alice_arith_shares),
+            with_uids(&client_data.uids_bob, bob_arith_shares),
+        )
+        .into_values()
+        .collect::<Vec<_>>();
+    let a2s_handles = iter_arc(sqcorr)
+        .zip(arith_shares.iter().cloned())
+        .zip(ids.a2s)
+        .map(|((corr, xs), id)| {
+            let peer = peer.clone();
+            spawn_gated(async move {
+                if is_alice {
+                    mpc::a2s::<A, C, _, { ALICE }>(id, &xs, &corr, peer, &mut ()).await
+                } else {
+                    mpc::a2s::<_, _, _, { BOB }>(id, &xs, &corr, peer, &mut ()).await
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut square_shares = Vec::with_capacity(client_data.num_clients());
+    for handle in a2s_handles {
+        square_shares.push(expect_joined(handle.await));
+    }
+
+    // Bound check, with the correlations of the witness bits, which follow
+    // the ones of A2S
+    let mut out_of_bound_clients = Vec::new();
+    let num_bound_verified = if let Some(bound) = client_data.bound {
+        let bound = bound.as_uint::<A>();
+        let bound_handles = iter_arc(sqcorr)
+            .zip(iter_arc(&client_data.witness))
+            .zip(arith_shares.iter().cloned())
+            .zip(ids.bound_check)
+            .map(|(((corr, witness), xs), (id1, id2))| {
+                let peer = peer.clone();
+                spawn_gated(async move {
+                    if is_alice {
+                        mpc::bound_check::<A, C, _, { ALICE }>(
+                            id1,
+                            id2,
+                            &xs,
+                            &witness,
+                            &corr,
+                            bound,
+                            peer,
+                            &mut (),
+                        )
+                        .await
+                    } else {
+                        mpc::bound_check::<A, C, _, { BOB }>(
+                            id1,
+                            id2,
+                            &xs,
+                            &witness,
+                            &corr,
+                            bound,
+                            peer,
+                            &mut (),
+                        )
+                        .await
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut out_of_bound = Vec::with_capacity(client_data.num_clients());
+        for handle in bound_handles {
+            out_of_bound.push(expect_joined(handle.await) != gsize);
+        }
+        for (uid, _) in client_data
+            .uids
+            .iter()
+            .zip(&out_of_bound)
+            .filter(|(_, o)| **o)
+        {
+            warn!("client {} rejected: {}", uid.id, ClientFailure::OutOfBound);
+            out_of_bound_clients.push(*uid);
+        }
+        let num_verified = client_data.num_clients() - out_of_bound_clients.len();
+        log_verify_status(num_verified, client_data.num_clients(), "Bound Check");
+
+        // both servers open the checks, so they leave out the same clients
+        arith_shares = without_rejected(arith_shares, out_of_bound.iter().copied());
+        square_shares = without_rejected(square_shares, out_of_bound);
+        Some(num_verified)
+    } else {
+        None
+    };
+
+    let a2s_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let mut rejected_clients = [failed_clients, rejected_clients, out_of_bound_clients].concat();
+    rejected_clients.sort_unstable();
+    PipelineOutput {
+        arith_shares,
+        square_shares,
+        rejected_clients,
+        num_ot_verified,
+        num_sqcorr_verified,
+        num_bound_verified,
+        sqcorr_gen_time,
+        b2a_time,
+        corr_verify_time,
+        a2s_time,
+    }
+}
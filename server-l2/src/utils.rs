@@ -1,24 +1,53 @@
 use bridge::id_tracker::{ExchangeId, IdGen, RecvId, SendId};
+use server_protocol::B2A_CHUNKS;
 
 /// Message IDs for various clients
 pub struct IdPool {
+    /// checks of the share commitments of all clients, before B2A: their
+    /// digests, then the clients to reject
+    pub exchange_commitments: (ExchangeId, ExchangeId),
+
     pub otverify_a: Vec<RecvId>,
     pub otverify_b: Vec<SendId>,
 
-    pub b2a_a: Vec<SendId>,
-    pub b2a_b: Vec<RecvId>,
+    /// `B2A_CHUNKS` consecutive ids for the `us` of each client
+    pub b2a_a: Vec<Vec<SendId>>,
+    pub b2a_b: Vec<Vec<RecvId>>,
 
     pub sqcorr: Vec<(ExchangeId, ExchangeId)>,
 
     pub a2s: Vec<ExchangeId>,
+
+    /// square correlations simulated by the servers with
+    /// `--simulate-server-corr`, a cost model only: the ROT correction from
+    /// Alice, the choice flips from Bob, and the `us` from Alice
+    pub sqcorr_gen_a: Vec<(SendId, RecvId, SendId)>,
+    pub sqcorr_gen_b: Vec<(RecvId, SendId, RecvId)>,
+
+    /// shares of the seed of chi, exchanged before OT verification
+    pub exchange_chi_seed: ExchangeId,
+
+    /// bound check after A2S, with a bound: the shares of `e`, then the
+    /// shares of the checks
+    pub bound_check: Vec<(ExchangeId, ExchangeId)>,
 }
 
 impl IdPool {
+    /// Clients that failed on either server before the protocol, exchanged
+    /// before the others are counted, so it does not depend on the counts.
+    pub fn exchange_failed() -> ExchangeId {
+        IdGen::new().next_exchange_id()
+    }
+
     pub fn build(alice_pool_size: usize, bob_pool_size: usize) -> Self {
         // manage message ids
         // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
 
         let mut id = IdGen::new();
+        // skip `exchange_failed`
+        id.next_exchange_id();
+
+        let exchange_commitments = (id.next_exchange_id(), id.next_exchange_id());
 
         let otverify_a = (0..alice_pool_size)
             .map(|_| id.next_recv_id())
@@ -28,10 +57,10 @@ impl IdPool {
             .collect::<Vec<_>>();
 
         let b2a_a = (0..alice_pool_size)
-            .map(|_| id.next_send_id())
+            .map(|_| (0..B2A_CHUNKS).map(|_| id.next_send_id()).collect())
             .collect::<Vec<_>>();
         let b2a_b = (0..bob_pool_size)
-            .map(|_| id.next_recv_id())
+            .map(|_| (0..B2A_CHUNKS).map(|_| id.next_recv_id()).collect())
             .collect::<Vec<_>>();
 
         let sqcorr = (0..alice_pool_size + bob_pool_size)
@@ -42,13 +71,57 @@ impl IdPool {
             .map(|_| id.next_exchange_id())
             .collect::<Vec<_>>();
 
+        let exchange_chi_seed = id.next_exchange_id();
+
+        // each message in its own pass, so that the ids I send under are the
+        // ones the peer receives under, as OT receiver of the same clients
+        let corrections_a = (0..alice_pool_size)
+            .map(|_| id.next_send_id())
+            .collect::<Vec<_>>();
+        let corrections_b = (0..bob_pool_size)
+            .map(|_| id.next_recv_id())
+            .collect::<Vec<_>>();
+        let flips_a = (0..alice_pool_size)
+            .map(|_| id.next_recv_id())
+            .collect::<Vec<_>>();
+        let flips_b = (0..bob_pool_size)
+            .map(|_| id.next_send_id())
+            .collect::<Vec<_>>();
+        let us_a = (0..alice_pool_size)
+            .map(|_| id.next_send_id())
+            .collect::<Vec<_>>();
+        let us_b = (0..bob_pool_size)
+            .map(|_| id.next_recv_id())
+            .collect::<Vec<_>>();
+        let sqcorr_gen_a = corrections_a
+            .into_iter()
+            .zip(flips_a)
+            .zip(us_a)
+            .map(|((correction, flips), us)| (correction, flips, us))
+            .collect::<Vec<_>>();
+        let sqcorr_gen_b = corrections_b
+            .into_iter()
+            .zip(flips_b)
+            .zip(us_b)
+            .map(|((correction, flips), us)| (correction, flips, us))
+            .collect::<Vec<_>>();
+
+        let bound_check = (0..alice_pool_size + bob_pool_size)
+            .map(|_| (id.next_exchange_id(), id.next_exchange_id()))
+            .collect::<Vec<_>>();
+
         IdPool {
+            exchange_commitments,
             otverify_a,
             otverify_b,
             b2a_a,
             b2a_b,
             sqcorr,
             a2s,
+            sqcorr_gen_a,
+            sqcorr_gen_b,
+            exchange_chi_seed,
+            bound_check,
         }
     }
 }
@@ -1,22 +1,42 @@
 //! Client interaction
 use bridge::{
-    client_server::ClientsPool,
+    client_server::{join_uids, with_uids, ClientsPool, PartitionPolicy},
     end_timer,
     id_tracker::{RecvId, SendId},
     start_timer,
+    tcp_bridge::{ClientID, ClientToken},
 };
 use crypto_primitives::{
+    bits::{Inputs1Hash, ShareCommitment},
     malpriv::MessageHash,
     message::po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
     uint::UInt,
+    utils::without_rejected,
 };
 use serialize::AsUseCast;
-use std::sync::Arc;
+use server_protocol::HeldShare;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
+use tracing::warn;
 
 pub struct ClientData<I: UInt, H: MessageHash> {
+    /// Uids of all clients, in order.
+    pub uids: Vec<ClientID>,
+    /// Decides which server is OT sender for each client.
+    pub policy: PartitionPolicy,
+    /// Uids of the clients of `po2_msgs_alice`, in the same order.
+    pub uids_alice: Vec<ClientID>,
+    /// Uids of the clients of `po2_msgs_bob`, in the same order.
+    pub uids_bob: Vec<ClientID>,
+
     pub po2_msgs_alice: Arc<[ClientPo2MsgToAlice]>,
     pub po2_msgs_bob: Arc<[ClientPo2MsgToBob<I>]>,
+    /// Share commitments sent by the clients, in uid order. See
+    /// `server_protocol::exchange_commitments`.
+    pub commitments: Vec<ShareCommitment>,
+    /// Session the clients bind into their commitments, 0 unless set by
+    /// `with_session`.
+    pub session: u64,
 
     pub comm_alice: usize,
     pub comm_bob: usize,
@@ -26,8 +46,14 @@ pub struct ClientData<I: UInt, H: MessageHash> {
     pub hash_b2a_ab: Vec<H::Output>,
 
     pub phase2_time: f64,
-    /// OT verification hashes from Bob to Alice, for clients where I'm Alice
-    pub hash_ot_ba: Vec<H::Output>,
+    /// OT verification hashes from Bob to Alice, for clients where I'm Alice.
+    /// `None` for dropped clients.
+    pub hash_ot_ba: Vec<Option<H::Output>>,
+    /// Clients that did not send phase 2 in time to either server, in uid
+    /// order. See `with_dropped`. They are kept in the vectors above, so
+    /// that these stay aligned with the message ids and hashers of the other
+    /// clients, and are skipped by the protocol.
+    pub dropped: Vec<ClientID>,
 }
 
 impl<I: UInt, H: MessageHash> ClientData<I, H> {
@@ -39,10 +65,79 @@ impl<I: UInt, H: MessageHash> ClientData<I, H> {
         self.po2_msgs_bob.len()
     }
 
-    pub async fn fetch(is_alice: bool, port: u16, num_clients: usize, chi_seed: u64) -> Self {
-        let listener = TcpListener::bind(("0.0.0.0", port)).await.unwrap();
+    /// Split `msgs` of all clients, in uid order, into messages of the
+    /// clients of `uids_alice` and of `uids_bob`, in their order.
+    pub fn split_by_uid<T>(
+        &self,
+        is_alice: bool,
+        msgs: impl IntoIterator<Item = T>,
+    ) -> (Vec<T>, Vec<T>) {
+        let (from_alice, from_bob) = self
+            .policy
+            .split_iter(is_alice, with_uids(&self.uids, msgs));
+        (
+            join_uids(&self.uids_alice, from_alice),
+            join_uids(&self.uids_bob, from_bob),
+        )
+    }
+
+    /// Inverse of `split_by_uid`.
+    pub fn merge_by_uid<T>(
+        &self,
+        is_alice: bool,
+        from_alice: impl IntoIterator<Item = T>,
+        from_bob: impl IntoIterator<Item = T>,
+    ) -> Vec<T> {
+        self.policy
+            .merge_msg(
+                is_alice,
+                with_uids(&self.uids_alice, from_alice),
+                with_uids(&self.uids_bob, from_bob),
+            )
+            .into_values()
+            .collect()
+    }
+
+    /// Accept `num_clients` clients that register with `token`, and receive
+    /// both phases of their messages. A client whose phase 2 does not arrive
+    /// within `phase2_timeout` after phase 1 is dropped on my side, see
+    /// `dropped_on_my_side`. Without a timeout, wait forever.
+    pub async fn fetch(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        addr: SocketAddr,
+        num_clients: usize,
+        token: ClientToken,
+        chi_seed: u64,
+        phase2_timeout: Option<Duration>,
+    ) -> Self {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        Self::fetch_with_listener(
+            is_alice,
+            policy,
+            listener,
+            num_clients,
+            token,
+            chi_seed,
+            phase2_timeout,
+        )
+        .await
+    }
+
+    /// Same as `fetch`, accepting the clients from a bound listener.
+    pub async fn fetch_with_listener(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        listener: TcpListener,
+        num_clients: usize,
+        token: ClientToken,
+        chi_seed: u64,
+        phase2_timeout: Option<Duration>,
+    ) -> Self {
         // accepts clients connection
-        let clients = ClientsPool::new(num_clients, listener).await;
+        let clients = ClientsPool::new(num_clients, listener, token)
+            .await
+            .with_policy(policy);
         // load balancing: split the clients pool and ALICE pool and BOB pool, notice
         // that this "Bob" is different from the "bob"
         // for global server role.  Alice is OT sender, Bob is OT receiver.
@@ -71,12 +166,22 @@ impl<I: UInt, H: MessageHash> ClientData<I, H> {
         let (alice_msg, bob_msg) = tokio::join!(alice_msg, bob_msg);
         let (alice_msg, bob_msg) = (alice_msg.unwrap(), bob_msg.unwrap());
 
-        let po2_msgs_alice = Arc::<[_]>::from(alice_msg.into_boxed_slice());
+        let (uids_alice, po2_msgs_alice): (Vec<_>, Vec<_>) = alice_msg
+            .into_iter()
+            .map(|(uid, mut m)| {
+                m.bind_to(uid.id);
+                (uid, m)
+            })
+            .unzip();
+        let po2_msgs_alice = Arc::<[_]>::from(po2_msgs_alice.into_boxed_slice());
 
+        let mut uids_bob = Vec::with_capacity(bob_msg.len());
         let mut po2_msgs_bob = Vec::with_capacity(bob_msg.len());
         let mut hash_b2a_ab = Vec::with_capacity(bob_msg.len());
 
-        for (m, h_b2a) in bob_msg {
+        for (uid, (mut m, h_b2a)) in bob_msg {
+            m.bind_to(uid.id);
+            uids_bob.push(uid);
             po2_msgs_bob.push(m);
             hash_b2a_ab.push(h_b2a);
         }
@@ -92,27 +197,156 @@ impl<I: UInt, H: MessageHash> ClientData<I, H> {
             .await;
 
         // receive phase 2 hashes for both alice and bob
-        let hash_ot_ba = {
-            let clients_alice = clients_alice.clone();
-            clients_alice
+        let hash_ot_ba = match phase2_timeout {
+            Some(timeout) => clients_alice
+                .subscribe_and_get_each_timeout::<H::Output>(RecvId::SECOND, timeout)
+                .await
+                .into_iter()
+                .map(|(uid, hash)| {
+                    hash.map_err(|e| warn!("client {} dropped in phase 2: {}", uid.id, e))
+                        .ok()
+                })
+                .collect(),
+            None => clients_alice
                 .subscribe_and_get::<H::Output>(RecvId::SECOND)
                 .await
                 .unwrap()
+                .into_iter()
+                .map(|(_, hash)| Some(hash))
+                .collect(),
         };
 
         let phase2_time = end_timer!(timer).elapsed().as_secs_f64();
 
+        let commitments = clients
+            .policy
+            .merge_msg(
+                is_alice,
+                with_uids(&uids_alice, po2_msgs_alice.iter().map(|m| m.commitment)),
+                with_uids(&uids_bob, po2_msgs_bob.iter().map(|m| m.commitment)),
+            )
+            .into_values()
+            .collect();
+
         let comm_alice = clients_alice.num_bytes_received_from_all();
         let comm_bob = clients_bob.num_bytes_received_from_all();
         Self {
+            uids: clients.uids(),
+            policy: clients.policy.clone(),
+            uids_alice,
+            uids_bob,
             po2_msgs_alice,
             po2_msgs_bob,
+            commitments,
+            session: 0,
             comm_alice,
             comm_bob,
             phase1_time,
             phase2_time,
             hash_b2a_ab,
             hash_ot_ba,
+            dropped: Vec::new(),
+        }
+    }
+
+    /// Same clients, whose commitments are bound to `session`.
+    pub fn with_session(mut self, session: u64) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// The share of each client I hold, in uid order, to check its
+    /// commitment with `server_protocol::exchange_commitments`.
+    pub fn held_shares(&self, is_alice: bool) -> Vec<HeldShare> {
+        self.merge_by_uid(
+            is_alice,
+            self.po2_msgs_alice
+                .iter()
+                .map(|m| HeldShare::Seed(m.inputs_0)),
+            self.po2_msgs_bob
+                .iter()
+                .map(|m| HeldShare::Inputs1(Inputs1Hash::new(m.salt, &m.inputs_1))),
+        )
+    }
+
+    /// Whether each client, in uid order, dropped out on my side. Only the
+    /// OT sender of a client waits for its phase 2, so the peer must be told
+    /// with `server_protocol::exchange_dropped`.
+    pub fn dropped_on_my_side(&self, is_alice: bool) -> Vec<bool> {
+        self.merge_by_uid(
+            is_alice,
+            self.hash_ot_ba.iter().map(Option::is_none),
+            vec![false; self.num_clients_as_bob()],
+        )
+    }
+
+    /// Mark the clients that dropped out on either server, in uid order.
+    /// Their entries are tombstoned rather than removed.
+    pub fn with_dropped(mut self, is_alice: bool, dropped: &[bool]) -> Self {
+        assert_eq!(dropped.len(), self.uids.len());
+        let (dropped_a, _) = self.split_by_uid(is_alice, dropped.iter().copied());
+        for (hash, dropped) in self.hash_ot_ba.iter_mut().zip(dropped_a) {
+            if dropped {
+                *hash = None;
+            }
+        }
+        self.dropped = self
+            .uids
+            .iter()
+            .zip(dropped)
+            .filter(|(_, dropped)| **dropped)
+            .map(|(uid, _)| *uid)
+            .collect();
+        self
+    }
+
+    /// Whether each client is dropped, split into clients where I'm Alice and
+    /// clients where I'm Bob, like the messages.
+    pub fn dropped_split(&self, is_alice: bool) -> (Vec<bool>, Vec<bool>) {
+        let dropped = self.uids.iter().map(|uid| self.dropped.contains(uid));
+        self.split_by_uid(is_alice, dropped)
+    }
+
+    /// Only the clients that are not `rejected`, which is in uid order.
+    /// Communication, time and the remaining dropped clients are kept.
+    pub fn without_clients(self, is_alice: bool, rejected: &[bool]) -> Self {
+        let rejected_uids = self
+            .uids
+            .iter()
+            .zip(rejected)
+            .filter(|(_, r)| **r)
+            .map(|(uid, _)| *uid)
+            .collect::<Vec<_>>();
+        let rejected = rejected.iter().copied();
+        let (rejected_a, rejected_b) = self.split_by_uid(is_alice, rejected.clone());
+        Self {
+            uids: without_rejected(self.uids, rejected.clone()),
+            policy: self.policy,
+            uids_alice: without_rejected(self.uids_alice, rejected_a.iter().copied()),
+            uids_bob: without_rejected(self.uids_bob, rejected_b.iter().copied()),
+            po2_msgs_alice: without_rejected(
+                self.po2_msgs_alice.iter().cloned(),
+                rejected_a.iter().copied(),
+            )
+            .into(),
+            po2_msgs_bob: without_rejected(
+                self.po2_msgs_bob.iter().cloned(),
+                rejected_b.iter().copied(),
+            )
+            .into(),
+            commitments: without_rejected(self.commitments, rejected),
+            session: self.session,
+            comm_alice: self.comm_alice,
+            comm_bob: self.comm_bob,
+            phase1_time: self.phase1_time,
+            hash_b2a_ab: without_rejected(self.hash_b2a_ab, rejected_b),
+            phase2_time: self.phase2_time,
+            hash_ot_ba: without_rejected(self.hash_ot_ba, rejected_a),
+            dropped: self
+                .dropped
+                .into_iter()
+                .filter(|uid| !rejected_uids.contains(uid))
+                .collect(),
         }
     }
 }
@@ -1 +1,3 @@
-pub mod mpc;
+pub mod client_msg;
+pub mod pipeline;
+pub mod utils;
@@ -0,0 +1,337 @@
+//! Server side of the MP-Po2 protocol after both client phases are received,
+//! from OT verification to transcript hash verification.
+//!
+//! A client that sent different share commitments to the two servers is
+//! removed on both before B2A. A client that did not send phase 2 in time
+//! is dropped instead: it keeps its message ids and hashers, which are
+//! shared by index with the peer, but is skipped by every phase and left out
+//! of the aggregate and of the verification counts.
+
+use crate::{
+    client_msg::ClientData,
+    utils::{log_verify_status, IdPool},
+};
+use bin_utils::{
+    hash_pool::{HashPhase, HashPool},
+    panic_report::expect_joined,
+    runtime::{block_on_compute, spawn_gated},
+};
+use bridge::{end_timer, mpc_conn::MpcConnection, start_timer};
+use crypto_primitives::{
+    cot::{
+        dims::ProtocolDims,
+        server::{sample_chi, Chi},
+    },
+    malpriv::MessageHash,
+    uint::UInt,
+    utils::{batch_sum, iter_arc, Hook},
+};
+use rayon::prelude::*;
+use server_protocol as mpc;
+use std::sync::Arc;
+use tracing::warn;
+use zeroize::Zeroizing;
+
+/// Shares held by one server, and verification results.
+pub struct PipelineOutput<A: UInt> {
+    /// B2A output: arithmetic shares of the inputs, in uid order, `None` for
+    /// dropped clients.
+    pub arith_shares: Vec<Option<Vec<A>>>,
+    /// Number of clients where I'm OT sender whose OTs are verified.
+    pub num_ot_verified: usize,
+    /// Number of clients where I'm OT receiver whose B2A transcript matches.
+    pub num_b2a_hash_verified: usize,
+    /// Number of clients where I'm OT sender whose OT verification transcript
+    /// matches.
+    pub num_ot_hash_verified: usize,
+
+    pub b2a_time: f64,
+    pub hash_verify_time: f64,
+}
+
+impl<A: UInt> PipelineOutput<A> {
+    /// My share of the sum of the inputs of all clients that were not
+    /// dropped.
+    pub fn aggregate(&self) -> Vec<A> {
+        batch_sum(self.arith_shares.iter().flatten())
+    }
+}
+
+/// Agree with `peer` on the clients of the round: remove the clients whose
+/// shares do not match their share commitment, and mark the clients that dropped out on
+/// either server. Returns the clients and the message ids of the protocol.
+pub async fn agree_on_clients<I: UInt, H: MessageHash>(
+    is_alice: bool,
+    client_data: ClientData<I, H>,
+    peer: &MpcConnection,
+) -> (ClientData<I, H>, IdPool) {
+    // manage message ids
+    // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
+    let ids = IdPool::build(
+        client_data.num_clients_as_alice(),
+        client_data.num_clients_as_bob(),
+    );
+
+    let dropped = mpc::exchange_dropped(
+        ids.exchange_dropped,
+        &client_data.dropped_on_my_side(is_alice),
+        peer,
+    )
+    .await;
+    let client_data = client_data.with_dropped(is_alice, &dropped);
+    for uid in &client_data.dropped {
+        warn!("client {} dropped: no phase 2 message", uid.id);
+    }
+
+    // both servers reject the same clients, so the message ids of the others
+    // still match
+    let rejected = mpc::exchange_commitments(
+        ids.exchange_commitments,
+        client_data.session,
+        &client_data.uids,
+        &client_data.commitments,
+        &client_data.held_shares(is_alice),
+        peer,
+    )
+    .await;
+    let client_data = if rejected.contains(&true) {
+        for (uid, _) in client_data.uids.iter().zip(&rejected).filter(|(_, r)| **r) {
+            warn!(
+                "client {} rejected: shares do not match the share commitment",
+                uid.id
+            );
+        }
+        client_data.without_clients(is_alice, &rejected)
+    } else {
+        client_data
+    };
+    (client_data, ids)
+}
+
+/// Run the protocol with `peer` on the clients agreed by
+/// `agree_on_clients`. `is_alice` is the global role of this server.
+#[allow(clippy::too_many_arguments)]
+pub async fn run<I, A, H, F>(
+    gsize: usize,
+    sec_param: usize,
+    is_alice: bool,
+    client_data: &ClientData<I, H>,
+    ids: IdPool,
+    chi_seed: u64,
+    peer: &MpcConnection,
+    make_hasher: F,
+) -> PipelineOutput<A>
+where
+    I: UInt,
+    A: UInt,
+    H: MessageHash<Output = Vec<u8>> + Send + 'static,
+    F: Fn() -> H,
+{
+    let (dropped_a, dropped_b) = client_data.dropped_split(is_alice);
+    let num_surviving_a = dropped_a.iter().filter(|d| !**d).count();
+    let num_surviving_b = dropped_b.iter().filter(|d| !**d).count();
+
+    // manage hashes
+    let mut hashers = HashPool::init(
+        [
+            (HashPhase::OtBA, client_data.num_clients_as_alice()),
+            (HashPhase::B2aAB, client_data.num_clients_as_bob()),
+        ],
+        make_hasher,
+    );
+
+    let timer = start_timer!(|| "OT Verify + B2A");
+
+    // first, sample chi that is used to generate all OTs
+    let dims = ProtocolDims::new::<I>(gsize, sec_param).expect("invalid protocol dimensions");
+    let chi = Chi::Shared(Arc::new(sample_chi(dims.num_total(), chi_seed)));
+
+    // OT Verify Alice Receive (Start)
+    let ot_alice_hook = Hook::new_named("OT Verify Alice");
+    let ot_ba_handles = iter_arc(&client_data.po2_msgs_alice)
+        .zip(ids.otverify_a)
+        .zip(&dropped_a)
+        .enumerate()
+        .map(|(idx, ((c_msg, id), dropped))| {
+            if *dropped {
+                return None;
+            }
+            let mut hasher = hashers.take(HashPhase::OtBA, idx);
+            let peer = peer.clone();
+            let chi = chi.clone();
+            Some(spawn_gated(async move {
+                let result =
+                    mpc::ot_verify_alice(id, &c_msg.cot, chi, sec_param, peer, &mut hasher).await;
+                (result, hasher)
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    // OT verify Bob send (Start)
+
+    let ot_bob_hook = Hook::new_named("OT Verify Bob");
+    let otverify_bob_handles = {
+        let peer = peer.clone();
+        let chi = chi.clone();
+        let c_msg = client_data.po2_msgs_bob.clone();
+        let dropped_b = dropped_b.clone();
+        tokio::task::spawn_blocking(move || {
+            c_msg
+                .par_iter()
+                .zip(ids.otverify_b)
+                .zip(dropped_b)
+                .filter(|(_, dropped)| !dropped)
+                .map(|((c_msg, id), _)| mpc::ot_verify_bob(id, c_msg, &peer, chi.clone(), dims))
+                .collect::<Vec<_>>()
+        })
+    };
+
+    // B2A Bob Receive (Start)
+    let b2a_bob_hook = Hook::new_named("B2A Bob");
+    let b2a_bob_handles = iter_arc(&client_data.po2_msgs_bob)
+        .zip(ids.b2a_b)
+        .zip(&dropped_b)
+        .enumerate()
+        .map(|(idx, ((c_msg, id), dropped))| {
+            if *dropped {
+                return None;
+            }
+            let mut hasher = hashers.take(HashPhase::B2aAB, idx);
+            let peer = peer.clone();
+            Some(spawn_gated(async move {
+                let result = {
+                    let (inputs_1, ts) = (&c_msg.inputs_1, c_msg.cot.ts());
+                    mpc::b2a_bob::<_, A, _>(&id, dims, inputs_1, ts, peer, &mut hasher).await
+                };
+                (result, hasher)
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    // OT Verify Alice Receive (Complete)
+    let mut qs_per_client = Vec::with_capacity(client_data.num_clients_as_alice());
+    let mut num_ot_verified = 0;
+    for (idx, alice_handle) in ot_ba_handles.into_iter().enumerate() {
+        let qs = match alice_handle {
+            Some(handle) => {
+                let (result, hasher) = expect_joined(handle.await);
+                let (qs, v) = result.expect("OT verification failed on a client");
+                num_ot_verified += v as usize;
+                hashers.put(HashPhase::OtBA, idx, hasher);
+                // wiped once B2A is done
+                Some(Zeroizing::new(qs))
+            },
+            None => None,
+        };
+        qs_per_client.push(qs);
+    }
+    log_verify_status(num_ot_verified, num_surviving_a, "OT Verify Alice");
+    ot_alice_hook.done();
+
+    // B2A Alice Send (Start)
+    let b2a_alice_hook = Hook::new_named("B2A Alice");
+    let b2a_alice_handles = block_on_compute(|| {
+        client_data
+            .po2_msgs_alice
+            .par_iter()
+            .zip(qs_per_client)
+            .zip(ids.b2a_a)
+            .map(|((c_msg, qs), id)| {
+                let qs = qs?;
+                Some(mpc::b2a_alice::<I, A>(
+                    &id,
+                    dims,
+                    c_msg.inputs_0,
+                    c_msg.cot.delta,
+                    &qs,
+                    peer,
+                ))
+            })
+            .collect::<Vec<_>>()
+    });
+
+    // B2A Bob Receive (Complete)
+    let mut bob_arith_shares = Vec::with_capacity(client_data.num_clients_as_bob());
+    for (idx, bob_handle) in b2a_bob_handles.into_iter().enumerate() {
+        let bob_arith_share = match bob_handle {
+            Some(handle) => {
+                let (result, hasher) = expect_joined(handle.await);
+                hashers.put(HashPhase::B2aAB, idx, hasher);
+                Some(result.expect("B2A failed on a client"))
+            },
+            None => None,
+        };
+        bob_arith_shares.push(bob_arith_share);
+    }
+    b2a_bob_hook.done();
+
+    // B2A Alice Send (Complete)
+    let mut alice_arith_shares = Vec::with_capacity(client_data.num_clients_as_alice());
+    for result in b2a_alice_handles {
+        let share = match result {
+            Some(result) => {
+                let (s, handle) = result.expect("B2A failed on a client");
+                handle
+                    .await
+                    .expect("MPC connection closed before the message was sent");
+                Some(s)
+            },
+            None => None,
+        };
+        alice_arith_shares.push(share);
+    }
+    b2a_alice_hook.done();
+
+    // OT Verify Bob Send (Complete)
+    for result in expect_joined(otverify_bob_handles.await) {
+        result
+            .expect("OT verification failed on a client")
+            .await
+            .expect("MPC connection closed before the message was sent");
+    }
+    ot_bob_hook.done();
+
+    let b2a_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let timer = start_timer!(|| "Hash Verification");
+    // B2A
+    let expected_b2a = client_data
+        .hash_b2a_ab
+        .iter()
+        .zip(&dropped_b)
+        .map(|(hash, dropped)| (!dropped).then_some(hash))
+        .collect::<Vec<_>>();
+    let num_b2a_hash_verified = hashers
+        .verify_surviving(HashPhase::B2aAB, &expected_b2a)
+        .into_iter()
+        .filter(|verified| *verified == Some(true))
+        .count();
+    log_verify_status(num_b2a_hash_verified, num_surviving_b, "B2A Hash AB");
+
+    // OT Verify
+    let expected_ot = client_data
+        .hash_ot_ba
+        .iter()
+        .zip(&dropped_a)
+        .map(|(hash, dropped)| hash.as_ref().filter(|_| !dropped))
+        .collect::<Vec<_>>();
+    let num_ot_hash_verified = hashers
+        .verify_surviving(HashPhase::OtBA, &expected_ot)
+        .into_iter()
+        .filter(|verified| *verified == Some(true))
+        .count();
+    log_verify_status(num_ot_hash_verified, num_surviving_a, "OT Verify Hash");
+
+    let hash_verify_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let arith_shares = client_data.merge_by_uid(is_alice, alice_arith_shares, bob_arith_shares);
+
+    PipelineOutput {
+        arith_shares,
+        num_ot_verified,
+        num_b2a_hash_verified,
+        num_ot_hash_verified,
+        b2a_time,
+        hash_verify_time,
+    }
+}
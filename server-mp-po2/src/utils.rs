@@ -1,22 +1,41 @@
-use bridge::id_tracker::{IdGen, RecvId, SendId};
-use crypto_primitives::malpriv::MessageHash;
+use bridge::id_tracker::{ExchangeId, IdGen, RecvId, SendId};
+use server_protocol::B2A_CHUNKS;
 use tracing::{error, info};
 
 /// Message IDs for various clients
 pub struct IdPool {
+    /// checks of the share commitments of all clients, before B2A: their
+    /// digests, then the clients to reject
+    pub exchange_commitments: (ExchangeId, ExchangeId),
+    /// clients dropped before phase 2 on either server, exchanged before B2A
+    pub exchange_dropped: ExchangeId,
+
     pub otverify_a: Vec<RecvId>,
     pub otverify_b: Vec<SendId>,
 
-    pub b2a_a: Vec<SendId>,
-    pub b2a_b: Vec<RecvId>,
+    /// `B2A_CHUNKS` consecutive ids for the `us` of each client
+    pub b2a_a: Vec<Vec<SendId>>,
+    pub b2a_b: Vec<Vec<RecvId>>,
 }
 
 impl IdPool {
+    /// Shares of the seed of chi, exchanged before the clients are fetched,
+    /// so before the number of clients of each role is known. `build`
+    /// allocates its ids after this one.
+    pub fn exchange_chi_seed() -> ExchangeId {
+        IdGen::new().next_exchange_id()
+    }
+
     pub fn build(alice_pool_size: usize, bob_pool_size: usize) -> Self {
         // manage message ids
         // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
 
         let mut id = IdGen::new();
+        // skip `exchange_chi_seed`
+        id.next_exchange_id();
+
+        let exchange_commitments = (id.next_exchange_id(), id.next_exchange_id());
+        let exchange_dropped = id.next_exchange_id();
 
         let otverify_a = (0..alice_pool_size)
             .map(|_| id.next_recv_id())
@@ -26,13 +45,15 @@ impl IdPool {
             .collect::<Vec<_>>();
 
         let b2a_a = (0..alice_pool_size)
-            .map(|_| id.next_send_id())
+            .map(|_| (0..B2A_CHUNKS).map(|_| id.next_send_id()).collect())
             .collect::<Vec<_>>();
         let b2a_b = (0..bob_pool_size)
-            .map(|_| id.next_recv_id())
+            .map(|_| (0..B2A_CHUNKS).map(|_| id.next_recv_id()).collect())
             .collect::<Vec<_>>();
 
         IdPool {
+            exchange_commitments,
+            exchange_dropped,
             otverify_a,
             otverify_b,
             b2a_a,
@@ -41,20 +62,6 @@ impl IdPool {
     }
 }
 
-pub struct HashPool<H: MessageHash> {
-    pub b2a_ab: Vec<H>,
-    pub ot_ba: Vec<H>,
-}
-
-impl<H: MessageHash> HashPool<H> {
-    pub fn init(alice_pool_size: usize, bob_pool_size: usize, hasher: impl Fn() -> H) -> Self {
-        let hasher = |_| hasher();
-        let b2a_ab = (0..bob_pool_size).map(hasher).collect::<Vec<_>>();
-        let ot_ba = (0..alice_pool_size).map(hasher).collect::<Vec<_>>();
-
-        Self { b2a_ab, ot_ba }
-    }
-}
 #[inline]
 pub fn log_verify_status(num_verified: usize, num_total: usize, name: &str) {
     if num_verified == num_total {
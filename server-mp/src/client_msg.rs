@@ -1,26 +1,67 @@
 //! Client interaction
-use bridge::{client_server::ClientsPool, end_timer, id_tracker::RecvId, start_timer};
+use bin_utils::signing::ClientKeys;
+use bridge::{
+    client_dump::ClientRecording,
+    client_server::{join_uids, with_uids, ClientSession, PartitionPolicy},
+    end_timer, start_timer,
+    tcp_bridge::{ClientID, ClientToken},
+};
 use crypto_primitives::{
-    malpriv::MessageHash,
+    a2s::{num_squared, WitnessShareSeedToAlice, WitnessSharesToBob},
+    bits::{Inputs1Hash, ShareCommitment},
+    malpriv::{derive_phase_seeds, MessageHash},
     message::{
         l2::{ClientMPMsgToAlice, ClientMPMsgToBob},
         po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
     },
-    square_corr::SquareCorrShare,
+    square_corr::{CorrError, SquareCorrShare},
     uint::UInt,
-    utils::bytes_to_seed_pairs,
+    utils::without_rejected,
 };
 use rayon::prelude::*;
+use server_protocol::{self as mpc, ClientFailure, HeldShare};
 
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 use tokio::net::TcpListener;
+use tracing::warn;
 
 pub struct ClientData<I: UInt, C: UInt, H: MessageHash> {
+    /// Uids of all clients, in order.
+    pub uids: Vec<ClientID>,
+    /// Decides which server is OT sender for each client.
+    pub policy: PartitionPolicy,
+    /// Uids of the clients of `po2_msgs_alice`, in the same order.
+    pub uids_alice: Vec<ClientID>,
+    /// Uids of the clients of `po2_msgs_bob`, in the same order.
+    pub uids_bob: Vec<ClientID>,
+
     pub po2_msgs_alice: Arc<[ClientPo2MsgToAlice]>,
     pub po2_msgs_bob: Arc<[ClientPo2MsgToBob<I>]>,
+    /// Share commitments sent by the clients, in uid order. See
+    /// `server_protocol::exchange_commitments`.
+    pub commitments: Vec<ShareCommitment>,
+    /// Session the clients bind into their commitments, 0 unless set by
+    /// `with_session`.
+    pub session: u64,
+    /// Whether the upload of each client is not signed by its key, in uid
+    /// order. Always false without `signed-uploads`, see `ClientKeys`.
+    pub bad_signatures: Vec<bool>,
 
-    pub sqcorr_alice: Arc<[Vec<SquareCorrShare<C>>]>,
-    pub sqcorr_bob: Arc<[Vec<SquareCorrShare<C>>]>,
+    /// Expanded square correlations, or the error of a client whose message
+    /// does not match the group size.
+    pub sqcorr_alice: Arc<[Result<Vec<SquareCorrShare<C>>, CorrError>]>,
+    pub sqcorr_bob: Arc<[Result<Vec<SquareCorrShare<C>>, CorrError>]>,
+    /// Bound of every input, checked after A2S, or `None` to check nothing.
+    /// The messages of the clients are for it.
+    pub bound: Option<I>,
+    /// Shares of the witness bits of the bound check, in uid order, empty
+    /// without a bound. Their square correlations follow the ones of the
+    /// inputs, see `a2s::num_squared`.
+    pub witness: Arc<[Vec<C>]>,
+    /// Clients whose upload cannot be deserialized, in uid order. They are in
+    /// none of the other fields, and are left out on both servers by
+    /// `pipeline::run_round`.
+    pub failed: Vec<ClientID>,
 
     pub comm_alice: usize,
     pub comm_bob: usize,
@@ -55,64 +96,315 @@ impl<I: UInt, C: UInt, H: MessageHash<Output = Vec<u8>>> ClientData<I, C, H> {
         self.num_clients_as_alice() + self.num_clients_as_bob()
     }
 
+    /// Split `msgs` of all clients, in uid order, into messages of the
+    /// clients of `uids_alice` and of `uids_bob`, in their order.
+    pub fn split_by_uid<T>(
+        &self,
+        is_alice: bool,
+        msgs: impl IntoIterator<Item = T>,
+    ) -> (Vec<T>, Vec<T>) {
+        let (from_alice, from_bob) = self
+            .policy
+            .split_iter(is_alice, with_uids(&self.uids, msgs));
+        (
+            join_uids(&self.uids_alice, from_alice),
+            join_uids(&self.uids_bob, from_bob),
+        )
+    }
+
+    /// Inverse of `split_by_uid`.
+    pub fn merge_by_uid<T>(
+        &self,
+        is_alice: bool,
+        from_alice: impl IntoIterator<Item = T>,
+        from_bob: impl IntoIterator<Item = T>,
+    ) -> Vec<T> {
+        merge_by_uid(
+            &self.policy,
+            is_alice,
+            (&self.uids_alice, from_alice),
+            (&self.uids_bob, from_bob),
+        )
+    }
+
+    /// Same clients, whose commitments are bound to `session`.
+    pub fn with_session(mut self, session: u64) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// The share of each client I hold, in uid order, to check its
+    /// commitment with `server_protocol::exchange_commitments`.
+    pub fn held_shares(&self, is_alice: bool) -> Vec<HeldShare> {
+        self.merge_by_uid(
+            is_alice,
+            self.po2_msgs_alice
+                .iter()
+                .map(|m| HeldShare::Seed(m.inputs_0)),
+            self.po2_msgs_bob
+                .iter()
+                .map(|m| HeldShare::Inputs1(Inputs1Hash::new(m.salt, &m.inputs_1))),
+        )
+    }
+
+    /// Only the clients that are not `rejected`, which is in uid order.
+    /// Communication, time and the failed clients are kept.
+    pub fn without_clients(&self, is_alice: bool, rejected: &[bool]) -> Self {
+        let rejected = rejected.iter().copied();
+        let (rejected_a, rejected_b) = self.split_by_uid(is_alice, rejected.clone());
+        let (rejected_a, rejected_b) = (rejected_a.iter().copied(), rejected_b.iter().copied());
+        Self {
+            uids: without_rejected(self.uids.iter().copied(), rejected.clone()),
+            policy: self.policy.clone(),
+            uids_alice: without_rejected(self.uids_alice.iter().copied(), rejected_a.clone()),
+            uids_bob: without_rejected(self.uids_bob.iter().copied(), rejected_b.clone()),
+            po2_msgs_alice: without_rejected(
+                self.po2_msgs_alice.iter().cloned(),
+                rejected_a.clone(),
+            )
+            .into(),
+            po2_msgs_bob: without_rejected(self.po2_msgs_bob.iter().cloned(), rejected_b.clone())
+                .into(),
+            commitments: without_rejected(self.commitments.iter().copied(), rejected.clone()),
+            session: self.session,
+            bad_signatures: without_rejected(self.bad_signatures.iter().copied(), rejected.clone()),
+            sqcorr_alice: without_rejected(self.sqcorr_alice.iter().cloned(), rejected_a.clone())
+                .into(),
+            sqcorr_bob: without_rejected(self.sqcorr_bob.iter().cloned(), rejected_b.clone())
+                .into(),
+            bound: self.bound,
+            witness: without_rejected(self.witness.iter().cloned(), rejected.clone()).into(),
+            failed: self.failed.clone(),
+            comm_alice: self.comm_alice,
+            comm_bob: self.comm_bob,
+            phase1_time: self.phase1_time,
+            hash_b2a_ab: without_rejected(self.hash_b2a_ab.iter().cloned(), rejected_b.clone()),
+            hash_a2s: without_rejected(self.hash_a2s.iter().cloned(), rejected.clone()),
+            phase2_time: self.phase2_time,
+            hash_ot_ba: without_rejected(self.hash_ot_ba.iter().cloned(), rejected_a.clone()),
+            hash_sqcorr_ab: without_rejected(self.hash_sqcorr_ab.iter().cloned(), rejected_b),
+            hash_sqcorr_ba: without_rejected(self.hash_sqcorr_ba.iter().cloned(), rejected_a),
+            chi_seed_share: without_rejected(self.chi_seed_share.iter().copied(), rejected.clone()),
+            t_seed_share: without_rejected(self.t_seed_share.iter().copied(), rejected),
+        }
+    }
+
+    /// Accept `num_clients` clients that register with `token`, and receive
+    /// their messages for `gsize` inputs at most `bound`, checking their
+    /// signatures with `keys`. If `dump_path` is given, the raw messages are also saved there for `replay`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn fetch<F>(
         is_alice: bool,
-        port: u16,
+        policy: PartitionPolicy,
+        addr: SocketAddr,
         num_clients: usize,
+        token: ClientToken,
         gsize: usize,
+        bound: Option<I>,
         hasher: F,
+        keys: &ClientKeys,
+        dump_path: Option<String>,
+    ) -> Self
+    where
+        F: Fn() -> H + Sync,
+    {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        Self::fetch_with_listener(
+            is_alice,
+            policy,
+            listener,
+            num_clients,
+            token,
+            gsize,
+            bound,
+            hasher,
+            keys,
+            dump_path,
+        )
+        .await
+    }
+
+    /// Same as `fetch`, accepting the clients from a bound listener.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn fetch_with_listener<F>(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        listener: TcpListener,
+        num_clients: usize,
+        token: ClientToken,
+        gsize: usize,
+        bound: Option<I>,
+        hasher: F,
+        keys: &ClientKeys,
+        dump_path: Option<String>,
     ) -> Self
     where
         F: Fn() -> H + Sync,
     {
-        let listener = TcpListener::bind(("0.0.0.0", port)).await.unwrap();
         // accepts clients connection
-        let clients = ClientsPool::new(num_clients, listener).await;
-        // load balancing: split the clients pool and ALICE pool and BOB pool, notice
-        // that this "Bob" is different from the "bob"
-        // for global server role.  Alice is OT sender, Bob is OT receiver.
-        let (clients_alice, clients_bob) = clients.split(is_alice);
+        let session = ClientSession::accept(is_alice, policy, listener, num_clients, token).await;
+        Self::fetch_round(&session, 0, gsize, bound, hasher, keys, dump_path).await
+    }
 
+    /// Receive the messages of round `round` of `session`. Communication is
+    /// `ClientSession::round_comm`.
+    pub async fn fetch_round<F>(
+        session: &ClientSession,
+        round: u64,
+        gsize: usize,
+        bound: Option<I>,
+        hasher: F,
+        keys: &ClientKeys,
+        dump_path: Option<String>,
+    ) -> Self
+    where
+        F: Fn() -> H + Sync,
+    {
         let timer = start_timer!(|| "Client Fetch");
 
-        let alice_msg = {
-            let clients_alice = clients_alice.clone();
-            tokio::spawn(async move {
-                clients_alice
-                    .subscribe_and_get::<ClientMPMsgToAlice<H>>(RecvId::FIRST)
-                    .await
-                    .unwrap()
+        let recording = session.fetch_round(round).await.unwrap();
+        let (comm_alice, comm_bob) = session.round_comm(&recording);
+        if let Some(path) = dump_path {
+            recording.dump(path).unwrap();
+        }
+        let mut data = Self::from_recording(
+            session.is_alice(),
+            session.policy().clone(),
+            recording,
+            gsize,
+            bound,
+            hasher,
+            keys,
+        );
+
+        data.phase1_time = end_timer!(timer).elapsed().as_secs_f64();
+
+        data.comm_alice = comm_alice;
+        data.comm_bob = comm_bob;
+        data
+    }
+
+    /// Load client messages saved by `fetch`, without accepting any client.
+    pub fn replay<F>(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        path: &str,
+        gsize: usize,
+        bound: Option<I>,
+        hasher: F,
+        keys: &ClientKeys,
+    ) -> Self
+    where
+        F: Fn() -> H + Sync,
+    {
+        Self::from_recording(
+            is_alice,
+            policy,
+            ClientRecording::load(path).unwrap(),
+            gsize,
+            bound,
+            hasher,
+            keys,
+        )
+    }
+
+    /// Check the signatures of the client messages with `keys`, and
+    /// deserialize and expand them, with the witness bits of `bound` if any.
+    /// A client whose upload cannot be
+    /// deserialized is logged and added to `failed`. Communication is the
+    /// size of the messages, and time is zero. Panics if the recording is not
+    /// split by `policy`.
+    pub fn from_recording<F>(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        recording: ClientRecording,
+        gsize: usize,
+        bound: Option<I>,
+        hasher: F,
+        keys: &ClientKeys,
+    ) -> Self
+    where
+        F: Fn() -> H + Sync,
+    {
+        assert!(
+            recording.matches_policy(is_alice, &policy),
+            "client messages are not split by partition policy {}",
+            policy
+        );
+        let comm_alice = recording.num_bytes_alice();
+        let comm_bob = recording.num_bytes_bob();
+        // the correlations of the witness bits follow the ones of the inputs
+        let num_squared = num_squared(gsize, bound);
+
+        let parsed_alice = recording
+            .alice
+            .into_par_iter()
+            .map(|(uid, m)| {
+                let (m, signed) = keys.open(uid, m);
+                let parsed = mpc::parse_upload::<ClientMPMsgToAlice<H>, WitnessShareSeedToAlice>(
+                    &m,
+                    bound.is_some(),
+                )
+                .map_err(ClientFailure::from)
+                .and_then(|(m, witness)| {
+                    let witness = match (witness, bound) {
+                        (Some(witness), Some(bound)) => witness.expand_checked(gsize, bound)?,
+                        _ => Vec::new(),
+                    };
+                    Ok((m, (signed, witness)))
+                });
+                (uid, parsed)
             })
-        };
-        let bob_msg = {
-            let clients_bob = clients_bob.clone();
-            tokio::spawn(async move {
-                clients_bob
-                    .subscribe_and_get::<ClientMPMsgToBob<I, C, H>>(RecvId::FIRST)
-                    .await
-                    .unwrap()
+            .collect::<Vec<_>>();
+        let parsed_bob = recording
+            .bob
+            .into_par_iter()
+            .map(|(uid, m)| {
+                let (m, signed) = keys.open(uid, m);
+                let parsed = mpc::parse_upload::<ClientMPMsgToBob<I, C, H>, WitnessSharesToBob<C>>(
+                    &m,
+                    bound.is_some(),
+                )
+                .map_err(ClientFailure::from)
+                .and_then(|(m, witness)| {
+                    let witness = match (witness, bound) {
+                        (Some(witness), Some(bound)) => witness.expand_checked(gsize, bound)?,
+                        _ => Vec::new(),
+                    };
+                    Ok((m, (signed, witness)))
+                });
+                (uid, parsed)
             })
-        };
-        let (alice_msg, bob_msg) = tokio::join!(alice_msg, bob_msg);
-        let (alice_msg, bob_msg) = (alice_msg.unwrap(), bob_msg.unwrap());
+            .collect::<Vec<_>>();
+        let (uids_alice, parsed_alice, failed_alice) = mpc::split_parsed(parsed_alice);
+        let (uids_bob, parsed_bob, failed_bob) = mpc::split_parsed(parsed_bob);
+        let (alice_msg, extra_a) = parsed_alice.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+        let (bob_msg, extra_b) = parsed_bob.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+        let (signed_a, witness_a) = extra_a.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+        let (signed_b, witness_b) = extra_b.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+
+        let mut failed = Vec::with_capacity(failed_alice.len() + failed_bob.len());
+        for (uid, failure) in failed_alice.into_iter().chain(failed_bob) {
+            warn!("client {} failed: {}", uid.id, failure);
+            failed.push(uid);
+        }
+        failed.sort_unstable();
+        let mut uids = [&uids_alice[..], &uids_bob[..]].concat();
+        uids.sort_unstable();
 
         let (chi_seeds_a, t_seeds_a) = alice_msg
             .par_iter()
             .map(|(phase_1_msg, _)| {
-                let mut hasher = hasher();
-                hasher.absorb(&phase_1_msg);
-                let hash = hasher.digest();
-                bytes_to_seed_pairs(&hash)
+                let seeds = derive_phase_seeds(hasher(), phase_1_msg);
+                (seeds.chi_seed, seeds.t_seed)
             })
             .unzip::<_, _, Vec<_>, Vec<_>>();
 
         let (chi_seeds_b, t_seeds_b) = bob_msg
             .par_iter()
             .map(|(phase_1_msg, _)| {
-                let mut hasher = hasher();
-                hasher.absorb(&phase_1_msg);
-                let hash = hasher.digest();
-                bytes_to_seed_pairs(&hash)
+                let seeds = derive_phase_seeds(hasher(), phase_1_msg);
+                (seeds.chi_seed, seeds.t_seed)
             })
             .unzip::<_, _, Vec<_>, Vec<_>>();
 
@@ -124,7 +416,10 @@ impl<I: UInt, C: UInt, H: MessageHash<Output = Vec<u8>>> ClientData<I, C, H> {
         let mut hash_sqcorr_ab = Vec::with_capacity(bob_msg.len());
         let mut hash_sqcorr_ba = Vec::with_capacity(alice_msg.len());
 
-        for ((m, h), (h_ot_ba, h_sqcorr_ba)) in alice_msg {
+        // the seeds are bound to the clients only now, as the phase seeds hash
+        // them as sent
+        for (uid, ((mut m, h), (h_ot_ba, h_sqcorr_ba))) in uids_alice.iter().zip(alice_msg) {
+            m.po2_msg.bind_to(uid.id);
             po2_msgs_alice.push(m.po2_msg);
             sqcorr_alice.push(m.square_corr);
             hash_a2s_ba.push(h);
@@ -134,7 +429,7 @@ impl<I: UInt, C: UInt, H: MessageHash<Output = Vec<u8>>> ClientData<I, C, H> {
 
         let sqcorr_alice = sqcorr_alice
             .into_par_iter()
-            .map(|v| v.expand(gsize * 2))
+            .map(|v| v.expand_checked(num_squared))
             .collect::<Vec<_>>();
         let sqcorr_alice = Arc::<[_]>::from(sqcorr_alice);
 
@@ -144,7 +439,8 @@ impl<I: UInt, C: UInt, H: MessageHash<Output = Vec<u8>>> ClientData<I, C, H> {
         let mut sqcorr_bob = Vec::with_capacity(bob_msg.len());
         let mut hash_b2a_ab = Vec::with_capacity(bob_msg.len());
         let mut hash_a2s_ab = Vec::with_capacity(bob_msg.len());
-        for ((m, h_b2a, h_a2s), h_sqcorr_ab) in bob_msg {
+        for (uid, ((mut m, h_b2a, h_a2s), h_sqcorr_ab)) in uids_bob.iter().zip(bob_msg) {
+            m.po2_msg.bind_to(uid.id);
             po2_msgs_bob.push(m.po2_msg);
             sqcorr_bob.push(m.square_corr);
             hash_b2a_ab.push(h_b2a);
@@ -153,31 +449,67 @@ impl<I: UInt, C: UInt, H: MessageHash<Output = Vec<u8>>> ClientData<I, C, H> {
         }
         let sqcorr_bob = sqcorr_bob
             .into_par_iter()
-            .map(|v| v.expand())
+            .map(|v| v.expand_checked(num_squared))
             .collect::<Vec<_>>();
         let sqcorr_bob = Arc::<[_]>::from(sqcorr_bob);
 
         let po2_msgs_bob = Arc::<[_]>::from(po2_msgs_bob);
 
-        let hash_a2s =
-            ClientsPool::merge_msg(is_alice, hash_a2s_ba.into_iter(), hash_a2s_ab.into_iter());
-        let chi_seed_share =
-            ClientsPool::merge_msg(is_alice, chi_seeds_a.into_iter(), chi_seeds_b.into_iter());
-        let t_seed_share =
-            ClientsPool::merge_msg(is_alice, t_seeds_a.into_iter(), t_seeds_b.into_iter());
-
-        let phase1_time = end_timer!(timer).elapsed().as_secs_f64();
+        let commitments = merge_by_uid(
+            &policy,
+            is_alice,
+            (&uids_alice, po2_msgs_alice.iter().map(|m| m.commitment)),
+            (&uids_bob, po2_msgs_bob.iter().map(|m| m.commitment)),
+        );
+        let bad_signatures = merge_by_uid(
+            &policy,
+            is_alice,
+            (&uids_alice, signed_a.into_iter().map(|signed| !signed)),
+            (&uids_bob, signed_b.into_iter().map(|signed| !signed)),
+        );
+        let hash_a2s = merge_by_uid(
+            &policy,
+            is_alice,
+            (&uids_alice, hash_a2s_ba),
+            (&uids_bob, hash_a2s_ab),
+        );
+        let witness = merge_by_uid(
+            &policy,
+            is_alice,
+            (&uids_alice, witness_a),
+            (&uids_bob, witness_b),
+        );
+        let chi_seed_share = merge_by_uid(
+            &policy,
+            is_alice,
+            (&uids_alice, chi_seeds_a),
+            (&uids_bob, chi_seeds_b),
+        );
+        let t_seed_share = merge_by_uid(
+            &policy,
+            is_alice,
+            (&uids_alice, t_seeds_a),
+            (&uids_bob, t_seeds_b),
+        );
 
-        let comm_alice = clients_alice.num_bytes_received_from_all();
-        let comm_bob = clients_bob.num_bytes_received_from_all();
         Self {
+            uids,
+            policy,
+            uids_alice,
+            uids_bob,
             po2_msgs_alice,
             po2_msgs_bob,
+            commitments,
+            session: 0,
+            bad_signatures,
             sqcorr_alice,
             sqcorr_bob,
+            bound,
+            witness: witness.into(),
+            failed,
             comm_alice,
             comm_bob,
-            phase1_time,
+            phase1_time: 0.,
             phase2_time: 0.,
             hash_b2a_ab,
             hash_a2s,
@@ -189,3 +521,21 @@ impl<I: UInt, C: UInt, H: MessageHash<Output = Vec<u8>>> ClientData<I, C, H> {
         }
     }
 }
+
+/// Merge the messages of the clients where I'm Alice and where I'm Bob, each
+/// with the uids of its clients in the same order, into uid order.
+fn merge_by_uid<T>(
+    policy: &PartitionPolicy,
+    is_alice: bool,
+    from_alice: (&[ClientID], impl IntoIterator<Item = T>),
+    from_bob: (&[ClientID], impl IntoIterator<Item = T>),
+) -> Vec<T> {
+    policy
+        .merge_msg(
+            is_alice,
+            with_uids(from_alice.0, from_alice.1),
+            with_uids(from_bob.0, from_bob.1),
+        )
+        .into_values()
+        .collect()
+}
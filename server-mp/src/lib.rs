@@ -0,0 +1,5 @@
+pub mod client_msg;
+pub mod pipeline;
+pub mod release;
+pub mod utils;
+pub mod webhook;
@@ -1,419 +1,429 @@
-use crate::{
-    client_msg::ClientData,
-    utils::{log_verify_status, HashPool, IdPool},
+use bin_utils::{
+    interrupt, panic_report,
+    runtime::run_on,
+    server::{
+        AggregationOptions, BoundOptions, ClientKeyOptions, ClientRecordOptions, InputSize,
+        LegacyCsv, MpcCommSnapshot, Options, SessionOptions, TlsOptions, WebhookOptions,
+    },
+    simulation,
 };
-use bin_utils::server::{InputSize, Options};
 use bridge::{
-    client_server::ClientsPool, end_timer, mpc_conn::MpcConnection, start_timer, BlackBox,
+    client_server::ClientSession,
+    mpc_conn::{MpcConnection, Priority},
+    BlackBox,
 };
-use crypto_primitives::{
-    cot::{client::num_additional_ot_needed, server::sample_chi},
-    malpriv::MessageHash,
-    uint::UInt,
-    utils::{batch_xor, iter_arc, Hook},
-    ALICE, BOB,
+use clap::{Arg, ArgMatches};
+use crypto_primitives::{aggregate::total_weight, const_assert, uint::UInt};
+use server_mp::{
+    client_msg::ClientData,
+    pipeline,
+    release::{self, Release, WITHHELD_EXIT_CODE},
+    utils::IdPool,
+    webhook::{RoundSummary, WebhookConfig, WebhookNotifier, Withheld},
 };
-use rayon::prelude::*;
 use sha2::Sha256;
-use std::sync::Arc;
-use tokio::runtime::Runtime;
-use tracing::warn;
-
-mod client_msg;
-mod mpc;
-mod utils;
+use std::iter;
+use tokio::{net::TcpListener, runtime::Handle};
+use tracing::{info, warn};
+use zeroize::Zeroize;
 
 type A = u64;
+// B2A shifts the highest bit of an input within `A`
+const_assert!(A::NUM_BITS >= InputSize::WIDEST.num_bits());
 type C = u128;
 type Hasher = Sha256;
 fn make_hasher() -> Hasher {
     Hasher::default()
 }
 
-async fn main_with_option<I: UInt>(options: Options) {
-    tracing_subscriber::fmt()
-        .pretty()
-        .with_max_level(options.log_level)
-        .init();
-
-    // connect to peer
-    let peer = if !cfg!(feature = "no-comm") {
-        if options.is_bob {
-            // I'm Bob and need a complete address of alice.
-            MpcConnection::new_as_bob(&options.mpc_addr, options.num_mpc_sockets).await
-        } else {
-            // I'm Alice and I need a port number of alice.
-            let mpc_addr =
-                u16::from_str_radix(&options.mpc_addr, 10).expect("invalid mpc_addr as port");
-            MpcConnection::new_as_alice(mpc_addr, options.num_mpc_sockets).await
-        }
-    } else {
-        warn!("no-comm feature is enabled, so no communication with peers");
-        MpcConnection::dummy()
-    };
-
-    let client_data = ClientData::<I, C, Hasher>::fetch(
-        options.is_alice(),
-        options.client_port,
-        options.num_clients,
-        options.gsize,
-        make_hasher,
-    )
-    .await;
-
-    // manage message ids
-    // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
-    let ids = IdPool::build(
-        client_data.num_clients_as_alice(),
-        client_data.num_clients_as_bob(),
-    );
-
-    // manage hashes
-    let mut hashers = HashPool::init(
-        client_data.num_clients_as_alice(),
-        client_data.num_clients_as_bob(),
-        make_hasher,
-    );
-
-    let timer = start_timer!(|| "Exchange seeds");
-    let chi_seed_peer = peer
-        .exchange_message(ids.exchange_chi_seed, &client_data.chi_seed_share)
-        .await
-        .unwrap();
-    let t_seed_peer = peer
-        .exchange_message(ids.exchange_t_seed, &client_data.t_seed_share)
-        .await
-        .unwrap();
-
-    let chi_seed = batch_xor(&client_data.chi_seed_share, &chi_seed_peer);
-    let t_seed = batch_xor(&client_data.t_seed_share, &t_seed_peer);
-    let (t_seeds_a, t_seeds_b) = ClientsPool::split_iter(options.is_alice(), t_seed.into_iter());
-    end_timer!(timer);
-
-    let timer = start_timer!(|| "OT Verify + B2A");
-
-    // first, sample chi that is used to generate all OTs
-    let num_ot = options.gsize * I::NUM_BITS as usize;
-    let num_additional_ot = num_additional_ot_needed(num_ot);
-    let chis = chi_seed
-        .par_iter()
-        .map(|seed| sample_chi(num_ot + num_additional_ot, *seed))
-        .collect::<Vec<_>>();
-    let (chis_a, chis_b) = ClientsPool::split_iter(options.is_alice(), chis.into_iter());
-
-    // OT Verify Alice Receive (Start)
-    let ot_alice_hook = Hook::new();
-    let ot_ba_handles = iter_arc(&client_data.po2_msgs_alice)
-        .zip(ids.otverify_a)
-        .zip(chis_a)
-        .zip(hashers.ot_ba)
-        .map(|(((c_msg, id), chi), mut hasher)| {
-            let peer = peer.clone();
-            tokio::spawn(async move {
-                let result =
-                    mpc::ot_verify_alice::<I, _>(id, &c_msg.cot, Arc::new(chi), peer, &mut hasher)
-                        .await;
-                (result, hasher)
-            })
-        })
-        .collect::<Vec<_>>();
-
-    // OT verify Bob send (Start)
-
-    let ot_bob_hook = Hook::new();
-    let otverify_bob_handles = {
-        let peer = peer.clone();
-        let c_msg = client_data.po2_msgs_bob.clone();
-        tokio::task::spawn_blocking(move || {
-            c_msg
-                .par_iter()
-                .zip(ids.otverify_b)
-                .zip(chis_b)
-                .map(|((c_msg, id), chi)| {
-                    mpc::ot_verify_bob(id, c_msg, &peer, Arc::new(chi), options.gsize)
-                })
-                .collect::<Vec<_>>()
-        })
-    };
+const LEGACY_CSV: LegacyCsv = LegacyCsv {
+    header: "client comm, MPC comm, client phase 1, client phase 2, OT + B2A, Correlation verify, A2S, Hash verify",
+    mpc_comm_sent: false,
+    phases: [
+        Some("client_phase1"),
+        Some("client_phase2"),
+        Some("b2a"),
+        Some("corr_verify"),
+        Some("a2s"),
+        Some("hash_verify"),
+    ],
+};
 
-    // B2A Bob Receive (Start)
-    let b2a_bob_hook = Hook::new();
-    let b2a_bob_handles = iter_arc(&client_data.po2_msgs_bob)
-        .zip(ids.b2a_b)
-        .zip(hashers.b2a_ab)
-        .map(|((c_msg, id), mut hasher)| {
-            let peer = peer.clone();
-            tokio::spawn(async move {
-                let result = mpc::b2a_bob::<_, A, _>(id, &*c_msg, peer, &mut hasher).await;
-                (result, hasher)
-            })
-        })
-        .collect::<Vec<_>>();
+/// Command line options of this server, on top of `Options`.
+struct MpOptions {
+    record: ClientRecordOptions,
+    session: SessionOptions,
+    tls: TlsOptions,
+    keys: ClientKeyOptions,
+    webhook: WebhookOptions,
+    aggregation: AggregationOptions,
+    bound: BoundOptions,
+    /// Withhold the aggregate if fewer clients pass every check.
+    min_included: usize,
+    simulate_server_corr: bool,
+}
 
-    // OT Verify Alice Receive (Complete)
-    let mut qs_per_client = Vec::with_capacity(client_data.num_clients_as_alice());
-    let mut num_verified_success = 0;
-    hashers.ot_ba = Vec::with_capacity(client_data.num_clients_as_alice());
-    for alice_handle in ot_ba_handles {
-        let ((qs, v), hasher) = alice_handle.await.unwrap();
-        qs_per_client.push(qs);
-        num_verified_success += v as usize;
-        hashers.ot_ba.push(hasher);
+impl MpOptions {
+    fn args<'a>() -> impl IntoIterator<Item = Arg<'a>> {
+        ClientRecordOptions::args()
+            .into_iter()
+            .chain(SessionOptions::args())
+            .chain(TlsOptions::args())
+            .chain(ClientKeyOptions::args())
+            .chain(WebhookOptions::args())
+            .chain(AggregationOptions::args())
+            .chain(BoundOptions::args())
+            .chain(iter::once(
+                Arg::new("min_included")
+                    .long("min-included")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("withhold the aggregate if fewer clients pass every check"),
+            ))
+            .chain(cfg!(feature = "simulation-helpers").then(|| {
+                simulation::insecure_arg(
+                    "simulate_server_corr",
+                    "simulate-server-corr",
+                    "INSECURE, benchmarking only: simulate square correlations generated by the servers, with OTs from a seed both know, to model their cost, and ignore the ones sent by clients",
+                )
+            }))
     }
-    log_verify_status(
-        num_verified_success,
-        client_data.num_clients_as_alice(),
-        "OT Verify Alice",
-    );
-    ot_alice_hook.done();
-
-    // B2A Alice Send (Start)
-    let b2a_alice_hook = Hook::new();
-    let b2a_alice_handles = tokio::task::block_in_place(|| {
-        client_data
-            .po2_msgs_alice
-            .par_iter()
-            .zip(qs_per_client)
-            .zip(ids.b2a_a)
-            .map(|((c_msg, qs), id)| {
-                mpc::b2a_alice::<I, A>(id, options.gsize, c_msg.inputs_0, &c_msg.cot, &qs, &peer)
-            })
-            .collect::<Vec<_>>()
-    });
 
-    // B2A Bob Receive (Complete)
-    let mut bob_arith_shares = Vec::with_capacity(client_data.num_clients_as_bob());
-    hashers.b2a_ab = Vec::with_capacity(client_data.num_clients_as_bob());
-    for bob_handle in b2a_bob_handles {
-        let (bob_arith_share, hasher) = bob_handle.await.unwrap();
-        bob_arith_shares.push(bob_arith_share);
-        hashers.b2a_ab.push(hasher);
+    fn parse(matches: &ArgMatches) -> Self {
+        let record = ClientRecordOptions::parse(matches);
+        let session = SessionOptions::parse(matches);
+        session.check_record(&record);
+        Self {
+            record,
+            session,
+            tls: TlsOptions::parse(matches),
+            keys: ClientKeyOptions::parse(matches),
+            webhook: WebhookOptions::parse(matches),
+            aggregation: AggregationOptions::parse(matches),
+            bound: BoundOptions::parse(matches),
+            min_included: matches
+                .value_of("min_included")
+                .unwrap()
+                .parse()
+                .expect("invalid min-included"),
+            simulate_server_corr: cfg!(feature = "simulation-helpers")
+                && matches.is_present("simulate_server_corr"),
+        }
     }
-    b2a_bob_hook.done();
+}
 
-    // B2A Alice Send (Complete)
-    let mut alice_arith_shares = Vec::with_capacity(client_data.num_clients_as_alice());
-    for (s, handle) in b2a_alice_handles {
-        handle.await.unwrap();
-        alice_arith_shares.push(s);
-    }
-    b2a_alice_hook.done();
+fn make_webhook(options: &Options<MpOptions>) -> Option<WebhookNotifier> {
+    let webhook = &options.custom_args.webhook;
+    let url = webhook.url.as_ref()?.parse().expect("invalid webhook url");
+    let role = if options.is_alice() { "alice" } else { "bob" };
+    Some(WebhookNotifier::spawn(WebhookConfig::new(
+        url,
+        webhook.secret.as_bytes().to_vec(),
+        options.is_alice(),
+        webhook.outcomes_from == role,
+        webhook.dead_letter.clone().into(),
+    )))
+}
 
-    // OT Verify Bob Send (Complete)
-    for handle in otverify_bob_handles
-        .await
-        .expect("OT Verify on Bob part failed")
-    {
-        handle.await.unwrap();
+/// Run the rounds of the session. Returns why the aggregate of a round is
+/// withheld, for the first round where it is.
+async fn main_with_option<I: UInt>(
+    options: Options<MpOptions>,
+    client_runtime: Handle,
+) -> Option<Withheld> {
+    tracing_subscriber::fmt()
+        .pretty()
+        .with_max_level(options.log_level)
+        .init();
+    options.log_threads();
+    if options.custom_args.simulate_server_corr {
+        simulation::warn_insecure_flag("simulate-server-corr");
     }
-    ot_bob_hook.done();
-
-    let b2a_time = end_timer!(timer).elapsed().as_secs_f64();
 
-    let timer = start_timer!(|| "SqCorr Verify");
-    assert!(client_data
-        .sqcorr_alice
-        .iter()
-        .all(|corrs| corrs.len() == options.gsize * 2));
-    assert!(client_data
-        .sqcorr_bob
-        .iter()
-        .all(|corrs| corrs.len() == options.gsize * 2));
+    let webhook = make_webhook(&options);
 
-    let (sqcorr_a, sqcorr_b) = ClientsPool::split_iter(options.is_alice(), ids.sqcorr.into_iter());
-    // SqCorr Verify
-    let sqcorr_alice_handles = iter_arc(&client_data.sqcorr_alice)
-        .zip(sqcorr_a)
-        .zip(t_seeds_a)
-        .zip(hashers.sqcorr_ba)
-        .map(|(((corr, id), t_seed), mut hasher)| {
-            let peer = peer.clone();
-            tokio::spawn(async move {
-                let result = mpc::corr_verify::<_, ALICE, Hasher>(
-                    id.0,
-                    id.1,
+    // connect to peer
+    #[cfg(not(feature = "no-comm"))]
+    let peer = if options.is_bob {
+        // I'm Bob and need a complete address of alice.
+        MpcConnection::new_as_bob_from(
+            options.mpc_bind_addr,
+            &options.mpc_addr,
+            options.num_mpc_sockets,
+        )
+        .await
+    } else {
+        // I'm Alice and I need a port number of alice.
+        let mpc_addr = options
+            .mpc_addr
+            .parse::<u16>()
+            .expect("invalid mpc_addr as port");
+        MpcConnection::new_as_alice(options.mpc_bind_addr, mpc_addr, options.num_mpc_sockets).await
+    };
+    #[cfg(feature = "no-comm")]
+    let peer = {
+        warn!("no-comm feature is enabled, so no communication with peers");
+        MpcConnection::dummy()
+    };
+    let peer = peer.with_network_profile(options.mpc_network);
+    let _metrics_server = options.serve_metrics(&peer).await;
+
+    // accepted in the first round, and kept for the others
+    let mut session: Option<ClientSession> = None;
+    let session_options = &options.custom_args.session;
+    let rounds = session_options.rounds;
+    let mut first_withheld = None;
+    let bound = options.custom_args.bound.bound_in::<I, A>();
+    for round in 0..rounds {
+        let comm_before = MpcCommSnapshot::take(&peer);
+        if let Some(webhook) = &webhook {
+            webhook.round_started(options.num_clients, options.gsize);
+        }
+        let round_future = async {
+            let client_data = match &options.custom_args.record.replay_clients {
+                Some(path) => ClientData::<I, C, Hasher>::replay(
+                    options.is_alice(),
+                    options.partition.clone(),
+                    path,
                     options.gsize,
-                    &*corr,
-                    t_seed,
-                    peer,
-                    &mut hasher,
-                )
-                .await;
-                (result, hasher)
-            })
-        })
-        .collect::<Vec<_>>();
-    let sqcorr_bob_handles = iter_arc(&client_data.sqcorr_bob)
-        .zip(sqcorr_b)
-        .zip(t_seeds_b)
-        .zip(hashers.sqcorr_ab)
-        .map(|(((corr, id), t_seed), mut hasher)| {
-            let peer = peer.clone();
-            tokio::spawn(async move {
-                let result = mpc::corr_verify::<_, BOB, Hasher>(
-                    id.0,
-                    id.1,
+                    bound,
+                    make_hasher,
+                    &options.custom_args.keys.client_keys,
+                ),
+                None => {
+                    let clients = match &session {
+                        Some(clients) => clients.clone(),
+                        None => {
+                            let listener = TcpListener::bind(options.client_listen_addr())
+                                .await
+                                .unwrap();
+                            let accept = ClientSession::accept_with_tls(
+                                options.is_alice(),
+                                options.partition.clone(),
+                                listener,
+                                options.num_clients,
+                                options.client_token,
+                                options.custom_args.tls.server_tls.clone(),
+                            );
+                            let accept = async move { accept.await.with_rounds(rounds) };
+                            session
+                                .insert(run_on(&client_runtime, accept).await)
+                                .clone()
+                        },
+                    };
+                    let gsize = options.gsize;
+                    let dump_path = options.custom_args.record.dump_clients.clone();
+                    let keys = options.custom_args.keys.client_keys.clone();
+                    let fetch = async move {
+                        ClientData::<I, C, Hasher>::fetch_round(
+                            &clients,
+                            round,
+                            gsize,
+                            bound,
+                            make_hasher,
+                            &keys,
+                            dump_path,
+                        )
+                        .await
+                    };
+                    run_on(&client_runtime, fetch).await
+                },
+            }
+            .with_session(options.session);
+            options
+                .metrics
+                .set_clients_connected(client_data.uids.len() + client_data.failed.len());
+
+            // refuse to run the round if a client has no weight
+            let weights = options
+                .custom_args
+                .aggregation
+                .weights_of(&client_data.uids);
+
+            let output = options
+                .client_gate()
+                .scope(pipeline::run_round::<I, A, C, _, _>(
+                    round,
                     options.gsize,
-                    &*corr,
-                    t_seed,
-                    peer,
-                    &mut hasher,
-                )
+                    options.sec_param,
+                    options.is_alice(),
+                    &client_data,
+                    &peer,
+                    make_hasher,
+                    options.custom_args.simulate_server_corr,
+                ))
                 .await;
-                (result, hasher)
-            })
-        })
-        .collect::<Vec<_>>();
+            if !output.failed_clients.is_empty() {
+                warn!(
+                    "{} client(s) failed and are left out: {:?}",
+                    output.failed_clients.len(),
+                    output
+                        .failed_clients
+                        .iter()
+                        .map(|uid| uid.id)
+                        .collect::<Vec<_>>()
+                );
+            }
+
+            // the same ids as in the pipeline
+            let ids = IdPool::build_for_round(
+                round,
+                client_data.num_clients_as_alice(),
+                client_data.num_clients_as_bob(),
+            );
+            let release = release::release(
+                &output,
+                weights.as_deref(),
+                options.custom_args.min_included,
+                ids.exchange_release,
+                &peer,
+            )
+            .await
+            .expect("cannot agree on the release of the aggregate");
+            (client_data, weights, output, release)
+        };
+        let (client_data, weights, mut output, release) =
+            match interrupt::run_interruptible(round_future, &peer).await {
+                Ok(done) => done,
+                Err(interrupted) => {
+                    let mut report = options.new_report("server-mp");
+                    report.round = session_options.report_round(round);
+                    options.exit_interrupted(report, interrupted, &peer, &LEGACY_CSV)
+                },
+            };
+        let withheld = match release {
+            Release::Released(aggregate) => {
+                match &weights {
+                    Some(weights) => {
+                        let included = output.included_weights(weights);
+                        info!(
+                            "released my share of the weighted sum of {} clients, of total weight {} (the mean is the aggregate divided by it)",
+                            included.len(),
+                            total_weight(&included)
+                        );
+                    },
+                    None => info!(
+                        "released my share of the aggregate of {} clients",
+                        output.num_included()
+                    ),
+                }
+                aggregate.drop_into_black_box();
+                None
+            },
+            Release::Withheld {
+                num_included,
+                min_included,
+            } => {
+                warn!(
+                    "aggregate withheld: {} client(s) included, below the threshold of {}",
+                    num_included, min_included
+                );
+                Some(Withheld::BelowThreshold {
+                    num_included,
+                    min_included,
+                })
+            },
+        };
+
+        let simulation_helpers_used = simulation::warn_if_constructed();
+        if let Some(webhook) = &webhook {
+            let mut uids = [&client_data.uids[..], &client_data.failed[..]].concat();
+            uids.sort_unstable();
+            webhook.client_outcomes(&uids, &output.failed_clients, withheld.is_some());
+            webhook.round_completed(RoundSummary {
+                num_clients: uids.len(),
+                num_accepted: uids.len() - output.failed_clients.len(),
+                num_failed: output.failed_clients.len(),
+                b2a_time: output.b2a_time,
+                corr_verify_time: output.corr_verify_time,
+                a2s_time: output.a2s_time,
+                hash_verify_time: output.hash_verify_time,
+                withheld,
+                simulation_helpers_used,
+            });
+        }
+        // the shares of the inputs and their squares are not needed any more
+        output.arith_shares.zeroize();
+        output.square_shares.zeroize();
+
+        info!(
+            "MPC send queue wait: control: {}; bulk: {}",
+            peer.queue_wait_stats(Priority::Control),
+            peer.queue_wait_stats(Priority::Bulk)
+        );
+
+        let metrics = peer.metrics().fold();
+        for (phase, sent, received) in &metrics.phases {
+            info!(
+                "MPC bytes in {}: sent {}, received {}",
+                phase, sent, received
+            );
+        }
 
-    let mut num_verified_success = 0;
-    hashers.sqcorr_ba = Vec::with_capacity(client_data.num_clients_as_alice());
-    hashers.sqcorr_ab = Vec::with_capacity(client_data.num_clients_as_bob());
-    for sqcorr_handle in sqcorr_alice_handles {
-        let (result, hasher) = sqcorr_handle.await.unwrap();
-        num_verified_success += if result == options.gsize { 1 } else { 0 };
-        hashers.sqcorr_ba.push(hasher);
+        let mut report = options.new_report("server-mp");
+        report.round = session_options.report_round(round);
+        report.client_comm = client_data.comm_alice + client_data.comm_bob;
+        comm_before.report_since(&mut report, &peer);
+        report
+            .phase("client_phase1", client_data.phase1_time)
+            .phase("client_phase2", client_data.phase2_time)
+            .phase("b2a", output.b2a_time)
+            .phase("corr_verify", output.corr_verify_time)
+            .phase("a2s", output.a2s_time)
+            .phase("hash_verify", output.hash_verify_time)
+            .verified("ot", output.num_ot_verified)
+            .verified("sqcorr", output.num_sqcorr_verified)
+            .verified("b2a_hash", output.num_b2a_hash_verified)
+            .verified("ot_hash", output.num_ot_hash_verified);
+        if let Some(num_a2s_hash_verified) = output.num_a2s_hash_verified {
+            report.verified("a2s_hash", num_a2s_hash_verified);
+        }
+        if let Some(num_sqcorr_hash_verified) = output.num_sqcorr_hash_verified {
+            report.verified("sqcorr_hash", num_sqcorr_hash_verified);
+        }
+        if let Some(num_bound_verified) = output.num_bound_verified {
+            report.verified("bound", num_bound_verified);
+        }
+        if options.custom_args.simulate_server_corr {
+            report.phase("sqcorr_gen", output.sqcorr_gen_time);
+        }
+        options.emit_report(report, &LEGACY_CSV);
+        first_withheld = first_withheld.or(withheld);
     }
-    for sqcorr_handle in sqcorr_bob_handles {
-        let (result, hasher) = sqcorr_handle.await.unwrap();
-        num_verified_success += if result == options.gsize { 1 } else { 0 };
-        hashers.sqcorr_ab.push(hasher);
+    if let Some(webhook) = webhook {
+        webhook.finish().await;
     }
-
-    log_verify_status(
-        num_verified_success,
-        client_data.num_clients(),
-        "SqCorr Verify",
-    );
-
-    let corr_verify_time = end_timer!(timer).elapsed().as_secs_f64();
-
-    let timer = start_timer!(|| "A2S");
-    // A2S
-    let arith_shares = ClientsPool::merge_msg(
-        options.is_alice(),
-        alice_arith_shares.into_iter(),
-        bob_arith_shares.into_iter(),
-    );
-    let sqcorr = ClientsPool::merge_msg(
-        options.is_alice(),
-        iter_arc(&client_data.sqcorr_alice),
-        iter_arc(&client_data.sqcorr_bob),
-    );
-    let a2s_handles = sqcorr
-        .into_iter()
-        .zip(arith_shares)
-        .zip(ids.a2s)
-        .zip(hashers.a2s)
-        .map(|(((corr, xs), id), mut hasher)| {
-            let peer = peer.clone();
-            tokio::spawn(async move {
-                let result = if !options.is_bob {
-                    mpc::a2s::<A, C, _, { ALICE }>(id, &xs, &*corr, peer, &mut hasher).await
-                } else {
-                    mpc::a2s::<_, _, _, { BOB }>(id, &xs, &*corr, peer, &mut hasher).await
-                };
-                (result, hasher)
-            })
-        })
-        .collect::<Vec<_>>();
-
-    hashers.a2s = Vec::with_capacity(client_data.num_clients());
-    for handle in a2s_handles {
-        let (result, hasher) = handle.await.unwrap();
-        hashers.a2s.push(hasher);
-        result.drop_into_black_box()
-    }
-
-    let a2s_time = end_timer!(timer).elapsed().as_secs_f64();
-
-    let timer = start_timer!(|| "Hash Verification");
-    // B2A
-    assert_eq!(client_data.hash_b2a_ab.len(), hashers.b2a_ab.len());
-    let num_verified = client_data
-        .hash_b2a_ab
-        .iter()
-        .zip(hashers.b2a_ab)
-        .map(|(expected, hasher)| {
-            let actual = hasher.digest();
-            (expected == &actual) as usize
-        })
-        .sum::<usize>();
-    log_verify_status(
-        num_verified,
-        client_data.num_clients_as_bob(),
-        "B2A Hash AB",
-    );
-    // A2S
-    let num_verified = client_data
-        .hash_a2s
-        .iter()
-        .zip(hashers.a2s)
-        .map(|(expected, hasher)| {
-            let actual = hasher.digest();
-            (expected == &actual) as usize
-        })
-        .sum::<usize>();
-    log_verify_status(num_verified, client_data.num_clients(), "A2S Hash");
-    // OT Verify
-    let num_verified = client_data
-        .hash_ot_ba
-        .iter()
-        .zip(hashers.ot_ba)
-        .map(|(expected, hasher)| {
-            let actual = hasher.digest();
-            (expected == &actual) as usize
-        })
-        .sum::<usize>();
-    log_verify_status(
-        num_verified,
-        client_data.num_clients_as_alice(),
-        "OT Verify Hash",
-    );
-    // SqCorr Verify
-    assert_eq!(client_data.hash_sqcorr_ba.len(), hashers.sqcorr_ba.len());
-    assert_eq!(client_data.hash_sqcorr_ab.len(), hashers.sqcorr_ab.len());
-    let num_sqcorr_verified = client_data
-        .hash_sqcorr_ba
-        .iter()
-        .chain(client_data.hash_sqcorr_ab.iter())
-        .zip(
-            hashers
-                .sqcorr_ba
-                .into_iter()
-                .chain(hashers.sqcorr_ab.into_iter()),
-        )
-        .map(|(expected, hasher)| {
-            let actual = hasher.digest();
-            (expected == &actual) as usize
-        })
-        .sum::<usize>();
-
-    log_verify_status(
-        num_sqcorr_verified,
-        client_data.num_clients(),
-        "SqCorr Verify Hash",
-    );
-    let hash_verify_time = end_timer!(timer).elapsed().as_secs_f64();
-
-    println!("client comm, MPC comm, client phase 1, client phase 2, OT + B2A, Correlation verify, A2S, Hash verify");
-    println!(
-        "{}, {}, {}, {}, {}, {}, {}, {}",
-        client_data.comm_alice + client_data.comm_bob,
-        peer.num_bytes_received(),
-        client_data.phase1_time,
-        client_data.phase2_time,
-        b2a_time,
-        corr_verify_time,
-        a2s_time,
-        hash_verify_time
-    );
+    first_withheld
 }
 
 pub fn main() {
-    let runtime = Runtime::new().unwrap();
-    let options = Options::load_from_args("ELSA MP Server");
-    match options.input_size {
-        InputSize::U8 => runtime.block_on(main_with_option::<u8>(options)),
-        InputSize::U32 => runtime.block_on(main_with_option::<u32>(options)),
-    }
+    panic_report::run_main(|| {
+        let options =
+            Options::load_from_args_custom("ELSA MP Server", MpOptions::args(), MpOptions::parse);
+        options.install_compute_pool().unwrap();
+        if options.run_self_test() {
+            return;
+        }
+        options.check_ring_budget::<A>(
+            options
+                .custom_args
+                .aggregation
+                .max_total_weight(options.num_clients),
+        );
+        let runtimes = options.runtimes().unwrap();
+        let withheld = match options.input_size {
+            InputSize::U8 => {
+                runtimes.block_on(main_with_option::<u8>(options, runtimes.client().clone()))
+            },
+            InputSize::U32 => {
+                runtimes.block_on(main_with_option::<u32>(options, runtimes.client().clone()))
+            },
+            InputSize::U64 => {
+                runtimes.block_on(main_with_option::<u64>(options, runtimes.client().clone()))
+            },
+        };
+        if withheld.is_some() {
+            std::process::exit(WITHHELD_EXIT_CODE);
+        }
+    })
 }
@@ -0,0 +1,2065 @@
+//! Server side of the MP protocol after the client messages are received,
+//! from seed exchange to transcript hash verification. If the clients are
+//! fetched with a bound, their inputs are checked against it after A2S, and
+//! the transcript of the check is hashed with the one of A2S.
+//!
+//! A client that sent different share commitments to the two servers is
+//! failed on both before B2A, and one whose upload is not signed by its key,
+//! with `signed-uploads`, on the server that received it. A malformed message, or a panic while processing
+//! it, only fails the client that sent it. Whenever I cannot compute a message to the peer for a failed
+//! client, a placeholder is sent instead, so the peer fails the client as well.
+//! After B2A, the servers exchange the clients that failed on either side, and
+//! skip them in all later phases.
+
+use crate::{
+    client_msg::ClientData,
+    utils::{log_verify_status, IdPool},
+};
+use bin_utils::{
+    hash_pool::{HashPhase, HashPool},
+    panic_report::{self, expect_joined},
+    runtime::{block_on_compute, spawn_gated},
+};
+use bridge::{
+    end_timer,
+    id_tracker::{ExchangeId, RecvId, SendId},
+    mpc_conn::MpcConnection,
+    start_timer,
+    tcp_bridge::ClientID,
+    BridgeError,
+};
+use bytes::Bytes;
+use crypto_primitives::{
+    a2s::num_squared,
+    aggregate::weighted_sum,
+    cot::{dims::ProtocolDims, server::Chi},
+    malpriv::MessageHash,
+    square_corr::{CorrError, SquareCorrShare},
+    uint::UInt,
+    utils::{batch_sum, batch_xor, iter_arc, Hook},
+    ALICE, BOB,
+};
+use rayon::prelude::*;
+use serialize::Communicate;
+use server_protocol::{self as mpc, ClientFailure};
+use std::{
+    future::Future,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::task::{JoinError, JoinHandle};
+use tracing::warn;
+use zeroize::Zeroizing;
+
+/// How long to wait for the seeds of the peer. The servers are in step after
+/// the commitment exchange, so a missing seed message is a bug rather than a
+/// slow peer, and should fail the round instead of hanging it.
+const SEED_EXCHANGE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Seed of the ROTs of the square correlations of client `uid` is
+/// `ROT_SEED + uid`, with `simulate_server_corr`. See `ROTsForSender::from_shared_seed`.
+#[cfg(feature = "simulation-helpers")]
+const ROT_SEED: u64 = 765432;
+
+/// Square correlations of the clients of one pool, in pool order.
+type PoolSqcorr<C> = Arc<[Result<Vec<SquareCorrShare<C>>, CorrError>]>;
+
+/// Shares held by one server, and verification results. Per-client vectors
+/// are in the order of the uids of the `ClientData`, and are `None` for
+/// failed clients.
+pub struct PipelineOutput<A: UInt> {
+    /// B2A output: arithmetic shares of the inputs.
+    pub arith_shares: Vec<Option<Vec<A>>>,
+    /// A2S output: arithmetic shares of the squared inputs.
+    pub square_shares: Vec<Option<Vec<A>>>,
+    /// Clients that failed on either server, in uid order, including the ones
+    /// whose upload cannot be deserialized. They are not counted by the
+    /// verification results below.
+    pub failed_clients: Vec<ClientID>,
+    /// Number of clients where I'm OT sender whose OTs are verified.
+    pub num_ot_verified: usize,
+    /// Number of clients whose square correlations are verified.
+    pub num_sqcorr_verified: usize,
+
+    /// Number of clients where I'm OT receiver whose B2A transcript matches.
+    pub num_b2a_hash_verified: usize,
+    /// Number of clients whose A2S transcript matches, or `None` with
+    /// `simulate_server_corr`, where the transcript of the client cannot match.
+    pub num_a2s_hash_verified: Option<usize>,
+    /// Number of clients where I'm OT sender whose OT verification transcript
+    /// matches.
+    pub num_ot_hash_verified: usize,
+    /// Number of clients whose square correlation verification transcript
+    /// matches, or `None` with `simulate_server_corr`.
+    pub num_sqcorr_hash_verified: Option<usize>,
+    /// Number of clients whose inputs are all within the bound, if the
+    /// clients are fetched with one. The others are failed on both servers.
+    pub num_bound_verified: Option<usize>,
+
+    /// Time to generate the square correlations, zero unless they are
+    /// generated by the servers.
+    pub sqcorr_gen_time: f64,
+    pub b2a_time: f64,
+    pub corr_verify_time: f64,
+    pub a2s_time: f64,
+    pub hash_verify_time: f64,
+}
+
+impl<A: UInt> PipelineOutput<A> {
+    /// My share of the sum of the inputs of all clients that did not fail.
+    pub fn aggregate(&self) -> Vec<A> {
+        batch_sum(self.arith_shares.iter().flatten())
+    }
+
+    /// Number of clients that did not fail, which is the same on both
+    /// servers.
+    pub fn num_included(&self) -> usize {
+        self.arith_shares.iter().flatten().count()
+    }
+
+    /// The output on all clients of `left_out`, which is in uid order, from
+    /// the output on the ones that are not left out. `failed_clients` failed
+    /// before the pipeline on either server, and are in uid order.
+    fn with_left_out(mut self, left_out: &[bool], failed_clients: Vec<ClientID>) -> Self {
+        let with_left_out = |shares: Vec<Option<Vec<A>>>| {
+            let mut shares = shares.into_iter();
+            let all = left_out
+                .iter()
+                .map(|left_out| {
+                    if *left_out {
+                        None
+                    } else {
+                        shares.next().unwrap()
+                    }
+                })
+                .collect::<Vec<_>>();
+            assert!(shares.next().is_none());
+            all
+        };
+        self.arith_shares = with_left_out(mem::take(&mut self.arith_shares));
+        self.square_shares = with_left_out(mem::take(&mut self.square_shares));
+        self.failed_clients.extend(failed_clients);
+        self.failed_clients.sort_unstable();
+        self
+    }
+
+    /// Shares and weights of the clients that did not fail and have a
+    /// nonzero weight. `weights` are in uid order, like `arith_shares`.
+    fn weighted_shares<'a>(
+        &'a self,
+        weights: &'a [u64],
+    ) -> impl Iterator<Item = (&'a Vec<A>, u64)> + 'a {
+        assert_eq!(weights.len(), self.arith_shares.len());
+        self.arith_shares
+            .iter()
+            .zip(weights)
+            .filter(|(_, weight)| **weight != 0)
+            .filter_map(|(share, weight)| Some((share.as_ref()?, *weight)))
+    }
+
+    /// My share of the weighted sum of the inputs of all clients that did not
+    /// fail.
+    pub fn weighted_aggregate(&self, weights: &[u64]) -> Vec<A> {
+        weighted_sum(self.weighted_shares(weights))
+    }
+
+    /// Weights of the clients in `weighted_aggregate`, so their number and
+    /// total are the same on both servers.
+    pub fn included_weights(&self, weights: &[u64]) -> Vec<u64> {
+        self.weighted_shares(weights).map(|(_, w)| w).collect()
+    }
+}
+
+/// Mark the client as failed. Only the first failure of a client is logged.
+fn fail(uid: ClientID, phase: &str, failure: ClientFailure, failed: &mut bool) {
+    if !*failed {
+        warn!("[{}] client {} failed: {}", phase, uid.id, failure);
+        *failed = true;
+    }
+}
+
+/// Number of clients that are not failed and whose hash verified.
+fn count_verified(verified: Vec<bool>, failed: &[bool]) -> usize {
+    assert_eq!(verified.len(), failed.len());
+    verified
+        .into_iter()
+        .zip(failed)
+        .filter(|(verified, failed)| *verified && !**failed)
+        .count()
+}
+
+/// Square correlations of a client that is not failed, so they were expanded.
+fn checked_sqcorr<C: UInt>(
+    corrs: &Result<Vec<SquareCorrShare<C>>, CorrError>,
+) -> &[SquareCorrShare<C>] {
+    corrs
+        .as_deref()
+        .expect("clients with bad square correlations are failed")
+}
+
+/// Spawn the work of client `uid`, so that its panics are attributed to it.
+/// It starts once the `ClientGate` of the caller has room for it.
+fn spawn_client<T, F>(uid: ClientID, task: F) -> JoinHandle<T>
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    spawn_gated(panic_report::scope_client_uid(uid.id, task))
+}
+
+/// Output of a per-client task returning its hasher. A panic fails the
+/// client, and a fresh hasher takes the place of the lost one.
+fn join_client<T, H>(
+    joined: Result<(Result<T, ClientFailure>, H), JoinError>,
+    make_hasher: impl Fn() -> H,
+) -> (Result<T, ClientFailure>, H) {
+    match joined {
+        Ok(output) => output,
+        Err(e) if e.is_panic() => (Err(ClientFailure::Panicked), make_hasher()),
+        Err(e) => panic!("task did not complete: {}", e),
+    }
+}
+
+/// Run the work of client `uid` on the compute pool. A panic fails the
+/// client.
+fn catch_client<T>(
+    uid: ClientID,
+    work: impl FnOnce() -> Result<T, ClientFailure>,
+) -> Result<T, ClientFailure> {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        panic_report::with_client_uid(uid.id, work)
+    }))
+    .unwrap_or(Err(ClientFailure::Panicked))
+}
+
+/// My message of each client for `mpc::exchange_batch`. A client that failed
+/// on my side gets an empty placeholder instead, so the peer fails it as
+/// well.
+fn batch_msgs<T>(rounds: &[Result<T, ClientFailure>], msg: impl Fn(&T) -> Bytes) -> Vec<Bytes> {
+    rounds
+        .iter()
+        .map(|round| round.as_ref().map_or_else(|_| Bytes::new(), &msg))
+        .collect()
+}
+
+/// A client in SqCorr Verify, where I play `party`. `idx` is its index in
+/// the pool of `phase`.
+struct SqcorrClient<'a, C: UInt, H> {
+    uid: ClientID,
+    phase: HashPhase,
+    party: bool,
+    idx: usize,
+    ids: (ExchangeId, ExchangeId),
+    corr: &'a [SquareCorrShare<C>],
+    t_seed: u64,
+    hasher: H,
+}
+
+/// A client in A2S. `idx` is its index in uid order.
+struct A2sClient<'a, A: UInt, C: UInt, H> {
+    uid: ClientID,
+    idx: usize,
+    id: ExchangeId,
+    xs: &'a [A],
+    corr: &'a [SquareCorrShare<C>],
+    hasher: H,
+}
+
+/// Square correlations of the clients where I'm OT sender and OT receiver,
+/// generated with the peer in place of the ones sent by the clients. A client
+/// whose generation fails is failed, and gets no correlations. Not secure,
+/// see `square_corr::server_gen`.
+#[cfg(feature = "simulation-helpers")]
+async fn server_sqcorr<I, C, H>(
+    num_squared: usize,
+    client_data: &ClientData<I, C, H>,
+    peer: &MpcConnection,
+    ids_a: Vec<(SendId, RecvId, SendId)>,
+    ids_b: Vec<(RecvId, SendId, RecvId)>,
+    failed_a: &mut [bool],
+    failed_b: &mut [bool],
+) -> (PoolSqcorr<C>, PoolSqcorr<C>)
+where
+    I: UInt,
+    C: UInt,
+    H: MessageHash,
+{
+    use crypto_primitives::cot::rot::server_generated::{ROTsForReceiver, ROTsForSender};
+
+    let count = 2 * num_squared;
+    let num_rots = count * C::NUM_BITS;
+    let rot_seed = |uid: &ClientID| ROT_SEED + uid.id;
+
+    // both directions exchange messages, so the tasks are not gated, see
+    // `spawn_gated`
+    let alice_handles = client_data
+        .uids_alice
+        .iter()
+        .zip(ids_a)
+        .map(|(&uid, ids)| {
+            let (peer, rot_seed) = (peer.clone(), rot_seed(&uid));
+            tokio::spawn(panic_report::scope_client_uid(uid.id, async move {
+                let rots = ROTsForSender::from_shared_seed(rot_seed, num_rots);
+                Ok(mpc::server_sqcorr_alice::<C>(ids, count, rots, peer).await)
+            }))
+        })
+        .collect::<Vec<_>>();
+    let bob_handles = client_data
+        .uids_bob
+        .iter()
+        .zip(ids_b)
+        .map(|(&uid, ids)| {
+            let (peer, rot_seed) = (peer.clone(), rot_seed(&uid));
+            tokio::spawn(panic_report::scope_client_uid(uid.id, async move {
+                let rots = ROTsForReceiver::from_shared_seed(rot_seed, num_rots);
+                mpc::server_sqcorr_bob::<C>(ids, count, rots, peer).await
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    let mut sqcorr = (Vec::new(), Vec::new());
+    for (handles, uids, failed, sqcorr) in [
+        (
+            alice_handles,
+            &client_data.uids_alice,
+            failed_a,
+            &mut sqcorr.0,
+        ),
+        (bob_handles, &client_data.uids_bob, failed_b, &mut sqcorr.1),
+    ] {
+        for ((handle, &uid), failed) in handles.into_iter().zip(uids).zip(failed) {
+            let result = match handle.await {
+                Ok(result) => result,
+                Err(e) if e.is_panic() => Err(ClientFailure::Panicked),
+                Err(e) => panic!("task did not complete: {}", e),
+            };
+            sqcorr.push(Ok(result.unwrap_or_else(|e| {
+                fail(uid, "SqCorr Gen", e, failed);
+                Vec::new()
+            })));
+        }
+    }
+    (sqcorr.0.into(), sqcorr.1.into())
+}
+
+#[cfg(not(feature = "simulation-helpers"))]
+async fn server_sqcorr<I, C, H>(
+    _num_squared: usize,
+    _client_data: &ClientData<I, C, H>,
+    _peer: &MpcConnection,
+    _ids_a: Vec<(SendId, RecvId, SendId)>,
+    _ids_b: Vec<(RecvId, SendId, RecvId)>,
+    _failed_a: &mut [bool],
+    _failed_b: &mut [bool],
+) -> (PoolSqcorr<C>, PoolSqcorr<C>)
+where
+    I: UInt,
+    C: UInt,
+    H: MessageHash,
+{
+    unreachable!("the servers only generate square correlations with simulation-helpers")
+}
+
+/// Run the work of client `uid` that ends by sending messages `msg_ids`. A
+/// panic fails the client, and placeholders are sent instead.
+fn catch_client_send<T>(
+    uid: ClientID,
+    msg_ids: &[SendId],
+    peer: &MpcConnection,
+    work: impl FnOnce() -> Result<T, ClientFailure>,
+) -> Result<T, ClientFailure> {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        panic_report::with_client_uid(uid.id, work)
+    }))
+    .unwrap_or_else(|_| {
+        for &id in msg_ids {
+            mpc::send_placeholder(id, peer);
+        }
+        Err(ClientFailure::Panicked)
+    })
+}
+
+/// Run the protocol with `peer` on received client messages. `is_alice` is
+/// the global role of this server.
+pub async fn run<I, A, C, H, F>(
+    gsize: usize,
+    sec_param: usize,
+    is_alice: bool,
+    client_data: &ClientData<I, C, H>,
+    peer: &MpcConnection,
+    make_hasher: F,
+    simulate_server_corr: bool,
+) -> PipelineOutput<A>
+where
+    I: UInt,
+    A: UInt,
+    C: UInt,
+    H: MessageHash<Output = Vec<u8>> + Send + 'static,
+    F: Fn() -> H,
+{
+    run_round(
+        0,
+        gsize,
+        sec_param,
+        is_alice,
+        client_data,
+        peer,
+        make_hasher,
+        simulate_server_corr,
+    )
+    .await
+}
+
+/// Same as `run`, for round `round` of a session over the same `peer`. The
+/// hashes and the verification state are built anew for each round.
+#[allow(clippy::too_many_arguments)]
+pub async fn run_round<I, A, C, H, F>(
+    round: u64,
+    gsize: usize,
+    sec_param: usize,
+    is_alice: bool,
+    client_data: &ClientData<I, C, H>,
+    peer: &MpcConnection,
+    make_hasher: F,
+    simulate_server_corr: bool,
+) -> PipelineOutput<A>
+where
+    I: UInt,
+    A: UInt,
+    C: UInt,
+    H: MessageHash<Output = Vec<u8>> + Send + 'static,
+    F: Fn() -> H,
+{
+    // both servers leave out the clients that failed on either, before the
+    // message ids of the others are allocated
+    let (failed_on_peer, failed_clients) = mpc::exchange_failed_uids(
+        IdPool::exchange_failed(round),
+        &client_data.uids,
+        &client_data.failed,
+        peer,
+    )
+    .await;
+    let parsed_data;
+    let parsed = if failed_on_peer.contains(&true) {
+        for (uid, _) in client_data
+            .uids
+            .iter()
+            .zip(&failed_on_peer)
+            .filter(|(_, f)| **f)
+        {
+            warn!("client {} failed: {}", uid.id, ClientFailure::FailedOnPeer);
+        }
+        parsed_data = client_data.without_clients(is_alice, &failed_on_peer);
+        &parsed_data
+    } else {
+        client_data
+    };
+    run_parsed(
+        round,
+        gsize,
+        sec_param,
+        is_alice,
+        parsed,
+        peer,
+        make_hasher,
+        simulate_server_corr,
+    )
+    .await
+    .with_left_out(&failed_on_peer, failed_clients)
+}
+
+/// Same as `run_round`, on clients that are parsed on both servers.
+#[allow(clippy::too_many_arguments)]
+async fn run_parsed<I, A, C, H, F>(
+    round: u64,
+    gsize: usize,
+    sec_param: usize,
+    is_alice: bool,
+    client_data: &ClientData<I, C, H>,
+    peer: &MpcConnection,
+    make_hasher: F,
+    simulate_server_corr: bool,
+) -> PipelineOutput<A>
+where
+    I: UInt,
+    A: UInt,
+    C: UInt,
+    H: MessageHash<Output = Vec<u8>> + Send + 'static,
+    F: Fn() -> H,
+{
+    let uids = &client_data.uids;
+
+    // manage message ids
+    // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
+    let ids = IdPool::build_for_round(
+        round,
+        client_data.num_clients_as_alice(),
+        client_data.num_clients_as_bob(),
+    );
+
+    // manage hashes
+    let mut hashers = HashPool::init(
+        [
+            (HashPhase::OtBA, client_data.num_clients_as_alice()),
+            (HashPhase::B2aAB, client_data.num_clients_as_bob()),
+            (HashPhase::SqcorrBA, client_data.num_clients_as_alice()),
+            (HashPhase::SqcorrAB, client_data.num_clients_as_bob()),
+            (HashPhase::A2s, client_data.num_clients()),
+        ],
+        &make_hasher,
+    );
+
+    let mut failed_a = vec![false; client_data.num_clients_as_alice()];
+    let mut failed_b = vec![false; client_data.num_clients_as_bob()];
+
+    // only I see the upload, so the peer fails the client from my
+    // placeholders or in the failure exchange
+    let (bad_signatures_a, bad_signatures_b) =
+        client_data.split_by_uid(is_alice, client_data.bad_signatures.iter().copied());
+    for ((bad_signature, &uid), failed) in bad_signatures_a
+        .into_iter()
+        .chain(bad_signatures_b)
+        .zip(client_data.uids_alice.iter().chain(&client_data.uids_bob))
+        .zip(failed_a.iter_mut().chain(&mut failed_b))
+    {
+        if bad_signature {
+            fail(uid, "Signature", ClientFailure::BadSignature, failed);
+        }
+    }
+
+    // both servers get the same rejected clients, so they are failed on both
+    // without exchanging failures
+    let rejected = mpc::exchange_commitments(
+        ids.exchange_commitments,
+        client_data.session,
+        &client_data.uids,
+        &client_data.commitments,
+        &client_data.held_shares(is_alice),
+        peer,
+    )
+    .await;
+    let (rejected_a, rejected_b) = client_data.split_by_uid(is_alice, rejected);
+    for ((rejected, &uid), failed) in rejected_a
+        .into_iter()
+        .chain(rejected_b)
+        .zip(client_data.uids_alice.iter().chain(&client_data.uids_bob))
+        .zip(failed_a.iter_mut().chain(&mut failed_b))
+    {
+        if rejected {
+            fail(
+                uid,
+                "Exchange commitments",
+                ClientFailure::CommitmentMismatch,
+                failed,
+            );
+        }
+    }
+
+    let timer = start_timer!(|| "Exchange seeds");
+    peer.metrics().set_phase("Exchange seeds");
+    let chi_seed_peer = peer
+        .exchange_message_timeout(
+            ids.exchange_chi_seed,
+            &client_data.chi_seed_share,
+            SEED_EXCHANGE_TIMEOUT,
+        )
+        .await
+        .expect("exchange chi seeds");
+    let t_seed_peer = peer
+        .exchange_message_timeout(
+            ids.exchange_t_seed,
+            &client_data.t_seed_share,
+            SEED_EXCHANGE_TIMEOUT,
+        )
+        .await
+        .expect("exchange t seeds");
+
+    let chi_seed = batch_xor(&client_data.chi_seed_share, &chi_seed_peer);
+    let t_seed = batch_xor(&client_data.t_seed_share, &t_seed_peer);
+    let (t_seeds_a, t_seeds_b) = client_data.split_by_uid(is_alice, t_seed);
+    end_timer!(timer);
+
+    // the inputs, and the witness bits of the bound check if any
+    let num_squared = num_squared(gsize, client_data.bound);
+
+    // the correlations sent by the clients are ignored with
+    // `simulate_server_corr`, which simulates the servers generating them
+    let timer = start_timer!(|| "SqCorr Gen");
+    let (sqcorr_alice, sqcorr_bob) = if simulate_server_corr {
+        peer.metrics().set_phase("SqCorr Gen");
+        server_sqcorr(
+            num_squared,
+            client_data,
+            peer,
+            ids.sqcorr_gen_a,
+            ids.sqcorr_gen_b,
+            &mut failed_a,
+            &mut failed_b,
+        )
+        .await
+    } else {
+        (
+            client_data.sqcorr_alice.clone(),
+            client_data.sqcorr_bob.clone(),
+        )
+    };
+    let sqcorr_gen_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let timer = start_timer!(|| "OT Verify + B2A");
+    peer.metrics().set_phase("OT Verify + B2A");
+
+    // chi is generated lazily from each client's seed while verifying, so it
+    // is never materialized for all clients at once
+    let dims = ProtocolDims::new::<I>(gsize, sec_param).expect("invalid protocol dimensions");
+    let chis = chi_seed.iter().map(|seed| Chi::Seeded {
+        num_ots: dims.num_total(),
+        seed: *seed,
+    });
+    let (chis_a, chis_b) = client_data.split_by_uid(is_alice, chis);
+
+    // OT Verify Alice Receive (Start)
+    let ot_alice_hook = Hook::new_named("OT Verify Alice");
+    let ot_ba_handles = iter_arc(&client_data.po2_msgs_alice)
+        .zip(&client_data.uids_alice)
+        .zip(ids.otverify_a)
+        .zip(chis_a)
+        .enumerate()
+        .map(|(idx, (((c_msg, &uid), id), chi))| {
+            let mut hasher = hashers.take(HashPhase::OtBA, idx);
+            let peer = peer.clone();
+            spawn_client(uid, async move {
+                let result =
+                    mpc::ot_verify_alice(id, &c_msg.cot, chi, sec_param, peer, &mut hasher).await;
+                (result, hasher)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // OT verify Bob send (Start)
+
+    let ot_bob_hook = Hook::new_named("OT Verify Bob");
+    let otverify_bob_handles = {
+        let peer = peer.clone();
+        let c_msg = client_data.po2_msgs_bob.clone();
+        let uids = client_data.uids_bob.clone();
+        tokio::task::spawn_blocking(move || {
+            c_msg
+                .par_iter()
+                .zip(uids)
+                .zip(ids.otverify_b)
+                .zip(chis_b)
+                .map(|(((c_msg, uid), id), chi)| {
+                    catch_client_send(uid, &[id], &peer, || {
+                        mpc::ot_verify_bob(id, c_msg, &peer, chi, dims)
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+    };
+
+    // B2A Bob Receive (Start)
+    let b2a_bob_hook = Hook::new_named("B2A Bob");
+    let b2a_bob_handles = iter_arc(&client_data.po2_msgs_bob)
+        .zip(&client_data.uids_bob)
+        .zip(ids.b2a_b)
+        .enumerate()
+        .map(|(idx, ((c_msg, &uid), id))| {
+            let mut hasher = hashers.take(HashPhase::B2aAB, idx);
+            let peer = peer.clone();
+            spawn_client(uid, async move {
+                let result = {
+                    let (inputs_1, ts) = (&c_msg.inputs_1, c_msg.cot.ts());
+                    mpc::b2a_bob::<_, A, _>(&id, dims, inputs_1, ts, peer, &mut hasher).await
+                };
+                (result, hasher)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // OT Verify Alice Receive (Complete)
+    let mut qs_per_client = Vec::with_capacity(client_data.num_clients_as_alice());
+    let mut num_ot_verified = 0;
+    for (idx, ((alice_handle, &uid), failed)) in ot_ba_handles
+        .into_iter()
+        .zip(&client_data.uids_alice)
+        .zip(&mut failed_a)
+        .enumerate()
+    {
+        let (result, hasher) = join_client(alice_handle.await, &make_hasher);
+        match result {
+            // rejected, so B2A is skipped
+            Ok(_) if *failed => qs_per_client.push(None),
+            Ok((qs, v)) => {
+                // wiped once B2A is done
+                qs_per_client.push(Some(Zeroizing::new(qs)));
+                num_ot_verified += v as usize;
+            },
+            Err(e) => {
+                fail(uid, "OT Verify Alice", e, failed);
+                qs_per_client.push(None);
+            },
+        }
+        hashers.put(HashPhase::OtBA, idx, hasher);
+    }
+    log_verify_status(
+        num_ot_verified,
+        client_data.num_clients_as_alice(),
+        "OT Verify Alice",
+    );
+    ot_alice_hook.done();
+
+    // B2A Alice Send (Start)
+    let b2a_alice_hook = Hook::new_named("B2A Alice");
+    let b2a_alice_handles = block_on_compute(|| {
+        client_data
+            .po2_msgs_alice
+            .par_iter()
+            .zip(&client_data.uids_alice)
+            .zip(qs_per_client)
+            .zip(ids.b2a_a)
+            .map(|(((c_msg, &uid), qs), ids)| match qs {
+                Some(qs) => Some(catch_client_send(uid, &ids, peer, || {
+                    mpc::b2a_alice::<I, A>(&ids, dims, c_msg.inputs_0, c_msg.cot.delta, &qs, peer)
+                })),
+                None => {
+                    // already rejected, or failed in OT verification
+                    for id in ids {
+                        mpc::send_placeholder(id, peer);
+                    }
+                    None
+                },
+            })
+            .collect::<Vec<_>>()
+    });
+
+    // B2A Bob Receive (Complete)
+    let mut bob_arith_shares = Vec::with_capacity(client_data.num_clients_as_bob());
+    for (idx, ((bob_handle, &uid), failed)) in b2a_bob_handles
+        .into_iter()
+        .zip(&client_data.uids_bob)
+        .zip(&mut failed_b)
+        .enumerate()
+    {
+        let (result, hasher) = join_client(bob_handle.await, &make_hasher);
+        match result {
+            Ok(bob_arith_share) => bob_arith_shares.push(Some(bob_arith_share)),
+            Err(e) => {
+                fail(uid, "B2A Bob", e, failed);
+                bob_arith_shares.push(None);
+            },
+        }
+        hashers.put(HashPhase::B2aAB, idx, hasher);
+    }
+    b2a_bob_hook.done();
+
+    // B2A Alice Send (Complete)
+    let mut alice_arith_shares = Vec::with_capacity(client_data.num_clients_as_alice());
+    for ((result, &uid), failed) in b2a_alice_handles
+        .into_iter()
+        .zip(&client_data.uids_alice)
+        .zip(&mut failed_a)
+    {
+        match result {
+            Some(Ok((s, handle))) => {
+                handle
+                    .await
+                    .expect("MPC connection closed before the message was sent");
+                alice_arith_shares.push(Some(s));
+            },
+            Some(Err(e)) => {
+                fail(uid, "B2A Alice", e, failed);
+                alice_arith_shares.push(None);
+            },
+            None => alice_arith_shares.push(None),
+        }
+    }
+    b2a_alice_hook.done();
+
+    // OT Verify Bob Send (Complete)
+    for ((result, &uid), failed) in expect_joined(otverify_bob_handles.await)
+        .into_iter()
+        .zip(&client_data.uids_bob)
+        .zip(&mut failed_b)
+    {
+        match result {
+            Ok(handle) => handle
+                .await
+                .expect("MPC connection closed before the message was sent"),
+            Err(e) => fail(uid, "OT Verify Bob", e, failed),
+        }
+    }
+    ot_bob_hook.done();
+
+    // square correlations are checked here, so that their failures are
+    // exchanged together with the ones above
+    for (corrs, (&uid, failed)) in sqcorr_alice.iter().chain(sqcorr_bob.iter()).zip(
+        client_data
+            .uids_alice
+            .iter()
+            .zip(&mut failed_a)
+            .chain(client_data.uids_bob.iter().zip(&mut failed_b)),
+    ) {
+        if let Err(e) = corrs {
+            fail(uid, "SqCorr", e.clone().into(), failed);
+        }
+    }
+
+    // agree with peer on the failed clients
+    let mut failed = client_data.merge_by_uid(is_alice, failed_a, failed_b);
+    let failed_peer = peer
+        .exchange_message(
+            ids.exchange_failures,
+            failed.iter().map(|&f| f as u8).collect::<Vec<_>>(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(failed_peer.len(), failed.len());
+    for ((failed, failed_peer), &uid) in failed.iter_mut().zip(failed_peer).zip(uids) {
+        if failed_peer != 0 {
+            fail(
+                uid,
+                "Exchange failures",
+                ClientFailure::FailedOnPeer,
+                failed,
+            );
+        }
+    }
+    let (mut failed_a, mut failed_b) = client_data.split_by_uid(is_alice, failed);
+
+    let b2a_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let timer = start_timer!(|| "SqCorr Verify");
+    peer.metrics().set_phase("SqCorr Verify");
+
+    let (sqcorr_a, sqcorr_b) = client_data.split_by_uid(is_alice, ids.sqcorr);
+    // SqCorr Verify, with the messages of all clients in one batch per
+    // exchange
+    let mut sqcorr_clients = Vec::with_capacity(client_data.num_clients());
+    for (phase, party, corrs, phase_uids, phase_failed, phase_ids, t_seeds) in [
+        (
+            HashPhase::SqcorrBA,
+            ALICE,
+            &sqcorr_alice,
+            &client_data.uids_alice,
+            &failed_a,
+            sqcorr_a,
+            t_seeds_a,
+        ),
+        (
+            HashPhase::SqcorrAB,
+            BOB,
+            &sqcorr_bob,
+            &client_data.uids_bob,
+            &failed_b,
+            sqcorr_b,
+            t_seeds_b,
+        ),
+    ] {
+        for (idx, ((((corr, &uid), &failed), ids), t_seed)) in corrs
+            .iter()
+            .zip(phase_uids)
+            .zip(phase_failed)
+            .zip(phase_ids)
+            .zip(t_seeds)
+            .enumerate()
+        {
+            if !failed {
+                sqcorr_clients.push(SqcorrClient {
+                    uid,
+                    phase,
+                    party,
+                    idx,
+                    ids,
+                    corr: checked_sqcorr(corr),
+                    t_seed,
+                    hasher: hashers.take(phase, idx),
+                });
+            }
+        }
+    }
+
+    let rounds = block_on_compute(|| {
+        sqcorr_clients
+            .par_iter_mut()
+            .map(|c| {
+                catch_client(c.uid, || {
+                    mpc::CorrVerifyRound::new(num_squared, c.corr, c.t_seed)
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+    let exchange_ids = sqcorr_clients.iter().map(|c| c.ids.0).collect::<Vec<_>>();
+    let db_peer = mpc::exchange_batch(
+        &exchange_ids,
+        batch_msgs(&rounds, |round| round.db().into_bytes_owned()),
+        peer,
+    )
+    .await
+    .expect("exchange shares of d");
+    let opened = block_on_compute(|| {
+        sqcorr_clients
+            .par_iter_mut()
+            .zip(rounds)
+            .zip(db_peer)
+            .map(|((c, round), db_other)| {
+                let round = round?;
+                let wb = catch_client(c.uid, || {
+                    let db_other =
+                        Vec::<C>::from_bytes_owned(db_other).map_err(BridgeError::from)?;
+                    if c.party == ALICE {
+                        round.open_d::<{ ALICE }, _>(db_other, &mut c.hasher)
+                    } else {
+                        round.open_d::<{ BOB }, _>(db_other, &mut c.hasher)
+                    }
+                })?;
+                Ok(wb)
+            })
+            .collect::<Vec<_>>()
+    });
+    let exchange_ids = sqcorr_clients.iter().map(|c| c.ids.1).collect::<Vec<_>>();
+    let wb_peer = mpc::exchange_batch(
+        &exchange_ids,
+        batch_msgs(&opened, |wb: &Vec<C>| wb.into_bytes_owned()),
+        peer,
+    )
+    .await
+    .expect("exchange shares of w");
+    let results = sqcorr_clients
+        .iter_mut()
+        .zip(opened)
+        .zip(wb_peer)
+        .map(|((c, wb), wb_other)| {
+            let wb = wb?;
+            catch_client(c.uid, || {
+                let wb_other = Vec::<C>::from_bytes_owned(wb_other).map_err(BridgeError::from)?;
+                mpc::CorrVerifyRound::count_passed(&wb, wb_other, &mut c.hasher)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut num_sqcorr_verified = 0;
+    for (c, result) in sqcorr_clients.into_iter().zip(results) {
+        hashers.put(c.phase, c.idx, c.hasher);
+        let failed = if c.party == ALICE {
+            &mut failed_a[c.idx]
+        } else {
+            &mut failed_b[c.idx]
+        };
+        match result {
+            Ok(result) => {
+                num_sqcorr_verified += if result == mpc::num_corr_checks(num_squared) {
+                    1
+                } else {
+                    0
+                }
+            },
+            Err(e) => fail(c.uid, "SqCorr Verify", e, failed),
+        }
+    }
+
+    let num_good_clients = failed_a.iter().chain(&failed_b).filter(|f| !**f).count();
+    log_verify_status(num_sqcorr_verified, num_good_clients, "SqCorr Verify");
+
+    let corr_verify_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let timer = start_timer!(|| "A2S");
+    peer.metrics().set_phase("A2S");
+    // A2S
+    let mut failed = client_data.merge_by_uid(is_alice, failed_a, failed_b);
+    let mut arith_shares = client_data.merge_by_uid(is_alice, alice_arith_shares, bob_arith_shares);
+    for (share, _) in arith_shares.iter_mut().zip(&failed).filter(|(_, f)| **f) {
+        *share = None;
+    }
+    let sqcorr = client_data.merge_by_uid(is_alice, sqcorr_alice.iter(), sqcorr_bob.iter());
+    // with the messages of all clients in one batch
+    let mut a2s_clients = Vec::with_capacity(client_data.num_clients());
+    for (idx, (((corr, xs), &uid), id)) in sqcorr
+        .into_iter()
+        .zip(&arith_shares)
+        .zip(uids)
+        .zip(ids.a2s)
+        .enumerate()
+    {
+        if let Some(xs) = xs {
+            a2s_clients.push(A2sClient {
+                uid,
+                idx,
+                id,
+                xs,
+                corr: checked_sqcorr(corr),
+                hasher: hashers.take(HashPhase::A2s, idx),
+            });
+        }
+    }
+
+    let rounds = block_on_compute(|| {
+        a2s_clients
+            .par_iter_mut()
+            .map(|c| catch_client(c.uid, || Ok(mpc::A2sRound::new(c.xs, c.corr))))
+            .collect::<Vec<_>>()
+    });
+    let a2s_clients_len = a2s_clients.len();
+    let exchange_ids = a2s_clients.iter().map(|c| c.id).collect::<Vec<_>>();
+    let eb_peer = mpc::exchange_batch(
+        &exchange_ids,
+        batch_msgs(&rounds, |round| round.eb().into_bytes_owned()),
+        peer,
+    )
+    .await
+    .expect("exchange shares of e");
+    let results = block_on_compute(|| {
+        a2s_clients
+            .par_iter_mut()
+            .zip(rounds)
+            .zip(eb_peer)
+            .map(|((c, round), eb_other)| {
+                let round = round?;
+                catch_client(c.uid, || {
+                    let eb_other =
+                        Vec::<A>::from_bytes_owned(eb_other).map_err(BridgeError::from)?;
+                    if is_alice {
+                        round.finish::<{ ALICE }, _>(c.xs, eb_other, &mut c.hasher)
+                    } else {
+                        round.finish::<{ BOB }, _>(c.xs, eb_other, &mut c.hasher)
+                    }
+                })
+            })
+            .collect::<Vec<_>>()
+    });
+
+    // the bound check follows on the same clients, with its transcript hashed
+    // into the one of A2S. Its checks are opened, so a client out of the
+    // bound is failed on both servers.
+    let results = match client_data.bound {
+        Some(bound) => {
+            let bound = bound.as_uint::<A>();
+            let rounds = block_on_compute(|| {
+                a2s_clients
+                    .par_iter_mut()
+                    .zip(results)
+                    .map(|(c, result)| {
+                        let square = result?;
+                        let round = catch_client(c.uid, || {
+                            mpc::BoundCheckRound::new(
+                                gsize,
+                                &client_data.witness[c.idx],
+                                c.corr,
+                                bound,
+                            )
+                        })?;
+                        Ok((square, round))
+                    })
+                    .collect::<Vec<_>>()
+            });
+            let (ids_e, ids_checks) = a2s_clients
+                .iter()
+                .map(|c| ids.bound_check[c.idx])
+                .unzip::<_, _, Vec<_>, Vec<_>>();
+            let eb_peer = mpc::exchange_batch(
+                &ids_e,
+                batch_msgs(&rounds, |(_, round)| round.eb().into_bytes_owned()),
+                peer,
+            )
+            .await
+            .expect("exchange shares of the bound check e");
+            let opened = block_on_compute(|| {
+                a2s_clients
+                    .par_iter_mut()
+                    .zip(rounds)
+                    .zip(eb_peer)
+                    .map(|((c, round), eb_other)| {
+                        let (square, round) = round?;
+                        let checks = catch_client(c.uid, || {
+                            let eb_other =
+                                Vec::<A>::from_bytes_owned(eb_other).map_err(BridgeError::from)?;
+                            // the checks are hashed, so the OT sender of the
+                            // client subtracts the bound, as in its simulation
+                            if client_data.policy.is_ot_sender(is_alice, c.uid) {
+                                round.open_e::<{ ALICE }, _>(c.xs, eb_other, &mut c.hasher)
+                            } else {
+                                round.open_e::<{ BOB }, _>(c.xs, eb_other, &mut c.hasher)
+                            }
+                        })?;
+                        Ok((square, round, checks))
+                    })
+                    .collect::<Vec<_>>()
+            });
+            let checks_peer = mpc::exchange_batch(
+                &ids_checks,
+                batch_msgs(&opened, |(_, _, checks)| checks.into_bytes_owned()),
+                peer,
+            )
+            .await
+            .expect("exchange shares of the bound checks");
+            a2s_clients
+                .iter_mut()
+                .zip(opened)
+                .zip(checks_peer)
+                .map(|((c, opened), checks_other)| {
+                    let (square, round, checks) = opened?;
+                    let num_accepted = catch_client(c.uid, || {
+                        let checks_other =
+                            Vec::<A>::from_bytes_owned(checks_other).map_err(BridgeError::from)?;
+                        round.count_accepted(&checks, checks_other, &mut c.hasher)
+                    })?;
+                    if num_accepted == gsize {
+                        Ok(square)
+                    } else {
+                        Err(ClientFailure::OutOfBound)
+                    }
+                })
+                .collect::<Vec<_>>()
+        },
+        None => results,
+    };
+
+    // clients without arithmetic shares are already failed
+    let mut square_shares = vec![None; client_data.num_clients()];
+    for (c, result) in a2s_clients.into_iter().zip(results) {
+        hashers.put(HashPhase::A2s, c.idx, c.hasher);
+        match result {
+            Ok(result) => square_shares[c.idx] = Some(result),
+            Err(e @ ClientFailure::OutOfBound) => fail(c.uid, "Bound Check", e, &mut failed[c.idx]),
+            Err(e) => fail(c.uid, "A2S", e, &mut failed[c.idx]),
+        }
+    }
+    let num_bound_verified = client_data.bound.map(|_| {
+        let num_verified = square_shares.iter().flatten().count();
+        log_verify_status(num_verified, a2s_clients_len, "Bound Check");
+        num_verified
+    });
+
+    // clients failing after the exchange are dropped by me only
+    for ((share, square), _) in arith_shares
+        .iter_mut()
+        .zip(&mut square_shares)
+        .zip(&failed)
+        .filter(|(_, f)| **f)
+    {
+        *share = None;
+        *square = None;
+    }
+    let failed_clients = uids
+        .iter()
+        .zip(&failed)
+        .filter(|(_, f)| **f)
+        .map(|(uid, _)| *uid)
+        .collect::<Vec<_>>();
+    let (failed_a, failed_b) = client_data.split_by_uid(is_alice, failed.iter().copied());
+    let num_good_a = failed_a.iter().filter(|f| !**f).count();
+    let num_good_b = failed_b.iter().filter(|f| !**f).count();
+
+    let a2s_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    let timer = start_timer!(|| "Hash Verification");
+    peer.metrics().set_phase("Hash Verification");
+    // B2A
+    let num_b2a_hash_verified = count_verified(
+        hashers.verify(HashPhase::B2aAB, &client_data.hash_b2a_ab),
+        &failed_b,
+    );
+    log_verify_status(num_b2a_hash_verified, num_good_b, "B2A Hash AB");
+    // A2S, whose transcript depends on the square correlations, like the one
+    // of SqCorr Verify, so neither is checked with `simulate_server_corr`
+    let num_a2s_hash_verified = (!simulate_server_corr).then(|| {
+        count_verified(
+            hashers.verify(HashPhase::A2s, &client_data.hash_a2s),
+            &failed,
+        )
+    });
+    if let Some(num_verified) = num_a2s_hash_verified {
+        log_verify_status(num_verified, num_good_a + num_good_b, "A2S Hash");
+    }
+    // OT Verify
+    let num_ot_hash_verified = count_verified(
+        hashers.verify(HashPhase::OtBA, &client_data.hash_ot_ba),
+        &failed_a,
+    );
+    log_verify_status(num_ot_hash_verified, num_good_a, "OT Verify Hash");
+    // SqCorr Verify
+    let num_sqcorr_hash_verified = (!simulate_server_corr).then(|| {
+        count_verified(
+            hashers.verify(HashPhase::SqcorrBA, &client_data.hash_sqcorr_ba),
+            &failed_a,
+        ) + count_verified(
+            hashers.verify(HashPhase::SqcorrAB, &client_data.hash_sqcorr_ab),
+            &failed_b,
+        )
+    });
+    if let Some(num_verified) = num_sqcorr_hash_verified {
+        log_verify_status(num_verified, num_good_a + num_good_b, "SqCorr Verify Hash");
+    }
+    let hash_verify_time = end_timer!(timer).elapsed().as_secs_f64();
+    peer.metrics().clear_phase();
+
+    PipelineOutput {
+        arith_shares,
+        square_shares,
+        failed_clients,
+        num_ot_verified,
+        num_sqcorr_verified,
+        num_b2a_hash_verified,
+        num_a2s_hash_verified,
+        num_ot_hash_verified,
+        num_sqcorr_hash_verified,
+        num_bound_verified,
+        sqcorr_gen_time,
+        b2a_time,
+        corr_verify_time,
+        a2s_time,
+        hash_verify_time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::Arc,
+    };
+
+    use bin_utils::{
+        runtime::{run_on, ServerRuntimes},
+        signing::ClientKeys,
+    };
+    use bridge::{
+        client_dump::ClientRecording,
+        client_server::{init_meta_clients, ClientConnectConfig, PartitionPolicy},
+        id_tracker::SendId,
+        mpc_conn::MpcConnection,
+        tcp_bridge::{ClientID, ClientToken},
+    };
+    use bytes::Bytes;
+    #[cfg(feature = "signed-uploads")]
+    use crypto_primitives::message::signed::{SignedMessage, SigningKey};
+    use crypto_primitives::{
+        bits::ShareCommitment,
+        cot::{
+            client::{B2ACOTToBob, DEFAULT_SEC_PARAM},
+            dims::ProtocolDims,
+        },
+        uint::UInt,
+        utils::{batch_sum, SliceExt},
+    };
+    use elsa_client::{l2::L2Client, mp::Client, po2::SingleRoundClient};
+    use rand::{rngs::StdRng, SeedableRng};
+    use serialize::Communicate;
+    use sha2::Sha256;
+    use tokio::{net::TcpListener, runtime::Runtime};
+
+    use super::PipelineOutput;
+    use crate::client_msg::ClientData;
+
+    type I = u8;
+    type A = u64;
+    type C = u128;
+
+    const GSIZE: usize = 4;
+    // odd, so that the two servers are OT sender for different numbers of
+    // clients
+    const NUM_CLIENTS: usize = 5;
+    const MASTER_SEED: u64 = 0x5eed;
+
+    /// Input of client `uid`, and the rng it uses for its message.
+    fn client_input(uid: usize, gsize: usize) -> (Vec<I>, StdRng) {
+        let mut rng = StdRng::seed_from_u64(MASTER_SEED + uid as u64);
+        let input = (0..gsize).map(|_| I::rand(&mut rng)).collect();
+        (input, rng)
+    }
+
+    fn serialize<M: Communicate>(msg: &M) -> Bytes {
+        let mut bytes = Vec::with_capacity(msg.size_in_bytes());
+        msg.to_bytes(&mut bytes);
+        bytes.into()
+    }
+
+    #[cfg(feature = "signed-uploads")]
+    fn signing_key(uid: usize) -> SigningKey {
+        SigningKey::from_bytes(&[uid as u8 + 1; 32])
+    }
+
+    /// Keys of the first 64 uids, so that every test runs with signed
+    /// uploads.
+    #[cfg(feature = "signed-uploads")]
+    fn client_keys() -> ClientKeys {
+        ClientKeys::new(
+            (0..64)
+                .map(|uid| (ClientID::new(uid as u64), signing_key(uid).verifying_key()))
+                .collect(),
+        )
+    }
+
+    #[cfg(not(feature = "signed-uploads"))]
+    fn client_keys() -> ClientKeys {
+        ClientKeys::default()
+    }
+
+    /// `msg` as uploaded by client `uid`.
+    #[cfg(feature = "signed-uploads")]
+    fn upload<M: Communicate>(uid: usize, msg: &M) -> Bytes {
+        SignedMessage::sign(&signing_key(uid), msg).into_bytes_owned()
+    }
+
+    #[cfg(not(feature = "signed-uploads"))]
+    fn upload<M: Communicate>(_uid: usize, msg: &M) -> Bytes {
+        serialize(msg)
+    }
+
+    /// Messages received by the server with global role `is_alice`, split
+    /// like `ClientsPool::split`.
+    fn recording(
+        is_alice: bool,
+        policy: &PartitionPolicy,
+        msgs: &[(Bytes, Bytes)],
+    ) -> ClientRecording {
+        let mut recording = ClientRecording::default();
+        for (uid, (to_alice, to_bob)) in msgs.iter().enumerate() {
+            let uid = ClientID::new(uid as u64);
+            if policy.is_ot_sender(is_alice, uid) {
+                recording.alice.push((uid, to_alice.clone()));
+            } else {
+                recording.bob.push((uid, to_bob.clone()));
+            }
+        }
+        recording
+    }
+
+    async fn mpc_pair() -> (MpcConnection, MpcConnection) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::join!(
+            MpcConnection::new_as_alice_with_listener(listener, 2),
+            MpcConnection::new_as_bob(addr, 2)
+        )
+    }
+
+    fn mp_msgs(
+        num_clients: usize,
+        gsize: usize,
+        sec_param: usize,
+        tamper: impl Fn(usize, &mut Client<I, C, Sha256>),
+    ) -> Vec<(Bytes, Bytes)> {
+        (0..num_clients)
+            .map(|uid| {
+                let (input, mut rng) = client_input(uid, gsize);
+                let mut client = Client::<I, C, Sha256>::prepare_message::<A, _, _>(
+                    ClientID::new(uid as u64),
+                    0,
+                    &input,
+                    &mut rng,
+                    sec_param,
+                    Sha256::default,
+                );
+                tamper(uid, &mut client);
+                (upload(uid, &client.msg_alice), upload(uid, &client.msg_bob))
+            })
+            .collect()
+    }
+
+    /// Run the MP pipeline on both servers. Return the output and the pool
+    /// sizes `(as Alice, as Bob)` of each server, Alice first.
+    async fn run_mp(
+        msgs: &[(Bytes, Bytes)],
+        gsize: usize,
+        sec_param: usize,
+    ) -> [(PipelineOutput<A>, (usize, usize)); 2] {
+        run_mp_with_policy(msgs, gsize, sec_param, PartitionPolicy::Parity).await
+    }
+
+    async fn run_mp_with_policy(
+        msgs: &[(Bytes, Bytes)],
+        gsize: usize,
+        sec_param: usize,
+        policy: PartitionPolicy,
+    ) -> [(PipelineOutput<A>, (usize, usize)); 2] {
+        let (alice, bob) = mpc_pair().await;
+        run_mp_on(
+            alice,
+            bob,
+            msgs,
+            gsize,
+            sec_param,
+            policy,
+            &client_keys(),
+            false,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn run_mp_on(
+        alice: MpcConnection,
+        bob: MpcConnection,
+        msgs: &[(Bytes, Bytes)],
+        gsize: usize,
+        sec_param: usize,
+        policy: PartitionPolicy,
+        keys: &ClientKeys,
+        simulate_server_corr: bool,
+    ) -> [(PipelineOutput<A>, (usize, usize)); 2] {
+        let run = |is_alice: bool, peer: MpcConnection| {
+            let data = ClientData::<I, C, Sha256>::from_recording(
+                is_alice,
+                policy.clone(),
+                recording(is_alice, &policy, msgs),
+                gsize,
+                None,
+                Sha256::default,
+                keys,
+            );
+            let num_clients = (data.num_clients_as_alice(), data.num_clients_as_bob());
+            let handle = tokio::spawn(async move {
+                super::run::<I, A, C, _, _>(
+                    gsize,
+                    sec_param,
+                    is_alice,
+                    &data,
+                    &peer,
+                    Sha256::default,
+                    simulate_server_corr,
+                )
+                .await
+            });
+            (handle, num_clients)
+        };
+        let ((alice, pools_alice), (bob, pools_bob)) = (run(true, alice), run(false, bob));
+        [
+            (alice.await.unwrap(), pools_alice),
+            (bob.await.unwrap(), pools_bob),
+        ]
+    }
+
+    /// Shares of clients that did not fail.
+    fn completed(shares: &[Option<Vec<A>>]) -> Vec<Vec<A>> {
+        shares.iter().flatten().cloned().collect()
+    }
+
+    fn reconstruct(shares_0: &[Vec<A>], shares_1: &[Vec<A>]) -> Vec<Vec<A>> {
+        shares_0
+            .iter()
+            .zip(shares_1)
+            .map(|(s0, s1)| s0.zip_map(s1, |a, b| a.wrapping_add(*b)))
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_mp_matches_semi_honest() {
+        let inputs = (0..NUM_CLIENTS)
+            .map(|uid| client_input(uid, GSIZE).0)
+            .collect::<Vec<_>>();
+
+        let semi_honest_msgs = (0..NUM_CLIENTS)
+            .map(|uid| {
+                let (input, mut rng) = client_input(uid, GSIZE);
+                let client = L2Client::<I, C>::new(
+                    ClientID::new(uid as u64),
+                    0,
+                    &input,
+                    &mut rng,
+                    DEFAULT_SEC_PARAM,
+                );
+                (
+                    serialize(&client.prepared_message_0),
+                    serialize(&client.prepared_message_1),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mp_msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |_, _| {});
+
+        // MP clients send the semi-honest messages plus transcript hashes
+        for (uid, (to_alice, to_bob)) in semi_honest_msgs.iter().enumerate() {
+            let (input, mut rng) = client_input(uid, GSIZE);
+            let client = Client::<I, C, Sha256>::prepare_message::<A, _, _>(
+                ClientID::new(uid as u64),
+                0,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+                Sha256::default,
+            );
+            assert_eq!(*to_alice, serialize(&client.msg_alice.0 .0));
+            assert_eq!(*to_bob, serialize(&client.msg_bob.0 .0));
+        }
+
+        // semi-honest pipeline
+        let (alice, bob) = mpc_pair().await;
+        let run_l2 = |is_alice: bool, peer: MpcConnection| {
+            let data = server_l2::client_msg::ClientData::<I, C>::from_recording(
+                is_alice,
+                PartitionPolicy::Parity,
+                recording(is_alice, &PartitionPolicy::Parity, &semi_honest_msgs),
+                GSIZE,
+                None,
+            );
+            tokio::spawn(async move {
+                server_l2::pipeline::run::<I, A, C>(
+                    GSIZE,
+                    DEFAULT_SEC_PARAM,
+                    is_alice,
+                    &data,
+                    &peer,
+                    None,
+                    false,
+                )
+                .await
+            })
+        };
+        let (l2_alice, l2_bob) = (run_l2(true, alice), run_l2(false, bob));
+        let (l2_alice, l2_bob) = (l2_alice.await.unwrap(), l2_bob.await.unwrap());
+
+        // MP pipeline
+        let [(mp_alice, pools_alice), (mp_bob, pools_bob)] =
+            run_mp(&mp_msgs, GSIZE, DEFAULT_SEC_PARAM).await;
+
+        // every check passes, so the comparison below is meaningful
+        for (output, (num_as_alice, num_as_bob)) in [(&mp_alice, pools_alice), (&mp_bob, pools_bob)]
+        {
+            assert!(output.failed_clients.is_empty());
+            assert_eq!(output.num_ot_verified, num_as_alice);
+            assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS);
+            assert_eq!(output.num_b2a_hash_verified, num_as_bob);
+            assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS));
+            assert_eq!(output.num_ot_hash_verified, num_as_alice);
+            assert_eq!(output.num_sqcorr_hash_verified, Some(NUM_CLIENTS));
+        }
+        assert_eq!(l2_alice.num_ot_verified, pools_alice.0);
+        assert_eq!(l2_bob.num_ot_verified, pools_bob.0);
+        assert_eq!(l2_alice.num_sqcorr_verified, NUM_CLIENTS);
+        assert_eq!(l2_bob.num_sqcorr_verified, NUM_CLIENTS);
+
+        // B2A and A2S compute the same values, which are the inputs and their
+        // squares
+        let l2_values = reconstruct(&l2_alice.arith_shares, &l2_bob.arith_shares);
+        let mp_values = reconstruct(
+            &completed(&mp_alice.arith_shares),
+            &completed(&mp_bob.arith_shares),
+        );
+        assert_eq!(l2_values, mp_values);
+        let expected_values = inputs
+            .iter()
+            .map(|input| input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(mp_values, expected_values);
+
+        let l2_squares = reconstruct(&l2_alice.square_shares, &l2_bob.square_shares);
+        let mp_squares = reconstruct(
+            &completed(&mp_alice.square_shares),
+            &completed(&mp_bob.square_shares),
+        );
+        assert_eq!(l2_squares, mp_squares);
+        let expected_squares = expected_values
+            .iter()
+            .map(|v| v.iter().map(|x| x * x).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(mp_squares, expected_squares);
+
+        // and so do the aggregates
+        let l2_aggregate = l2_alice
+            .aggregate()
+            .zip_map(&l2_bob.aggregate(), |a, b| a.wrapping_add(*b));
+        let mp_aggregate = mp_alice
+            .aggregate()
+            .zip_map(&mp_bob.aggregate(), |a, b| a.wrapping_add(*b));
+        assert_eq!(l2_aggregate, mp_aggregate);
+        assert_eq!(mp_aggregate, batch_sum(&expected_values));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_sqcorr_and_a2s_batched() {
+        const NUM_CLIENTS: usize = 40;
+
+        let msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |_, _| {});
+        let (alice, bob) = mpc_pair().await;
+        let [(out_alice, _), (out_bob, _)] = run_mp_on(
+            alice.clone(),
+            bob.clone(),
+            &msgs,
+            GSIZE,
+            DEFAULT_SEC_PARAM,
+            PartitionPolicy::Parity,
+            &client_keys(),
+            false,
+        )
+        .await;
+
+        for output in [&out_alice, &out_bob] {
+            assert!(output.failed_clients.is_empty());
+            assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS);
+            assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS));
+            assert_eq!(output.num_sqcorr_hash_verified, Some(NUM_CLIENTS));
+        }
+        let expected_squares = (0..NUM_CLIENTS)
+            .map(|uid| {
+                let input = client_input(uid, GSIZE).0;
+                input
+                    .iter()
+                    .map(|x| x.as_uint::<A>().pow(2))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let squares = reconstruct(
+            &completed(&out_alice.square_shares),
+            &completed(&out_bob.square_shares),
+        );
+        assert_eq!(squares, expected_squares);
+
+        // one frame per client for OT Verify, or `B2A_CHUNKS` for B2A, then
+        // the failed uploads, the two of the commitment check, the two seeds,
+        // the failures, the two batches of SqCorr Verify and the batch of A2S,
+        // instead of three more frames per client
+        for (server, peer) in [(&alice, &bob), (&bob, &alice)] {
+            let per_client = NUM_CLIENTS / 2 * (1 + server_protocol::B2A_CHUNKS);
+            assert_eq!(server.num_frames_sent(), per_client + 9);
+            assert_eq!(peer.num_frames_received(), server.num_frames_sent());
+        }
+    }
+
+    /// Both servers in one process over a dummy pair, without sockets, send
+    /// as much as over TCP and compute the same squares.
+    #[cfg(feature = "simulation-helpers")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_mp_over_dummy_pair() {
+        const NUM_CLIENTS: usize = 10;
+
+        let msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |_, _| {});
+        let mut squares = Vec::new();
+        let mut num_bytes_sent = Vec::new();
+        for (alice, bob) in [mpc_pair().await, MpcConnection::dummy_pair()] {
+            let [(out_alice, _), (out_bob, _)] = run_mp_on(
+                alice.clone(),
+                bob.clone(),
+                &msgs,
+                GSIZE,
+                DEFAULT_SEC_PARAM,
+                PartitionPolicy::Parity,
+                &client_keys(),
+                false,
+            )
+            .await;
+            for output in [&out_alice, &out_bob] {
+                assert!(output.failed_clients.is_empty());
+                assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS);
+            }
+            squares.push(reconstruct(
+                &completed(&out_alice.square_shares),
+                &completed(&out_bob.square_shares),
+            ));
+            num_bytes_sent.push((alice.num_bytes_sent(), bob.num_bytes_sent()));
+            assert_eq!(bob.num_bytes_received(), alice.num_bytes_sent());
+        }
+        assert_eq!(squares[0], squares[1]);
+        assert_eq!(num_bytes_sent[0], num_bytes_sent[1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_malformed_client_isolated() {
+        const NUM_CLIENTS: usize = 6;
+        // odd, so Alice is its OT receiver
+        const BAD_UID: usize = 3;
+
+        let msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |uid, client| {
+            if uid == BAD_UID {
+                client.msg_bob.0 .0.po2_msg.cot.truncate(5);
+            }
+        });
+        let [(alice, pools_alice), (bob, pools_bob)] =
+            run_mp(&msgs, GSIZE, DEFAULT_SEC_PARAM).await;
+
+        // Bob is OT sender of the bad client
+        for (output, (num_as_alice, num_as_bob), bad_as_alice) in
+            [(&alice, pools_alice, 0), (&bob, pools_bob, 1)]
+        {
+            let bad_as_bob = 1 - bad_as_alice;
+            assert_eq!(output.failed_clients, vec![ClientID::new(BAD_UID as u64)]);
+            assert!(output.arith_shares[BAD_UID].is_none());
+            assert!(output.square_shares[BAD_UID].is_none());
+            assert_eq!(output.num_ot_verified, num_as_alice - bad_as_alice);
+            assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS - 1);
+            assert_eq!(output.num_b2a_hash_verified, num_as_bob - bad_as_bob);
+            assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS - 1));
+            assert_eq!(output.num_ot_hash_verified, num_as_alice - bad_as_alice);
+            assert_eq!(output.num_sqcorr_hash_verified, Some(NUM_CLIENTS - 1));
+        }
+
+        // the other clients are aggregated as usual
+        let expected_values = (0..NUM_CLIENTS)
+            .filter(|&uid| uid != BAD_UID)
+            .map(|uid| {
+                let input = client_input(uid, GSIZE).0;
+                input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let values = reconstruct(
+            &completed(&alice.arith_shares),
+            &completed(&bob.arith_shares),
+        );
+        assert_eq!(values, expected_values);
+        let squares = reconstruct(
+            &completed(&alice.square_shares),
+            &completed(&bob.square_shares),
+        );
+        let expected_squares = expected_values
+            .iter()
+            .map(|v| v.iter().map(|x| x * x).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(squares, expected_squares);
+        let aggregate = alice
+            .aggregate()
+            .zip_map(&bob.aggregate(), |a, b| a.wrapping_add(*b));
+        assert_eq!(aggregate, batch_sum(&expected_values));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_bad_square_corrs_isolated() {
+        // even, so the bad message goes to Alice
+        const BAD_UID: usize = 2;
+
+        let msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |uid, client| {
+            if uid == BAD_UID {
+                client.msg_alice.0 .0.square_corr.expected_len += 2;
+            }
+        });
+        let [(alice, _), (bob, _)] = run_mp(&msgs, GSIZE, DEFAULT_SEC_PARAM).await;
+
+        for output in [&alice, &bob] {
+            assert_eq!(output.failed_clients, vec![ClientID::new(BAD_UID as u64)]);
+            assert!(output.arith_shares[BAD_UID].is_none());
+            assert!(output.square_shares[BAD_UID].is_none());
+            assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS - 1);
+            assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS - 1));
+        }
+        let expected_values = (0..NUM_CLIENTS)
+            .filter(|&uid| uid != BAD_UID)
+            .map(|uid| {
+                let input = client_input(uid, GSIZE).0;
+                input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let aggregate = alice
+            .aggregate()
+            .zip_map(&bob.aggregate(), |a, b| a.wrapping_add(*b));
+        assert_eq!(aggregate, batch_sum(&expected_values));
+    }
+
+    #[cfg(feature = "simulation-helpers")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_simulated_server_corr() {
+        // ignored with `simulate_server_corr`, so the client is not failed
+        const BAD_UID: usize = 2;
+
+        let msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |uid, client| {
+            if uid == BAD_UID {
+                client.msg_alice.0 .0.square_corr.expected_len += 2;
+            }
+        });
+        let (alice, bob) = mpc_pair().await;
+        let [(alice, pools_alice), (bob, pools_bob)] = run_mp_on(
+            alice,
+            bob,
+            &msgs,
+            GSIZE,
+            DEFAULT_SEC_PARAM,
+            PartitionPolicy::Parity,
+            &client_keys(),
+            true,
+        )
+        .await;
+
+        for (output, (num_as_alice, num_as_bob)) in [(&alice, pools_alice), (&bob, pools_bob)] {
+            assert!(output.failed_clients.is_empty());
+            assert_eq!(output.num_ot_verified, num_as_alice);
+            assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS);
+            assert_eq!(output.num_b2a_hash_verified, num_as_bob);
+            assert_eq!(output.num_a2s_hash_verified, None);
+            assert_eq!(output.num_sqcorr_hash_verified, None);
+        }
+        let expected_values = (0..NUM_CLIENTS)
+            .map(|uid| {
+                let input = client_input(uid, GSIZE).0;
+                input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let squares = reconstruct(
+            &completed(&alice.square_shares),
+            &completed(&bob.square_shares),
+        );
+        let expected_squares = expected_values
+            .iter()
+            .map(|v| v.iter().map(|x| x * x).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(squares, expected_squares);
+        let aggregate = alice
+            .aggregate()
+            .zip_map(&bob.aggregate(), |a, b| a.wrapping_add(*b));
+        assert_eq!(aggregate, batch_sum(&expected_values));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_mismatched_commitment_rejected() {
+        const BAD_UID: usize = 1;
+
+        let msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |uid, client| {
+            if uid == BAD_UID {
+                client.msg_bob.0 .0.po2_msg.commitment = ShareCommitment::default();
+            }
+        });
+        let [(alice, _), (bob, _)] = run_mp(&msgs, GSIZE, DEFAULT_SEC_PARAM).await;
+
+        for output in [&alice, &bob] {
+            assert_eq!(output.failed_clients, vec![ClientID::new(BAD_UID as u64)]);
+            assert!(output.arith_shares[BAD_UID].is_none());
+            assert!(output.square_shares[BAD_UID].is_none());
+            assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS - 1));
+        }
+        let expected_values = (0..NUM_CLIENTS)
+            .filter(|&uid| uid != BAD_UID)
+            .map(|uid| {
+                let input = client_input(uid, GSIZE).0;
+                input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let aggregate = alice
+            .aggregate()
+            .zip_map(&bob.aggregate(), |a, b| a.wrapping_add(*b));
+        assert_eq!(aggregate, batch_sum(&expected_values));
+    }
+
+    #[cfg(feature = "signed-uploads")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_bad_signatures_isolated() {
+        // its upload to Alice is flipped after signing
+        const FLIPPED_UID: usize = 2;
+        // signs with a key other than the one the servers know for it
+        const WRONG_KEY_UID: usize = 3;
+
+        let mut msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |_, _| {});
+        let mut flipped = msgs[FLIPPED_UID].0.to_vec();
+        flipped[10] ^= 1;
+        msgs[FLIPPED_UID].0 = flipped.into();
+        let keys = ClientKeys::new(
+            (0..NUM_CLIENTS)
+                .map(|uid| {
+                    let key = match uid {
+                        WRONG_KEY_UID => SigningKey::from_bytes(&[0xff; 32]),
+                        _ => signing_key(uid),
+                    };
+                    (ClientID::new(uid as u64), key.verifying_key())
+                })
+                .collect(),
+        );
+        let (alice, bob) = mpc_pair().await;
+        let [(alice, _), (bob, _)] = run_mp_on(
+            alice,
+            bob,
+            &msgs,
+            GSIZE,
+            DEFAULT_SEC_PARAM,
+            PartitionPolicy::Parity,
+            &keys,
+            false,
+        )
+        .await;
+
+        let bad_uids = [FLIPPED_UID, WRONG_KEY_UID];
+        for output in [&alice, &bob] {
+            assert_eq!(
+                output.failed_clients,
+                bad_uids.map(|uid| ClientID::new(uid as u64))
+            );
+            assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS - 2));
+        }
+        let expected_values = (0..NUM_CLIENTS)
+            .filter(|uid| !bad_uids.contains(uid))
+            .map(|uid| {
+                let input = client_input(uid, GSIZE).0;
+                input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let aggregate = alice
+            .aggregate()
+            .zip_map(&bob.aggregate(), |a, b| a.wrapping_add(*b));
+        assert_eq!(aggregate, batch_sum(&expected_values));
+    }
+
+    /// Run the whole protocol with `gsize` inputs per client, and check that
+    /// every client is verified and aggregated.
+    async fn check_all_verified(gsize: usize, sec_param: usize, policy: PartitionPolicy) {
+        let msgs = mp_msgs(NUM_CLIENTS, gsize, sec_param, |_, _| {});
+        let [(alice, pools_alice), (bob, pools_bob)] =
+            run_mp_with_policy(&msgs, gsize, sec_param, policy).await;
+        assert_eq!(pools_alice, (pools_bob.1, pools_bob.0));
+
+        for (output, (num_as_alice, num_as_bob)) in [(&alice, pools_alice), (&bob, pools_bob)] {
+            assert!(output.failed_clients.is_empty());
+            assert_eq!(output.num_ot_verified, num_as_alice);
+            assert_eq!(output.num_sqcorr_verified, NUM_CLIENTS);
+            assert_eq!(output.num_b2a_hash_verified, num_as_bob);
+            assert_eq!(output.num_a2s_hash_verified, Some(NUM_CLIENTS));
+            assert_eq!(output.num_ot_hash_verified, num_as_alice);
+        }
+        let expected_values = (0..NUM_CLIENTS)
+            .map(|uid| {
+                let input = client_input(uid, gsize).0;
+                input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let aggregate = alice
+            .aggregate()
+            .zip_map(&bob.aggregate(), |a, b| a.wrapping_add(*b));
+        assert_eq!(aggregate, batch_sum(&expected_values));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_single_input() {
+        let dims = ProtocolDims::new::<I>(1, DEFAULT_SEC_PARAM).unwrap();
+        assert!(dims.num_ot() < dims.num_additional());
+        check_all_verified(1, DEFAULT_SEC_PARAM, PartitionPolicy::Parity).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_input_ots_as_many_as_additional() {
+        const GSIZE: usize = 20;
+        const SEC_PARAM: usize = 6;
+        let dims = ProtocolDims::new::<I>(GSIZE, SEC_PARAM).unwrap();
+        assert_eq!(dims.num_ot(), dims.num_additional());
+        check_all_verified(GSIZE, SEC_PARAM, PartitionPolicy::Parity).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_input_ots_more_than_additional() {
+        const GSIZE: usize = 64;
+        let dims = ProtocolDims::new::<I>(GSIZE, DEFAULT_SEC_PARAM).unwrap();
+        assert!(dims.num_ot() > dims.num_additional());
+        check_all_verified(GSIZE, DEFAULT_SEC_PARAM, PartitionPolicy::Parity).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_extra_ots_ignored() {
+        let msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |_, client| {
+            let cot = &mut client.msg_bob.0 .0.po2_msg.cot;
+            let mut ts = cot.ts().to_vec();
+            ts.push(ts[0]);
+            *cot = B2ACOTToBob::new(cot.r_seed, ts, cot.tail().len() + 1);
+        });
+        let [(alice, pools_alice), (bob, pools_bob)] =
+            run_mp(&msgs, GSIZE, DEFAULT_SEC_PARAM).await;
+        for (output, (num_as_alice, _)) in [(&alice, pools_alice), (&bob, pools_bob)] {
+            assert!(output.failed_clients.is_empty());
+            assert_eq!(output.num_ot_verified, num_as_alice);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_ratio_partition() {
+        // Alice is OT sender for uids 0, 1, 2 and 4
+        check_all_verified(GSIZE, DEFAULT_SEC_PARAM, PartitionPolicy::Ratio(3, 1)).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_custom_partition() {
+        // Bob is OT sender for all clients but one
+        let policy = PartitionPolicy::Custom(Arc::new(|uid: ClientID| uid.id == 3));
+        check_all_verified(GSIZE, DEFAULT_SEC_PARAM, policy).await;
+    }
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind(("127.0.0.1", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// A whole round over localhost, where each server fetches the clients on
+    /// its client runtime, and runs the MPC connection and the pipeline on
+    /// its MPC runtime, like the server binary.
+    #[test]
+    fn test_round_on_split_runtimes() {
+        let client_ports = [free_port(), free_port()];
+        let mpc_port = free_port();
+        let servers = [true, false].map(|is_alice| {
+            let runtimes = ServerRuntimes::new(Some(1), Some(1)).unwrap();
+            let client_runtime = runtimes.client().clone();
+            let port = client_ports[!is_alice as usize];
+            let output = runtimes.mpc().spawn(async move {
+                let localhost = Ipv4Addr::LOCALHOST.into();
+                let peer = if is_alice {
+                    MpcConnection::new_as_alice(localhost, mpc_port, 2).await
+                } else {
+                    MpcConnection::new_as_bob_from(localhost, (localhost, mpc_port), 2).await
+                };
+                let fetch = async move {
+                    ClientData::<I, C, Sha256>::fetch(
+                        is_alice,
+                        PartitionPolicy::Parity,
+                        SocketAddr::new(localhost, port),
+                        NUM_CLIENTS,
+                        ClientToken::default(),
+                        GSIZE,
+                        None,
+                        Sha256::default,
+                        &client_keys(),
+                        None,
+                    )
+                    .await
+                };
+                let data = run_on(&client_runtime, fetch).await;
+                super::run::<I, A, C, _, _>(
+                    GSIZE,
+                    DEFAULT_SEC_PARAM,
+                    is_alice,
+                    &data,
+                    &peer,
+                    Sha256::default,
+                    false,
+                )
+                .await
+            });
+            (runtimes, output)
+        });
+
+        // the clients are another process, so they get their own runtime
+        let clients = Runtime::new().unwrap();
+        let msgs = mp_msgs(NUM_CLIENTS, GSIZE, DEFAULT_SEC_PARAM, |_, _| {});
+        let [(alice, _), (bob, _)] = clients.block_on(async {
+            let connections = init_meta_clients(
+                NUM_CLIENTS,
+                ("127.0.0.1", client_ports[0]),
+                ("127.0.0.1", client_ports[1]),
+                ClientToken::default(),
+                ClientConnectConfig::default(),
+            )
+            .await
+            .unwrap();
+            for ((to_alice, to_bob), (conn_alice, conn_bob)) in msgs.into_iter().zip(connections) {
+                let (alice, bob) = if PartitionPolicy::Parity.alice_is_ot_sender(conn_alice.uid()) {
+                    (conn_alice, conn_bob)
+                } else {
+                    (conn_bob, conn_alice)
+                };
+                alice.send_message_bytes(SendId::FIRST, to_alice);
+                bob.send_message_bytes(SendId::FIRST, to_bob);
+            }
+            let [(runtimes_alice, alice), (runtimes_bob, bob)] = servers;
+            [
+                (alice.await.unwrap(), runtimes_alice),
+                (bob.await.unwrap(), runtimes_bob),
+            ]
+        });
+
+        assert!(alice.failed_clients.is_empty());
+        assert!(bob.failed_clients.is_empty());
+        let expected_values = (0..NUM_CLIENTS)
+            .map(|uid| {
+                let input = client_input(uid, GSIZE).0;
+                input.iter().map(|x| x.as_uint::<A>()).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let aggregate = alice
+            .aggregate()
+            .zip_map(&bob.aggregate(), |a, b| a.wrapping_add(*b));
+        assert_eq!(aggregate, batch_sum(&expected_values));
+    }
+}
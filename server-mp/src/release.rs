@@ -0,0 +1,233 @@
+//! Release of the aggregate of a round, only if enough clients are included.
+//!
+//! An aggregate over a handful of clients gives away what each of them sent,
+//! so a round where fewer than `min_included` clients pass every check keeps
+//! no share of the aggregate. The included clients are the ones that did not
+//! fail on either server, which both servers agree on after B2A. The servers
+//! still exchange their thresholds and counts before releasing, so a peer
+//! started with another threshold, or counting other clients, stops the round
+//! instead of one server releasing alone.
+//!
+//! With client weights, the aggregate is the weighted sum, and clients of
+//! weight 0 are not included.
+
+use bridge::{id_tracker::ExchangeId, mpc_conn::MpcConnection, BridgeError};
+use crypto_primitives::uint::UInt;
+use serialize::UseCast;
+use thiserror::Error;
+
+use crate::pipeline::PipelineOutput;
+
+/// Exit code of a server that withheld the aggregate of its round.
+pub const WITHHELD_EXIT_CODE: i32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Release<A> {
+    /// My share of the (weighted) sum of the inputs of the included clients.
+    Released(Vec<A>),
+    /// Fewer than `min_included` clients are included, so no share of the
+    /// aggregate is computed.
+    Withheld {
+        num_included: usize,
+        min_included: usize,
+    },
+}
+
+impl<A> Release<A> {
+    pub fn is_withheld(&self) -> bool {
+        matches!(self, Release::Withheld { .. })
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ReleaseError {
+    #[error("peer requires {peer} included clients, but I require {mine}")]
+    ThresholdMismatch { mine: usize, peer: usize },
+    #[error("peer counts {peer} included clients, but I count {mine}")]
+    CountMismatch { mine: usize, peer: usize },
+    #[error("cannot exchange counts with peer: {0}")]
+    Bridge(#[from] BridgeError),
+}
+
+/// Release my share of the aggregate of `output` if at least `min_included`
+/// clients are included. `weights` of the clients, in uid order, make the
+/// aggregate a weighted sum. Only the threshold and the count are sent to the
+/// peer with message `msg_id`, never the aggregate.
+pub async fn release<A: UInt>(
+    output: &PipelineOutput<A>,
+    weights: Option<&[u64]>,
+    min_included: usize,
+    msg_id: ExchangeId,
+    peer: &MpcConnection,
+) -> Result<Release<A>, ReleaseError> {
+    let num_included = match weights {
+        Some(weights) => output.included_weights(weights).len(),
+        None => output.num_included(),
+    };
+    let [peer_min_included, peer_num_included] = peer
+        .exchange_message(msg_id, UseCast([min_included as u64, num_included as u64]))
+        .await?;
+    if peer_min_included != min_included as u64 {
+        return Err(ReleaseError::ThresholdMismatch {
+            mine: min_included,
+            peer: peer_min_included as usize,
+        });
+    }
+    if peer_num_included != num_included as u64 {
+        return Err(ReleaseError::CountMismatch {
+            mine: num_included,
+            peer: peer_num_included as usize,
+        });
+    }
+
+    if num_included < min_included {
+        Ok(Release::Withheld {
+            num_included,
+            min_included,
+        })
+    } else {
+        Ok(Release::Released(match weights {
+            Some(weights) => output.weighted_aggregate(weights),
+            None => output.aggregate(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bridge::{metrics::HEADER_SIZE, mpc_conn::MpcConnection, tcp_bridge::ClientID};
+    use tokio::net::TcpListener;
+
+    use super::{release, Release, ReleaseError};
+    use crate::pipeline::PipelineOutput;
+
+    type A = u64;
+
+    /// Output of a round of 5 clients, where clients 1 and 3 failed.
+    fn output(is_alice: bool) -> PipelineOutput<A> {
+        let arith_shares = (0..5u64)
+            .map(|uid| match uid {
+                1 | 3 => None,
+                _ if is_alice => Some(vec![uid, 10 * uid]),
+                _ => Some(vec![1, 1]),
+            })
+            .collect();
+        PipelineOutput {
+            arith_shares,
+            square_shares: vec![None; 5],
+            failed_clients: vec![ClientID::new(1), ClientID::new(3)],
+            num_ot_verified: 0,
+            num_sqcorr_verified: 0,
+            num_b2a_hash_verified: 0,
+            num_a2s_hash_verified: Some(0),
+            num_ot_hash_verified: 0,
+            num_sqcorr_hash_verified: Some(0),
+            num_bound_verified: None,
+            sqcorr_gen_time: 0.,
+            b2a_time: 0.,
+            corr_verify_time: 0.,
+            a2s_time: 0.,
+            hash_verify_time: 0.,
+        }
+    }
+
+    async fn mpc_pair() -> (MpcConnection, MpcConnection) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::join!(
+            MpcConnection::new_as_alice_with_listener(listener, 2),
+            MpcConnection::new_as_bob(addr, 2)
+        )
+    }
+
+    /// Release on both servers with thresholds `(Alice, Bob)`. Also returns
+    /// the number of bytes each server sent, as received by its peer: a send
+    /// may not be counted yet when the exchange returns, a receive is.
+    async fn release_both(
+        min_included: (usize, usize),
+    ) -> [(Result<Release<A>, ReleaseError>, usize); 2] {
+        release_both_weighted(min_included, None).await
+    }
+
+    async fn release_both_weighted(
+        min_included: (usize, usize),
+        weights: Option<&[u64]>,
+    ) -> [(Result<Release<A>, ReleaseError>, usize); 2] {
+        let (alice, bob) = mpc_pair().await;
+        let (output_alice, output_bob) = (output(true), output(false));
+        let (released_alice, released_bob) = tokio::join!(
+            release(&output_alice, weights, min_included.0, 12.into(), &alice),
+            release(&output_bob, weights, min_included.1, 12.into(), &bob)
+        );
+        [
+            (released_alice, bob.num_bytes_received()),
+            (released_bob, alice.num_bytes_received()),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_below_threshold_withheld() {
+        for (released, num_bytes_sent) in release_both((4, 4)).await {
+            assert_eq!(
+                released.unwrap(),
+                Release::Withheld {
+                    num_included: 3,
+                    min_included: 4
+                }
+            );
+            // only the threshold and the count, in one message
+            assert_eq!(num_bytes_sent, HEADER_SIZE as usize + 16);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_at_threshold_released() {
+        let [(alice, _), (bob, _)] = release_both((3, 3)).await;
+        let (Release::Released(alice), Release::Released(bob)) = (alice.unwrap(), bob.unwrap())
+        else {
+            panic!("aggregate withheld at the threshold");
+        };
+        assert_eq!(alice, vec![6, 60]);
+        assert_eq!(bob, vec![3, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_weighted_release() {
+        // client 2 has weight 0, so only clients 0 and 4 are included
+        let weights = [5, 1, 0, 1, 3];
+        let [(alice, _), (bob, _)] = release_both_weighted((2, 2), Some(&weights)).await;
+        let (Release::Released(alice), Release::Released(bob)) = (alice.unwrap(), bob.unwrap())
+        else {
+            panic!("aggregate withheld at the threshold");
+        };
+        let aggregate = alice
+            .iter()
+            .zip(&bob)
+            .map(|(a, b)| a.wrapping_add(*b))
+            .collect::<Vec<_>>();
+        // inputs are (uid + 1, 10 * uid + 1): 5 * (1, 1) + 3 * (5, 41)
+        assert_eq!(aggregate, vec![20, 128]);
+
+        let [(alice, _), _] = release_both_weighted((3, 3), Some(&weights)).await;
+        assert_eq!(
+            alice.unwrap(),
+            Release::Withheld {
+                num_included: 2,
+                min_included: 3
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_threshold_mismatch() {
+        let [(alice, _), (bob, _)] = release_both((3, 4)).await;
+        assert!(matches!(
+            alice,
+            Err(ReleaseError::ThresholdMismatch { mine: 3, peer: 4 })
+        ));
+        assert!(matches!(
+            bob,
+            Err(ReleaseError::ThresholdMismatch { mine: 4, peer: 3 })
+        ));
+    }
+}
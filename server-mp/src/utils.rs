@@ -1,29 +1,68 @@
 use bridge::id_tracker::{ExchangeId, IdGen, RecvId, SendId};
-use crypto_primitives::malpriv::MessageHash;
+use server_protocol::B2A_CHUNKS;
 use tracing::{error, info};
 
 /// Message IDs for various clients
 pub struct IdPool {
+    /// checks of the share commitments of all clients, before B2A: their
+    /// digests, then the clients to reject
+    pub exchange_commitments: (ExchangeId, ExchangeId),
     pub exchange_chi_seed: ExchangeId,
     pub exchange_t_seed: ExchangeId,
 
     pub otverify_a: Vec<RecvId>,
     pub otverify_b: Vec<SendId>,
 
-    pub b2a_a: Vec<SendId>,
-    pub b2a_b: Vec<RecvId>,
+    /// `B2A_CHUNKS` consecutive ids for the `us` of each client
+    pub b2a_a: Vec<Vec<SendId>>,
+    pub b2a_b: Vec<Vec<RecvId>>,
+
+    /// clients that failed on either server, exchanged after B2A
+    pub exchange_failures: ExchangeId,
 
     pub sqcorr: Vec<(ExchangeId, ExchangeId)>,
 
     pub a2s: Vec<ExchangeId>,
+
+    /// square correlations simulated by the servers with
+    /// `--simulate-server-corr`, a cost model only: the ROT correction from
+    /// Alice, the choice flips from Bob, and the `us` from Alice
+    pub sqcorr_gen_a: Vec<(SendId, RecvId, SendId)>,
+    pub sqcorr_gen_b: Vec<(RecvId, SendId, RecvId)>,
+
+    /// bound check of each client after A2S: the shares of `e`, then the
+    /// shares of the checks
+    pub bound_check: Vec<(ExchangeId, ExchangeId)>,
+
+    /// threshold and number of included clients, exchanged before the
+    /// aggregate is released. It does not depend on the pool sizes.
+    pub exchange_release: ExchangeId,
 }
 
 impl IdPool {
     pub fn build(alice_pool_size: usize, bob_pool_size: usize) -> Self {
+        Self::build_for_round(0, alice_pool_size, bob_pool_size)
+    }
+
+    /// Clients that failed on either server before round `round`, exchanged
+    /// before the others are counted, so it does not depend on the counts.
+    pub fn exchange_failed(round: u64) -> ExchangeId {
+        IdGen::for_round(round).next_exchange_id()
+    }
+
+    /// Ids of round `round` of a session, disjoint from the other rounds.
+    pub fn build_for_round(round: u64, alice_pool_size: usize, bob_pool_size: usize) -> Self {
         // manage message ids
         // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
 
-        let mut id = IdGen::new();
+        let mut id = IdGen::for_round(round);
+        // skip `exchange_failed`
+        id.next_exchange_id();
+        // before the counts, as the pool is rebuilt after the pipeline from
+        // the clients before `exchange_failed`
+        let exchange_release = id.next_exchange_id();
+
+        let exchange_commitments = (id.next_exchange_id(), id.next_exchange_id());
 
         let exchange_chi_seed = id.next_exchange_id();
         let exchange_t_seed = id.next_exchange_id();
@@ -36,12 +75,14 @@ impl IdPool {
             .collect::<Vec<_>>();
 
         let b2a_a = (0..alice_pool_size)
-            .map(|_| id.next_send_id())
+            .map(|_| (0..B2A_CHUNKS).map(|_| id.next_send_id()).collect())
             .collect::<Vec<_>>();
         let b2a_b = (0..bob_pool_size)
-            .map(|_| id.next_recv_id())
+            .map(|_| (0..B2A_CHUNKS).map(|_| id.next_recv_id()).collect())
             .collect::<Vec<_>>();
 
+        let exchange_failures = id.next_exchange_id();
+
         let sqcorr = (0..alice_pool_size + bob_pool_size)
             .map(|_| (id.next_exchange_id(), id.next_exchange_id()))
             .collect::<Vec<_>>();
@@ -50,47 +91,62 @@ impl IdPool {
             .map(|_| id.next_exchange_id())
             .collect::<Vec<_>>();
 
+        // each message in its own pass, so that the ids I send under are the
+        // ones the peer receives under, as OT receiver of the same clients
+        let corrections_a = (0..alice_pool_size)
+            .map(|_| id.next_send_id())
+            .collect::<Vec<_>>();
+        let corrections_b = (0..bob_pool_size)
+            .map(|_| id.next_recv_id())
+            .collect::<Vec<_>>();
+        let flips_a = (0..alice_pool_size)
+            .map(|_| id.next_recv_id())
+            .collect::<Vec<_>>();
+        let flips_b = (0..bob_pool_size)
+            .map(|_| id.next_send_id())
+            .collect::<Vec<_>>();
+        let us_a = (0..alice_pool_size)
+            .map(|_| id.next_send_id())
+            .collect::<Vec<_>>();
+        let us_b = (0..bob_pool_size)
+            .map(|_| id.next_recv_id())
+            .collect::<Vec<_>>();
+        let sqcorr_gen_a = corrections_a
+            .into_iter()
+            .zip(flips_a)
+            .zip(us_a)
+            .map(|((correction, flips), us)| (correction, flips, us))
+            .collect::<Vec<_>>();
+        let sqcorr_gen_b = corrections_b
+            .into_iter()
+            .zip(flips_b)
+            .zip(us_b)
+            .map(|((correction, flips), us)| (correction, flips, us))
+            .collect::<Vec<_>>();
+
+        let bound_check = (0..alice_pool_size + bob_pool_size)
+            .map(|_| (id.next_exchange_id(), id.next_exchange_id()))
+            .collect::<Vec<_>>();
+
         IdPool {
+            exchange_commitments,
             exchange_chi_seed,
             exchange_t_seed,
             otverify_a,
             otverify_b,
             b2a_a,
             b2a_b,
+            exchange_failures,
             sqcorr,
             a2s,
+            sqcorr_gen_a,
+            sqcorr_gen_b,
+            bound_check,
+            exchange_release,
         }
     }
 }
 
-pub struct HashPool<H: MessageHash> {
-    pub b2a_ab: Vec<H>,
-    pub a2s: Vec<H>,
-    pub ot_ba: Vec<H>,
-    pub sqcorr_ba: Vec<H>,
-    pub sqcorr_ab: Vec<H>,
-}
-
-impl<H: MessageHash> HashPool<H> {
-    pub fn init(alice_pool_size: usize, bob_pool_size: usize, hasher: impl Fn() -> H) -> Self {
-        let hasher = |_| hasher();
-        let b2a_ab = (0..bob_pool_size).map(hasher).collect::<Vec<_>>();
-        let ot_ba = (0..alice_pool_size).map(hasher).collect::<Vec<_>>();
-        let sqcorr_ab = (0..bob_pool_size).map(hasher).collect::<Vec<_>>();
-        let sqcorr_ba = (0..alice_pool_size).map(hasher).collect::<Vec<_>>();
-        let a2s = (0..alice_pool_size + bob_pool_size)
-            .map(hasher)
-            .collect::<Vec<_>>();
-
-        Self {
-            b2a_ab,
-            a2s,
-            ot_ba,
-            sqcorr_ab,
-            sqcorr_ba,
-        }
-    }
-}
 #[inline]
 pub fn log_verify_status(num_verified: usize, num_total: usize, name: &str) {
     if num_verified == num_total {
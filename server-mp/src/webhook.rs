@@ -0,0 +1,465 @@
+//! Optional notifications of round progress to an external HTTP endpoint.
+//!
+//! Events are queued and delivered by a background task, so the round never
+//! waits for the webhook. Each event is a JSON object POSTed to the configured
+//! url, signed with HMAC-SHA256 over the body. An event that still fails after
+//! the last attempt is appended to the dead-letter file, one JSON object per
+//! line, so it can be replayed later.
+//!
+//! Both servers send lifecycle events tagged with their role. Per-client
+//! outcomes are the same on both servers after failures are exchanged, so only
+//! the server selected by `WebhookConfig::emit_outcomes` sends them.
+
+use bridge::tcp_bridge::ClientID;
+use hmac::{Hmac, Mac};
+use hyper::{header, Body, Client, Method, Request, Uri};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tracing::{debug, warn};
+
+/// Version of the event payloads. Bump it on any incompatible change.
+pub const WEBHOOK_SCHEMA_VERSION: u32 = 1;
+/// Header with the hex HMAC-SHA256 of the body, as `sha256=<hex>`.
+pub const SIGNATURE_HEADER: &str = "x-elsa-signature";
+/// Maximum number of client outcomes in one event.
+pub const OUTCOME_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: Uri,
+    pub secret: Vec<u8>,
+    /// "alice" or "bob", included in every event.
+    pub role: &'static str,
+    /// Whether this server sends the per-client outcomes.
+    pub emit_outcomes: bool,
+    /// Number of tries of each event, including the first one.
+    pub max_attempts: usize,
+    /// Delay before the first retry, doubled after each retry.
+    pub retry_delay: Duration,
+    pub request_timeout: Duration,
+    pub dead_letter: PathBuf,
+}
+
+impl WebhookConfig {
+    /// Config with the default retry policy: 4 attempts, starting 500ms apart.
+    pub fn new(
+        url: Uri,
+        secret: Vec<u8>,
+        is_alice: bool,
+        emit_outcomes: bool,
+        dead_letter: PathBuf,
+    ) -> Self {
+        Self {
+            url,
+            secret,
+            role: if is_alice { "alice" } else { "bob" },
+            emit_outcomes,
+            max_attempts: 4,
+            retry_delay: Duration::from_millis(500),
+            request_timeout: Duration::from_secs(10),
+            dead_letter,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ClientOutcome {
+    pub uid: u64,
+    pub accepted: bool,
+    /// The aggregate of the round is withheld, so even the input of an
+    /// accepted client is not part of any released result.
+    pub round_withheld: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoundSummary {
+    pub num_clients: usize,
+    pub num_accepted: usize,
+    pub num_failed: usize,
+    pub b2a_time: f64,
+    pub corr_verify_time: f64,
+    pub a2s_time: f64,
+    pub hash_verify_time: f64,
+    /// Why the aggregate is not released, or `None` if it is.
+    pub withheld: Option<Withheld>,
+    /// Whether an insecure simulation helper was constructed in this run, so
+    /// that the numbers are not from a secure run.
+    pub simulation_helpers_used: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Withheld {
+    /// Fewer than `min_included` clients passed every check.
+    BelowThreshold {
+        num_included: usize,
+        min_included: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    RoundStarted { num_clients: usize, gsize: usize },
+    RoundCompleted { summary: RoundSummary },
+    ClientOutcomes { outcomes: Vec<ClientOutcome> },
+}
+
+/// What is POSTed: the event with its schema version and the sender.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    schema_version: u32,
+    role: &'static str,
+    /// Milliseconds since the unix epoch when the event is queued.
+    timestamp_ms: u128,
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+}
+
+/// Handle to the delivery task. Call `finish` before exiting, so queued events
+/// are delivered or dead-lettered.
+pub struct WebhookNotifier {
+    sender: mpsc::UnboundedSender<String>,
+    role: &'static str,
+    emit_outcomes: bool,
+    handle: JoinHandle<()>,
+}
+
+impl WebhookNotifier {
+    pub fn spawn(config: WebhookConfig) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let role = config.role;
+        let emit_outcomes = config.emit_outcomes;
+        let handle = tokio::spawn(deliver_all(config, receiver));
+        Self {
+            sender,
+            role,
+            emit_outcomes,
+            handle,
+        }
+    }
+
+    /// Queue `event`. Never blocks.
+    pub fn notify(&self, event: &WebhookEvent) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_millis())
+            .unwrap_or_default();
+        let payload = serde_json::to_string(&Envelope {
+            schema_version: WEBHOOK_SCHEMA_VERSION,
+            role: self.role,
+            timestamp_ms,
+            event,
+        })
+        .expect("webhook events are always serializable");
+        // the delivery task only quits after `finish`
+        self.sender.send(payload).unwrap_or(());
+    }
+
+    pub fn round_started(&self, num_clients: usize, gsize: usize) {
+        self.notify(&WebhookEvent::RoundStarted { num_clients, gsize });
+    }
+
+    pub fn round_completed(&self, summary: RoundSummary) {
+        self.notify(&WebhookEvent::RoundCompleted { summary });
+    }
+
+    /// Queue the outcomes of `uids` (in batches of `OUTCOME_BATCH_SIZE`), if
+    /// this server is the one sending outcomes. `failed_clients` is sorted, as
+    /// in `PipelineOutput`.
+    pub fn client_outcomes(
+        &self,
+        uids: &[ClientID],
+        failed_clients: &[ClientID],
+        round_withheld: bool,
+    ) {
+        if !self.emit_outcomes {
+            return;
+        }
+        for batch in uids.chunks(OUTCOME_BATCH_SIZE) {
+            let outcomes = batch
+                .iter()
+                .map(|uid| ClientOutcome {
+                    uid: uid.id,
+                    accepted: failed_clients.binary_search(uid).is_err(),
+                    round_withheld,
+                })
+                .collect();
+            self.notify(&WebhookEvent::ClientOutcomes { outcomes });
+        }
+    }
+
+    /// Wait until all queued events are delivered or dead-lettered.
+    pub async fn finish(self) {
+        drop(self.sender);
+        if let Err(e) = self.handle.await {
+            warn!("webhook delivery task failed: {}", e);
+        }
+    }
+}
+
+/// Hex HMAC-SHA256 of `body`, in the format of `SIGNATURE_HEADER`.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex = digest
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!("sha256={}", hex)
+}
+
+async fn deliver_all(config: WebhookConfig, mut receiver: mpsc::UnboundedReceiver<String>) {
+    let client = Client::new();
+    while let Some(payload) = receiver.recv().await {
+        if let Err(e) = deliver(&client, &config, &payload).await {
+            warn!(
+                "webhook event is undeliverable ({}), write to {}",
+                e,
+                config.dead_letter.display()
+            );
+            if let Err(e) = dead_letter(&config, &payload) {
+                warn!("failed to write webhook dead letter: {}", e);
+            }
+        }
+    }
+}
+
+/// POST `payload`, with retries. Returns the last error.
+async fn deliver(
+    client: &Client<hyper::client::HttpConnector>,
+    config: &WebhookConfig,
+    payload: &str,
+) -> Result<(), String> {
+    let signature = sign(&config.secret, payload.as_bytes());
+    let mut delay = config.retry_delay;
+    let mut last_error = String::new();
+    for attempt in 1..=config.max_attempts {
+        if attempt > 1 {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(config.url.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .body(Body::from(payload.to_owned()))
+            .map_err(|e| e.to_string())?;
+        last_error =
+            match tokio::time::timeout(config.request_timeout, client.request(request)).await {
+                Ok(Ok(response)) if response.status().is_success() => return Ok(()),
+                Ok(Ok(response)) => format!("status {}", response.status()),
+                Ok(Err(e)) => e.to_string(),
+                Err(_) => "request timed out".to_string(),
+            };
+        debug!(
+            "webhook attempt {}/{} failed: {}",
+            attempt, config.max_attempts, last_error
+        );
+    }
+    Err(last_error)
+}
+
+fn dead_letter(config: &WebhookConfig, payload: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.dead_letter)?;
+    writeln!(file, "{}", payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Response, Server, StatusCode,
+    };
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    const SECRET: &[u8] = b"test secret";
+
+    /// Requests received by the test server, as (signature, body).
+    type Received = Arc<Mutex<Vec<(String, String)>>>;
+
+    /// Start a webhook endpoint that answers 500 to the first `num_errors`
+    /// requests and 200 afterwards.
+    fn test_server(num_errors: usize) -> (SocketAddr, Received) {
+        let received = Received::default();
+        let make_service = {
+            let received = received.clone();
+            make_service_fn(move |_| {
+                let received = received.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        let received = received.clone();
+                        async move {
+                            let signature = request.headers()[SIGNATURE_HEADER]
+                                .to_str()
+                                .unwrap()
+                                .to_string();
+                            let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                            let mut received = received.lock().unwrap();
+                            received.push((signature, String::from_utf8(body.to_vec()).unwrap()));
+                            let status = if received.len() <= num_errors {
+                                StatusCode::INTERNAL_SERVER_ERROR
+                            } else {
+                                StatusCode::OK
+                            };
+                            Ok::<_, Infallible>(
+                                Response::builder()
+                                    .status(status)
+                                    .body(Body::empty())
+                                    .unwrap(),
+                            )
+                        }
+                    }))
+                }
+            })
+        };
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, received)
+    }
+
+    fn test_config(addr: SocketAddr, dead_letter: &str) -> WebhookConfig {
+        let dead_letter = std::env::temp_dir().join(dead_letter);
+        let _ = std::fs::remove_file(&dead_letter);
+        let mut config = WebhookConfig::new(
+            format!("http://{}/events", addr).parse().unwrap(),
+            SECRET.to_vec(),
+            true,
+            true,
+            dead_letter,
+        );
+        config.max_attempts = 3;
+        config.retry_delay = Duration::from_millis(10);
+        config
+    }
+
+    fn parse(body: &str) -> serde_json::Value {
+        serde_json::from_str(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_events_are_signed_and_batched() {
+        let (addr, received) = test_server(0);
+        let notifier = WebhookNotifier::spawn(test_config(addr, "elsa-webhook-signed"));
+        let uids = (0..OUTCOME_BATCH_SIZE as u64 + 2)
+            .map(ClientID::new)
+            .collect::<Vec<_>>();
+        notifier.round_started(uids.len(), 10);
+        notifier.client_outcomes(&uids, &[ClientID::new(1)], false);
+        notifier.finish().await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        for (signature, body) in received.iter() {
+            assert_eq!(signature, &sign(SECRET, body.as_bytes()));
+            assert_ne!(signature, &sign(b"other secret", body.as_bytes()));
+            let event = parse(body);
+            assert_eq!(event["schema_version"], WEBHOOK_SCHEMA_VERSION);
+            assert_eq!(event["role"], "alice");
+        }
+        let started = parse(&received[0].1);
+        assert_eq!(started["event"], "round_started");
+        assert_eq!(started["num_clients"], OUTCOME_BATCH_SIZE + 2);
+        assert_eq!(started["gsize"], 10);
+
+        let first = parse(&received[1].1);
+        assert_eq!(first["event"], "client_outcomes");
+        let outcomes = first["outcomes"].as_array().unwrap();
+        assert_eq!(outcomes.len(), OUTCOME_BATCH_SIZE);
+        assert_eq!(outcomes[0]["accepted"], true);
+        assert_eq!(outcomes[1]["uid"], 1);
+        assert_eq!(outcomes[1]["accepted"], false);
+        assert_eq!(outcomes[1]["round_withheld"], false);
+        let second = parse(&received[2].1);
+        assert_eq!(second["outcomes"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_outcomes_only_from_selected_server() {
+        let (addr, received) = test_server(0);
+        let mut config = test_config(addr, "elsa-webhook-selected");
+        config.emit_outcomes = false;
+        config.role = "bob";
+        let notifier = WebhookNotifier::spawn(config);
+        notifier.client_outcomes(&[ClientID::new(0)], &[], false);
+        notifier.round_completed(RoundSummary {
+            num_clients: 1,
+            num_accepted: 1,
+            num_failed: 0,
+            b2a_time: 0.,
+            corr_verify_time: 0.,
+            a2s_time: 0.,
+            hash_verify_time: 0.,
+            withheld: Some(Withheld::BelowThreshold {
+                num_included: 1,
+                min_included: 2,
+            }),
+            simulation_helpers_used: true,
+        });
+        notifier.finish().await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let completed = parse(&received[0].1);
+        assert_eq!(completed["event"], "round_completed");
+        assert_eq!(completed["role"], "bob");
+        assert_eq!(completed["summary"]["num_accepted"], 1);
+        let withheld = &completed["summary"]["withheld"];
+        assert_eq!(withheld["reason"], "below_threshold");
+        assert_eq!(withheld["num_included"], 1);
+        assert_eq!(withheld["min_included"], 2);
+        assert_eq!(completed["summary"]["simulation_helpers_used"], true);
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_server_error() {
+        let (addr, received) = test_server(2);
+        let config = test_config(addr, "elsa-webhook-retry");
+        let dead_letter = config.dead_letter.clone();
+        let notifier = WebhookNotifier::spawn(config);
+        notifier.round_started(1, 1);
+        notifier.finish().await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(received.iter().all(|(_, body)| body == &received[0].1));
+        assert!(!dead_letter.exists());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter() {
+        let (addr, received) = test_server(usize::MAX);
+        let config = test_config(addr, "elsa-webhook-dead-letter");
+        let dead_letter = config.dead_letter.clone();
+        let notifier = WebhookNotifier::spawn(config);
+        notifier.round_started(1, 1);
+        notifier.round_started(2, 1);
+        notifier.finish().await;
+
+        assert_eq!(received.lock().unwrap().len(), 6);
+        let lines = std::fs::read_to_string(&dead_letter).unwrap();
+        let events = lines.lines().map(parse).collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["num_clients"], 1);
+        assert_eq!(events[1]["num_clients"], 2);
+        std::fs::remove_file(dead_letter).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+//! Server side of the bits protocol, for clients whose inputs are single bits,
+//! see `crypto_primitives::message::bits`. It runs on server-po2 with
+//! `--protocol bits`, and its output is my share of the aggregate instead of
+//! arithmetic shares of every client.
+
+use bridge::{
+    client_dump::ClientRecording,
+    client_server::{with_uids, ClientSession, PartitionPolicy},
+    end_timer,
+    id_tracker::IdGen,
+    mpc_conn::MpcConnection,
+    start_timer,
+    tcp_bridge::ClientID,
+};
+use crypto_primitives::{
+    bits::PackedBits,
+    message::bits::{BitAggregation, ClientBitsMsgToAlice, ClientBitsMsgToBob},
+    utils::batch_sum,
+};
+use serialize::Communicate;
+use server_protocol::{self as mpc, ClientFailure};
+use tracing::warn;
+
+/// Messages of the clients of one round of the bits protocol.
+pub struct BitsData {
+    /// Uids of all clients, in order.
+    pub uids: Vec<ClientID>,
+    /// Uids of the clients of `msgs_alice`, in the same order.
+    pub uids_alice: Vec<ClientID>,
+    /// Uids of the clients of `msgs_bob`, in the same order.
+    pub uids_bob: Vec<ClientID>,
+    /// Decides which server is OT sender for each client.
+    pub policy: PartitionPolicy,
+
+    pub msgs_alice: Vec<ClientBitsMsgToAlice>,
+    pub msgs_bob: Vec<ClientBitsMsgToBob>,
+    /// Clients whose message cannot be deserialized, in uid order. They are
+    /// in none of the fields above, and are left out on both servers by
+    /// `run_round`.
+    pub failed: Vec<ClientID>,
+
+    pub comm_alice: usize,
+    pub comm_bob: usize,
+
+    pub time: f64,
+}
+
+impl BitsData {
+    /// Receive the messages of round `round` of `session`. Communication is
+    /// `ClientSession::round_comm`.
+    pub async fn fetch_round(session: &ClientSession, round: u64) -> Self {
+        let timer = start_timer!(|| "Client Phase 1");
+        let recording = session.fetch_round(round).await.unwrap();
+        let (comm_alice, comm_bob) = session.round_comm(&recording);
+        let mut data =
+            Self::from_recording(session.is_alice(), session.policy().clone(), recording);
+        data.time = end_timer!(timer).elapsed().as_secs_f64();
+        data.comm_alice = comm_alice;
+        data.comm_bob = comm_bob;
+        data
+    }
+
+    /// Deserialize the client messages, see `ClientData::from_recording`.
+    /// Panics if the recording is not split by `policy`.
+    pub fn from_recording(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        recording: ClientRecording,
+    ) -> Self {
+        assert!(
+            recording.matches_policy(is_alice, &policy),
+            "client messages are not split by partition policy {}",
+            policy
+        );
+        let comm_alice = recording.num_bytes_alice();
+        let comm_bob = recording.num_bytes_bob();
+        let parsed_alice = recording.alice.into_iter().map(|(uid, m)| {
+            let parsed = ClientBitsMsgToAlice::from_bytes_owned(m).map(|mut m| {
+                m.bind_to(uid.id);
+                m
+            });
+            (uid, parsed.map_err(ClientFailure::from))
+        });
+        let parsed_bob = recording.bob.into_iter().map(|(uid, m)| {
+            let parsed = ClientBitsMsgToBob::from_bytes_owned(m).map(|mut m| {
+                m.bind_to(uid.id);
+                m
+            });
+            (uid, parsed.map_err(ClientFailure::from))
+        });
+        let (uids_alice, msgs_alice, failed_alice) = mpc::split_parsed(parsed_alice);
+        let (uids_bob, msgs_bob, failed_bob) = mpc::split_parsed(parsed_bob);
+
+        let mut failed = Vec::with_capacity(failed_alice.len() + failed_bob.len());
+        for (uid, failure) in failed_alice.into_iter().chain(failed_bob) {
+            warn!("client {} failed: {}", uid.id, failure);
+            failed.push(uid);
+        }
+        failed.sort_unstable();
+        let mut uids = [&uids_alice[..], &uids_bob[..]].concat();
+        uids.sort_unstable();
+        BitsData {
+            uids,
+            uids_alice,
+            uids_bob,
+            policy,
+            msgs_alice,
+            msgs_bob,
+            failed,
+            comm_alice,
+            comm_bob,
+            time: 0.,
+        }
+    }
+
+    /// Only the clients that are not in `rejected`. Communication, time and
+    /// the failed clients are kept.
+    fn without_clients(&self, rejected: &[ClientID]) -> Self {
+        let keep = |uids: &[ClientID]| {
+            uids.iter()
+                .map(|uid| !rejected.contains(uid))
+                .collect::<Vec<_>>()
+        };
+        let (keep_alice, keep_bob) = (keep(&self.uids_alice), keep(&self.uids_bob));
+        let kept = |uids: &[ClientID], keep: &[bool]| {
+            uids.iter()
+                .zip(keep)
+                .filter(|(_, k)| **k)
+                .map(|(uid, _)| *uid)
+                .collect::<Vec<_>>()
+        };
+        BitsData {
+            uids: kept(&self.uids, &keep(&self.uids)),
+            uids_alice: kept(&self.uids_alice, &keep_alice),
+            uids_bob: kept(&self.uids_bob, &keep_bob),
+            policy: self.policy.clone(),
+            msgs_alice: self
+                .msgs_alice
+                .iter()
+                .zip(&keep_alice)
+                .filter(|(_, k)| **k)
+                .map(|(m, _)| m.clone())
+                .collect(),
+            msgs_bob: self
+                .msgs_bob
+                .iter()
+                .zip(&keep_bob)
+                .filter(|(_, k)| **k)
+                .map(|(m, _)| m.clone())
+                .collect(),
+            failed: self.failed.clone(),
+            comm_alice: self.comm_alice,
+            comm_bob: self.comm_bob,
+            time: self.time,
+        }
+    }
+}
+
+/// My share of the aggregate of the bits of all accepted clients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BitsShare {
+    /// Boolean share of the XOR of the bits, for `BitAggregation::Xor`.
+    Xor(PackedBits),
+    /// Arithmetic share of the number of clients with each bit set, for
+    /// `BitAggregation::Sum`.
+    Sum(Vec<u32>),
+}
+
+pub struct BitsOutput {
+    pub share: BitsShare,
+    /// Clients that failed on either server before the protocol, e.g. whose
+    /// message cannot be deserialized, or whose number of bits is not `gsize`
+    /// on either server, or whose COTs do not fit the aggregation, in uid
+    /// order.
+    pub rejected_clients: Vec<ClientID>,
+
+    pub aggregate_time: f64,
+}
+
+/// Aggregate the bits of the clients of `data` by `aggregation` with `peer`,
+/// in round `round` of a session. `is_alice` is the global role of this
+/// server. Every client must send exactly `gsize` bits.
+pub async fn run_round(
+    round: u64,
+    gsize: usize,
+    is_alice: bool,
+    aggregation: BitAggregation,
+    data: &BitsData,
+    peer: &MpcConnection,
+) -> BitsOutput {
+    let mut ids = IdGen::for_round(round);
+
+    // both servers leave out the clients that failed on either, before the
+    // message ids of the others are allocated
+    let (failed_on_peer, failed_clients) =
+        mpc::exchange_failed_uids(ids.next_exchange_id(), &data.uids, &data.failed, peer).await;
+    let parsed_data;
+    let data = if failed_on_peer.contains(&true) {
+        for (uid, _) in data.uids.iter().zip(&failed_on_peer).filter(|(_, f)| **f) {
+            warn!("client {} failed: {}", uid.id, ClientFailure::FailedOnPeer);
+        }
+        parsed_data = data.without_clients(&failed_clients);
+        &parsed_data
+    } else {
+        data
+    };
+
+    let exchange_gsizes = ids.next_exchange_id();
+    let b2a_a = data
+        .msgs_alice
+        .iter()
+        .map(|_| ids.next_send_id())
+        .collect::<Vec<_>>();
+    let b2a_b = data
+        .msgs_bob
+        .iter()
+        .map(|_| ids.next_recv_id())
+        .collect::<Vec<_>>();
+
+    // both servers reject the same clients, so the message ids of the others
+    // still match
+    let with_cot = aggregation == BitAggregation::Sum;
+    let from_alice = data
+        .msgs_alice
+        .iter()
+        .map(|m| (m.shares.len() == gsize && m.cot.is_some() == with_cot).then_some(gsize));
+    let from_bob = data.msgs_bob.iter().map(|m| {
+        let num_ots = m.cot.as_ref().map(|cot| cot.ts().len());
+        (m.shares.len() == gsize && num_ots == with_cot.then_some(gsize)).then_some(gsize)
+    });
+    let gsizes = data
+        .policy
+        .merge_msg(
+            is_alice,
+            with_uids(&data.uids_alice, from_alice),
+            with_uids(&data.uids_bob, from_bob),
+        )
+        .into_values()
+        .collect::<Vec<_>>();
+    let bad_gsizes = mpc::exchange_gsizes(exchange_gsizes, &gsizes, peer).await;
+    let mut rejected_clients = failed_clients;
+    for (uid, bad) in data.uids.iter().zip(bad_gsizes) {
+        if bad {
+            warn!(
+                "client {} rejected: number of bits does not match its message",
+                uid.id
+            );
+            rejected_clients.push(*uid);
+        }
+    }
+    rejected_clients.sort_unstable();
+    let accepted = |uid: &ClientID| !rejected_clients.contains(uid);
+
+    let timer = start_timer!(|| "Aggregate bits");
+    let share = match aggregation {
+        BitAggregation::Xor => {
+            let shares_alice =
+                with_uids(&data.uids_alice, data.msgs_alice.iter().map(|m| &m.shares));
+            let shares_bob = with_uids(&data.uids_bob, data.msgs_bob.iter().map(|m| &m.shares));
+            let zero = (0..gsize).map(|_| false).collect::<PackedBits>();
+            BitsShare::Xor(
+                shares_alice
+                    .into_iter()
+                    .chain(shares_bob)
+                    .filter(|(uid, _)| accepted(uid))
+                    .fold(zero, |acc, (_, shares)| &acc ^ shares),
+            )
+        },
+        BitAggregation::Sum => {
+            // I'm OT sender for the clients where I'm Alice, so the `us` are
+            // all sent before I wait for any
+            let mut counts = Vec::with_capacity(data.uids.len());
+            let mut send_handles = Vec::with_capacity(data.msgs_alice.len());
+            for ((uid, m), id) in data.uids_alice.iter().zip(&data.msgs_alice).zip(b2a_a) {
+                if accepted(uid) {
+                    let cot = m.cot.as_ref().unwrap();
+                    let (y0s, handle) =
+                        mpc::bits_b2a_alice::<u32>(id, &m.shares, cot, peer).unwrap();
+                    counts.push(y0s);
+                    send_handles.push(handle);
+                }
+            }
+            for ((uid, m), id) in data.uids_bob.iter().zip(&data.msgs_bob).zip(b2a_b) {
+                if accepted(uid) {
+                    let cot = m.cot.as_ref().unwrap();
+                    let y1s = mpc::bits_b2a_bob::<u32>(id, &m.shares, cot, peer)
+                        .await
+                        .unwrap();
+                    counts.push(y1s);
+                }
+            }
+            for handle in send_handles {
+                handle
+                    .await
+                    .expect("MPC connection closed before the message was sent");
+            }
+            let mut sum = batch_sum(&counts);
+            sum.resize(gsize, 0);
+            BitsShare::Sum(sum)
+        },
+    };
+    let aggregate_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    BitsOutput {
+        share,
+        rejected_clients,
+        aggregate_time,
+    }
+}
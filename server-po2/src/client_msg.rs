@@ -1,15 +1,74 @@
 //! Client interaction
-use bridge::{client_server::ClientsPool, end_timer, id_tracker::RecvId, start_timer};
+use bridge::{
+    client_dump::ClientRecording,
+    client_server::{join_uids, with_uids, ClientSession, PartitionPolicy},
+    end_timer, start_timer,
+    tcp_bridge::{ClientID, ClientToken},
+};
 use crypto_primitives::{
-    message::po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
+    bits::{Inputs1Hash, ShareCommitment},
+    cot::client::{B2ACOTToAliceReverse, B2ACOTToBobReverse},
+    message::po2::{
+        ClientPo2MsgToAlice, ClientPo2MsgToBob, ClientPo2SplitMsgToAlice, ClientPo2SplitMsgToBob,
+    },
     uint::UInt,
+    utils::without_rejected,
 };
-use std::sync::Arc;
+use rayon::prelude::*;
+use serialize::{Communicate, Lz4Compressed};
+use server_protocol::{self as mpc, ClientFailure, HeldShare};
+use std::{net::SocketAddr, sync::Arc};
 use tokio::net::TcpListener;
+use tracing::warn;
+
+/// How the clients serialize their messages. It must match the clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MsgFormat {
+    /// The messages to the OT receiver are `Lz4Compressed`.
+    pub compressed: bool,
+    /// The clients split their inputs, see `ClientPo2SplitMsgToAlice`.
+    pub split: bool,
+}
+
+/// Reverse B2A of clients that split their inputs, in the order of
+/// `po2_msgs_alice` and `po2_msgs_bob`.
+#[derive(Clone)]
+pub struct SplitData {
+    /// Index of the first input of the reverse B2A of each client.
+    pub splits_alice: Vec<usize>,
+    pub splits_bob: Vec<usize>,
+    pub cots_alice: Arc<[B2ACOTToAliceReverse]>,
+    pub cots_bob: Arc<[B2ACOTToBobReverse]>,
+}
 
 pub struct ClientData<I: UInt> {
+    /// Uids of all clients, in order.
+    pub uids: Vec<ClientID>,
+    /// Uids of the clients of `po2_msgs_alice`, in the same order.
+    pub uids_alice: Vec<ClientID>,
+    /// Uids of the clients of `po2_msgs_bob`, in the same order.
+    pub uids_bob: Vec<ClientID>,
+    /// Decides which server is OT sender for each client.
+    pub policy: PartitionPolicy,
+
     pub po2_msgs_alice: Arc<[ClientPo2MsgToAlice]>,
     pub po2_msgs_bob: Arc<[ClientPo2MsgToBob<I>]>,
+    /// Share commitments sent by the clients, in uid order. See
+    /// `server_protocol::exchange_commitments`.
+    pub commitments: Vec<ShareCommitment>,
+    /// Session the clients bind into their commitments, 0 unless set by
+    /// `with_session`.
+    pub session: u64,
+    /// Number of inputs of each client, in uid order: `gsize` of the message
+    /// to Alice, or the length of `inputs_1` of the message to Bob.
+    pub gsizes: Vec<usize>,
+    /// `Some` if the clients split their inputs. The messages then only have
+    /// the COTs of the inputs before the split.
+    pub split: Option<SplitData>,
+    /// Clients whose message cannot be deserialized, in uid order. They are
+    /// in none of the fields above, and are left out on both servers by
+    /// `pipeline::run_round`.
+    pub failed: Vec<ClientID>,
 
     pub comm_alice: usize,
     pub comm_bob: usize,
@@ -26,63 +85,420 @@ impl<I: UInt> ClientData<I> {
         self.po2_msgs_bob.len()
     }
 
-    pub async fn fetch(is_alice: bool, port: u16, num_clients: usize) -> Self {
-        let listener = TcpListener::bind(("0.0.0.0", port)).await.unwrap();
+    /// Accept `num_clients` clients that register with `token`, and receive
+    /// their messages. If `dump_path` is given, the raw messages are also
+    /// saved there for `replay`.
+    pub async fn fetch(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        addr: SocketAddr,
+        num_clients: usize,
+        token: ClientToken,
+        format: MsgFormat,
+        dump_path: Option<String>,
+    ) -> Self {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        Self::fetch_with_listener(
+            is_alice,
+            policy,
+            listener,
+            num_clients,
+            token,
+            format,
+            dump_path,
+        )
+        .await
+    }
+
+    /// Same as `fetch`, accepting the clients from a bound listener.
+    pub async fn fetch_with_listener(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        listener: TcpListener,
+        num_clients: usize,
+        token: ClientToken,
+        format: MsgFormat,
+        dump_path: Option<String>,
+    ) -> Self {
         // accepts clients connection
-        let clients = ClientsPool::new(num_clients, listener).await;
-        // load balancing: split the clients pool and ALICE pool and BOB pool, notice
-        // that this "Bob" is different from the "bob"
-        // for global server role.  Alice is OT sender, Bob is OT receiver.
-        let (clients_alice, clients_bob) = clients.split(is_alice);
+        let session = ClientSession::accept(is_alice, policy, listener, num_clients, token).await;
+        Self::fetch_round(&session, 0, format, dump_path).await
+    }
 
+    /// Receive the messages of round `round` of `session`. Communication is
+    /// `ClientSession::round_comm`.
+    pub async fn fetch_round(
+        session: &ClientSession,
+        round: u64,
+        format: MsgFormat,
+        dump_path: Option<String>,
+    ) -> Self {
         let timer = start_timer!(|| "Client Phase 1");
 
-        let alice_msg = {
-            let clients_alice = clients_alice.clone();
-            tokio::spawn(async move {
-                clients_alice
-                    .subscribe_and_get::<ClientPo2MsgToAlice>(RecvId::FIRST)
-                    .await
-                    .unwrap()
+        let recording = session.fetch_round(round).await.unwrap();
+        let (comm_alice, comm_bob) = session.round_comm(&recording);
+        if let Some(path) = dump_path {
+            recording.dump(path).unwrap();
+        }
+        let mut data = Self::from_recording(
+            session.is_alice(),
+            session.policy().clone(),
+            format,
+            recording,
+        );
+
+        data.time = end_timer!(timer).elapsed().as_secs_f64();
+
+        data.comm_alice = comm_alice;
+        data.comm_bob = comm_bob;
+        data
+    }
+
+    /// Load client messages saved by `fetch`, without accepting any client.
+    pub fn replay(is_alice: bool, policy: PartitionPolicy, format: MsgFormat, path: &str) -> Self {
+        Self::from_recording(
+            is_alice,
+            policy,
+            format,
+            ClientRecording::load(path).unwrap(),
+        )
+    }
+
+    /// Deserialize the client messages. A client whose message cannot be, e.g.
+    /// is not in `format`, is logged and added to `failed`. Communication is
+    /// the size of the messages, and time is zero. Panics if the recording is
+    /// not split by `policy`.
+    pub fn from_recording(
+        is_alice: bool,
+        policy: PartitionPolicy,
+        format: MsgFormat,
+        recording: ClientRecording,
+    ) -> Self {
+        assert!(
+            recording.matches_policy(is_alice, &policy),
+            "client messages are not split by partition policy {}",
+            policy
+        );
+        let comm_alice = recording.num_bytes_alice();
+        let comm_bob = recording.num_bytes_bob();
+
+        let parsed_alice = recording
+            .alice
+            .into_par_iter()
+            .map(|(uid, m)| {
+                let parsed = if format.split {
+                    ClientPo2SplitMsgToAlice::from_bytes_owned(m).map(|mut m| {
+                        m.bind_to(uid.id);
+                        (m.po2_msg, Some((m.split as usize, m.reverse)))
+                    })
+                } else {
+                    ClientPo2MsgToAlice::from_bytes_owned(m).map(|mut m| {
+                        m.bind_to(uid.id);
+                        (m, None)
+                    })
+                };
+                (uid, parsed.map_err(ClientFailure::from))
             })
-        };
-        let bob_msg = {
-            let clients_bob = clients_bob.clone();
-            tokio::spawn(async move {
-                clients_bob
-                    .subscribe_and_get::<ClientPo2MsgToBob<I>>(RecvId::FIRST)
-                    .await
-                    .unwrap()
+            .collect::<Vec<_>>();
+        let parsed_bob = recording
+            .bob
+            .into_par_iter()
+            .map(|(uid, m)| {
+                let parsed = match (format.compressed, format.split) {
+                    (compressed, false) => if compressed {
+                        Lz4Compressed::<ClientPo2MsgToBob<I>>::from_bytes_owned(m)
+                    } else {
+                        ClientPo2MsgToBob::<I>::from_bytes_owned(m)
+                    }
+                    .map(|mut m| {
+                        m.bind_to(uid.id);
+                        (m, None)
+                    }),
+                    (compressed, true) => if compressed {
+                        Lz4Compressed::<ClientPo2SplitMsgToBob<I>>::from_bytes_owned(m)
+                    } else {
+                        ClientPo2SplitMsgToBob::<I>::from_bytes_owned(m)
+                    }
+                    .map(|mut m| {
+                        m.bind_to(uid.id);
+                        (m.po2_msg, Some((m.split as usize, m.reverse)))
+                    }),
+                };
+                (uid, parsed.map_err(ClientFailure::from))
             })
-        };
-        let (alice_msg, bob_msg) = tokio::join!(alice_msg, bob_msg);
-        let (alice_msg, bob_msg) = (alice_msg.unwrap(), bob_msg.unwrap());
-
-        let mut po2_msgs_alice = Vec::with_capacity(alice_msg.len());
+            .collect::<Vec<_>>();
+        let (uids_alice, msgs_alice, failed_alice) = mpc::split_parsed(parsed_alice);
+        let (uids_bob, msgs_bob, failed_bob) = mpc::split_parsed(parsed_bob);
+        let (po2_msgs_alice, reverse_alice): (Vec<_>, Vec<_>) = msgs_alice.into_iter().unzip();
+        let (po2_msgs_bob, reverse_bob): (Vec<_>, Vec<_>) = msgs_bob.into_iter().unzip();
 
-        for m in alice_msg {
-            po2_msgs_alice.push(m);
+        let mut failed = Vec::with_capacity(failed_alice.len() + failed_bob.len());
+        for (uid, failure) in failed_alice.into_iter().chain(failed_bob) {
+            warn!("client {} failed: {}", uid.id, failure);
+            failed.push(uid);
         }
+        failed.sort_unstable();
+        let mut uids = [&uids_alice[..], &uids_bob[..]].concat();
+        uids.sort_unstable();
 
-        let po2_msgs_alice = Arc::<[_]>::from(po2_msgs_alice.into_boxed_slice());
+        let split = format.split.then(|| {
+            let (splits_alice, cots_alice): (Vec<_>, Vec<_>) =
+                reverse_alice.into_iter().flatten().unzip();
+            let (splits_bob, cots_bob): (Vec<_>, Vec<_>) =
+                reverse_bob.into_iter().flatten().unzip();
+            SplitData {
+                splits_alice,
+                splits_bob,
+                cots_alice: cots_alice.into(),
+                cots_bob: cots_bob.into(),
+            }
+        });
+        let commitments = policy
+            .merge_msg(
+                is_alice,
+                with_uids(&uids_alice, po2_msgs_alice.iter().map(|m| m.commitment)),
+                with_uids(&uids_bob, po2_msgs_bob.iter().map(|m| m.commitment)),
+            )
+            .into_values()
+            .collect();
+        let gsizes = policy
+            .merge_msg(
+                is_alice,
+                with_uids(&uids_alice, po2_msgs_alice.iter().map(|m| m.gsize as usize)),
+                with_uids(&uids_bob, po2_msgs_bob.iter().map(|m| m.inputs_1.len())),
+            )
+            .into_values()
+            .collect();
 
-        let mut po2_msgs_bob = Vec::with_capacity(bob_msg.len());
-        for m in bob_msg {
-            po2_msgs_bob.push(m);
+        Self {
+            uids,
+            uids_alice,
+            uids_bob,
+            policy,
+            po2_msgs_alice: po2_msgs_alice.into(),
+            po2_msgs_bob: po2_msgs_bob.into(),
+            commitments,
+            session: 0,
+            gsizes,
+            split,
+            failed,
+            comm_alice,
+            comm_bob,
+            time: 0.,
         }
+    }
 
-        let po2_msgs_bob = Arc::<[_]>::from(po2_msgs_bob);
+    /// Same clients, whose commitments are bound to `session`.
+    pub fn with_session(mut self, session: u64) -> Self {
+        self.session = session;
+        self
+    }
 
-        let time = end_timer!(timer).elapsed().as_secs_f64();
+    /// The share of each client I hold, in uid order, to check its
+    /// commitment with `server_protocol::exchange_commitments`.
+    pub fn held_shares(&self, is_alice: bool) -> Vec<HeldShare> {
+        self.policy
+            .merge_msg(
+                is_alice,
+                with_uids(
+                    &self.uids_alice,
+                    self.po2_msgs_alice
+                        .iter()
+                        .map(|m| HeldShare::Seed(m.inputs_0)),
+                ),
+                with_uids(
+                    &self.uids_bob,
+                    self.po2_msgs_bob
+                        .iter()
+                        .map(|m| HeldShare::Inputs1(Inputs1Hash::new(m.salt, &m.inputs_1))),
+                ),
+            )
+            .into_values()
+            .collect()
+    }
 
-        let comm_alice = clients_alice.num_bytes_received_from_all();
-        let comm_bob = clients_bob.num_bytes_received_from_all();
+    /// Only the clients that are not `rejected`, which is in uid order.
+    /// Communication, time and the failed clients are kept.
+    pub fn without_clients(&self, is_alice: bool, rejected: &[bool]) -> Self {
+        let rejected = rejected.iter().copied();
+        let (rejected_a, rejected_b) = self
+            .policy
+            .split_iter(is_alice, with_uids(&self.uids, rejected.clone()));
+        let rejected_a = join_uids(&self.uids_alice, rejected_a);
+        let rejected_b = join_uids(&self.uids_bob, rejected_b);
         Self {
-            po2_msgs_alice,
-            po2_msgs_bob,
-            comm_alice,
-            comm_bob,
-            time,
+            uids: without_rejected(self.uids.iter().copied(), rejected.clone()),
+            uids_alice: without_rejected(
+                self.uids_alice.iter().copied(),
+                rejected_a.iter().copied(),
+            ),
+            uids_bob: without_rejected(self.uids_bob.iter().copied(), rejected_b.iter().copied()),
+            policy: self.policy.clone(),
+            po2_msgs_alice: without_rejected(
+                self.po2_msgs_alice.iter().cloned(),
+                rejected_a.iter().copied(),
+            )
+            .into(),
+            po2_msgs_bob: without_rejected(
+                self.po2_msgs_bob.iter().cloned(),
+                rejected_b.iter().copied(),
+            )
+            .into(),
+            commitments: without_rejected(self.commitments.iter().copied(), rejected.clone()),
+            session: self.session,
+            gsizes: without_rejected(self.gsizes.iter().copied(), rejected),
+            split: self.split.as_ref().map(|split| SplitData {
+                splits_alice: without_rejected(
+                    split.splits_alice.iter().copied(),
+                    rejected_a.iter().copied(),
+                ),
+                splits_bob: without_rejected(
+                    split.splits_bob.iter().copied(),
+                    rejected_b.iter().copied(),
+                ),
+                cots_alice: without_rejected(
+                    split.cots_alice.iter().cloned(),
+                    rejected_a.iter().copied(),
+                )
+                .into(),
+                cots_bob: without_rejected(
+                    split.cots_bob.iter().cloned(),
+                    rejected_b.iter().copied(),
+                )
+                .into(),
+            }),
+            failed: self.failed.clone(),
+            comm_alice: self.comm_alice,
+            comm_bob: self.comm_bob,
+            time: self.time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use block::Block;
+    use bridge::{
+        client_server::PartitionPolicy,
+        id_tracker::SendId,
+        metrics::HEADER_SIZE,
+        tcp_bridge::{ClientID, ClientToken, TcpConnection},
+    };
+    use crypto_primitives::{
+        bits::{BitsLE, SeededInputShare, ShareCommitment, ShareSalt},
+        cot::{
+            client::{B2ACOTToAlice, B2ACOTToBob, DEFAULT_SEC_PARAM},
+            dims::ProtocolDims,
+            ChoiceSeed,
+        },
+        message::po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
+    };
+    use serialize::Communicate;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{ClientData, MsgFormat};
+
+    const NUM_CLIENTS: usize = 6;
+    const GSIZE: usize = 4;
+
+    async fn run_client(port: u16, uid: u64) {
+        let socket = loop {
+            match TcpStream::connect(("localhost", port)).await {
+                Ok(s) => break s,
+                Err(_) => tokio::time::sleep(tokio::time::Duration::from_millis(10)).await,
+            }
+        };
+        let (conn, wait) =
+            TcpConnection::new_client_side(socket, ClientID::new(uid), ClientToken::default());
+        wait.await.unwrap();
+        // the test server is Alice, so clients with even uid send to it as Alice
+        let sent = if ClientID::new(uid).is_even() {
+            let msg = ClientPo2MsgToAlice::new(
+                GSIZE,
+                SeededInputShare(uid),
+                ShareCommitment::default(),
+                B2ACOTToAlice::default(),
+            );
+            conn.send_message(SendId::FIRST, msg).unwrap()
+        } else {
+            let mut inputs_1 = vec![BitsLE(0); GSIZE];
+            inputs_1[0] = BitsLE(uid as u8);
+            let dims = ProtocolDims::new::<u8>(GSIZE, DEFAULT_SEC_PARAM).unwrap();
+            let cot = B2ACOTToBob::new(
+                ChoiceSeed(0),
+                vec![Block::default(); dims.num_total()],
+                dims.num_additional(),
+            );
+            let msg = ClientPo2MsgToBob::<u8>::new(
+                inputs_1,
+                ShareCommitment::default(),
+                ShareSalt::default(),
+                cot,
+            );
+            conn.send_message(SendId::FIRST, msg).unwrap()
+        };
+        sent.await.unwrap();
+    }
+
+    fn to_bytes<M: Communicate>(msgs: &[M]) -> Vec<Vec<u8>> {
+        msgs.iter().map(|m| m.into_bytes_owned().to_vec()).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dump_and_replay() {
+        let port = TcpListener::bind(("localhost", 0))
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let path = std::env::temp_dir().join(format!("elsa-client-dump-{}", port));
+        let path_str = path.to_str().unwrap().to_string();
+
+        let server = tokio::spawn(async move {
+            ClientData::<u8>::fetch(
+                true,
+                PartitionPolicy::Parity,
+                SocketAddr::from(([127, 0, 0, 1], port)),
+                NUM_CLIENTS,
+                ClientToken::default(),
+                MsgFormat::default(),
+                Some(path_str),
+            )
+            .await
+        });
+        for uid in 0..NUM_CLIENTS as u64 {
+            tokio::spawn(run_client(port, uid));
         }
+        let live = server.await.unwrap();
+        let replayed = ClientData::<u8>::replay(
+            true,
+            PartitionPolicy::Parity,
+            MsgFormat::default(),
+            path.to_str().unwrap(),
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(live.num_clients_as_alice(), NUM_CLIENTS / 2);
+        assert_eq!(live.num_clients_as_bob(), NUM_CLIENTS / 2);
+        assert_eq!(
+            to_bytes(&live.po2_msgs_alice),
+            to_bytes(&replayed.po2_msgs_alice)
+        );
+        assert_eq!(
+            to_bytes(&live.po2_msgs_bob),
+            to_bytes(&replayed.po2_msgs_bob)
+        );
+        // live communication also counts the registration message (uid and
+        // token) of each client, and the headers of both messages
+        let overhead_per_client = 2 * HEADER_SIZE as usize
+            + std::mem::size_of::<ClientID>()
+            + std::mem::size_of::<ClientToken>();
+        let overhead = overhead_per_client * NUM_CLIENTS / 2;
+        assert_eq!(live.comm_alice, replayed.comm_alice + overhead);
+        assert_eq!(live.comm_bob, replayed.comm_bob + overhead);
+        assert_eq!(replayed.time, 0.);
     }
 }
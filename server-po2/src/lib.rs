@@ -1,2 +1,4 @@
+pub mod bits;
 pub mod client_msg;
-pub mod mpc;
+pub mod pipeline;
+pub mod utils;
@@ -1,175 +1,387 @@
-use crate::{client_msg::ClientData, utils::IdPool};
-use bin_utils::server::{InputSize, Options};
-use bridge::{end_timer, mpc_conn::MpcConnection, start_timer};
-use crypto_primitives::{
-    cot::{client::num_additional_ot_needed, server::sample_chi},
-    uint::UInt,
-    utils::{iter_arc, log_verify_status, Hook},
+use bin_utils::{
+    interrupt, panic_report,
+    runtime::run_on,
+    server::{
+        ClientRecordOptions, InputSize, LegacyCsv, MpcCommSnapshot, Options, SessionOptions,
+        TlsOptions,
+    },
+    simulation,
 };
-use rayon::prelude::*;
-use std::sync::Arc;
-use tokio::runtime::Runtime;
-use tracing::warn;
-
-mod client_msg;
-mod mpc;
-mod utils;
+use bridge::{
+    client_server::ClientSession,
+    mpc_conn::{MpcConnection, Priority},
+    perf_trace::TimerScope,
+};
+use clap::{Arg, ArgMatches};
+use crypto_primitives::{const_assert, message::bits::BitAggregation, uint::UInt};
+use server_po2::{
+    bits::{self, BitsData},
+    client_msg::{ClientData, MsgFormat},
+    pipeline,
+};
+use tokio::{net::TcpListener, runtime::Handle};
+use tracing::info;
+use zeroize::Zeroize;
 
 type A = u64;
+// B2A shifts the highest bit of an input within `A`
+const_assert!(A::NUM_BITS >= InputSize::WIDEST.num_bits());
 
-const CHI_SEED: u64 = 123456;
+const LEGACY_CSV: LegacyCsv = LegacyCsv {
+    header: "client comm, MPC comm, client phase 1, client phase 2, OT + B2A, Correlation verify, A2S, Hash verify",
+    mpc_comm_sent: false,
+    phases: [Some("client"), None, Some("b2a"), None, None, None],
+};
 
-async fn main_with_options<I: UInt>(options: Options) {
-    tracing_subscriber::fmt()
-        .pretty()
-        .with_max_level(options.log_level)
-        .init();
+const BITS_LEGACY_CSV: LegacyCsv = LegacyCsv {
+    phases: [Some("client"), None, Some("aggregate"), None, None, None],
+    ..LEGACY_CSV
+};
+
+/// Protocol run by this server, see `--protocol`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ServerProtocol {
+    Po2,
+    /// Clients with single-bit inputs, see `server_po2::bits`.
+    Bits(BitAggregation),
+}
+
+/// Command line options of this server, on top of `Options`.
+struct Po2Options {
+    record: ClientRecordOptions,
+    session: SessionOptions,
+    tls: TlsOptions,
+    simulate_server_ot: bool,
+    format: MsgFormat,
+    protocol: ServerProtocol,
+}
+
+impl Po2Options {
+    fn args<'a>() -> impl IntoIterator<Item = Arg<'a>> {
+        ClientRecordOptions::args()
+            .into_iter()
+            .chain(SessionOptions::args())
+            .chain(TlsOptions::args())
+            .chain(cfg!(feature = "simulation-helpers").then(|| {
+                simulation::insecure_arg(
+                    "simulate_server_ot",
+                    "simulate-server-ot",
+                    "INSECURE, benchmarking only: simulate B2A COTs generated by the servers, expanding them from a seed both know instead of running an OT extension (none is implemented), and ignore the ones sent by clients (which may send an empty `ts`)",
+                )
+            }))
+            .chain([
+            Arg::new("compress")
+                .long("compress")
+                .help("decompress the messages to the OT receiver, for clients run with --compress"),
+            Arg::new("split_b2a")
+                .long("split-b2a")
+                .help("expect clients that split their inputs, for clients run with --split-b2a: each server is OT sender for the inputs on its side of the split"),
+            Arg::new("protocol")
+                .long("protocol")
+                .takes_value(true)
+                .possible_values(["po2", "bits"])
+                .default_value("po2")
+                .help("po2, or bits for client-bits, where gsize is the number of bits of each client"),
+            Arg::new("bit_aggregation")
+                .long("aggregation")
+                .takes_value(true)
+                .default_value("sum")
+                .help("how to aggregate the bits with --protocol bits: xor, or sum into u32 counters (must match the clients)"),
+        ])
+    }
 
-    // connect to peer
-    let peer = if !cfg!(feature = "no-comm") {
-        if options.is_bob {
-            // I'm Bob and need a complete address of alice.
-            MpcConnection::new_as_bob(&options.mpc_addr, options.num_mpc_sockets).await
-        } else {
-            // I'm Alice and I need a port number of alice.
-            let mpc_addr =
-                u16::from_str_radix(&options.mpc_addr, 10).expect("invalid mpc_addr as port");
-            MpcConnection::new_as_alice(mpc_addr, options.num_mpc_sockets).await
+    fn parse(matches: &ArgMatches) -> Self {
+        let record = ClientRecordOptions::parse(matches);
+        let session = SessionOptions::parse(matches);
+        session.check_record(&record);
+        let protocol = match matches.value_of("protocol").unwrap() {
+            "bits" => ServerProtocol::Bits(
+                matches
+                    .value_of("bit_aggregation")
+                    .unwrap()
+                    .parse()
+                    .unwrap(),
+            ),
+            _ => ServerProtocol::Po2,
+        };
+        Self {
+            record,
+            session,
+            tls: TlsOptions::parse(matches),
+            simulate_server_ot: cfg!(feature = "simulation-helpers")
+                && matches.is_present("simulate_server_ot"),
+            format: MsgFormat {
+                compressed: matches.is_present("compress"),
+                split: matches.is_present("split_b2a"),
+            },
+            protocol,
         }
+    }
+}
+
+/// Connect to the peer server, with the network profile of `options`.
+async fn connect_peer<T>(options: &Options<T>) -> MpcConnection {
+    #[cfg(not(feature = "no-comm"))]
+    let peer = if options.is_bob {
+        // I'm Bob and need a complete address of alice.
+        MpcConnection::new_as_bob_from(
+            options.mpc_bind_addr,
+            &options.mpc_addr,
+            options.num_mpc_sockets,
+        )
+        .await
     } else {
-        warn!("no-comm feature is enabled, so no communication with peers");
-        MpcConnection::dummy()
+        // I'm Alice and I need a port number of alice.
+        let mpc_addr = options
+            .mpc_addr
+            .parse::<u16>()
+            .expect("invalid mpc_addr as port");
+        MpcConnection::new_as_alice(options.mpc_bind_addr, mpc_addr, options.num_mpc_sockets).await
     };
-
-    let client_data =
-        ClientData::<I>::fetch(options.is_alice(), options.client_port, options.num_clients).await;
-
-    // manage message ids
-    // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
-    let ids = IdPool::build(
-        client_data.num_clients_as_alice(),
-        client_data.num_clients_as_bob(),
-    );
-
-    let timer = start_timer!(|| "OT Verify + B2A");
-
-    // first, sample chi that is used to generate all OTs
-    let num_ot = options.gsize * I::NUM_BITS as usize;
-    let num_additional_ot = num_additional_ot_needed(num_ot);
-    let chi = Arc::new(sample_chi(num_ot + num_additional_ot, CHI_SEED));
-
-    // OT Verify Alice Receive (Start)
-    let ot_alice_hook = Hook::new();
-    let ot_ba_handles = iter_arc(&client_data.po2_msgs_alice)
-        .zip(ids.otverify_a)
-        .map(|(c_msg, id)| {
-            let peer = peer.clone();
-            let chi = chi.clone();
-            tokio::spawn(async move { mpc::ot_verify_alice::<I>(id, &c_msg.cot, chi, peer).await })
-        })
-        .collect::<Vec<_>>();
-
-    // OT verify Bob send (Start)
-
-    let ot_bob_hook = Hook::new();
-    let otverify_bob_handles = {
-        let peer = peer.clone();
-        let chi = chi.clone();
-        let c_msg = client_data.po2_msgs_bob.clone();
-        tokio::task::spawn_blocking(move || {
-            c_msg
-                .par_iter()
-                .zip(ids.otverify_b)
-                .map(|(c_msg, id)| mpc::ot_verify_bob(id, c_msg, &peer, chi.clone(), options.gsize))
-                .collect::<Vec<_>>()
-        })
+    #[cfg(feature = "no-comm")]
+    let peer = {
+        tracing::warn!("no-comm feature is enabled, so no communication with peers");
+        MpcConnection::dummy()
     };
+    peer.with_network_profile(options.mpc_network)
+}
 
-    // B2A Bob Receive (Start)
-    let b2a_bob_hook = Hook::new();
-    let b2a_bob_handles = iter_arc(&client_data.po2_msgs_bob)
-        .zip(ids.b2a_b)
-        .map(|(c_msg, id)| {
-            let peer = peer.clone();
-            tokio::spawn(async move { mpc::b2a_bob::<_, A>(id, &*c_msg, peer).await })
-        })
-        .collect::<Vec<_>>();
-
-    // OT Verify Alice Receive (Complete)
-    let mut qs_per_client = Vec::with_capacity(client_data.num_clients_as_alice());
-    let mut num_verified_success = 0;
-    for alice_handle in ot_ba_handles {
-        let (qs, v) = alice_handle.await.unwrap();
-        qs_per_client.push(qs);
-        num_verified_success += v as usize;
-    }
-    log_verify_status(
-        num_verified_success,
-        client_data.num_clients_as_alice(),
-        "OT Verify Alice",
-    );
-    ot_alice_hook.done();
-
-    // B2A Alice Send (Start)
-    let b2a_alice_hook = Hook::new();
-    let b2a_alice_handles = tokio::task::block_in_place(|| {
-        client_data
-            .po2_msgs_alice
-            .par_iter()
-            .zip(qs_per_client)
-            .zip(ids.b2a_a)
-            .map(|((c_msg, qs), id)| mpc::b2a_alice::<I, A>(id, options.gsize, c_msg, &qs, &peer))
-            .collect::<Vec<_>>()
-    });
-
-    // B2A Bob Receive (Complete)
-    let mut bob_arith_shares = Vec::with_capacity(client_data.num_clients_as_bob());
-    for bob_handle in b2a_bob_handles {
-        let bob_arith_share = bob_handle.await.unwrap();
-        bob_arith_shares.push(bob_arith_share);
+async fn main_with_options<I: UInt>(options: Options<Po2Options>, client_runtime: Handle) {
+    tracing_subscriber::fmt()
+        .pretty()
+        .with_max_level(options.log_level)
+        .init();
+    options.log_threads();
+    if options.custom_args.simulate_server_ot {
+        simulation::warn_insecure_flag("simulate-server-ot");
     }
-    b2a_bob_hook.done();
 
-    // B2A Alice Send (Complete)
-    let mut alice_arith_shares = Vec::with_capacity(client_data.num_clients_as_alice());
-    for (s, handle) in b2a_alice_handles {
-        handle.await.unwrap();
-        alice_arith_shares.push(s);
+    let peer = connect_peer(&options).await;
+    let _metrics_server = options.serve_metrics(&peer).await;
+
+    // accepted in the first round, and kept for the others
+    let mut session: Option<ClientSession> = None;
+    let session_options = &options.custom_args.session;
+    let rounds = session_options.rounds;
+    for round in 0..rounds {
+        let comm_before = MpcCommSnapshot::take(&peer);
+        let round_future = async {
+            let client_data = match &options.custom_args.record.replay_clients {
+                Some(path) => ClientData::<I>::replay(
+                    options.is_alice(),
+                    options.partition.clone(),
+                    options.custom_args.format,
+                    path,
+                ),
+                None => {
+                    let clients = match &session {
+                        Some(clients) => clients.clone(),
+                        None => {
+                            let listener = TcpListener::bind(options.client_listen_addr())
+                                .await
+                                .unwrap();
+                            let accept = ClientSession::accept_with_tls(
+                                options.is_alice(),
+                                options.partition.clone(),
+                                listener,
+                                options.num_clients,
+                                options.client_token,
+                                options.custom_args.tls.server_tls.clone(),
+                            );
+                            let accept = async move { accept.await.with_rounds(rounds) };
+                            session
+                                .insert(run_on(&client_runtime, accept).await)
+                                .clone()
+                        },
+                    };
+                    let format = options.custom_args.format;
+                    let dump_path = options.custom_args.record.dump_clients.clone();
+                    let fetch = async move {
+                        ClientData::<I>::fetch_round(&clients, round, format, dump_path).await
+                    };
+                    run_on(&client_runtime, fetch).await
+                },
+            }
+            .with_session(options.session);
+            options
+                .metrics
+                .set_clients_connected(client_data.uids.len() + client_data.failed.len());
+
+            // clients may send fewer inputs, see `pipeline::run`
+            let output = options
+                .client_gate()
+                .scope(pipeline::run_round::<I, A>(
+                    round,
+                    options.gsize,
+                    options.sec_param,
+                    options.is_alice(),
+                    &client_data,
+                    &peer,
+                    options.custom_args.simulate_server_ot,
+                ))
+                .await;
+            (client_data, output)
+        };
+        let (client_data, mut output) =
+            match interrupt::run_interruptible(round_future, &peer).await {
+                Ok(done) => done,
+                Err(interrupted) => {
+                    let mut report = options.new_report("server-po2");
+                    report.round = session_options.report_round(round);
+                    options.exit_interrupted(report, interrupted, &peer, &LEGACY_CSV)
+                },
+            };
+        // the shares of the inputs are not needed any more
+        output.arith_shares.zeroize();
+
+        // per-client steps interleave, so each is summed over all clients
+        for (name, (count, total)) in TimerScope::aggregate() {
+            info!(
+                "{}: {} times, {:.3}s in total",
+                name,
+                count,
+                total.as_secs_f64()
+            );
+        }
+        info!(
+            "MPC send queue wait: control: {}; bulk: {}",
+            peer.queue_wait_stats(Priority::Control),
+            peer.queue_wait_stats(Priority::Bulk)
+        );
+
+        simulation::warn_if_constructed();
+        let mut report = options.new_report("server-po2");
+        report.round = session_options.report_round(round);
+        report.client_comm = client_data.comm_alice + client_data.comm_bob;
+        comm_before.report_since(&mut report, &peer);
+        report
+            .phase("client", client_data.time)
+            .phase("b2a", output.b2a_time);
+        if let Some(num_ot_verified) = output.num_ot_verified {
+            report.verified("ot", num_ot_verified);
+        }
+        options.emit_report(report, &LEGACY_CSV);
     }
-    b2a_alice_hook.done();
+}
 
-    // OT Verify Bob Send (Complete)
-    for handle in otverify_bob_handles
-        .await
-        .expect("OT Verify on Bob part failed")
-    {
-        handle.await.unwrap();
+/// Same as `main_with_options`, for `ServerProtocol::Bits`. Client messages
+/// are neither dumped nor replayed.
+async fn main_bits(
+    options: Options<Po2Options>,
+    aggregation: BitAggregation,
+    client_runtime: Handle,
+) {
+    tracing_subscriber::fmt()
+        .pretty()
+        .with_max_level(options.log_level)
+        .init();
+    options.log_threads();
+    let peer = connect_peer(&options).await;
+    let _metrics_server = options.serve_metrics(&peer).await;
+
+    // accepted in the first round, and kept for the others
+    let mut session: Option<ClientSession> = None;
+    let session_options = &options.custom_args.session;
+    let rounds = session_options.rounds;
+    for round in 0..rounds {
+        let comm_before = MpcCommSnapshot::take(&peer);
+        let round_future = async {
+            let clients = match &session {
+                Some(clients) => clients.clone(),
+                None => {
+                    let listener = TcpListener::bind(options.client_listen_addr())
+                        .await
+                        .unwrap();
+                    let accept = ClientSession::accept_with_tls(
+                        options.is_alice(),
+                        options.partition.clone(),
+                        listener,
+                        options.num_clients,
+                        options.client_token,
+                        options.custom_args.tls.server_tls.clone(),
+                    );
+                    let accept = async move { accept.await.with_rounds(rounds) };
+                    session
+                        .insert(run_on(&client_runtime, accept).await)
+                        .clone()
+                },
+            };
+            let fetch = async move { BitsData::fetch_round(&clients, round).await };
+            let bits_data = run_on(&client_runtime, fetch).await;
+            options
+                .metrics
+                .set_clients_connected(bits_data.uids.len() + bits_data.failed.len());
+
+            let output = bits::run_round(
+                round,
+                options.gsize,
+                options.is_alice(),
+                aggregation,
+                &bits_data,
+                &peer,
+            )
+            .await;
+            (bits_data, output)
+        };
+        let (bits_data, output) = match interrupt::run_interruptible(round_future, &peer).await {
+            Ok(done) => done,
+            Err(interrupted) => {
+                let mut report = options.new_report("server-po2");
+                report.round = session_options.report_round(round);
+                options.exit_interrupted(report, interrupted, &peer, &BITS_LEGACY_CSV)
+            },
+        };
+        info!(
+            "{:?} of the bits of {} clients, {} rejected",
+            aggregation,
+            bits_data.uids.len() + bits_data.failed.len(),
+            output.rejected_clients.len()
+        );
+
+        let mut report = options.new_report("server-po2");
+        report.round = session_options.report_round(round);
+        report.client_comm = bits_data.comm_alice + bits_data.comm_bob;
+        comm_before.report_since(&mut report, &peer);
+        report
+            .phase("client", bits_data.time)
+            .phase("aggregate", output.aggregate_time);
+        options.emit_report(report, &BITS_LEGACY_CSV);
     }
-    ot_bob_hook.done();
-
-    let b2a_time = end_timer!(timer).elapsed().as_secs_f64();
-
-    println!("client comm, MPC comm, client phase 1, client phase 2, OT + B2A, Correlation verify, A2S, Hash verify");
-    println!(
-        "{}, {}, {}, {}, {}, {}, {}, {}",
-        client_data.comm_alice + client_data.comm_bob,
-        peer.num_bytes_received(),
-        client_data.time,
-        0f64,
-        b2a_time,
-        0f64,
-        0f64,
-        0f64
-    );
 }
 
 pub fn main() {
-    let options = Options::load_from_args("ELSA Server Po2");
-    let runtime = Runtime::new().unwrap();
-    match options.input_size {
-        InputSize::U8 => {
-            runtime.block_on(main_with_options::<u8>(options));
-        },
-        InputSize::U32 => runtime.block_on(main_with_options::<u32>(options)),
-    }
+    panic_report::run_main(|| {
+        let options = Options::load_from_args_custom(
+            "ELSA Server Po2",
+            Po2Options::args(),
+            Po2Options::parse,
+        );
+        options.install_compute_pool().unwrap();
+        if options.run_self_test() {
+            return;
+        }
+        let runtimes = options.runtimes().unwrap();
+        if let ServerProtocol::Bits(aggregation) = options.custom_args.protocol {
+            if options.custom_args.record.dump_clients.is_some()
+                || options.custom_args.record.replay_clients.is_some()
+            {
+                eprintln!("--protocol bits does not dump or replay client messages");
+                std::process::exit(2);
+            }
+            let client_runtime = runtimes.client().clone();
+            runtimes.block_on(main_bits(options, aggregation, client_runtime));
+            return;
+        }
+        match options.input_size {
+            InputSize::U8 => {
+                runtimes.block_on(main_with_options::<u8>(options, runtimes.client().clone()));
+            },
+            InputSize::U32 => {
+                runtimes.block_on(main_with_options::<u32>(options, runtimes.client().clone()))
+            },
+            InputSize::U64 => {
+                runtimes.block_on(main_with_options::<u64>(options, runtimes.client().clone()))
+            },
+        }
+    })
 }
@@ -0,0 +1,958 @@
+//! Server side of the Po2 protocol after the client messages are received:
+//! B2A, with COTs either sent by the clients or generated by the servers.
+
+use crate::{
+    client_msg::{ClientData, SplitData},
+    utils::{IdPool, ReverseIdPool},
+};
+use bin_utils::{
+    panic_report::expect_joined,
+    runtime::{block_on_compute, spawn_gated},
+};
+use bridge::{
+    client_server::with_uids, end_timer, mpc_conn::MpcConnection, perf_trace::TimerScope,
+    start_timer, tcp_bridge::ClientID,
+};
+use crypto_primitives::{
+    cot::{
+        dims::ProtocolDims,
+        server::{sample_chi, Chi},
+    },
+    message::po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
+    uint::UInt,
+    utils::{batch_sum_unequal, iter_arc, log_verify_status, Hook, UnequalLengths},
+};
+use rayon::prelude::*;
+use server_protocol::{self as mpc, ClientFailure};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+use tracing::warn;
+use zeroize::Zeroizing;
+
+/// Seed of the ROTs of client `uid` is `ROT_SEED + uid`. See
+/// `ROTsForSender::from_shared_seed`.
+#[cfg(feature = "simulation-helpers")]
+const ROT_SEED: u64 = 654321;
+
+/// Shares held by one server.
+pub struct PipelineOutput<A: UInt> {
+    /// B2A output: arithmetic shares of the inputs, in uid order, without the
+    /// rejected clients.
+    pub arith_shares: Vec<Vec<A>>,
+    /// Clients that failed on either server before the protocol, e.g. whose
+    /// message cannot be deserialized, or that sent different share
+    /// commitments, numbers of inputs or splits to the two servers, or a
+    /// message inconsistent with its number of inputs, in uid order. They are
+    /// rejected before B2A.
+    pub rejected_clients: Vec<ClientID>,
+    /// Number of clients where I'm OT sender whose OTs are verified, counting
+    /// the reverse B2A of clients that split their inputs. `None` if the
+    /// COTs are generated by the servers, which need no verification.
+    pub num_ot_verified: Option<usize>,
+
+    pub b2a_time: f64,
+}
+
+impl<A: UInt> PipelineOutput<A> {
+    /// My share of the sum of the inputs of all accepted clients. Clients may
+    /// have different numbers of inputs, which are summed by `policy`. Both
+    /// servers accept the same clients, so their shares have the same length.
+    pub fn aggregate(&self, policy: UnequalLengths) -> Vec<A> {
+        batch_sum_unequal(&self.arith_shares, policy)
+    }
+}
+
+/// Sizes of the OTs of a client with `gsize` inputs. Server-generated COTs
+/// need no verification, so no additional OTs.
+fn client_dims<I: UInt>(gsize: usize, sec_param: usize, simulate_server_ot: bool) -> ProtocolDims {
+    if simulate_server_ot {
+        ProtocolDims::with_num_additional::<I>(gsize, 0)
+    } else {
+        ProtocolDims::new::<I>(gsize, sec_param)
+    }
+    .expect("invalid protocol dimensions")
+}
+
+/// Split inputs of the clients, unless the COTs are generated by the
+/// servers, which then convert all inputs as without split.
+fn client_split<I: UInt>(
+    client_data: &ClientData<I>,
+    simulate_server_ot: bool,
+) -> Option<&SplitData> {
+    client_data.split.as_ref().filter(|_| !simulate_server_ot)
+}
+
+/// Number of inputs of each client, in uid order, or `None` if its message
+/// on my side is inconsistent with it: more than `max_gsize` inputs, a split
+/// after the last input, or, with COTs sent by the client, not exactly the
+/// OTs they need.
+fn client_gsizes<I: UInt>(
+    is_alice: bool,
+    client_data: &ClientData<I>,
+    max_gsize: usize,
+    sec_param: usize,
+    simulate_server_ot: bool,
+) -> Vec<Option<usize>> {
+    let dims = |gsize: usize| client_dims::<I>(gsize, sec_param, simulate_server_ot);
+    let split = client_split(client_data, simulate_server_ot);
+    let from_alice = client_data.po2_msgs_alice.iter().enumerate().map(|(i, m)| {
+        let gsize = Some(m.gsize as usize).filter(|g| *g <= max_gsize)?;
+        match split {
+            // Alice is OT receiver after the split
+            Some(split) => {
+                let num_ots = dims(gsize.checked_sub(split.splits_alice[i])?).num_total();
+                (split.cots_alice[i].ts().len() == num_ots).then_some(gsize)
+            },
+            None => Some(gsize),
+        }
+    });
+    let from_bob = client_data.po2_msgs_bob.iter().enumerate().map(|(i, m)| {
+        let gsize = Some(m.inputs_1.len()).filter(|g| *g <= max_gsize)?;
+        // Bob is OT receiver before the split
+        let num_received = match split {
+            Some(split) => Some(split.splits_bob[i]).filter(|s| *s <= gsize)?,
+            None => gsize,
+        };
+        let num_ots = dims(num_received).num_total();
+        (simulate_server_ot || m.cot.ts().len() == num_ots).then_some(gsize)
+    });
+    client_data
+        .policy
+        .merge_msg(
+            is_alice,
+            with_uids(&client_data.uids_alice, from_alice),
+            with_uids(&client_data.uids_bob, from_bob),
+        )
+        .into_values()
+        .collect()
+}
+
+/// Run B2A with `peer` on received client messages. `is_alice` is the global
+/// role of this server. Each client has its own number of inputs, which is at
+/// most `max_gsize`. If `simulate_server_ot` is set, the COTs in the client
+/// messages are ignored, and the servers simulate generating them, from a seed
+/// both know (see `cot::rot::server_generated`). Otherwise, for clients
+/// that split their inputs, each server is OT sender on its side of the
+/// split, and the two directions run concurrently.
+pub async fn run<I: UInt, A: UInt>(
+    max_gsize: usize,
+    sec_param: usize,
+    is_alice: bool,
+    client_data: &ClientData<I>,
+    peer: &MpcConnection,
+    simulate_server_ot: bool,
+) -> PipelineOutput<A> {
+    run_round(
+        0,
+        max_gsize,
+        sec_param,
+        is_alice,
+        client_data,
+        peer,
+        simulate_server_ot,
+    )
+    .await
+}
+
+/// Same as `run`, for round `round` of a session over the same `peer`.
+pub async fn run_round<I: UInt, A: UInt>(
+    round: u64,
+    max_gsize: usize,
+    sec_param: usize,
+    is_alice: bool,
+    client_data: &ClientData<I>,
+    peer: &MpcConnection,
+    simulate_server_ot: bool,
+) -> PipelineOutput<A> {
+    // both servers leave out the clients that failed on either, before the
+    // message ids of the others are allocated
+    let (failed_on_peer, failed_clients) = mpc::exchange_failed_uids(
+        IdPool::exchange_failed(round),
+        &client_data.uids,
+        &client_data.failed,
+        peer,
+    )
+    .await;
+    let parsed_data;
+    let client_data = if failed_on_peer.contains(&true) {
+        for (uid, _) in client_data
+            .uids
+            .iter()
+            .zip(&failed_on_peer)
+            .filter(|(_, f)| **f)
+        {
+            warn!("client {} failed: {}", uid.id, ClientFailure::FailedOnPeer);
+        }
+        parsed_data = client_data.without_clients(is_alice, &failed_on_peer);
+        &parsed_data
+    } else {
+        client_data
+    };
+
+    // manage message ids
+    // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
+    let ids = IdPool::build_for_round(
+        round,
+        client_data.num_clients_as_alice(),
+        client_data.num_clients_as_bob(),
+    );
+
+    // both servers reject the same clients, so the message ids of the others
+    // still match
+    let bad_commitments = mpc::exchange_commitments(
+        ids.exchange_commitments,
+        client_data.session,
+        &client_data.uids,
+        &client_data.commitments,
+        &client_data.held_shares(is_alice),
+        peer,
+    )
+    .await;
+    let gsizes = client_gsizes(
+        is_alice,
+        client_data,
+        max_gsize,
+        sec_param,
+        simulate_server_ot,
+    );
+    let bad_gsizes = mpc::exchange_gsizes(ids.exchange_gsizes, &gsizes, peer).await;
+    let bad_splits = match client_split(client_data, simulate_server_ot) {
+        Some(split) => {
+            let splits = client_data
+                .policy
+                .merge_msg(
+                    is_alice,
+                    with_uids(
+                        &client_data.uids_alice,
+                        split.splits_alice.iter().map(|s| Some(*s)),
+                    ),
+                    with_uids(
+                        &client_data.uids_bob,
+                        split.splits_bob.iter().map(|s| Some(*s)),
+                    ),
+                )
+                .into_values()
+                .collect::<Vec<_>>();
+            mpc::exchange_gsizes(ids.exchange_splits, &splits, peer).await
+        },
+        None => vec![false; client_data.uids.len()],
+    };
+    let mut rejected = Vec::with_capacity(client_data.uids.len());
+    let mut rejected_clients = failed_clients;
+    for (((uid, bad_commitment), bad_gsize), bad_split) in client_data
+        .uids
+        .iter()
+        .zip(bad_commitments)
+        .zip(bad_gsizes)
+        .zip(bad_splits)
+    {
+        if bad_commitment {
+            warn!("client {} rejected: share commitments do not match", uid.id);
+        } else if bad_gsize {
+            warn!(
+                "client {} rejected: number of inputs does not match its message",
+                uid.id
+            );
+        } else if bad_split {
+            warn!(
+                "client {} rejected: split of its inputs does not match",
+                uid.id
+            );
+        }
+        let bad = bad_commitment || bad_gsize || bad_split;
+        if bad {
+            rejected_clients.push(*uid);
+        }
+        rejected.push(bad);
+    }
+    rejected_clients.sort_unstable();
+    let accepted_data;
+    let client_data = if !rejected.contains(&true) {
+        client_data
+    } else {
+        accepted_data = client_data.without_clients(is_alice, &rejected);
+        &accepted_data
+    };
+
+    let timer = start_timer!(|| "OT Verify + B2A");
+    let (alice_arith_shares, bob_arith_shares, num_ot_verified) = if simulate_server_ot {
+        let (alice, bob) = b2a_with_server_cots::<I, A>(client_data, peer, ids).await;
+        (alice, bob, None)
+    } else {
+        let (alice, bob, num_ot_verified) =
+            b2a_with_client_cots::<I, A>(sec_param, client_data, peer, ids).await;
+        (alice, bob, Some(num_ot_verified))
+    };
+    let b2a_time = end_timer!(timer).elapsed().as_secs_f64();
+
+    PipelineOutput {
+        arith_shares: client_data
+            .policy
+            .merge_msg(
+                is_alice,
+                with_uids(&client_data.uids_alice, alice_arith_shares),
+                with_uids(&client_data.uids_bob, bob_arith_shares),
+            )
+            .into_values()
+            .collect(),
+        rejected_clients,
+        num_ot_verified,
+        b2a_time,
+    }
+}
+
+/// Return shares of clients where I'm Alice, shares of clients where I'm Bob,
+/// and number of clients whose OTs are verified.
+async fn b2a_with_client_cots<I: UInt, A: UInt>(
+    sec_param: usize,
+    client_data: &ClientData<I>,
+    peer: &MpcConnection,
+    ids: IdPool,
+) -> (Vec<Vec<A>>, Vec<Vec<A>>, usize) {
+    let dims = move |gsize: usize| client_dims::<I>(gsize, sec_param, false);
+    // number of inputs of each client where its OT sender is OT sender: all
+    // of them, or the ones before the split
+    let (straight_alice, straight_bob) = match &client_data.split {
+        Some(split) => (split.splits_alice.clone(), split.splits_bob.clone()),
+        None => (
+            client_data
+                .po2_msgs_alice
+                .iter()
+                .map(|m| m.gsize as usize)
+                .collect(),
+            client_data
+                .po2_msgs_bob
+                .iter()
+                .map(|m| m.inputs_1.len())
+                .collect::<Vec<_>>(),
+        ),
+    };
+    // first, sample chi that is used to generate all OTs, once for each
+    // number of inputs
+    let chi_seed = mpc::sample_chi_seed(ids.exchange_chi_seed, peer).await;
+    let gsizes = client_data
+        .po2_msgs_alice
+        .iter()
+        .map(|m| m.gsize as usize)
+        .chain(client_data.po2_msgs_bob.iter().map(|m| m.inputs_1.len()));
+    let chis = straight_alice
+        .iter()
+        .chain(&straight_bob)
+        .zip(gsizes)
+        .flat_map(|(&straight, gsize)| {
+            let reverse = client_data.split.is_some().then(|| gsize - straight);
+            [Some(straight), reverse].into_iter().flatten()
+        })
+        .map(|num_inputs| dims(num_inputs).num_total())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|num_total| {
+            (
+                num_total,
+                Chi::Shared(Arc::new(sample_chi(num_total, chi_seed))),
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    let chis = Arc::new(chis);
+    let chi_for = |chis: &BTreeMap<usize, Chi>, dims: ProtocolDims| chis[&dims.num_total()].clone();
+
+    // the reverse B2A runs alongside, with the roles swapped
+    let reverse_handle = client_data.split.clone().map(|split| {
+        tokio::spawn(b2a_reverse::<I, A>(
+            sec_param,
+            client_data.po2_msgs_alice.clone(),
+            client_data.po2_msgs_bob.clone(),
+            split,
+            chis.clone(),
+            peer.clone(),
+            ids.reverse,
+        ))
+    });
+
+    // OT Verify Alice Receive (Start)
+    let ot_alice_hook = Hook::new_named("OT Verify Alice");
+    let ot_ba_handles = iter_arc(&client_data.po2_msgs_alice)
+        .zip(ids.otverify_a)
+        .zip(&straight_alice)
+        .map(|((c_msg, id), &num_inputs)| {
+            let peer = peer.clone();
+            let chi = chi_for(&chis, dims(num_inputs));
+            spawn_gated(async move {
+                let _scope = TimerScope::enter("ot_verify_alice");
+                mpc::ot_verify_alice(id, &c_msg.cot, chi, sec_param, peer, &mut ())
+                    .await
+                    .unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // OT verify Bob send (Start)
+
+    let ot_bob_hook = Hook::new_named("OT Verify Bob");
+    let otverify_bob_handles = {
+        let peer = peer.clone();
+        let chis = chis.clone();
+        let c_msg = client_data.po2_msgs_bob.clone();
+        let straight_bob = straight_bob.clone();
+        tokio::task::spawn_blocking(move || {
+            c_msg
+                .par_iter()
+                .zip(ids.otverify_b)
+                .zip(straight_bob)
+                .map(|((c_msg, id), num_inputs)| {
+                    let _scope = TimerScope::enter("ot_verify_bob");
+                    let dims = dims(num_inputs);
+                    let chi = chi_for(&chis, dims);
+                    let inputs_1 = &c_msg.inputs_1[..num_inputs];
+                    mpc::ot_verify_receiver(id, inputs_1, &c_msg.cot, &peer, chi, dims).unwrap()
+                })
+                .collect::<Vec<_>>()
+        })
+    };
+
+    // B2A Bob Receive (Start)
+    let b2a_bob_hook = Hook::new_named("B2A Bob");
+    let b2a_bob_handles = iter_arc(&client_data.po2_msgs_bob)
+        .zip(ids.b2a_b)
+        .zip(straight_bob)
+        .map(|((c_msg, id), num_inputs)| {
+            let peer = peer.clone();
+            spawn_gated(async move {
+                let _scope = TimerScope::enter("b2a_bob");
+                let (inputs_1, ts) = (&c_msg.inputs_1[..num_inputs], c_msg.cot.ts());
+                let dims = dims(num_inputs);
+                mpc::b2a_bob::<_, A, _>(&id, dims, inputs_1, ts, peer, &mut ())
+                    .await
+                    .unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // OT Verify Alice Receive (Complete)
+    let mut qs_per_client = Vec::with_capacity(client_data.num_clients_as_alice());
+    let mut num_verified_success = 0;
+    for alice_handle in ot_ba_handles {
+        let (qs, v) = expect_joined(alice_handle.await);
+        // wiped once B2A is done
+        qs_per_client.push(Zeroizing::new(qs));
+        num_verified_success += v as usize;
+    }
+    log_verify_status(
+        num_verified_success,
+        client_data.num_clients_as_alice(),
+        "OT Verify Alice",
+    );
+    ot_alice_hook.done();
+
+    // B2A Alice Send (Start)
+    let b2a_alice_hook = Hook::new_named("B2A Alice");
+    let b2a_alice_handles = block_on_compute(|| {
+        client_data
+            .po2_msgs_alice
+            .par_iter()
+            .zip(qs_per_client)
+            .zip(ids.b2a_a)
+            .zip(&straight_alice)
+            .map(|(((c_msg, qs), id), &num_inputs)| {
+                let _scope = TimerScope::enter("b2a_alice");
+                // the expansion of the seed starts with the shares before the
+                // split
+                let (inputs_0, delta) = (c_msg.inputs_0, c_msg.cot.delta);
+                let dims = dims(num_inputs);
+                mpc::b2a_alice::<I, A>(&id, dims, inputs_0, delta, &qs, peer).unwrap()
+            })
+            .collect::<Vec<_>>()
+    });
+
+    // B2A Bob Receive (Complete)
+    let mut bob_arith_shares = Vec::with_capacity(client_data.num_clients_as_bob());
+    for bob_handle in b2a_bob_handles {
+        let bob_arith_share = expect_joined(bob_handle.await);
+        bob_arith_shares.push(bob_arith_share);
+    }
+    b2a_bob_hook.done();
+
+    // B2A Alice Send (Complete)
+    let mut alice_arith_shares = Vec::with_capacity(client_data.num_clients_as_alice());
+    for (s, handle) in b2a_alice_handles {
+        handle
+            .await
+            .expect("MPC connection closed before the message was sent");
+        alice_arith_shares.push(s);
+    }
+    b2a_alice_hook.done();
+
+    // OT Verify Bob Send (Complete)
+    for handle in expect_joined(otverify_bob_handles.await) {
+        handle
+            .await
+            .expect("MPC connection closed before the message was sent");
+    }
+    ot_bob_hook.done();
+
+    // Reverse B2A (Complete): each client's shares after the split follow the
+    // ones before it
+    if let Some(handle) = reverse_handle {
+        let (reverse_alice, reverse_bob, num_reverse_verified) = expect_joined(handle.await);
+        for (shares, reverse) in alice_arith_shares.iter_mut().zip(reverse_alice) {
+            shares.extend(reverse);
+        }
+        for (shares, reverse) in bob_arith_shares.iter_mut().zip(reverse_bob) {
+            shares.extend(reverse);
+        }
+        num_verified_success += num_reverse_verified;
+    }
+
+    (alice_arith_shares, bob_arith_shares, num_verified_success)
+}
+
+/// Reverse B2A of clients that split their inputs, on the inputs after the
+/// split: as OT receiver for clients where I'm Alice, with my shares as
+/// choices, and as OT sender for clients where I'm Bob. Return shares of
+/// clients where I'm Alice, shares of clients where I'm Bob, and number of
+/// clients whose reverse OTs are verified.
+async fn b2a_reverse<I: UInt, A: UInt>(
+    sec_param: usize,
+    msgs_alice: Arc<[ClientPo2MsgToAlice]>,
+    msgs_bob: Arc<[ClientPo2MsgToBob<I>]>,
+    split: SplitData,
+    chis: Arc<BTreeMap<usize, Chi>>,
+    peer: MpcConnection,
+    ids: ReverseIdPool,
+) -> (Vec<Vec<A>>, Vec<Vec<A>>, usize) {
+    let dims = move |gsize: usize| client_dims::<I>(gsize, sec_param, false);
+
+    // not gated by the `ClientGate`: I spawn my clients as Alice first, and
+    // the peer spawns them last as Bob, so with a small gate each server
+    // would wait for clients that the other has not started
+    // OT Verify + B2A Alice as OT receiver (Start)
+    let alice_hook = Hook::new_named("Reverse B2A Alice");
+    let alice_handles = iter_arc(&msgs_alice)
+        .zip(iter_arc(&split.cots_alice))
+        .zip(split.splits_alice)
+        .zip(ids.otverify_a.into_iter().zip(ids.b2a_a))
+        .map(|(((c_msg, cot), split), (verify_id, b2a_ids))| {
+            let peer = peer.clone();
+            let chis = chis.clone();
+            tokio::spawn(async move {
+                let _scope = TimerScope::enter("reverse_b2a_alice");
+                let mut inputs_0 = Zeroizing::new(c_msg.inputs_0.expand::<I>(c_msg.gsize as usize));
+                let inputs_0 = Zeroizing::new(inputs_0.split_off(split));
+                let dims = dims(inputs_0.len());
+                let chi = chis[&dims.num_total()].clone();
+                let handle =
+                    mpc::ot_verify_receiver(verify_id, &inputs_0, &cot, &peer, chi, dims).unwrap();
+                let y1s =
+                    mpc::b2a_bob::<I, A, _>(&b2a_ids, dims, &inputs_0, cot.ts(), peer, &mut ())
+                        .await
+                        .unwrap();
+                handle
+                    .await
+                    .expect("MPC connection closed before the message was sent");
+                y1s
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // OT Verify + B2A Bob as OT sender (Start)
+    let bob_hook = Hook::new_named("Reverse B2A Bob");
+    let bob_handles = iter_arc(&msgs_bob)
+        .zip(iter_arc(&split.cots_bob))
+        .zip(split.splits_bob)
+        .zip(ids.otverify_b.into_iter().zip(ids.b2a_b))
+        .map(|(((c_msg, cot), split), (verify_id, b2a_ids))| {
+            let peer = peer.clone();
+            let dims = dims(c_msg.inputs_1.len() - split);
+            let chi = chis[&dims.num_total()].clone();
+            tokio::spawn(async move {
+                let _scope = TimerScope::enter("reverse_b2a_bob");
+                let (qs, ok) =
+                    mpc::ot_verify_alice(verify_id, &cot, chi, sec_param, peer.clone(), &mut ())
+                        .await
+                        .unwrap();
+                let qs = Zeroizing::new(qs);
+                let inputs_1 = &c_msg.inputs_1[split..];
+                let (y0s, handle) =
+                    mpc::b2a_sender::<I, A>(&b2a_ids, dims, inputs_1, cot.delta, &qs, &peer)
+                        .unwrap();
+                handle
+                    .await
+                    .expect("MPC connection closed before the message was sent");
+                (y0s, ok)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // OT Verify + B2A Alice as OT receiver (Complete)
+    let mut alice_arith_shares = Vec::with_capacity(alice_handles.len());
+    for handle in alice_handles {
+        alice_arith_shares.push(expect_joined(handle.await));
+    }
+    alice_hook.done();
+
+    // OT Verify + B2A Bob as OT sender (Complete)
+    let mut bob_arith_shares = Vec::with_capacity(bob_handles.len());
+    let mut num_verified_success = 0;
+    for handle in bob_handles {
+        let (y0s, ok) = expect_joined(handle.await);
+        bob_arith_shares.push(y0s);
+        num_verified_success += ok as usize;
+    }
+    log_verify_status(
+        num_verified_success,
+        bob_arith_shares.len(),
+        "Reverse OT Verify Bob",
+    );
+    bob_hook.done();
+
+    (alice_arith_shares, bob_arith_shares, num_verified_success)
+}
+
+/// Return shares of clients where I'm Alice, and shares of clients where I'm
+/// Bob. Not secure, see `ROTsForSender::from_shared_seed`.
+#[cfg(feature = "simulation-helpers")]
+async fn b2a_with_server_cots<I: UInt, A: UInt>(
+    client_data: &ClientData<I>,
+    peer: &MpcConnection,
+    ids: IdPool,
+) -> (Vec<Vec<A>>, Vec<Vec<A>>) {
+    use block::Block;
+    use crypto_primitives::cot::rot::server_generated::{ROTsForReceiver, ROTsForSender};
+
+    let dims = |gsize: usize| client_dims::<I>(gsize, 0, true);
+    let delta = Block::rand(&mut rand::thread_rng());
+    let rot_seed = |uid: &ClientID| ROT_SEED + uid.id;
+    let rot_seeds_a = client_data.uids_alice.iter().map(rot_seed);
+    let rot_seeds_b = client_data.uids_bob.iter().map(rot_seed);
+
+    // COT + B2A Alice (Start)
+    let alice_hook = Hook::new_named("COT + B2A Alice");
+    let alice_handles = iter_arc(&client_data.po2_msgs_alice)
+        .zip(rot_seeds_a)
+        .zip(ids.cot_correction_a.into_iter().zip(ids.cot_flips_a))
+        .zip(ids.b2a_a)
+        .map(|(((c_msg, rot_seed), cot_ids), b2a_id)| {
+            let peer = peer.clone();
+            let dims = dims(c_msg.gsize as usize);
+            tokio::spawn(async move {
+                let _scope = TimerScope::enter("cot_b2a_alice");
+                let rots = ROTsForSender::from_shared_seed(rot_seed, dims.num_ot());
+                let cots = mpc::server_cot_alice::<I>(cot_ids, rots, delta, peer.clone()).await;
+                let (y0s, handle) = mpc::b2a_alice::<I, A>(
+                    &b2a_id,
+                    dims,
+                    c_msg.inputs_0,
+                    cots.delta,
+                    &cots.qs,
+                    &peer,
+                )
+                .unwrap();
+                handle
+                    .await
+                    .expect("MPC connection closed before the message was sent");
+                y0s
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // COT + B2A Bob (Start)
+    let bob_hook = Hook::new_named("COT + B2A Bob");
+    let bob_handles = iter_arc(&client_data.po2_msgs_bob)
+        .zip(rot_seeds_b)
+        .zip(ids.cot_correction_b.into_iter().zip(ids.cot_flips_b))
+        .zip(ids.b2a_b)
+        .map(|(((c_msg, rot_seed), cot_ids), b2a_id)| {
+            let peer = peer.clone();
+            let dims = dims(c_msg.inputs_1.len());
+            tokio::spawn(async move {
+                let _scope = TimerScope::enter("cot_b2a_bob");
+                let rots = ROTsForReceiver::from_shared_seed(rot_seed, dims.num_ot());
+                let ts =
+                    mpc::server_cot_bob::<I>(cot_ids, rots, &c_msg.inputs_1, peer.clone()).await;
+                mpc::b2a_bob::<I, A, _>(&b2a_id, dims, &c_msg.inputs_1, &ts, peer, &mut ())
+                    .await
+                    .unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // COT + B2A Alice (Complete)
+    let mut alice_arith_shares = Vec::with_capacity(client_data.num_clients_as_alice());
+    for handle in alice_handles {
+        alice_arith_shares.push(expect_joined(handle.await));
+    }
+    alice_hook.done();
+
+    // COT + B2A Bob (Complete)
+    let mut bob_arith_shares = Vec::with_capacity(client_data.num_clients_as_bob());
+    for handle in bob_handles {
+        bob_arith_shares.push(expect_joined(handle.await));
+    }
+    bob_hook.done();
+
+    (alice_arith_shares, bob_arith_shares)
+}
+
+#[cfg(not(feature = "simulation-helpers"))]
+async fn b2a_with_server_cots<I: UInt, A: UInt>(
+    _client_data: &ClientData<I>,
+    _peer: &MpcConnection,
+    _ids: IdPool,
+) -> (Vec<Vec<A>>, Vec<Vec<A>>) {
+    unreachable!("the servers only generate COTs with simulation-helpers")
+}
+
+// every test runs the servers on COTs they generate, see `run_servers`
+#[cfg(all(test, feature = "simulation-helpers"))]
+mod tests {
+    use bridge::{
+        client_dump::ClientRecording, client_server::PartitionPolicy, mpc_conn::MpcConnection,
+        tcp_bridge::ClientID,
+    };
+    use crypto_primitives::{
+        bits::{BitsLE, SeededInputShare, ShareCommitment, ShareSalt},
+        cot::client::{B2ACOTToAlice, B2ACOTToBob},
+        message::po2::{ClientPo2MsgToAlice, ClientPo2MsgToBob},
+        uint::UInt,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+    use serialize::Communicate;
+    use tokio::net::TcpListener;
+
+    use super::PipelineOutput;
+    use crate::client_msg::{ClientData, MsgFormat};
+
+    type I = u32;
+    type A = u64;
+
+    const GSIZE: usize = 3;
+    const NUM_CLIENTS: usize = 5;
+
+    /// Boolean shares of `input` from the sharing with `seed`.
+    fn share(seed: u64, input: &[I]) -> (SeededInputShare, Vec<BitsLE<I>>) {
+        let inputs_0 = SeededInputShare(seed);
+        let inputs_1 = inputs_0
+            .expand::<I>(GSIZE)
+            .into_iter()
+            .zip(input)
+            .map(|(x0, x)| x0 ^ BitsLE(*x))
+            .collect();
+        (inputs_0, inputs_1)
+    }
+
+    /// Messages of a client that sends `inputs_0` to Alice and `inputs_1` to
+    /// Bob, with `commitment` to both.
+    fn msgs_with(
+        inputs_0: SeededInputShare,
+        (inputs_1, salt): (Vec<BitsLE<I>>, ShareSalt),
+        commitment: ShareCommitment,
+    ) -> (ClientPo2MsgToAlice, ClientPo2MsgToBob<I>) {
+        (
+            ClientPo2MsgToAlice::new(GSIZE, inputs_0, commitment, B2ACOTToAlice::default()),
+            ClientPo2MsgToBob::new(inputs_1, commitment, salt, B2ACOTToBob::default()),
+        )
+    }
+
+    /// Messages of a client that only sends boolean shares of `input`.
+    fn client_msgs(uid: usize, input: &[I]) -> (ClientPo2MsgToAlice, ClientPo2MsgToBob<I>) {
+        let (inputs_0, inputs_1) = share(uid as u64 + 100, input);
+        let salt = ShareSalt([uid as u8; 16]);
+        let commitment = ShareCommitment::new(uid as u64, 0, inputs_0, salt, &inputs_1);
+        msgs_with(inputs_0, (inputs_1, salt), commitment)
+    }
+
+    fn random_inputs() -> Vec<Vec<I>> {
+        let mut rng = StdRng::seed_from_u64(283);
+        (0..NUM_CLIENTS)
+            .map(|_| (0..GSIZE).map(|_| I::rand(&mut rng)).collect::<Vec<_>>())
+            .collect()
+    }
+
+    /// Run the pipeline with server-generated COTs on both servers.
+    async fn run_servers(
+        msgs: &[(ClientPo2MsgToAlice, ClientPo2MsgToBob<I>)],
+    ) -> (PipelineOutput<A>, PipelineOutput<A>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (alice, bob) = tokio::join!(
+            MpcConnection::new_as_alice_with_listener(listener, 2),
+            MpcConnection::new_as_bob(addr, 2)
+        );
+
+        let run = |is_alice: bool, peer: MpcConnection| {
+            let mut recording = ClientRecording::default();
+            for (uid, (to_alice, to_bob)) in msgs.iter().enumerate() {
+                let uid = ClientID::new(uid as u64);
+                if PartitionPolicy::Parity.is_ot_sender(is_alice, uid) {
+                    recording.alice.push((uid, to_alice.into_bytes_owned()));
+                } else {
+                    recording.bob.push((uid, to_bob.into_bytes_owned()));
+                }
+            }
+            let data = ClientData::<I>::from_recording(
+                is_alice,
+                PartitionPolicy::Parity,
+                MsgFormat::default(),
+                recording,
+            );
+            tokio::spawn(async move {
+                super::run::<I, A>(GSIZE, 40, is_alice, &data, &peer, true).await
+            })
+        };
+        let (alice, bob) = (run(true, alice), run(false, bob));
+        (alice.await.unwrap(), bob.await.unwrap())
+    }
+
+    /// Check that the shares of the two servers add up to `inputs`.
+    fn check_shares(alice: &PipelineOutput<A>, bob: &PipelineOutput<A>, inputs: &[&Vec<I>]) {
+        assert_eq!(alice.arith_shares.len(), inputs.len());
+        assert_eq!(bob.arith_shares.len(), inputs.len());
+        for ((share_0, share_1), input) in
+            alice.arith_shares.iter().zip(&bob.arith_shares).zip(inputs)
+        {
+            let values = share_0
+                .iter()
+                .zip(share_1)
+                .map(|(a, b)| a.wrapping_add(*b))
+                .collect::<Vec<_>>();
+            let expected = input.iter().map(|x| *x as A).collect::<Vec<_>>();
+            assert_eq!(values, expected);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_simulated_server_ot_b2a() {
+        let inputs = random_inputs();
+        let msgs = inputs
+            .iter()
+            .enumerate()
+            .map(|(uid, input)| client_msgs(uid, input))
+            .collect::<Vec<_>>();
+        assert!(msgs.iter().all(|(_, to_bob)| to_bob.cot.ts().is_empty()));
+
+        let (alice, bob) = run_servers(&msgs).await;
+
+        assert_eq!(alice.num_ot_verified, None);
+        assert_eq!(bob.num_ot_verified, None);
+        assert!(alice.rejected_clients.is_empty());
+        assert!(bob.rejected_clients.is_empty());
+        check_shares(&alice, &bob, &inputs.iter().collect::<Vec<_>>());
+    }
+
+    /// Run the servers on the clients of `random_inputs`, where `corrupt`
+    /// changes the messages of the clients in `bad_uids`, and check that
+    /// exactly those are rejected.
+    async fn check_rejected(
+        bad_uids: &[usize],
+        corrupt: impl Fn(usize, &[I], &mut (ClientPo2MsgToAlice, ClientPo2MsgToBob<I>)),
+    ) {
+        let inputs = random_inputs();
+        let mut msgs = inputs
+            .iter()
+            .enumerate()
+            .map(|(uid, input)| client_msgs(uid, input))
+            .collect::<Vec<_>>();
+        for &uid in bad_uids {
+            corrupt(uid, &inputs[uid], &mut msgs[uid]);
+        }
+
+        let (alice, bob) = run_servers(&msgs).await;
+
+        let bad_uids = bad_uids
+            .iter()
+            .map(|uid| ClientID::new(*uid as u64))
+            .collect::<Vec<_>>();
+        assert_eq!(alice.rejected_clients, bad_uids);
+        assert_eq!(bob.rejected_clients, bad_uids);
+        let accepted = inputs
+            .iter()
+            .enumerate()
+            .filter(|(uid, _)| !bad_uids.contains(&ClientID::new(*uid as u64)))
+            .map(|(_, input)| input)
+            .collect::<Vec<_>>();
+        check_shares(&alice, &bob, &accepted);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_mismatched_shares_are_rejected() {
+        // the share to Bob comes from another sharing of the same input, so
+        // the two shares add up to garbage, and Bob gets the commitment of
+        // that sharing
+        check_rejected(&[1, 2], |uid, input, msgs| {
+            let (inputs_0, inputs_1) = share(999, input);
+            let salt = ShareSalt([9; 16]);
+            let commitment = ShareCommitment::new(uid as u64, 0, inputs_0, salt, &inputs_1);
+            msgs.1 = ClientPo2MsgToBob::new(inputs_1, commitment, salt, B2ACOTToBob::default());
+        })
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_mismatched_shares_with_same_commitment_are_rejected() {
+        // both servers get the same commitment, but only one of the shares
+        // is from its sharing, so only the recomputed commitment catches it
+        check_rejected(&[1, 2], |uid, input, msgs| {
+            let (inputs_0, inputs_1) = share(999, input);
+            let salt = ShareSalt([9; 16]);
+            let other = msgs_with(
+                inputs_0,
+                (inputs_1.clone(), salt),
+                ShareCommitment::new(uid as u64, 0, inputs_0, salt, &inputs_1),
+            );
+            // of Bob's sharing for one client, and of Alice's for the other
+            if uid == 1 {
+                msgs.1 = other.1;
+                msgs.1.commitment = msgs.0.commitment;
+            } else {
+                msgs.0 = other.0;
+                msgs.0.commitment = msgs.1.commitment;
+            }
+        })
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_commitment_of_another_uid_is_rejected() {
+        // consistent shares, but bound to the uid of another client
+        check_rejected(&[3], |uid, _, msgs| {
+            let (to_alice, to_bob) = msgs;
+            let commitment = ShareCommitment::new(
+                uid as u64 + 1,
+                0,
+                to_alice.inputs_0,
+                to_bob.salt,
+                &to_bob.inputs_1,
+            );
+            to_alice.commitment = commitment;
+            to_bob.commitment = commitment;
+        })
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_mismatched_gsize_is_rejected() {
+        const BAD_UID: usize = 3;
+        let inputs = random_inputs();
+        let mut msgs = inputs
+            .iter()
+            .enumerate()
+            .map(|(uid, input)| client_msgs(uid, input))
+            .collect::<Vec<_>>();
+        msgs[BAD_UID].0.gsize -= 1;
+
+        let (alice, bob) = run_servers(&msgs).await;
+
+        let bad_uid = ClientID::new(BAD_UID as u64);
+        assert_eq!(alice.rejected_clients, vec![bad_uid]);
+        assert_eq!(bob.rejected_clients, vec![bad_uid]);
+        let accepted = inputs
+            .iter()
+            .enumerate()
+            .filter(|(uid, _)| *uid != BAD_UID)
+            .map(|(_, input)| input)
+            .collect::<Vec<_>>();
+        check_shares(&alice, &bob, &accepted);
+    }
+}
@@ -1,20 +1,70 @@
-use bridge::id_tracker::{IdGen, RecvId, SendId};
+use bridge::id_tracker::{ExchangeId, IdGen, RecvId, SendId};
+use server_protocol::B2A_CHUNKS;
 
 /// Message IDs for various clients
 pub struct IdPool {
+    /// checks of the share commitments of all clients, before B2A: their
+    /// digests, then the clients to reject
+    pub exchange_commitments: (ExchangeId, ExchangeId),
+    /// number of inputs of all clients, exchanged with the commitments
+    pub exchange_gsizes: ExchangeId,
+
     pub otverify_a: Vec<RecvId>,
     pub otverify_b: Vec<SendId>,
 
-    pub b2a_a: Vec<SendId>,
-    pub b2a_b: Vec<RecvId>,
+    /// `B2A_CHUNKS` consecutive ids for the `us` of each client
+    pub b2a_a: Vec<Vec<SendId>>,
+    pub b2a_b: Vec<Vec<RecvId>>,
+
+    /// COTs generated by the servers: the ROT correction from Alice, and the
+    /// choice flips from Bob
+    pub cot_correction_a: Vec<SendId>,
+    pub cot_correction_b: Vec<RecvId>,
+    pub cot_flips_a: Vec<RecvId>,
+    pub cot_flips_b: Vec<SendId>,
+
+    /// shares of the seed of chi, exchanged before OT verification
+    pub exchange_chi_seed: ExchangeId,
+
+    /// where the clients split their inputs, exchanged after their numbers
+    /// of inputs
+    pub exchange_splits: ExchangeId,
+    pub reverse: ReverseIdPool,
+}
+
+/// Message IDs of the reverse B2A of clients that split their inputs, on the
+/// inputs after the split. Here `a` (Alice of the client) is OT receiver, and
+/// `b` is OT sender.
+pub struct ReverseIdPool {
+    pub otverify_a: Vec<SendId>,
+    pub otverify_b: Vec<RecvId>,
+
+    pub b2a_a: Vec<Vec<RecvId>>,
+    pub b2a_b: Vec<Vec<SendId>>,
 }
 
 impl IdPool {
     pub fn build(alice_pool_size: usize, bob_pool_size: usize) -> Self {
+        Self::build_for_round(0, alice_pool_size, bob_pool_size)
+    }
+
+    /// Clients that failed on either server before round `round`, exchanged
+    /// before the others are counted, so it does not depend on the counts.
+    pub fn exchange_failed(round: u64) -> ExchangeId {
+        IdGen::for_round(round).next_exchange_id()
+    }
+
+    /// Ids of round `round` of a session, disjoint from the other rounds.
+    pub fn build_for_round(round: u64, alice_pool_size: usize, bob_pool_size: usize) -> Self {
         // manage message ids
         // for now, denote `a` as Alice (OT Sender) and `b` as Bob (OT Receiver)
 
-        let mut id = IdGen::new();
+        let mut id = IdGen::for_round(round);
+        // skip `exchange_failed`
+        id.next_exchange_id();
+
+        let exchange_commitments = (id.next_exchange_id(), id.next_exchange_id());
+        let exchange_gsizes = id.next_exchange_id();
 
         let otverify_a = (0..alice_pool_size)
             .map(|_| id.next_recv_id())
@@ -24,17 +74,57 @@ impl IdPool {
             .collect::<Vec<_>>();
 
         let b2a_a = (0..alice_pool_size)
-            .map(|_| id.next_send_id())
+            .map(|_| (0..B2A_CHUNKS).map(|_| id.next_send_id()).collect())
             .collect::<Vec<_>>();
         let b2a_b = (0..bob_pool_size)
+            .map(|_| (0..B2A_CHUNKS).map(|_| id.next_recv_id()).collect())
+            .collect::<Vec<_>>();
+
+        let cot_correction_a = (0..alice_pool_size)
+            .map(|_| id.next_send_id())
+            .collect::<Vec<_>>();
+        let cot_correction_b = (0..bob_pool_size)
+            .map(|_| id.next_recv_id())
+            .collect::<Vec<_>>();
+
+        let cot_flips_a = (0..alice_pool_size)
             .map(|_| id.next_recv_id())
             .collect::<Vec<_>>();
+        let cot_flips_b = (0..bob_pool_size)
+            .map(|_| id.next_send_id())
+            .collect::<Vec<_>>();
+
+        let exchange_chi_seed = id.next_exchange_id();
+
+        let exchange_splits = id.next_exchange_id();
+
+        // the mirror of the ids above: the ids I send under are the ones the
+        // peer receives under, as OT sender of the same clients
+        let reverse = ReverseIdPool {
+            otverify_a: (0..alice_pool_size).map(|_| id.next_send_id()).collect(),
+            otverify_b: (0..bob_pool_size).map(|_| id.next_recv_id()).collect(),
+            b2a_a: (0..alice_pool_size)
+                .map(|_| (0..B2A_CHUNKS).map(|_| id.next_recv_id()).collect())
+                .collect(),
+            b2a_b: (0..bob_pool_size)
+                .map(|_| (0..B2A_CHUNKS).map(|_| id.next_send_id()).collect())
+                .collect(),
+        };
 
         IdPool {
+            exchange_commitments,
+            exchange_gsizes,
             otverify_a,
             otverify_b,
             b2a_a,
             b2a_b,
+            cot_correction_a,
+            cot_correction_b,
+            cot_flips_a,
+            cot_flips_b,
+            exchange_chi_seed,
+            exchange_splits,
+            reverse,
         }
     }
 }
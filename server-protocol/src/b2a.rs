@@ -0,0 +1,300 @@
+use block::Block;
+use bridge::{
+    id_tracker::{RecvId, SendId},
+    mpc_conn::MpcConnection,
+};
+use crypto_primitives::{
+    b2a::{
+        b2a_chunk_elems, bit_comp_as_ot_receiver_batch_chunked,
+        bit_comp_as_ot_sender_batch_chunked, B2A_CHUNKS,
+    },
+    bits::{BitsLE, SeededInputShare},
+    cot::{
+        client::{B2ACOTToAlice, B2ACOTToBob},
+        dims::ProtocolDims,
+        rot::server_generated::{
+            choice_flips, ROTsForReceiver, ROTsForSender, ServerCOTsForReceiver,
+            ServerCOTsForSender,
+        },
+        server::{Chi, ChiChunks, TTil},
+        verify::{OtVerifyReceiver, OtVerifySender, VerifyOutcome},
+    },
+    malpriv::MessageHash,
+    message::po2::ClientPo2MsgToBob,
+    uint::UInt,
+};
+use serialize::{AsUseCast, UseCast};
+use zeroize::Zeroizing;
+
+use crate::{send, send_placeholder, ChunkedSendHandle, ClientFailure, SendHandle};
+
+/// The input region of the client's OTs, or `NotEnoughOts`.
+fn input_ots<'a, T>(dims: &ProtocolDims, ots: &'a [T]) -> Result<&'a [T], ClientFailure> {
+    dims.input_region(ots).ok_or(ClientFailure::NotEnoughOts {
+        expected: dims.num_ot(),
+        actual: ots.len(),
+    })
+}
+
+fn check_num_inputs(dims: &ProtocolDims, actual: usize) -> Result<(), ClientFailure> {
+    if actual != dims.gsize() {
+        return Err(ClientFailure::WrongInputLength {
+            expected: dims.gsize(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Run OT Verify on one client, assuming I'm OT sender.
+/// Return COT (qs), verify result, and client message
+/// `I` is Input ring. `A` is Aggregation Ring. `C` is Output ring.
+/// `sec_param` is absorbed into `hasher_bob` to match the client transcript.
+pub async fn ot_verify_alice<H: MessageHash>(
+    msg_id: RecvId,
+    cot: &B2ACOTToAlice,
+    chi: Chi,
+    sec_param: usize,
+    peer: MpcConnection,
+    hasher_bob: &mut H,
+) -> Result<(Vec<Block>, bool), ClientFailure> {
+    // ROUND 1: verify COT
+
+    // receive x_til and t_til from peer
+    let (x_til, t_til) = if cfg!(feature = "no-comm") {
+        (Default::default(), TTil::new(Default::default()))
+    } else {
+        peer.subscribe_and_get::<(UseCast<Block>, TTil)>(msg_id)
+            .await?
+    };
+
+    // verify cot
+    let VerifyOutcome { qs, ok } =
+        OtVerifySender::new(cot.qs_seed, cot.delta, chi).receive(x_til, t_til);
+    hasher_bob.absorb(&(sec_param as u64).use_cast());
+    hasher_bob.absorb(&(x_til.use_cast(), t_til));
+    Ok((qs, ok))
+}
+
+/// Run OT Verify on one client, assuming I'm OT receiver. Return a send handle
+/// indicating if send has finished. If the client message is malformed, a
+/// placeholder is sent instead.
+pub fn ot_verify_bob<I: UInt>(
+    msg_id: SendId,
+    client_msg: &ClientPo2MsgToBob<I>,
+    peer: &MpcConnection,
+    chi: Chi,
+    dims: ProtocolDims,
+) -> Result<SendHandle, ClientFailure> {
+    ot_verify_receiver(
+        msg_id,
+        &client_msg.inputs_1,
+        &client_msg.cot,
+        peer,
+        chi,
+        dims,
+    )
+}
+
+/// Same as `ot_verify_bob`, with choices `inputs` and COTs `cot`, e.g. as OT
+/// receiver of reverse B2A, where the choices are the shares of Alice.
+pub fn ot_verify_receiver<I: UInt>(
+    msg_id: SendId,
+    inputs: &[BitsLE<I>],
+    cot: &B2ACOTToBob,
+    peer: &MpcConnection,
+    chi: Chi,
+    dims: ProtocolDims,
+) -> Result<SendHandle, ClientFailure> {
+    assert_eq!(chi.num_ots(), dims.num_total());
+    let ts = check_num_inputs(&dims, inputs.len()).and_then(|_| {
+        let ts = cot.ts();
+        ts.get(..dims.num_total())
+            .ok_or(ClientFailure::NotEnoughOts {
+                expected: dims.num_total(),
+                actual: ts.len(),
+            })
+    });
+    let ts = match ts {
+        Ok(ts) => ts,
+        Err(e) => {
+            send_placeholder(msg_id, peer);
+            return Err(e);
+        },
+    };
+
+    // ROUND 1: verify COT
+    let (x_til, t_til) = match OtVerifyReceiver::new(ts, &chi, inputs, cot.r_seed) {
+        Ok(receiver) => receiver.message(),
+        Err(e) => {
+            send_placeholder(msg_id, peer);
+            return Err(e.into());
+        },
+    };
+    Ok(send(peer, msg_id, (x_til.use_cast(), t_til)))
+}
+
+/// Run OT B2A on one client, assuming I'm OT sender with correlation `delta`.
+/// `qs` come from the client, or from `server_cot_alice`. The `us` are sent
+/// in `B2A_CHUNKS` chunks under `msg_ids`, each as soon as it is computed.
+/// Return my arithmetic shares, and a handle for all chunks. If `qs` is too
+/// short, placeholders are sent instead.
+pub fn b2a_alice<I: UInt, A: UInt>(
+    msg_ids: &[SendId],
+    dims: ProtocolDims,
+    inputs_0: SeededInputShare,
+    delta: Block,
+    qs: &[Block],
+    peer: &MpcConnection,
+) -> Result<(Vec<A>, ChunkedSendHandle), ClientFailure> {
+    let inputs_0 = Zeroizing::new(inputs_0.expand::<I>(dims.gsize()));
+    b2a_sender(msg_ids, dims, &inputs_0, delta, qs, peer)
+}
+
+/// Same as `b2a_alice`, with boolean shares `inputs`, e.g. as OT sender of
+/// reverse B2A, where the shares are the ones of Bob. If `inputs` are not
+/// `dims.gsize()`, placeholders are sent instead.
+pub fn b2a_sender<I: UInt, A: UInt>(
+    msg_ids: &[SendId],
+    dims: ProtocolDims,
+    inputs: &[BitsLE<I>],
+    delta: Block,
+    qs: &[Block],
+    peer: &MpcConnection,
+) -> Result<(Vec<A>, ChunkedSendHandle), ClientFailure> {
+    assert_eq!(msg_ids.len(), B2A_CHUNKS);
+    let qs = match check_num_inputs(&dims, inputs.len()).and_then(|_| input_ots(&dims, qs)) {
+        Ok(qs) => qs,
+        Err(e) => {
+            for &id in msg_ids {
+                send_placeholder(id, peer);
+            }
+            return Err(e);
+        },
+    };
+
+    let mut y0s = Vec::with_capacity(dims.gsize());
+    let mut ids = msg_ids.iter();
+    let mut send_handles = Vec::with_capacity(B2A_CHUNKS);
+    let chunk_elems = b2a_chunk_elems(dims.gsize());
+    let converted =
+        bit_comp_as_ot_sender_batch_chunked(inputs, delta, qs, chunk_elems, |y0, us: &[A]| {
+            y0s.extend_from_slice(y0);
+            // send us
+            send_handles.push(send(peer, *ids.next().unwrap(), us.to_vec()));
+        });
+    if let Err(e) = converted {
+        // it fails before any chunk is sent
+        for &id in ids {
+            send_placeholder(id, peer);
+        }
+        return Err(e.into());
+    }
+    // clients with fewer inputs than chunks
+    send_handles.extend(ids.map(|&id| send(peer, id, Vec::<A>::new())));
+
+    Ok((y0s, ChunkedSendHandle::new(send_handles)))
+}
+
+/// Run OT B2A on one client, assuming I'm OT receiver with choices
+/// `inputs_1`. `ts` come from the client, or from `server_cot_bob`. The `us`
+/// arrive in `B2A_CHUNKS` chunks under `msg_ids`. What Alice sends is absorbed
+/// into `hasher_ab` before the client message is checked.
+pub async fn b2a_bob<I: UInt, A: UInt, H: MessageHash>(
+    msg_ids: &[RecvId],
+    dims: ProtocolDims,
+    inputs_1: &[BitsLE<I>],
+    ts: &[Block],
+    peer: MpcConnection,
+    hasher_ab: &mut H,
+) -> Result<Vec<A>, ClientFailure> {
+    assert_eq!(msg_ids.len(), B2A_CHUNKS);
+    // receive us
+    let chunk_elems = b2a_chunk_elems(dims.gsize());
+    let mut us = Vec::with_capacity(dims.num_ot());
+    // the chunks must have the sizes Alice sends, so that they split `us` at
+    // the boundaries of inputs
+    let mut misaligned = false;
+    for (i, &id) in msg_ids.iter().enumerate() {
+        let expected = dims
+            .gsize()
+            .saturating_sub(i * chunk_elems)
+            .min(chunk_elems)
+            * I::NUM_BITS;
+        let chunk = if cfg!(feature = "no-comm") {
+            vec![A::zero(); expected]
+        } else {
+            peer.subscribe_and_get::<Vec<A>>(id).await?
+        };
+        misaligned |= chunk.len() != expected;
+        us.extend_from_slice(&chunk);
+    }
+
+    hasher_ab.absorb(&us);
+
+    check_num_inputs(&dims, inputs_1.len())?;
+    let ts = input_ots(&dims, ts)?;
+    if misaligned {
+        return Err(ClientFailure::NotEnoughOts {
+            expected: dims.num_ot(),
+            actual: us.len(),
+        });
+    }
+    Ok(bit_comp_as_ot_receiver_batch_chunked(
+        inputs_1,
+        ts,
+        us.chunks(chunk_elems * I::NUM_BITS),
+    )?)
+}
+
+/// Turn ROTs of one client into COTs with the peer, assuming I'm OT sender.
+/// `ids` are for the correction I send and the choice flips I receive.
+pub async fn server_cot_alice<I: UInt>(
+    ids: (SendId, RecvId),
+    rots: ROTsForSender,
+    delta: Block,
+    peer: MpcConnection,
+) -> ServerCOTsForSender {
+    let (mut cots, correction) = ServerCOTsForSender::from_rots(rots, delta);
+    let num_inputs = cots.qs.len() / I::NUM_BITS;
+    let send_handle = send(&peer, ids.0, correction);
+
+    let flips = if cfg!(feature = "no-comm") {
+        vec![BitsLE(I::zero()); num_inputs]
+    } else {
+        peer.subscribe_and_get::<Vec<BitsLE<I>>>(ids.1)
+            .await
+            .unwrap()
+    };
+    cots.apply_flips(&flips);
+
+    send_handle
+        .await
+        .expect("MPC connection closed before the message was sent");
+    cots
+}
+
+/// Turn ROTs of one client into COTs with the peer, assuming I'm OT receiver,
+/// so that my choices are `inputs_1`. `ids` are for the correction I receive
+/// and the choice flips I send. Return `ts`.
+pub async fn server_cot_bob<I: UInt>(
+    ids: (RecvId, SendId),
+    rots: ROTsForReceiver,
+    inputs_1: &[BitsLE<I>],
+    peer: MpcConnection,
+) -> Vec<Block> {
+    let flips = choice_flips(&rots.choices, inputs_1);
+    let send_handle = send(&peer, ids.1, flips);
+
+    let correction = if cfg!(feature = "no-comm") {
+        vec![Block::default(); rots.mc.len()]
+    } else {
+        peer.subscribe_and_get::<Vec<Block>>(ids.0).await.unwrap()
+    };
+    let cots = ServerCOTsForReceiver::from_rots(rots, &correction);
+
+    send_handle
+        .await
+        .expect("MPC connection closed before the message was sent");
+    cots.ts
+}
@@ -0,0 +1,55 @@
+use bridge::{
+    id_tracker::{RecvId, SendId},
+    mpc_conn::MpcConnection,
+};
+use crypto_primitives::{
+    b2a::{bits_to_arith_as_ot_receiver, bits_to_arith_as_ot_sender},
+    bits::PackedBits,
+    cot::client::{B2ACOTToAlice, B2ACOTToBob},
+    uint::UInt,
+};
+use zeroize::Zeroizing;
+
+use crate::{send, send_placeholder, ClientFailure, SendHandle};
+
+/// Run B2A on the bits of one client, assuming I'm OT sender, see
+/// `bits_to_arith_as_ot_sender`. The `us` are sent in one message under
+/// `msg_id`. Return my arithmetic shares, and a handle for the send. If the
+/// client message is malformed, a placeholder is sent instead.
+pub fn bits_b2a_alice<A: UInt>(
+    msg_id: SendId,
+    shares: &PackedBits,
+    cot: &B2ACOTToAlice,
+    peer: &MpcConnection,
+) -> Result<(Vec<A>, SendHandle), ClientFailure> {
+    let qs = Zeroizing::new(cot.qs_seed.expand(shares.len()));
+    match bits_to_arith_as_ot_sender::<A>(shares, cot.delta, &qs) {
+        Ok((y0s, us)) => Ok((y0s, send(peer, msg_id, us))),
+        Err(e) => {
+            send_placeholder(msg_id, peer);
+            Err(e.into())
+        },
+    }
+}
+
+/// Same as `bits_b2a_alice`, assuming I'm OT receiver, where the choices of
+/// the COTs are my `shares`.
+pub async fn bits_b2a_bob<A: UInt>(
+    msg_id: RecvId,
+    shares: &PackedBits,
+    cot: &B2ACOTToBob,
+    peer: &MpcConnection,
+) -> Result<Vec<A>, ClientFailure> {
+    let us = if cfg!(feature = "no-comm") {
+        vec![A::zero(); shares.len()]
+    } else {
+        peer.subscribe_and_get::<Vec<A>>(msg_id).await?
+    };
+    if us.len() != shares.len() {
+        return Err(ClientFailure::WrongPeerLength {
+            expected: shares.len(),
+            actual: us.len(),
+        });
+    }
+    Ok(bits_to_arith_as_ot_receiver(shares, cot.ts(), &us)?)
+}
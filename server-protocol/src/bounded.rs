@@ -0,0 +1,114 @@
+use block::Block;
+use bridge::{
+    id_tracker::{ExchangeId, RecvId, SendId},
+    mpc_conn::MpcConnection,
+};
+use crypto_primitives::{
+    bits::PackedBits,
+    bounded::InputBound,
+    cot::server::TTil,
+    message::bounded::{ClientBoundedMsgToAlice, ClientBoundedMsgToBob},
+    uint::UInt,
+};
+use serialize::{AsUseCast, UseCast};
+
+use crate::{send, send_placeholder, ClientFailure};
+
+/// Run the range check of `bound` on one client, assuming I'm Alice, see
+/// `crypto_primitives::bounded`. `ids` are for the `us` of the AND gates I
+/// send, the OT verification message of Bob, and the opening of the accept
+/// bit. The COTs of the gates are verified with the coefficients of
+/// `chi_seed`, which must be sampled after the client messages are received,
+/// e.g. with `sample_chi_seed`. Return whether every input of the client is
+/// below the bound, or `ClientFailure::OtVerify` if its COTs are not
+/// consistent. The number of inputs comes from the message, so it must be
+/// checked before.
+pub async fn bounded_check_alice<I: UInt>(
+    ids: (SendId, RecvId, ExchangeId),
+    bound: &InputBound<I>,
+    msg: &ClientBoundedMsgToAlice,
+    sec_param: usize,
+    chi_seed: u64,
+    peer: &MpcConnection,
+) -> Result<bool, ClientFailure> {
+    let po2_msg = &msg.po2_msg;
+    let (share, us, ot_check) = bound.check_as_alice(
+        po2_msg.inputs_0,
+        po2_msg.gsize as usize,
+        &msg.check,
+        sec_param,
+        chi_seed,
+    );
+    let send_handle = send(peer, ids.0, us.iter().collect::<PackedBits>());
+    let verified = if cfg!(feature = "no-comm") {
+        true
+    } else {
+        // a placeholder of Bob fails the client, without verification
+        peer.subscribe_and_get::<(UseCast<Block>, TTil)>(ids.1)
+            .await
+            .is_ok_and(|(x_til, t_til)| ot_check.verify(x_til, t_til))
+    };
+    let accept = open_accept(ids.2, share, verified, peer).await;
+    send_handle
+        .await
+        .expect("MPC connection closed before the message was sent");
+    accept
+}
+
+/// Same as `bounded_check_alice`, assuming I'm Bob, so that `ids` are for
+/// the `us` I receive and the OT verification message I send. If the message
+/// of the client or the `us` do not fit its inputs, placeholders are sent
+/// instead of my messages.
+pub async fn bounded_check_bob<I: UInt>(
+    ids: (RecvId, SendId, ExchangeId),
+    bound: &InputBound<I>,
+    msg: &ClientBoundedMsgToBob<I>,
+    sec_param: usize,
+    chi_seed: u64,
+    peer: &MpcConnection,
+) -> Result<bool, ClientFailure> {
+    let inputs_1 = &msg.po2_msg.inputs_1;
+    let us = if cfg!(feature = "no-comm") {
+        Ok(vec![false; 2 * bound.num_and_gates(inputs_1.len())])
+    } else {
+        peer.subscribe_and_get::<PackedBits>(ids.0)
+            .await
+            .map(|us| us.iter().collect::<Vec<_>>())
+            .map_err(ClientFailure::from)
+    };
+    let checked =
+        us.and_then(|us| Ok(bound.check_as_bob(inputs_1, &msg.check, &us, sec_param, chi_seed)?));
+    match checked {
+        Ok((share, (x_til, t_til))) => {
+            send(peer, ids.1, (x_til.use_cast(), t_til));
+            // Bob has nothing to verify
+            open_accept(ids.2, share, true, peer).await
+        },
+        Err(e) => {
+            send_placeholder(ids.1, peer);
+            send_placeholder(ids.2.send_id, peer);
+            Err(e)
+        },
+    }
+}
+
+/// Exchange my share of the accept bit with `peer`, along with whether the
+/// COTs of the client passed my verification, and return the bit, or
+/// `ClientFailure::OtVerify` if they failed on either server.
+async fn open_accept(
+    id: ExchangeId,
+    share: bool,
+    verified: bool,
+    peer: &MpcConnection,
+) -> Result<bool, ClientFailure> {
+    let flags = share as u8 | (verified as u8) << 1;
+    let flags_peer = if cfg!(feature = "no-comm") {
+        0b10
+    } else {
+        peer.exchange_message(id, UseCast(flags)).await?
+    };
+    if flags & flags_peer & 0b10 == 0 {
+        return Err(ClientFailure::OtVerify);
+    }
+    Ok(share ^ (flags_peer & 1 == 1))
+}
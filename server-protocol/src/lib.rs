@@ -0,0 +1,861 @@
+//! Protocol steps run between the two servers on one client's messages, shared
+//! by all servers. The steps that are also checked by malicious-privacy
+//! servers take a `MessageHash`, which absorbs what the peer sends in the same
+//! order as the client transcript. Semi-honest servers pass `&mut ()`.
+
+use bridge::{
+    id_tracker::{ExchangeId, SendId},
+    mpc_conn::MpcConnection,
+    tcp_bridge::ClientID,
+    BridgeError,
+};
+use bytes::Bytes;
+use crypto_primitives::{
+    bits::{Inputs1Hash, SeededInputShare, ShareCommitment},
+    error::ProtocolError,
+    square_corr::CorrError,
+};
+#[cfg(not(feature = "no-comm"))]
+use rand::{rngs::OsRng, RngCore};
+use serialize::Communicate;
+#[cfg(not(feature = "no-comm"))]
+use serialize::UseCast;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use thiserror::Error;
+use tokio::sync::oneshot;
+
+mod b2a;
+mod bits;
+mod bounded;
+mod sqcorr;
+// insecure: the ROTs of the servers come from a seed known to both, see
+// `ROTsForSender::from_shared_seed`
+#[cfg(feature = "simulation-helpers")]
+mod sqcorr_gen;
+
+#[cfg(feature = "simulation-helpers")]
+pub use crate::sqcorr_gen::{server_sqcorr_alice, server_sqcorr_bob};
+pub use crate::{
+    b2a::{
+        b2a_alice, b2a_bob, b2a_sender, ot_verify_alice, ot_verify_bob, ot_verify_receiver,
+        server_cot_alice, server_cot_bob,
+    },
+    bits::{bits_b2a_alice, bits_b2a_bob},
+    bounded::{bounded_check_alice, bounded_check_bob},
+    sqcorr::{
+        a2s, bound_check, corr_verify, num_corr_checks, A2sRound, BoundCheckRound, CorrVerifyRound,
+    },
+};
+pub use crypto_primitives::b2a::B2A_CHUNKS;
+
+/// Send Handle is a receive channel indicating if `send` is finished.
+pub type SendHandle = oneshot::Receiver<()>;
+
+/// Resolves when the sends of all chunks of a message are finished, see
+/// `b2a_alice`.
+pub struct ChunkedSendHandle(Vec<SendHandle>);
+
+impl ChunkedSendHandle {
+    pub fn new(handles: Vec<SendHandle>) -> Self {
+        Self(handles)
+    }
+}
+
+impl Future for ChunkedSendHandle {
+    type Output = Result<(), oneshot::error::RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        while let Some(handle) = self.0.last_mut() {
+            match Pin::new(handle).poll(cx) {
+                Poll::Ready(Ok(())) => {
+                    self.0.pop();
+                },
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(not(feature = "no-comm"))]
+fn send<M: Communicate>(peer: &MpcConnection, id: SendId, msg: M) -> SendHandle {
+    peer.send_message(id, msg)
+}
+
+#[cfg(feature = "no-comm")]
+fn send<M: Communicate>(peer: &MpcConnection, id: SendId, msg: M) -> SendHandle {
+    peer.send_message_dummy(id, msg)
+}
+
+/// Failure of the protocol on one client. The other clients are not affected.
+#[derive(Error, Debug)]
+pub enum ClientFailure {
+    #[error("client sent {actual} inputs, but the group size is {expected}")]
+    WrongInputLength { expected: usize, actual: usize },
+    #[error("client sent {actual} OTs, but {expected} are needed")]
+    NotEnoughOts { expected: usize, actual: usize },
+    #[error("bad square correlations: {0}")]
+    SquareCorr(#[from] CorrError),
+    #[error("malformed message: {0}")]
+    Protocol(#[from] ProtocolError),
+    #[error("bad message from peer: {0}")]
+    Peer(#[from] BridgeError),
+    #[error("peer sent {actual} values, but {expected} are expected")]
+    WrongPeerLength { expected: usize, actual: usize },
+    #[error("panicked")]
+    Panicked,
+    #[error("failed on peer")]
+    FailedOnPeer,
+    #[error("sent shares that do not match its share commitment")]
+    CommitmentMismatch,
+    #[error("upload is not signed by the key of the client")]
+    BadSignature,
+    #[error("cannot deserialize its upload: {0}")]
+    Deserialize(#[from] serialize::Error),
+    #[error("its OTs do not pass OT verification")]
+    OtVerify,
+    #[error("its inputs are not within the bound")]
+    OutOfBound,
+}
+
+/// Sample the seed of `chi` for this round jointly with `peer`: each server
+/// contributes a random share, and the seed is their XOR. Clients learn it
+/// only after their OTs are sent, so they cannot prepare OTs that pass
+/// verification while being inconsistent.
+#[cfg(not(feature = "no-comm"))]
+pub async fn sample_chi_seed(msg_id: ExchangeId, peer: &MpcConnection) -> u64 {
+    let share = OsRng.next_u64();
+    let share_peer = peer
+        .exchange_message(msg_id, UseCast(share))
+        .await
+        .expect("exchange chi seed shares");
+    share ^ share_peer
+}
+
+/// Without a peer, both servers use the same fixed seed.
+#[cfg(feature = "no-comm")]
+pub async fn sample_chi_seed(_msg_id: ExchangeId, _peer: &MpcConnection) -> u64 {
+    crypto_primitives::simulation::fixed_chi_seed()
+}
+
+/// The share of a client a server holds, to check its `ShareCommitment`.
+#[derive(Debug, Clone, Copy)]
+pub enum HeldShare {
+    /// I am OT sender for the client, and got the seed of `inputs_0`.
+    Seed(SeededInputShare),
+    /// I am OT receiver for the client, and got `inputs_1` and its salt.
+    Inputs1(Inputs1Hash),
+}
+
+/// Check the share commitments of all clients, in uid order, with `peer`.
+/// For each client, the OT receiver sends its `Inputs1Hash`, and the OT
+/// sender sends its commitment. The OT sender recomputes the commitment from
+/// its seed, and the OT receiver compares the commitments. Return whether
+/// either check failed for each client, in which case its two shares do not
+/// come from the same sharing and it should be rejected before B2A. Both
+/// servers get the same result.
+///
+/// `session` must be the session of the clients, see `ShareCommitment`.
+pub async fn exchange_commitments(
+    msg_ids: (ExchangeId, ExchangeId),
+    session: u64,
+    uids: &[ClientID],
+    commitments: &[ShareCommitment],
+    held: &[HeldShare],
+    peer: &MpcConnection,
+) -> Vec<bool> {
+    assert_eq!(uids.len(), commitments.len());
+    assert_eq!(held.len(), commitments.len());
+    if cfg!(feature = "no-comm") {
+        return vec![false; commitments.len()];
+    }
+    let mine = commitments
+        .iter()
+        .zip(held)
+        .map(|(commitment, held)| match held {
+            HeldShare::Seed(_) => commitment.0,
+            HeldShare::Inputs1(hash) => hash.0,
+        })
+        .collect::<Vec<_>>();
+    let theirs = peer.exchange_message(msg_ids.0, mine).await.unwrap();
+    assert_eq!(theirs.len(), commitments.len());
+    let rejected = uids
+        .iter()
+        .zip(commitments)
+        .zip(held)
+        .zip(theirs)
+        .map(|(((uid, commitment), held), theirs)| match held {
+            HeldShare::Seed(seed) => {
+                ShareCommitment::from_hash(uid.id, session, *seed, Inputs1Hash(theirs))
+                    != *commitment
+            },
+            HeldShare::Inputs1(_) => ShareCommitment(theirs) != *commitment,
+        })
+        .collect::<Vec<_>>();
+    exchange_dropped(msg_ids.1, &rejected, peer).await
+}
+
+/// Exchange the number of inputs of all clients, in uid order, with `peer`.
+/// `None` is a client whose message is inconsistent with its own length on
+/// my side. Return whether each client should be rejected because either
+/// server has `None` for it, or the two servers got different lengths. Both
+/// servers get the same result.
+pub async fn exchange_gsizes(
+    msg_id: ExchangeId,
+    gsizes: &[Option<usize>],
+    peer: &MpcConnection,
+) -> Vec<bool> {
+    // `u64::MAX` is never a valid length, so it stands for `None`
+    let mine = gsizes
+        .iter()
+        .map(|g| g.map_or(u64::MAX, |g| g as u64))
+        .collect::<Vec<_>>();
+    if cfg!(feature = "no-comm") {
+        return mine.iter().map(|g| *g == u64::MAX).collect();
+    }
+    let theirs = peer.exchange_message(msg_id, mine.clone()).await.unwrap();
+    assert_eq!(theirs.len(), mine.len());
+    mine.iter()
+        .zip(&theirs)
+        .map(|(mine, theirs)| *mine == u64::MAX || mine != theirs)
+        .collect()
+}
+
+/// Exchange whether each client, in uid order, dropped out on my side, e.g.
+/// did not send phase 2 in time, with `peer`. Return whether each client
+/// dropped out on either server. Both servers get the same result.
+pub async fn exchange_dropped(
+    msg_id: ExchangeId,
+    dropped: &[bool],
+    peer: &MpcConnection,
+) -> Vec<bool> {
+    if cfg!(feature = "no-comm") {
+        return dropped.to_vec();
+    }
+    let mine = dropped.iter().map(|&d| d as u8).collect::<Vec<_>>();
+    let theirs = peer.exchange_message(msg_id, mine).await.unwrap();
+    assert_eq!(theirs.len(), dropped.len());
+    dropped
+        .iter()
+        .zip(&theirs)
+        .map(|(mine, theirs)| *mine || *theirs != 0)
+        .collect()
+}
+
+/// Deserialize an upload of message `M`, followed by the shares of the
+/// witness bits `W` of the client if `with_witness`, see
+/// `a2s::make_witness_shares`.
+pub fn parse_upload<M: Communicate, W: Communicate>(
+    mut bytes: &[u8],
+    with_witness: bool,
+) -> serialize::Result<(M::Deserialized, Option<W::Deserialized>)> {
+    let msg = M::from_bytes(&mut bytes)?;
+    let witness = if with_witness {
+        Some(W::from_bytes(&mut bytes)?)
+    } else {
+        None
+    };
+    Ok((msg, witness))
+}
+
+/// Split client messages, each parsed or failed with the reason, into the
+/// uids and messages of the parsed clients, and the failed clients with
+/// their reasons, in the same order. The failed clients must be exchanged
+/// with `exchange_failed_uids` before the protocol.
+#[allow(clippy::type_complexity)]
+pub fn split_parsed<T>(
+    parsed: impl IntoIterator<Item = (ClientID, Result<T, ClientFailure>)>,
+) -> (Vec<ClientID>, Vec<T>, Vec<(ClientID, ClientFailure)>) {
+    let mut uids = Vec::new();
+    let mut msgs = Vec::new();
+    let mut failed = Vec::new();
+    for (uid, parsed) in parsed {
+        match parsed {
+            Ok(msg) => {
+                uids.push(uid);
+                msgs.push(msg);
+            },
+            Err(failure) => failed.push((uid, failure)),
+        }
+    }
+    (uids, msgs, failed)
+}
+
+/// Exchange the clients that failed on my side before the protocol, e.g.
+/// whose upload cannot be deserialized, with `peer`. `uids` are the other
+/// clients, and both are in uid order; together they are all the clients,
+/// the same on both servers. Return whether each client of `uids` failed on
+/// the peer, and all clients that failed on either server, in uid order.
+/// Both servers get the same failed clients, so they can leave them out
+/// before the message ids are allocated.
+pub async fn exchange_failed_uids(
+    msg_id: ExchangeId,
+    uids: &[ClientID],
+    failed: &[ClientID],
+    peer: &MpcConnection,
+) -> (Vec<bool>, Vec<ClientID>) {
+    let mut all = [uids, failed].concat();
+    all.sort_unstable();
+    let mine = all
+        .iter()
+        .map(|uid| failed.binary_search(uid).is_ok())
+        .collect::<Vec<_>>();
+    let either = exchange_dropped(msg_id, &mine, peer).await;
+    let all_failed = all
+        .iter()
+        .zip(&either)
+        .filter(|(_, failed)| **failed)
+        .map(|(uid, _)| *uid)
+        .collect::<Vec<_>>();
+    let failed_on_peer = uids
+        .iter()
+        .map(|uid| all_failed.binary_search(uid).is_ok())
+        .collect();
+    (failed_on_peer, all_failed)
+}
+
+/// Exchange one message per client with `peer`, in one batch each way
+/// instead of one frame per client. `msgs[i]` is sent with `ids[i].send_id`,
+/// and the message of the peer with `ids[i].recv_id` is returned at index `i`.
+#[cfg(not(feature = "no-comm"))]
+pub async fn exchange_batch(
+    ids: &[ExchangeId],
+    msgs: Vec<Bytes>,
+    peer: &MpcConnection,
+) -> Result<Vec<Bytes>, BridgeError> {
+    assert_eq!(ids.len(), msgs.len());
+    let sent = peer.send_batch(ids.iter().map(|id| id.send_id).zip(msgs).collect());
+    let recv_ids = ids.iter().map(|id| id.recv_id).collect::<Vec<_>>();
+    let received = peer.subscribe_batch(&recv_ids).await;
+    sent.await
+        .expect("MPC connection closed before the batch was sent");
+    received
+}
+
+/// Without a peer, my own messages stand in for the ones of the peer, so that
+/// their sizes match.
+#[cfg(feature = "no-comm")]
+pub async fn exchange_batch(
+    ids: &[ExchangeId],
+    msgs: Vec<Bytes>,
+    _peer: &MpcConnection,
+) -> Result<Vec<Bytes>, BridgeError> {
+    assert_eq!(ids.len(), msgs.len());
+    Ok(msgs)
+}
+
+/// Send an empty message instead of the one with `msg_id`, which cannot be
+/// computed because the client failed. The peer fails to deserialize it and
+/// fails the client too, instead of waiting forever.
+#[cfg(not(feature = "no-comm"))]
+pub fn send_placeholder(msg_id: SendId, peer: &MpcConnection) -> SendHandle {
+    peer.send_message_bytes(msg_id, Bytes::new())
+}
+
+#[cfg(feature = "no-comm")]
+pub fn send_placeholder(msg_id: SendId, peer: &MpcConnection) -> SendHandle {
+    peer.send_message_dummy(msg_id, Vec::<u8>::new())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "simulation-helpers")]
+    use block::Block;
+    use bridge::{
+        id_tracker::{ExchangeId, RecvId, SendId},
+        mpc_conn::MpcConnection,
+        tcp_bridge::ClientID,
+    };
+    #[cfg(not(feature = "no-comm"))]
+    use crypto_primitives::{bits::Inputs1Hash, cot::server::sample_chi};
+    use crypto_primitives::{
+        cot::{
+            client::DEFAULT_SEC_PARAM,
+            dims::ProtocolDims,
+            server::{Chi, REDUCED_OT_VERIFY},
+        },
+        error::ProtocolError,
+        malpriv::MessageHash,
+        message::l2::{ClientL2MsgToAlice, ClientL2MsgToBob},
+        square_corr::{SquareCorrShare, COMPRESSED_CORR_VERIFY},
+        uint::UInt,
+        ALICE, BOB,
+    };
+    use elsa_client::{l2::L2Client, po2::SingleRoundClient};
+    use rand::{rngs::StdRng, SeedableRng};
+    use serialize::UseCast;
+    use sha2::Sha256;
+    use tokio::net::TcpListener;
+    // insecure ROTs of the server-generated COTs and correlations
+    #[cfg(feature = "simulation-helpers")]
+    use crypto_primitives::cot::rot::server_generated::{ROTsForReceiver, ROTsForSender};
+
+    type I = u8;
+    type A = u64;
+    type C = u128;
+
+    const GSIZE: usize = 4;
+    const CHI_SEED: u64 = 0xc41;
+    const T_SEED: u64 = 0x12345678;
+    #[cfg(feature = "simulation-helpers")]
+    const ROT_SEED: u64 = 0x207;
+
+    // Transcript digests of (Alice, Bob), recorded with the `mpc` modules of
+    // server-mp (MP) and of server-l2 and server-po2 (semi-honest) before
+    // they were merged into this crate. MP clients hash the same messages, so
+    // a change here breaks hash verification against existing clients.
+    const GOLDEN_MP: (&str, &str) = (
+        "6079f0c6af4a7d5ed2de395f61fdbcda87026b7caddf0bc8817f214e0ee4479e",
+        "4833591700c2694cfc0e897ce4abcec2856dfc65776541625c28cc67357dced9",
+    );
+    // the same with `reduced-ot-verify`: Alice hashes the reduced `t_til`
+    const GOLDEN_MP_REDUCED: (&str, &str) = (
+        "4bf8b1f9772e353baeaf63717ce79f100db94fa5a7ee6a6487990ca52b99fdd6",
+        "4833591700c2694cfc0e897ce4abcec2856dfc65776541625c28cc67357dced9",
+    );
+    // the same with `compressed-corr-verify`: one combined `w` is opened
+    const GOLDEN_MP_COMPRESSED: (&str, &str) = (
+        "517f38d6bcb4a5f5a18e409f3afe7d9f4ef3d77aa42e6f1e7028062f5435419e",
+        "3ea44be2fd32dc8862d161cb8b5edfa9aaacb3970f92bf950e21a199f84dbf9c",
+    );
+    const GOLDEN_MP_REDUCED_COMPRESSED: (&str, &str) = (
+        "b8de5ed769d5dc80eae11d37daf2fb51292b02e643fb07de6db229529a71da43",
+        "3ea44be2fd32dc8862d161cb8b5edfa9aaacb3970f92bf950e21a199f84dbf9c",
+    );
+    #[cfg(feature = "simulation-helpers")]
+    const GOLDEN_SEMI_HONEST: (&str, &str) = (
+        "9bea707185c9dc3bccd94dc1ac114ec2de1783ab169597127b193b79204c21fc",
+        "79a50b622f83090fa48f7944852ea00b92c4d3f34eb4c9871ff9b292655cb545",
+    );
+    #[cfg(feature = "simulation-helpers")]
+    const GOLDEN_SEMI_HONEST_COMPRESSED: (&str, &str) = (
+        "207b0bb1814034d494a9e5b889eed129f475709bad8b0a3cd1e684c87b19ff1f",
+        "9ea8a7c19675a34607675cbbf728fd551b56bb184818dfc8de49e01aae701dfb",
+    );
+
+    async fn mpc_pair() -> (MpcConnection, MpcConnection) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::join!(
+            MpcConnection::new_as_alice_with_listener(listener, 2),
+            MpcConnection::new_as_bob(addr, 2)
+        )
+    }
+
+    fn client_msgs(seed: u64) -> (ClientL2MsgToAlice, ClientL2MsgToBob<I, C>) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let input = (0..GSIZE).map(|_| I::rand(&mut rng)).collect::<Vec<_>>();
+        let client =
+            L2Client::<I, C>::new(ClientID::new(0), 0, &input, &mut rng, DEFAULT_SEC_PARAM);
+        (client.prepared_message_0, client.prepared_message_1)
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn chi(dims: &ProtocolDims) -> Chi {
+        Chi::Seeded {
+            num_ots: dims.num_total(),
+            seed: CHI_SEED,
+        }
+    }
+
+    /// Ids of the B2A chunks, starting at `first`.
+    fn send_ids(first: u64) -> Vec<SendId> {
+        (first..).take(super::B2A_CHUNKS).map(SendId).collect()
+    }
+
+    fn recv_ids(first: u64) -> Vec<RecvId> {
+        (first..).take(super::B2A_CHUNKS).map(RecvId).collect()
+    }
+
+    /// OT Verify, B2A, SqCorr Verify and A2S as Alice. Every output and the
+    /// final digest of `H` are absorbed into `out`.
+    async fn run_alice<H: MessageHash + Default>(
+        msg: &ClientL2MsgToAlice,
+        peer: &MpcConnection,
+        out: &mut Sha256,
+    ) {
+        let mut hasher = H::default();
+        let dims = ProtocolDims::new::<I>(GSIZE, DEFAULT_SEC_PARAM).unwrap();
+        let cot = &msg.po2_msg.cot;
+        let (qs, ok) = super::ot_verify_alice(
+            RecvId(1),
+            cot,
+            chi(&dims),
+            DEFAULT_SEC_PARAM,
+            peer.clone(),
+            &mut hasher,
+        )
+        .await
+        .unwrap();
+        out.absorb(&qs);
+        out.absorb(&UseCast(ok as u64));
+
+        let (y0s, handle) = super::b2a_alice::<I, A>(
+            &send_ids(10),
+            dims,
+            msg.po2_msg.inputs_0,
+            cot.delta,
+            &qs,
+            peer,
+        )
+        .unwrap();
+        handle.await.unwrap();
+        out.absorb(&y0s);
+
+        let sq = msg.square_corr.expand_checked::<C>(GSIZE).unwrap();
+        let passed = super::corr_verify::<C, ALICE, _>(
+            ExchangeId::from(3),
+            ExchangeId::from(4),
+            GSIZE,
+            &sq,
+            T_SEED,
+            peer.clone(),
+            &mut hasher,
+        )
+        .await;
+        out.absorb(&UseCast(passed as u64));
+
+        let squares =
+            super::a2s::<A, C, _, ALICE>(ExchangeId::from(5), &y0s, &sq, peer.clone(), &mut hasher)
+                .await;
+        out.absorb(&squares);
+        out.absorb(&hasher.digest());
+    }
+
+    /// Same as `run_alice`, as Bob.
+    async fn run_bob<H: MessageHash + Default>(
+        msg: &ClientL2MsgToBob<I, C>,
+        peer: &MpcConnection,
+        out: &mut Sha256,
+    ) {
+        let mut hasher = H::default();
+        let dims = ProtocolDims::new::<I>(GSIZE, DEFAULT_SEC_PARAM).unwrap();
+        let po2_msg = &msg.po2_msg;
+        super::ot_verify_bob(SendId(1), po2_msg, peer, chi(&dims), dims)
+            .unwrap()
+            .await
+            .unwrap();
+
+        let shares = super::b2a_bob::<I, A, _>(
+            &recv_ids(10),
+            dims,
+            &po2_msg.inputs_1,
+            po2_msg.cot.ts(),
+            peer.clone(),
+            &mut hasher,
+        )
+        .await
+        .unwrap();
+        out.absorb(&shares);
+
+        let sq = msg.square_corr.expand_checked(GSIZE).unwrap();
+        let passed = super::corr_verify::<C, BOB, _>(
+            ExchangeId::from(3),
+            ExchangeId::from(4),
+            GSIZE,
+            &sq,
+            T_SEED,
+            peer.clone(),
+            &mut hasher,
+        )
+        .await;
+        out.absorb(&UseCast(passed as u64));
+
+        let squares = super::a2s::<A, C, _, BOB>(
+            ExchangeId::from(5),
+            &shares,
+            &sq,
+            peer.clone(),
+            &mut hasher,
+        )
+        .await;
+        out.absorb(&squares);
+        out.absorb(&hasher.digest());
+    }
+
+    #[cfg(feature = "simulation-helpers")]
+    /// Server-generated COTs and B2A on them as Alice.
+    async fn run_server_cot_alice(
+        msg: &ClientL2MsgToAlice,
+        peer: &MpcConnection,
+        out: &mut Sha256,
+    ) {
+        let dims = ProtocolDims::with_num_additional::<I>(GSIZE, 0).unwrap();
+        let delta = Block::rand(&mut StdRng::seed_from_u64(ROT_SEED));
+        let rots = ROTsForSender::from_shared_seed(ROT_SEED, dims.num_ot());
+        let cots =
+            super::server_cot_alice::<I>((SendId(6), RecvId(7)), rots, delta, peer.clone()).await;
+        out.absorb(&cots.qs);
+
+        let (y0s, handle) = super::b2a_alice::<I, A>(
+            &send_ids(20),
+            dims,
+            msg.po2_msg.inputs_0,
+            cots.delta,
+            &cots.qs,
+            peer,
+        )
+        .unwrap();
+        handle.await.unwrap();
+        out.absorb(&y0s);
+    }
+
+    #[cfg(feature = "simulation-helpers")]
+    /// Same as `run_server_cot_alice`, as Bob.
+    async fn run_server_cot_bob(
+        msg: &ClientL2MsgToBob<I, C>,
+        peer: &MpcConnection,
+        out: &mut Sha256,
+    ) {
+        let dims = ProtocolDims::with_num_additional::<I>(GSIZE, 0).unwrap();
+        let inputs_1 = &msg.po2_msg.inputs_1;
+        let rots = ROTsForReceiver::from_shared_seed(ROT_SEED, dims.num_ot());
+        let ts =
+            super::server_cot_bob::<I>((RecvId(6), SendId(7)), rots, inputs_1, peer.clone()).await;
+        out.absorb(&ts);
+
+        let shares =
+            super::b2a_bob::<I, A, _>(&recv_ids(20), dims, inputs_1, &ts, peer.clone(), &mut ())
+                .await
+                .unwrap();
+        out.absorb(&shares);
+    }
+
+    #[cfg(not(feature = "no-comm"))]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_commitments_bind_both_shares() {
+        const SESSION: u64 = 288;
+        const BAD_UID: u64 = 1;
+        let client = |uid: u64, seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let input = [uid as I; GSIZE];
+            L2Client::<I, C>::new(
+                ClientID::new(uid),
+                SESSION,
+                &input,
+                &mut rng,
+                DEFAULT_SEC_PARAM,
+            )
+        };
+        // Alice is OT sender for every client
+        let uids = (0..3).map(ClientID::new).collect::<Vec<_>>();
+        let (mut commitments, mut held_alice, mut held_bob) = (Vec::new(), Vec::new(), Vec::new());
+        for uid in &uids {
+            let to_alice = client(uid.id, uid.id).prepared_message_0.po2_msg;
+            let mut to_bob = client(uid.id, uid.id).prepared_message_1.po2_msg;
+            if uid.id == BAD_UID {
+                // Bob gets a share of the same input from another sharing, but
+                // the same commitment as Alice
+                to_bob = client(uid.id, 999).prepared_message_1.po2_msg;
+                to_bob.commitment = to_alice.commitment;
+            }
+            assert_eq!(to_alice.commitment, to_bob.commitment);
+            commitments.push(to_alice.commitment);
+            held_alice.push(super::HeldShare::Seed(to_alice.inputs_0));
+            held_bob.push(super::HeldShare::Inputs1(Inputs1Hash::new(
+                to_bob.salt,
+                &to_bob.inputs_1,
+            )));
+        }
+
+        let (alice, bob) = mpc_pair().await;
+        let check = |ids: (u64, u64), session_alice: u64| {
+            let ids = (
+                ExchangeId::from((ids.0, ids.0)),
+                ExchangeId::from((ids.1, ids.1)),
+            );
+            let (uids, commitments) = (&uids, &commitments);
+            let (held_alice, held_bob, alice, bob) = (&held_alice, &held_bob, &alice, &bob);
+            async move {
+                tokio::join!(
+                    super::exchange_commitments(
+                        ids,
+                        session_alice,
+                        uids,
+                        commitments,
+                        held_alice,
+                        alice
+                    ),
+                    super::exchange_commitments(ids, SESSION, uids, commitments, held_bob, bob)
+                )
+            }
+        };
+        let expected = vec![false, true, false];
+        assert_eq!(check((0, 1), SESSION).await, (expected.clone(), expected));
+        // the commitments are bound to the session
+        let expected = vec![true; 3];
+        assert_eq!(
+            check((2, 3), SESSION + 1).await,
+            (expected.clone(), expected)
+        );
+    }
+
+    #[cfg(not(feature = "no-comm"))]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_chi_seed_is_joint_and_fresh() {
+        let (alice, bob) = mpc_pair().await;
+        let mut seeds = Vec::new();
+        for id in 0..2 {
+            let id = ExchangeId::from((id, id));
+            let (seed_alice, seed_bob) = tokio::join!(
+                super::sample_chi_seed(id, &alice),
+                super::sample_chi_seed(id, &bob)
+            );
+            assert_eq!(seed_alice, seed_bob);
+            let dims = ProtocolDims::new::<I>(GSIZE, DEFAULT_SEC_PARAM).unwrap();
+            assert_eq!(
+                sample_chi(dims.num_total(), seed_alice),
+                sample_chi(dims.num_total(), seed_bob)
+            );
+            seeds.push(seed_alice);
+        }
+        // a fixed seed would let clients prepare OTs that pass verification
+        assert_ne!(seeds[0], seeds[1]);
+    }
+
+    #[cfg(not(feature = "no-comm"))]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_failed_uids_are_joined() {
+        let (alice, bob) = mpc_pair().await;
+        let uids = |ids: &[u64]| ids.iter().map(|id| ClientID::new(*id)).collect::<Vec<_>>();
+        // client 1 fails on Alice, clients 3 and 4 on Bob
+        let id = ExchangeId::from((0, 0));
+        let (parsed_alice, failed_alice) = (uids(&[0, 2, 3, 4]), uids(&[1]));
+        let (parsed_bob, failed_bob) = (uids(&[0, 1, 2]), uids(&[3, 4]));
+        let (on_alice, on_bob) = tokio::join!(
+            super::exchange_failed_uids(id, &parsed_alice, &failed_alice, &alice),
+            super::exchange_failed_uids(id, &parsed_bob, &failed_bob, &bob)
+        );
+        assert_eq!(on_alice, (vec![false, false, true, true], uids(&[1, 3, 4])));
+        assert_eq!(on_bob, (vec![false, true, false], uids(&[1, 3, 4])));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_mp_transcript_matches_golden() {
+        let (to_alice, to_bob) = client_msgs(0x901d);
+        let (alice, bob) = mpc_pair().await;
+        let digests = tokio::join!(
+            async {
+                let mut out = Sha256::default();
+                run_alice::<Sha256>(&to_alice, &alice, &mut out).await;
+                hex(&out.digest())
+            },
+            async {
+                let mut out = Sha256::default();
+                run_bob::<Sha256>(&to_bob, &bob, &mut out).await;
+                hex(&out.digest())
+            }
+        );
+        let golden = match (REDUCED_OT_VERIFY, COMPRESSED_CORR_VERIFY) {
+            (false, false) => GOLDEN_MP,
+            (true, false) => GOLDEN_MP_REDUCED,
+            (false, true) => GOLDEN_MP_COMPRESSED,
+            (true, true) => GOLDEN_MP_REDUCED_COMPRESSED,
+        };
+        assert_eq!((digests.0.as_str(), digests.1.as_str()), golden);
+    }
+
+    #[cfg(feature = "simulation-helpers")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_semi_honest_transcript_matches_golden() {
+        let (to_alice, to_bob) = client_msgs(0x901e);
+        let (alice, bob) = mpc_pair().await;
+        let digests = tokio::join!(
+            async {
+                let mut out = Sha256::default();
+                run_alice::<()>(&to_alice, &alice, &mut out).await;
+                run_server_cot_alice(&to_alice, &alice, &mut out).await;
+                hex(&out.digest())
+            },
+            async {
+                let mut out = Sha256::default();
+                run_bob::<()>(&to_bob, &bob, &mut out).await;
+                run_server_cot_bob(&to_bob, &bob, &mut out).await;
+                hex(&out.digest())
+            }
+        );
+        let golden = if COMPRESSED_CORR_VERIFY {
+            GOLDEN_SEMI_HONEST_COMPRESSED
+        } else {
+            GOLDEN_SEMI_HONEST
+        };
+        assert_eq!((digests.0.as_str(), digests.1.as_str()), golden);
+    }
+
+    #[cfg(all(feature = "simulation-helpers", not(feature = "no-comm")))]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_server_sqcorr_pass_corr_verify() {
+        let (alice, bob) = mpc_pair().await;
+        let count = 2 * GSIZE;
+        let num_rots = count * C::NUM_BITS;
+        let (corr_a, corr_b) = tokio::join!(
+            super::server_sqcorr_alice::<C>(
+                (SendId(1), RecvId(2), SendId(3)),
+                count,
+                ROTsForSender::from_shared_seed(ROT_SEED, num_rots),
+                alice.clone(),
+            ),
+            super::server_sqcorr_bob::<C>(
+                (RecvId(1), SendId(2), RecvId(3)),
+                count,
+                ROTsForReceiver::from_shared_seed(ROT_SEED, num_rots),
+                bob.clone(),
+            )
+        );
+        let corr_b = corr_b.unwrap();
+        for (a, b) in corr_a.iter().zip(&corr_b) {
+            let x = a.a().wrapping_add(b.a());
+            assert_eq!(a.c().wrapping_add(b.c()), x.wrapping_mul(x));
+        }
+
+        let ids = (ExchangeId::from(4), ExchangeId::from(5));
+        let (mut hasher_a, mut hasher_b) = ((), ());
+        let passed = tokio::join!(
+            super::corr_verify::<C, ALICE, _>(
+                ids.0,
+                ids.1,
+                GSIZE,
+                &corr_a,
+                T_SEED,
+                alice,
+                &mut hasher_a
+            ),
+            super::corr_verify::<C, BOB, _>(
+                ids.0,
+                ids.1,
+                GSIZE,
+                &corr_b,
+                T_SEED,
+                bob,
+                &mut hasher_b
+            )
+        );
+        let checks = super::num_corr_checks(GSIZE);
+        assert_eq!(passed, (checks, checks));
+    }
+
+    #[test]
+    fn test_short_sqcorr_fails_client() {
+        let corr = vec![SquareCorrShare::<C>([0; 2]); 2 * GSIZE - 1];
+        let failure = match super::CorrVerifyRound::new(GSIZE, &corr, T_SEED) {
+            Ok(_) => panic!("accepted {} correlations", corr.len()),
+            Err(failure) => failure,
+        };
+        assert!(matches!(
+            failure,
+            super::ClientFailure::Protocol(ProtocolError::LengthMismatch {
+                expected,
+                actual,
+                what: "square correlations",
+            }) if expected == 2 * GSIZE && actual == 2 * GSIZE - 1
+        ));
+    }
+}
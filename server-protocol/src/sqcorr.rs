@@ -0,0 +1,354 @@
+use bridge::{id_tracker::ExchangeId, mpc_conn::MpcConnection};
+use crypto_primitives::{
+    a2s::{
+        batch_a2s_first, batch_a2s_second, batch_bound_check_first, batch_bound_check_result,
+        batch_bound_check_second, bound_witness_len,
+    },
+    error::ProtocolError,
+    malpriv::MessageHash,
+    square_corr::{SquareCorrShare, COMPRESSED_CORR_VERIFY},
+    uint::UInt,
+    utils::{batch_wrapping_add, SliceExt},
+};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::ClientFailure;
+
+/// Fails if the peer sent `actual` values instead of `expected`.
+fn check_peer_len(expected: usize, actual: usize) -> Result<(), ClientFailure> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(ClientFailure::WrongPeerLength { expected, actual })
+    }
+}
+
+/// Number of shares of `w` of a client with `input_len` inputs, i.e. of
+/// checks `count_passed` counts: one per correlation, or a single one if
+/// `COMPRESSED_CORR_VERIFY` is set.
+pub fn num_corr_checks(input_len: usize) -> usize {
+    if COMPRESSED_CORR_VERIFY {
+        1
+    } else {
+        input_len
+    }
+}
+
+/// `corr_verify` of one client, split at its two exchanges, so that a server
+/// can exchange the messages of all clients in one batch.
+pub struct CorrVerifyRound<'a, C: UInt> {
+    corr_b: &'a [SquareCorrShare<C>],
+    sacr_b: &'a [SquareCorrShare<C>],
+    t_seed: u64,
+    t: Vec<C>,
+    db: Vec<C>,
+}
+
+impl<'a, C: UInt> CorrVerifyRound<'a, C> {
+    /// Sample `t` from `t_seed`, and compute my share of `d`. Fails unless
+    /// there are two correlations per input.
+    pub fn new(
+        input_len: usize,
+        square_corr: &'a [SquareCorrShare<C>],
+        t_seed: u64,
+    ) -> Result<Self, ClientFailure> {
+        let mut t_rng = StdRng::seed_from_u64(t_seed);
+
+        if square_corr.len() != input_len * 2 {
+            return Err(ProtocolError::LengthMismatch {
+                expected: input_len * 2,
+                actual: square_corr.len(),
+                what: "square correlations",
+            }
+            .into());
+        }
+        let mut db = vec![C::zero(); input_len];
+        let corr_b = &square_corr[..input_len];
+        let sacr_b = &square_corr[input_len..];
+        let t = (0..input_len)
+            .map(|_| C::rand(&mut t_rng))
+            .collect::<Vec<_>>();
+
+        SquareCorrShare::verify_phase_1(corr_b, sacr_b, &t, &mut db)?;
+        Ok(Self {
+            corr_b,
+            sacr_b,
+            t_seed,
+            t,
+            db,
+        })
+    }
+
+    /// My share of `d`, sent in the first exchange.
+    pub fn db(&self) -> &Vec<C> {
+        &self.db
+    }
+
+    /// Open `d` with the share of the peer, which is absorbed into `hasher`.
+    /// Return my shares of `w`, sent in the second exchange, see
+    /// `SquareCorrShare::verify_phase_2_shares`.
+    pub fn open_d<const PARTY: bool, H: MessageHash>(
+        &self,
+        db_other: Vec<C>,
+        hasher: &mut H,
+    ) -> Result<Vec<C>, ClientFailure> {
+        hasher.absorb(&db_other);
+
+        check_peer_len(self.db.len(), db_other.len())?;
+
+        let d = batch_wrapping_add(&self.db, &db_other);
+
+        Ok(SquareCorrShare::verify_phase_2_shares::<{ PARTY }>(
+            self.corr_b,
+            self.sacr_b,
+            &self.t,
+            &d,
+            self.t_seed,
+        )?)
+    }
+
+    /// Number of passed checks, out of `num_corr_checks`, given both shares
+    /// of `w`. The share of the peer is absorbed into `hasher`.
+    pub fn count_passed<H: MessageHash>(
+        wb: &[C],
+        wb_other: Vec<C>,
+        hasher: &mut H,
+    ) -> Result<usize, ClientFailure> {
+        hasher.absorb(&wb_other);
+
+        check_peer_len(wb.len(), wb_other.len())?;
+
+        Ok(wb
+            .iter()
+            .zip(wb_other.iter())
+            .filter(|(a, b)| a.wrapping_add(b).is_zero())
+            .count())
+    }
+}
+
+/// parties exchange their shares to open `d`. Return number of passed
+/// checks, out of `num_corr_checks`. Both shares the peer sends are absorbed
+/// into `hasher`.
+pub async fn corr_verify<C: UInt, const PARTY: bool, H: MessageHash>(
+    msg_id1: ExchangeId,
+    msg_id2: ExchangeId,
+    input_len: usize,
+    square_corr: &[SquareCorrShare<C>],
+    t_seed: u64,
+    peer: MpcConnection,
+    hasher: &mut H,
+) -> usize {
+    let round = CorrVerifyRound::new(input_len, square_corr, t_seed).unwrap();
+
+    let db_other = if cfg!(feature = "no-comm") {
+        vec![C::zero(); input_len]
+    } else {
+        peer.exchange_message(msg_id1, round.db()).await.unwrap()
+    };
+
+    let wb = round.open_d::<PARTY, _>(db_other, hasher).unwrap();
+
+    let wb_other = if cfg!(feature = "no-comm") {
+        vec![C::zero(); wb.len()]
+    } else {
+        peer.exchange_message(msg_id2, &wb).await.unwrap()
+    };
+
+    CorrVerifyRound::count_passed(&wb, wb_other, hasher).unwrap()
+}
+
+/// `a2s` of one client, split at its exchange, so that a server can exchange
+/// the messages of all clients in one batch.
+pub struct A2sRound<A: UInt> {
+    corr: Vec<SquareCorrShare<A>>,
+    eb: Vec<A>,
+}
+
+impl<A: UInt> A2sRound<A> {
+    /// Compute my share of `e` for the inputs `xb`.
+    pub fn new<C: UInt>(xb: &[A], square_corr: &[SquareCorrShare<C>]) -> Self {
+        let size = xb.len();
+        let corr = square_corr[..size]
+            .iter()
+            .map(|x| x.cut())
+            .collect::<Vec<SquareCorrShare<A>>>();
+        assert_eq!(corr.len(), size);
+
+        let eb = batch_a2s_first(xb, &corr);
+        Self { corr, eb }
+    }
+
+    /// My share of `e`, sent in the exchange.
+    pub fn eb(&self) -> &Vec<A> {
+        &self.eb
+    }
+
+    /// Open `e` with the share of the peer, which is absorbed into
+    /// `hasher_other`. Return the share of the squares of `xb`.
+    pub fn finish<const PARTY: bool, H: MessageHash>(
+        &self,
+        xb: &[A],
+        eb_other: Vec<A>,
+        hasher_other: &mut H,
+    ) -> Result<Vec<A>, ClientFailure> {
+        hasher_other.absorb(&eb_other);
+
+        check_peer_len(self.eb.len(), eb_other.len())?;
+
+        let e = self.eb.par_zip_map(&eb_other, |a, b| a.wrapping_add(b));
+
+        Ok(batch_a2s_second::<_, PARTY>(&e, xb, &self.corr))
+    }
+}
+
+/// return the share of squares of each input
+pub async fn a2s<A: UInt, C: UInt, H: MessageHash, const PARTY: bool>(
+    msg_id: ExchangeId,
+    xb: &[A],
+    square_corr: &[SquareCorrShare<C>],
+    peer: MpcConnection,
+    hasher_other: &mut H,
+) -> Vec<A> {
+    let round = A2sRound::new(xb, square_corr);
+    let eb_other = if cfg!(feature = "no-comm") {
+        vec![A::zero(); xb.len()]
+    } else {
+        peer.exchange_message(msg_id, round.eb()).await.unwrap()
+    };
+
+    round
+        .finish::<PARTY, _>(xb, eb_other, hasher_other)
+        .unwrap()
+}
+
+/// Bound check of one client after A2S, see `a2s::batch_bound_check_second`,
+/// split at its two exchanges like `A2sRound`.
+pub struct BoundCheckRound<A: UInt> {
+    bound: A,
+    wb: Vec<A>,
+    corr: Vec<SquareCorrShare<A>>,
+    eb: Vec<A>,
+}
+
+impl<A: UInt> BoundCheckRound<A> {
+    /// Compute my share of `e` for the witness bits `witness` of `input_len`
+    /// inputs, whose square correlations follow the ones of the inputs in
+    /// `square_corr`. Fails unless there is a share and two correlations for
+    /// each witness bit, see `a2s::num_squared`.
+    pub fn new<C: UInt>(
+        input_len: usize,
+        witness: &[C],
+        square_corr: &[SquareCorrShare<C>],
+        bound: A,
+    ) -> Result<Self, ClientFailure> {
+        let len = input_len * bound_witness_len(bound);
+        if witness.len() != len {
+            return Err(ProtocolError::LengthMismatch {
+                expected: len,
+                actual: witness.len(),
+                what: "bound witness shares",
+            }
+            .into());
+        }
+        if square_corr.len() != (input_len + len) * 2 {
+            return Err(ProtocolError::LengthMismatch {
+                expected: (input_len + len) * 2,
+                actual: square_corr.len(),
+                what: "square correlations",
+            }
+            .into());
+        }
+        let wb = witness.iter().map(|w| w.as_uint()).collect::<Vec<A>>();
+        let corr = square_corr[input_len..input_len + len]
+            .iter()
+            .map(|x| x.cut())
+            .collect::<Vec<SquareCorrShare<A>>>();
+
+        let eb = batch_bound_check_first(&wb, &corr);
+        Ok(Self {
+            bound,
+            wb,
+            corr,
+            eb,
+        })
+    }
+
+    /// My share of `e`, sent in the first exchange.
+    pub fn eb(&self) -> &Vec<A> {
+        &self.eb
+    }
+
+    /// Open `e` with the share of the peer, which is absorbed into
+    /// `hasher_other`. Return my shares of the checks of the inputs `xb`,
+    /// sent in the second exchange.
+    pub fn open_e<const PARTY: bool, H: MessageHash>(
+        &self,
+        xb: &[A],
+        eb_other: Vec<A>,
+        hasher_other: &mut H,
+    ) -> Result<Vec<A>, ClientFailure> {
+        hasher_other.absorb(&eb_other);
+
+        check_peer_len(self.eb.len(), eb_other.len())?;
+
+        let e = batch_wrapping_add(&self.eb, &eb_other);
+        Ok(batch_bound_check_second::<_, PARTY>(
+            &e, xb, &self.wb, &self.corr, self.bound,
+        ))
+    }
+
+    /// Number of inputs within the bound, given both shares of the checks.
+    /// The share of the peer is absorbed into `hasher_other`.
+    pub fn count_accepted<H: MessageHash>(
+        &self,
+        checks: &[A],
+        checks_other: Vec<A>,
+        hasher_other: &mut H,
+    ) -> Result<usize, ClientFailure> {
+        hasher_other.absorb(&checks_other);
+
+        check_peer_len(checks.len(), checks_other.len())?;
+
+        Ok(batch_bound_check_result(checks, &checks_other, self.bound)
+            .into_iter()
+            .filter(|accepted| *accepted)
+            .count())
+    }
+}
+
+/// Check that each input of `xb` is at most `bound`, with the witness bits
+/// and square correlations of the client. Return the number of inputs within
+/// the bound. Both messages the peer sends are absorbed into `hasher_other`.
+#[allow(clippy::too_many_arguments)]
+pub async fn bound_check<A: UInt, C: UInt, H: MessageHash, const PARTY: bool>(
+    msg_id1: ExchangeId,
+    msg_id2: ExchangeId,
+    xb: &[A],
+    witness: &[C],
+    square_corr: &[SquareCorrShare<C>],
+    bound: A,
+    peer: MpcConnection,
+    hasher_other: &mut H,
+) -> usize {
+    let round = BoundCheckRound::new(xb.len(), witness, square_corr, bound).unwrap();
+
+    let eb_other = if cfg!(feature = "no-comm") {
+        vec![A::zero(); round.eb().len()]
+    } else {
+        peer.exchange_message(msg_id1, round.eb()).await.unwrap()
+    };
+
+    let checks = round
+        .open_e::<PARTY, _>(xb, eb_other, hasher_other)
+        .unwrap();
+
+    let checks_other = if cfg!(feature = "no-comm") {
+        vec![A::zero(); checks.len()]
+    } else {
+        peer.exchange_message(msg_id2, &checks).await.unwrap()
+    };
+
+    round
+        .count_accepted(&checks, checks_other, hasher_other)
+        .unwrap()
+}
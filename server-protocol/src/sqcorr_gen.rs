@@ -0,0 +1,63 @@
+use block::Block;
+use bridge::{
+    id_tracker::{RecvId, SendId},
+    mpc_conn::MpcConnection,
+};
+use crypto_primitives::{
+    bits::BitsLE,
+    cot::rot::server_generated::{ROTsForReceiver, ROTsForSender},
+    square_corr::{server_gen, SquareCorrShare},
+    uint::UInt,
+};
+
+use crate::{
+    b2a::{server_cot_alice, server_cot_bob},
+    send, ClientFailure,
+};
+
+/// Generate `count` square correlations of one client with the peer instead
+/// of taking the ones of the client, assuming I'm OT sender. `rots` are
+/// `count * C::NUM_BITS` ROTs with the peer. `ids` are for the ROT correction
+/// I send, the choice flips I receive, and the `us` I send. See
+/// `square_corr::server_gen`.
+pub async fn server_sqcorr_alice<C: UInt>(
+    ids: (SendId, RecvId, SendId),
+    count: usize,
+    rots: ROTsForSender,
+    peer: MpcConnection,
+) -> Vec<SquareCorrShare<C>> {
+    let (a0s, delta) = {
+        let mut rng = rand::thread_rng();
+        let a0s = server_gen::sample_shares::<C, _>(&mut rng, count);
+        (a0s, Block::rand(&mut rng))
+    };
+    let cots = server_cot_alice::<C>((ids.0, ids.1), rots, delta, peer.clone()).await;
+    let (corrs, us) = server_gen::gen_as_ot_sender(&a0s, cots.delta, &cots.qs)
+        .expect("one ROT per bit of the correlations");
+
+    send(&peer, ids.2, us)
+        .await
+        .expect("MPC connection closed before the message was sent");
+    corrs
+}
+
+/// Same as `server_sqcorr_alice`, assuming I'm OT receiver. `ids` are for the
+/// ROT correction I receive, the choice flips I send, and the `us` I receive.
+/// Fails if the `us` of the peer are not one per ROT.
+pub async fn server_sqcorr_bob<C: UInt>(
+    ids: (RecvId, SendId, RecvId),
+    count: usize,
+    rots: ROTsForReceiver,
+    peer: MpcConnection,
+) -> Result<Vec<SquareCorrShare<C>>, ClientFailure> {
+    let a1s = server_gen::sample_shares::<C, _>(&mut rand::thread_rng(), count);
+    let choices = a1s.iter().map(|a| BitsLE(*a)).collect::<Vec<_>>();
+    let ts = server_cot_bob::<C>((ids.0, ids.1), rots, &choices, peer.clone()).await;
+
+    let us = if cfg!(feature = "no-comm") {
+        vec![C::zero(); ts.len()]
+    } else {
+        peer.subscribe_and_get::<Vec<C>>(ids.2).await?
+    };
+    Ok(server_gen::gen_as_ot_receiver(&a1s, &ts, &us)?)
+}